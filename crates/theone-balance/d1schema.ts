@@ -1,6 +1,8 @@
 import * as sqlite from 'drizzle-orm/sqlite-core'
 import * as drizzle from 'drizzle-orm'
 
+// Schema changes must also be added as a migration in src/migrations.rs.
+
 export type Key = typeof keys.$inferSelect
 export const keys = sqlite.sqliteTable(
     'keys',
@@ -10,6 +12,7 @@ export const keys = sqlite.sqliteTable(
             .primaryKey()
             .$defaultFn(() => crypto.randomUUID()),
         key: sqlite.text('key').notNull(),
+        keyHash: sqlite.text('key_hash').notNull().default(''), // FNV-1a of key, backfilled for older rows
         provider: sqlite.text('provider').notNull(),
         modelCoolings: sqlite.text('model_coolings', { mode: 'json' }).$type<Record<string, ModelCooling>>(),
         totalCoolingSeconds: sqlite.integer('total_cooling_seconds').notNull().default(0), // across all models, in seconds
@@ -23,18 +26,434 @@ export const keys = sqlite.sqliteTable(
             .notNull()
             .default(drizzle.sql`(strftime('%s', 'now'))`),
         latencyMs: sqlite.integer('latency_ms').notNull().default(0),
+        latencyEwmaMs: sqlite.integer('latency_ewma_ms').notNull().default(0),
+        latencyHistogram: sqlite.text('latency_histogram').notNull().default('[]'), // JSON array of decaying bucket counts
         successRate: sqlite.integer('success_rate').notNull().default(1000),
         consecutiveFailures: sqlite.integer('consecutive_failures').notNull().default(0),
         lastCheckedAt: sqlite.integer('last_checked_at', { mode: 'timestamp' }).notNull().default(0),
         lastSucceededAt: sqlite.integer('last_succeeded_at', { mode: 'timestamp' }).notNull().default(0),
+        deletedAt: sqlite.integer('deleted_at', { mode: 'timestamp' }).notNull().default(0), // 0 unless in the trash
+        // requests served in the current and previous hourly window, for fair key rotation
+        usageWindowStart: sqlite.integer('usage_window_start').notNull().default(0),
+        usageWindowRequests: sqlite.integer('usage_window_requests').notNull().default(0),
+        usagePrevWindowRequests: sqlite.integer('usage_prev_window_requests').notNull().default(0),
+        tier: sqlite.text('tier').notNull().default('free'), // 'free' or 'paid'; free keys are used first
+        blockReason: sqlite.text('block_reason').notNull().default(''), // why the key was blocked, empty while active
+        blockedAt: sqlite.integer('blocked_at', { mode: 'timestamp' }).notNull().default(0),
+        reactivations: sqlite.integer('reactivations').notNull().default(0), // reactivations by the blocked-key probation
+        note: sqlite.text('note').notNull().default(''),
+        tags: sqlite.text('tags').notNull().default(''), // ',team-a,team-b,': comma-wrapped so LIKE '%,tag,%' matches whole tags
+        weight: sqlite.integer('weight').notNull().default(0), // added to the key's health score
     },
     table => {
         return {
             providerKeyUnqIdx: sqlite.uniqueIndex('provider_key_unq_idx').on(table.provider, table.key),
+            providerKeyHashUnqIdx: sqlite
+                .uniqueIndex('provider_key_hash_unq_idx')
+                .on(table.provider, table.keyHash)
+                .where(drizzle.sql`key_hash != ''`),
             providerStatusCreatedAtIdx: sqlite
                 .index('provider_status_created_at_idx')
                 .on(table.provider, table.status, table.createdAt),
-            totalCoolingSecondsIdx: sqlite.index('total_cooling_seconds_idx').on(table.totalCoolingSeconds)
+            totalCoolingSecondsIdx: sqlite.index('total_cooling_seconds_idx').on(table.totalCoolingSeconds),
+            deletedAtIdx: sqlite.index('deleted_at_idx').on(table.deletedAt),
+            routingIdx: sqlite.index('keys_routing_idx').on(table.status, table.deletedAt, table.provider),
+            listingIdx: sqlite.index('keys_listing_idx').on(table.provider, table.deletedAt, table.createdAt)
+        }
+    }
+)
+
+export type ClientKey = typeof clientKeys.$inferSelect
+export const clientKeys = sqlite.sqliteTable(
+    'client_keys',
+    {
+        id: sqlite
+            .text('id')
+            .primaryKey()
+            .$defaultFn(() => crypto.randomUUID()),
+        name: sqlite.text('name').notNull(),
+        key: sqlite.text('key').notNull(), // SHA-256 of the key, hex
+        keyPrefix: sqlite.text('key_prefix').notNull().default(''),
+        allowedProviders: sqlite.text('allowed_providers').notNull().default(''), // comma-separated, empty = all
+        allowedModels: sqlite.text('allowed_models').notNull().default(''), // comma-separated, empty = all
+        status: sqlite.text('status').notNull().default('active'), // active, revoked
+        maxRequestsPerDay: sqlite.integer('max_requests_per_day').notNull().default(0), // 0 = unlimited
+        maxTokensPerMonth: sqlite.integer('max_tokens_per_month').notNull().default(0), // 0 = unlimited
+        maxBudgetMicros: sqlite.integer('max_budget_micros').notNull().default(0), // millionths of a USD, 0 = unlimited
+        role: sqlite.text('role').notNull().default(''), // viewer, operator, admin; empty = proxy only
+        createdAt: sqlite
+            .integer('created_at', { mode: 'timestamp' })
+            .notNull()
+            .default(drizzle.sql`(strftime('%s', 'now'))`),
+        updatedAt: sqlite
+            .integer('updated_at', { mode: 'timestamp' })
+            .notNull()
+            .default(drizzle.sql`(strftime('%s', 'now'))`),
+        lastUsedAt: sqlite.integer('last_used_at', { mode: 'timestamp' }).notNull().default(0),
+    },
+    table => {
+        return {
+            clientKeyUnqIdx: sqlite.uniqueIndex('client_key_unq_idx').on(table.key),
+            clientStatusIdx: sqlite.index('client_status_idx').on(table.status)
+        }
+    }
+)
+
+export type UsageEvent = typeof usageEvents.$inferSelect
+export const usageEvents = sqlite.sqliteTable(
+    'usage_events',
+    {
+        id: sqlite
+            .text('id')
+            .primaryKey()
+            .$defaultFn(() => crypto.randomUUID()),
+        clientId: sqlite.text('client_id').notNull().default(''), // empty for the master key
+        provider: sqlite.text('provider').notNull(),
+        model: sqlite.text('model').notNull(),
+        keyId: sqlite.text('key_id').notNull(),
+        endpoint: sqlite.text('endpoint').notNull(),
+        promptTokens: sqlite.integer('prompt_tokens').notNull().default(0),
+        completionTokens: sqlite.integer('completion_tokens').notNull().default(0),
+        images: sqlite.integer('images').notNull().default(0),
+        costMicros: sqlite.integer('cost_micros').notNull().default(0), // millionths of a USD
+        estimated: sqlite.integer('estimated').notNull().default(0), // 1 if token counts are estimated
+        createdAt: sqlite
+            .integer('created_at', { mode: 'timestamp' })
+            .notNull()
+            .default(drizzle.sql`(strftime('%s', 'now'))`),
+    },
+    table => {
+        return {
+            usageClientCreatedAtIdx: sqlite.index('usage_client_created_at_idx').on(table.clientId, table.createdAt),
+            usageProviderCreatedAtIdx: sqlite.index('usage_provider_created_at_idx').on(table.provider, table.createdAt)
+        }
+    }
+)
+
+export type Sample = typeof samples.$inferSelect
+export const samples = sqlite.sqliteTable(
+    'samples',
+    {
+        id: sqlite
+            .text('id')
+            .primaryKey()
+            .$defaultFn(() => crypto.randomUUID()),
+        provider: sqlite.text('provider').notNull(),
+        model: sqlite.text('model').notNull(),
+        keyId: sqlite.text('key_id').notNull(),
+        clientId: sqlite.text('client_id').notNull().default(''), // empty for the master key
+        endpoint: sqlite.text('endpoint').notNull(),
+        requestBody: sqlite.text('request_body').notNull(), // redacted
+        responseBody: sqlite.text('response_body').notNull(), // redacted
+        latencyMs: sqlite.integer('latency_ms').notNull().default(0),
+        createdAt: sqlite
+            .integer('created_at', { mode: 'timestamp' })
+            .notNull()
+            .default(drizzle.sql`(strftime('%s', 'now'))`),
+    },
+    table => {
+        return {
+            sampleProviderIdx: sqlite.index('sample_provider_idx').on(table.provider),
+            sampleCreatedAtIdx: sqlite.index('sample_created_at_idx').on(table.createdAt)
+        }
+    }
+)
+
+export type ModelCatalog = typeof modelCatalog.$inferSelect
+export const modelCatalog = sqlite.sqliteTable(
+    'model_catalog',
+    {
+        id: sqlite
+            .text('id')
+            .primaryKey()
+            .$defaultFn(() => crypto.randomUUID()),
+        provider: sqlite.text('provider').notNull(),
+        models: sqlite.text('models').notNull().default('[]'), // JSON array of model names
+        fetchedAt: sqlite.integer('fetched_at', { mode: 'timestamp' }).notNull().default(0),
+    },
+    table => {
+        return {
+            modelCatalogProviderUnqIdx: sqlite.uniqueIndex('model_catalog_provider_unq_idx').on(table.provider)
+        }
+    }
+)
+
+export type ProviderSetting = typeof providerSettings.$inferSelect
+export const providerSettings = sqlite.sqliteTable(
+    'provider_settings',
+    {
+        id: sqlite
+            .text('id')
+            .primaryKey()
+            .$defaultFn(() => crypto.randomUUID()),
+        provider: sqlite.text('provider').notNull(),
+        observeOnly: sqlite.integer('observe_only').notNull().default(0), // 1 = probed only, no live traffic
+        rpmLimit: sqlite.integer('rpm_limit').notNull().default(0), // per-key requests per minute, 0 = unlimited
+        tpmLimit: sqlite.integer('tpm_limit').notNull().default(0), // per-key tokens per minute, 0 = unlimited
+        updatedAt: sqlite
+            .integer('updated_at', { mode: 'timestamp' })
+            .notNull()
+            .default(drizzle.sql`(strftime('%s', 'now'))`),
+    },
+    table => {
+        return {
+            providerSettingsProviderUnqIdx: sqlite.uniqueIndex('provider_settings_provider_unq_idx').on(table.provider)
+        }
+    }
+)
+
+export type CustomProvider = typeof providers.$inferSelect
+export const providers = sqlite.sqliteTable(
+    'providers',
+    {
+        id: sqlite
+            .text('id')
+            .primaryKey()
+            .$defaultFn(() => crypto.randomUUID()),
+        name: sqlite.text('name').notNull(), // the model prefix clients use
+        baseUrl: sqlite.text('base_url').notNull(), // OpenAI-compatible base URL, e.g. https://llm.internal/v1
+        authHeader: sqlite.text('auth_header').notNull().default('Authorization'),
+        authScheme: sqlite.text('auth_scheme').notNull().default('Bearer'), // empty = the key alone
+        color: sqlite.text('color').notNull().default(''), // CSS color for the UI
+        updatedAt: sqlite
+            .integer('updated_at', { mode: 'timestamp' })
+            .notNull()
+            .default(drizzle.sql`(strftime('%s', 'now'))`),
+    },
+    table => {
+        return {
+            providersNameUnqIdx: sqlite.uniqueIndex('providers_name_unq_idx').on(table.name)
+        }
+    }
+)
+
+export type Setting = typeof settings.$inferSelect
+export const settings = sqlite.sqliteTable(
+    'settings',
+    {
+        id: sqlite
+            .text('id')
+            .primaryKey()
+            .$defaultFn(() => crypto.randomUUID()),
+        name: sqlite.text('name').notNull(), // worker var overridden, e.g. TARGET_TIMEOUT_MS
+        value: sqlite.text('value').notNull(),
+        updatedAt: sqlite
+            .integer('updated_at', { mode: 'timestamp' })
+            .notNull()
+            .default(drizzle.sql`(strftime('%s', 'now'))`),
+    },
+    table => {
+        return {
+            settingsNameUnqIdx: sqlite.uniqueIndex('settings_name_unq_idx').on(table.name)
+        }
+    }
+)
+
+export type MetricSeries = typeof metrics.$inferSelect
+export const metrics = sqlite.sqliteTable(
+    'metrics',
+    {
+        id: sqlite
+            .text('id')
+            .primaryKey()
+            .$defaultFn(() => crypto.randomUUID()),
+        name: sqlite.text('name').notNull(),
+        labels: sqlite.text('labels').notNull().default(''), // e.g. provider="openai",status="200"
+        value: sqlite.integer('value').notNull().default(0), // counts, or milliseconds for latency sums
+        updatedAt: sqlite
+            .integer('updated_at', { mode: 'timestamp' })
+            .notNull()
+            .default(drizzle.sql`(strftime('%s', 'now'))`),
+    },
+    table => {
+        return {
+            metricsNameIdx: sqlite.index('metrics_name_idx').on(table.name),
+            metricsSeriesUnqIdx: sqlite.uniqueIndex('metrics_series_unq_idx').on(table.name, table.labels)
+        }
+    }
+)
+
+export type RequestEvent = typeof requestEvents.$inferSelect
+export const requestEvents = sqlite.sqliteTable(
+    'request_events',
+    {
+        id: sqlite
+            .text('id')
+            .primaryKey()
+            .$defaultFn(() => crypto.randomUUID()),
+        provider: sqlite.text('provider').notNull(),
+        model: sqlite.text('model').notNull(),
+        status: sqlite.integer('status').notNull(),
+        errorClass: sqlite.text('error_class').notNull().default(''), // empty on success
+        latencyMs: sqlite.integer('latency_ms').notNull().default(0),
+        requestBody: sqlite.text('request_body').notNull().default(''), // only with PAYLOAD_LOGGING
+        responseBody: sqlite.text('response_body').notNull().default(''),
+        createdAt: sqlite
+            .integer('created_at', { mode: 'timestamp' })
+            .notNull()
+            .default(drizzle.sql`(strftime('%s', 'now'))`),
+    },
+    table => {
+        return {
+            requestEventProviderIdx: sqlite.index('request_event_provider_idx').on(table.provider),
+            requestEventCreatedAtIdx: sqlite.index('request_event_created_at_idx').on(table.createdAt)
+        }
+    }
+)
+
+export type KeyScore = typeof keyScores.$inferSelect
+export const keyScores = sqlite.sqliteTable(
+    'key_scores',
+    {
+        id: sqlite
+            .text('id')
+            .primaryKey()
+            .$defaultFn(() => crypto.randomUUID()),
+        requestId: sqlite.text('request_id').notNull(),
+        provider: sqlite.text('provider').notNull(),
+        model: sqlite.text('model').notNull(),
+        keyId: sqlite.text('key_id').notNull(),
+        rank: sqlite.integer('rank').notNull(), // 0 for the key tried first
+        latencyScore: sqlite.integer('latency_score').notNull(),
+        successScore: sqlite.integer('success_score').notNull(),
+        failurePenalty: sqlite.integer('failure_penalty').notNull(),
+        recentSuccessBonus: sqlite.integer('recent_success_bonus').notNull(),
+        fairnessPenalty: sqlite.integer('fairness_penalty').notNull(),
+        weight: sqlite.integer('weight').notNull().default(0),
+        total: sqlite.integer('total').notNull(),
+        createdAt: sqlite
+            .integer('created_at', { mode: 'timestamp' })
+            .notNull()
+            .default(drizzle.sql`(strftime('%s', 'now'))`),
+    },
+    table => {
+        return {
+            keyScoreKeyIdIdx: sqlite.index('key_score_key_id_idx').on(table.keyId),
+            keyScoreCreatedAtIdx: sqlite.index('key_score_created_at_idx').on(table.createdAt)
+        }
+    }
+)
+
+export type AlertEvent = typeof alertEvents.$inferSelect
+export const alertEvents = sqlite.sqliteTable(
+    'alert_events',
+    {
+        id: sqlite
+            .text('id')
+            .primaryKey()
+            .$defaultFn(() => crypto.randomUUID()),
+        provider: sqlite.text('provider').notNull(),
+        metric: sqlite.text('metric').notNull(), // active_keys, usable_keys or success_rate
+        state: sqlite.text('state').notNull(), // firing or resolved
+        value: sqlite.integer('value').notNull(),
+        threshold: sqlite.integer('threshold').notNull(),
+        message: sqlite.text('message').notNull(),
+        createdAt: sqlite
+            .integer('created_at', { mode: 'timestamp' })
+            .notNull()
+            .default(drizzle.sql`(strftime('%s', 'now'))`),
+    },
+    table => {
+        return {
+            alertEventProviderIdx: sqlite.index('alert_event_provider_idx').on(table.provider),
+            alertEventCreatedAtIdx: sqlite.index('alert_event_created_at_idx').on(table.createdAt)
+        }
+    }
+)
+
+export type KeyEvent = typeof keyEvents.$inferSelect
+export const keyEvents = sqlite.sqliteTable(
+    'key_events',
+    {
+        id: sqlite
+            .text('id')
+            .primaryKey()
+            .$defaultFn(() => crypto.randomUUID()),
+        keyId: sqlite.text('key_id').notNull(),
+        provider: sqlite.text('provider').notNull(),
+        model: sqlite.text('model').notNull(), // empty when the event applies to the whole key
+        event: sqlite.text('event').notNull(), // blocked, cooldown or reactivated
+        status: sqlite.integer('status').notNull(), // provider status code, 0 without a response
+        errorClass: sqlite.text('error_class').notNull(),
+        message: sqlite.text('message').notNull(), // redacted, truncated provider message
+        createdAt: sqlite
+            .integer('created_at', { mode: 'timestamp' })
+            .notNull()
+            .default(drizzle.sql`(strftime('%s', 'now'))`),
+    },
+    table => {
+        return {
+            keyEventKeyIdx: sqlite.index('key_event_key_idx').on(table.keyId, table.createdAt),
+            keyEventCreatedAtIdx: sqlite.index('key_event_created_at_idx').on(table.createdAt)
+        }
+    }
+)
+
+export type Session = typeof sessions.$inferSelect
+export const sessions = sqlite.sqliteTable(
+    'sessions',
+    {
+        id: sqlite
+            .text('id')
+            .primaryKey()
+            .$defaultFn(() => crypto.randomUUID()),
+        clientId: sqlite.text('client_id').notNull(), // empty for the master key
+        role: sqlite.text('role').notNull(), // viewer, operator, admin
+        createdAt: sqlite
+            .integer('created_at', { mode: 'timestamp' })
+            .notNull()
+            .default(drizzle.sql`(strftime('%s', 'now'))`),
+        expiresAt: sqlite.integer('expires_at', { mode: 'timestamp' }).notNull(),
+        revokedAt: sqlite.integer('revoked_at', { mode: 'timestamp' }).notNull().default(0), // 0 while valid
+    },
+    table => {
+        return {
+            sessionClientIdx: sqlite.index('session_client_idx').on(table.clientId),
+            sessionExpiresAtIdx: sqlite.index('session_expires_at_idx').on(table.expiresAt)
+        }
+    }
+)
+
+export type TotpSecret = typeof totpSecrets.$inferSelect
+export const totpSecrets = sqlite.sqliteTable(
+    'totp_secrets',
+    {
+        id: sqlite
+            .text('id')
+            .primaryKey()
+            .$defaultFn(() => crypto.randomUUID()),
+        subject: sqlite.text('subject').notNull(), // master, or the client key id
+        secret: sqlite.text('secret').notNull(), // base32
+        enabledAt: sqlite.integer('enabled_at', { mode: 'timestamp' }).notNull().default(0), // 0 while provisioning
+        lastStep: sqlite.integer('last_step').notNull().default(0), // last accepted 30-second step
+        createdAt: sqlite
+            .integer('created_at', { mode: 'timestamp' })
+            .notNull()
+            .default(drizzle.sql`(strftime('%s', 'now'))`),
+    },
+    table => {
+        return {
+            totpSubjectUnqIdx: sqlite.uniqueIndex('totp_subject_unq_idx').on(table.subject)
+        }
+    }
+)
+
+export type KeyTag = typeof keyTags.$inferSelect
+export const keyTags = sqlite.sqliteTable(
+    'key_tags',
+    {
+        id: sqlite
+            .text('id')
+            .primaryKey()
+            .$defaultFn(() => crypto.randomUUID()),
+        keyId: sqlite.text('key_id').notNull(),
+        provider: sqlite.text('provider').notNull(),
+        tag: sqlite.text('tag').notNull(), // one row per tag in keys.tags
+    },
+    table => {
+        return {
+            keyTagsKeyTagUnqIdx: sqlite.uniqueIndex('key_tags_key_tag_unq_idx').on(table.keyId, table.tag),
+            keyTagsProviderTagIdx: sqlite.index('key_tags_provider_tag_idx').on(table.provider, table.tag),
         }
     }
 )
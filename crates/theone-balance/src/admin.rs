@@ -0,0 +1,1631 @@
+//! This module contains the JSON admin API used to manage the gateway programmatically.
+//! All routes live under `/api/admin` and require the master AUTH_KEY or a client key with
+//! a role, either as a Bearer token or via the UI's session cookie (see `auth`).
+
+use crate::{
+    admin_limits::{self, Bucket},
+    auth::{self, Principal},
+    balancer, d1_storage,
+    hybrid::SqlPreview,
+    inflight::{self, InflightRequest},
+    key_transfer::{self, KeyRecord},
+    migrations::{self, MigrationStatus},
+    schema_drift,
+    settings::{self, ScoringConfig, Settings},
+    rate_budget,
+    state::strategy::{CustomProvider, KeyScore, KeyTier, Role},
+    totp,
+    usage::{ClientQuota, ClientUsage},
+    util, AppState,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use axum::{
+    extract::{FromRef, FromRequestParts, Path, Query, Request, State},
+    http::{header, request::Parts, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+    routing::{get, post},
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tower_cookies::Cookies;
+use worker::send::SendFuture;
+use tracing::{error, info, warn};
+use worker::D1Database;
+
+// --- Router ---
+
+pub fn admin_router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route(
+            "/api/admin/clients",
+            get(list_clients_handler).post(create_client_handler),
+        )
+        .route(
+            "/api/admin/clients/{id}",
+            axum::routing::delete(delete_client_handler),
+        )
+        .route("/api/admin/clients/{id}/revoke", post(revoke_client_handler))
+        .route("/api/admin/clients/{id}/role", axum::routing::put(set_client_role_handler))
+        .route("/api/admin/sessions", get(list_sessions_handler))
+        .route("/api/admin/sessions/{id}/revoke", post(revoke_session_handler))
+        .route("/api/admin/totp/{subject}", axum::routing::delete(reset_totp_handler))
+        .route("/api/admin/quotas", get(list_quotas_handler))
+        .route(
+            "/api/admin/quotas/{id}",
+            axum::routing::put(set_quota_handler).post(set_quota_handler),
+        )
+        .route("/api/admin/samples", get(export_samples_handler))
+        .route("/api/admin/payloads", get(list_payloads_handler))
+        .route("/api/admin/alerts", get(list_alerts_handler))
+        .route("/api/admin/keys/export", get(export_keys_handler))
+        .route("/api/admin/keys/import", post(import_keys_handler))
+        .route("/api/admin/keys/changes", get(key_changes_handler))
+        .route("/api/admin/keys/delete", post(delete_keys_handler))
+        .route("/api/admin/keys/restore", post(restore_keys_handler))
+        .route("/api/admin/keys/purge", post(purge_keys_handler))
+        .route("/api/admin/providers", get(list_provider_settings_handler))
+        .route(
+            "/api/admin/providers/{provider}",
+            axum::routing::put(set_provider_settings_handler),
+        )
+        .route("/api/admin/custom-providers", get(list_custom_providers_handler))
+        .route(
+            "/api/admin/custom-providers/{name}",
+            axum::routing::put(set_custom_provider_handler).delete(delete_custom_provider_handler),
+        )
+        .route("/api/admin/settings", get(list_settings_handler))
+        .route(
+            "/api/admin/settings/{name}",
+            axum::routing::put(set_setting_handler).delete(delete_setting_handler),
+        )
+        .route("/api/admin/inflight", get(list_inflight_handler))
+        .route("/api/admin/inflight/{id}/cancel", post(cancel_inflight_handler))
+        .route("/api/admin/debug/penalty-box", get(penalty_box_handler))
+        .route("/api/admin/debug/sql/{operation}", get(sql_preview_handler))
+        .route("/api/admin/debug/scores/{provider}", get(score_breakdown_handler))
+        .route("/api/admin/routing/{provider}/{*model}", get(routing_dry_run_handler))
+        .route("/api/admin/keys/{id}/scores", get(key_scores_handler))
+        .route("/api/admin/migrations", get(list_migrations_handler))
+        .route("/api/admin/migrations/apply", post(apply_migrations_handler))
+        .route("/api/admin/schema/drift", get(schema_drift_handler))
+}
+
+// region: --- AdminAuth Extractor
+
+/// Extractor that only succeeds for requests authenticated with the master key, a client
+/// key with a role or a session of either, within the caller's admin rate limit. Behind `authorize`
+/// it takes the identity the middleware resolved.
+#[derive(Clone)]
+pub struct AdminAuth {
+    /// The `Principal::subject` the admin rate limits are counted for.
+    credential: String,
+    pub role: Role,
+    /// The session of a UI-style call; `None` for Bearer calls.
+    session_id: Option<String>,
+}
+
+impl AdminAuth {
+    /// Counts the call against the stricter limit for exports and other bulk reads,
+    /// returning the `429` to answer with when it is over the limit.
+    pub fn sensitive_rate_limit(&self, settings: &Settings) -> Option<Response> {
+        admin_limits::check(settings, &self.credential, Bucket::Sensitive)
+            .err()
+            .map(rate_limited)
+    }
+}
+
+impl<S> FromRequestParts<S> for AdminAuth
+where
+    S: Send + Sync,
+    Arc<AppState>: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(auth) = parts.extensions.get::<AdminAuth>() {
+            return Ok(auth.clone());
+        }
+        let app_state = Arc::<AppState>::from_ref(state);
+
+        let bearer = parts
+            .headers
+            .get("Authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(|v| v.to_string());
+        // Client keys and sessions are looked up in D1, whose futures aren't `Send` as
+        // extractors must be.
+        let mut principal = None;
+        if let Some(key) = bearer {
+            principal = SendFuture::new(auth::authenticate_key(&key, &app_state)).await;
+        }
+        if principal.is_none() {
+            if let Ok(cookies) = Cookies::from_request_parts(parts, state).await {
+                principal = SendFuture::new(auth::from_cookies(&app_state, &cookies)).await;
+            }
+        }
+
+        let Some(Principal { subject: credential, role, session_id }) = principal else {
+            return Err(admin_error(StatusCode::UNAUTHORIZED, "Invalid admin credentials."));
+        };
+        admin_limits::check(&app_state.settings, &credential, Bucket::All).map_err(rate_limited)?;
+        Ok(AdminAuth { credential, role, session_id })
+    }
+}
+
+/// The role an admin API call needs. Clients, sessions, second factors, quotas,
+/// configuration and the debug and payload views are for admins; changing keys, reading
+/// them in full (the export and the change log) and cancelling requests for operators; the
+/// remaining reads for viewers.
+pub fn required_role(method: &Method, path: &str) -> Role {
+    let resource = path.trim_start_matches("/api/admin/");
+    let section = resource.split('/').next().unwrap_or_default();
+    match section {
+        "clients" | "quotas" | "settings" | "providers" | "custom-providers" | "migrations" | "schema" | "debug"
+        | "samples" | "payloads" | "sessions" | "totp" => Role::Admin,
+        "keys" if method != Method::GET || resource == "keys/export" || resource == "keys/changes" => Role::Operator,
+        "inflight" if method != Method::GET => Role::Operator,
+        _ => Role::Viewer,
+    }
+}
+
+/// Middleware of the admin API: authenticates the call and checks the caller's role
+/// against `required_role`.
+#[worker::send]
+pub async fn authorize(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let (mut parts, body) = req.into_parts();
+    let auth = match AdminAuth::from_request_parts(&mut parts, &state).await {
+        Ok(auth) => auth,
+        Err(rejection) => return rejection,
+    };
+    let required = required_role(&parts.method, parts.uri.path());
+    if auth.role < required {
+        return admin_error(
+            StatusCode::FORBIDDEN,
+            &format!("This action requires the {} role.", required.as_str()),
+        );
+    }
+    parts.extensions.insert(auth);
+    next.run(Request::from_parts(parts, body)).await
+}
+
+// endregion: --- AdminAuth Extractor
+
+/// The answer to a call over its admin rate limit.
+pub fn rate_limited(retry_after_seconds: u64) -> Response {
+    let mut resp = admin_error(
+        StatusCode::TOO_MANY_REQUESTS,
+        &format!("Admin rate limit exceeded. Retry in {} seconds.", retry_after_seconds),
+    );
+    if let Ok(value) = header::HeaderValue::from_str(&retry_after_seconds.to_string()) {
+        resp.headers_mut().insert(header::RETRY_AFTER, value);
+    }
+    resp
+}
+
+/// Builds a JSON error body for admin API failures.
+pub fn admin_error(status: StatusCode, message: &str) -> Response {
+    (status, Json(serde_json::json!({ "error": message }))).into_response()
+}
+
+// region: --- Client Key Handlers
+
+#[derive(Serialize)]
+pub struct ClientKeySummary {
+    pub id: String,
+    pub name: String,
+    /// The secret is never returned after creation, only a redacted form.
+    pub key_preview: String,
+    pub allowed_providers: Vec<String>,
+    pub allowed_models: Vec<String>,
+    pub role: Option<Role>,
+    pub revoked: bool,
+    pub created_at: u64,
+    pub last_used_at: u64,
+}
+
+#[derive(Deserialize)]
+pub struct CreateClientRequest {
+    pub name: String,
+    #[serde(default)]
+    pub allowed_providers: Vec<String>,
+    #[serde(default)]
+    pub allowed_models: Vec<String>,
+    /// UI and admin API access; omitted for a key that only proxies requests.
+    #[serde(default)]
+    pub role: Option<Role>,
+}
+
+#[derive(Deserialize)]
+pub struct SetClientRoleRequest {
+    /// `null` takes UI and admin API access away.
+    pub role: Option<Role>,
+}
+
+#[worker::send]
+pub async fn list_clients_handler(State(state): State<Arc<AppState>>, _auth: AdminAuth) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::list_client_keys(&db).await {
+        Ok(keys) => {
+            let summaries: Vec<ClientKeySummary> = keys
+                .into_iter()
+                .map(|k| ClientKeySummary {
+                    key_preview: k.key_preview(),
+                    id: k.id,
+                    name: k.name,
+                    allowed_providers: k.allowed_providers,
+                    allowed_models: k.allowed_models,
+                    role: k.role,
+                    revoked: k.revoked,
+                    created_at: k.created_at,
+                    last_used_at: k.last_used_at,
+                })
+                .collect();
+            (StatusCode::OK, Json(summaries)).into_response()
+        }
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to list client keys: {}", e),
+        ),
+    }
+}
+
+#[worker::send]
+pub async fn create_client_handler(
+    State(state): State<Arc<AppState>>,
+    _auth: AdminAuth,
+    Json(req): Json<CreateClientRequest>,
+) -> Response {
+    if req.name.trim().is_empty() {
+        return admin_error(StatusCode::BAD_REQUEST, "Client name must not be empty.");
+    }
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::create_client_key(
+        &db,
+        req.name.trim(),
+        &req.allowed_providers,
+        &req.allowed_models,
+        req.role,
+    )
+    .await
+    {
+        Ok(client) => {
+            info!(client_id = %client.id, name = %client.name, role = ?client.role, "Issued new client key.");
+            // This is the only time the full secret is returned.
+            (StatusCode::CREATED, Json(client)).into_response()
+        }
+        Err(e) => {
+            error!("Failed to create client key: {}", e);
+            admin_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Failed to create client key: {}", e),
+            )
+        }
+    }
+}
+
+#[worker::send]
+pub async fn revoke_client_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    _auth: AdminAuth,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::revoke_client_key(&db, &id).await {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to revoke client key: {}", e),
+        ),
+    }
+}
+
+#[worker::send]
+pub async fn delete_client_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    _auth: AdminAuth,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::delete_client_key(&db, &id).await {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to delete client key: {}", e),
+        ),
+    }
+}
+
+#[worker::send]
+pub async fn set_client_role_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    _auth: AdminAuth,
+    Json(req): Json<SetClientRoleRequest>,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::set_client_role(&db, &id, req.role).await {
+        Ok(true) => {
+            info!(client_id = %id, role = ?req.role, "Changed client key role.");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => admin_error(StatusCode::NOT_FOUND, "No such client key."),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to set client key role: {}", e),
+        ),
+    }
+}
+
+// endregion: --- Client Key Handlers
+
+// region: --- Session Handlers
+
+/// The sessions that are neither expired nor revoked, newest first.
+#[worker::send]
+pub async fn list_sessions_handler(State(state): State<Arc<AppState>>, _auth: AdminAuth) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::list_sessions(&db).await {
+        Ok(sessions) => (StatusCode::OK, Json(sessions)).into_response(),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to list sessions: {}", e),
+        ),
+    }
+}
+
+#[worker::send]
+pub async fn revoke_session_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    _auth: AdminAuth,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::revoke_session(&db, &id).await {
+        Ok(true) => {
+            info!(session_id = %id, "Revoked session.");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => admin_error(StatusCode::NOT_FOUND, "No such active session."),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to revoke session: {}", e),
+        ),
+    }
+}
+
+/// The header carrying a current code of the caller's own second factor.
+const TOTP_CODE_HEADER: &str = "X-TOTP-Code";
+
+/// Whether the caller passed their own second factor: with a session started after it was
+/// enabled (such sessions only start with a code), or with a current code in
+/// `X-TOTP-Code`. Callers without a second factor never have.
+async fn passed_second_factor(db: &D1Database, auth: &AdminAuth, headers: &HeaderMap) -> worker::Result<bool> {
+    let Some(enrollment) = d1_storage::get_totp(db, &auth.credential).await?.filter(|e| e.enabled()) else {
+        return Ok(false);
+    };
+    if let Some(session_id) = &auth.session_id {
+        if let Some(session) = d1_storage::find_session_via_cache(db, session_id).await? {
+            if session.created_at >= enrollment.enabled_at {
+                return Ok(true);
+            }
+        }
+    }
+    match headers.get(TOTP_CODE_HEADER).and_then(|v| v.to_str().ok()) {
+        Some(code) => totp::check(db, &enrollment, code).await,
+        None => Ok(false),
+    }
+}
+
+/// Removes the second factor of `master` or a client key id, for a lost device. A leaked
+/// key alone must not strip the second factor off the UI, so the caller has to have passed
+/// their own, see `passed_second_factor`.
+#[worker::send]
+pub async fn reset_totp_handler(
+    State(state): State<Arc<AppState>>,
+    Path(subject): Path<String>,
+    headers: HeaderMap,
+    auth: AdminAuth,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+    if let Some(resp) = auth.sensitive_rate_limit(&state.settings) {
+        return resp;
+    }
+    match passed_second_factor(&db, &auth, &headers).await {
+        Ok(true) => {}
+        Ok(false) => {
+            warn!(caller = %auth.credential, subject = %subject, "Second factor reset refused: caller has not passed their own second factor.");
+            return admin_error(
+                StatusCode::FORBIDDEN,
+                "Resetting a second factor needs a session started with your own second factor, or its current code in X-TOTP-Code.",
+            );
+        }
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Failed to check second factor: {}", e)),
+    }
+
+    match d1_storage::delete_totp(&db, &subject).await {
+        Ok(_) => {
+            info!(subject = %subject, "Reset second factor.");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to reset second factor: {}", e),
+        ),
+    }
+}
+
+// endregion: --- Session Handlers
+
+// region: --- Quota Handlers
+
+#[derive(Serialize)]
+pub struct ClientQuotaStatus {
+    pub client_id: String,
+    pub name: String,
+    pub revoked: bool,
+    pub quota: ClientQuota,
+    pub usage: ClientUsage,
+}
+
+#[worker::send]
+pub async fn list_quotas_handler(State(state): State<Arc<AppState>>, _auth: AdminAuth) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    let clients = match d1_storage::list_client_keys(&db).await {
+        Ok(clients) => clients,
+        Err(e) => {
+            return admin_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Failed to list client keys: {}", e),
+            )
+        }
+    };
+
+    let mut statuses = Vec::with_capacity(clients.len());
+    for client in clients {
+        let usage = match d1_storage::get_client_usage(&db, &client.id).await {
+            Ok(usage) => usage,
+            Err(e) => {
+                return admin_error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("Failed to load usage for client {}: {}", client.id, e),
+                )
+            }
+        };
+        statuses.push(ClientQuotaStatus {
+            client_id: client.id,
+            name: client.name,
+            revoked: client.revoked,
+            quota: client.quota,
+            usage,
+        });
+    }
+    (StatusCode::OK, Json(statuses)).into_response()
+}
+
+#[worker::send]
+pub async fn set_quota_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    _auth: AdminAuth,
+    Json(quota): Json<ClientQuota>,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::set_client_quota(&db, &id, &quota).await {
+        Ok(true) => {
+            info!(client_id = %id, ?quota, "Updated client quota.");
+            (StatusCode::OK, Json(quota)).into_response()
+        }
+        Ok(false) => admin_error(StatusCode::NOT_FOUND, "No such client key."),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to update client quota: {}", e),
+        ),
+    }
+}
+
+// endregion: --- Quota Handlers
+
+// region: --- Sample Export
+
+#[derive(Deserialize)]
+pub struct SampleExportParams {
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    /// Unix timestamp (seconds); only samples created at or after it are exported.
+    #[serde(default)]
+    pub since: i64,
+    pub limit: Option<u32>,
+}
+
+/// Exports sampled prompt/response pairs as newline-delimited JSON, newest first.
+#[worker::send]
+pub async fn export_samples_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SampleExportParams>,
+    auth: AdminAuth,
+) -> Response {
+    if let Some(resp) = auth.sensitive_rate_limit(&state.settings) {
+        return resp;
+    }
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    let limit = params.limit.unwrap_or(1000).min(5000);
+    match d1_storage::list_samples(
+        &db,
+        params.provider.as_deref(),
+        params.model.as_deref(),
+        params.since,
+        limit,
+    )
+    .await
+    {
+        Ok(samples) => {
+            let body: String = samples
+                .iter()
+                .filter_map(|sample| serde_json::to_string(sample).ok())
+                .map(|line| line + "\n")
+                .collect();
+            (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "application/x-ndjson")],
+                body,
+            )
+                .into_response()
+        }
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to export samples: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PayloadListParams {
+    pub provider: Option<String>,
+    pub limit: Option<u32>,
+}
+
+/// Lists the latest logged prompts and responses (see `PAYLOAD_LOGGING`), newest first.
+#[worker::send]
+pub async fn list_payloads_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<PayloadListParams>,
+    auth: AdminAuth,
+) -> Response {
+    if let Some(resp) = auth.sensitive_rate_limit(&state.settings) {
+        return resp;
+    }
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+    let limit = params.limit.unwrap_or(50).min(500);
+    match d1_storage::list_logged_payloads(&db, params.provider.as_deref(), limit).await {
+        Ok(rows) => (StatusCode::OK, Json(rows)).into_response(),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to list logged payloads: {}", e),
+        ),
+    }
+}
+
+/// Lists the latest alert transitions (see `ALERT_RULES`), newest first.
+#[worker::send]
+pub async fn list_alerts_handler(State(state): State<Arc<AppState>>, _auth: AdminAuth) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+    match d1_storage::list_alert_events(&db).await {
+        Ok(rows) => (StatusCode::OK, Json(rows)).into_response(),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to list alert events: {}", e),
+        ),
+    }
+}
+
+// endregion: --- Sample Export
+
+// region: --- Provider Settings Handlers
+
+/// Fields left out keep their current value.
+#[derive(Deserialize)]
+pub struct ProviderSettingsRequest {
+    pub observe_only: Option<bool>,
+    /// Per-key requests per minute; 0 means unlimited.
+    pub rpm_limit: Option<u64>,
+    /// Per-key tokens per minute; 0 means unlimited.
+    pub tpm_limit: Option<u64>,
+}
+
+#[worker::send]
+pub async fn list_provider_settings_handler(
+    State(state): State<Arc<AppState>>,
+    _auth: AdminAuth,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::list_provider_settings(&db).await {
+        Ok(settings) => (StatusCode::OK, Json(settings)).into_response(),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to list provider settings: {}", e),
+        ),
+    }
+}
+
+#[worker::send]
+pub async fn set_provider_settings_handler(
+    State(state): State<Arc<AppState>>,
+    Path(provider): Path<String>,
+    _auth: AdminAuth,
+    Json(req): Json<ProviderSettingsRequest>,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    let result = async {
+        if let Some(observe_only) = req.observe_only {
+            d1_storage::set_provider_observe_only(&db, &provider, observe_only).await?;
+        }
+        if req.rpm_limit.is_some() || req.tpm_limit.is_some() {
+            d1_storage::set_provider_rate_limits(&db, &provider, req.rpm_limit, req.tpm_limit).await?;
+        }
+        Ok::<_, d1_storage::StorageError>(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => {
+            info!(provider = %provider, observe_only = ?req.observe_only, rpm_limit = ?req.rpm_limit, tpm_limit = ?req.tpm_limit, "Updated provider settings.");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to update provider settings: {}", e),
+        ),
+    }
+}
+
+// endregion: --- Provider Settings Handlers
+
+// region: --- Custom Provider Handlers
+
+#[derive(Deserialize)]
+pub struct CustomProviderRequest {
+    /// The OpenAI-compatible base URL, e.g. `https://llm.internal/v1`.
+    pub base_url: String,
+    /// Defaults to `Authorization`.
+    pub auth_header: Option<String>,
+    /// Defaults to `Bearer`; empty sends the key alone.
+    pub auth_scheme: Option<String>,
+    /// A CSS hex color for the UI, e.g. `#10b981`.
+    pub color: Option<String>,
+}
+
+fn validate_custom_provider(provider: &CustomProvider) -> Result<(), String> {
+    // The name is the model prefix, so it can't contain a slash or shadow the compat routes.
+    let valid_name = !provider.name.is_empty()
+        && provider.name != "compat"
+        && provider.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid_name {
+        return Err(format!("invalid provider name '{}'", provider.name));
+    }
+    match url::Url::parse(&provider.base_url) {
+        Ok(url) if url.scheme() == "https" || url.scheme() == "http" => {}
+        _ => return Err(format!("base_url '{}' is not an http(s) URL", provider.base_url)),
+    }
+    if provider.auth_header.is_empty() || !provider.auth_header.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(format!("invalid auth_header '{}'", provider.auth_header));
+    }
+    // The color ends up in a style attribute, so only hex colors are accepted.
+    let hex = provider.color.strip_prefix('#').unwrap_or("-");
+    let valid_color = matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit());
+    if !provider.color.is_empty() && !valid_color {
+        return Err(format!("color '{}' is not a hex color like #10b981", provider.color));
+    }
+    Ok(())
+}
+
+#[worker::send]
+pub async fn list_custom_providers_handler(
+    State(state): State<Arc<AppState>>,
+    _auth: AdminAuth,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::list_custom_providers(&db).await {
+        Ok(providers) => (StatusCode::OK, Json(providers)).into_response(),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to list custom providers: {}", e),
+        ),
+    }
+}
+
+/// Registers or updates a custom provider.
+#[worker::send]
+pub async fn set_custom_provider_handler(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    _auth: AdminAuth,
+    Json(req): Json<CustomProviderRequest>,
+) -> Response {
+    let provider = CustomProvider {
+        name,
+        base_url: req.base_url.trim().to_string(),
+        auth_header: req.auth_header.map(|h| h.trim().to_string()).unwrap_or_else(|| "Authorization".to_string()),
+        auth_scheme: req.auth_scheme.map(|s| s.trim().to_string()).unwrap_or_else(|| "Bearer".to_string()),
+        color: req.color.map(|c| c.trim().to_string()).unwrap_or_default(),
+        updated_at: 0,
+    };
+    if let Err(e) = validate_custom_provider(&provider) {
+        return admin_error(StatusCode::BAD_REQUEST, &format!("Invalid custom provider: {}", e));
+    }
+
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::save_custom_provider(&db, &provider).await {
+        Ok(_) => {
+            info!(provider = %provider.name, base_url = %provider.base_url, "Saved custom provider.");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to save custom provider: {}", e),
+        ),
+    }
+}
+
+#[worker::send]
+pub async fn delete_custom_provider_handler(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    _auth: AdminAuth,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::delete_custom_provider(&db, &name).await {
+        Ok(_) => {
+            info!(provider = %name, "Deleted custom provider.");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to delete custom provider: {}", e),
+        ),
+    }
+}
+
+// endregion: --- Custom Provider Handlers
+
+// region: --- Key Import/Export Handlers
+
+#[derive(Deserialize)]
+pub struct KeyTransferParams {
+    pub provider: Option<String>,
+    /// `json` (default) or `csv`.
+    pub format: Option<String>,
+}
+
+fn wants_csv(params: &KeyTransferParams, headers: &HeaderMap) -> bool {
+    match params.format.as_deref() {
+        Some(format) => format.eq_ignore_ascii_case("csv"),
+        None => headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|ct| ct.contains("csv")),
+    }
+}
+
+/// Downloads keys with their status and health metrics, as JSON or CSV.
+#[worker::send]
+pub async fn export_keys_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<KeyTransferParams>,
+    headers: HeaderMap,
+    auth: AdminAuth,
+) -> Response {
+    if let Some(resp) = auth.sensitive_rate_limit(&state.settings) {
+        return resp;
+    }
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    let keys = match d1_storage::list_all_keys(&db, params.provider.as_deref()).await {
+        Ok(keys) => keys,
+        Err(e) => {
+            return admin_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Failed to export keys: {}", e),
+            )
+        }
+    };
+    let records: Vec<KeyRecord> = keys.into_iter().map(KeyRecord::from).collect();
+    let name = params.provider.as_deref().unwrap_or("all");
+    if wants_csv(&params, &headers) {
+        (
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, "text/csv".to_string()),
+                (header::CONTENT_DISPOSITION, format!("attachment; filename=\"keys-{}.csv\"", name)),
+            ],
+            key_transfer::to_csv(&records),
+        )
+            .into_response()
+    } else {
+        (
+            StatusCode::OK,
+            [(header::CONTENT_DISPOSITION, format!("attachment; filename=\"keys-{}.json\"", name))],
+            Json(records),
+        )
+            .into_response()
+    }
+}
+
+/// Imports keys in the export format. The whole import is rejected if any record is
+/// invalid; keys that already exist are skipped and counted as duplicates.
+#[worker::send]
+pub async fn import_keys_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<KeyTransferParams>,
+    headers: HeaderMap,
+    _auth: AdminAuth,
+    body: String,
+) -> Response {
+    let parsed = if wants_csv(&params, &headers) {
+        key_transfer::from_csv(&body)
+    } else {
+        serde_json::from_str::<Vec<KeyRecord>>(&body).map_err(|e| e.to_string())
+    };
+    let records = match parsed {
+        Ok(records) => records,
+        Err(e) => return admin_error(StatusCode::BAD_REQUEST, &format!("Invalid import: {}", e)),
+    };
+
+    let errors: Vec<String> = records
+        .iter()
+        .enumerate()
+        .filter_map(|(i, record)| record.validate().err().map(|e| format!("record {}: {}", i + 1, e)))
+        .collect();
+    if !errors.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": "Invalid records, nothing was imported.", "details": errors })),
+        )
+            .into_response();
+    }
+
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::import_keys(&db, records).await {
+        Ok(summary) => {
+            info!(imported = summary.imported, duplicates = summary.duplicates, "Imported keys.");
+            (StatusCode::OK, Json(summary)).into_response()
+        }
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to import keys: {}", e),
+        ),
+    }
+}
+
+// endregion: --- Key Import/Export Handlers
+
+// region: --- Key Changes Handlers
+
+#[derive(Deserialize)]
+pub struct KeyChangesParams {
+    /// Unix timestamp (seconds); only keys changed at or after it are listed. Ignored
+    /// when a cursor is given.
+    #[serde(default)]
+    pub updated_since: i64,
+    /// The `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+    pub provider: Option<String>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct KeyChangesResponse {
+    pub keys: Vec<d1_storage::KeyChange>,
+    /// Where the next poll continues; returned even when the page is empty.
+    pub next_cursor: String,
+    /// Whether more changed keys are waiting right now.
+    pub has_more: bool,
+}
+
+/// The position after a key, as an opaque cursor.
+fn encode_cursor(updated_at: i64, id: &str) -> String {
+    URL_SAFE_NO_PAD.encode(format!("{}:{}", updated_at, id))
+}
+
+fn decode_cursor(cursor: &str) -> Option<(i64, String)> {
+    let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+    let (updated_at, id) = decoded.split_once(':')?;
+    Some((updated_at.parse().ok()?, id.to_string()))
+}
+
+/// Lists keys changed since a cursor, oldest change first, for systems mirroring the pool.
+#[worker::send]
+pub async fn key_changes_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<KeyChangesParams>,
+    auth: AdminAuth,
+) -> Response {
+    if let Some(resp) = auth.sensitive_rate_limit(&state.settings) {
+        return resp;
+    }
+    let (after_updated_at, after_id) = match params.cursor.as_deref() {
+        Some(cursor) => match decode_cursor(cursor) {
+            Some(position) => position,
+            None => return admin_error(StatusCode::BAD_REQUEST, "Invalid cursor."),
+        },
+        None => (params.updated_since, String::new()),
+    };
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    let limit = params.limit.unwrap_or(100).clamp(1, 1000);
+    match d1_storage::list_key_changes(&db, after_updated_at, &after_id, params.provider.as_deref(), limit).await {
+        Ok(keys) => {
+            let next_cursor = match keys.last() {
+                Some(last) => encode_cursor(last.updated_at, &last.id),
+                None => encode_cursor(after_updated_at, &after_id),
+            };
+            let has_more = keys.len() == limit as usize;
+            (StatusCode::OK, Json(KeyChangesResponse { keys, next_cursor, has_more })).into_response()
+        }
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to list key changes: {}", e),
+        ),
+    }
+}
+
+// endregion: --- Key Changes Handlers
+
+// region: --- Trash Handlers
+
+#[derive(Deserialize)]
+pub struct KeyIdsRequest {
+    pub ids: Vec<String>,
+}
+
+/// Moves keys to the trash, like deleting them in the UI.
+#[worker::send]
+pub async fn delete_keys_handler(
+    State(state): State<Arc<AppState>>,
+    _auth: AdminAuth,
+    Json(req): Json<KeyIdsRequest>,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    let count = req.ids.len();
+    match d1_storage::delete_keys(&db, req.ids).await {
+        Ok(_) => {
+            info!(count, "Moved keys to the trash.");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to delete keys: {}", e),
+        ),
+    }
+}
+
+/// Moves deleted keys out of the trash.
+#[worker::send]
+pub async fn restore_keys_handler(
+    State(state): State<Arc<AppState>>,
+    _auth: AdminAuth,
+    Json(req): Json<KeyIdsRequest>,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::restore_keys(&db, req.ids).await {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to restore keys: {}", e),
+        ),
+    }
+}
+
+/// Permanently deletes keys that are in the trash; other ids are ignored.
+#[worker::send]
+pub async fn purge_keys_handler(
+    State(state): State<Arc<AppState>>,
+    _auth: AdminAuth,
+    Json(req): Json<KeyIdsRequest>,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::purge_keys(&db, req.ids).await {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to purge keys: {}", e),
+        ),
+    }
+}
+
+// endregion: --- Trash Handlers
+
+// region: --- Settings Handlers
+
+#[derive(Serialize)]
+pub struct SettingsResponse {
+    /// The settings this request ran with, vars and overrides combined.
+    pub effective: Settings,
+    /// The stored overrides, by var name.
+    pub overrides: std::collections::BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+pub struct SetSettingRequest {
+    pub value: String,
+}
+
+/// Lists the effective settings and the stored overrides.
+#[worker::send]
+pub async fn list_settings_handler(State(state): State<Arc<AppState>>, _auth: AdminAuth) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::list_settings(&db).await {
+        Ok(overrides) => (
+            StatusCode::OK,
+            Json(SettingsResponse {
+                effective: state.settings.clone(),
+                overrides: overrides.into_iter().collect(),
+            }),
+        )
+            .into_response(),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to list settings: {}", e),
+        ),
+    }
+}
+
+/// Stores an override for a setting. Other isolates pick it up within their cache TTL.
+#[worker::send]
+pub async fn set_setting_handler(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    _auth: AdminAuth,
+    Json(req): Json<SetSettingRequest>,
+) -> Response {
+    if let Err(e) = settings::validate(&name, &req.value) {
+        return admin_error(StatusCode::BAD_REQUEST, &e);
+    }
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::set_setting(&db, &name, req.value.trim()).await {
+        Ok(_) => {
+            settings::invalidate_cache();
+            info!(name = %name, value = %req.value, "Updated setting override.");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to update setting: {}", e),
+        ),
+    }
+}
+
+/// Removes the override for a setting, so its var applies again.
+#[worker::send]
+pub async fn delete_setting_handler(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    _auth: AdminAuth,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::delete_setting(&db, &name).await {
+        Ok(_) => {
+            settings::invalidate_cache();
+            info!(name = %name, "Removed setting override.");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to remove setting: {}", e),
+        ),
+    }
+}
+
+// endregion: --- Settings Handlers
+
+// region: --- In-flight Request Handlers
+
+#[derive(Deserialize)]
+pub struct InflightQuery {
+    /// Only list requests running for at least this long.
+    pub min_age_ms: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct InflightEntry {
+    #[serde(flatten)]
+    pub request: InflightRequest,
+    pub running_ms: u64,
+}
+
+fn inflight_unavailable() -> Response {
+    admin_error(
+        StatusCode::NOT_IMPLEMENTED,
+        &format!("The in-flight registry is not configured (no {} binding).", inflight::BINDING),
+    )
+}
+
+/// Lists the proxied requests in flight, oldest first.
+#[worker::send]
+pub async fn list_inflight_handler(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<InflightQuery>,
+    _auth: AdminAuth,
+) -> Response {
+    if state.env.durable_object(inflight::BINDING).is_err() {
+        return inflight_unavailable();
+    }
+    match inflight::list(&state.env).await {
+        Ok(requests) => {
+            let now = worker::Date::now().as_millis();
+            let min_age_ms = query.min_age_ms.unwrap_or(0);
+            let entries: Vec<InflightEntry> = requests
+                .into_iter()
+                .map(|request| InflightEntry {
+                    running_ms: now.saturating_sub(request.started_at),
+                    request,
+                })
+                .filter(|entry| entry.running_ms >= min_age_ms)
+                .collect();
+            Json(entries).into_response()
+        }
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to list in-flight requests: {}", e),
+        ),
+    }
+}
+
+/// Aborts an in-flight request's upstream calls. The client gets an error, or the stream
+/// ends, once the request notices.
+#[worker::send]
+pub async fn cancel_inflight_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    _auth: AdminAuth,
+) -> Response {
+    if state.env.durable_object(inflight::BINDING).is_err() {
+        return inflight_unavailable();
+    }
+    match inflight::cancel(&state.env, &id).await {
+        Ok(true) => {
+            info!(request_id = %id, "Cancelled in-flight request.");
+            StatusCode::ACCEPTED.into_response()
+        }
+        Ok(false) => admin_error(StatusCode::NOT_FOUND, "No request with this id is in flight."),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to cancel the request: {}", e),
+        ),
+    }
+}
+
+// endregion: --- In-flight Request Handlers
+
+// region: --- Debug Handlers
+
+#[derive(Serialize)]
+pub struct PenaltyBoxEntry {
+    #[serde(flatten)]
+    pub penalty: d1_storage::Penalty,
+    pub remaining_ms: u64,
+}
+
+#[derive(Serialize)]
+pub struct PenaltyBoxResponse {
+    pub deploy_env: String,
+    pub entries: Vec<PenaltyBoxEntry>,
+}
+
+/// Dumps the keys benched in the local penalty box. The box is per isolate, so this shows
+/// the isolate that served the call, not every instance of the worker.
+pub async fn penalty_box_handler(State(state): State<Arc<AppState>>, _auth: AdminAuth) -> Response {
+    let now = worker::Date::now().as_millis();
+    let entries = d1_storage::penalty_box_entries()
+        .into_iter()
+        .map(|penalty| PenaltyBoxEntry {
+            remaining_ms: penalty.expires_at_ms.saturating_sub(now),
+            penalty,
+        })
+        .collect();
+    let response = PenaltyBoxResponse {
+        deploy_env: state.settings.deploy_env.clone(),
+        entries,
+    };
+    (StatusCode::OK, Json(response)).into_response()
+}
+
+#[derive(Serialize)]
+pub struct SqlPreviewResponse {
+    pub operation: String,
+    pub statements: Vec<SqlPreview>,
+}
+
+/// Shows the SQL and bound parameters a storage operation would run, without running it.
+/// The operation's arguments come from the query string, e.g.
+/// `/api/admin/debug/sql/list_keys?provider=openai&status=blocked&q=sk-`.
+pub async fn sql_preview_handler(
+    State(state): State<Arc<AppState>>,
+    Path(operation): Path<String>,
+    Query(args): Query<d1_storage::PreviewArgs>,
+    _auth: AdminAuth,
+) -> Response {
+    if !d1_storage::PREVIEW_OPERATIONS.contains(&operation.as_str()) {
+        return admin_error(
+            StatusCode::NOT_FOUND,
+            &format!(
+                "Unknown storage operation '{}'. Known operations: {}.",
+                operation,
+                d1_storage::PREVIEW_OPERATIONS.join(", ")
+            ),
+        );
+    }
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match d1_storage::preview_sql(&db, &operation, &args) {
+        Ok(statements) => (StatusCode::OK, Json(SqlPreviewResponse { operation, statements })).into_response(),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to generate SQL: {}", e),
+        ),
+    }
+}
+
+#[derive(Serialize)]
+pub struct RoutingCandidate {
+    pub key_id: String,
+    pub key_hash: String,
+    pub tier: KeyTier,
+    pub score: KeyScore,
+    /// Why a request would pass over the key: `cooling` for the model or `rate_budget`.
+    pub skip_reason: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+pub struct RoutingDryRunResponse {
+    pub provider: String,
+    pub model: String,
+    /// Observe-only providers get no live traffic, whatever the ranking.
+    pub observe_only: bool,
+    /// The keys in the order a request would try them. Keys benched by the circuit breaker
+    /// or the penalty box, or cooling for every model, are left out.
+    pub candidates: Vec<RoutingCandidate>,
+}
+
+/// Ranks a provider's keys for a model the way a request would, with each key's score
+/// components, without sending anything. Like the penalty box, rankings are per isolate.
+#[worker::send]
+pub async fn routing_dry_run_handler(
+    State(state): State<Arc<AppState>>,
+    Path((provider, model)): Path<(String, String)>,
+    _auth: AdminAuth,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+    let provider_settings = match d1_storage::get_provider_settings_via_cache(&db, &provider).await {
+        Ok(provider_settings) => provider_settings,
+        Err(e) => {
+            return admin_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Failed to load provider settings: {}", e),
+            )
+        }
+    };
+    let keys = match d1_storage::get_healthy_sorted_keys_via_cache(&state.settings, &db, &provider).await {
+        Ok(keys) => keys,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Failed to rank keys: {}", e)),
+    };
+
+    let now = worker::Date::now().as_millis() / 1000;
+    let scores = balancer::score_keys(&state.settings, &keys, now);
+    let candidates = keys
+        .iter()
+        .zip(scores)
+        .map(|(key, score)| RoutingCandidate {
+            key_id: key.id.clone(),
+            key_hash: util::key_hash(&key.key),
+            tier: key.tier,
+            score,
+            skip_reason: if key.get_cooldown_end(&model).is_some_and(|end| now < end) {
+                Some("cooling")
+            } else if !rate_budget::has_budget(&key.id, &provider_settings) {
+                Some("rate_budget")
+            } else {
+                None
+            },
+        })
+        .collect();
+    let response = RoutingDryRunResponse {
+        provider,
+        model,
+        observe_only: provider_settings.observe_only,
+        candidates,
+    };
+    (StatusCode::OK, Json(response)).into_response()
+}
+
+#[derive(Serialize)]
+pub struct ScoreBreakdown {
+    pub key_id: String,
+    pub key_hash: String,
+    /// The score's inputs.
+    pub latency_ms: i64,
+    pub latency_ewma_ms: i64,
+    pub latency_p50_ms: i64,
+    pub latency_p95_ms: i64,
+    pub success_rate: f64,
+    pub consecutive_failures: i64,
+    /// `None` for keys that never succeeded.
+    pub seconds_since_success: Option<u64>,
+    pub recent_requests: u64,
+    pub score: KeyScore,
+}
+
+#[derive(Serialize)]
+pub struct ScoreBreakdownResponse {
+    pub provider: String,
+    pub scoring: ScoringConfig,
+    pub fairness_weight: i64,
+    /// The provider's keys, best first; keys benched by the circuit breaker are left out.
+    pub keys: Vec<ScoreBreakdown>,
+}
+
+/// Explains the health score of each of a provider's keys: the weights in effect, the
+/// key's inputs and the resulting components. Unlike the routing dry run, it ignores
+/// model cooldowns and rate budgets.
+#[worker::send]
+pub async fn score_breakdown_handler(
+    State(state): State<Arc<AppState>>,
+    Path(provider): Path<String>,
+    _auth: AdminAuth,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+    let keys = match d1_storage::get_healthy_sorted_keys_via_cache(&state.settings, &db, &provider).await {
+        Ok(keys) => keys,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Failed to rank keys: {}", e)),
+    };
+
+    let now = worker::Date::now().as_millis() / 1000;
+    let scores = balancer::score_keys(&state.settings, &keys, now);
+    let keys = keys
+        .iter()
+        .zip(scores)
+        .map(|(key, score)| ScoreBreakdown {
+            key_id: key.id.clone(),
+            key_hash: util::key_hash(&key.key),
+            latency_ms: key.latency_ms,
+            latency_ewma_ms: key.latency_ewma_ms,
+            latency_p50_ms: key.latency_p50_ms,
+            latency_p95_ms: key.latency_p95_ms,
+            success_rate: key.success_rate,
+            consecutive_failures: key.consecutive_failures,
+            seconds_since_success: (key.last_succeeded_at > 0).then(|| now.saturating_sub(key.last_succeeded_at)),
+            recent_requests: key.recent_requests,
+            score,
+        })
+        .collect();
+    let response = ScoreBreakdownResponse {
+        provider,
+        scoring: state.settings.scoring,
+        fairness_weight: state.settings.key_fairness_weight,
+        keys,
+    };
+    (StatusCode::OK, Json(response)).into_response()
+}
+
+/// A key's stored rankings from sampled requests (see `SCORE_SAMPLE_RATE_PERCENT`).
+#[worker::send]
+pub async fn key_scores_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    _auth: AdminAuth,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+    match d1_storage::list_key_scores(&db, &id).await {
+        Ok(rows) => (StatusCode::OK, Json(rows)).into_response(),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to list key scores: {}", e),
+        ),
+    }
+}
+
+// endregion: --- Debug Handlers
+
+// region: --- Migration Handlers
+
+#[derive(Serialize)]
+pub struct ApplyMigrationsResponse {
+    pub applied: Vec<u32>,
+    pub migrations: Vec<MigrationStatus>,
+}
+
+/// Lists the schema migrations and when each was applied.
+#[worker::send]
+pub async fn list_migrations_handler(State(state): State<Arc<AppState>>, _auth: AdminAuth) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match migrations::status(&db).await {
+        Ok(status) => (StatusCode::OK, Json(status)).into_response(),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to list migrations: {}", e),
+        ),
+    }
+}
+
+/// Applies pending schema migrations.
+#[worker::send]
+pub async fn apply_migrations_handler(State(state): State<Arc<AppState>>, _auth: AdminAuth) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    let applied = match migrations::apply_pending(&db).await {
+        Ok(applied) => applied,
+        Err(e) => {
+            error!("Failed to apply migrations: {}", e);
+            return admin_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Failed to apply migrations: {}", e),
+            );
+        }
+    };
+    match migrations::status(&db).await {
+        Ok(migrations) => (StatusCode::OK, Json(ApplyMigrationsResponse { applied, migrations })).into_response(),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to list migrations: {}", e),
+        ),
+    }
+}
+
+/// Compares the live D1 schema with the models and reports what is missing.
+#[worker::send]
+pub async fn schema_drift_handler(State(state): State<Arc<AppState>>, _auth: AdminAuth) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e)),
+    };
+
+    match schema_drift::check(&db).await {
+        Ok(report) => (StatusCode::OK, Json(report)).into_response(),
+        Err(e) => admin_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to check the schema: {}", e),
+        ),
+    }
+}
+
+// endregion: --- Migration Handlers
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_keys_need_an_operator() {
+        for path in ["/api/admin/keys/export", "/api/admin/keys/changes"] {
+            let required = required_role(&Method::GET, path);
+            assert_eq!(required, Role::Operator, "{}", path);
+            assert!(Role::Viewer < required, "a viewer must get 403 on {}", path);
+        }
+        assert_eq!(required_role(&Method::GET, "/api/admin/keys"), Role::Viewer);
+        assert_eq!(required_role(&Method::POST, "/api/admin/keys/delete"), Role::Operator);
+    }
+}
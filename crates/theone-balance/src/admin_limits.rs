@@ -0,0 +1,60 @@
+//! This module rate limits admin calls per credential, so a leaked admin token can't be
+//! used to rapidly exfiltrate keys or hammer the pool. Every admin API call counts against
+//! `ADMIN_RATE_LIMIT_PER_MINUTE`; key exports, the key change log, key reveals and key test
+//! runs also count against the stricter `ADMIN_SENSITIVE_RATE_LIMIT_PER_MINUTE`. 0 disables
+//! a limit.
+//!
+//! Calls are counted over a sliding one-minute window (see `sliding_window`) under a hash
+//! of the credential rather than the credential itself.
+
+use crate::settings::Settings;
+use crate::sliding_window::WindowCache;
+use crate::util;
+use once_cell::sync::Lazy;
+use worker::Date;
+
+/// The limit a call counts against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bucket {
+    /// Every admin API call.
+    All,
+    /// Calls that read keys in bulk or spend them: exports, reveals and test runs.
+    Sensitive,
+}
+
+impl Bucket {
+    fn as_str(self) -> &'static str {
+        match self {
+            Bucket::All => "all",
+            Bucket::Sensitive => "sensitive",
+        }
+    }
+
+    fn limit(self, settings: &Settings) -> u64 {
+        match self {
+            Bucket::All => settings.admin_rate_limit_per_minute,
+            Bucket::Sensitive => settings.admin_sensitive_rate_limit_per_minute,
+        }
+    }
+}
+
+static WINDOWS: Lazy<WindowCache<()>> = Lazy::new(|| WindowCache::new(1_000));
+
+/// Counts a call made with `credential`. Returns the seconds until the caller may try
+/// again if it is over the bucket's limit; a rejected call isn't counted.
+pub fn check(settings: &Settings, credential: &str, bucket: Bucket) -> Result<(), u64> {
+    let limit = bucket.limit(settings);
+    if limit == 0 {
+        return Ok(());
+    }
+    let id = format!("{}:{}", bucket.as_str(), util::key_hash(credential));
+    let now_ms = Date::now().as_millis();
+    WINDOWS.update(&id, |calls| {
+        calls.expire(now_ms);
+        if calls.len() as u64 >= limit {
+            return Err(calls.retry_after_seconds(now_ms));
+        }
+        calls.push(now_ms, ());
+        Ok(())
+    })
+}
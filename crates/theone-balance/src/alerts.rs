@@ -0,0 +1,298 @@
+//! This module evaluates the operator's alert rules on each cron trigger and notifies a
+//! webhook when a rule starts or stops firing for a provider. Rules are configured with
+//! `ALERT_RULES`, a `;`-separated list of `provider-pattern:metric<threshold,...` rules;
+//! patterns ending in `*` match by prefix and the first matching rule wins, e.g.
+//! `openai:usable_keys<2;*:active_keys<3,success_rate<90`. The metrics are:
+//!
+//! - `active_keys`: keys with the active status, cooling or not.
+//! - `usable_keys`: active keys not cooling down for any model.
+//! - `success_rate`: percentage of successful requests over the last 15 minutes; not
+//!   judged with fewer than `MIN_REQUESTS` requests.
+//!
+//! Each change is stored in the `alert_events` table, which doubles as the alert state, and
+//! POSTed to `ALERT_WEBHOOK_URL` (with `ALERT_WEBHOOK_SECRET` as a Bearer token when set).
+//! With `ALERT_WEBHOOK_FORMAT=slack` the payload is a Slack-compatible `{"text": ...}`
+//! message, accepted by Slack, Mattermost and Discord's `/slack` webhooks.
+
+use crate::d1_storage::{self, ProviderDashboardStats};
+use crate::key_events;
+use crate::settings::Settings;
+use js_sys::Date;
+use serde::Serialize;
+use std::collections::HashSet;
+use tracing::{error, warn};
+use worker::{D1Database, Env};
+
+/// The cron trigger that only evaluates alerts; other triggers also run the maintenance.
+pub const ALERT_CRON: &str = "*/5 * * * *";
+/// The window `success_rate` is measured over.
+const WINDOW_SECONDS: i32 = 15 * 60;
+/// Requests the window needs before `success_rate` is judged.
+const MIN_REQUESTS: i64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Metric {
+    ActiveKeys,
+    UsableKeys,
+    SuccessRate,
+}
+
+impl Metric {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "active_keys" => Some(Metric::ActiveKeys),
+            "usable_keys" => Some(Metric::UsableKeys),
+            "success_rate" => Some(Metric::SuccessRate),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Metric::ActiveKeys => "active_keys",
+            Metric::UsableKeys => "usable_keys",
+            Metric::SuccessRate => "success_rate",
+        }
+    }
+
+    /// The provider's current value, or `None` when there isn't enough data to judge.
+    fn value(self, stats: &ProviderDashboardStats) -> Option<i64> {
+        match self {
+            Metric::ActiveKeys => Some(stats.active_keys),
+            Metric::UsableKeys => Some(stats.active_keys - stats.cooling_keys),
+            Metric::SuccessRate => {
+                let rate = stats.success_rate().filter(|_| stats.requests >= MIN_REQUESTS)?;
+                Some((rate * 100.0).round() as i64)
+            }
+        }
+    }
+
+    fn describe(self, value: i64) -> String {
+        match self {
+            Metric::ActiveKeys => format!("{} active keys", value),
+            Metric::UsableKeys => format!("{} usable keys", value),
+            Metric::SuccessRate => format!("a {}% success rate over the last 15 minutes", value),
+        }
+    }
+}
+
+/// Fires while the metric is below the threshold.
+#[derive(Debug, Clone, Copy)]
+struct Condition {
+    metric: Metric,
+    threshold: i64,
+}
+
+#[derive(Debug, Clone)]
+struct AlertRule {
+    pattern: String,
+    conditions: Vec<Condition>,
+}
+
+impl AlertRule {
+    fn matches(&self, provider: &str) -> bool {
+        match self.pattern.strip_suffix('*') {
+            Some(prefix) => provider.starts_with(prefix),
+            None => self.pattern == provider,
+        }
+    }
+}
+
+fn parse_rule(rule: &str) -> Result<AlertRule, String> {
+    let (pattern, conditions) = rule
+        .split_once(':')
+        .ok_or_else(|| format!("'{}' is missing the 'provider-pattern:' prefix", rule))?;
+    let conditions = conditions
+        .split(',')
+        .map(|condition| {
+            let (metric, threshold) = condition
+                .split_once('<')
+                .ok_or_else(|| format!("'{}' is not of the form metric<threshold", condition.trim()))?;
+            Ok(Condition {
+                metric: Metric::parse(metric).ok_or_else(|| {
+                    format!("'{}' is not one of active_keys, usable_keys, success_rate", metric.trim())
+                })?,
+                threshold: threshold
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("'{}' is not a whole number", threshold.trim()))?,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(AlertRule {
+        pattern: pattern.trim().to_string(),
+        conditions,
+    })
+}
+
+fn rules(value: &str) -> impl Iterator<Item = Result<AlertRule, String>> + '_ {
+    value.split(';').map(str::trim).filter(|rule| !rule.is_empty()).map(parse_rule)
+}
+
+/// Checks that an `ALERT_RULES` value parses.
+pub fn check_rules(value: &str) -> Result<(), String> {
+    rules(value).try_for_each(|rule| rule.map(|_| ()))
+}
+
+/// Serialized as the webhook event name.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertState {
+    #[serde(rename = "alert_firing")]
+    Firing,
+    #[serde(rename = "alert_resolved")]
+    Resolved,
+}
+
+impl AlertState {
+    fn as_str(self) -> &'static str {
+        match self {
+            AlertState::Firing => "firing",
+            AlertState::Resolved => "resolved",
+        }
+    }
+}
+
+/// The JSON payload of a notification.
+#[derive(Serialize, Debug, Clone)]
+pub struct AlertNotification {
+    pub event: AlertState,
+    pub provider: String,
+    pub metric: String,
+    pub value: i64,
+    pub threshold: i64,
+    pub message: String,
+    /// Unix seconds.
+    pub at: u64,
+}
+
+#[derive(Serialize)]
+struct SlackMessage {
+    text: String,
+}
+
+async fn notify(env: &Env, notification: &AlertNotification) {
+    let Some(url) = env.var("ALERT_WEBHOOK_URL").ok().map(|v| v.to_string()).filter(|v| !v.trim().is_empty()) else {
+        return;
+    };
+    let slack = env.var("ALERT_WEBHOOK_FORMAT").is_ok_and(|v| v.to_string().trim() == "slack");
+    let result = if slack {
+        let icon = match notification.event {
+            AlertState::Firing => ":rotating_light:",
+            AlertState::Resolved => ":white_check_mark:",
+        };
+        let message = SlackMessage {
+            text: format!("{} {}", icon, notification.message),
+        };
+        key_events::post(env, url.trim(), "ALERT_WEBHOOK_SECRET", &message).await
+    } else {
+        key_events::post(env, url.trim(), "ALERT_WEBHOOK_SECRET", notification).await
+    };
+    if let Err(e) = result {
+        error!("Failed to deliver alert to the webhook: {}", e);
+    }
+}
+
+/// Evaluates the alert rules against the current stats and records and notifies every
+/// rule that started or stopped firing since the last run.
+pub async fn evaluate(env: &Env, db: &D1Database, settings: &Settings) {
+    let rules: Vec<AlertRule> = rules(&settings.alert_rules)
+        .filter_map(|rule| rule.inspect_err(|e| warn!("Skipping invalid alert rule: {}", e)).ok())
+        .collect();
+    if rules.is_empty() {
+        return;
+    }
+    let mut stats = match d1_storage::get_provider_stats(db, WINDOW_SECONDS).await {
+        Ok(stats) => stats,
+        Err(e) => {
+            error!("Failed to load provider stats for alerts: {}", e);
+            return;
+        }
+    };
+    // A provider named by a rule still alerts once its last key is gone.
+    for rule in rules.iter().filter(|rule| !rule.pattern.ends_with('*')) {
+        if !stats.iter().any(|s| s.provider == rule.pattern) {
+            stats.push(ProviderDashboardStats {
+                provider: rule.pattern.clone(),
+                active_keys: 0,
+                blocked_keys: 0,
+                cooling_keys: 0,
+                avg_latency_ms: None,
+                requests: 0,
+                successes: 0,
+            });
+        }
+    }
+    let firing: HashSet<(String, String)> = match d1_storage::list_alert_states(db).await {
+        Ok(states) => states
+            .into_iter()
+            .filter(|row| row.state == AlertState::Firing.as_str())
+            .map(|row| (row.provider, row.metric))
+            .collect(),
+        Err(e) => {
+            error!("Failed to load alert states: {}", e);
+            return;
+        }
+    };
+
+    for provider_stats in &stats {
+        let Some(rule) = rules.iter().find(|rule| rule.matches(&provider_stats.provider)) else {
+            continue;
+        };
+        for condition in &rule.conditions {
+            let Some(value) = condition.metric.value(provider_stats) else {
+                continue;
+            };
+            let breached = value < condition.threshold;
+            let was_firing = firing.contains(&(provider_stats.provider.clone(), condition.metric.as_str().to_string()));
+            if breached == was_firing {
+                continue;
+            }
+            let state = if breached { AlertState::Firing } else { AlertState::Resolved };
+            let message = match state {
+                AlertState::Firing => format!(
+                    "{} has {}, below the threshold of {}",
+                    provider_stats.provider,
+                    condition.metric.describe(value),
+                    condition.threshold
+                ),
+                AlertState::Resolved => format!(
+                    "{} is back to {} (threshold {})",
+                    provider_stats.provider,
+                    condition.metric.describe(value),
+                    condition.threshold
+                ),
+            };
+            warn!(
+                provider = %provider_stats.provider,
+                metric = condition.metric.as_str(),
+                state = state.as_str(),
+                "{}",
+                message
+            );
+
+            if let Err(e) = d1_storage::record_alert_event(
+                db,
+                &provider_stats.provider,
+                condition.metric.as_str(),
+                state.as_str(),
+                value,
+                condition.threshold,
+                &message,
+            )
+            .await
+            {
+                error!("Failed to record alert event: {}", e);
+            }
+            let notification = AlertNotification {
+                event: state,
+                provider: provider_stats.provider.clone(),
+                metric: condition.metric.as_str().to_string(),
+                value,
+                threshold: condition.threshold,
+                message,
+                at: (Date::now() / 1000.0) as u64,
+            };
+            notify(env, &notification).await;
+        }
+    }
+}
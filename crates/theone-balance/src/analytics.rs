@@ -0,0 +1,39 @@
+//! This module writes per-request telemetry to Workers Analytics Engine. The `ANALYTICS`
+//! dataset binding is optional; when it isn't configured nothing is written.
+//!
+//! Each datapoint is indexed by provider and carries:
+//! - blobs: provider, model, key hash, status
+//! - doubles: status, latency (ms), prompt tokens, completion tokens, failovers, cooldowns
+
+use crate::metrics::RequestOutcome;
+use tracing::warn;
+use worker::{AnalyticsEngineDataPointBuilder, Env};
+
+const ANALYTICS_BINDING: &str = "ANALYTICS";
+
+/// Writes one datapoint for a proxied request, if the `ANALYTICS` binding exists.
+pub fn write_request(env: &Env, outcome: &RequestOutcome) {
+    let Ok(dataset) = env.analytics_engine(ANALYTICS_BINDING) else {
+        return;
+    };
+    let result = AnalyticsEngineDataPointBuilder::new()
+        .indexes([outcome.provider.as_str()])
+        .blobs([
+            outcome.provider.as_str(),
+            outcome.model.as_str(),
+            outcome.key_hash.as_str(),
+            &outcome.status.to_string(),
+        ])
+        .doubles([
+            outcome.status as f64,
+            outcome.latency_ms as f64,
+            outcome.prompt_tokens as f64,
+            outcome.completion_tokens as f64,
+            outcome.failovers as f64,
+            outcome.cooldowns as f64,
+        ])
+        .write_to(&dataset);
+    if let Err(e) = result {
+        warn!("Failed to write analytics datapoint: {}", e);
+    }
+}
@@ -0,0 +1,370 @@
+//! This module contains the sessions shared by the web UI and the admin API. Logging in
+//! with the master key or a client key with a role starts a session: a row in the
+//! `sessions` table, referenced by a `session` cookie of the form
+//! `{id}.{expires_at}.{signature}`. The signature is an HMAC-SHA256 of the id and expiry
+//! under the `SESSION_SECRET` secret, or the AUTH_KEY when that isn't set, so forged and
+//! expired cookies are turned away without a D1 lookup and the key itself never ends up
+//! in the browser.
+//!
+//! Sessions end after `SESSION_TTL_SECONDS`, on logout, when an admin revokes them, and
+//! when their client key is revoked, deleted or given another role.
+//!
+//! Callers who enabled a second factor (see `totp`) get no session for their key alone:
+//! they get a short-lived signed `login_challenge` cookie instead, which a valid code turns
+//! into a session. A device can be remembered for `TOTP_REMEMBER_DAYS` with a signed
+//! `trusted_device` cookie, bound to the enrollment so re-provisioning forgets it.
+//!
+//! Forms posted with a session carry its CSRF token, an HMAC of the session id, so other
+//! sites can't submit them on the caller's behalf. Being derived, it needs no storage and
+//! changes with every login.
+
+use crate::{
+    d1_storage,
+    settings::Settings,
+    state::strategy::Role,
+    totp,
+    util::{self, Caller},
+    AppState,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use serde::{Deserialize, Serialize};
+use tower_cookies::cookie::SameSite;
+use tower_cookies::{Cookie, Cookies};
+use tracing::{error, warn};
+use worker::js_sys::{self, Array, Function, Object, Promise, Reflect, Uint8Array};
+use worker::wasm_bindgen::{JsCast, JsValue};
+use worker::wasm_bindgen_futures::JsFuture;
+use worker::{Date, Env, Result};
+
+/// The cookie holding the signed session token.
+pub const SESSION_COOKIE: &str = "session";
+/// Who logged in with their key and still has to enter a code.
+const CHALLENGE_COOKIE: &str = "login_challenge";
+const CHALLENGE_TTL_SECONDS: u64 = 5 * 60;
+/// A device whose codes were accepted and which skips the code until the cookie expires.
+const DEVICE_COOKIE: &str = "trusted_device";
+/// The cookie that held the raw auth key before sessions; removed on login and logout.
+const LEGACY_COOKIE: &str = "auth_key";
+/// The subject of the master key's sessions and calls.
+const MASTER_SUBJECT: &str = "master";
+
+/// A session, as stored in the `sessions` table.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Session {
+    pub id: String,
+    /// The client key id, or empty for the master key.
+    pub client_id: String,
+    pub role: Role,
+    pub created_at: i64,
+    pub expires_at: i64,
+}
+
+/// Who acts in the UI or the admin API, and with which role.
+#[derive(Clone, Debug)]
+pub struct Principal {
+    /// `master`, or the id of the client key. Admin rate limits are counted per subject.
+    pub subject: String,
+    pub role: Role,
+    /// The session the caller is logged in with; `None` for Bearer calls of the admin API.
+    pub session_id: Option<String>,
+}
+
+impl Principal {
+    fn from_session(session: Session) -> Self {
+        let subject = if session.client_id.is_empty() {
+            MASTER_SUBJECT.to_string()
+        } else {
+            session.client_id
+        };
+        Principal {
+            subject,
+            role: session.role,
+            session_id: Some(session.id),
+        }
+    }
+}
+
+/// What happened with a login's key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginStep {
+    SessionStarted,
+    /// The caller has a second factor; see `complete_login`.
+    CodeRequired,
+}
+
+/// Resolves a UI or admin API credential: the master key is an admin, client keys act with
+/// the role they were given. Keys without a role only proxy requests.
+pub async fn authenticate_key(key: &str, state: &AppState) -> Option<Principal> {
+    match util::authenticate(key, state).await? {
+        Caller::Master => Some(Principal {
+            subject: MASTER_SUBJECT.to_string(),
+            role: Role::Admin,
+            session_id: None,
+        }),
+        Caller::Client(client) => client.role.map(|role| Principal {
+            subject: client.id,
+            role,
+            session_id: None,
+        }),
+    }
+}
+
+/// Logs `principal` in after their key was checked: starts a session and sets its cookie,
+/// unless they have a second factor and this isn't a remembered device.
+pub async fn login(state: &AppState, principal: &Principal, cookies: &Cookies) -> Result<LoginStep> {
+    let (env, settings) = (&*state.env, &state.settings);
+    let db = state.db()?;
+    if let Some(enrollment) = d1_storage::get_totp(&db, &principal.subject).await?.filter(|e| e.enabled()) {
+        if !is_trusted_device(env, &enrollment, cookies).await {
+            let expires_at = now_seconds() + CHALLENGE_TTL_SECONDS as i64;
+            let payload = format!("{}.{}.{}", principal.subject, principal.role.as_str(), expires_at);
+            let token = sign(env, CHALLENGE_COOKIE, &payload).await?;
+            cookies.add(cookie(settings, CHALLENGE_COOKIE, token, CHALLENGE_TTL_SECONDS));
+            return Ok(LoginStep::CodeRequired);
+        }
+    }
+    start_session(state, principal, cookies).await?;
+    Ok(LoginStep::SessionStarted)
+}
+
+/// The caller who logged in with their key and has yet to enter a code.
+pub async fn pending_challenge(env: &Env, cookies: &Cookies) -> Option<Principal> {
+    let token = cookies.get(CHALLENGE_COOKIE)?.value().to_string();
+    let fields = unsign(env, CHALLENGE_COOKIE, &token).await?;
+    let [subject, role] = fields[..] else {
+        return None;
+    };
+    Some(Principal {
+        subject: subject.to_string(),
+        role: Role::parse(role)?,
+        session_id: None,
+    })
+}
+
+/// Checks the code of a pending login and starts the session when it is valid, remembering
+/// the device when asked to. Returns whether the code was accepted.
+pub async fn complete_login(
+    state: &AppState,
+    principal: &Principal,
+    code: &str,
+    remember_device: bool,
+    cookies: &Cookies,
+) -> Result<bool> {
+    let (env, settings) = (&*state.env, &state.settings);
+    let db = state.db()?;
+    let Some(enrollment) = d1_storage::get_totp(&db, &principal.subject).await?.filter(|e| e.enabled()) else {
+        // The second factor was removed since the key was entered.
+        start_session(state, principal, cookies).await?;
+        cookies.remove(removal_cookie(CHALLENGE_COOKIE));
+        return Ok(true);
+    };
+    if !totp::check(&db, &enrollment, code).await? {
+        warn!(subject = %principal.subject, "Auth Check Failed: invalid second factor code");
+        return Ok(false);
+    }
+
+    start_session(state, principal, cookies).await?;
+    cookies.remove(removal_cookie(CHALLENGE_COOKIE));
+    if remember_device && settings.totp_remember_days > 0 {
+        let ttl_seconds = settings.totp_remember_days * 24 * 60 * 60;
+        let expires_at = now_seconds() + ttl_seconds as i64;
+        let payload = format!("{}.{}.{}", enrollment.subject, enrollment.enabled_at, expires_at);
+        let token = sign(env, DEVICE_COOKIE, &payload).await?;
+        cookies.add(cookie(settings, DEVICE_COOKIE, token, ttl_seconds));
+    }
+    Ok(true)
+}
+
+/// Whether the request carries a remembered-device cookie of this very enrollment.
+async fn is_trusted_device(env: &Env, enrollment: &totp::Enrollment, cookies: &Cookies) -> bool {
+    let Some(token) = cookies.get(DEVICE_COOKIE).map(|cookie| cookie.value().to_string()) else {
+        return false;
+    };
+    let Some(fields) = unsign(env, DEVICE_COOKIE, &token).await else {
+        return false;
+    };
+    matches!(fields[..], [subject, enabled_at] if subject == enrollment.subject && enabled_at == enrollment.enabled_at.to_string())
+}
+
+/// Starts a session for `principal` and sets its cookie.
+async fn start_session(state: &AppState, principal: &Principal, cookies: &Cookies) -> Result<()> {
+    let (env, settings) = (&*state.env, &state.settings);
+    let db = state.db()?;
+    let client_id = if principal.subject == MASTER_SUBJECT { "" } else { &principal.subject };
+    let session = d1_storage::create_session(&db, client_id, principal.role, settings.session_ttl_seconds).await?;
+
+    let token = sign(env, SESSION_COOKIE, &format!("{}.{}", session.id, session.expires_at)).await?;
+    cookies.add(cookie(settings, SESSION_COOKIE, token, settings.session_ttl_seconds));
+    cookies.remove(removal_cookie(LEGACY_COOKIE));
+    Ok(())
+}
+
+/// The principal behind the request's session cookie, if its signature holds and the
+/// session is neither expired nor revoked.
+pub async fn from_cookies(state: &AppState, cookies: &Cookies) -> Option<Principal> {
+    let token = cookies.get(SESSION_COOKIE)?.value().to_string();
+    verify(state, &token).await
+}
+
+/// Checks a session token, see the module docs for its format.
+pub async fn verify(state: &AppState, token: &str) -> Option<Principal> {
+    let fields = unsign(&state.env, SESSION_COOKIE, token).await?;
+    let [id] = fields[..] else {
+        return None;
+    };
+
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => {
+            error!("Failed to get D1 binding for session lookup: {}", e);
+            return None;
+        }
+    };
+    match d1_storage::find_session_via_cache(&db, id).await {
+        Ok(Some(session)) => Some(Principal::from_session(session)),
+        Ok(None) => {
+            warn!(session_id = %id, "Auth Check Failed: session has been revoked");
+            None
+        }
+        Err(e) => {
+            error!("Failed to look up session: {}", e);
+            None
+        }
+    }
+}
+
+/// Revokes the request's session, if any, and removes its cookie.
+pub async fn logout(state: &AppState, cookies: &Cookies) -> Result<()> {
+    if let Some(session_id) = from_cookies(state, cookies).await.and_then(|principal| principal.session_id) {
+        d1_storage::revoke_session(&state.db()?, &session_id).await?;
+    }
+    cookies.remove(removal_cookie(SESSION_COOKIE));
+    cookies.remove(removal_cookie(CHALLENGE_COOKIE));
+    cookies.remove(removal_cookie(LEGACY_COOKIE));
+    Ok(())
+}
+
+/// The auth cookies are only sent over HTTPS, except to `wrangler dev` (`IS_LOCAL`).
+fn cookie(settings: &Settings, name: &'static str, value: String, max_age_seconds: u64) -> Cookie<'static> {
+    Cookie::build((name, value))
+        .path("/")
+        .http_only(true)
+        .secure(!settings.is_local)
+        .same_site(SameSite::Strict)
+        .max_age(time::Duration::seconds(max_age_seconds as i64))
+        .into()
+}
+
+/// The CSRF token of a session, rendered into its forms.
+pub async fn csrf_token(env: &Env, session_id: &str) -> Result<String> {
+    let secret = signing_secret(env)
+        .ok_or_else(|| worker::Error::RustError("Neither SESSION_SECRET nor AUTH_KEY is set".to_string()))?;
+    let token = hmac("SHA-256", secret.as_bytes(), format!("csrf:{}", session_id).as_bytes()).await?;
+    Ok(URL_SAFE_NO_PAD.encode(token))
+}
+
+/// Whether `submitted` is the CSRF token of `session_id`.
+pub async fn check_csrf(env: &Env, session_id: &str, submitted: Option<&str>) -> bool {
+    match (csrf_token(env, session_id).await, submitted) {
+        (Ok(expected), Some(submitted)) => constant_time_eq(expected.as_bytes(), submitted.as_bytes()),
+        (Err(e), _) => {
+            error!("Failed to derive the CSRF token: {}", e);
+            false
+        }
+        (Ok(_), None) => false,
+    }
+}
+
+/// A cookie to remove `name` with; the path must match the one it was set with.
+fn removal_cookie(name: &'static str) -> Cookie<'static> {
+    Cookie::build((name, "")).path("/").into()
+}
+
+fn now_seconds() -> i64 {
+    (Date::now().as_millis() / 1000) as i64
+}
+
+/// Appends the signature of `payload` for `purpose`, so a token issued for one cookie
+/// can't be passed off as another. Payloads end with their expiry, see `unsign`.
+async fn sign(env: &Env, purpose: &str, payload: &str) -> Result<String> {
+    let secret = signing_secret(env)
+        .ok_or_else(|| worker::Error::RustError("Neither SESSION_SECRET nor AUTH_KEY is set".to_string()))?;
+    let signature = hmac("SHA-256", secret.as_bytes(), format!("{}:{}", purpose, payload).as_bytes()).await?;
+    Ok(format!("{}.{}", payload, URL_SAFE_NO_PAD.encode(signature)))
+}
+
+/// The `.`-separated fields of a token `sign` made for `purpose`, without the trailing
+/// expiry, if the signature holds and the token hasn't expired.
+async fn unsign<'a>(env: &Env, purpose: &str, token: &'a str) -> Option<Vec<&'a str>> {
+    let (payload, signature) = token.rsplit_once('.')?;
+    let mut fields: Vec<&str> = payload.split('.').collect();
+    let expires_at: i64 = fields.pop()?.parse().ok()?;
+    if expires_at <= now_seconds() {
+        return None;
+    }
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+    let secret = signing_secret(env)?;
+    let expected = match hmac("SHA-256", secret.as_bytes(), format!("{}:{}", purpose, payload).as_bytes()).await {
+        Ok(expected) => expected,
+        Err(e) => {
+            error!("Failed to sign the {} token: {}", purpose, e);
+            return None;
+        }
+    };
+    if !constant_time_eq(&expected, &signature) {
+        warn!("Auth Check Failed: {} token with an invalid signature", purpose);
+        return None;
+    }
+    Some(fields)
+}
+
+/// Sessions are signed with their own secret when one is set, so rotating it logs everyone
+/// out without changing the master key.
+fn signing_secret(env: &Env) -> Option<String> {
+    env.secret("SESSION_SECRET")
+        .or_else(|_| env.secret("AUTH_KEY"))
+        .ok()
+        .map(|secret| secret.to_string())
+        .filter(|secret| !secret.is_empty())
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The SHA-256 digest of `data`, through the runtime's WebCrypto.
+pub(crate) async fn sha256(data: &[u8]) -> Result<Vec<u8>> {
+    let crypto = Reflect::get(&js_sys::global(), &JsValue::from_str("crypto"))?;
+    let subtle = Reflect::get(&crypto, &JsValue::from_str("subtle"))?;
+    let digest: Function = Reflect::get(&subtle, &JsValue::from_str("digest"))?.dyn_into()?;
+    let promise: Promise = digest
+        .call2(&subtle, &JsValue::from_str("SHA-256"), &Uint8Array::from(data))?
+        .dyn_into()?;
+    let hash = JsFuture::from(promise).await?;
+    Ok(Uint8Array::new(&hash).to_vec())
+}
+
+/// Signs `data` with HMAC over `hash` (e.g. `SHA-256`) through the runtime's WebCrypto.
+pub(crate) async fn hmac(hash: &str, secret: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let crypto = Reflect::get(&js_sys::global(), &JsValue::from_str("crypto"))?;
+    let subtle = Reflect::get(&crypto, &JsValue::from_str("subtle"))?;
+    let algorithm = Object::new();
+    Reflect::set(&algorithm, &JsValue::from_str("name"), &JsValue::from_str("HMAC"))?;
+    Reflect::set(&algorithm, &JsValue::from_str("hash"), &JsValue::from_str(hash))?;
+
+    let import_key: Function = Reflect::get(&subtle, &JsValue::from_str("importKey"))?.dyn_into()?;
+    let import_args = Array::of5(
+        &JsValue::from_str("raw"),
+        &Uint8Array::from(secret),
+        &algorithm,
+        &JsValue::FALSE,
+        &Array::of1(&JsValue::from_str("sign")),
+    );
+    let promise: Promise = import_key.apply(&subtle, &import_args)?.dyn_into()?;
+    let crypto_key = JsFuture::from(promise).await?;
+
+    let sign: Function = Reflect::get(&subtle, &JsValue::from_str("sign"))?.dyn_into()?;
+    let promise: Promise = sign.call3(&subtle, &algorithm, &crypto_key, &Uint8Array::from(data))?.dyn_into()?;
+    let signature = JsFuture::from(promise).await?;
+    Ok(Uint8Array::new(&signature).to_vec())
+}
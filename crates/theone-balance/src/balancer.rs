@@ -0,0 +1,264 @@
+//! This module is the balancer as a library: how keys are ranked for a request, how a
+//! provider failure is classified, and how OpenAI-shaped requests are translated for other
+//! providers. Nothing here touches the worker entrypoints, the D1 binding or the clock, so
+//! it can be embedded in other Rust services or tested natively:
+//!
+//! - [`KeyStore`] is where keys come from and where attempt outcomes go. The worker
+//!   implements it on `D1Database` (see `d1_storage`); an embedder brings its own.
+//! - [`healthy_keys`] loads a provider's keys from a store and ranks them, [`rank_keys`]
+//!   and [`score_keys`] do the same for keys at hand, and [`order_by_tier`] applies a
+//!   [`TierStrategy`]. Time is passed in as Unix seconds.
+//! - [`analyze_provider_error`] turns a failed upstream response into an [`ErrorAnalysis`],
+//!   which says whether to cool the key down, disable it or give up.
+//! - [`embeddings_provider`] and the `translate_*` functions convert between the OpenAI
+//!   shape and the provider APIs.
+//!
+//! The worker's own modules build on these; everything else in the crate (routing,
+//! caching, admin, UI) is the Cloudflare deployment around them.
+
+pub use crate::embeddings::{provider as embeddings_provider, EmbeddingsProvider};
+pub use crate::error_handling::{analyze_provider_error, decode_error_body, ErrorAnalysis};
+pub use crate::gcp::{
+    translate_chat_request, translate_chat_response, translate_embeddings_request, translate_embeddings_response,
+};
+pub use crate::settings::{ScoringConfig, Settings, TierStrategy};
+pub use crate::state::strategy::{ApiKey, ApiKeyStatus, KeyScore, KeyTier, LatencyHistogram};
+
+/// How long a key past `Settings::recovery_threshold` consecutive failures sits out
+/// before it gets another chance.
+pub const RECOVERY_PERIOD_SECONDS: u64 = 3600;
+
+/// A source of keys and a sink for attempt outcomes.
+// The futures aren't `Send`: the worker's D1 futures can't be.
+#[allow(async_fn_in_trait)]
+pub trait KeyStore {
+    type Error;
+
+    /// The provider's keys with the active status that aren't cooling down.
+    async fn active_keys(&self, provider: &str) -> Result<Vec<ApiKey>, Self::Error>;
+
+    /// Records the outcome and latency of one attempt with a key.
+    async fn record_attempt(&self, key_id: &str, success: bool, latency_ms: i64) -> Result<(), Self::Error>;
+}
+
+/// Loads the provider's keys from `store` and ranks them with [`rank_keys`].
+pub async fn healthy_keys<S: KeyStore>(
+    store: &S,
+    settings: &Settings,
+    provider: &str,
+    now: u64,
+) -> Result<Vec<ApiKey>, S::Error> {
+    let keys = store.active_keys(provider).await?;
+    Ok(rank_keys(settings, keys, now))
+}
+
+/// Drops keys that failed too often recently, then orders the rest by their health score,
+/// best first.
+pub fn rank_keys(settings: &Settings, keys: Vec<ApiKey>, now: u64) -> Vec<ApiKey> {
+    let keys: Vec<ApiKey> = keys
+        .into_iter()
+        .filter(|key| {
+            // A key past the threshold gets a probationary attempt once it sat out long enough.
+            key.consecutive_failures < settings.recovery_threshold
+                || now.saturating_sub(key.last_checked_at) > RECOVERY_PERIOD_SECONDS
+        })
+        .collect();
+
+    let scores = score_keys(settings, &keys, now);
+    let mut scored: Vec<(ApiKey, KeyScore)> = keys.into_iter().zip(scores).collect();
+    scored.sort_by_key(|(_, score)| std::cmp::Reverse(score.total));
+    scored.into_iter().map(|(key, _)| key).collect()
+}
+
+/// Scores each of `keys` for the failover order, weighted by `Settings::scoring`. The
+/// fairness component compares a key with the average of `keys`, so the same key can score
+/// differently in another set.
+pub fn score_keys(settings: &Settings, keys: &[ApiKey], now: u64) -> Vec<KeyScore> {
+    // How strongly traffic is spread across keys; 0 ranks purely by health.
+    let fairness_weight = settings.key_fairness_weight;
+    let weights = settings.scoring;
+    let average_recent_requests =
+        (keys.iter().map(|k| k.recent_requests).sum::<u64>() / keys.len().max(1) as u64).max(1);
+
+    keys.iter()
+        .map(|key| {
+            // Lower latency is better, higher success rate is better. The p95 is steadier than
+            // the last latency and catches keys with slow tails; keys without a histogram yet
+            // fall back to their last latency.
+            let latency = if key.latency_p95_ms > 0 { key.latency_p95_ms } else { key.latency_ms };
+            let latency_score = (10000 - latency) * weights.latency_weight_percent / 100;
+            // key.success_rate is a float between 0.0 and 1.0. Scale it for the score.
+            let success_score = (key.success_rate * 1000.0) as i64 * weights.success_weight_percent / 100;
+
+            // Penalize consecutive failures heavily.
+            let failure_penalty = key.consecutive_failures * weights.failure_penalty;
+
+            // Add a small bonus for recently successful keys to break ties.
+            let recent_success_bonus = if now.saturating_sub(key.last_succeeded_at) < weights.recent_success_window_seconds {
+                weights.recent_success_bonus
+            } else {
+                0
+            };
+
+            // Keys that served more than their share recently rank lower, and idle keys higher,
+            // so the fastest key doesn't absorb all traffic and run into its quota.
+            let fairness_penalty = (key.recent_requests as i64 - average_recent_requests as i64)
+                * fairness_weight
+                / average_recent_requests as i64;
+
+            KeyScore {
+                latency_score,
+                success_score,
+                failure_penalty,
+                recent_success_bonus,
+                fairness_penalty,
+                // An operator's manual weight, set on the keys page.
+                weight: key.weight,
+                total: latency_score + success_score - failure_penalty + recent_success_bonus - fairness_penalty
+                    + key.weight,
+            }
+        })
+        .collect()
+}
+
+/// Orders ranked keys by tier. The sort is stable, so keys keep their health ranking
+/// within a tier.
+pub fn order_by_tier(strategy: TierStrategy, keys: &mut [ApiKey]) {
+    match strategy {
+        TierStrategy::FreeFirst => keys.sort_by_key(|key| key.tier != KeyTier::Free),
+        TierStrategy::PaidFirst => keys.sort_by_key(|key| key.tier != KeyTier::Paid),
+        TierStrategy::Mixed => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::FutureExt;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    fn key(id: &str, latency_ms: i64, consecutive_failures: i64, tier: KeyTier) -> ApiKey {
+        ApiKey {
+            id: id.to_string(),
+            key: format!("sk-{}", id),
+            provider: "openai".to_string(),
+            status: ApiKeyStatus::Active,
+            model_coolings: HashMap::new(),
+            total_cooling_seconds: 0,
+            created_at: 0,
+            updated_at: 0,
+            latency_ms,
+            latency_ewma_ms: latency_ms,
+            latency_p50_ms: 0,
+            latency_p95_ms: 0,
+            success_rate: 1.0,
+            consecutive_failures,
+            last_checked_at: 1_000,
+            last_succeeded_at: 0,
+            recent_requests: 0,
+            tier,
+            block_reason: String::new(),
+            note: String::new(),
+            tags: Vec::new(),
+            weight: 0,
+        }
+    }
+
+    #[derive(Default)]
+    struct MemoryStore {
+        keys: Vec<ApiKey>,
+        attempts: RefCell<Vec<(String, bool)>>,
+    }
+
+    impl KeyStore for MemoryStore {
+        type Error = ();
+
+        async fn active_keys(&self, provider: &str) -> Result<Vec<ApiKey>, ()> {
+            Ok(self.keys.iter().filter(|k| k.provider == provider).cloned().collect())
+        }
+
+        async fn record_attempt(&self, key_id: &str, success: bool, _latency_ms: i64) -> Result<(), ()> {
+            self.attempts.borrow_mut().push((key_id.to_string(), success));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ranks_keys_from_a_store_by_health() {
+        let store = MemoryStore {
+            keys: vec![
+                key("slow", 900, 0, KeyTier::Free),
+                key("fast", 100, 0, KeyTier::Free),
+                key("failing", 50, 10, KeyTier::Free),
+            ],
+            ..Default::default()
+        };
+        let ranked = healthy_keys(&store, &Settings::default(), "openai", 2_000)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let ids: Vec<&str> = ranked.iter().map(|k| k.id.as_str()).collect();
+        assert_eq!(ids, ["fast", "slow"]);
+
+        store.record_attempt("fast", true, 120).now_or_never().unwrap().unwrap();
+        assert_eq!(store.attempts.borrow().as_slice(), [("fast".to_string(), true)]);
+    }
+
+    #[test]
+    fn failing_keys_get_another_chance_after_the_recovery_period() {
+        let keys = vec![key("failing", 50, 10, KeyTier::Free)];
+        let now = 1_000 + RECOVERY_PERIOD_SECONDS + 1;
+        assert_eq!(rank_keys(&Settings::default(), keys, now).len(), 1);
+    }
+
+    #[test]
+    fn slow_tails_outweigh_a_fast_last_attempt() {
+        let mut histogram = LatencyHistogram::default();
+        for latency in [80, 90, 90, 95, 4_000] {
+            histogram.record(latency);
+        }
+        assert!(histogram.quantile(0.5) <= 100);
+        assert!(histogram.quantile(0.95) > 3_000);
+
+        let mut spiky = key("spiky", 90, 0, KeyTier::Free);
+        spiky.latency_p95_ms = histogram.quantile(0.95);
+        let steady = key("steady", 400, 0, KeyTier::Free);
+        let ranked = rank_keys(&Settings::default(), vec![spiky, steady], 2_000);
+        let ids: Vec<&str> = ranked.iter().map(|k| k.id.as_str()).collect();
+        assert_eq!(ids, ["steady", "spiky"]);
+    }
+
+    #[test]
+    fn scoring_weights_come_from_the_settings() {
+        let keys = vec![key("fast-failing", 200, 3, KeyTier::Free), key("slow", 300, 0, KeyTier::Free)];
+        let ids = |ranked: Vec<ApiKey>| ranked.into_iter().map(|k| k.id).collect::<Vec<_>>();
+        assert_eq!(ids(rank_keys(&Settings::default(), keys.clone(), 2_000)), ["slow", "fast-failing"]);
+
+        let mut settings = Settings::default();
+        settings.scoring.failure_penalty = 10;
+        assert_eq!(ids(rank_keys(&settings, keys, 2_000)), ["fast-failing", "slow"]);
+    }
+
+    #[test]
+    fn weight_moves_a_key_up_the_failover_order() {
+        let mut slow = key("slow", 300, 0, KeyTier::Free);
+        let fast = key("fast", 200, 0, KeyTier::Free);
+        slow.weight = 150;
+        let ranked = rank_keys(&Settings::default(), vec![fast, slow], 2_000);
+        let ids: Vec<&str> = ranked.iter().map(|k| k.id.as_str()).collect();
+        assert_eq!(ids, ["slow", "fast"]);
+    }
+
+    #[test]
+    fn tier_order_keeps_the_health_ranking_within_a_tier() {
+        let mut keys = vec![
+            key("paid", 100, 0, KeyTier::Paid),
+            key("free-a", 200, 0, KeyTier::Free),
+            key("free-b", 300, 0, KeyTier::Free),
+        ];
+        order_by_tier(TierStrategy::FreeFirst, &mut keys);
+        let ids: Vec<&str> = keys.iter().map(|k| k.id.as_str()).collect();
+        assert_eq!(ids, ["free-a", "free-b", "paid"]);
+    }
+}
@@ -1,23 +1,32 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use std::io::Write;
+use std::time::Duration;
 use tracing::info;
 
 use crate::cli::{
-    args::SyncArgs,
+    args::{ExportArgs, StatsArgs, SyncArgs, ValidateArgs},
+    key_format,
     source::{KeySource, Source},
-    targets::{KeyTarget, Target},
+    targets::{ApiTarget, KeyTarget, ProviderStatus, Target},
+    utils::confirm,
+    validate::{self, Outcome},
+    watch,
 };
 
 pub struct App;
 
 impl App {
     pub async fn sync(args: SyncArgs) -> Result<()> {
+        if args.watch {
+            return watch::run(&args).await;
+        }
         info!(
             "Starting sync from {:?} to {:?}...",
-            args.source, args.target
+            args.input.source, args.target
         );
 
-        let source = Source::from_config(args.source, args.source_name).await?;
-        let mut target = Target::from_config(args.target, args.target_name).await?;
+        let source = Source::from_config(&args.input).await?;
+        let mut target = Target::from_config(&args).await?;
 
         let keys = source.fetch_keys().await?;
         info!("Fetched {} keys from source.", keys.len());
@@ -32,4 +41,142 @@ impl App {
 
         Ok(())
     }
+
+    pub async fn validate(args: ValidateArgs) -> Result<()> {
+        let provider = args
+            .input
+            .provider
+            .clone()
+            .ok_or_else(|| anyhow!("The provider to validate is required. Use --provider."))?;
+        // Resolve the target first, so a missing configuration fails before any key is tested.
+        let target = if args.prune { Some(ApiTarget::new(None, 500, false)?) } else { None };
+
+        let source = Source::from_config(&args.input).await?;
+        let keys: Vec<String> = source
+            .fetch_keys()
+            .await?
+            .into_iter()
+            .filter(|k| k.provider == provider)
+            .map(|k| k.key)
+            .collect();
+        if keys.is_empty() {
+            info!("No {} keys to validate. Exiting.", provider);
+            return Ok(());
+        }
+        info!(provider = %provider, model = ?args.model, "Testing {} keys...", keys.len());
+
+        let checks = validate::check_keys(
+            &provider,
+            keys,
+            args.model.as_deref(),
+            args.concurrency,
+            Duration::from_secs(args.timeout),
+        )
+        .await?;
+        for check in &checks {
+            println!(
+                "{:<5}  {:<16} {:>6} ms  {}",
+                check.outcome.label(),
+                key_format::partially_redact_key(&check.key),
+                check.latency.as_millis(),
+                check.details
+            );
+        }
+        let count = |outcome| checks.iter().filter(|c| c.outcome == outcome).count();
+        let errored = count(Outcome::Error);
+        println!(
+            "{} keys passed, {} failed, {} could not be tested.",
+            count(Outcome::Pass),
+            count(Outcome::Fail),
+            errored
+        );
+        let failed: Vec<String> = checks
+            .into_iter()
+            .filter(|c| c.outcome == Outcome::Fail)
+            .map(|c| c.key)
+            .collect();
+
+        if failed.is_empty() && errored == 0 {
+            return Ok(());
+        }
+        // Only keys the provider rejected are pruned; the others may be fine.
+        let Some(target) = target.filter(|_| !failed.is_empty()) else {
+            return Err(anyhow!("{} keys failed validation and {} could not be tested.", failed.len(), errored));
+        };
+        if !args.input.yes && !confirm(&format!("Move the {} failed keys to the worker's trash?", failed.len()))? {
+            return Err(anyhow!("Pruning cancelled."));
+        }
+        let trashed = target.trash_keys(&provider, &failed).await?;
+        println!("Moved {} keys to the trash ({} were not in the worker).", trashed, failed.len() - trashed);
+        Ok(())
+    }
+
+    pub async fn stats(args: StatsArgs) -> Result<()> {
+        let target = ApiTarget::new(None, 500, false)?;
+        let mut report = target.status().await?;
+        if let Some(provider) = &args.provider {
+            report.providers.retain(|p| &p.provider == provider);
+            if report.providers.is_empty() {
+                return Err(anyhow!("The worker has no {} keys.", provider));
+            }
+        }
+        if args.json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            return Ok(());
+        }
+
+        println!(
+            "{:<20} {:<12} {:>7} {:>8} {:>8} {:>9} {:>8} {:>9}",
+            "PROVIDER", "STATUS", "ACTIVE", "COOLING", "BLOCKED", "REQUESTS", "SUCCESS", "LATENCY"
+        );
+        for p in &report.providers {
+            print_status_row(p);
+        }
+        if report.providers.len() > 1 {
+            let requests: i64 = report.providers.iter().map(|p| p.requests).sum();
+            // Weighted by traffic, so the busy providers count for more.
+            let failed: f64 = report
+                .providers
+                .iter()
+                .filter_map(|p| p.error_rate.map(|rate| rate * p.requests as f64))
+                .sum();
+            print_status_row(&ProviderStatus {
+                provider: "total".to_string(),
+                status: String::new(),
+                active_keys: report.providers.iter().map(|p| p.active_keys).sum(),
+                cooling_keys: report.providers.iter().map(|p| p.cooling_keys).sum(),
+                blocked_keys: report.providers.iter().map(|p| p.blocked_keys).sum(),
+                breaker: String::new(),
+                requests,
+                error_rate: (requests > 0).then(|| failed / requests as f64),
+                avg_latency_ms: None,
+            });
+        }
+        println!("Traffic over the last {} minutes.", report.window_seconds / 60);
+        Ok(())
+    }
+
+    pub async fn export(args: ExportArgs) -> Result<()> {
+        let target = ApiTarget::new(None, 500, false)?;
+        let body = target.export_keys(args.provider.as_deref(), args.format.as_str()).await?;
+        match &args.output {
+            Some(path) => {
+                std::fs::write(path, &body)?;
+                info!("Wrote the export to {}.", path.display());
+            }
+            None => std::io::stdout().write_all(&body)?,
+        }
+        Ok(())
+    }
+}
+
+fn print_status_row(p: &ProviderStatus) {
+    let success = p
+        .error_rate
+        .map_or("-".to_string(), |rate| format!("{:.1}%", (1.0 - rate) * 100.0));
+    let latency = p.avg_latency_ms.map_or("-".to_string(), |ms| format!("{:.0} ms", ms));
+    println!(
+        "{:<20} {:<12} {:>7} {:>8} {:>8} {:>9} {:>8} {:>9}",
+        p.provider, p.status, p.active_keys, p.cooling_keys, p.blocked_keys, p.requests, success, latency
+    );
 }
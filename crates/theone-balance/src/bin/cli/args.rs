@@ -1,4 +1,5 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
 
 use crate::cli::config::ConfigSource;
 
@@ -12,19 +13,119 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Commands {
     Sync(SyncArgs),
+    /// Test keys against their provider from this machine, optionally trashing the failures.
+    Validate(ValidateArgs),
+    /// Print the worker's key counts, cooling keys and success rates per provider.
+    Stats(StatsArgs),
+    /// Download the worker's keys with their health metrics, through the admin API.
+    Export(ExportArgs),
 }
 
+/// Where keys are read from.
 #[derive(Args)]
-pub struct SyncArgs {
+pub struct SourceArgs {
     #[arg(short, long, value_enum)]
     pub source: ConfigSource,
 
+    #[arg(long)]
+    pub source_name: Option<String>,
+
+    /// Provider for keys from a file or the environment whose format isn't recognized.
+    #[arg(long)]
+    pub provider: Option<String>,
+
+    /// Skip the confirmations, e.g. after the detected providers are shown.
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+#[derive(Args)]
+pub struct SyncArgs {
+    #[command(flatten)]
+    pub input: SourceArgs,
+
     #[arg(short, long, value_enum)]
     pub target: ConfigSource,
 
     #[arg(long)]
-    pub source_name: Option<String>,
+    pub target_name: Option<String>,
 
+    /// Print what would be synced without uploading anything (api target).
     #[arg(long)]
-    pub target_name: Option<String>,
+    pub dry_run: bool,
+
+    /// Keys per upload request (api target).
+    #[arg(long, default_value_t = 500)]
+    pub chunk_size: usize,
+
+    /// Keep running and sync the changes of the source every --interval (api target).
+    #[arg(long)]
+    pub watch: bool,
+
+    /// Seconds between two syncs with --watch.
+    #[arg(long, default_value_t = 300)]
+    pub interval: u64,
+}
+
+#[derive(Args)]
+pub struct ValidateArgs {
+    /// The keys to test; only those of --provider are tested.
+    #[command(flatten)]
+    pub input: SourceArgs,
+
+    /// Send a one-word chat request to this model instead of listing the provider's models.
+    #[arg(long)]
+    pub model: Option<String>,
+
+    /// Keys tested at the same time.
+    #[arg(long, default_value_t = 4)]
+    pub concurrency: usize,
+
+    /// Seconds before a test request counts as failed.
+    #[arg(long, default_value_t = 30)]
+    pub timeout: u64,
+
+    /// Move the keys that failed to the trash of the worker, through the admin API.
+    #[arg(long)]
+    pub prune: bool,
+}
+
+#[derive(Args)]
+pub struct StatsArgs {
+    /// Only show this provider.
+    #[arg(long)]
+    pub provider: Option<String>,
+
+    /// Print the status as JSON instead of a table.
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Only export this provider's keys.
+    #[arg(long)]
+    pub provider: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+    pub format: ExportFormat,
+
+    /// Write the export to this file instead of stdout.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
 }
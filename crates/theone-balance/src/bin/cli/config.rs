@@ -5,4 +5,10 @@ use serde::{Deserialize, Serialize};
 pub enum ConfigSource {
     OneBalance,
     TheOne,
+    /// The worker's JSON admin API, with the auth key as a Bearer token.
+    Api,
+    /// A file of keys: one per line or separated by commas, or CSV or JSON with a provider column.
+    File,
+    /// Environment variables: `SYNC_KEYS` and `SYNC_KEYS_<PROVIDER>`, or another prefix given as --source-name.
+    Env,
 }
@@ -1,7 +1,25 @@
 pub mod app;
 pub mod args;
+// Shared with the worker: the model list endpoints, for testing keys.
+#[allow(dead_code)]
+#[path = "../../compat.rs"]
+pub mod compat;
 pub mod config;
+// Shared with the worker, for reading key export CSV files.
+#[allow(dead_code)]
+#[path = "../../csv_line.rs"]
+pub mod csv_line;
+// Shared with the worker, for detecting the provider of a key.
+#[allow(dead_code)]
+#[path = "../../key_format.rs"]
+pub mod key_format;
+// Shared with the worker, for building the requests that test a key.
+#[allow(dead_code)]
+#[path = "../../key_probe.rs"]
+pub mod key_probe;
 pub mod source;
 pub mod targets;
 pub mod types;
 pub mod utils;
+pub mod validate;
+pub mod watch;
@@ -1,10 +1,13 @@
 use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, HashSet};
 use tracing::info;
 
-use crate::cli::{config::ConfigSource, types::ApiKey};
+use crate::cli::{args::SourceArgs, config::ConfigSource, key_format, types::ApiKey, utils::confirm};
 
-use self::one_balance::OneBalanceSource;
+use self::{env::EnvSource, file::FileSource, one_balance::OneBalanceSource};
 
+mod env;
+mod file;
 mod one_balance;
 
 pub trait KeySource {
@@ -13,15 +16,26 @@ pub trait KeySource {
 
 pub enum Source {
     OneBalance(OneBalanceSource),
+    File(FileSource),
+    Env(EnvSource),
 }
 
 impl Source {
-    pub async fn from_config(source: ConfigSource, name: Option<String>) -> Result<Self> {
-        match source {
+    pub async fn from_config(args: &SourceArgs) -> Result<Self> {
+        let name = args.source_name.clone();
+        match args.source {
             ConfigSource::OneBalance => {
                 let source = OneBalanceSource::new(name).await?;
                 Ok(Self::OneBalance(source))
             }
+            ConfigSource::File => {
+                let source = FileSource::new(name, args.provider.clone(), args.yes)?;
+                Ok(Self::File(source))
+            }
+            ConfigSource::Env => {
+                let source = EnvSource::new(name, args.provider.clone(), args.yes)?;
+                Ok(Self::Env(source))
+            }
             _ => Err(anyhow!("Unsupported source type")),
         }
     }
@@ -32,6 +46,83 @@ impl KeySource for Source {
         info!("Fetching keys from source...");
         match self {
             Self::OneBalance(source) => source.fetch_keys().await,
+            Self::File(source) => source.fetch_keys().await,
+            Self::Env(source) => source.fetch_keys().await,
+        }
+    }
+}
+
+/// Groups `(provider, key)` pairs by provider. Keys without a provider get the one their
+/// format points to, or `fallback`; duplicates are dropped.
+fn group_keys(entries: Vec<(Option<String>, String)>, fallback: &str) -> BTreeMap<String, Vec<String>> {
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut seen = HashSet::new();
+    for (provider, key) in entries {
+        let provider = provider.unwrap_or_else(|| key_format::detect(&key).unwrap_or(fallback).to_string());
+        if seen.insert((provider.clone(), key.clone())) {
+            groups.entry(provider).or_default().push(key);
         }
     }
+    groups
+}
+
+/// Checks the keys of a local source against their provider's format, shows the count
+/// per provider and, unless `assume_yes`, asks before going on.
+fn review_keys(origin: &str, groups: BTreeMap<String, Vec<String>>, assume_yes: bool) -> Result<Vec<ApiKey>> {
+    if let Some(unknown) = groups.get("") {
+        return Err(anyhow!(
+            "{} keys have no recognizable format (e.g. {}). Use --provider to assign them.",
+            unknown.len(),
+            key_format::partially_redact_key(&unknown[0])
+        ));
+    }
+    let rejected: Vec<String> = groups
+        .iter()
+        .flat_map(|(provider, keys)| keys.iter().filter_map(|key| key_format::check(provider, key).err()))
+        .collect();
+    if !rejected.is_empty() {
+        return Err(anyhow!("Invalid keys in {}: {}", origin, rejected.join("; ")));
+    }
+
+    println!("Keys in {} by provider:", origin);
+    for (provider, keys) in &groups {
+        println!("  {:<20} {}", provider, keys.len());
+    }
+    if !assume_yes && !confirm("Sync these keys?")? {
+        return Err(anyhow!("Sync cancelled."));
+    }
+
+    Ok(groups
+        .into_iter()
+        .flat_map(|(provider, keys)| {
+            keys.into_iter().map(move |key| ApiKey {
+                key,
+                provider: provider.clone(),
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_keys_drops_duplicates_per_provider_in_order() {
+        let entries = vec![
+            (Some("openai".to_string()), "k1".to_string()),
+            (Some("openai".to_string()), "k2".to_string()),
+            (Some("openai".to_string()), "k1".to_string()),
+            (Some("groq".to_string()), "k1".to_string()),
+        ];
+        let groups = group_keys(entries, "");
+        assert_eq!(groups["openai"], ["k1", "k2"]);
+        assert_eq!(groups["groq"], ["k1"]);
+    }
+
+    #[test]
+    fn group_keys_assigns_the_fallback_to_unrecognized_keys() {
+        let groups = group_keys(vec![(None, "not-a-known-format".to_string())], "custom");
+        assert_eq!(groups["custom"], ["not-a-known-format"]);
+    }
 }
@@ -0,0 +1,94 @@
+use anyhow::{anyhow, Result};
+use tracing::{info, instrument};
+
+use super::{group_keys, review_keys, KeySource};
+use crate::cli::{key_format, types::ApiKey};
+
+/// The variable prefix when `--source-name` is not given.
+const DEFAULT_PREFIX: &str = "SYNC_KEYS";
+
+/// Reads keys from environment variables, e.g. from a `.env` file. `SYNC_KEYS_OPENAI` holds
+/// keys for `openai` and `SYNC_KEYS_GOOGLE_AI_STUDIO` keys for `google-ai-studio`; the
+/// provider of keys in plain `SYNC_KEYS` is detected from their format. Values are lists
+/// of keys, separated by commas or newlines.
+pub struct EnvSource {
+    prefix: String,
+    fallback_provider: Option<String>,
+    assume_yes: bool,
+}
+
+impl EnvSource {
+    #[instrument]
+    pub fn new(name: Option<String>, fallback_provider: Option<String>, assume_yes: bool) -> Result<Self> {
+        let prefix = name.unwrap_or_else(|| DEFAULT_PREFIX.to_string());
+        info!("Initializing EnvSource with variable prefix: {}", prefix);
+        Ok(Self {
+            prefix,
+            fallback_provider,
+            assume_yes,
+        })
+    }
+
+    /// The provider a variable names, `Some(None)` for the bare prefix, or `None` for
+    /// variables that don't belong to the source.
+    fn provider_of(&self, var: &str) -> Option<Option<String>> {
+        let rest = var.strip_prefix(&self.prefix)?;
+        if rest.is_empty() {
+            return Some(None);
+        }
+        let provider = rest.strip_prefix('_').filter(|p| !p.is_empty())?;
+        Some(Some(provider.to_ascii_lowercase().replace('_', "-")))
+    }
+}
+
+impl KeySource for EnvSource {
+    #[instrument(skip(self))]
+    async fn fetch_keys(&self) -> Result<Vec<ApiKey>> {
+        let mut entries = Vec::new();
+        let mut vars: Vec<(String, String)> = std::env::vars().collect();
+        vars.sort();
+        for (var, value) in vars {
+            let Some(provider) = self.provider_of(&var) else {
+                continue;
+            };
+            info!(var = %var, "Reading keys from environment variable");
+            entries.extend(key_format::split_keys(&value).map(|key| (provider.clone(), key.to_string())));
+        }
+        if entries.is_empty() {
+            return Err(anyhow!(
+                "No keys found in {} or {}_<PROVIDER> environment variables.",
+                self.prefix,
+                self.prefix
+            ));
+        }
+        let fallback = self.fallback_provider.as_deref().unwrap_or("");
+        review_keys(&format!("{}_*", self.prefix), group_keys(entries, fallback), self.assume_yes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source() -> EnvSource {
+        EnvSource::new(None, None, true).unwrap()
+    }
+
+    #[test]
+    fn provider_suffix_names_the_provider() {
+        assert_eq!(source().provider_of("SYNC_KEYS_GOOGLE_AI_STUDIO"), Some(Some("google-ai-studio".to_string())));
+        assert_eq!(source().provider_of("SYNC_KEYS_OPENAI"), Some(Some("openai".to_string())));
+    }
+
+    #[test]
+    fn bare_prefix_has_no_provider() {
+        assert_eq!(source().provider_of("SYNC_KEYS"), Some(None));
+    }
+
+    #[test]
+    fn unrelated_variables_are_ignored() {
+        assert_eq!(source().provider_of("SYNC_KEYSX"), None);
+        assert_eq!(source().provider_of("SYNC_KEYS_"), None);
+        assert_eq!(source().provider_of("PATH"), None);
+    }
+}
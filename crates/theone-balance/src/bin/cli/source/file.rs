@@ -0,0 +1,154 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+use tracing::{info, instrument};
+
+use super::{group_keys, review_keys, KeySource};
+use crate::cli::{csv_line, key_format, types::ApiKey};
+
+/// Reads keys from a file and assigns each the provider of its `provider` column, or the
+/// one its format points to. The file is a plain list of keys (one per line or separated
+/// by commas), a CSV file with a header row, or a JSON array; the latter two are what the
+/// worker's key export writes.
+pub struct FileSource {
+    path: String,
+    fallback_provider: Option<String>,
+    assume_yes: bool,
+}
+
+impl FileSource {
+    #[instrument]
+    pub fn new(name: Option<String>, fallback_provider: Option<String>, assume_yes: bool) -> Result<Self> {
+        let path = name.ok_or_else(|| anyhow!("Path of the key file is required. Use --source-name."))?;
+        info!("Initializing FileSource with key file: {}", path);
+        Ok(Self {
+            path,
+            fallback_provider,
+            assume_yes,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum FileFormat {
+    Lines,
+    Csv,
+    Json,
+}
+
+/// The format by extension, or JSON for any file that starts with an array.
+fn file_format(path: &str, text: &str) -> FileFormat {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("csv") => FileFormat::Csv,
+        Some("json") => FileFormat::Json,
+        _ if text.trim_start().starts_with('[') => FileFormat::Json,
+        _ => FileFormat::Lines,
+    }
+}
+
+/// A JSON entry: a bare key, or an object with the key and optionally its provider.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonEntry {
+    Key(String),
+    Record {
+        key: String,
+        #[serde(default)]
+        provider: Option<String>,
+    },
+}
+
+/// `(provider, key)` pairs of a CSV file with a `key` column and an optional `provider`
+/// column; an empty provider is detected from the key.
+fn parse_csv(text: &str) -> Result<Vec<(Option<String>, String)>> {
+    let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+    let header = csv_line::split_line(lines.next().ok_or_else(|| anyhow!("missing header row"))?).map_err(|e| anyhow!(e))?;
+    let column = |name: &str| header.iter().position(|h| h.trim().eq_ignore_ascii_case(name));
+    let key_col = column("key").ok_or_else(|| anyhow!("header must contain a 'key' column"))?;
+    let provider_col = column("provider");
+
+    let mut entries = Vec::new();
+    for (i, line) in lines.enumerate() {
+        let fields = csv_line::split_line(line).map_err(|e| anyhow!(e)).with_context(|| format!("row {}", i + 2))?;
+        let field = |idx: usize| fields.get(idx).map(|v| v.trim()).filter(|v| !v.is_empty());
+        let Some(key) = field(key_col) else {
+            continue;
+        };
+        entries.push((provider_col.and_then(field).map(str::to_string), key.to_string()));
+    }
+    Ok(entries)
+}
+
+fn parse_json(text: &str) -> Result<Vec<(Option<String>, String)>> {
+    let entries: Vec<JsonEntry> = serde_json::from_str(text)?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| match entry {
+            JsonEntry::Key(key) => (None, key),
+            JsonEntry::Record { key, provider } => (provider.filter(|p| !p.trim().is_empty()), key),
+        })
+        .collect())
+}
+
+impl KeySource for FileSource {
+    #[instrument(skip(self))]
+    async fn fetch_keys(&self) -> Result<Vec<ApiKey>> {
+        let text = tokio::fs::read_to_string(&self.path).await?;
+        let fallback = self.fallback_provider.as_deref().unwrap_or("");
+        let entries = match file_format(&self.path, &text) {
+            FileFormat::Lines => key_format::split_keys(&text).map(|key| (None, key.to_string())).collect(),
+            FileFormat::Csv => parse_csv(&text).with_context(|| format!("Invalid CSV in {}", self.path))?,
+            FileFormat::Json => parse_json(&text).with_context(|| format!("Invalid JSON in {}", self.path))?,
+        };
+        review_keys(&self.path, group_keys(entries, fallback), self.assume_yes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(provider: Option<&str>, key: &str) -> (Option<String>, String) {
+        (provider.map(str::to_string), key.to_string())
+    }
+
+    #[test]
+    fn csv_reads_the_key_and_provider_columns() {
+        let text = "Provider,note,KEY\nopenai,\"a, b\",k1\n,,k2\ngroq,,\n\n";
+        assert_eq!(parse_csv(text).unwrap(), [entry(Some("openai"), "k1"), entry(None, "k2")]);
+    }
+
+    #[test]
+    fn csv_without_provider_column_detects_providers() {
+        assert_eq!(parse_csv("key\nk1\n").unwrap(), [entry(None, "k1")]);
+    }
+
+    #[test]
+    fn csv_errors_name_the_row() {
+        assert!(parse_csv("provider\nopenai\n").is_err());
+        let err = parse_csv("key\nk1\n\"k2\n").unwrap_err();
+        assert!(format!("{:#}", err).contains("row 3"));
+    }
+
+    #[test]
+    fn json_accepts_bare_keys_and_records() {
+        let text = r#"["k1", {"key": "k2", "provider": "openai"}, {"key": "k3", "provider": " "}, {"key": "k4"}]"#;
+        assert_eq!(
+            parse_json(text).unwrap(),
+            [entry(None, "k1"), entry(Some("openai"), "k2"), entry(None, "k3"), entry(None, "k4")]
+        );
+        assert!(parse_json(r#"[{"provider": "openai"}]"#).is_err());
+    }
+
+    #[test]
+    fn format_follows_extension_then_content() {
+        assert_eq!(file_format("keys.CSV", "[]"), FileFormat::Csv);
+        assert_eq!(file_format("keys.json", ""), FileFormat::Json);
+        assert_eq!(file_format("keys.txt", "  [\"k1\"]"), FileFormat::Json);
+        assert_eq!(file_format("keys.txt", "k1\nk2"), FileFormat::Lines);
+    }
+}
@@ -32,7 +32,7 @@ impl KeySource for OneBalanceSource {
     async fn fetch_keys(&self) -> Result<Vec<ApiKey>> {
         info!(db_name = %self.db_name, "Fetching keys via `npx wrangler d1 execute`");
 
-        let sql = "SELECT key, provider FROM keys WHERE status = 'active';";
+        let sql = "SELECT key, provider FROM keys WHERE status = 'active' AND deleted_at = 0;";
 
         let mut command = Command::new("npx");
         command.arg("wrangler");
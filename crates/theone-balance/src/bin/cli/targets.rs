@@ -1,10 +1,12 @@
 use anyhow::{anyhow, Result};
 use tracing::info;
 
-use crate::cli::{config::ConfigSource, types::{ApiKey, SyncResult}};
+use crate::cli::{args::SyncArgs, config::ConfigSource, types::{ApiKey, SyncResult}};
 
+pub use self::api::{ApiTarget, ProviderStatus};
 use self::the_one::TheOneTarget;
 
+mod api;
 mod the_one;
 
 pub trait KeyTarget {
@@ -13,15 +15,24 @@ pub trait KeyTarget {
 
 pub enum Target {
     TheOne(TheOneTarget),
+    Api(ApiTarget),
 }
 
 impl Target {
-    pub async fn from_config(target: ConfigSource, name: Option<String>) -> Result<Self> {
-        match target {
+    pub async fn from_config(args: &SyncArgs) -> Result<Self> {
+        let name = args.target_name.clone();
+        match args.target {
             ConfigSource::TheOne => {
+                if args.dry_run {
+                    return Err(anyhow!("--dry-run is only supported by the api target"));
+                }
                 let target = TheOneTarget::new(name).await?;
                 Ok(Self::TheOne(target))
             }
+            ConfigSource::Api => {
+                let target = ApiTarget::new(name, args.chunk_size, args.dry_run)?;
+                Ok(Self::Api(target))
+            }
             _ => Err(anyhow!("Unsupported target type")),
         }
     }
@@ -32,6 +43,7 @@ impl KeyTarget for Target {
         info!("Syncing keys to target...");
         match self {
             Self::TheOne(target) => target.sync_keys(keys).await,
+            Self::Api(target) => target.sync_keys(keys).await,
         }
     }
 }
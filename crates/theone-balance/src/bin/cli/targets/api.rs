@@ -0,0 +1,370 @@
+use anyhow::{Result, anyhow};
+use reqwest::{Client, RequestBuilder, Response, StatusCode, header};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+use tracing::{info, instrument, warn};
+
+use crate::cli::{
+    key_format,
+    targets::KeyTarget,
+    types::{ApiKey, SyncResult},
+};
+
+/// Attempts per request before giving up on a 5xx, a 429 or a connection error.
+const MAX_ATTEMPTS: u32 = 5;
+/// The wait after the first failed attempt; doubled after each further one.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+/// Waits, backoff or `Retry-After`, never exceed this.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Syncs keys through the worker's JSON admin API (`/api/admin/keys/import`), authenticated
+/// with the auth key as a Bearer token. Keys are uploaded in chunks, so one rejected chunk
+/// doesn't fail the rest, and keys the worker already has are counted as skipped.
+pub struct ApiTarget {
+    client: Client,
+    worker_url: String,
+    auth_key: String,
+    chunk_size: usize,
+    dry_run: bool,
+}
+
+/// The fields of the worker's key import records the CLI fills in; the rest default.
+#[derive(Serialize)]
+struct ImportRecord<'a> {
+    provider: &'a str,
+    key: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ImportSummary {
+    imported: usize,
+    duplicates: usize,
+}
+
+#[derive(Deserialize)]
+struct ExportedKey {
+    provider: String,
+    key: String,
+}
+
+/// A page of `/api/admin/keys/changes`, which is the admin API's only listing with key ids.
+#[derive(Deserialize)]
+struct KeyChangesPage {
+    keys: Vec<KeyChange>,
+    next_cursor: String,
+    has_more: bool,
+}
+
+#[derive(Deserialize)]
+struct KeyChange {
+    id: String,
+    provider: String,
+    key: String,
+    deleted_at: i64,
+}
+
+/// The worker's `/api/status`: key counts, the breaker and recent traffic per provider.
+#[derive(Serialize, Deserialize)]
+pub struct StatusReport {
+    /// Unix seconds.
+    pub generated_at: u64,
+    pub window_seconds: i32,
+    pub providers: Vec<ProviderStatus>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProviderStatus {
+    pub provider: String,
+    /// `operational`, `degraded` or `down`.
+    pub status: String,
+    pub active_keys: i64,
+    pub cooling_keys: i64,
+    pub blocked_keys: i64,
+    pub breaker: String,
+    pub requests: i64,
+    /// `None` without traffic in the window.
+    pub error_rate: Option<f64>,
+    pub avg_latency_ms: Option<f64>,
+}
+
+impl ApiTarget {
+    #[instrument]
+    pub fn new(_name: Option<String>, chunk_size: usize, dry_run: bool) -> Result<Self> {
+        info!("Initializing ApiTarget via the admin API.");
+
+        let worker_url = std::env::var("THE_ONE_WORKER_URL")
+            .map_err(|_| anyhow!("THE_ONE_WORKER_URL environment variable not set. e.g., https://my-worker.example.com"))?;
+        let auth_key = std::env::var("THE_ONE_AUTH_KEY")
+            .map_err(|_| anyhow!("THE_ONE_AUTH_KEY environment variable not set"))?;
+        if chunk_size == 0 {
+            return Err(anyhow!("--chunk-size must be at least 1"));
+        }
+
+        Ok(Self {
+            client: Client::builder().timeout(Duration::from_secs(60)).build()?,
+            worker_url: worker_url.trim_end_matches('/').to_string(),
+            auth_key,
+            chunk_size,
+            dry_run,
+        })
+    }
+
+    /// Sends a request built by `build`, retrying 5xx, 429 and connection errors with
+    /// exponential backoff. A 429's `Retry-After` is honoured.
+    async fn send_with_retry(&self, build: impl Fn() -> RequestBuilder) -> Result<Response> {
+        let mut backoff = INITIAL_BACKOFF;
+        for attempt in 1..=MAX_ATTEMPTS {
+            let (wait, reason) = match build().bearer_auth(&self.auth_key).send().await {
+                Ok(response)
+                    if response.status().is_server_error()
+                        || response.status() == StatusCode::TOO_MANY_REQUESTS =>
+                {
+                    let retry_after = response
+                        .headers()
+                        .get(header::RETRY_AFTER)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.parse::<u64>().ok())
+                        .map(Duration::from_secs);
+                    if attempt == MAX_ATTEMPTS {
+                        return Ok(response);
+                    }
+                    (
+                        retry_after.unwrap_or(backoff),
+                        format!("status {}", response.status()),
+                    )
+                }
+                Ok(response) => return Ok(response),
+                Err(e) if attempt == MAX_ATTEMPTS => return Err(e.into()),
+                Err(e) => (backoff, e.to_string()),
+            };
+            let wait = wait.min(MAX_BACKOFF);
+            warn!(attempt, reason = %reason, "Request failed, retrying in {:?}", wait);
+            tokio::time::sleep(wait).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+        unreachable!("the last attempt always returns")
+    }
+
+    /// The keys the worker already has, by provider, from the key export.
+    pub async fn existing_keys(&self) -> Result<HashSet<(String, String)>> {
+        let url = format!("{}/api/admin/keys/export", self.worker_url);
+        let response = self.send_with_retry(|| self.client.get(&url)).await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(anyhow!(
+                "Key export failed with status {}: {}",
+                status,
+                response.text().await?
+            ));
+        }
+        let keys: Vec<ExportedKey> = response.json().await?;
+        Ok(keys.into_iter().map(|k| (k.provider, k.key)).collect())
+    }
+
+    /// The keys the worker has, by provider, as the keys in use and the keys in its trash.
+    /// Unlike the export, this sees trashed keys, which the worker refuses to import again.
+    pub async fn existing_and_trashed_keys(&self) -> Result<(HashSet<(String, String)>, HashSet<(String, String)>)> {
+        let mut existing = HashSet::new();
+        let mut trashed = HashSet::new();
+        for change in self.key_changes(None).await? {
+            let set = if change.deleted_at == 0 { &mut existing } else { &mut trashed };
+            set.insert((change.provider, change.key));
+        }
+        Ok((existing, trashed))
+    }
+
+    /// Every key of the worker, or of one provider, trashed ones included, from the key changes.
+    async fn key_changes(&self, provider: Option<&str>) -> Result<Vec<KeyChange>> {
+        let changes_url = format!("{}/api/admin/keys/changes", self.worker_url);
+        let mut keys = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let response = self
+                .send_with_retry(|| {
+                    let mut query = vec![("limit", "1000".to_string())];
+                    if let Some(provider) = provider {
+                        query.push(("provider", provider.to_string()));
+                    }
+                    if let Some(cursor) = &cursor {
+                        query.push(("cursor", cursor.clone()));
+                    }
+                    self.client.get(&changes_url).query(&query)
+                })
+                .await?;
+            let status = response.status();
+            if !status.is_success() {
+                return Err(anyhow!("Listing keys failed with status {}: {}", status, response.text().await?));
+            }
+            let page: KeyChangesPage = response.json().await?;
+            keys.extend(page.keys);
+            if !page.has_more {
+                return Ok(keys);
+            }
+            cursor = Some(page.next_cursor);
+        }
+    }
+
+    /// The worker's per-provider status.
+    pub async fn status(&self) -> Result<StatusReport> {
+        let url = format!("{}/api/status", self.worker_url);
+        let response = self.send_with_retry(|| self.client.get(&url)).await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(anyhow!("Status request failed with status {}: {}", status, response.text().await?));
+        }
+        Ok(response.json().await?)
+    }
+
+    /// The worker's key export with health metrics, as `csv` or `json`, all providers unless one is given.
+    pub async fn export_keys(&self, provider: Option<&str>, format: &str) -> Result<Vec<u8>> {
+        let url = format!("{}/api/admin/keys/export", self.worker_url);
+        let mut query = vec![("format", format)];
+        if let Some(provider) = provider {
+            query.push(("provider", provider));
+        }
+        let response = self.send_with_retry(|| self.client.get(&url).query(&query)).await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(anyhow!("Key export failed with status {}: {}", status, response.text().await?));
+        }
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    /// Moves a provider's keys to the worker's trash and returns how many were found there.
+    /// Keys are looked up by their text, so their ids are read from the key changes first.
+    pub async fn trash_keys(&self, provider: &str, keys: &[String]) -> Result<usize> {
+        let wanted: HashSet<&str> = keys.iter().map(String::as_str).collect();
+        let ids: Vec<String> = self
+            .key_changes(Some(provider))
+            .await?
+            .into_iter()
+            .filter(|k| k.deleted_at == 0 && wanted.contains(k.key.as_str()))
+            .map(|k| k.id)
+            .collect();
+
+        let delete_url = format!("{}/api/admin/keys/delete", self.worker_url);
+        for chunk in ids.chunks(self.chunk_size) {
+            let body = serde_json::json!({ "ids": chunk });
+            let response = self.send_with_retry(|| self.client.post(&delete_url).json(&body)).await?;
+            let status = response.status();
+            if !status.is_success() {
+                return Err(anyhow!("Deleting keys failed with status {}: {}", status, response.text().await?));
+            }
+        }
+        Ok(ids.len())
+    }
+
+    /// Prints what a sync would upload, per provider, without changing anything.
+    async fn print_plan(&self, keys: &[ApiKey]) -> Result<SyncResult> {
+        let existing = self.existing_keys().await?;
+        let mut by_provider: BTreeMap<&str, (Vec<&str>, usize)> = BTreeMap::new();
+        for api_key in keys {
+            let entry = by_provider.entry(&api_key.provider).or_default();
+            if existing.contains(&(api_key.provider.clone(), api_key.key.clone())) {
+                entry.1 += 1;
+            } else {
+                entry.0.push(&api_key.key);
+            }
+        }
+
+        println!("Dry run against {}, nothing is uploaded:", self.worker_url);
+        let mut skipped_count = 0;
+        for (provider, (new_keys, present)) in &by_provider {
+            println!(
+                "  {:<20} {} new, {} already present",
+                provider,
+                new_keys.len(),
+                present
+            );
+            for key in new_keys {
+                println!("    + {}", key_format::partially_redact_key(key));
+            }
+            skipped_count += present;
+        }
+        let new_count = keys.len() - skipped_count;
+        println!(
+            "Would upload {} keys in {} chunks of up to {}.",
+            new_count,
+            new_count.div_ceil(self.chunk_size),
+            self.chunk_size
+        );
+
+        Ok(SyncResult {
+            success: true,
+            synced_count: 0,
+            skipped_count,
+            failed_count: 0,
+            errors: vec![],
+        })
+    }
+}
+
+impl KeyTarget for ApiTarget {
+    #[instrument(skip(self, keys))]
+    async fn sync_keys(&mut self, keys: Vec<ApiKey>) -> Result<SyncResult> {
+        if self.dry_run {
+            return self.print_plan(&keys).await;
+        }
+
+        let url = format!("{}/api/admin/keys/import", self.worker_url);
+        let mut synced_count = 0;
+        let mut skipped_count = 0;
+        let mut failed_count = 0;
+        let mut errors = Vec::new();
+
+        let chunks = keys.chunks(self.chunk_size);
+        let total = chunks.len();
+        for (i, chunk) in chunks.enumerate() {
+            let records: Vec<ImportRecord> = chunk
+                .iter()
+                .map(|k| ImportRecord {
+                    provider: &k.provider,
+                    key: &k.key,
+                })
+                .collect();
+            info!(chunk = i + 1, total, "Uploading {} keys", records.len());
+
+            let result = self
+                .send_with_retry(|| self.client.post(&url).json(&records))
+                .await;
+            let error_msg = match result {
+                Ok(response) if response.status().is_success() => {
+                    let summary: ImportSummary = response.json().await?;
+                    synced_count += summary.imported;
+                    skipped_count += summary.duplicates;
+                    continue;
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    format!(
+                        "Chunk {}/{}: import of {} keys failed (status {}): {}",
+                        i + 1,
+                        total,
+                        chunk.len(),
+                        status,
+                        response.text().await?
+                    )
+                }
+                Err(e) => format!(
+                    "Chunk {}/{}: import of {} keys failed: {}",
+                    i + 1,
+                    total,
+                    chunk.len(),
+                    e
+                ),
+            };
+            warn!(error = %error_msg);
+            failed_count += chunk.len();
+            errors.push(error_msg);
+        }
+
+        Ok(SyncResult {
+            success: failed_count == 0,
+            synced_count,
+            skipped_count,
+            failed_count,
+            errors,
+        })
+    }
+}
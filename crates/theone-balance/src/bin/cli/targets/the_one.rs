@@ -1,5 +1,5 @@
 use anyhow::{anyhow, Result};
-use reqwest::Client;
+use reqwest::{redirect, Client};
 use std::collections::HashMap;
 use tracing::{info, instrument, warn};
 
@@ -11,7 +11,10 @@ use crate::cli::{
 pub struct TheOneTarget {
     client: Client,
     api_url_template: String,
-    auth_key: String,
+    /// The `session=...` cookie obtained by logging in with the auth key.
+    session_cookie: String,
+    /// The session's CSRF token, which the keys form must carry.
+    csrf_token: String,
 }
 
 impl TheOneTarget {
@@ -28,16 +31,69 @@ impl TheOneTarget {
         let auth_key = std::env::var("THE_ONE_AUTH_KEY")
             .map_err(|_| anyhow!("THE_ONE_AUTH_KEY environment variable not set"))?;
 
-        let client = Client::new();
+        // Redirects aren't followed: the session cookie is set on the login's redirect, and
+        // the keys page answers a successful add with one.
+        let client = Client::builder().redirect(redirect::Policy::none()).build()?;
+        let session_cookie = login(&client, worker_url.trim_end_matches('/'), &auth_key).await?;
+        let csrf_token = fetch_csrf_token(&client, worker_url.trim_end_matches('/'), &session_cookie).await?;
 
         Ok(Self {
             client,
             api_url_template,
-            auth_key,
+            session_cookie,
+            csrf_token,
         })
     }
 }
 
+/// Logs in to the UI and returns the session cookie to send with the form posts.
+async fn login(client: &Client, worker_url: &str, auth_key: &str) -> Result<String> {
+    let response = client
+        .post(format!("{}/login", worker_url))
+        .form(&[("auth_key", auth_key)])
+        .send()
+        .await?;
+    if !response.status().is_success() && !response.status().is_redirection() {
+        return Err(anyhow!("Login failed with status {}", response.status()));
+    }
+    let cookies: Vec<&str> = response
+        .headers()
+        .get_all(reqwest::header::SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(|value| value.split(';').next())
+        .collect();
+    if cookies.iter().any(|cookie| cookie.starts_with("login_challenge=")) {
+        return Err(anyhow!("Login requires a two-factor code, which the sync CLI can't enter"));
+    }
+    cookies
+        .into_iter()
+        .find(|cookie| cookie.starts_with("session="))
+        .map(|cookie| cookie.to_string())
+        .ok_or_else(|| anyhow!("Login succeeded but no session cookie was set"))
+}
+
+/// Reads the session's CSRF token from a form of the providers page.
+async fn fetch_csrf_token(client: &Client, worker_url: &str, session_cookie: &str) -> Result<String> {
+    let page = client
+        .get(format!("{}/", worker_url))
+        .header("Cookie", session_cookie)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    const FIELD: &str = r#"name="csrf_token" value=""#;
+    let start = page
+        .find(FIELD)
+        .map(|i| i + FIELD.len())
+        .ok_or_else(|| anyhow!("The providers page carries no CSRF token"))?;
+    let end = page[start..]
+        .find('"')
+        .ok_or_else(|| anyhow!("Malformed CSRF token field"))?;
+    Ok(page[start..start + end].to_string())
+}
+
 impl KeyTarget for TheOneTarget {
     #[instrument(skip(self, keys))]
     async fn sync_keys(&mut self, keys: Vec<ApiKey>) -> Result<SyncResult> {
@@ -45,6 +101,7 @@ impl KeyTarget for TheOneTarget {
             return Ok(SyncResult {
                 success: true,
                 synced_count: 0,
+                skipped_count: 0,
                 failed_count: 0,
                 errors: vec![],
             });
@@ -73,12 +130,13 @@ impl KeyTarget for TheOneTarget {
             // Use form subment web api, not pure api.
             form_data.insert("action", "add");
             form_data.insert("keys", &keys_str);
+            form_data.insert("csrf_token", &self.csrf_token);
 
             let response = self
                 .client
                 .post(&url)
-                // The UI uses a cookie for auth, so we need to emulate that.
-                .header("Cookie", format!("auth_key={}", self.auth_key))
+                // The UI uses a session cookie for auth, so we need to emulate that.
+                .header("Cookie", &self.session_cookie)
                 .form(&form_data)
                 .send()
                 .await?;
@@ -105,6 +163,7 @@ impl KeyTarget for TheOneTarget {
         Ok(SyncResult {
             success: failed_count == 0,
             synced_count,
+            skipped_count: 0,
             failed_count,
             errors,
         })
@@ -10,6 +10,8 @@ pub struct ApiKey {
 pub struct SyncResult {
     pub success: bool,
     pub synced_count: usize,
+    /// Keys the target already had.
+    pub skipped_count: usize,
     pub failed_count: usize,
     pub errors: Vec<String>,
 }
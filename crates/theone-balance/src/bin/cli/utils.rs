@@ -1,9 +1,22 @@
+use anyhow::Result;
+use std::io::{BufRead, Write};
 use tracing_subscriber::fmt;
 
-/// Initializes tracing for the CLI, separate from the worker's tracing.
+/// Initializes tracing for the CLI, separate from the worker's tracing. Logs go to stderr so
+/// the output of `stats` and `export` can be piped.
 pub fn init_tracing() {
     fmt()
+        .with_writer(std::io::stderr)
         .with_span_events(fmt::format::FmtSpan::CLOSE)
         .with_env_filter(std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_owned()))
         .init();
 }
+
+/// Asks on the terminal whether to go on; anything but `y` or `yes` declines.
+pub fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
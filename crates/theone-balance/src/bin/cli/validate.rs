@@ -0,0 +1,109 @@
+use anyhow::{anyhow, Result};
+use futures_util::stream::{self, StreamExt};
+use reqwest::{Client, Method, StatusCode};
+use std::time::{Duration, Instant};
+
+use crate::cli::key_probe::{self, ProbeRequest};
+
+/// How a key's test ended.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Pass,
+    /// The provider rejected the key: a 4xx other than 429.
+    Fail,
+    /// The test said nothing about the key: the request failed, was rate limited or hit a 5xx.
+    Error,
+}
+
+impl Outcome {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Outcome::Pass => "PASS",
+            Outcome::Fail => "FAIL",
+            Outcome::Error => "ERROR",
+        }
+    }
+}
+
+/// The result of testing one key.
+pub struct KeyCheck {
+    pub key: String,
+    pub outcome: Outcome,
+    pub latency: Duration,
+    /// `OK`, or why the test failed.
+    pub details: String,
+}
+
+/// Longest provider error message printed per key.
+const MAX_DETAILS_CHARS: usize = 200;
+
+/// Tests keys with the requests the worker's key tests send: a one-word chat request when
+/// a model is given, otherwise the provider's model list, which costs no tokens. Results
+/// are in the order of `keys`.
+pub async fn check_keys(
+    provider: &str,
+    keys: Vec<String>,
+    model: Option<&str>,
+    concurrency: usize,
+    timeout: Duration,
+) -> Result<Vec<KeyCheck>> {
+    // Fail before sending anything if the provider can't be probed.
+    let probe = |key: &str| match model {
+        Some(model) => key_probe::chat_test_request(provider, key, model).map_err(|e| anyhow!(e)),
+        None => key_probe::model_list_request(provider, key).ok_or_else(|| {
+            anyhow!("Provider '{}' has no model list to test keys with. Use --model.", provider)
+        }),
+    };
+    let requests = keys
+        .into_iter()
+        .map(|key| probe(&key).map(|request| (key, request)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let client = Client::builder().timeout(timeout).build()?;
+    Ok(stream::iter(requests)
+        .map(|(key, request)| {
+            let client = &client;
+            async move {
+                let start = Instant::now();
+                let (outcome, details) = send(client, request).await;
+                KeyCheck {
+                    key,
+                    outcome,
+                    latency: start.elapsed(),
+                    details,
+                }
+            }
+        })
+        .buffered(concurrency.max(1))
+        .collect()
+        .await)
+}
+
+async fn send(client: &Client, request: ProbeRequest) -> (Outcome, String) {
+    let method = if request.method == "POST" { Method::POST } else { Method::GET };
+    let mut builder = client.request(method, &request.url);
+    for (name, value) in request.headers {
+        builder = builder.header(name, value);
+    }
+    if let Some(body) = request.body {
+        builder = builder.body(body);
+    }
+    let response = match builder.send().await {
+        Ok(response) => response,
+        Err(e) => return (Outcome::Error, format!("{:#}", anyhow!(e))),
+    };
+    let status = response.status();
+    if status.is_success() {
+        return (Outcome::Pass, "OK".to_string());
+    }
+    let outcome = if status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS {
+        Outcome::Fail
+    } else {
+        Outcome::Error
+    };
+    let body: String = response.text().await.unwrap_or_default().chars().take(MAX_DETAILS_CHARS).collect();
+    (
+        outcome,
+        format!("status {}: {}", status.as_u16(), body.split_whitespace().collect::<Vec<_>>().join(" ")),
+    )
+}
@@ -0,0 +1,199 @@
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, HashSet};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::cli::{
+    args::SyncArgs,
+    config::ConfigSource,
+    source::{KeySource, Source},
+    targets::{ApiTarget, KeyTarget},
+    types::ApiKey,
+};
+
+/// `(provider, key)` pairs.
+type KeySet = HashSet<(String, String)>;
+
+/// What one cycle found and did.
+#[derive(Default)]
+struct CycleReport {
+    source_keys: usize,
+    added: usize,
+    removed: usize,
+    imported: usize,
+    trashed: usize,
+    failed: usize,
+}
+
+/// Syncs the source to the api target every `--interval` until interrupted. Each cycle
+/// uploads the source's keys the worker lacks and trashes the keys that left the source
+/// since the previous cycle; keys the worker got some other way are left alone, and keys
+/// trashed on the worker stay in its trash until they are restored there.
+pub async fn run(args: &SyncArgs) -> Result<()> {
+    if !matches!(args.target, ConfigSource::Api) {
+        return Err(anyhow!("--watch is only supported by the api target"));
+    }
+    if !args.input.yes {
+        return Err(anyhow!("--watch runs unattended, so it needs --yes"));
+    }
+    if args.interval == 0 {
+        return Err(anyhow!("--interval must be at least 1"));
+    }
+    let source = Source::from_config(&args.input).await?;
+    let mut target = ApiTarget::new(args.target_name.clone(), args.chunk_size, false)?;
+    let interval = Duration::from_secs(args.interval);
+
+    info!(interval_secs = args.interval, dry_run = args.dry_run, "Watching the source for changes");
+    let mut previous: Option<KeySet> = None;
+    let mut cycle: u64 = 0;
+    loop {
+        cycle += 1;
+        let start = Instant::now();
+        match sync_changes(&source, &mut target, previous.as_ref(), args.dry_run).await {
+            Ok((keys, report)) => {
+                info!(
+                    cycle,
+                    source_keys = report.source_keys,
+                    added = report.added,
+                    removed = report.removed,
+                    imported = report.imported,
+                    trashed = report.trashed,
+                    failed = report.failed,
+                    elapsed_ms = start.elapsed().as_millis() as u64,
+                    "Sync cycle finished"
+                );
+                previous = Some(keys);
+            }
+            // The next cycle diffs against the last source that was synced, so removals are retried.
+            Err(e) => warn!(
+                cycle,
+                error = %format!("{:#}", e),
+                elapsed_ms = start.elapsed().as_millis() as u64,
+                "Sync cycle failed"
+            ),
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = tokio::signal::ctrl_c() => {
+                info!(cycles = cycle, "Interrupted, stopping the watch");
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Runs one cycle and returns the source's keys, to diff the next cycle against.
+async fn sync_changes(
+    source: &Source,
+    target: &mut ApiTarget,
+    previous: Option<&KeySet>,
+    dry_run: bool,
+) -> Result<(KeySet, CycleReport)> {
+    let current: KeySet = source
+        .fetch_keys()
+        .await?
+        .into_iter()
+        .map(|k| (k.provider, k.key))
+        .collect();
+    let (existing, trashed) = target.existing_and_trashed_keys().await?;
+    let (added, removed) = plan_cycle(&current, previous, &existing, &trashed);
+
+    let mut report = CycleReport {
+        source_keys: current.len(),
+        added: added.len(),
+        removed: removed.values().map(Vec::len).sum(),
+        ..Default::default()
+    };
+    if !dry_run {
+        if !added.is_empty() {
+            let result = target.sync_keys(added).await?;
+            report.imported = result.synced_count;
+            report.failed = result.failed_count;
+        }
+        for (provider, keys) in &removed {
+            report.trashed += target.trash_keys(provider, keys).await?;
+        }
+    }
+    Ok((current, report))
+}
+
+/// The keys to upload, those in the source the worker has neither in use nor in its
+/// trash, and the keys to trash by provider, those that left the source since `previous`
+/// and are still in use on the worker. Both are sorted.
+fn plan_cycle(
+    current: &KeySet,
+    previous: Option<&KeySet>,
+    existing: &KeySet,
+    trashed: &KeySet,
+) -> (Vec<ApiKey>, BTreeMap<String, Vec<String>>) {
+    let mut added: Vec<ApiKey> = current
+        .iter()
+        .filter(|k| !existing.contains(*k) && !trashed.contains(*k))
+        .map(|(provider, key)| ApiKey {
+            key: key.clone(),
+            provider: provider.clone(),
+        })
+        .collect();
+    added.sort_by(|a, b| (&a.provider, &a.key).cmp(&(&b.provider, &b.key)));
+
+    let mut removed: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (provider, key) in previous
+        .into_iter()
+        .flatten()
+        .filter(|k| !current.contains(*k) && existing.contains(*k))
+    {
+        removed.entry(provider.clone()).or_default().push(key.clone());
+    }
+    removed.values_mut().for_each(|keys| keys.sort());
+    (added, removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(pairs: &[(&str, &str)]) -> KeySet {
+        pairs.iter().map(|(p, k)| (p.to_string(), k.to_string())).collect()
+    }
+
+    fn added(plan: &(Vec<ApiKey>, BTreeMap<String, Vec<String>>)) -> Vec<(&str, &str)> {
+        plan.0.iter().map(|k| (k.provider.as_str(), k.key.as_str())).collect()
+    }
+
+    #[test]
+    fn uploads_source_keys_the_worker_lacks() {
+        let current = keys(&[("openai", "k1"), ("openai", "k2"), ("groq", "k3")]);
+        let plan = plan_cycle(&current, None, &keys(&[("openai", "k1")]), &KeySet::new());
+        assert_eq!(added(&plan), [("groq", "k3"), ("openai", "k2")]);
+        assert!(plan.1.is_empty());
+    }
+
+    #[test]
+    fn keys_trashed_on_the_worker_are_not_uploaded_again() {
+        let current = keys(&[("openai", "k1"), ("openai", "k2")]);
+        let previous = current.clone();
+        let plan = plan_cycle(&current, Some(&previous), &keys(&[("openai", "k2")]), &keys(&[("openai", "k1")]));
+        assert!(added(&plan).is_empty());
+        assert!(plan.1.is_empty());
+    }
+
+    #[test]
+    fn trashes_keys_that_left_the_source() {
+        let previous = keys(&[("openai", "k1"), ("openai", "k2"), ("groq", "k3"), ("groq", "k4")]);
+        let current = keys(&[("openai", "k1")]);
+        // k4 was already trashed on the worker; k5 never came from the source.
+        let existing = keys(&[("openai", "k1"), ("openai", "k2"), ("groq", "k3"), ("groq", "k5")]);
+        let plan = plan_cycle(&current, Some(&previous), &existing, &keys(&[("groq", "k4")]));
+        assert!(added(&plan).is_empty());
+        let removed: Vec<(&str, Vec<String>)> = plan.1.iter().map(|(p, k)| (p.as_str(), k.clone())).collect();
+        assert_eq!(removed, [("groq", vec!["k3".to_string()]), ("openai", vec!["k2".to_string()])]);
+    }
+
+    #[test]
+    fn first_cycle_removes_nothing() {
+        let plan = plan_cycle(&KeySet::new(), None, &keys(&[("openai", "k1")]), &KeySet::new());
+        assert!(added(&plan).is_empty());
+        assert!(plan.1.is_empty());
+    }
+}
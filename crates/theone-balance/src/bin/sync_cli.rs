@@ -15,6 +15,9 @@ async fn main() -> Result<()> {
     let cli = Cli::parse();
     let result = match cli.command {
         Commands::Sync(args) => App::sync(args).await,
+        Commands::Validate(args) => App::validate(args).await,
+        Commands::Stats(args) => App::stats(args).await,
+        Commands::Export(args) => App::export(args).await,
     };
 
     if let Err(e) = result {
@@ -0,0 +1,86 @@
+//! This module reports which build is serving traffic: the git commit and build time
+//! embedded by `build.rs`, and the cargo features the worker was compiled with. It backs
+//! the `/version` endpoint and the banner logged when an isolate starts.
+
+use axum::response::Json;
+use serde::Serialize;
+use tracing::info;
+
+#[derive(Serialize, Debug)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    /// Unix seconds.
+    pub build_timestamp: u64,
+    pub features: Vec<&'static str>,
+    /// `raw_d1`, `do_sqlite` or `do_kv`.
+    pub storage_strategy: &'static str,
+    /// `wait_until` or `use_queue`.
+    pub background_tasks: &'static str,
+}
+
+fn enabled_features() -> Vec<&'static str> {
+    [
+        ("raw_d1", cfg!(feature = "raw_d1")),
+        ("do_sqlite", cfg!(feature = "do_sqlite")),
+        ("do_kv", cfg!(feature = "do_kv")),
+        ("axumrouter", cfg!(feature = "axumrouter")),
+        ("wait_until", cfg!(feature = "wait_until")),
+        ("use_queue", cfg!(feature = "use_queue")),
+        ("tracing-worker", cfg!(feature = "tracing-worker")),
+    ]
+    .into_iter()
+    .filter_map(|(name, enabled)| enabled.then_some(name))
+    .collect()
+}
+
+fn storage_strategy() -> &'static str {
+    if cfg!(feature = "raw_d1") {
+        "raw_d1"
+    } else if cfg!(feature = "do_sqlite") {
+        "do_sqlite"
+    } else if cfg!(feature = "do_kv") {
+        "do_kv"
+    } else {
+        "none"
+    }
+}
+
+fn background_tasks() -> &'static str {
+    if cfg!(feature = "use_queue") {
+        "use_queue"
+    } else if cfg!(feature = "wait_until") {
+        "wait_until"
+    } else {
+        "none"
+    }
+}
+
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("BUILD_GIT_SHA"),
+        build_timestamp: env!("BUILD_TIMESTAMP").parse().unwrap_or(0),
+        features: enabled_features(),
+        storage_strategy: storage_strategy(),
+        background_tasks: background_tasks(),
+    }
+}
+
+/// Logs the build info once per isolate.
+pub fn log_banner() {
+    let info = build_info();
+    info!(
+        version = info.version,
+        git_sha = info.git_sha,
+        build_timestamp = info.build_timestamp,
+        features = %info.features.join(","),
+        storage_strategy = info.storage_strategy,
+        background_tasks = info.background_tasks,
+        "one-balance starting"
+    );
+}
+
+pub async fn version_handler() -> Json<BuildInfo> {
+    Json(build_info())
+}
@@ -0,0 +1,136 @@
+//! This module contains an opt-in chaos mode for dev and staging deployments. It injects
+//! random delays and error responses into upstream calls per provider, so failover,
+//! timeouts and alerting can be exercised before relying on them in production.
+//!
+//! Rules are read from `CHAOS_MODE`, a `;`-separated list of `provider-pattern:setting,...`
+//! entries, e.g. `openai:delay_ms=200-3000,error_rate=20;*:error_rate=5,error_status=429`.
+//! Patterns ending in `*` match by prefix and the first matching rule wins. Settings:
+//! - `delay_ms`: a fixed delay or a `min-max` range added before the upstream call
+//! - `delay_rate`: the percentage of calls delayed, default 100
+//! - `error_rate`: the percentage of calls answered with an injected error, default 0
+//! - `error_status`: the injected status, 429 or 5xx, default 500
+//!
+//! Chaos mode is ignored unless `DEPLOY_ENV` is `dev` or `staging`, or `IS_LOCAL` is `true`.
+//! Injected errors go through the normal error handling, so they cool down real keys.
+
+use tracing::warn;
+use worker::{Env, Response, Result};
+
+const ALLOWED_ENVIRONMENTS: &[&str] = &["dev", "development", "staging"];
+
+#[derive(Debug, Clone)]
+pub struct ChaosRule {
+    delay_min_ms: u64,
+    delay_max_ms: u64,
+    delay_rate: u64,
+    error_rate: u64,
+    error_status: u16,
+}
+
+impl Default for ChaosRule {
+    fn default() -> Self {
+        ChaosRule {
+            delay_min_ms: 0,
+            delay_max_ms: 0,
+            delay_rate: 100,
+            error_rate: 0,
+            error_status: 500,
+        }
+    }
+}
+
+/// What to do to one upstream call.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ChaosFault {
+    pub delay_ms: u64,
+    pub error_status: Option<u16>,
+}
+
+fn percent_hit(rate: u64) -> bool {
+    rate > 0 && rand::random::<u64>() % 100 < rate
+}
+
+impl ChaosRule {
+    fn parse(settings: &str) -> Self {
+        let mut rule = ChaosRule::default();
+        for setting in settings.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let parsed = setting.split_once('=').and_then(|(name, value)| {
+                let value = value.trim();
+                match name.trim() {
+                    "delay_ms" => {
+                        let (min, max) = value.split_once('-').unwrap_or((value, value));
+                        let (min, max) = (min.trim().parse().ok()?, max.trim().parse().ok()?);
+                        (min <= max).then(|| (rule.delay_min_ms, rule.delay_max_ms) = (min, max))
+                    }
+                    "delay_rate" => value.parse().ok().filter(|r| *r <= 100).map(|r| rule.delay_rate = r),
+                    "error_rate" => value.parse().ok().filter(|r| *r <= 100).map(|r| rule.error_rate = r),
+                    "error_status" => value
+                        .parse()
+                        .ok()
+                        .filter(|s| *s == 429 || (500..600).contains(s))
+                        .map(|s| rule.error_status = s),
+                    _ => None,
+                }
+            });
+            if parsed.is_none() {
+                warn!("Ignoring invalid CHAOS_MODE setting '{}'", setting);
+            }
+        }
+        rule
+    }
+
+    /// Draws the fault for one upstream call.
+    pub fn roll(&self) -> ChaosFault {
+        let delay_ms = if self.delay_max_ms > 0 && percent_hit(self.delay_rate) {
+            self.delay_min_ms + rand::random::<u64>() % (self.delay_max_ms - self.delay_min_ms + 1)
+        } else {
+            0
+        };
+        ChaosFault {
+            delay_ms,
+            error_status: percent_hit(self.error_rate).then_some(self.error_status),
+        }
+    }
+}
+
+fn is_enabled(env: &Env) -> bool {
+    let deploy_env = env.var("DEPLOY_ENV").map(|v| v.to_string()).unwrap_or_default();
+    let is_local = env.var("IS_LOCAL").map(|v| v.to_string() == "true").unwrap_or(false);
+    is_local || ALLOWED_ENVIRONMENTS.contains(&deploy_env.as_str())
+}
+
+fn pattern_matches(pattern: &str, provider: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => provider.starts_with(prefix),
+        None => pattern == provider,
+    }
+}
+
+/// Returns the chaos rule for a provider, or `None` when chaos mode is off or no rule applies.
+pub fn rule_for(env: &Env, provider: &str) -> Option<ChaosRule> {
+    let config = env.var("CHAOS_MODE").ok()?.to_string();
+    if config.trim().is_empty() {
+        return None;
+    }
+    if !is_enabled(env) {
+        warn!("CHAOS_MODE is set but ignored outside dev and staging deployments.");
+        return None;
+    }
+    config
+        .split(';')
+        .filter_map(|rule| rule.split_once(':'))
+        .find(|(pattern, _)| pattern_matches(pattern.trim(), provider))
+        .map(|(_, settings)| ChaosRule::parse(settings))
+}
+
+/// The response returned in place of the upstream call when an error is injected.
+pub fn error_response(status: u16) -> Result<Response> {
+    let body = serde_json::json!({
+        "error": {
+            "message": format!("Injected by chaos mode (status {})", status),
+            "type": "chaos_injected",
+            "code": status,
+        }
+    });
+    Ok(Response::from_json(&body)?.with_status(status))
+}
@@ -0,0 +1,163 @@
+//! This module describes which providers support each OpenAI-compatible route that
+//! cannot go through the AI Gateway's unified `compat` endpoint, and where to send them.
+
+use phf::phf_map;
+use std::borrow::Cow;
+
+/// Where to send a compat request for a given provider.
+#[derive(Clone)]
+pub struct CompatEndpoint {
+    /// The full native URL, used in local development.
+    pub native_url: Cow<'static, str>,
+    /// The provider-specific path appended to the AI Gateway base URL in production.
+    pub gateway_path: Cow<'static, str>,
+}
+
+/// Providers that expose an OpenAI Whisper-style `audio/transcriptions` endpoint.
+static TRANSCRIPTION_ENDPOINTS: phf::Map<&'static str, CompatEndpoint> = phf_map! {
+    "openai" => CompatEndpoint {
+        native_url: Cow::Borrowed("https://api.openai.com/v1/audio/transcriptions"),
+        gateway_path: Cow::Borrowed("openai/audio/transcriptions"),
+    },
+    "groq" => CompatEndpoint {
+        native_url: Cow::Borrowed("https://api.groq.com/openai/v1/audio/transcriptions"),
+        gateway_path: Cow::Borrowed("groq/audio/transcriptions"),
+    },
+};
+
+/// Returns the transcription endpoint for a provider, if it supports transcription.
+pub fn transcription_endpoint(provider: &str) -> Option<&'static CompatEndpoint> {
+    TRANSCRIPTION_ENDPOINTS.get(provider)
+}
+
+/// Providers that expose an OpenAI-style `images/generations` endpoint.
+static IMAGE_GENERATION_ENDPOINTS: phf::Map<&'static str, CompatEndpoint> = phf_map! {
+    "openai" => CompatEndpoint {
+        native_url: Cow::Borrowed("https://api.openai.com/v1/images/generations"),
+        gateway_path: Cow::Borrowed("openai/images/generations"),
+    },
+    "grok" => CompatEndpoint {
+        native_url: Cow::Borrowed("https://api.x.ai/v1/images/generations"),
+        gateway_path: Cow::Borrowed("grok/v1/images/generations"),
+    },
+};
+
+/// Returns the image generation endpoint for a provider, if it supports image generation.
+pub fn image_generation_endpoint(provider: &str) -> Option<&'static CompatEndpoint> {
+    IMAGE_GENERATION_ENDPOINTS.get(provider)
+}
+
+/// The request/response shape a provider's rerank endpoint speaks.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RerankStyle {
+    /// Cohere/Jina-style `query` + `documents`; passed through with the model rewritten.
+    Cohere,
+    /// Workers AI `query` + `contexts`; translated both ways.
+    WorkersAi,
+}
+
+/// Providers that expose a Cohere-style `rerank` endpoint.
+static RERANK_ENDPOINTS: phf::Map<&'static str, CompatEndpoint> = phf_map! {
+    "cohere" => CompatEndpoint {
+        native_url: Cow::Borrowed("https://api.cohere.com/v2/rerank"),
+        gateway_path: Cow::Borrowed("cohere/v2/rerank"),
+    },
+};
+
+/// Returns the rerank endpoint for a provider and model, along with the shape it expects.
+/// Workers AI runs each model at its own URL, so its endpoint depends on the model and account.
+pub fn rerank_endpoint(
+    provider: &str,
+    model: &str,
+    account_id: &str,
+) -> Option<(CompatEndpoint, RerankStyle)> {
+    if provider == "workers-ai" {
+        return Some((
+            CompatEndpoint {
+                native_url: Cow::Owned(format!(
+                    "https://api.cloudflare.com/client/v4/accounts/{}/ai/run/{}",
+                    account_id, model
+                )),
+                gateway_path: Cow::Owned(format!("workers-ai/{}", model)),
+            },
+            RerankStyle::WorkersAi,
+        ));
+    }
+    RERANK_ENDPOINTS
+        .get(provider)
+        .map(|endpoint| (endpoint.clone(), RerankStyle::Cohere))
+}
+
+/// Where a provider lists its models, and how to read the model names from the response.
+pub struct ModelListEndpoint {
+    pub url: &'static str,
+    /// The array field holding the models.
+    pub list_field: &'static str,
+    /// The field of each entry holding the model name.
+    pub id_field: &'static str,
+    /// A prefix to strip from each name (Gemini returns `models/gemini-...`).
+    pub strip_prefix: &'static str,
+}
+
+/// Providers whose model lists can be aggregated into `/compat/models`.
+static MODEL_LIST_ENDPOINTS: phf::Map<&'static str, ModelListEndpoint> = phf_map! {
+    "openai" => ModelListEndpoint {
+        url: "https://api.openai.com/v1/models",
+        list_field: "data",
+        id_field: "id",
+        strip_prefix: "",
+    },
+    "google-ai-studio" => ModelListEndpoint {
+        url: "https://generativelanguage.googleapis.com/v1beta/models?pageSize=1000",
+        list_field: "models",
+        id_field: "name",
+        strip_prefix: "models/",
+    },
+    "anthropic" => ModelListEndpoint {
+        url: "https://api.anthropic.com/v1/models?limit=1000",
+        list_field: "data",
+        id_field: "id",
+        strip_prefix: "",
+    },
+    "groq" => ModelListEndpoint {
+        url: "https://api.groq.com/openai/v1/models",
+        list_field: "data",
+        id_field: "id",
+        strip_prefix: "",
+    },
+    "grok" => ModelListEndpoint {
+        url: "https://api.x.ai/v1/models",
+        list_field: "data",
+        id_field: "id",
+        strip_prefix: "",
+    },
+    "mistral" => ModelListEndpoint {
+        url: "https://api.mistral.ai/v1/models",
+        list_field: "data",
+        id_field: "id",
+        strip_prefix: "",
+    },
+    "deepseek" => ModelListEndpoint {
+        url: "https://api.deepseek.com/models",
+        list_field: "data",
+        id_field: "id",
+        strip_prefix: "",
+    },
+    "openrouter" => ModelListEndpoint {
+        url: "https://openrouter.ai/api/v1/models",
+        list_field: "data",
+        id_field: "id",
+        strip_prefix: "",
+    },
+    "cohere" => ModelListEndpoint {
+        url: "https://api.cohere.com/v1/models?page_size=1000",
+        list_field: "models",
+        id_field: "name",
+        strip_prefix: "",
+    },
+};
+
+/// Returns the model listing endpoint for a provider, if it has one we know how to read.
+pub fn model_list_endpoint(provider: &str) -> Option<&'static ModelListEndpoint> {
+    MODEL_LIST_ENDPOINTS.get(provider)
+}
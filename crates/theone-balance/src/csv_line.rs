@@ -0,0 +1,71 @@
+//! This module reads and writes single CSV lines for the key import/export format. The
+//! sync CLI compiles this file too, so it only depends on `std`.
+
+/// Quotes a field when it contains a separator, a quote or a line break.
+pub fn quote_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Splits one CSV line into fields, honouring double-quoted fields.
+pub fn split_line(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err("unterminated quoted field".to_string());
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(line: &str) -> Vec<String> {
+        split_line(line).unwrap()
+    }
+
+    #[test]
+    fn splits_plain_and_empty_fields() {
+        assert_eq!(split("a,b,c"), ["a", "b", "c"]);
+        assert_eq!(split("a,,c,"), ["a", "", "c", ""]);
+        assert_eq!(split(""), [""]);
+    }
+
+    #[test]
+    fn keeps_separators_and_escaped_quotes_inside_quotes() {
+        assert_eq!(split(r#""a,b",c"#), ["a,b", "c"]);
+        assert_eq!(split(r#""say ""hi""",x"#), [r#"say "hi""#, "x"]);
+        assert_eq!(split(r#""""#), [""]);
+    }
+
+    #[test]
+    fn rejects_an_unterminated_quote() {
+        assert!(split_line(r#"a,"b"#).is_err());
+        assert!(split_line(r#""a"",b"#).is_err());
+    }
+
+    #[test]
+    fn quoted_fields_round_trip() {
+        for value in ["plain", "a,b", r#"say "hi""#, "two\nlines", ""] {
+            assert_eq!(split(&format!("{},x", quote_field(value))), [value, "x"]);
+        }
+    }
+}
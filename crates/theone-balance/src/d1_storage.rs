@@ -1,11 +1,27 @@
 //! This module contains the state management logic using a raw D1 database binding.
 //! It is only compiled when the `raw_d1` feature is enabled.
 
-use crate::dbmodels::{Key as DbKey, ModelCooling};
+use crate::auth::Session;
+use crate::balancer::{self, KeyStore};
+use crate::dbmodels::{
+    ClientKey as DbClientKey, CustomProvider as DbCustomProvider, Key as DbKey, ModelCatalog, ModelCooling, ProviderSetting,
+    AlertEvent, KeyEvent, RequestEvent, Sample, Session as DbSession, Setting, UsageEvent,
+};
 use crate::error_handling;
-use crate::hybrid::{get_schema, HybridExecutor};
+use crate::hybrid::{d1_integer, get_schema, HybridExecutor, SqlPreview};
+use crate::key_format;
+use crate::key_transfer::KeyRecord;
 use crate::request as key_tester;
-use crate::state::strategy::{ApiKey, ApiKeyStatus};
+use crate::metrics::{KeyCountRow, MetricDelta, MetricRow, RequestOutcome};
+use crate::sampling::{self, SampleRecord};
+use crate::settings::Settings;
+use crate::util;
+use crate::totp::Enrollment;
+use crate::state::strategy::{
+    join_tags, parse_tags, split_tags, ApiKey, ApiKeyStatus, ClientKey, CustomProvider, KeyMetricsDelta, KeyScore, KeyTier,
+    LatencyHistogram, ProviderSettings, Role, MAX_KEY_NOTE_CHARS, MAX_KEY_WEIGHT,
+};
+use crate::usage::{ClientQuota, ClientUsage, UsageRecord};
 use futures_util::future::join_all;
 use js_sys::Date;
 use mini_moka::sync::Cache;
@@ -18,8 +34,8 @@ use thiserror::Error;
 use toasty::stmt::{IntoInsert, IntoSelect};
 use toasty::Error as ToastyError;
 use toasty::Model;
-use tracing::{debug, info, warn};
-use worker::{D1Database, Env, Fetch, Headers, Method, Request, RequestInit};
+use tracing::{info, warn};
+use worker::{D1Database, Fetch, Headers, Method, Request, RequestInit};
 
 static API_KEY_CACHE: Lazy<Cache<String, Vec<ApiKey>>> = Lazy::new(|| {
     Cache::builder()
@@ -27,10 +43,92 @@ static API_KEY_CACHE: Lazy<Cache<String, Vec<ApiKey>>> = Lazy::new(|| {
         .build()
 });
 
-// The new "Penalty Box" cache.
-static COOLDOWN_CACHE: Lazy<Cache<String, ()>> =
+// The new "Penalty Box" cache. Entries live as long as the penalty for their cause, and
+// are keyed by `penalty_key`.
+static COOLDOWN_CACHE: Lazy<Cache<String, Penalty>> =
     Lazy::new(|| Cache::builder().max_capacity(10_000).build());
 
+/// Why a key is in the penalty box and until when.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Penalty {
+    /// The deployment that benched the key, see `Settings::deploy_env`.
+    pub deploy_env: String,
+    pub provider: String,
+    pub key_id: String,
+    /// Milliseconds since the epoch.
+    pub expires_at_ms: u64,
+    /// The failure class, see `ErrorAnalysis::class`.
+    pub cause: &'static str,
+}
+
+/// Penalty-box entries are namespaced by deployment and provider, so the entries of
+/// environments sharing code, or dumped for debugging, can't be mistaken for each other.
+fn penalty_key(settings: &Settings, provider: &str, key_id: &str) -> String {
+    format!("{}/{}/{}", settings.deploy_env, provider, key_id)
+}
+
+// Client key lookups happen on every proxied request, so cache them briefly.
+// Misses are cached as `None` so unknown keys don't hit D1 repeatedly.
+static CLIENT_KEY_CACHE: Lazy<Cache<String, Option<ClientKey>>> = Lazy::new(|| {
+    Cache::builder()
+        .max_capacity(10_000)
+        .time_to_live(Duration::from_secs(60))
+        .build()
+});
+
+// Client keys touched recently by this isolate, so `touch_client_key` doesn't hit D1 on every
+// proxied request. Entries live as long as the touch interval.
+static CLIENT_TOUCH_CACHE: Lazy<Cache<String, ()>> = Lazy::new(|| {
+    Cache::builder()
+        .max_capacity(10_000)
+        .time_to_live(Duration::from_secs(CLIENT_TOUCH_INTERVAL_SECONDS as u64))
+        .build()
+});
+
+// Every UI page and admin API call with a session cookie looks its session up. Revocations
+// reach the other isolates once their entry expires.
+static SESSION_CACHE: Lazy<Cache<String, Option<Session>>> = Lazy::new(|| {
+    Cache::builder()
+        .max_capacity(10_000)
+        .time_to_live(Duration::from_secs(30))
+        .build()
+});
+
+// Quota checks tolerate a few seconds of staleness in exchange for not aggregating
+// usage_events on every request.
+static CLIENT_USAGE_CACHE: Lazy<Cache<String, ClientUsage>> = Lazy::new(|| {
+    Cache::builder()
+        .max_capacity(10_000)
+        .time_to_live(Duration::from_secs(10))
+        .build()
+});
+
+// Provider settings are read on every proxied request but change rarely.
+static PROVIDER_SETTINGS_CACHE: Lazy<Cache<String, ProviderSettings>> = Lazy::new(|| {
+    Cache::builder()
+        .max_capacity(1_000)
+        .time_to_live(Duration::from_secs(60))
+        .build()
+});
+
+// Every proxied request looks up its provider here, built-in providers included, so
+// misses are cached too.
+static CUSTOM_PROVIDER_CACHE: Lazy<Cache<String, Option<CustomProvider>>> = Lazy::new(|| {
+    Cache::builder()
+        .max_capacity(1_000)
+        .time_to_live(Duration::from_secs(60))
+        .build()
+});
+
+// Every proxied request labels its metrics by the provider's model list, which is only
+// refreshed every few hours.
+static MODEL_CATALOG_CACHE: Lazy<Cache<String, Vec<String>>> = Lazy::new(|| {
+    Cache::builder()
+        .max_capacity(1_000)
+        .time_to_live(Duration::from_secs(300))
+        .build()
+});
+
 #[derive(Debug, Error)]
 pub enum StorageError {
     #[error("Toasty error: {0}")]
@@ -50,8 +148,25 @@ impl From<StorageError> for worker::Error {
 
 use uuid::Uuid;
 
+/// Parses a `model_coolings` column into each model's cooldown end. Both the
+/// `{model: end_at}` form written by the queue consumer and the
+/// `{model: {total_seconds, end_at}}` form written on the request path are accepted.
+fn parse_model_coolings(json: &str) -> HashMap<String, u64> {
+    let coolings: HashMap<String, serde_json::Value> = serde_json::from_str(json).unwrap_or_default();
+    coolings
+        .into_iter()
+        .filter_map(|(model, cooling)| {
+            let end_at = cooling
+                .as_u64()
+                .or_else(|| cooling.get("end_at").and_then(|end_at| end_at.as_u64()))?;
+            Some((model, end_at))
+        })
+        .collect()
+}
+
 /// Convert a DbKey to an ApiKey
 fn db_key_to_api_key(db_key: DbKey) -> ApiKey {
+    let latency_histogram = LatencyHistogram::from_db(&db_key.latency_histogram);
     ApiKey {
         id: db_key.id.to_string(),
         key: db_key.key,
@@ -61,81 +176,252 @@ fn db_key_to_api_key(db_key: DbKey) -> ApiKey {
         } else {
             ApiKeyStatus::Blocked
         },
-        model_coolings: serde_json::from_str(&db_key.model_coolings).unwrap_or_default(),
+        model_coolings: parse_model_coolings(&db_key.model_coolings),
         total_cooling_seconds: db_key.total_cooling_seconds as u64,
         created_at: db_key.created_at as u64,
         updated_at: db_key.updated_at as u64,
         latency_ms: db_key.latency_ms,
+        latency_ewma_ms: db_key.latency_ewma_ms,
+        latency_p50_ms: latency_histogram.quantile(0.5),
+        latency_p95_ms: latency_histogram.quantile(0.95),
         // success_rate is stored as i64 (scaled by 1000), so we convert it to f64 for ApiKey
         success_rate: db_key.success_rate as f64 / 1000.0,
         consecutive_failures: db_key.consecutive_failures,
         last_checked_at: db_key.last_checked_at as u64,
         last_succeeded_at: db_key.last_succeeded_at as u64,
+        recent_requests: estimate_recent_requests(
+            db_key.usage_window_start,
+            db_key.usage_window_requests,
+            db_key.usage_prev_window_requests,
+            (Date::now() / 1000.0) as i64,
+        ),
+        tier: KeyTier::from_db(&db_key.tier),
+        block_reason: db_key.block_reason,
+        note: db_key.note,
+        tags: split_tags(&db_key.tags),
+        weight: db_key.weight,
+    }
+}
+
+/// The columns the keys list shows. Of the `model_coolings` JSON only the cooldowns still
+/// running are kept; the full history is loaded per key on demand.
+const KEY_LIST_COLUMNS: &[&str] = &[
+    "id",
+    "key",
+    "provider",
+    "status",
+    "model_coolings",
+    "total_cooling_seconds",
+    "created_at",
+    "updated_at",
+    "tier",
+    "block_reason",
+    "note",
+    "tags",
+    "weight",
+];
+
+#[derive(serde::Deserialize)]
+struct KeyListRow {
+    id: String,
+    key: String,
+    provider: String,
+    status: String,
+    model_coolings: String,
+    total_cooling_seconds: i64,
+    created_at: i64,
+    updated_at: i64,
+    tier: String,
+    block_reason: String,
+    note: String,
+    tags: String,
+    weight: i64,
+}
+
+impl From<KeyListRow> for ApiKey {
+    fn from(row: KeyListRow) -> Self {
+        let now = (Date::now() / 1000.0) as u64;
+        let mut model_coolings = parse_model_coolings(&row.model_coolings);
+        model_coolings.retain(|_, end_at| *end_at > now);
+        ApiKey {
+            id: row.id,
+            key: row.key,
+            provider: row.provider,
+            status: if row.status == "active" {
+                ApiKeyStatus::Active
+            } else {
+                ApiKeyStatus::Blocked
+            },
+            model_coolings,
+            total_cooling_seconds: row.total_cooling_seconds as u64,
+            created_at: row.created_at as u64,
+            updated_at: row.updated_at as u64,
+            latency_ms: 0,
+            latency_ewma_ms: 0,
+            latency_p50_ms: 0,
+            latency_p95_ms: 0,
+            success_rate: 0.0,
+            consecutive_failures: 0,
+            last_checked_at: 0,
+            last_succeeded_at: 0,
+            recent_requests: 0,
+            tier: KeyTier::from_db(&row.tier),
+            block_reason: row.block_reason,
+            note: row.note,
+            tags: split_tags(&row.tags),
+            weight: row.weight,
+        }
+    }
+}
+
+/// Length of the windows over which requests served per key are counted.
+pub const KEY_USAGE_WINDOW_SECONDS: i64 = 3600;
+
+/// Start of the usage window containing `now`.
+fn current_usage_window_start(now: i64) -> i64 {
+    now - now.rem_euclid(KEY_USAGE_WINDOW_SECONDS)
+}
+
+/// Rolls a key's usage counters forward to the window containing `now`, returning
+/// `(window_start, window_requests, prev_window_requests)`.
+fn roll_usage_window(window_start: i64, requests: i64, prev_requests: i64, now: i64) -> (i64, i64, i64) {
+    let current_start = current_usage_window_start(now);
+    if current_start == window_start {
+        (window_start, requests, prev_requests)
+    } else if current_start - window_start == KEY_USAGE_WINDOW_SECONDS {
+        (current_start, 0, requests)
+    } else {
+        (current_start, 0, 0)
+    }
+}
+
+/// Estimates requests served over the last full window: the current window's count
+/// plus the part of the previous window that still overlaps it.
+fn estimate_recent_requests(window_start: i64, requests: i64, prev_requests: i64, now: i64) -> u64 {
+    let (window_start, requests, prev_requests) = roll_usage_window(window_start, requests, prev_requests, now);
+    let elapsed = (now - window_start).clamp(0, KEY_USAGE_WINDOW_SECONDS);
+    let carried = prev_requests * (KEY_USAGE_WINDOW_SECONDS - elapsed) / KEY_USAGE_WINDOW_SECONDS;
+    (requests + carried).max(0) as u64
+}
+
+/// Splits a comma-separated scope column into its entries.
+fn split_scope(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Convert a DbClientKey to a ClientKey
+fn db_client_key_to_client_key(db_key: DbClientKey) -> ClientKey {
+    ClientKey {
+        id: db_key.id.to_string(),
+        name: db_key.name,
+        key: String::new(),
+        key_prefix: db_key.key_prefix,
+        allowed_providers: split_scope(&db_key.allowed_providers),
+        allowed_models: split_scope(&db_key.allowed_models),
+        revoked: db_key.status != "active",
+        quota: ClientQuota {
+            max_requests_per_day: db_key.max_requests_per_day as u64,
+            max_tokens_per_month: db_key.max_tokens_per_month as u64,
+            max_budget_micros: db_key.max_budget_micros as u64,
+        },
+        role: Role::parse(&db_key.role),
+        created_at: db_key.created_at as u64,
+        last_used_at: db_key.last_used_at as u64,
     }
 }
 
 // Helper to get the HybridExecutor
+/// The query builder of the keys table, as returned by `DbKey::filter_by_provider` and co.
+type KeyQuery = <DbKey as toasty::relation::Relation>::Query;
+
 fn get_executor(db: &D1Database) -> HybridExecutor {
     HybridExecutor::new(db, get_schema().clone())
 }
 
+/// Builds a `LIKE` pattern matching `needle` anywhere, escaping its wildcards.
+fn like_substring_pattern(needle: &str) -> String {
+    let escaped = needle
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+/// The keys of a tab of a provider's keys page matching the search. The "trash" tab lists
+/// deleted keys of any status; the other tabs only list live keys. Search terms of the form
+/// `tag:team-a` keep the keys carrying that tag, the rest is searched for in the key. Tags
+/// are matched on the `tags` column, within the provider's rows, so the list stays a single
+/// select; `key_tags` serves the counts per tag.
+fn key_list_query(provider: &str, status: &str, q: &str) -> KeyQuery {
+    let mut query = if status == "trash" {
+        DbKey::filter_by_provider(provider.to_string()).filter(DbKey::FIELDS.deleted_at.gt(0))
+    } else {
+        DbKey::filter_by_provider(provider.to_string())
+            .filter_by_status(status.to_string())
+            .filter(DbKey::FIELDS.deleted_at.eq(0))
+    };
+    let mut terms = Vec::new();
+    for term in q.split_whitespace() {
+        match term.strip_prefix("tag:") {
+            Some(tag) if !tag.is_empty() => {
+                let tag = format!(",{},", tag.to_lowercase());
+                query = query.filter(DbKey::FIELDS.tags.like(like_substring_pattern(&tag)));
+            }
+            _ => terms.push(term),
+        }
+    }
+    if terms.is_empty() {
+        query
+    } else {
+        query.filter(DbKey::FIELDS.key.like(like_substring_pattern(&terms.join(" "))))
+    }
+}
+
+/// Which page of a provider's keys `list_keys` returns: the status tab, search, page and
+/// sort order.
+pub struct KeyListPage<'a> {
+    pub status: &'a str,
+    pub q: &'a str,
+    pub page: usize,
+    pub page_size: usize,
+    pub sort_by: &'a str,
+    pub sort_order: &'a str,
+}
+
+/// One page of `key_list_query`, sorted.
+fn key_list_page_query(provider: &str, list: &KeyListPage) -> KeyQuery {
+    let KeyListPage { status, q, page, page_size, sort_by, sort_order } = *list;
+    let query = key_list_query(provider, status, q);
+    let query = match (sort_by, sort_order) {
+        ("createdAt", "asc") => query.order_by(DbKey::FIELDS.created_at.asc()),
+        ("createdAt", _) => query.order_by(DbKey::FIELDS.created_at.desc()),
+        ("totalCoolingSeconds", "asc") => query.order_by(DbKey::FIELDS.total_cooling_seconds.asc()),
+        ("totalCoolingSeconds", _) => query.order_by(DbKey::FIELDS.total_cooling_seconds.desc()),
+        (_, "asc") => query.order_by(DbKey::FIELDS.updated_at.asc()),
+        _ => query.order_by(DbKey::FIELDS.updated_at.desc()),
+    };
+    let offset = (page.max(1) - 1) * page_size;
+    query.limit(page_size as i64).offset(offset as i64)
+}
+
 #[worker::send]
 pub async fn list_keys(
     db: &D1Database,
     provider: &str,
-    status: &str,
-    _q: &str,
-    page: usize,
-    page_size: usize,
-    sort_by: &str,
-    sort_order: &str,
+    list: &KeyListPage<'_>,
 ) -> StdResult<(Vec<ApiKey>, i32), StorageError> {
     let executor = get_executor(db);
 
-    // Build the base query using correct Toasty API
-    let mut base_query =
-        DbKey::filter_by_provider(provider.to_string()).filter_by_status(status.to_string());
-
-    // Apply sorting
-    match sort_by {
-        "createdAt" => {
-            if sort_order == "asc" {
-                base_query = base_query.order_by(DbKey::FIELDS.created_at.asc());
-            } else {
-                base_query = base_query.order_by(DbKey::FIELDS.created_at.desc());
-            }
-        }
-        "totalCoolingSeconds" => {
-            if sort_order == "asc" {
-                base_query = base_query.order_by(DbKey::FIELDS.total_cooling_seconds.asc());
-            } else {
-                base_query = base_query.order_by(DbKey::FIELDS.total_cooling_seconds.desc());
-            }
-        }
-        _ => {
-            if sort_order == "asc" {
-                base_query = base_query.order_by(DbKey::FIELDS.updated_at.asc());
-            } else {
-                base_query = base_query.order_by(DbKey::FIELDS.updated_at.desc());
-            }
-        }
-    }
-
-    // Get total count - we need a separate query for this
-    let count_query =
-        DbKey::filter_by_provider(provider.to_string()).filter_by_status(status.to_string());
-    let all_results = executor.exec_query(count_query).await?;
-    let total_count = all_results.len() as i32;
+    // Get total count with a COUNT(*) in D1 rather than loading every row
+    let total_count = executor.exec_count(key_list_query(provider, list.status, list.q)).await? as i32;
 
-    // Apply pagination with limit and offset
-    let offset = (page - 1) * page_size;
-    let paginated_query = base_query.limit(page_size as i64).offset(offset as i64);
-
-    let paginated_results = executor.exec_query(paginated_query).await?;
-    let api_keys: Vec<ApiKey> = paginated_results
-        .into_iter()
-        .map(db_key_to_api_key)
-        .collect();
+    let paginated_query = key_list_page_query(provider, list);
+    let paginated_results: Vec<KeyListRow> = executor.exec_query_columns(paginated_query, KEY_LIST_COLUMNS).await?;
+    let api_keys: Vec<ApiKey> = paginated_results.into_iter().map(ApiKey::from).collect();
 
     Ok((api_keys, total_count))
 }
@@ -148,29 +434,40 @@ pub async fn add_keys(
     let executor = get_executor(db);
 
     // Parse and deduplicate the input keys first.
-    let mut unique_new_keys: HashSet<String> = keys_str
-        .split(|c| c == '\n' || c == ',')
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
-        .collect();
+    let mut unique_new_keys: HashSet<String> = key_format::split_keys(keys_str).map(str::to_string).collect();
 
     if unique_new_keys.is_empty() {
         return Ok(());
     }
 
     // Fetch existing keys for the provider to find which ones we actually need to add.
-    let existing_db_keys = executor
-        .exec_query(DbKey::filter_by_provider(provider.to_string()))
+    #[derive(serde::Deserialize)]
+    struct ExistingKey {
+        id: String,
+        key: String,
+        deleted_at: i64,
+    }
+    let existing_db_keys: Vec<ExistingKey> = executor
+        .exec_query_columns(DbKey::filter_by_provider(provider.to_string()), &["id", "key", "deleted_at"])
         .await?;
 
+    let now = (Date::now() / 1000.0) as i64;
+
     // Remove any keys that already exist in the database from our set of new keys.
+    // Re-adding a key that is in the trash restores it.
     for existing_key in existing_db_keys {
-        unique_new_keys.remove(&existing_key.key);
+        if unique_new_keys.remove(&existing_key.key) && existing_key.deleted_at > 0 {
+            let update_query = DbKey::filter_by_id(existing_key.id)
+                .update()
+                .deleted_at(0)
+                .updated_at(now);
+            executor.exec_update(update_query.stmt).await?;
+        }
     }
 
-    let now = (Date::now() / 1000.0) as i64;
-
-    // Insert only the truly new keys.
+    // Insert only the truly new keys, all in one batch. A key added concurrently by another
+    // request is skipped by the unique (provider, key_hash) index instead of duplicated.
+    let mut inserts = Vec::with_capacity(unique_new_keys.len());
     for key in unique_new_keys {
         let id_str = Uuid::new_v4().to_string();
         let untyped_id = toasty_core::stmt::Id::from_string(DbKey::ID, id_str);
@@ -178,6 +475,7 @@ pub async fn add_keys(
 
         let insert = DbKey::create()
             .id(typed_id)
+            .key_hash(util::key_hash(&key))
             .key(key)
             .provider(provider.to_string())
             .status("active".to_string())
@@ -186,13 +484,27 @@ pub async fn add_keys(
             .created_at(now)
             .updated_at(now)
             .latency_ms(0)
+            .latency_ewma_ms(0)
+            .latency_histogram("[]".to_string())
             .success_rate(1000)
             .consecutive_failures(0)
             .last_checked_at(0)
-            .last_succeeded_at(0);
-
-        executor.exec_insert(insert.into_insert()).await?;
+            .last_succeeded_at(0)
+            .deleted_at(0)
+            .usage_window_start(0)
+            .usage_window_requests(0)
+            .usage_prev_window_requests(0)
+            .tier(KeyTier::Free.as_str().to_string())
+            .block_reason(String::new())
+            .blocked_at(0)
+            .reactivations(0)
+            .note(String::new())
+            .tags(String::new())
+            .weight(0);
+
+        inserts.push(insert.into_insert());
     }
+    executor.exec_insert_batch_ignoring_conflicts(inserts).await?;
 
     // Invalidate the cache for this provider since we've added new keys.
     API_KEY_CACHE.invalidate(&provider.to_string());
@@ -200,44 +512,310 @@ pub async fn add_keys(
     Ok(())
 }
 
-pub async fn delete_keys(db: &D1Database, ids: Vec<String>) -> StdResult<(), StorageError> {
-    if ids.is_empty() {
-        return Ok(());
-    }
+/// Lists every live key, optionally for a single provider, for export.
+pub async fn list_all_keys(db: &D1Database, provider: Option<&str>) -> StdResult<Vec<ApiKey>, StorageError> {
     let executor = get_executor(db);
+    let db_keys = executor.exec_query(all_keys_query(provider)).await?;
+    Ok(db_keys.into_iter().map(db_key_to_api_key).collect())
+}
 
-    // First, fetch the keys to be deleted so we can find out their providers.
-    let keys_to_delete = executor
-        .exec_query(DbKey::filter(DbKey::FIELDS.id.in_set(ids.clone())))
-        .await?;
+/// The live keys of a provider, or of all providers, oldest first.
+fn all_keys_query(provider: Option<&str>) -> KeyQuery {
+    let live = DbKey::FIELDS.deleted_at.eq(0);
+    let query = match provider {
+        Some(provider) => DbKey::filter_by_provider(provider.to_string()).filter(live),
+        None => DbKey::filter(live),
+    };
+    query.order_by(DbKey::FIELDS.created_at.asc())
+}
+
+/// Result of a key import.
+#[derive(serde::Serialize, Debug, Default)]
+pub struct ImportSummary {
+    pub imported: usize,
+    /// Keys already present in the database or repeated within the import.
+    pub duplicates: usize,
+}
+
+/// Imports validated key records with their status and health metrics. Keys that
+/// already exist for their provider, or appear twice in the input, are skipped.
+pub async fn import_keys(db: &D1Database, records: Vec<KeyRecord>) -> StdResult<ImportSummary, StorageError> {
+    let executor = get_executor(db);
+    let mut summary = ImportSummary::default();
+    let mut seen: HashSet<(String, String)> = HashSet::new();
+    let mut existing_by_provider: HashMap<String, HashSet<String>> = HashMap::new();
+    let now = (Date::now() / 1000.0) as i64;
+    let mut inserts = Vec::new();
+
+    for record in records {
+        if !existing_by_provider.contains_key(&record.provider) {
+            #[derive(serde::Deserialize)]
+            struct ExistingKey {
+                key: String,
+            }
+            let existing: HashSet<String> = executor
+                .exec_query_columns::<_, ExistingKey>(DbKey::filter_by_provider(record.provider.clone()), &["key"])
+                .await?
+                .into_iter()
+                .map(|k| k.key)
+                .collect();
+            existing_by_provider.insert(record.provider.clone(), existing);
+        }
+        if existing_by_provider[&record.provider].contains(&record.key)
+            || !seen.insert((record.provider.clone(), record.key.clone()))
+        {
+            summary.duplicates += 1;
+            continue;
+        }
+
+        let id_str = Uuid::new_v4().to_string();
+        let untyped_id = toasty_core::stmt::Id::from_string(DbKey::ID, id_str);
+        let typed_id = toasty::stmt::Id::from_untyped(untyped_id);
+        let created_at = if record.created_at > 0 { record.created_at as i64 } else { now };
+        // The reason of an imported block is unknown, so probation leaves the key alone.
+        let blocked_at = if record.status == "blocked" { now } else { 0 };
+
+        let insert = DbKey::create()
+            .id(typed_id)
+            .key_hash(util::key_hash(&record.key))
+            .key(record.key)
+            .provider(record.provider.clone())
+            .status(record.status)
+            .model_coolings("{}".to_string())
+            .total_cooling_seconds(record.total_cooling_seconds as i64)
+            .created_at(created_at)
+            .updated_at(now)
+            .latency_ms(record.latency_ms)
+            .latency_ewma_ms(record.latency_ms)
+            .latency_histogram("[]".to_string())
+            .success_rate((record.success_rate * 1000.0) as i64)
+            .consecutive_failures(record.consecutive_failures)
+            .last_checked_at(record.last_checked_at as i64)
+            .last_succeeded_at(record.last_succeeded_at as i64)
+            .deleted_at(0)
+            .usage_window_start(0)
+            .usage_window_requests(0)
+            .usage_prev_window_requests(0)
+            .tier(record.tier)
+            .block_reason(String::new())
+            .blocked_at(blocked_at)
+            .reactivations(0)
+            .note(record.note)
+            .tags(join_tags(&record.tags))
+            .weight(record.weight);
+
+        inserts.push(insert.into_insert());
+    }
+
+    // All new keys are written in one batch, so a failed import leaves nothing behind.
+    // Keys added concurrently since the lookup above are skipped and count as duplicates.
+    let attempted = inserts.len();
+    summary.imported = executor.exec_insert_batch_ignoring_conflicts(inserts).await?;
+    summary.duplicates += attempted - summary.imported;
+    if summary.imported > 0 {
+        db.prepare(SYNC_KEY_TAGS_SQL).run().await?;
+    }
+    for provider in existing_by_provider.keys() {
+        API_KEY_CACHE.invalidate(provider);
+    }
 
-    // Collect all unique provider names from the keys being deleted.
-    let providers_to_invalidate: HashSet<String> =
-        keys_to_delete.into_iter().map(|k| k.provider).collect();
+    Ok(summary)
+}
+
+/// Invalidates the cache of every provider owning one of the given keys.
+async fn invalidate_providers_of(db: &D1Database, ids: &[String]) -> StdResult<(), StorageError> {
+    let executor = get_executor(db);
+    #[derive(serde::Deserialize)]
+    struct KeyProvider {
+        provider: String,
+    }
+    let keys: Vec<KeyProvider> = executor
+        .exec_query_columns(DbKey::filter(DbKey::FIELDS.id.in_set(ids.to_vec())), &["provider"])
+        .await?;
 
-    // Invalidate the cache for each affected provider.
+    // Collect all unique provider names from the affected keys.
+    let providers_to_invalidate: HashSet<String> = keys.into_iter().map(|k| k.provider).collect();
     for provider in providers_to_invalidate {
         API_KEY_CACHE.invalidate(&provider);
     }
+    Ok(())
+}
 
-    // Use filter with in_set for multiple IDs
-    let delete_query = DbKey::filter(DbKey::FIELDS.id.in_set(ids));
+/// Sets `deleted_at` on the given keys, either moving them to the trash (`now`) or
+/// restoring them (0).
+async fn set_deleted_at(db: &D1Database, ids: Vec<String>, deleted_at: i64) -> StdResult<(), StorageError> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+    invalidate_providers_of(db, &ids).await?;
+
+    let executor = get_executor(db);
+    let update_query = DbKey::filter(DbKey::FIELDS.id.in_set(ids))
+        .update()
+        .deleted_at(deleted_at)
+        .updated_at((Date::now() / 1000.0) as i64);
+    executor.exec_update(update_query.stmt).await?;
+    Ok(())
+}
+
+/// Moves keys to the trash. Trashed keys are never used for requests and can be
+/// restored with `restore_keys` until they are purged.
+pub async fn delete_keys(db: &D1Database, ids: Vec<String>) -> StdResult<(), StorageError> {
+    set_deleted_at(db, ids, (Date::now() / 1000.0) as i64).await
+}
+
+/// Moves trashed keys back into their provider's pool with their previous status.
+pub async fn restore_keys(db: &D1Database, ids: Vec<String>) -> StdResult<(), StorageError> {
+    set_deleted_at(db, ids, 0).await
+}
+
+/// Permanently deletes keys, but only those already in the trash.
+pub async fn purge_keys(db: &D1Database, ids: Vec<String>) -> StdResult<(), StorageError> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+    let executor = get_executor(db);
+    let delete_query =
+        DbKey::filter(DbKey::FIELDS.id.in_set(ids)).filter(DbKey::FIELDS.deleted_at.gt(0));
     executor
         .exec_delete(delete_query.into_select().delete())
         .await?;
+    Ok(())
+}
+
+/// The block reason of keys blocked by an operator, which probation never reactivates.
+pub const MANUAL_BLOCK_REASON: &str = "blocked manually";
+
+/// Sets the status of several keys in a single statement, as an operator does.
+pub async fn bulk_update_status(
+    db: &D1Database,
+    ids: Vec<String>,
+    status: ApiKeyStatus,
+) -> StdResult<(), StorageError> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+    invalidate_providers_of(db, &ids).await?;
 
+    let now = (Date::now() / 1000.0) as i64;
+    let (status_str, block_reason, blocked_at) = if status == ApiKeyStatus::Active {
+        ("active", "", 0)
+    } else {
+        ("blocked", MANUAL_BLOCK_REASON, now)
+    };
+    let executor = get_executor(db);
+    let update_query = DbKey::filter(DbKey::FIELDS.id.in_set(ids))
+        .update()
+        .status(status_str.to_string())
+        .block_reason(block_reason.to_string())
+        .blocked_at(blocked_at)
+        .updated_at(now);
+    executor.exec_update(update_query.stmt).await?;
+    Ok(())
+}
+
+/// Moves keys to another billing tier.
+pub async fn bulk_update_tier(db: &D1Database, ids: Vec<String>, tier: KeyTier) -> StdResult<(), StorageError> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+    invalidate_providers_of(db, &ids).await?;
+
+    let executor = get_executor(db);
+    let update_query = DbKey::filter(DbKey::FIELDS.id.in_set(ids))
+        .update()
+        .tier(tier.as_str().to_string())
+        .updated_at((Date::now() / 1000.0) as i64);
+    executor.exec_update(update_query.stmt).await?;
     Ok(())
 }
 
+/// The metadata an operator edits on a key. Fields left out keep their value.
+#[derive(serde::Deserialize, Default, Debug)]
+pub struct KeyMetadataUpdate {
+    pub note: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub weight: Option<i64>,
+    pub tier: Option<KeyTier>,
+}
+
+impl KeyMetadataUpdate {
+    /// Checks the bounds of the fields and normalizes the tags.
+    pub fn validate(&mut self) -> StdResult<(), String> {
+        if let Some(note) = &mut self.note {
+            *note = note.trim().to_string();
+            if note.chars().count() > MAX_KEY_NOTE_CHARS {
+                return Err(format!("The note is longer than {} characters.", MAX_KEY_NOTE_CHARS));
+            }
+        }
+        if let Some(tags) = &mut self.tags {
+            *tags = parse_tags(&tags.join(","))?;
+        }
+        if let Some(weight) = self.weight {
+            if weight.abs() > MAX_KEY_WEIGHT {
+                return Err(format!("The weight must be between -{max} and {max}.", max = MAX_KEY_WEIGHT));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Updates the note, tags, weight or tier of a key, returning the updated key, or `None`
+/// when there is no such key. The fields must have been validated.
+pub async fn update_key_metadata(
+    db: &D1Database,
+    id: &str,
+    update: KeyMetadataUpdate,
+) -> StdResult<Option<ApiKey>, StorageError> {
+    let executor = get_executor(db);
+    let Some(existing) = executor.exec_first(DbKey::filter_by_id(id.to_string())).await? else {
+        return Ok(None);
+    };
+
+    let mut update_query = DbKey::filter_by_id(id.to_string())
+        .update()
+        .updated_at((Date::now() / 1000.0) as i64);
+    if let Some(note) = update.note {
+        update_query = update_query.note(note);
+    }
+    let tags = update.tags;
+    if let Some(tags) = &tags {
+        update_query = update_query.tags(join_tags(tags));
+    }
+    if let Some(weight) = update.weight {
+        update_query = update_query.weight(weight);
+    }
+    if let Some(tier) = update.tier {
+        update_query = update_query.tier(tier.as_str().to_string());
+    }
+    executor.exec_update(update_query.stmt).await?;
+    if let Some(tags) = &tags {
+        replace_key_tags(db, id, &existing.provider, tags).await?;
+    }
+    // The weight and tier change the failover order.
+    API_KEY_CACHE.invalidate(&existing.provider);
+
+    Ok(executor
+        .exec_first(DbKey::filter_by_id(id.to_string()))
+        .await?
+        .map(db_key_to_api_key))
+}
+
+/// Moves every blocked key of a provider to the trash.
 pub async fn delete_all_blocked(db: &D1Database, provider: &str) -> StdResult<(), StorageError> {
     let executor = get_executor(db);
 
-    let query =
-        DbKey::filter_by_provider(provider.to_string()).filter_by_status("blocked".to_string());
+    let now = (Date::now() / 1000.0) as i64;
+    let update_query = DbKey::filter_by_provider(provider.to_string())
+        .filter_by_status("blocked".to_string())
+        .filter(DbKey::FIELDS.deleted_at.eq(0))
+        .update()
+        .deleted_at(now)
+        .updated_at(now);
 
     // Invalidate the cache for this provider since we are deleting keys.
     API_KEY_CACHE.invalidate(&provider.to_string());
-    executor.exec_delete(query.into_select().delete()).await?;
+    executor.exec_update(update_query.stmt).await?;
     Ok(())
 }
 
@@ -276,6 +854,13 @@ pub async fn get_keys_by_ids(
     Ok(api_keys)
 }
 
+/// The live keys of a provider with the "active" status, cooling or not.
+fn active_keys_query(provider: &str) -> KeyQuery {
+    DbKey::filter_by_provider(provider.to_string())
+        .filter_by_status("active".to_string())
+        .filter(DbKey::FIELDS.deleted_at.eq(0))
+}
+
 pub async fn get_active_keys(
     db: &D1Database,
     provider: &str,
@@ -287,10 +872,7 @@ pub async fn get_active_keys(
     }
     let executor = get_executor(db);
 
-    let query =
-        DbKey::filter_by_provider(provider.to_string()).filter_by_status("active".to_string());
-
-    let db_keys = executor.exec_query(query).await?;
+    let db_keys = executor.exec_query(active_keys_query(provider)).await?;
 
     let now = (Date::now() / 1000.0) as u64;
 
@@ -312,7 +894,7 @@ pub async fn get_active_keys(
 }
 
 pub async fn get_healthy_sorted_keys_via_cache(
-    env: &Env,
+    settings: &Settings,
     db: &D1Database,
     provider: &str,
 ) -> StdResult<Vec<ApiKey>, StorageError> {
@@ -321,7 +903,7 @@ pub async fn get_healthy_sorted_keys_via_cache(
         keys
     } else {
         // Or fetch from D1 if the main cache is empty.
-        let keys_from_db = get_healthy_sorted_keys(env, db, provider).await?;
+        let keys_from_db = get_healthy_sorted_keys(settings, db, provider).await?;
         info!(
             provider,
             "Cache miss for provider. Populating cache from D1 with {} keys.",
@@ -343,18 +925,22 @@ pub async fn get_healthy_sorted_keys_via_cache(
     );
 
     // Step 2: NEW - Filter the list in-memory against the cooldown cache.
-    let currently_usable_keys: Vec<ApiKey> = all_cached_keys
+    let mut currently_usable_keys: Vec<ApiKey> = all_cached_keys
         .into_iter()
         .filter(|key| {
             // A key is usable if its ID is NOT in the cooldown cache.
-            let is_on_cooldown = COOLDOWN_CACHE.get(&key.id).is_some();
-            if is_on_cooldown {
-                info!(key_id = %key.id, "Skipping key in local cache due to active cooldown.");
+            let penalty = COOLDOWN_CACHE.get(&penalty_key(settings, provider, &key.id));
+            if let Some(penalty) = &penalty {
+                info!(key_id = %key.id, cause = penalty.cause, "Skipping key in local cache due to active cooldown.");
             }
-            !is_on_cooldown
+            penalty.is_none()
         })
         .collect();
 
+    // Step 3: Order by tier. The sort is stable, so keys keep their health ranking
+    // within a tier; a strategy change applies without waiting for the cache.
+    balancer::order_by_tier(settings.key_tier_strategy, &mut currently_usable_keys);
+
     info!(
         provider,
         "Final count of usable failover keys: {}",
@@ -364,18 +950,53 @@ pub async fn get_healthy_sorted_keys_via_cache(
     Ok(currently_usable_keys)
 }
 
-pub fn flag_key_with_cooldown(key_id: &str, duration_seconds: u64) {
+pub fn flag_key_with_cooldown(
+    settings: &Settings,
+    provider: &str,
+    key_id: &str,
+    cause: &'static str,
+    duration_seconds: u64,
+) {
     info!(
         key_id,
+        cause,
         duration_seconds, "Flagging key for temporary cooldown in local cache."
     );
     COOLDOWN_CACHE.insert_with_ttl(
-        key_id.to_string(),
-        (),
+        penalty_key(settings, provider, key_id),
+        Penalty {
+            deploy_env: settings.deploy_env.clone(),
+            provider: provider.to_string(),
+            key_id: key_id.to_string(),
+            expires_at_ms: Date::now() as u64 + duration_seconds * 1000,
+            cause,
+        },
         Duration::from_secs(duration_seconds),
     );
 }
 
+/// Returns when the first of a provider's locally cooling keys becomes usable again,
+/// in milliseconds since the epoch, or `None` if none of its cached keys are cooling.
+pub fn earliest_cooldown_expiry_ms(settings: &Settings, provider: &str) -> Option<u64> {
+    let keys = API_KEY_CACHE.get(&provider.to_string())?;
+    keys.iter()
+        .filter_map(|key| COOLDOWN_CACHE.get(&penalty_key(settings, provider, &key.id)))
+        .map(|penalty| penalty.expires_at_ms)
+        .min()
+}
+
+/// The keys in this isolate's penalty box, soonest to expire first.
+pub fn penalty_box_entries() -> Vec<Penalty> {
+    let now_ms = Date::now() as u64;
+    let mut entries: Vec<Penalty> = COOLDOWN_CACHE
+        .iter()
+        .map(|entry| entry.value().clone())
+        .filter(|penalty| penalty.expires_at_ms > now_ms)
+        .collect();
+    entries.sort_by_key(|penalty| penalty.expires_at_ms);
+    entries
+}
+
 pub async fn update_status(
     db: &D1Database,
     id: &str,
@@ -393,16 +1014,19 @@ pub async fn update_status(
         API_KEY_CACHE.invalidate(&key.provider);
 
         // Use toasty's update query
-        let status_str = if status == ApiKeyStatus::Active {
-            "active".to_string()
+        let now = (Date::now() / 1000.0) as i64;
+        let (status_str, blocked_at) = if status == ApiKeyStatus::Active {
+            ("active", 0)
         } else {
-            "blocked".to_string()
+            ("blocked", now)
         };
 
         let update_query = DbKey::filter_by_id(id.to_string())
             .update()
-            .status(status_str)
-            .updated_at((Date::now() / 1000.0) as i64);
+            .status(status_str.to_string())
+            .block_reason(String::new())
+            .blocked_at(blocked_at)
+            .updated_at(now);
 
         // Now we can access the public stmt field and execute it
         executor.exec_update(update_query.stmt).await?;
@@ -411,6 +1035,24 @@ pub async fn update_status(
     Ok(())
 }
 
+/// Blocks a key the provider rejected, recording why so probation can re-test it later.
+pub async fn block_key(db: &D1Database, id: &str, provider: &str, reason: &str) -> StdResult<(), StorageError> {
+    let now = (Date::now() / 1000.0) as i64;
+    db.prepare(
+        "UPDATE keys SET status = 'blocked', block_reason = ?2, blocked_at = ?3, updated_at = ?3 \
+         WHERE id = ?1 AND status = 'active'",
+    )
+    .bind_refs(&[
+        worker::D1Type::Text(id),
+        worker::D1Type::Text(reason),
+        d1_integer(now),
+    ])?
+    .run()
+    .await?;
+    API_KEY_CACHE.invalidate(&provider.to_string());
+    Ok(())
+}
+
 pub async fn set_cooldown(
     db: &D1Database,
     id: &str,
@@ -499,117 +1141,117 @@ pub async fn set_key_model_cooldown_if_available(
         Ok(false)
     }
 }
+
 async fn get_healthy_sorted_keys(
-    env: &Env,
+    settings: &Settings,
     db: &D1Database,
     provider: &str,
 ) -> StdResult<Vec<ApiKey>, StorageError> {
-    let all_active_keys = get_active_keys(db, provider).await?;
-    info!(
-        provider,
-        "Initial DB query returned {} active keys before circuit breaker filter.",
-        all_active_keys.len()
-    );
-
     let now = (Date::now() / 1000.0) as u64;
-    const RECOVERY_PERIOD_SECONDS: u64 = 3600; // 1 hour
-
-    let recovery_threshold = env
-        .var("RECOVERY_THRESHOLD")
-        .map(|v| v.to_string().parse().unwrap_or(5))
-        .unwrap_or(5);
+    balancer::healthy_keys(db, settings, provider, now).await
+}
 
-    let mut active_keys: Vec<ApiKey> = all_active_keys
-        .into_iter()
-        .filter(|key| {
-            if key.consecutive_failures < recovery_threshold {
-                true // Key is healthy, include it.
-            } else {
-                // Key has failed 5+ times. Check if it's time for another chance.
-                let time_since_last_check = now.saturating_sub(key.last_checked_at);
-                if time_since_last_check > RECOVERY_PERIOD_SECONDS {
-                    debug!(key_id = %key.id, "Key has been sidelined for over 1 hour. Adding to probationary pool.");
-                    true // Give it a chance.
-                } else {
-                    false // Still in the penalty box.
-                }
-            }
-        })
-        .collect();
+impl KeyStore for D1Database {
+    type Error = StorageError;
 
-    if active_keys.is_empty() {
-        return Ok(Vec::new());
+    async fn active_keys(&self, provider: &str) -> StdResult<Vec<ApiKey>, StorageError> {
+        get_active_keys(self, provider).await
     }
 
-    // Define a helper closure to calculate score
-    let calculate_health_score = |key: &ApiKey| -> i64 {
-        // Lower latency is better, higher success rate is better.
-        let latency_score = 10000 - key.latency_ms;
-        // key.success_rate is a float between 0.0 and 1.0. Scale it for the score.
-        let success_score = (key.success_rate * 1000.0) as i64;
-
-        // Penalize consecutive failures heavily.
-        let failure_penalty = key.consecutive_failures * 50;
-
-        // Add a small bonus for recently successful keys to break ties.
-        let recent_success_bonus = if now.saturating_sub(key.last_succeeded_at) < 300 {
-            10
-        } else {
-            0
-        };
-
-        latency_score + success_score - failure_penalty + recent_success_bonus
-    };
-
-    // Sort by the health score, descending.
-    active_keys.sort_by(|a, b| {
-        let score_b = calculate_health_score(b);
-        let score_a = calculate_health_score(a);
-        score_b.cmp(&score_a)
-    });
-
-    Ok(active_keys)
-}
+    async fn record_attempt(&self, key_id: &str, success: bool, latency_ms: i64) -> StdResult<(), StorageError> {
+        update_key_metrics(self, key_id, success, latency_ms).await
+    }
+}
 
+/// Records the outcome of one attempt with a key in a single UPDATE, so concurrent
+/// requests can't overwrite each other's counts.
 pub async fn update_key_metrics(
     db: &D1Database,
     key_id: &str,
     is_success: bool,
     latency: i64,
 ) -> StdResult<(), StorageError> {
-    let executor = get_executor(db);
-    let key_result = executor
-        .exec_first(DbKey::filter_by_id(key_id.to_string()))
-        .await?;
-
-    if let Some(mut key) = key_result {
-        let now = (Date::now() / 1000.0) as i64;
-        let new_latency = latency;
-        let new_last_checked_at = now;
-
-        let (new_consecutive_failures, new_success_rate, new_last_succeeded_at) = if is_success {
-            // Recalculate success rate using a simple moving average.
-            // We scale by 1000, so 1.0 is 1000.
-            let new_success_rate = (key.success_rate * 99 + 1000) / 100;
-            (0, new_success_rate, now)
-        } else {
-            let new_failures = key.consecutive_failures + 1;
-            // Penalize success rate on failure.
-            let new_success_rate = (key.success_rate * 99) / 100;
-            (new_failures, new_success_rate, key.last_succeeded_at)
-        };
-
-        let update_query = DbKey::filter_by_id(key_id.to_string())
-            .update()
-            .latency_ms(new_latency)
-            .success_rate(new_success_rate)
-            .consecutive_failures(new_consecutive_failures)
-            .last_checked_at(new_last_checked_at)
-            .last_succeeded_at(new_last_succeeded_at)
-            .updated_at(now);
+    apply_key_metrics(db, key_id, &KeyMetricsDelta::attempt(is_success, latency)).await
+}
 
-        executor.exec_update(update_query.stmt).await?;
-    }
+/// Records several attempts with a key, e.g. a batch from the state queue, in a single
+/// UPDATE, as if they had been recorded one by one.
+pub async fn apply_key_metrics(db: &D1Database, key_id: &str, delta: &KeyMetricsDelta) -> StdResult<(), StorageError> {
+    let now = (Date::now() / 1000.0) as i64;
+    let window_start = current_usage_window_start(now);
+
+    // Success rate is a moving average scaled by 1000, so 1.0 is 1000; each attempt keeps
+    // 99% of it. Applied n times that keeps 0.99^n, and the successes among the attempts
+    // make up the rest. Every attempt counts as served, successful or not, since both use
+    // up quota. SQLite evaluates all assignments against the row as it was before the update.
+    let attempts = delta.attempts();
+    let kept = 0.99f64.powi(attempts as i32);
+    let gained = if attempts == 0 {
+        0.0
+    } else {
+        1000.0 * delta.successes as f64 / attempts as f64 * (1.0 - kept)
+    };
+    // The latency average keeps 80% per attempt, and the histogram counts, scaled by 1000,
+    // keep 99% so it reflects roughly the last hundred attempts. Both batch the same way
+    // as the success rate.
+    let samples = delta.latency_histogram.total();
+    let mean_latency = if samples == 0 {
+        0
+    } else {
+        delta.latency_total_ms / samples as i64
+    };
+    let histogram = delta.latency_histogram.to_db();
+    let sql = r#"
+        UPDATE keys SET
+            latency_ms = COALESCE(?2, latency_ms),
+            latency_ewma_ms = CASE
+                WHEN ?11 = 0 THEN latency_ewma_ms
+                WHEN latency_ewma_ms = 0 THEN ?12
+                ELSE CAST(ROUND(latency_ewma_ms * ?13 + ?12 * (1 - ?13)) AS INTEGER)
+            END,
+            latency_histogram = CASE WHEN ?11 = 0 THEN latency_histogram ELSE (
+                SELECT json_group_array(CAST(ROUND(
+                    COALESCE(json_extract(keys.latency_histogram, '$[' || sample.key || ']'), 0) * ?14
+                        + sample.value * 1000
+                ) AS INTEGER))
+                FROM json_each(?15) AS sample
+            ) END,
+            success_rate = CAST(ROUND(success_rate * ?7 + ?8) AS INTEGER),
+            consecutive_failures = CASE WHEN ?5 > 0 THEN ?9 ELSE consecutive_failures + ?9 END,
+            last_checked_at = CASE WHEN ?10 > 0 THEN ?3 ELSE last_checked_at END,
+            last_succeeded_at = CASE WHEN ?5 > 0 THEN ?3 ELSE last_succeeded_at END,
+            usage_prev_window_requests = CASE
+                WHEN usage_window_start = ?4 THEN usage_prev_window_requests
+                WHEN usage_window_start = ?4 - ?6 THEN usage_window_requests
+                ELSE 0
+            END,
+            usage_window_requests = CASE WHEN usage_window_start = ?4 THEN usage_window_requests + ?10 ELSE ?10 END,
+            usage_window_start = ?4,
+            updated_at = ?3
+        WHERE id = ?1
+    "#;
+    db.prepare(sql)
+        .bind_refs(&[
+            worker::D1Type::Text(key_id),
+            delta
+                .latency_ms
+                .map_or(worker::D1Type::Null, d1_integer),
+            d1_integer(now),
+            d1_integer(window_start),
+            d1_integer(delta.successes as i64),
+            d1_integer(KEY_USAGE_WINDOW_SECONDS),
+            worker::D1Type::Real(kept),
+            worker::D1Type::Real(gained),
+            d1_integer(delta.trailing_failures as i64),
+            d1_integer(attempts as i64),
+            d1_integer(samples as i64),
+            d1_integer(mean_latency),
+            worker::D1Type::Real(0.8f64.powi(samples as i32)),
+            worker::D1Type::Real(0.99f64.powi(samples as i32)),
+            worker::D1Type::Text(&histogram),
+        ])?
+        .run()
+        .await?;
 
     Ok(())
 }
@@ -622,13 +1264,15 @@ async fn is_key_permanently_invalid(db: &D1Database, key: &DbKey) -> bool {
     }
 
     // Use the key_tester to send a real, lightweight request to the native provider endpoint.
-    match key_tester::send_native_chat_test_request(&key.provider, &key.key, "gemini-2.5-pro").await
+    match key_tester::send_native_chat_test_request(&key.provider, &key.key, "gemini-2.5-pro", None).await
     {
         Ok(mut resp) => {
             let status = resp.status_code();
             if status == 200 {
                 // The key works, so it's definitely not invalid.
-                if let Ok(body_text) = resp.text().await {
+                let content_encoding = resp.headers().get("Content-Encoding").ok().flatten();
+                if let Ok(body_bytes) = resp.bytes().await {
+                    let body_text = error_handling::decode_error_body(&body_bytes, content_encoding.as_deref());
                     info!(key_id = %key.id, body_preview = %body_text.chars().take(100).collect::<String>(), "Key validation test passed. Key is valid.");
                 } else {
                     info!(key_id = %key.id, "Key validation test passed. Key is valid. (Could not read response body for preview)");
@@ -667,14 +1311,11 @@ async fn is_key_permanently_invalid(db: &D1Database, key: &DbKey) -> bool {
 }
 
 pub async fn delete_permanently_failed_keys(
-    env: &Env,
+    settings: &Settings,
     db: &D1Database,
     provider: &str,
 ) -> StdResult<usize, StorageError> {
-    let recovery_threshold = env
-        .var("RECOVERY_THRESHOLD")
-        .map(|v| v.to_string().parse().unwrap_or(5))
-        .unwrap_or(5);
+    let recovery_threshold = settings.recovery_threshold;
 
     // A key is considered permanently failed if its failure count is a large multiple of the recovery threshold.
     let permanently_failed_threshold: i64 = recovery_threshold * 10;
@@ -692,7 +1333,8 @@ pub async fn delete_permanently_failed_keys(
             DbKey::FIELDS
                 .consecutive_failures
                 .gt(permanently_failed_threshold),
-        );
+        )
+        .filter(DbKey::FIELDS.deleted_at.eq(0));
 
     let candidate_keys = executor.exec_query(query).await?;
     let candidate_count = candidate_keys.len();
@@ -742,3 +1384,1813 @@ pub async fn delete_permanently_failed_keys(
 
     Ok(final_delete_count)
 }
+
+// region: --- Cleanup
+
+/// Failure counts of keys nobody tried for this long are reset, so a key that failed
+/// during an old outage isn't still ranked down, or sidelined, for it.
+const STALE_FAILURES_SECONDS: i64 = 24 * 60 * 60;
+/// Model cooldowns that ended longer ago than this are dropped from `model_coolings`, which
+/// otherwise grows with every model a key ever cooled down for.
+const COOLING_HISTORY_SECONDS: i64 = 7 * 24 * 60 * 60;
+
+/// What a cleanup run did for a provider.
+#[derive(serde::Serialize, Debug, Default)]
+pub struct CleanupSummary {
+    pub provider: String,
+    /// Active keys with a very high failure count that a live test confirmed invalid.
+    pub confirmed_invalid: usize,
+    /// Keys whose long-ended model cooldowns were dropped.
+    pub purged_coolings: usize,
+    /// Keys whose stale failure count was reset.
+    pub reset_failures: usize,
+    /// Keys blocked for longer than `BLOCKED_KEY_RETENTION_DAYS`, moved to the trash.
+    pub trashed_blocked: usize,
+}
+
+/// Runs the maintenance of a provider's keys, from the scheduled handler or the manual
+/// cleanup route.
+pub async fn run_cleanup(settings: &Settings, db: &D1Database, provider: &str) -> StdResult<CleanupSummary, StorageError> {
+    let now = (Date::now() / 1000.0) as i64;
+    let summary = CleanupSummary {
+        provider: provider.to_string(),
+        confirmed_invalid: delete_permanently_failed_keys(settings, db, provider).await?,
+        purged_coolings: purge_ended_coolings(db, provider, now).await?,
+        reset_failures: reset_stale_failures(db, provider, now).await?,
+        trashed_blocked: trash_long_blocked_keys(settings, db, provider, now).await?,
+    };
+    if summary.purged_coolings + summary.reset_failures + summary.trashed_blocked > 0 {
+        API_KEY_CACHE.invalidate(&provider.to_string());
+    }
+    Ok(summary)
+}
+
+async fn purge_ended_coolings(db: &D1Database, provider: &str, now: i64) -> StdResult<usize, StorageError> {
+    #[derive(serde::Deserialize)]
+    struct CoolingsRow {
+        id: String,
+        model_coolings: String,
+    }
+    let executor = get_executor(db);
+    let rows: Vec<CoolingsRow> = executor
+        .exec_raw(
+            "SELECT id, model_coolings FROM keys WHERE provider = ?1 AND deleted_at = 0 \
+             AND model_coolings IS NOT NULL AND model_coolings NOT IN ('', '{}', 'null')",
+            vec![worker::D1Type::Text(provider)],
+        )
+        .await?;
+
+    let cutoff = now - COOLING_HISTORY_SECONDS;
+    let mut updates = Vec::new();
+    for row in rows {
+        let mut coolings: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(&row.model_coolings).unwrap_or_default();
+        let before = coolings.len();
+        // Both stored forms, see `parse_model_coolings`; entries without an end are kept.
+        coolings.retain(|_, cooling| {
+            cooling
+                .as_i64()
+                .or_else(|| cooling.get("end_at").and_then(|end_at| end_at.as_i64()))
+                .is_none_or(|end_at| end_at > cutoff)
+        });
+        if coolings.len() < before {
+            let json = serde_json::Value::Object(coolings).to_string();
+            updates.push((row.id, json, row.model_coolings));
+        }
+    }
+    if updates.is_empty() {
+        return Ok(0);
+    }
+
+    // Only rewrite rows no request changed in the meantime.
+    let sql = "UPDATE keys SET model_coolings = ?2 WHERE id = ?1 AND model_coolings = ?3";
+    let mut statements = Vec::with_capacity(updates.len());
+    for (id, json, previous) in &updates {
+        let params = [
+            worker::D1Type::Text(id),
+            worker::D1Type::Text(json),
+            worker::D1Type::Text(previous),
+        ];
+        statements.push(db.prepare(sql).bind_refs(&params)?);
+    }
+    db.batch(statements).await?;
+    Ok(updates.len())
+}
+
+async fn reset_stale_failures(db: &D1Database, provider: &str, now: i64) -> StdResult<usize, StorageError> {
+    let executor = get_executor(db);
+    // Only the number of updated rows matters.
+    let rows: Vec<serde::de::IgnoredAny> = executor
+        .exec_raw(
+            "UPDATE keys SET consecutive_failures = 0 WHERE provider = ?1 AND status = 'active' \
+             AND deleted_at = 0 AND consecutive_failures > 0 AND last_checked_at < ?2 RETURNING id",
+            vec![
+                worker::D1Type::Text(provider),
+                d1_integer(now - STALE_FAILURES_SECONDS),
+            ],
+        )
+        .await?;
+    Ok(rows.len())
+}
+
+/// Moves keys blocked for longer than `BLOCKED_KEY_RETENTION_DAYS` to the trash.
+async fn trash_long_blocked_keys(
+    settings: &Settings,
+    db: &D1Database,
+    provider: &str,
+    now: i64,
+) -> StdResult<usize, StorageError> {
+    if settings.blocked_key_retention_days == 0 {
+        return Ok(0);
+    }
+    let cutoff = now - settings.blocked_key_retention_days as i64 * 24 * 60 * 60;
+    let executor = get_executor(db);
+    let rows: Vec<serde::de::IgnoredAny> = executor
+        .exec_raw(
+            "UPDATE keys SET deleted_at = ?3, updated_at = ?3 WHERE provider = ?1 AND status = 'blocked' \
+             AND deleted_at = 0 AND blocked_at < ?2 RETURNING id",
+            vec![
+                worker::D1Type::Text(provider),
+                d1_integer(cutoff),
+                d1_integer(now),
+            ],
+        )
+        .await?;
+    if !rows.is_empty() {
+        info!(provider, count = rows.len(), "Moved long-blocked keys to the trash.");
+    }
+    Ok(rows.len())
+}
+
+// endregion: --- Cleanup
+
+// region: --- Probation
+
+/// A blocked key due for a probation test.
+#[derive(serde::Deserialize, Debug)]
+pub struct ProbationCandidate {
+    pub id: String,
+    pub key: String,
+    pub provider: String,
+    pub block_reason: String,
+    pub reactivations: i64,
+}
+
+/// A random sample of blocked keys probation may re-test: blocked by a provider (not by an
+/// operator or before block reasons were recorded) at least `min_blocked_seconds` ago, and
+/// reactivated fewer than `max_reactivations` times.
+pub async fn list_probation_candidates(
+    db: &D1Database,
+    min_blocked_seconds: i64,
+    max_reactivations: u64,
+    limit: u64,
+) -> StdResult<Vec<ProbationCandidate>, StorageError> {
+    let cutoff = (Date::now() / 1000.0) as i64 - min_blocked_seconds;
+    let executor = get_executor(db);
+    Ok(executor
+        .exec_raw(
+            "SELECT id, key, provider, block_reason, reactivations FROM keys \
+             WHERE status = 'blocked' AND deleted_at = 0 AND block_reason NOT IN ('', ?1) \
+             AND blocked_at < ?2 AND reactivations < ?3 ORDER BY RANDOM() LIMIT ?4",
+            vec![
+                worker::D1Type::Text(MANUAL_BLOCK_REASON),
+                d1_integer(cutoff),
+                d1_integer(max_reactivations as i64),
+                d1_integer(limit as i64),
+            ],
+        )
+        .await?)
+}
+
+/// Reactivates a blocked key that passed probation with a clean failure count.
+pub async fn reactivate_key(db: &D1Database, id: &str, provider: &str) -> StdResult<(), StorageError> {
+    let now = (Date::now() / 1000.0) as i64;
+    db.prepare(
+        "UPDATE keys SET status = 'active', block_reason = '', blocked_at = 0, \
+         reactivations = reactivations + 1, consecutive_failures = 0, updated_at = ?2 \
+         WHERE id = ?1 AND status = 'blocked'",
+    )
+    .bind_refs(&[worker::D1Type::Text(id), d1_integer(now)])?
+    .run()
+    .await?;
+    API_KEY_CACHE.invalidate(&provider.to_string());
+    Ok(())
+}
+
+// endregion: --- Probation
+
+// region: --- Key Events
+
+/// Key history older than this is pruned by the scheduled job.
+const KEY_EVENT_RETENTION_SECONDS: i32 = 30 * 24 * 60 * 60;
+/// Longest provider message kept per event, in characters.
+const KEY_EVENT_MESSAGE_CHARS: usize = 500;
+
+/// A block, cooldown or reactivation to record in a key's history.
+#[derive(Debug, Clone, Default)]
+pub struct KeyEventRecord {
+    pub key_id: String,
+    pub provider: String,
+    pub model: String,
+    /// `blocked`, `cooldown` or `reactivated`.
+    pub event: &'static str,
+    pub status: u16,
+    pub error_class: String,
+    pub message: String,
+}
+
+pub async fn record_key_event(db: &D1Database, record: &KeyEventRecord) -> StdResult<(), StorageError> {
+    let executor = get_executor(db);
+    let id_str = Uuid::new_v4().to_string();
+    let untyped_id = toasty_core::stmt::Id::from_string(KeyEvent::ID, id_str);
+    let typed_id = toasty::stmt::Id::from_untyped(untyped_id);
+    let message: String = sampling::redact(&record.message).chars().take(KEY_EVENT_MESSAGE_CHARS).collect();
+
+    let insert = KeyEvent::create()
+        .id(typed_id)
+        .key_id(record.key_id.clone())
+        .provider(record.provider.clone())
+        .model(record.model.clone())
+        .event(record.event.to_string())
+        .status(record.status as i64)
+        .error_class(record.error_class.clone())
+        .message(message)
+        .created_at((Date::now() / 1000.0) as i64);
+
+    executor.exec_insert(insert.into_insert()).await?;
+    Ok(())
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct KeyEventRow {
+    pub model: String,
+    pub event: String,
+    pub status: i64,
+    pub error_class: String,
+    pub message: String,
+    pub created_at: i64,
+}
+
+/// The latest `limit` events of a key, newest first.
+pub async fn list_key_events(db: &D1Database, key_id: &str, limit: u32) -> StdResult<Vec<KeyEventRow>, StorageError> {
+    let executor = get_executor(db);
+    Ok(executor
+        .exec_raw(
+            "SELECT model, event, status, error_class, message, created_at FROM key_events \
+             WHERE key_id = ?1 ORDER BY created_at DESC LIMIT ?2",
+            vec![worker::D1Type::Text(key_id), d1_integer(limit as i64)],
+        )
+        .await?)
+}
+
+pub async fn prune_key_events(db: &D1Database) -> StdResult<(), StorageError> {
+    let cutoff = (Date::now() / 1000.0) as i64 - i64::from(KEY_EVENT_RETENTION_SECONDS);
+    db.prepare("DELETE FROM key_events WHERE created_at < ?1")
+        .bind_refs(&[d1_integer(cutoff)])?
+        .run()
+        .await?;
+    Ok(())
+}
+
+// endregion: --- Key Events
+
+// region: --- Key Tags
+
+/// Adds the `key_tags` rows missing for the tags in the `keys.tags` column, e.g. of imported
+/// keys. Tags are stored there as `,a,b,`, which the recursive query splits.
+pub const SYNC_KEY_TAGS_SQL: &str = "WITH RECURSIVE split(key_id, provider, tag, rest) AS (
+        SELECT id, provider, '', substr(tags, 2) FROM keys WHERE tags != ''
+        UNION ALL
+        SELECT key_id, provider, substr(rest, 1, instr(rest, ',') - 1), substr(rest, instr(rest, ',') + 1)
+        FROM split WHERE rest != ''
+    )
+    INSERT OR IGNORE INTO key_tags (id, key_id, provider, tag)
+    SELECT lower(hex(randomblob(16))), key_id, provider, tag FROM split WHERE tag != ''";
+
+/// Replaces the `key_tags` rows of a key in one batch.
+async fn replace_key_tags(db: &D1Database, key_id: &str, provider: &str, tags: &[String]) -> StdResult<(), StorageError> {
+    let mut statements = Vec::with_capacity(tags.len() + 1);
+    statements.push(
+        db.prepare("DELETE FROM key_tags WHERE key_id = ?1")
+            .bind_refs(&[worker::D1Type::Text(key_id)])?,
+    );
+    for tag in tags {
+        let id = Uuid::new_v4().to_string();
+        statements.push(
+            db.prepare("INSERT OR IGNORE INTO key_tags (id, key_id, provider, tag) VALUES (?1, ?2, ?3, ?4)")
+                .bind_refs(&[
+                    worker::D1Type::Text(&id),
+                    worker::D1Type::Text(key_id),
+                    worker::D1Type::Text(provider),
+                    worker::D1Type::Text(tag),
+                ])?,
+        );
+    }
+    db.batch(statements).await?;
+    Ok(())
+}
+
+/// How many live keys of a provider carry a tag.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct TagCount {
+    pub provider: String,
+    pub tag: String,
+    pub keys: i64,
+}
+
+/// Counts the live keys per tag, of one provider or of all, by provider and tag. Trashed
+/// keys are left out.
+pub async fn list_tag_counts(db: &D1Database, provider: Option<&str>) -> StdResult<Vec<TagCount>, StorageError> {
+    let executor = get_executor(db);
+    let (filter, params) = match provider {
+        Some(provider) => ("AND t.provider = ?1", vec![worker::D1Type::Text(provider)]),
+        None => ("", Vec::new()),
+    };
+    let sql = format!(
+        "SELECT t.provider, t.tag, COUNT(*) AS keys FROM key_tags t JOIN keys k ON k.id = t.key_id \
+         WHERE k.deleted_at = 0 {} GROUP BY t.provider, t.tag ORDER BY t.tag, t.provider",
+        filter
+    );
+    Ok(executor.exec_raw(&sql, params).await?)
+}
+
+/// Drops the tags of purged keys.
+pub async fn prune_key_tags(db: &D1Database) -> StdResult<(), StorageError> {
+    db.prepare("DELETE FROM key_tags WHERE key_id NOT IN (SELECT id FROM keys)")
+        .run()
+        .await?;
+    Ok(())
+}
+
+// endregion: --- Key Tags
+
+// region: --- Client Keys
+
+pub async fn list_client_keys(db: &D1Database) -> StdResult<Vec<ClientKey>, StorageError> {
+    let executor = get_executor(db);
+    let query = DbClientKey::all().order_by(DbClientKey::FIELDS.created_at.desc());
+    let db_keys = executor.exec_query(query).await?;
+    Ok(db_keys.into_iter().map(db_client_key_to_client_key).collect())
+}
+
+/// How much of a client key is kept for display: `sk-theone-` and four more characters.
+const CLIENT_KEY_PREFIX_LEN: usize = 14;
+
+/// What client keys are stored and looked up by: the SHA-256 of the secret, hex, so a D1
+/// dump or export doesn't leak them.
+pub async fn client_key_hash(key: &str) -> StdResult<String, StorageError> {
+    let digest = crate::auth::sha256(key.as_bytes()).await?;
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn client_key_prefix(key: &str) -> String {
+    key.chars().take(CLIENT_KEY_PREFIX_LEN).collect()
+}
+
+/// Replaces the secret of a client key issued before hashing with its hash.
+async fn hash_client_key(db: &D1Database, id: &str, key: &str) -> StdResult<(), StorageError> {
+    let hash = client_key_hash(key).await?;
+    let prefix = client_key_prefix(key);
+    db.prepare("UPDATE client_keys SET key = ?1, key_prefix = ?2 WHERE id = ?3")
+        .bind_refs(&[
+            worker::D1Type::Text(&hash),
+            worker::D1Type::Text(&prefix),
+            worker::D1Type::Text(id),
+        ])?
+        .run()
+        .await?;
+    Ok(())
+}
+
+/// Hashes the client keys still stored as they were issued, e.g. unused since hashing was
+/// introduced. Returns how many were hashed.
+pub async fn hash_client_keys(db: &D1Database) -> StdResult<usize, StorageError> {
+    let executor = get_executor(db);
+    let legacy = executor
+        .exec_query(DbClientKey::filter(DbClientKey::FIELDS.key_prefix.eq(String::new())))
+        .await?;
+    for client in &legacy {
+        hash_client_key(db, &client.id.to_string(), &client.key).await?;
+        CLIENT_KEY_CACHE.invalidate(&client.key);
+    }
+    Ok(legacy.len())
+}
+
+/// Issues a new client key with the given scopes and role and returns it, including the secret.
+/// Only its hash and prefix are stored, so this is the only time the secret is known.
+pub async fn create_client_key(
+    db: &D1Database,
+    name: &str,
+    allowed_providers: &[String],
+    allowed_models: &[String],
+    role: Option<Role>,
+) -> StdResult<ClientKey, StorageError> {
+    let executor = get_executor(db);
+    let now = (Date::now() / 1000.0) as i64;
+
+    let id_str = Uuid::new_v4().to_string();
+    let key = format!("sk-theone-{}", Uuid::new_v4().simple());
+    let hash = client_key_hash(&key).await?;
+    let key_prefix = client_key_prefix(&key);
+    let untyped_id = toasty_core::stmt::Id::from_string(DbClientKey::ID, id_str.clone());
+    let typed_id = toasty::stmt::Id::from_untyped(untyped_id);
+
+    let insert = DbClientKey::create()
+        .id(typed_id)
+        .name(name.to_string())
+        .key(hash.clone())
+        .key_prefix(key_prefix.clone())
+        .allowed_providers(allowed_providers.join(","))
+        .allowed_models(allowed_models.join(","))
+        .status("active".to_string())
+        .max_requests_per_day(0)
+        .max_tokens_per_month(0)
+        .max_budget_micros(0)
+        .role(role.map(|r| r.as_str()).unwrap_or_default().to_string())
+        .created_at(now)
+        .updated_at(now)
+        .last_used_at(0);
+
+    executor.exec_insert(insert.into_insert()).await?;
+    CLIENT_KEY_CACHE.invalidate(&hash);
+
+    Ok(ClientKey {
+        id: id_str,
+        name: name.to_string(),
+        key,
+        key_prefix,
+        allowed_providers: allowed_providers.to_vec(),
+        allowed_models: allowed_models.to_vec(),
+        revoked: false,
+        quota: ClientQuota::default(),
+        role,
+        created_at: now as u64,
+        last_used_at: 0,
+    })
+}
+
+/// Marks a client key as revoked. Revoked keys are kept for auditing but can no longer authenticate.
+pub async fn revoke_client_key(db: &D1Database, id: &str) -> StdResult<(), StorageError> {
+    let executor = get_executor(db);
+
+    if let Some(existing) = executor
+        .exec_first(DbClientKey::filter_by_id(id.to_string()))
+        .await?
+    {
+        CLIENT_KEY_CACHE.invalidate(&existing.key);
+    }
+
+    let update_query = DbClientKey::filter_by_id(id.to_string())
+        .update()
+        .status("revoked".to_string())
+        .updated_at((Date::now() / 1000.0) as i64);
+    executor.exec_update(update_query.stmt).await?;
+    revoke_client_sessions(db, id).await
+}
+
+pub async fn delete_client_key(db: &D1Database, id: &str) -> StdResult<(), StorageError> {
+    let executor = get_executor(db);
+
+    if let Some(existing) = executor
+        .exec_first(DbClientKey::filter_by_id(id.to_string()))
+        .await?
+    {
+        CLIENT_KEY_CACHE.invalidate(&existing.key);
+    }
+
+    executor
+        .exec_delete(DbClientKey::filter_by_id(id.to_string()).into_select().delete())
+        .await?;
+    delete_totp(db, id).await?;
+    revoke_client_sessions(db, id).await
+}
+
+/// Looks up a client key by its secret value, consulting the local cache first. The cache
+/// is keyed by the hash, like the table.
+pub async fn find_client_key_via_cache(
+    db: &D1Database,
+    key: &str,
+) -> StdResult<Option<ClientKey>, StorageError> {
+    let hash = client_key_hash(key).await?;
+    if let Some(cached) = CLIENT_KEY_CACHE.get(&hash) {
+        return Ok(cached);
+    }
+
+    let executor = get_executor(db);
+    let mut found = executor
+        .exec_first(DbClientKey::filter_by_key(hash.clone()))
+        .await?;
+    if found.is_none() {
+        // Keys issued before hashing are still stored as they are; hash them on first use.
+        found = executor
+            .exec_first(DbClientKey::filter_by_key(key.to_string()))
+            .await?;
+        if let Some(legacy) = &mut found {
+            hash_client_key(db, &legacy.id.to_string(), key).await?;
+            legacy.key_prefix = client_key_prefix(key);
+        }
+    }
+    let found = found.map(db_client_key_to_client_key);
+
+    CLIENT_KEY_CACHE.insert(hash, found.clone());
+    Ok(found)
+}
+
+/// How stale a client key's `last_used_at` may get before it is written again.
+const CLIENT_TOUCH_INTERVAL_SECONDS: i64 = 5 * 60;
+
+/// Records that a client key was used. The timestamp is only written once per
+/// `CLIENT_TOUCH_INTERVAL_SECONDS`, both per isolate and across them, so proxied requests
+/// don't each cost a D1 write.
+pub async fn touch_client_key(db: &D1Database, id: &str) -> StdResult<(), StorageError> {
+    if CLIENT_TOUCH_CACHE.contains_key(&id.to_string()) {
+        return Ok(());
+    }
+    let now = (Date::now() / 1000.0) as i64;
+    db.prepare("UPDATE client_keys SET last_used_at = ?1 WHERE id = ?2 AND last_used_at < ?3")
+        .bind_refs(&[
+            d1_integer(now),
+            worker::D1Type::Text(id),
+            d1_integer(now - CLIENT_TOUCH_INTERVAL_SECONDS),
+        ])?
+        .run()
+        .await?;
+    CLIENT_TOUCH_CACHE.insert(id.to_string(), ());
+    Ok(())
+}
+
+/// Replaces the quota limits of a client key. Returns whether the key exists.
+pub async fn set_client_quota(
+    db: &D1Database,
+    id: &str,
+    quota: &ClientQuota,
+) -> StdResult<bool, StorageError> {
+    let executor = get_executor(db);
+
+    let Some(existing) = executor
+        .exec_first(DbClientKey::filter_by_id(id.to_string()))
+        .await?
+    else {
+        return Ok(false);
+    };
+    CLIENT_KEY_CACHE.invalidate(&existing.key);
+
+    let update_query = DbClientKey::filter_by_id(id.to_string())
+        .update()
+        .max_requests_per_day(quota.max_requests_per_day as i64)
+        .max_tokens_per_month(quota.max_tokens_per_month as i64)
+        .max_budget_micros(quota.max_budget_micros as i64)
+        .updated_at((Date::now() / 1000.0) as i64);
+    executor.exec_update(update_query.stmt).await?;
+    Ok(true)
+}
+
+/// Gives a client key a role, or takes UI and admin API access away with `None`. Returns
+/// whether the key exists.
+pub async fn set_client_role(db: &D1Database, id: &str, role: Option<Role>) -> StdResult<bool, StorageError> {
+    let executor = get_executor(db);
+
+    let Some(existing) = executor
+        .exec_first(DbClientKey::filter_by_id(id.to_string()))
+        .await?
+    else {
+        return Ok(false);
+    };
+    CLIENT_KEY_CACHE.invalidate(&existing.key);
+
+    let update_query = DbClientKey::filter_by_id(id.to_string())
+        .update()
+        .role(role.map(|r| r.as_str()).unwrap_or_default().to_string())
+        .updated_at((Date::now() / 1000.0) as i64);
+    executor.exec_update(update_query.stmt).await?;
+    // Sessions carry the role they were started with.
+    revoke_client_sessions(db, id).await?;
+    Ok(true)
+}
+
+// endregion: --- Client Keys
+
+// region: --- Sessions
+
+const SESSION_COLUMNS: &str = "id, client_id, role, created_at, expires_at";
+
+/// Starts a session of `client_id` (empty for the master key) that expires after `ttl_seconds`.
+pub async fn create_session(
+    db: &D1Database,
+    client_id: &str,
+    role: Role,
+    ttl_seconds: u64,
+) -> StdResult<Session, StorageError> {
+    let executor = get_executor(db);
+    let now = (Date::now() / 1000.0) as i64;
+
+    let id_str = Uuid::new_v4().to_string();
+    let untyped_id = toasty_core::stmt::Id::from_string(DbSession::ID, id_str.clone());
+    let typed_id = toasty::stmt::Id::from_untyped(untyped_id);
+
+    let session = Session {
+        id: id_str,
+        client_id: client_id.to_string(),
+        role,
+        created_at: now,
+        expires_at: now + ttl_seconds as i64,
+    };
+    let insert = DbSession::create()
+        .id(typed_id)
+        .client_id(session.client_id.clone())
+        .role(role.as_str().to_string())
+        .created_at(session.created_at)
+        .expires_at(session.expires_at)
+        .revoked_at(0);
+
+    executor.exec_insert(insert.into_insert()).await?;
+    Ok(session)
+}
+
+/// Looks up a session that hasn't been revoked, consulting the local cache first. Expiry is
+/// checked by the caller against the signed token.
+pub async fn find_session_via_cache(db: &D1Database, id: &str) -> StdResult<Option<Session>, StorageError> {
+    if let Some(cached) = SESSION_CACHE.get(&id.to_string()) {
+        return Ok(cached);
+    }
+
+    let executor = get_executor(db);
+    let found = executor
+        .exec_raw::<Session>(
+            &format!("SELECT {} FROM sessions WHERE id = ?1 AND revoked_at = 0", SESSION_COLUMNS),
+            vec![worker::D1Type::Text(id)],
+        )
+        .await?
+        .into_iter()
+        .next();
+
+    SESSION_CACHE.insert(id.to_string(), found.clone());
+    Ok(found)
+}
+
+/// The sessions that are neither expired nor revoked, newest first.
+pub async fn list_sessions(db: &D1Database) -> StdResult<Vec<Session>, StorageError> {
+    let executor = get_executor(db);
+    let now = (Date::now() / 1000.0) as i64;
+    Ok(executor
+        .exec_raw(
+            &format!(
+                "SELECT {} FROM sessions WHERE revoked_at = 0 AND expires_at > ?1 ORDER BY created_at DESC",
+                SESSION_COLUMNS
+            ),
+            vec![d1_integer(now)],
+        )
+        .await?)
+}
+
+/// Revokes a session, e.g. on logout. Returns whether it was still valid.
+pub async fn revoke_session(db: &D1Database, id: &str) -> StdResult<bool, StorageError> {
+    let now = (Date::now() / 1000.0) as i64;
+    let result = db
+        .prepare("UPDATE sessions SET revoked_at = ?1 WHERE id = ?2 AND revoked_at = 0")
+        .bind_refs(&[d1_integer(now), worker::D1Type::Text(id)])?
+        .run()
+        .await?;
+    SESSION_CACHE.invalidate(&id.to_string());
+    Ok(result.meta()?.and_then(|meta| meta.changes).unwrap_or(0) > 0)
+}
+
+/// Revokes every session of a client key, after the key was revoked, deleted or its role changed.
+pub async fn revoke_client_sessions(db: &D1Database, client_id: &str) -> StdResult<(), StorageError> {
+    let now = (Date::now() / 1000.0) as i64;
+    db.prepare("UPDATE sessions SET revoked_at = ?1 WHERE client_id = ?2 AND revoked_at = 0")
+        .bind_refs(&[d1_integer(now), worker::D1Type::Text(client_id)])?
+        .run()
+        .await?;
+    // The cache is keyed by session, so drop it all rather than look the sessions up.
+    SESSION_CACHE.invalidate_all();
+    Ok(())
+}
+
+/// Deletes expired sessions; revoked ones are kept until they expire for the record.
+pub async fn prune_sessions(db: &D1Database) -> StdResult<(), StorageError> {
+    let now = (Date::now() / 1000.0) as i64;
+    db.prepare("DELETE FROM sessions WHERE expires_at < ?1")
+        .bind_refs(&[d1_integer(now)])?
+        .run()
+        .await?;
+    Ok(())
+}
+
+// endregion: --- Sessions
+
+// region: --- TOTP
+
+/// The TOTP secret of `subject` (`master` or a client key id), enabled or being provisioned.
+pub async fn get_totp(db: &D1Database, subject: &str) -> StdResult<Option<Enrollment>, StorageError> {
+    let executor = get_executor(db);
+    Ok(executor
+        .exec_raw::<Enrollment>(
+            "SELECT subject, secret, enabled_at, last_step FROM totp_secrets WHERE subject = ?1",
+            vec![worker::D1Type::Text(subject)],
+        )
+        .await?
+        .into_iter()
+        .next())
+}
+
+/// Stores a new secret for `subject` to be confirmed with `enable_totp`, replacing one that
+/// was being provisioned. An enabled secret is kept.
+pub async fn provision_totp(db: &D1Database, subject: &str, secret: &str) -> StdResult<(), StorageError> {
+    let id = Uuid::new_v4().to_string();
+    let now = (Date::now() / 1000.0) as i64;
+    db.prepare(
+        "INSERT INTO totp_secrets (id, subject, secret, enabled_at, last_step, created_at) VALUES (?1, ?2, ?3, 0, 0, ?4) \
+         ON CONFLICT (subject) DO UPDATE SET secret = excluded.secret, last_step = 0, created_at = excluded.created_at \
+         WHERE totp_secrets.enabled_at = 0",
+    )
+    .bind_refs(&[
+        worker::D1Type::Text(&id),
+        worker::D1Type::Text(subject),
+        worker::D1Type::Text(secret),
+        d1_integer(now),
+    ])?
+    .run()
+    .await?;
+    Ok(())
+}
+
+pub async fn enable_totp(db: &D1Database, subject: &str) -> StdResult<(), StorageError> {
+    let now = (Date::now() / 1000.0) as i64;
+    db.prepare("UPDATE totp_secrets SET enabled_at = ?1 WHERE subject = ?2 AND enabled_at = 0")
+        .bind_refs(&[d1_integer(now), worker::D1Type::Text(subject)])?
+        .run()
+        .await?;
+    Ok(())
+}
+
+/// Records that a code of time step `step` was accepted. Returns false when a code of this
+/// or a later step was accepted before, i.e. the code is being replayed.
+pub async fn use_totp_step(db: &D1Database, subject: &str, step: i64) -> StdResult<bool, StorageError> {
+    let result = db
+        .prepare("UPDATE totp_secrets SET last_step = ?1 WHERE subject = ?2 AND last_step < ?1")
+        .bind_refs(&[d1_integer(step), worker::D1Type::Text(subject)])?
+        .run()
+        .await?;
+    Ok(result.meta()?.and_then(|meta| meta.changes).unwrap_or(0) > 0)
+}
+
+/// Removes the second factor of `subject`, e.g. when its device was lost.
+pub async fn delete_totp(db: &D1Database, subject: &str) -> StdResult<(), StorageError> {
+    db.prepare("DELETE FROM totp_secrets WHERE subject = ?1")
+        .bind_refs(&[worker::D1Type::Text(subject)])?
+        .run()
+        .await?;
+    Ok(())
+}
+
+// endregion: --- TOTP
+
+// region: --- Usage
+
+pub async fn record_usage(db: &D1Database, record: &UsageRecord) -> StdResult<(), StorageError> {
+    let executor = get_executor(db);
+    let id_str = Uuid::new_v4().to_string();
+    let untyped_id = toasty_core::stmt::Id::from_string(UsageEvent::ID, id_str);
+    let typed_id = toasty::stmt::Id::from_untyped(untyped_id);
+
+    let insert = UsageEvent::create()
+        .id(typed_id)
+        .client_id(record.client_id.clone().unwrap_or_default())
+        .provider(record.provider.clone())
+        .model(record.model.clone())
+        .key_id(record.key_id.clone())
+        .endpoint(record.endpoint.clone())
+        .prompt_tokens(record.prompt_tokens as i64)
+        .completion_tokens(record.completion_tokens as i64)
+        .images(record.images as i64)
+        .cost_micros(record.cost_micros as i64)
+        .estimated(record.estimated as i64)
+        .created_at((Date::now() / 1000.0) as i64);
+
+    executor.exec_insert(insert.into_insert()).await?;
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+struct ClientUsageRow {
+    requests_today: Option<i64>,
+    tokens_this_month: Option<i64>,
+    cost_micros_this_month: Option<i64>,
+}
+
+/// Aggregates a client's usage over the quota windows: requests in the last 24 hours,
+/// tokens and spend in the last 30 days.
+pub async fn get_client_usage(db: &D1Database, client_id: &str) -> StdResult<ClientUsage, StorageError> {
+    let now = (Date::now() / 1000.0) as i64;
+    let day_start = now - 24 * 60 * 60;
+    let month_start = now - 30 * 24 * 60 * 60;
+
+    let sql = "SELECT \
+            SUM(CASE WHEN created_at >= ?2 THEN 1 ELSE 0 END) AS requests_today, \
+            SUM(prompt_tokens + completion_tokens) AS tokens_this_month, \
+            SUM(cost_micros) AS cost_micros_this_month \
+        FROM usage_events WHERE client_id = ?1 AND created_at >= ?3";
+    let executor = get_executor(db);
+    let rows: Vec<ClientUsageRow> = executor
+        .exec_raw(
+            sql,
+            vec![
+                worker::D1Type::Text(client_id),
+                d1_integer(day_start),
+                d1_integer(month_start),
+            ],
+        )
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .next()
+        .map(|row| ClientUsage {
+            requests_today: row.requests_today.unwrap_or(0) as u64,
+            tokens_this_month: row.tokens_this_month.unwrap_or(0) as u64,
+            cost_micros_this_month: row.cost_micros_this_month.unwrap_or(0) as u64,
+        })
+        .unwrap_or_default())
+}
+
+/// Like `get_client_usage`, but served from a short-lived cache so quota checks
+/// don't run an aggregate query on every request.
+pub async fn get_client_usage_via_cache(
+    db: &D1Database,
+    client_id: &str,
+) -> StdResult<ClientUsage, StorageError> {
+    if let Some(cached) = CLIENT_USAGE_CACHE.get(&client_id.to_string()) {
+        return Ok(cached);
+    }
+    let usage = get_client_usage(db, client_id).await?;
+    CLIENT_USAGE_CACHE.insert(client_id.to_string(), usage);
+    Ok(usage)
+}
+
+// endregion: --- Usage
+
+// region: --- Key Changes
+
+/// A key as mirrored by external pollers. Keys in the trash are included, with
+/// `deleted_at` set, so a mirror can drop them; purged keys simply stop appearing.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct KeyChange {
+    pub id: String,
+    pub provider: String,
+    pub key: String,
+    pub status: String,
+    pub latency_ms: i64,
+    pub success_rate: f64,
+    pub consecutive_failures: i64,
+    pub total_cooling_seconds: i64,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub deleted_at: i64,
+    pub tier: String,
+}
+
+/// Keys changed after the position `(updated_at, id)`, in `(updated_at, id)` order. Keys
+/// changed in the current second are held back until it is over, so a key updated again
+/// later always sorts after any position already handed out.
+pub async fn list_key_changes(
+    db: &D1Database,
+    after_updated_at: i64,
+    after_id: &str,
+    provider: Option<&str>,
+    limit: u32,
+) -> StdResult<Vec<KeyChange>, StorageError> {
+    let executor = get_executor(db);
+    let now = (Date::now() / 1000.0) as i64;
+
+    let mut sql = String::from(
+        "SELECT id, provider, key, status, latency_ms, success_rate / 1000.0 AS success_rate, \
+         consecutive_failures, total_cooling_seconds, created_at, updated_at, deleted_at, tier FROM keys \
+         WHERE (updated_at > ?1 OR (updated_at = ?1 AND id > ?2)) AND updated_at < ?3",
+    );
+    let mut params = vec![
+        d1_integer(after_updated_at.max(0)),
+        worker::D1Type::Text(after_id),
+        d1_integer(now),
+    ];
+    if let Some(provider) = provider {
+        params.push(worker::D1Type::Text(provider));
+        sql.push_str(&format!(" AND provider = ?{}", params.len()));
+    }
+    sql.push_str(&format!(" ORDER BY updated_at, id LIMIT {}", limit));
+
+    Ok(executor.exec_raw(&sql, params).await?)
+}
+
+// endregion: --- Key Changes
+
+// region: --- Samples
+
+pub async fn record_sample(db: &D1Database, record: &SampleRecord) -> StdResult<(), StorageError> {
+    let executor = get_executor(db);
+    let id_str = Uuid::new_v4().to_string();
+    let untyped_id = toasty_core::stmt::Id::from_string(Sample::ID, id_str);
+    let typed_id = toasty::stmt::Id::from_untyped(untyped_id);
+
+    let insert = Sample::create()
+        .id(typed_id)
+        .provider(record.provider.clone())
+        .model(record.model.clone())
+        .key_id(record.key_id.clone())
+        .client_id(record.client_id.clone().unwrap_or_default())
+        .endpoint(record.endpoint.clone())
+        .request_body(record.request_body.clone())
+        .response_body(record.response_body.clone())
+        .latency_ms(record.latency_ms)
+        .created_at((Date::now() / 1000.0) as i64);
+
+    executor.exec_insert(insert.into_insert()).await?;
+    Ok(())
+}
+
+/// A stored sample, as returned by the export endpoint.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct SampleRow {
+    pub id: String,
+    pub provider: String,
+    pub model: String,
+    pub key_id: String,
+    pub client_id: String,
+    pub endpoint: String,
+    pub request_body: String,
+    pub response_body: String,
+    pub latency_ms: i64,
+    pub created_at: i64,
+}
+
+/// Lists samples newest first, optionally filtered by provider, model and creation time.
+pub async fn list_samples(
+    db: &D1Database,
+    provider: Option<&str>,
+    model: Option<&str>,
+    since: i64,
+    limit: u32,
+) -> StdResult<Vec<SampleRow>, StorageError> {
+    let executor = get_executor(db);
+    let since = since.max(0);
+    let mut sql = String::from(
+        "SELECT id, provider, model, key_id, client_id, endpoint, request_body, response_body, \
+         latency_ms, created_at FROM samples WHERE created_at >= ?1",
+    );
+    let mut params = vec![d1_integer(since)];
+    if let Some(provider) = provider {
+        params.push(worker::D1Type::Text(provider));
+        sql.push_str(&format!(" AND provider = ?{}", params.len()));
+    }
+    if let Some(model) = model {
+        params.push(worker::D1Type::Text(model));
+        sql.push_str(&format!(" AND model = ?{}", params.len()));
+    }
+    sql.push_str(&format!(" ORDER BY created_at DESC LIMIT {}", limit));
+
+    Ok(executor.exec_raw(&sql, params).await?)
+}
+
+// endregion: --- Samples
+
+// region: --- Model Catalog
+
+#[derive(serde::Deserialize)]
+struct ProviderRow {
+    provider: String,
+}
+
+/// Returns every provider that has at least one key outside the trash.
+pub async fn list_key_providers(db: &D1Database) -> StdResult<Vec<String>, StorageError> {
+    let executor = get_executor(db);
+    let rows: Vec<ProviderRow> = executor
+        .exec_raw("SELECT DISTINCT provider FROM keys WHERE deleted_at = 0 ORDER BY provider", vec![])
+        .await?;
+    Ok(rows.into_iter().map(|row| row.provider).collect())
+}
+
+/// Returns every provider that has at least one active key.
+pub async fn list_active_providers(db: &D1Database) -> StdResult<Vec<String>, StorageError> {
+    let executor = get_executor(db);
+    let rows: Vec<ProviderRow> = executor
+        .exec_raw(
+            "SELECT DISTINCT provider FROM keys WHERE status = 'active' AND deleted_at = 0 ORDER BY provider",
+            vec![],
+        )
+        .await?;
+    Ok(rows.into_iter().map(|row| row.provider).collect())
+}
+
+/// Returns a provider's cached model list and when it was fetched, if it has been cached.
+pub async fn get_model_catalog(
+    db: &D1Database,
+    provider: &str,
+) -> StdResult<Option<(Vec<String>, u64)>, StorageError> {
+    let executor = get_executor(db);
+    let found = executor
+        .exec_first(ModelCatalog::filter_by_provider(provider.to_string()))
+        .await?;
+    Ok(found.map(|catalog| {
+        (
+            serde_json::from_str(&catalog.models).unwrap_or_default(),
+            catalog.fetched_at as u64,
+        )
+    }))
+}
+
+/// Returns a provider's cached model list, empty if it hasn't been cached.
+pub async fn get_catalog_models_via_cache(db: &D1Database, provider: &str) -> StdResult<Vec<String>, StorageError> {
+    if let Some(cached) = MODEL_CATALOG_CACHE.get(&provider.to_string()) {
+        return Ok(cached);
+    }
+
+    let models = get_model_catalog(db, provider)
+        .await?
+        .map(|(models, _)| models)
+        .unwrap_or_default();
+    MODEL_CATALOG_CACHE.insert(provider.to_string(), models.clone());
+    Ok(models)
+}
+
+/// Stores a freshly fetched model list for a provider.
+pub async fn save_model_catalog(
+    db: &D1Database,
+    provider: &str,
+    models: &[String],
+) -> StdResult<(), StorageError> {
+    let executor = get_executor(db);
+    let now = (Date::now() / 1000.0) as i64;
+    let models_json = serde_json::to_string(models).unwrap_or_else(|_| "[]".to_string());
+
+    let existing = executor
+        .exec_first(ModelCatalog::filter_by_provider(provider.to_string()))
+        .await?;
+    if existing.is_some() {
+        let update_query = ModelCatalog::filter_by_provider(provider.to_string())
+            .update()
+            .models(models_json)
+            .fetched_at(now);
+        executor.exec_update(update_query.stmt).await?;
+    } else {
+        let id_str = Uuid::new_v4().to_string();
+        let untyped_id = toasty_core::stmt::Id::from_string(ModelCatalog::ID, id_str);
+        let typed_id = toasty::stmt::Id::from_untyped(untyped_id);
+        let insert = ModelCatalog::create()
+            .id(typed_id)
+            .provider(provider.to_string())
+            .models(models_json)
+            .fetched_at(now);
+        executor.exec_insert(insert.into_insert()).await?;
+    }
+    MODEL_CATALOG_CACHE.invalidate(&provider.to_string());
+    Ok(())
+}
+
+// endregion: --- Model Catalog
+
+// region: --- Provider Settings
+
+fn db_provider_setting_to_settings(setting: ProviderSetting) -> ProviderSettings {
+    ProviderSettings {
+        provider: setting.provider,
+        observe_only: setting.observe_only != 0,
+        rpm_limit: setting.rpm_limit.max(0) as u64,
+        tpm_limit: setting.tpm_limit.max(0) as u64,
+        updated_at: setting.updated_at as u64,
+    }
+}
+
+pub async fn list_provider_settings(db: &D1Database) -> StdResult<Vec<ProviderSettings>, StorageError> {
+    let executor = get_executor(db);
+    let settings = executor.exec_query(ProviderSetting::all()).await?;
+    Ok(settings.into_iter().map(db_provider_setting_to_settings).collect())
+}
+
+/// Returns a provider's settings, or the defaults if none are stored.
+pub async fn get_provider_settings_via_cache(
+    db: &D1Database,
+    provider: &str,
+) -> StdResult<ProviderSettings, StorageError> {
+    if let Some(cached) = PROVIDER_SETTINGS_CACHE.get(&provider.to_string()) {
+        return Ok(cached);
+    }
+
+    let settings = get_provider_settings(db, provider).await?;
+    PROVIDER_SETTINGS_CACHE.insert(provider.to_string(), settings.clone());
+    Ok(settings)
+}
+
+async fn get_provider_settings(db: &D1Database, provider: &str) -> StdResult<ProviderSettings, StorageError> {
+    let executor = get_executor(db);
+    Ok(executor
+        .exec_first(ProviderSetting::filter_by_provider(provider.to_string()))
+        .await?
+        .map(db_provider_setting_to_settings)
+        .unwrap_or_else(|| ProviderSettings {
+            provider: provider.to_string(),
+            ..Default::default()
+        }))
+}
+
+pub async fn set_provider_observe_only(
+    db: &D1Database,
+    provider: &str,
+    observe_only: bool,
+) -> StdResult<(), StorageError> {
+    let mut settings = get_provider_settings(db, provider).await?;
+    settings.observe_only = observe_only;
+    save_provider_settings(db, &settings).await
+}
+
+/// Sets the per-key rate budgets of a provider; 0 lifts a budget and `None` keeps it.
+pub async fn set_provider_rate_limits(
+    db: &D1Database,
+    provider: &str,
+    rpm_limit: Option<u64>,
+    tpm_limit: Option<u64>,
+) -> StdResult<(), StorageError> {
+    let mut settings = get_provider_settings(db, provider).await?;
+    settings.rpm_limit = rpm_limit.unwrap_or(settings.rpm_limit);
+    settings.tpm_limit = tpm_limit.unwrap_or(settings.tpm_limit);
+    save_provider_settings(db, &settings).await
+}
+
+async fn save_provider_settings(db: &D1Database, settings: &ProviderSettings) -> StdResult<(), StorageError> {
+    let executor = get_executor(db);
+    let now = (Date::now() / 1000.0) as i64;
+    let provider = settings.provider.clone();
+
+    let existing = executor
+        .exec_first(ProviderSetting::filter_by_provider(provider.clone()))
+        .await?;
+    if existing.is_some() {
+        let update_query = ProviderSetting::filter_by_provider(provider.clone())
+            .update()
+            .observe_only(settings.observe_only as i64)
+            .rpm_limit(settings.rpm_limit as i64)
+            .tpm_limit(settings.tpm_limit as i64)
+            .updated_at(now);
+        executor.exec_update(update_query.stmt).await?;
+    } else {
+        let id_str = Uuid::new_v4().to_string();
+        let untyped_id = toasty_core::stmt::Id::from_string(ProviderSetting::ID, id_str);
+        let typed_id = toasty::stmt::Id::from_untyped(untyped_id);
+        let insert = ProviderSetting::create()
+            .id(typed_id)
+            .provider(provider.clone())
+            .observe_only(settings.observe_only as i64)
+            .rpm_limit(settings.rpm_limit as i64)
+            .tpm_limit(settings.tpm_limit as i64)
+            .updated_at(now);
+        executor.exec_insert(insert.into_insert()).await?;
+    }
+
+    PROVIDER_SETTINGS_CACHE.invalidate(&provider);
+    Ok(())
+}
+
+// endregion: --- Provider Settings
+
+// region: --- Custom Providers
+
+fn db_custom_provider_to_provider(provider: DbCustomProvider) -> CustomProvider {
+    CustomProvider {
+        name: provider.name,
+        base_url: provider.base_url,
+        auth_header: provider.auth_header,
+        auth_scheme: provider.auth_scheme,
+        color: provider.color,
+        updated_at: provider.updated_at as u64,
+    }
+}
+
+pub async fn list_custom_providers(db: &D1Database) -> StdResult<Vec<CustomProvider>, StorageError> {
+    let executor = get_executor(db);
+    let providers = executor.exec_query(DbCustomProvider::all()).await?;
+    let mut providers: Vec<CustomProvider> = providers.into_iter().map(db_custom_provider_to_provider).collect();
+    providers.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(providers)
+}
+
+/// Returns the custom provider registered under `name`, if any.
+pub async fn get_custom_provider_via_cache(
+    db: &D1Database,
+    name: &str,
+) -> StdResult<Option<CustomProvider>, StorageError> {
+    if let Some(cached) = CUSTOM_PROVIDER_CACHE.get(&name.to_string()) {
+        return Ok(cached);
+    }
+
+    let executor = get_executor(db);
+    let provider = executor
+        .exec_first(DbCustomProvider::filter_by_name(name.to_string()))
+        .await?
+        .map(db_custom_provider_to_provider);
+    CUSTOM_PROVIDER_CACHE.insert(name.to_string(), provider.clone());
+    Ok(provider)
+}
+
+/// Registers a custom provider, replacing any previous one of the same name.
+pub async fn save_custom_provider(db: &D1Database, provider: &CustomProvider) -> StdResult<(), StorageError> {
+    let executor = get_executor(db);
+    let now = (Date::now() / 1000.0) as i64;
+
+    let existing = executor
+        .exec_first(DbCustomProvider::filter_by_name(provider.name.clone()))
+        .await?;
+    if existing.is_some() {
+        let update_query = DbCustomProvider::filter_by_name(provider.name.clone())
+            .update()
+            .base_url(provider.base_url.clone())
+            .auth_header(provider.auth_header.clone())
+            .auth_scheme(provider.auth_scheme.clone())
+            .color(provider.color.clone())
+            .updated_at(now);
+        executor.exec_update(update_query.stmt).await?;
+    } else {
+        let id_str = Uuid::new_v4().to_string();
+        let untyped_id = toasty_core::stmt::Id::from_string(DbCustomProvider::ID, id_str);
+        let typed_id = toasty::stmt::Id::from_untyped(untyped_id);
+        let insert = DbCustomProvider::create()
+            .id(typed_id)
+            .name(provider.name.clone())
+            .base_url(provider.base_url.clone())
+            .auth_header(provider.auth_header.clone())
+            .auth_scheme(provider.auth_scheme.clone())
+            .color(provider.color.clone())
+            .updated_at(now);
+        executor.exec_insert(insert.into_insert()).await?;
+    }
+
+    CUSTOM_PROVIDER_CACHE.invalidate(&provider.name);
+    Ok(())
+}
+
+/// Removes a custom provider. Its keys stay, but requests for it go to the AI Gateway again.
+pub async fn delete_custom_provider(db: &D1Database, name: &str) -> StdResult<(), StorageError> {
+    let executor = get_executor(db);
+    let delete_query = DbCustomProvider::filter_by_name(name.to_string());
+    executor
+        .exec_delete(delete_query.into_select().delete())
+        .await?;
+    CUSTOM_PROVIDER_CACHE.invalidate(&name.to_string());
+    Ok(())
+}
+
+// endregion: --- Custom Providers
+
+// region: --- Settings
+
+/// Returns the stored setting overrides as `(name, value)` pairs.
+pub async fn list_settings(db: &D1Database) -> StdResult<Vec<(String, String)>, StorageError> {
+    let executor = get_executor(db);
+    let settings = executor.exec_query(Setting::all()).await?;
+    Ok(settings.into_iter().map(|s| (s.name, s.value)).collect())
+}
+
+/// Stores an override for a setting, replacing any previous one. Settings weigh into how
+/// keys are sorted, so this isolate's key lists are dropped too.
+pub async fn set_setting(db: &D1Database, name: &str, value: &str) -> StdResult<(), StorageError> {
+    let now = (Date::now() / 1000.0) as i64;
+    let id = Uuid::new_v4().to_string();
+    db.prepare(
+        "INSERT INTO settings (id, name, value, updated_at) VALUES (?1, ?2, ?3, ?4) \
+         ON CONFLICT(name) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at",
+    )
+    .bind_refs(&[
+        worker::D1Type::Text(&id),
+        worker::D1Type::Text(name),
+        worker::D1Type::Text(value),
+        d1_integer(now),
+    ])?
+    .run()
+    .await?;
+    API_KEY_CACHE.invalidate_all();
+    Ok(())
+}
+
+/// Removes the override for a setting, so the worker var applies again.
+pub async fn delete_setting(db: &D1Database, name: &str) -> StdResult<(), StorageError> {
+    let executor = get_executor(db);
+    let delete_query = Setting::filter_by_name(name.to_string());
+    executor
+        .exec_delete(delete_query.into_select().delete())
+        .await?;
+    API_KEY_CACHE.invalidate_all();
+    Ok(())
+}
+
+// endregion: --- Settings
+
+// region: --- Metrics
+
+/// Adds the given increments to their series, creating series on first use.
+/// All increments are applied in a single D1 batch.
+pub async fn increment_metrics(db: &D1Database, deltas: &[MetricDelta]) -> StdResult<(), StorageError> {
+    if deltas.is_empty() {
+        return Ok(());
+    }
+    let now = (Date::now() / 1000.0) as i64;
+    let sql = "INSERT INTO metrics (id, name, labels, value, updated_at) VALUES (?1, ?2, ?3, ?4, ?5) \
+        ON CONFLICT(name, labels) DO UPDATE SET value = value + excluded.value, updated_at = excluded.updated_at";
+
+    let mut statements = Vec::with_capacity(deltas.len());
+    for delta in deltas {
+        let id = Uuid::new_v4().to_string();
+        let params = [
+            worker::D1Type::Text(&id),
+            worker::D1Type::Text(&delta.name),
+            worker::D1Type::Text(&delta.labels),
+            d1_integer(delta.value),
+            d1_integer(now),
+        ];
+        statements.push(db.prepare(sql).bind_refs(&params)?);
+    }
+    db.batch(statements).await?;
+    Ok(())
+}
+
+pub async fn load_metrics(db: &D1Database) -> StdResult<Vec<MetricRow>, StorageError> {
+    let executor = get_executor(db);
+    Ok(executor
+        .exec_raw("SELECT name, labels, value FROM metrics ORDER BY name, labels", vec![])
+        .await?)
+}
+
+pub async fn count_keys_by_status(db: &D1Database) -> StdResult<Vec<KeyCountRow>, StorageError> {
+    let executor = get_executor(db);
+    Ok(executor
+        .exec_raw(
+            "SELECT provider, status, COUNT(*) AS count FROM keys WHERE deleted_at = 0 GROUP BY provider, status ORDER BY provider, status",
+            vec![],
+        )
+        .await?)
+}
+
+// endregion: --- Metrics
+
+// region: --- Dashboard
+
+/// Request events older than this are pruned by the scheduled job.
+const REQUEST_EVENT_RETENTION_SECONDS: i32 = 2 * 24 * 60 * 60;
+
+/// Per-provider health figures for the dashboard and the alert rules.
+#[derive(serde::Deserialize, Debug)]
+pub struct ProviderDashboardStats {
+    pub provider: String,
+    pub active_keys: i64,
+    pub blocked_keys: i64,
+    /// Active keys with at least one model on cooldown.
+    pub cooling_keys: i64,
+    /// Average latency of the active keys, in milliseconds.
+    pub avg_latency_ms: Option<f64>,
+    /// Requests over the window the stats were taken for.
+    pub requests: i64,
+    pub successes: i64,
+}
+
+impl ProviderDashboardStats {
+    /// Share of successful requests over the window, if there were any.
+    pub fn success_rate(&self) -> Option<f64> {
+        (self.requests > 0).then(|| self.successes as f64 / self.requests as f64)
+    }
+}
+
+/// How often a failure class was seen for a provider over the last 24 hours.
+#[derive(serde::Deserialize, Debug)]
+pub struct ErrorClassCount {
+    pub provider: String,
+    pub error_class: String,
+    pub count: i64,
+    pub last_seen_at: i64,
+}
+
+pub async fn record_request_event(db: &D1Database, outcome: &RequestOutcome) -> StdResult<(), StorageError> {
+    let executor = get_executor(db);
+    let id_str = Uuid::new_v4().to_string();
+    let untyped_id = toasty_core::stmt::Id::from_string(RequestEvent::ID, id_str);
+    let typed_id = toasty::stmt::Id::from_untyped(untyped_id);
+
+    let insert = RequestEvent::create()
+        .id(typed_id)
+        .provider(outcome.provider.clone())
+        .model(outcome.model.clone())
+        .status(outcome.status as i64)
+        .error_class(outcome.error_class.clone())
+        .latency_ms(outcome.latency_ms as i64)
+        .request_body(outcome.payload.as_ref().map(|p| p.request_body.clone()).unwrap_or_default())
+        .response_body(outcome.payload.as_ref().map(|p| p.response_body.clone()).unwrap_or_default())
+        .created_at((Date::now() / 1000.0) as i64);
+
+    executor.exec_insert(insert.into_insert()).await?;
+    Ok(())
+}
+
+/// A request event with a logged payload (see `payload_log`).
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct LoggedPayloadRow {
+    pub provider: String,
+    pub model: String,
+    pub status: i64,
+    pub error_class: String,
+    pub latency_ms: i64,
+    pub request_body: String,
+    pub response_body: String,
+    pub created_at: i64,
+}
+
+/// Lists the latest request events with a logged payload, newest first.
+pub async fn list_logged_payloads(
+    db: &D1Database,
+    provider: Option<&str>,
+    limit: u32,
+) -> StdResult<Vec<LoggedPayloadRow>, StorageError> {
+    let executor = get_executor(db);
+    let sql = format!(
+        "SELECT provider, model, status, error_class, latency_ms, request_body, response_body, created_at \
+         FROM request_events WHERE request_body != '' AND (?1 = '' OR provider = ?1) \
+         ORDER BY created_at DESC LIMIT {}",
+        limit
+    );
+    Ok(executor
+        .exec_raw(&sql, vec![worker::D1Type::Text(provider.unwrap_or_default())])
+        .await?)
+}
+
+/// Keys hashed per batch by `backfill_key_hashes`, so one batch stays well within D1's
+/// limits however many keys predate the column.
+const KEY_HASH_BACKFILL_BATCH: u32 = 500;
+
+#[derive(serde::Deserialize)]
+struct UnhashedKey {
+    id: String,
+    key: String,
+}
+
+/// Fills in `key_hash` for keys created before the column existed, so the unique
+/// (provider, key_hash) index covers them too. Works through them in batches until none
+/// are left.
+pub async fn backfill_key_hashes(db: &D1Database) -> StdResult<usize, StorageError> {
+    let executor = get_executor(db);
+    let mut total = 0;
+    loop {
+        let keys: Vec<UnhashedKey> = executor
+            .exec_raw(
+                "SELECT id, key FROM keys WHERE key_hash = '' LIMIT ?1",
+                vec![d1_integer(i64::from(KEY_HASH_BACKFILL_BATCH))],
+            )
+            .await?;
+        if keys.is_empty() {
+            return Ok(total);
+        }
+
+        let mut statements = Vec::with_capacity(keys.len());
+        for key in &keys {
+            let hash = util::key_hash(&key.key);
+            statements.push(
+                db.prepare("UPDATE keys SET key_hash = ?1 WHERE id = ?2")
+                    .bind_refs(&[worker::D1Type::Text(&hash), worker::D1Type::Text(&key.id)])?,
+            );
+        }
+        db.batch(statements).await?;
+        total += keys.len();
+        if keys.len() < KEY_HASH_BACKFILL_BATCH as usize {
+            return Ok(total);
+        }
+    }
+}
+
+pub async fn prune_request_events(db: &D1Database) -> StdResult<(), StorageError> {
+    let cutoff = (Date::now() / 1000.0) as i64 - i64::from(REQUEST_EVENT_RETENTION_SECONDS);
+    db.prepare("DELETE FROM request_events WHERE created_at < ?1")
+        .bind_refs(&[d1_integer(cutoff)])?
+        .run()
+        .await?;
+    Ok(())
+}
+
+/// Aggregates key pool counts from `keys` and the request outcomes of the last
+/// `window_seconds` from `request_events`. Malformed `model_coolings` values are treated
+/// as not cooling.
+pub async fn get_provider_stats(
+    db: &D1Database,
+    window_seconds: i32,
+) -> StdResult<Vec<ProviderDashboardStats>, StorageError> {
+    let now = (Date::now() / 1000.0) as i64;
+    let window_start = now - i64::from(window_seconds);
+
+    let sql = r#"
+        WITH pool AS (
+            SELECT
+                provider,
+                SUM(CASE WHEN status = 'active' THEN 1 ELSE 0 END) AS active_keys,
+                SUM(CASE WHEN status = 'blocked' THEN 1 ELSE 0 END) AS blocked_keys,
+                SUM(CASE WHEN status = 'active' AND EXISTS (
+                    SELECT 1 FROM json_each(CASE WHEN json_valid(model_coolings) THEN model_coolings ELSE '{}' END)
+                    WHERE json_extract(value, '$.end_at') > ?1
+                ) THEN 1 ELSE 0 END) AS cooling_keys,
+                AVG(CASE WHEN status = 'active' AND latency_ms > 0 THEN latency_ms END) AS avg_latency_ms
+            FROM keys
+            WHERE deleted_at = 0
+            GROUP BY provider
+        ),
+        traffic AS (
+            SELECT
+                provider,
+                COUNT(*) AS requests,
+                SUM(CASE WHEN status < 400 THEN 1 ELSE 0 END) AS successes
+            FROM request_events
+            WHERE created_at >= ?2
+            GROUP BY provider
+        )
+        SELECT
+            pool.provider AS provider,
+            pool.active_keys AS active_keys,
+            pool.blocked_keys AS blocked_keys,
+            pool.cooling_keys AS cooling_keys,
+            pool.avg_latency_ms AS avg_latency_ms,
+            COALESCE(traffic.requests, 0) AS requests,
+            COALESCE(traffic.successes, 0) AS successes
+        FROM pool LEFT JOIN traffic ON traffic.provider = pool.provider
+        ORDER BY pool.provider
+    "#;
+    let executor = get_executor(db);
+    Ok(executor
+        .exec_raw(sql, vec![d1_integer(now), d1_integer(window_start)])
+        .await?)
+}
+
+/// Failure classes seen over the last 24 hours, most recent first.
+pub async fn get_recent_error_classes(db: &D1Database) -> StdResult<Vec<ErrorClassCount>, StorageError> {
+    let day_start = (Date::now() / 1000.0) as i64 - 24 * 60 * 60;
+    let sql = "SELECT provider, error_class, COUNT(*) AS count, MAX(created_at) AS last_seen_at \
+        FROM request_events WHERE created_at >= ?1 AND error_class != '' \
+        GROUP BY provider, error_class ORDER BY last_seen_at DESC LIMIT 50";
+    let executor = get_executor(db);
+    Ok(executor
+        .exec_raw(sql, vec![d1_integer(day_start)])
+        .await?)
+}
+
+// endregion: --- Dashboard
+
+// region: --- Alerts
+
+/// Alert history older than this is pruned by the scheduled job.
+const ALERT_EVENT_RETENTION_SECONDS: i32 = 90 * 24 * 60 * 60;
+/// Alert events listed by the admin API.
+const ALERT_EVENTS_LIMIT: u32 = 200;
+
+/// Records that an alert rule started (`firing`) or stopped (`resolved`) firing.
+pub async fn record_alert_event(
+    db: &D1Database,
+    provider: &str,
+    metric: &str,
+    state: &str,
+    value: i64,
+    threshold: i64,
+    message: &str,
+) -> StdResult<(), StorageError> {
+    let executor = get_executor(db);
+    let id_str = Uuid::new_v4().to_string();
+    let untyped_id = toasty_core::stmt::Id::from_string(AlertEvent::ID, id_str);
+    let typed_id = toasty::stmt::Id::from_untyped(untyped_id);
+
+    let insert = AlertEvent::create()
+        .id(typed_id)
+        .provider(provider.to_string())
+        .metric(metric.to_string())
+        .state(state.to_string())
+        .value(value)
+        .threshold(threshold)
+        .message(message.to_string())
+        .created_at((Date::now() / 1000.0) as i64);
+
+    executor.exec_insert(insert.into_insert()).await?;
+    Ok(())
+}
+
+/// The latest state of every alert rule, per provider and metric.
+#[derive(serde::Deserialize, Debug)]
+pub struct AlertStateRow {
+    pub provider: String,
+    pub metric: String,
+    pub state: String,
+}
+
+pub async fn list_alert_states(db: &D1Database) -> StdResult<Vec<AlertStateRow>, StorageError> {
+    // SQLite takes the bare columns from the row holding the MAX.
+    let sql = "SELECT provider, metric, state, MAX(created_at) AS created_at FROM alert_events \
+               GROUP BY provider, metric";
+    let executor = get_executor(db);
+    Ok(executor.exec_raw(sql, vec![]).await?)
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct AlertEventRow {
+    pub provider: String,
+    pub metric: String,
+    pub state: String,
+    pub value: i64,
+    pub threshold: i64,
+    pub message: String,
+    pub created_at: i64,
+}
+
+/// Lists the latest alert events, newest first.
+pub async fn list_alert_events(db: &D1Database) -> StdResult<Vec<AlertEventRow>, StorageError> {
+    let sql = format!(
+        "SELECT provider, metric, state, value, threshold, message, created_at FROM alert_events \
+         ORDER BY created_at DESC LIMIT {}",
+        ALERT_EVENTS_LIMIT
+    );
+    let executor = get_executor(db);
+    Ok(executor.exec_raw(&sql, vec![]).await?)
+}
+
+pub async fn prune_alert_events(db: &D1Database) -> StdResult<(), StorageError> {
+    let cutoff = (Date::now() / 1000.0) as i64 - i64::from(ALERT_EVENT_RETENTION_SECONDS);
+    db.prepare("DELETE FROM alert_events WHERE created_at < ?1")
+        .bind_refs(&[d1_integer(cutoff)])?
+        .run()
+        .await?;
+    Ok(())
+}
+
+// endregion: --- Alerts
+
+// region: --- Key Scores
+
+/// At most this many keys of a ranking are stored; the rest are unlikely to be tried.
+const MAX_SCORED_KEYS: usize = 20;
+/// Stored rankings listed per key.
+const KEY_SCORES_LIMIT: u32 = 100;
+
+/// Stores the score components of the keys a sampled request ranked, in failover order.
+pub async fn record_key_scores(
+    db: &D1Database,
+    request_id: &str,
+    provider: &str,
+    model: &str,
+    ranking: &[(String, KeyScore)],
+) -> StdResult<(), StorageError> {
+    let now = (Date::now() / 1000.0) as i64;
+    let mut statements = Vec::with_capacity(ranking.len().min(MAX_SCORED_KEYS));
+    for (rank, (key_id, score)) in ranking.iter().take(MAX_SCORED_KEYS).enumerate() {
+        let id = Uuid::new_v4().to_string();
+        let components = [
+            score.latency_score,
+            score.success_score,
+            score.failure_penalty,
+            score.recent_success_bonus,
+            score.fairness_penalty,
+            score.weight,
+            score.total,
+        ];
+        statements.push(
+            db.prepare(
+                "INSERT INTO key_scores (id, request_id, provider, model, key_id, rank, latency_score, \
+                 success_score, failure_penalty, recent_success_bonus, fairness_penalty, weight, total, created_at) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            )
+            .bind_refs(&[
+                worker::D1Type::Text(&id),
+                worker::D1Type::Text(request_id),
+                worker::D1Type::Text(provider),
+                worker::D1Type::Text(model),
+                worker::D1Type::Text(key_id),
+                d1_integer(rank as i64),
+                d1_integer(components[0]),
+                d1_integer(components[1]),
+                d1_integer(components[2]),
+                d1_integer(components[3]),
+                d1_integer(components[4]),
+                d1_integer(components[5]),
+                d1_integer(components[6]),
+                d1_integer(now),
+            ])?,
+        );
+    }
+    if !statements.is_empty() {
+        db.batch(statements).await?;
+    }
+    Ok(())
+}
+
+/// A stored ranking of a key, as shown on the key details.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct KeyScoreRow {
+    pub request_id: String,
+    pub provider: String,
+    pub model: String,
+    pub rank: i64,
+    pub latency_score: i64,
+    pub success_score: i64,
+    pub failure_penalty: i64,
+    pub recent_success_bonus: i64,
+    pub fairness_penalty: i64,
+    pub weight: i64,
+    pub total: i64,
+    pub created_at: i64,
+}
+
+/// Lists a key's latest stored rankings, newest first.
+pub async fn list_key_scores(db: &D1Database, key_id: &str) -> StdResult<Vec<KeyScoreRow>, StorageError> {
+    let executor = get_executor(db);
+    let sql = format!(
+        "SELECT request_id, provider, model, rank, latency_score, success_score, failure_penalty, \
+         recent_success_bonus, fairness_penalty, weight, total, created_at FROM key_scores WHERE key_id = ?1 \
+         ORDER BY created_at DESC, rank LIMIT {}",
+        KEY_SCORES_LIMIT
+    );
+    Ok(executor.exec_raw(&sql, vec![worker::D1Type::Text(key_id)]).await?)
+}
+
+/// Stored rankings are kept as long as request events.
+pub async fn prune_key_scores(db: &D1Database) -> StdResult<(), StorageError> {
+    let cutoff = (Date::now() / 1000.0) as i64 - i64::from(REQUEST_EVENT_RETENTION_SECONDS);
+    db.prepare("DELETE FROM key_scores WHERE created_at < ?1")
+        .bind_refs(&[d1_integer(cutoff)])?
+        .run()
+        .await?;
+    Ok(())
+}
+
+// endregion: --- Key Scores
+
+// region: --- SQL Previews
+
+/// Storage operations whose generated SQL can be previewed, see `preview_sql`.
+pub const PREVIEW_OPERATIONS: &[&str] = &["list_keys", "list_all_keys", "get_active_keys", "get_key", "get_keys_by_ids"];
+
+/// Arguments of a previewed operation. Each operation reads the ones of the function it
+/// previews; `key_id` is a comma-separated list for `get_keys_by_ids`.
+#[derive(serde::Deserialize, Debug)]
+#[serde(default)]
+pub struct PreviewArgs {
+    pub provider: String,
+    pub status: String,
+    pub q: String,
+    pub page: usize,
+    pub page_size: usize,
+    pub sort_by: String,
+    pub sort_order: String,
+    pub key_id: String,
+}
+
+impl Default for PreviewArgs {
+    fn default() -> Self {
+        Self {
+            provider: String::new(),
+            status: "active".to_string(),
+            q: String::new(),
+            page: 1,
+            page_size: 20,
+            sort_by: "updatedAt".to_string(),
+            sort_order: "desc".to_string(),
+            key_id: String::new(),
+        }
+    }
+}
+
+/// The SQL and bound parameters the named operation sends to D1 for `args`, in the order
+/// it runs them, without running anything. For diagnosing Toasty lowering against live data.
+pub fn preview_sql(db: &D1Database, operation: &str, args: &PreviewArgs) -> StdResult<Vec<SqlPreview>, StorageError> {
+    let executor = get_executor(db);
+    let provider = args.provider.as_str();
+    let previews = match operation {
+        "list_keys" => vec![
+            executor.preview_count(key_list_query(provider, &args.status, &args.q))?,
+            executor.preview_query_columns(
+                key_list_page_query(
+                    provider,
+                    &KeyListPage {
+                        status: &args.status,
+                        q: &args.q,
+                        page: args.page,
+                        page_size: args.page_size,
+                        sort_by: &args.sort_by,
+                        sort_order: &args.sort_order,
+                    },
+                ),
+                KEY_LIST_COLUMNS,
+            )?,
+        ],
+        "list_all_keys" => vec![executor.preview_query(all_keys_query(Some(provider).filter(|p| !p.is_empty())))?],
+        "get_active_keys" => vec![executor.preview_query(active_keys_query(provider))?],
+        "get_key" => vec![executor.preview_query(DbKey::filter_by_id(args.key_id.clone()))?],
+        "get_keys_by_ids" => {
+            let ids: Vec<String> = args.key_id.split(',').map(|id| id.trim().to_string()).collect();
+            vec![executor.preview_query(DbKey::filter(DbKey::FIELDS.id.in_set(ids)))?]
+        }
+        _ => {
+            return Err(StorageError::Worker(worker::Error::from(format!(
+                "Unknown storage operation '{}'",
+                operation
+            ))))
+        }
+    };
+    Ok(previews)
+}
+
+// endregion: --- SQL Previews
@@ -16,6 +16,9 @@ pub struct Key {
     #[auto]
     pub id: Id<Self>,
     pub key: String,
+    /// `util::key_hash` of `key`; unique per provider, so concurrent adds can't duplicate a key.
+    #[index]
+    pub key_hash: String,
     #[index]
     pub provider: String,
     pub model_coolings: String, // Stored as JSON
@@ -31,6 +34,10 @@ pub struct Key {
     // Health Metrics
     #[index]
     pub latency_ms: i64,
+    /// Moving average of the latency.
+    pub latency_ewma_ms: i64,
+    /// Decaying latency histogram, a JSON array of bucket counts (see `strategy::LatencyHistogram`).
+    pub latency_histogram: String,
     #[index]
     pub success_rate: i64,
     #[index]
@@ -39,8 +46,91 @@ pub struct Key {
     pub last_checked_at: i64,
     #[index]
     pub last_succeeded_at: i64,
+
+    /// When the key was moved to the trash, 0 while it is live.
+    #[index]
+    pub deleted_at: i64,
+
+    // Usage over fixed windows, for fairness. `usage_window_start` is the start of the
+    // current window; the previous window's count is kept to smooth the rollover.
+    pub usage_window_start: i64,
+    pub usage_window_requests: i64,
+    pub usage_prev_window_requests: i64,
+
+    /// `free` or `paid`; free keys are preferred so paid quota is spent last.
+    pub tier: String,
+
+    /// Why the key was blocked, empty while it is active.
+    pub block_reason: String,
+    /// When the key was blocked, 0 while it is active.
+    pub blocked_at: i64,
+    /// How often probation reactivated the key after a block.
+    pub reactivations: i64,
+
+    /// A free-form note from an operator.
+    pub note: String,
+    /// Comma-separated tags, with a leading and trailing comma so `LIKE '%,tag,%'` matches
+    /// whole tags (see `strategy::join_tags`).
+    pub tags: String,
+    /// Added to the key's health score, so a positive weight moves it up the failover order.
+    pub weight: i64,
+}
+
+/// A downstream API key issued to a client of the gateway.
+/// Scopes are stored as comma-separated lists; an empty list means "all".
+#[derive(Debug, Model, Clone, Serialize, Deserialize)]
+#[table = "client_keys"]
+pub struct ClientKey {
+    #[key]
+    #[auto]
+    pub id: Id<Self>,
+    pub name: String,
+    /// The SHA-256 of the secret, hex; see `d1_storage::client_key_hash`. Keys issued before
+    /// hashing hold the secret until their first use or the daily job hashes it.
+    #[unique]
+    pub key: String,
+    /// The start of the secret, for display.
+    pub key_prefix: String,
+    pub allowed_providers: String,
+    pub allowed_models: String,
+    #[index]
+    pub status: String,
+    /// Quota limits; zero means unlimited.
+    pub max_requests_per_day: i64,
+    pub max_tokens_per_month: i64,
+    pub max_budget_micros: i64,
+    /// `viewer`, `operator` or `admin` for UI and admin API access; empty for proxy-only keys.
+    pub role: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub last_used_at: i64,
 }
 
+/// One billable upstream call, used for usage reporting and cost accounting.
+#[derive(Debug, Model, Clone, Serialize, Deserialize)]
+#[table = "usage_events"]
+pub struct UsageEvent {
+    #[key]
+    #[auto]
+    pub id: Id<Self>,
+    /// The client key id, or empty for requests made with the master key.
+    #[index]
+    pub client_id: String,
+    #[index]
+    pub provider: String,
+    pub model: String,
+    pub key_id: String,
+    pub endpoint: String,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub images: i64,
+    /// Cost in millionths of a US dollar.
+    pub cost_micros: i64,
+    /// 1 if the token counts were estimated rather than reported by the provider.
+    pub estimated: i64,
+    #[index]
+    pub created_at: i64,
+}
 
 impl Key {
     pub fn get_model_coolings(&self) -> anyhow::Result<Option<HashMap<String, ModelCooling>>> {
@@ -59,3 +149,247 @@ impl Key {
         Ok(())
     }
 }
+
+/// A sampled prompt/response pair kept for offline quality evaluation. Bodies are redacted.
+#[derive(Debug, Model, Clone, Serialize, Deserialize)]
+#[table = "samples"]
+pub struct Sample {
+    #[key]
+    #[auto]
+    pub id: Id<Self>,
+    #[index]
+    pub provider: String,
+    pub model: String,
+    pub key_id: String,
+    pub client_id: String,
+    pub endpoint: String,
+    pub request_body: String,
+    pub response_body: String,
+    pub latency_ms: i64,
+    #[index]
+    pub created_at: i64,
+}
+
+/// A provider's model list, cached for the `/compat/models` endpoint.
+#[derive(Debug, Model, Clone, Serialize, Deserialize)]
+#[table = "model_catalog"]
+pub struct ModelCatalog {
+    #[key]
+    #[auto]
+    pub id: Id<Self>,
+    #[unique]
+    pub provider: String,
+    /// JSON array of bare model names.
+    pub models: String,
+    pub fetched_at: i64,
+}
+
+/// Per-provider routing settings managed by operators.
+#[derive(Debug, Model, Clone, Serialize, Deserialize)]
+#[table = "provider_settings"]
+pub struct ProviderSetting {
+    #[key]
+    #[auto]
+    pub id: Id<Self>,
+    #[unique]
+    pub provider: String,
+    /// 1 if the provider's keys are only probed by the scheduled tester and receive no live traffic.
+    pub observe_only: i64,
+    /// Per-key budgets, per minute; 0 means unlimited.
+    pub rpm_limit: i64,
+    pub tpm_limit: i64,
+    pub updated_at: i64,
+}
+
+/// An OpenAI-compatible endpoint registered by operators, e.g. a self-hosted model server.
+#[derive(Debug, Model, Clone, Serialize, Deserialize)]
+#[table = "providers"]
+pub struct CustomProvider {
+    #[key]
+    #[auto]
+    pub id: Id<Self>,
+    /// The provider name clients use as the model prefix.
+    #[unique]
+    pub name: String,
+    /// Requests go to this URL followed by the path after the provider, e.g. `chat/completions`.
+    pub base_url: String,
+    pub auth_header: String,
+    /// Put before the key in the auth header, e.g. `Bearer`; empty sends the key alone.
+    pub auth_scheme: String,
+    /// A CSS color for the provider in the UI, e.g. `#10b981`.
+    pub color: String,
+    pub updated_at: i64,
+}
+
+/// A runtime setting stored by operators, overriding the worker var of the same name.
+#[derive(Debug, Model, Clone, Serialize, Deserialize)]
+#[table = "settings"]
+pub struct Setting {
+    #[key]
+    #[auto]
+    pub id: Id<Self>,
+    /// The var name, e.g. `TARGET_TIMEOUT_MS`.
+    #[unique]
+    pub name: String,
+    pub value: String,
+    pub updated_at: i64,
+}
+
+/// The outcome of one proxied request, kept for a couple of days for the dashboard.
+#[derive(Debug, Model, Clone, Serialize, Deserialize)]
+#[table = "request_events"]
+pub struct RequestEvent {
+    #[key]
+    #[auto]
+    pub id: Id<Self>,
+    #[index]
+    pub provider: String,
+    pub model: String,
+    pub status: i64,
+    /// Why the request failed, empty on success.
+    pub error_class: String,
+    pub latency_ms: i64,
+    /// The redacted, truncated prompt when payload logging covered the request; empty otherwise.
+    pub request_body: String,
+    pub response_body: String,
+    #[index]
+    pub created_at: i64,
+}
+
+/// A key's health score components when a sampled request ranked it, so operators can see
+/// why a key was or wasn't chosen. Kept as long as request events.
+#[derive(Debug, Model, Clone, Serialize, Deserialize)]
+#[table = "key_scores"]
+pub struct KeyScoreSample {
+    #[key]
+    #[auto]
+    pub id: Id<Self>,
+    pub request_id: String,
+    pub provider: String,
+    pub model: String,
+    #[index]
+    pub key_id: String,
+    /// Position in the failover order, 0 for the key tried first.
+    pub rank: i64,
+    pub latency_score: i64,
+    pub success_score: i64,
+    pub failure_penalty: i64,
+    pub recent_success_bonus: i64,
+    pub fairness_penalty: i64,
+    pub weight: i64,
+    pub total: i64,
+    #[index]
+    pub created_at: i64,
+}
+
+/// An alert rule that started or stopped firing for a provider (see `alerts`).
+#[derive(Debug, Model, Clone, Serialize, Deserialize)]
+#[table = "alert_events"]
+pub struct AlertEvent {
+    #[key]
+    #[auto]
+    pub id: Id<Self>,
+    #[index]
+    pub provider: String,
+    /// `active_keys`, `usable_keys` or `success_rate`.
+    pub metric: String,
+    /// `firing` or `resolved`.
+    pub state: String,
+    pub value: i64,
+    pub threshold: i64,
+    pub message: String,
+    #[index]
+    pub created_at: i64,
+}
+
+/// Why a key was blocked, cooled down or reactivated, for its history in the keys list.
+#[derive(Debug, Model, Clone, Serialize, Deserialize)]
+#[table = "key_events"]
+pub struct KeyEvent {
+    #[key]
+    #[auto]
+    pub id: Id<Self>,
+    #[index]
+    pub key_id: String,
+    pub provider: String,
+    /// Empty when the event applies to the whole key.
+    pub model: String,
+    /// `blocked`, `cooldown` or `reactivated`.
+    pub event: String,
+    /// The provider's status code, 0 when there was no upstream response.
+    pub status: i64,
+    pub error_class: String,
+    /// The provider's error message (redacted and truncated) or another explanation.
+    pub message: String,
+    #[index]
+    pub created_at: i64,
+}
+
+/// A login to the web UI or the admin API, referenced by the signed `session` cookie.
+#[derive(Debug, Model, Clone, Serialize, Deserialize)]
+#[table = "sessions"]
+pub struct Session {
+    #[key]
+    #[auto]
+    pub id: Id<Self>,
+    /// The client key id, or empty for sessions of the master key.
+    #[index]
+    pub client_id: String,
+    /// The role at login: `viewer`, `operator` or `admin`.
+    pub role: String,
+    pub created_at: i64,
+    #[index]
+    pub expires_at: i64,
+    /// When the session was logged out or revoked; 0 while it is valid.
+    pub revoked_at: i64,
+}
+
+/// A tag of a key, one row per tag, so keys can be counted and looked up by tag. Mirrors
+/// the key's `tags` column (see `d1_storage::update_key_metadata`).
+#[derive(Debug, Model, Clone, Serialize, Deserialize)]
+#[table = "key_tags"]
+pub struct KeyTag {
+    #[key]
+    #[auto]
+    pub id: Id<Self>,
+    #[index]
+    pub key_id: String,
+    #[index]
+    pub provider: String,
+    #[index]
+    pub tag: String,
+}
+
+/// The TOTP second factor of the master key (subject `master`) or a client key (its id).
+#[derive(Debug, Model, Clone, Serialize, Deserialize)]
+#[table = "totp_secrets"]
+pub struct TotpSecret {
+    #[key]
+    #[auto]
+    pub id: Id<Self>,
+    #[unique]
+    pub subject: String,
+    /// Base32, as shown on the provisioning page.
+    pub secret: String,
+    /// When the first code was confirmed; 0 while the secret is being provisioned.
+    pub enabled_at: i64,
+    /// The last time step a code was accepted for, so codes can't be replayed.
+    pub last_step: i64,
+    pub created_at: i64,
+}
+
+/// One Prometheus series (a counter or histogram component) aggregated in D1.
+#[derive(Debug, Model, Clone, Serialize, Deserialize)]
+#[table = "metrics"]
+pub struct MetricSeries {
+    #[key]
+    #[auto]
+    pub id: Id<Self>,
+    #[index]
+    pub name: String,
+    /// Rendered label set, e.g. `provider="openai",status="200"`.
+    pub labels: String,
+    /// Counts, or milliseconds for latency sums (Toasty has no float columns).
+    pub value: i64,
+    pub updated_at: i64,
+}
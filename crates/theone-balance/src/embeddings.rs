@@ -0,0 +1,153 @@
+//! This module serves `/compat/embeddings` for the providers with an embeddings API. The
+//! request is always OpenAI-shaped; the provider, picked by the model prefix (e.g.
+//! `cohere/embed-v4.0`), decides where it goes and how both directions are translated.
+//!
+//! OpenAI and Mistral speak the OpenAI shape already, so only the model name is rewritten;
+//! Gemini and Cohere are translated both ways.
+
+use crate::compat::CompatEndpoint;
+use crate::gcp;
+use crate::models::{
+    CohereEmbedRequest, CohereEmbedResponse, EmbeddingInput, GeminiEmbeddingsResponse, OpenAiEmbedding,
+    OpenAiEmbeddingsRequest, OpenAiEmbeddingsResponse, OpenAiUsage,
+};
+use std::borrow::Cow;
+
+/// A provider's embeddings API, as seen from the OpenAI-shaped compat route.
+pub trait EmbeddingsProvider: Sync {
+    /// Where to send the request for `model`.
+    fn endpoint(&self, model: &str) -> CompatEndpoint;
+
+    /// Builds the provider's request body from the OpenAI-shaped one. `model` is the bare
+    /// model name, without the provider prefix.
+    fn translate_request(&self, body: &[u8], model: &str) -> serde_json::Result<Vec<u8>>;
+
+    /// Builds the OpenAI-shaped response body from the provider's one.
+    fn translate_response(&self, body: &[u8], model: &str) -> serde_json::Result<Vec<u8>>;
+}
+
+/// Providers that take and return the OpenAI shape as is.
+struct OpenAiStyle {
+    native_url: &'static str,
+    gateway_path: &'static str,
+}
+
+impl EmbeddingsProvider for OpenAiStyle {
+    fn endpoint(&self, _model: &str) -> CompatEndpoint {
+        CompatEndpoint {
+            native_url: Cow::Borrowed(self.native_url),
+            gateway_path: Cow::Borrowed(self.gateway_path),
+        }
+    }
+
+    fn translate_request(&self, body: &[u8], model: &str) -> serde_json::Result<Vec<u8>> {
+        // Parsed loosely, so options like `dimensions` and `encoding_format` pass through.
+        let mut req: serde_json::Value = serde_json::from_slice(body)?;
+        req["model"] = serde_json::Value::String(model.to_string());
+        serde_json::to_vec(&req)
+    }
+
+    fn translate_response(&self, body: &[u8], _model: &str) -> serde_json::Result<Vec<u8>> {
+        Ok(body.to_vec())
+    }
+}
+
+/// Gemini's `batchEmbedContents`.
+struct Gemini;
+
+impl EmbeddingsProvider for Gemini {
+    fn endpoint(&self, model: &str) -> CompatEndpoint {
+        CompatEndpoint {
+            native_url: Cow::Owned(format!(
+                "https://generativelanguage.googleapis.com/v1beta/models/{}:batchEmbedContents",
+                model
+            )),
+            gateway_path: Cow::Owned(format!("google-ai-studio/v1beta/models/{}:batchEmbedContents", model)),
+        }
+    }
+
+    fn translate_request(&self, body: &[u8], model: &str) -> serde_json::Result<Vec<u8>> {
+        let req: OpenAiEmbeddingsRequest = serde_json::from_slice(body)?;
+        serde_json::to_vec(&gcp::translate_embeddings_request(req, model))
+    }
+
+    fn translate_response(&self, body: &[u8], model: &str) -> serde_json::Result<Vec<u8>> {
+        let resp: GeminiEmbeddingsResponse = serde_json::from_slice(body)?;
+        serde_json::to_vec(&gcp::translate_embeddings_response(resp, model))
+    }
+}
+
+/// Cohere's v2 `embed`.
+struct Cohere;
+
+impl EmbeddingsProvider for Cohere {
+    fn endpoint(&self, _model: &str) -> CompatEndpoint {
+        CompatEndpoint {
+            native_url: Cow::Borrowed("https://api.cohere.com/v2/embed"),
+            gateway_path: Cow::Borrowed("cohere/v2/embed"),
+        }
+    }
+
+    fn translate_request(&self, body: &[u8], model: &str) -> serde_json::Result<Vec<u8>> {
+        let req: OpenAiEmbeddingsRequest = serde_json::from_slice(body)?;
+        let texts = match req.input {
+            EmbeddingInput::String(s) => vec![s],
+            EmbeddingInput::StringArray(arr) => arr,
+        };
+        serde_json::to_vec(&CohereEmbedRequest {
+            model: model.to_string(),
+            texts,
+            // OpenAI has no notion of input types; documents are the general-purpose choice.
+            input_type: "search_document".to_string(),
+            embedding_types: vec!["float".to_string()],
+            output_dimension: req.dimensions,
+        })
+    }
+
+    fn translate_response(&self, body: &[u8], model: &str) -> serde_json::Result<Vec<u8>> {
+        let resp: CohereEmbedResponse = serde_json::from_slice(body)?;
+        let input_tokens = resp.meta.billed_units.input_tokens;
+        let data = resp
+            .embeddings
+            .float
+            .into_iter()
+            .enumerate()
+            .map(|(i, embedding)| OpenAiEmbedding {
+                object: "embedding".to_string(),
+                embedding,
+                index: i as u32,
+            })
+            .collect();
+        serde_json::to_vec(&OpenAiEmbeddingsResponse {
+            object: "list".to_string(),
+            data,
+            model: model.to_string(),
+            usage: OpenAiUsage {
+                prompt_tokens: input_tokens,
+                completion_tokens: 0,
+                total_tokens: input_tokens,
+            },
+        })
+    }
+}
+
+static OPENAI: OpenAiStyle = OpenAiStyle {
+    native_url: "https://api.openai.com/v1/embeddings",
+    gateway_path: "openai/embeddings",
+};
+
+static MISTRAL: OpenAiStyle = OpenAiStyle {
+    native_url: "https://api.mistral.ai/v1/embeddings",
+    gateway_path: "mistral/v1/embeddings",
+};
+
+/// Returns the embeddings API of a provider, if it has one we can translate to.
+pub fn provider(provider: &str) -> Option<&'static dyn EmbeddingsProvider> {
+    match provider {
+        "google-ai-studio" => Some(&Gemini),
+        "openai" => Some(&OPENAI),
+        "mistral" => Some(&MISTRAL),
+        "cohere" => Some(&Cohere),
+        _ => None,
+    }
+}
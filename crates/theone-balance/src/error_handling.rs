@@ -3,7 +3,9 @@
 use crate::models::GoogleErrorResponse;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response as AxumResponse};
-use tracing::info;
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use std::io::Read;
+use tracing::{info, warn};
 use worker::{Error as WorkerError, Response as WorkerResponse};
 
 // --- Newtype Wrappers to solve the Orphan Rule ---
@@ -26,6 +28,12 @@ impl IntoResponse for AxumWorkerError {
 const DEFAULT_COOLDOWN_SECONDS: u64 = 65;
 const DAILY_COOLDOWN_SECONDS: u64 = 24 * 60 * 60;
 
+// Local penalty-box durations for failures that don't come with a provider cooldown.
+// Transient failures bench a key only briefly; an invalid key is blocked in D1 anyway.
+const INVALID_KEY_PENALTY_SECONDS: u64 = 300;
+const SERVER_ERROR_PENALTY_SECONDS: u64 = 10;
+const TIMEOUT_PENALTY_SECONDS: u64 = 30;
+
 /// Represents the outcome of analyzing a provider error.
 pub enum ErrorAnalysis {
     /// The key is invalid and should be disabled.
@@ -38,10 +46,44 @@ pub enum ErrorAnalysis {
     TransientServerError,
     /// The provider request timed out.
     RequestTimeout,
+    /// The provider answered with a success that failed response validation.
+    MalformedResponse,
     /// The error is unrecognized.
     Unknown,
 }
 
+impl ErrorAnalysis {
+    /// A short, stable label for the kind of failure, used in request events.
+    pub fn class(&self) -> &'static str {
+        match self {
+            ErrorAnalysis::KeyIsInvalid => "invalid_key",
+            ErrorAnalysis::KeyOnCooldown { .. } => "rate_limited",
+            ErrorAnalysis::UserError => "user_error",
+            ErrorAnalysis::TransientServerError => "server_error",
+            ErrorAnalysis::RequestTimeout => "timeout",
+            ErrorAnalysis::MalformedResponse => "malformed_response",
+            ErrorAnalysis::Unknown => "unknown",
+        }
+    }
+
+    /// Whether the key ran out of its daily quota, rather than hitting a short rate limit.
+    pub fn is_daily_quota(&self) -> bool {
+        matches!(self, ErrorAnalysis::KeyOnCooldown { cooldown_seconds } if *cooldown_seconds >= DAILY_COOLDOWN_SECONDS)
+    }
+
+    /// How long the key should sit in the local penalty box after this failure, if at all.
+    pub fn penalty_seconds(&self) -> Option<u64> {
+        match self {
+            ErrorAnalysis::KeyOnCooldown { cooldown_seconds } => Some(*cooldown_seconds),
+            ErrorAnalysis::KeyIsInvalid => Some(INVALID_KEY_PENALTY_SECONDS),
+            ErrorAnalysis::TransientServerError => Some(SERVER_ERROR_PENALTY_SECONDS),
+            ErrorAnalysis::RequestTimeout => Some(TIMEOUT_PENALTY_SECONDS),
+            // A malformed answer says little about the key itself, so it isn't benched.
+            ErrorAnalysis::UserError | ErrorAnalysis::MalformedResponse | ErrorAnalysis::Unknown => None,
+        }
+    }
+}
+
 /// Analyzes a Google API error response to determine the cause.
 pub fn analyze_google_error(error_body: &GoogleErrorResponse) -> ErrorAnalysis {
     for detail in &error_body.error.details {
@@ -116,6 +158,44 @@ pub fn key_is_invalid_from_error(error_body: &GoogleErrorResponse) -> bool {
     false
 }
 
+/// Decompressed error bodies are cut off at this size.
+const MAX_DECODED_ERROR_BODY_BYTES: u64 = 1024 * 1024;
+
+fn read_decoded(reader: impl Read) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    reader
+        .take(MAX_DECODED_ERROR_BODY_BYTES)
+        .read_to_end(&mut decoded)
+        .ok()
+        .map(|_| decoded)
+}
+
+/// Reads a provider error body as text. Some providers compress error bodies, and when
+/// the runtime passes them on still encoded, reading them as text yields garbage that
+/// defeats the error analysis. Bodies are decompressed according to `content_encoding`,
+/// or when they start with the gzip magic bytes; a body that fails to decompress is read
+/// as it is.
+pub fn decode_error_body(bytes: &[u8], content_encoding: Option<&str>) -> String {
+    let encoding = content_encoding.unwrap_or_default().trim().to_ascii_lowercase();
+    let decoded = if encoding == "gzip" || encoding == "x-gzip" || bytes.starts_with(&[0x1f, 0x8b]) {
+        read_decoded(GzDecoder::new(bytes))
+    } else if encoding == "deflate" {
+        // "deflate" is meant to be zlib-wrapped, but some servers send a raw stream.
+        read_decoded(ZlibDecoder::new(bytes)).or_else(|| read_decoded(DeflateDecoder::new(bytes)))
+    } else {
+        None
+    };
+    match decoded {
+        Some(decoded) => String::from_utf8_lossy(&decoded).into_owned(),
+        None => {
+            if !encoding.is_empty() && encoding != "identity" {
+                warn!(encoding = %encoding, "Failed to decompress the error body; reading it as is.");
+            }
+            String::from_utf8_lossy(bytes).into_owned()
+        }
+    }
+}
+
 /// A new, more generic error analysis function that handles different providers
 /// and status codes before delegating to provider-specific logic.
 pub async fn analyze_provider_error(provider: &str, status: u16, body_text: &str) -> ErrorAnalysis {
@@ -161,3 +241,62 @@ pub async fn analyze_provider_error(provider: &str, status: u16, body_text: &str
         _ => ErrorAnalysis::Unknown,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use futures_util::FutureExt;
+    use std::io::Write;
+
+    const GOOGLE_INVALID_KEY: &str = r#"{"error":{"code":400,"message":"API key not valid. Please pass a valid API key.","status":"INVALID_ARGUMENT","details":[{"@type":"type.googleapis.com/google.rpc.ErrorInfo","reason":"API_KEY_INVALID","domain":"googleapis.com"}]}}"#;
+    const GOOGLE_DAILY_QUOTA: &str = r#"[{"error":{"code":429,"message":"You exceeded your current quota.","status":"RESOURCE_EXHAUSTED","details":[{"@type":"type.googleapis.com/google.rpc.QuotaFailure","violations":[{"subject":"project:123","description":"Daily request limit","quotaMetric":"generativelanguage.googleapis.com/generate_content_free_tier_requests","quotaId":"GenerateRequestsPerDayPerProjectPerModel-FreeTier"}]}]}}]"#;
+
+    fn gzip(body: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn analyze(status: u16, body: &str) -> ErrorAnalysis {
+        analyze_provider_error("google-ai-studio", status, body)
+            .now_or_never()
+            .expect("the analysis doesn't wait on anything")
+    }
+
+    #[test]
+    fn gzipped_google_errors_are_classified() {
+        let body = decode_error_body(&gzip(GOOGLE_INVALID_KEY), Some("gzip"));
+        assert_eq!(body, GOOGLE_INVALID_KEY);
+        assert!(matches!(analyze(400, &body), ErrorAnalysis::KeyIsInvalid));
+
+        let body = decode_error_body(&gzip(GOOGLE_DAILY_QUOTA), Some("gzip"));
+        assert!(matches!(
+            analyze(429, &body),
+            ErrorAnalysis::KeyOnCooldown { cooldown_seconds: DAILY_COOLDOWN_SECONDS }
+        ));
+    }
+
+    #[test]
+    fn gzip_is_detected_without_the_header() {
+        assert_eq!(decode_error_body(&gzip(GOOGLE_INVALID_KEY), None), GOOGLE_INVALID_KEY);
+    }
+
+    #[test]
+    fn deflate_bodies_are_decoded_with_or_without_the_zlib_wrapper() {
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(GOOGLE_INVALID_KEY.as_bytes()).unwrap();
+        assert_eq!(decode_error_body(&zlib.finish().unwrap(), Some("deflate")), GOOGLE_INVALID_KEY);
+
+        let mut raw = DeflateEncoder::new(Vec::new(), Compression::default());
+        raw.write_all(GOOGLE_INVALID_KEY.as_bytes()).unwrap();
+        assert_eq!(decode_error_body(&raw.finish().unwrap(), Some("Deflate")), GOOGLE_INVALID_KEY);
+    }
+
+    #[test]
+    fn plain_and_undecodable_bodies_are_read_as_is() {
+        assert_eq!(decode_error_body(GOOGLE_INVALID_KEY.as_bytes(), None), GOOGLE_INVALID_KEY);
+        assert_eq!(decode_error_body(b"not gzip", Some("gzip")), "not gzip");
+    }
+}
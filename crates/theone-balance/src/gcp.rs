@@ -1,14 +1,36 @@
 //! This module handles the translation logic between OpenAI-compatible models
 //! and the native Google Gemini models, primarily for the embeddings endpoint
-//! which requires a direct provider call.
+//! which requires a direct provider call. Streamed chat completions are translated
+//! event by event, see `translate_chat_stream`.
+//!
+//! It also authenticates `google-vertex-ai` keys stored as service-account JSON: a JWT
+//! signed with the account's private key is exchanged for an OAuth access token, which is
+//! cached per key until shortly before it expires.
 
 pub use crate::models::{
     EmbeddingInput, GeminiContent, GeminiEmbeddingContent, GeminiEmbeddingsRequest, GeminiEmbeddingsResponse, GeminiPart,
-    OpenAiEmbedding, OpenAiEmbeddingsRequest, OpenAiEmbeddingsResponse, OpenAiUsage,
+    OpenAiEmbedding, OpenAiEmbeddingsRequest, OpenAiEmbeddingsResponse, OpenAiStop, OpenAiUsage,
     OpenAiChatCompletionRequest, GeminiChatRequest, GeminiChatResponse, OpenAiChatCompletionResponse,
-    OpenAiChatChoice, OpenAiChatMessage,
+    OpenAiChatChoice, OpenAiChatMessage, OpenAiChatCompletionChunk, OpenAiChatChunkChoice, OpenAiChatDelta,
+    GeminiStreamChunk, GeminiUsageMetadata, GeminiFunctionCall, GeminiFunctionCallingConfig, GeminiFunctionDeclaration,
+    GeminiFunctionResponse, GeminiGenerationConfig, GeminiTool, GeminiToolConfig, OpenAiFunctionCall, OpenAiToolCall,
 };
 
+use crate::sse::EventScanner;
+use base64::{engine::general_purpose::STANDARD, engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use futures_util::stream::{self, Stream, StreamExt};
+use mini_moka::sync::Cache;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+use worker::js_sys::{self, Array, Function, Object, Promise, Reflect, Uint8Array};
+use worker::wasm_bindgen::{JsCast, JsValue};
+use worker::wasm_bindgen_futures::JsFuture;
+use worker::{AbortSignal, Date, Fetch, Headers, Method, Request, RequestInit, Result};
+
 /// Translates an OpenAI-compatible embeddings request into a native Gemini embeddings request.
 pub fn translate_embeddings_request(
     req: OpenAiEmbeddingsRequest,
@@ -24,7 +46,7 @@ pub fn translate_embeddings_request(
         .map(|text| GeminiEmbeddingContent {
             model: format!("models/{}", model_name),
             content: GeminiContent {
-                parts: vec![GeminiPart { text }],
+                parts: vec![GeminiPart::text(text)],
                 role: None,
             },
         })
@@ -58,18 +80,195 @@ pub fn translate_embeddings_response(
     }
 }
 
-/// Translates an OpenAI-compatible chat completion request into a native Gemini chat request.
+/// Translates an OpenAI-compatible chat completion request into a native Gemini chat request,
+/// including tools, the tool choice, tool calls and their results, and JSON mode.
 pub fn translate_chat_request(req: OpenAiChatCompletionRequest) -> GeminiChatRequest {
-    let contents = req
-        .messages
+    let tools = if req.tools.is_empty() {
+        Vec::new()
+    } else {
+        let function_declarations = req
+            .tools
+            .into_iter()
+            .filter(|tool| tool.kind == "function")
+            .map(|tool| GeminiFunctionDeclaration {
+                name: tool.function.name,
+                description: tool.function.description,
+                parameters: tool.function.parameters.map(to_gemini_schema),
+            })
+            .collect();
+        vec![GeminiTool { function_declarations }]
+    };
+
+    let mut generation_config = GeminiGenerationConfig {
+        temperature: req.temperature,
+        top_p: req.top_p,
+        max_output_tokens: req.max_completion_tokens.or(req.max_tokens),
+        stop_sequences: req.stop.map(OpenAiStop::into_vec).unwrap_or_default(),
+        ..Default::default()
+    };
+    if let Some(format) = req.response_format.filter(|f| matches!(f.kind.as_str(), "json_object" | "json_schema")) {
+        generation_config.response_mime_type = Some("application/json".to_string());
+        generation_config.response_schema = format
+            .json_schema
+            .and_then(|mut json_schema| json_schema.get_mut("schema").map(serde_json::Value::take))
+            .map(to_gemini_schema);
+    }
+
+    // Gemini takes the system prompt apart from the conversation, as a single instruction.
+    let (system, messages): (Vec<_>, Vec<_>) =
+        req.messages.into_iter().partition(|msg| matches!(msg.role.as_str(), "system" | "developer"));
+    let system_parts: Vec<GeminiPart> = system
         .into_iter()
-        .map(|msg| GeminiContent {
-            parts: vec![GeminiPart { text: msg.content }],
-            role: Some(map_role_to_gemini(msg.role)),
-        })
+        .filter_map(|msg| msg.content.filter(|text| !text.is_empty()))
+        .map(GeminiPart::text)
         .collect();
 
-    GeminiChatRequest { contents }
+    GeminiChatRequest {
+        system_instruction: (!system_parts.is_empty()).then_some(GeminiContent {
+            parts: system_parts,
+            role: None,
+        }),
+        contents: translate_chat_messages(messages),
+        tools,
+        tool_config: req.tool_choice.as_ref().and_then(translate_tool_choice),
+        generation_config: (generation_config != GeminiGenerationConfig::default()).then_some(generation_config),
+    }
+}
+
+/// Translates the conversation. Gemini names the function a result answers rather than the
+/// call, so the name is looked up from the call; results of parallel calls go in one turn.
+fn translate_chat_messages(messages: Vec<OpenAiChatMessage>) -> Vec<GeminiContent> {
+    let mut call_names: HashMap<String, String> = HashMap::new();
+    let mut contents: Vec<GeminiContent> = Vec::new();
+    for msg in messages {
+        if msg.role == "tool" {
+            let name = msg
+                .tool_call_id
+                .and_then(|id| call_names.get(&id).cloned())
+                .unwrap_or_default();
+            let part = GeminiPart {
+                function_response: Some(GeminiFunctionResponse {
+                    name,
+                    response: tool_result_object(msg.content.unwrap_or_default()),
+                }),
+                ..Default::default()
+            };
+            match contents.last_mut() {
+                Some(last) if last.parts.iter().all(|p| p.function_response.is_some()) => last.parts.push(part),
+                _ => contents.push(GeminiContent {
+                    parts: vec![part],
+                    role: Some("user".to_string()),
+                }),
+            }
+            continue;
+        }
+
+        let mut parts: Vec<GeminiPart> = msg
+            .content
+            .filter(|text| !text.is_empty())
+            .map(GeminiPart::text)
+            .into_iter()
+            .collect();
+        for call in msg.tool_calls {
+            call_names.insert(call.id, call.function.name.clone());
+            parts.push(GeminiPart {
+                function_call: Some(GeminiFunctionCall {
+                    name: call.function.name,
+                    args: serde_json::from_str(&call.function.arguments).unwrap_or_else(|_| serde_json::json!({})),
+                }),
+                ..Default::default()
+            });
+        }
+        if parts.is_empty() {
+            parts.push(GeminiPart::text(""));
+        }
+        contents.push(GeminiContent {
+            parts,
+            role: Some(map_role_to_gemini(msg.role)),
+        });
+    }
+    contents
+}
+
+/// Gemini takes a function's result as a JSON object; anything else is wrapped in one.
+fn tool_result_object(content: String) -> serde_json::Value {
+    match serde_json::from_str::<serde_json::Value>(&content) {
+        Ok(value @ serde_json::Value::Object(_)) => value,
+        Ok(value) => serde_json::json!({ "content": value }),
+        Err(_) => serde_json::json!({ "content": content }),
+    }
+}
+
+fn translate_tool_choice(choice: &serde_json::Value) -> Option<GeminiToolConfig> {
+    let (mode, allowed_function_names) = match choice {
+        serde_json::Value::String(choice) => match choice.as_str() {
+            "none" => ("NONE", Vec::new()),
+            "auto" => ("AUTO", Vec::new()),
+            "required" => ("ANY", Vec::new()),
+            _ => return None,
+        },
+        choice => {
+            let name = choice.pointer("/function/name")?.as_str()?;
+            ("ANY", vec![name.to_string()])
+        }
+    };
+    Some(GeminiToolConfig {
+        function_calling_config: GeminiFunctionCallingConfig {
+            mode: mode.to_string(),
+            allowed_function_names,
+        },
+    })
+}
+
+/// Gemini accepts an OpenAPI subset of JSON schema and rejects the keywords below, which
+/// OpenAI clients commonly send.
+fn to_gemini_schema(mut schema: serde_json::Value) -> serde_json::Value {
+    fn strip(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for keyword in ["$schema", "additionalProperties", "strict"] {
+                    map.remove(keyword);
+                }
+                for (key, value) in map.iter_mut() {
+                    // The keys of `properties` are property names, not keywords.
+                    match (key.as_str(), value) {
+                        ("properties", serde_json::Value::Object(properties)) => {
+                            properties.values_mut().for_each(strip)
+                        }
+                        (_, value) => strip(value),
+                    }
+                }
+            }
+            serde_json::Value::Array(items) => items.iter_mut().for_each(strip),
+            _ => {}
+        }
+    }
+    strip(&mut schema);
+    schema
+}
+
+/// Splits Gemini parts into their text and OpenAI tool calls. `index` numbers the calls
+/// for stream deltas, starting at `first_index`.
+fn split_parts(parts: Vec<GeminiPart>, first_index: Option<u32>) -> (String, Vec<OpenAiToolCall>) {
+    let mut text = String::new();
+    let mut tool_calls = Vec::new();
+    for part in parts {
+        if let Some(part_text) = part.text {
+            text.push_str(&part_text);
+        }
+        if let Some(call) = part.function_call {
+            tool_calls.push(OpenAiToolCall {
+                index: first_index.map(|first| first + tool_calls.len() as u32),
+                id: format!("call_{}", uuid::Uuid::new_v4().simple()),
+                kind: "function".to_string(),
+                function: OpenAiFunctionCall {
+                    name: call.name,
+                    arguments: call.args.to_string(),
+                },
+            });
+        }
+    }
+    (text, tool_calls)
 }
 
 /// Translates a native Gemini chat response back into an OpenAI-compatible one.
@@ -80,13 +279,19 @@ pub fn translate_chat_response(
     let choices = gemini_resp
         .candidates
         .into_iter()
-        .map(|candidate| OpenAiChatChoice {
-            finish_reason: candidate.finish_reason,
-            index: candidate.index,
-            message: OpenAiChatMessage {
-                role: "assistant".to_string(), // Gemini response roles are not consistently provided
-                content: candidate.content.parts.get(0).map_or("".to_string(), |p| p.text.clone()),
-            },
+        .map(|candidate| {
+            let (text, tool_calls) = split_parts(candidate.content.parts, None);
+            let finish_reason = if tool_calls.is_empty() { candidate.finish_reason } else { "tool_calls".to_string() };
+            OpenAiChatChoice {
+                finish_reason,
+                index: candidate.index,
+                message: OpenAiChatMessage {
+                    role: "assistant".to_string(), // Gemini response roles are not consistently provided
+                    content: (!text.is_empty() || tool_calls.is_empty()).then_some(text),
+                    tool_calls,
+                    tool_call_id: None,
+                },
+            }
         })
         .collect();
 
@@ -96,20 +301,345 @@ pub fn translate_chat_response(
         created: js_sys::Date::now() as u64 / 1000,
         model: model_name.to_string(),
         object: "chat.completion".to_string(),
-        // Gemini API does not provide token usage for chat.
-        usage: OpenAiUsage::default(),
+        usage: gemini_resp
+            .usage_metadata
+            .map(|usage| OpenAiUsage {
+                prompt_tokens: usage.prompt_token_count,
+                completion_tokens: usage.candidates_token_count,
+                total_tokens: usage.total_token_count,
+            })
+            .unwrap_or_default(),
     }
 }
 
+/// Translates a native Gemini `streamGenerateContent` SSE stream into OpenAI-compatible
+/// `chat.completion.chunk` events, chunk by chunk as they arrive. The first text of each
+/// candidate carries the assistant role, and function calls become `tool_calls` deltas;
+/// once the stream ends, a chunk without choices reports the usage, followed by `[DONE]`.
+/// Error events are passed through unchanged.
+struct ChatStreamTranslator {
+    id: String,
+    created: u64,
+    model: String,
+    scanner: EventScanner,
+    /// Candidates whose role has been sent.
+    started: Vec<u32>,
+    /// Tool calls sent so far, by candidate.
+    tool_calls: HashMap<u32, u32>,
+    usage: GeminiUsageMetadata,
+}
+
+impl ChatStreamTranslator {
+    fn new(model_name: &str) -> Self {
+        Self {
+            id: format!("chatcmpl-{}", uuid::Uuid::new_v4()),
+            created: js_sys::Date::now() as u64 / 1000,
+            model: model_name.to_string(),
+            scanner: EventScanner::default(),
+            started: Vec::new(),
+            tool_calls: HashMap::new(),
+            usage: GeminiUsageMetadata::default(),
+        }
+    }
+
+    /// Translates the events a chunk of the Gemini stream completes.
+    fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for event in self.scanner.push(chunk) {
+            let data: String = event
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(str::trim_start)
+                .collect();
+            if data.is_empty() {
+                continue;
+            }
+            let Ok(gemini_chunk) = serde_json::from_str::<GeminiStreamChunk>(&data) else {
+                // Error events (and anything else unexpected) reach the client as they are.
+                out.extend_from_slice(format!("data: {}\n\n", data).as_bytes());
+                continue;
+            };
+            if let Some(usage) = gemini_chunk.usage_metadata {
+                self.usage = usage;
+            }
+            let choices: Vec<OpenAiChatChunkChoice> = gemini_chunk
+                .candidates
+                .into_iter()
+                .map(|candidate| {
+                    let sent_calls = self.tool_calls.entry(candidate.index).or_default();
+                    let parts = candidate.content.map(|content| content.parts).unwrap_or_default();
+                    let (text, tool_calls) = split_parts(parts, Some(*sent_calls));
+                    *sent_calls += tool_calls.len() as u32;
+                    let called = *sent_calls > 0;
+                    let role = (!self.started.contains(&candidate.index)).then(|| {
+                        self.started.push(candidate.index);
+                        "assistant".to_string()
+                    });
+                    OpenAiChatChunkChoice {
+                        index: candidate.index,
+                        delta: OpenAiChatDelta {
+                            role,
+                            content: (!text.is_empty()).then_some(text),
+                            tool_calls,
+                        },
+                        finish_reason: candidate.finish_reason.as_deref().map(|reason| {
+                            if called { "tool_calls".to_string() } else { map_finish_reason_to_openai(reason) }
+                        }),
+                    }
+                })
+                .collect();
+            if !choices.is_empty() {
+                out.extend(self.event(choices, None));
+            }
+        }
+        out
+    }
+
+    /// The usage chunk and `[DONE]`, once the Gemini stream has ended.
+    fn finish(&self) -> Vec<u8> {
+        let usage = OpenAiUsage {
+            prompt_tokens: self.usage.prompt_token_count,
+            completion_tokens: self.usage.candidates_token_count,
+            total_tokens: self.usage.total_token_count,
+        };
+        let mut out = self.event(Vec::new(), Some(usage));
+        out.extend_from_slice(b"data: [DONE]\n\n");
+        out
+    }
+
+    fn event(&self, choices: Vec<OpenAiChatChunkChoice>, usage: Option<OpenAiUsage>) -> Vec<u8> {
+        let chunk = OpenAiChatCompletionChunk {
+            id: self.id.clone(),
+            object: "chat.completion.chunk".to_string(),
+            created: self.created,
+            model: self.model.clone(),
+            choices,
+            usage,
+        };
+        match serde_json::to_string(&chunk) {
+            Ok(json) => format!("data: {}\n\n", json).into_bytes(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+/// Translates a native Gemini chat stream into an OpenAI-compatible one, see
+/// `ChatStreamTranslator`.
+pub fn translate_chat_stream(
+    events: impl Stream<Item = Result<Vec<u8>>>,
+    model_name: &str,
+) -> impl Stream<Item = Result<Vec<u8>>> {
+    let translator = Rc::new(RefCell::new(ChatStreamTranslator::new(model_name)));
+    let finisher = translator.clone();
+    events
+        .map(move |chunk| chunk.map(|bytes| translator.borrow_mut().push(&bytes)))
+        .chain(stream::once(async move { Ok(finisher.borrow().finish()) }))
+}
+
+/// Maps a Gemini finish reason to the OpenAI one.
+fn map_finish_reason_to_openai(reason: &str) -> String {
+    match reason {
+        "STOP" => "stop",
+        "MAX_TOKENS" => "length",
+        "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" => "content_filter",
+        _ => "stop",
+    }
+    .to_string()
+}
+
 /// Maps OpenAI role names to Gemini role names.
 fn map_role_to_gemini(role: String) -> String {
     match role.as_str() {
         "user" => "user".to_string(),
         "assistant" => "model".to_string(),
-        // Gemini doesn't have a direct equivalent of "system" prompt,
-        // it's often handled as the first "user" message.
-        "system" => "user".to_string(), 
+        // System prompts go to `systemInstruction` before the conversation is translated.
         _ => "user".to_string(),
     }
 }
 
+// region: --- Vertex AI Service Accounts
+
+const TOKEN_SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+const DEFAULT_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+const ASSERTION_LIFETIME_SECONDS: u64 = 3600;
+/// Tokens are refreshed this long before they expire, so none expires mid-request.
+const REFRESH_MARGIN_SECONDS: u64 = 300;
+
+/// Access tokens by key id; each entry lives until its refresh time.
+static ACCESS_TOKENS: Lazy<Cache<String, String>> = Lazy::new(|| Cache::builder().max_capacity(10_000).build());
+
+/// The fields of a service-account key file this module needs.
+#[derive(Deserialize)]
+struct ServiceAccount {
+    client_email: String,
+    private_key: String,
+    #[serde(default)]
+    private_key_id: String,
+    #[serde(default)]
+    token_uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+/// Why a service account got no access token.
+#[derive(Debug)]
+pub enum TokenError {
+    /// The account can't authenticate: its JSON or private key is unusable, or the token
+    /// endpoint refused it. The key should be benched.
+    Rejected(String),
+    /// The exchange failed otherwise, e.g. a network error, a Google 5xx or 429, or the
+    /// request was aborted. The key itself may be fine.
+    Failed(String),
+}
+
+impl std::fmt::Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenError::Rejected(message) | TokenError::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+/// Whether the token endpoint refused the account itself, rather than failing to answer.
+fn is_token_rejection(status: u16, body: &str) -> bool {
+    if status == 401 {
+        return true;
+    }
+    let error = serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|body| body.get("error").and_then(|e| e.as_str()).map(str::to_string));
+    matches!(error.as_deref(), Some("invalid_grant" | "invalid_client" | "unauthorized_client"))
+}
+
+/// Whether a key is a service-account JSON rather than a plain API key.
+pub fn is_service_account(key: &str) -> bool {
+    key.trim_start().starts_with('{')
+}
+
+/// An access token for the service account in `key`, from the cache or a fresh exchange.
+pub async fn vertex_access_token(
+    key_id: &str,
+    key: &str,
+    signal: &AbortSignal,
+) -> std::result::Result<String, TokenError> {
+    if let Some(token) = ACCESS_TOKENS.get(&key_id.to_string()) {
+        return Ok(token);
+    }
+
+    let account: ServiceAccount = serde_json::from_str(key)
+        .map_err(|e| TokenError::Rejected(format!("Invalid service-account JSON: {}", e)))?;
+    let token_uri = account.token_uri.clone().unwrap_or_else(|| DEFAULT_TOKEN_URI.to_string());
+    let assertion = signed_assertion(&account, &token_uri)
+        .await
+        .map_err(|e| TokenError::Rejected(format!("Failed to sign the assertion: {}", e)))?;
+
+    let failed = |e: worker::Error| TokenError::Failed(e.to_string());
+    let body = serde_urlencoded::to_string([
+        ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+        ("assertion", assertion.as_str()),
+    ])
+    .map_err(|e| TokenError::Failed(e.to_string()))?;
+    let headers = Headers::new();
+    headers.set("Content-Type", "application/x-www-form-urlencoded").map_err(failed)?;
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post)
+        .with_headers(headers)
+        .with_body(Some(JsValue::from_str(&body)));
+    let request = Request::new_with_init(&token_uri, &init).map_err(failed)?;
+    let mut resp = Fetch::Request(request).send_with_signal(signal).await.map_err(failed)?;
+    let status = resp.status_code();
+    if status != 200 {
+        let error = resp.text().await.unwrap_or_default();
+        let message = format!("Token exchange for {} failed with {}: {}", account.client_email, status, error);
+        return Err(if is_token_rejection(status, &error) {
+            TokenError::Rejected(message)
+        } else {
+            TokenError::Failed(message)
+        });
+    }
+    let token: TokenResponse = resp.json().await.map_err(failed)?;
+
+    let ttl = token.expires_in.saturating_sub(REFRESH_MARGIN_SECONDS);
+    if ttl > 0 {
+        ACCESS_TOKENS.insert_with_ttl(key_id.to_string(), token.access_token.clone(), Duration::from_secs(ttl));
+    }
+    Ok(token.access_token)
+}
+
+/// The RS256-signed JWT asserting the service account's identity to `token_uri`.
+async fn signed_assertion(account: &ServiceAccount, token_uri: &str) -> Result<String> {
+    let now = Date::now().as_millis() / 1000;
+    let header = serde_json::json!({ "alg": "RS256", "typ": "JWT", "kid": account.private_key_id });
+    let claims = serde_json::json!({
+        "iss": account.client_email,
+        "scope": TOKEN_SCOPE,
+        "aud": token_uri,
+        "iat": now,
+        "exp": now + ASSERTION_LIFETIME_SECONDS,
+    });
+    let signing_input = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(header.to_string()),
+        URL_SAFE_NO_PAD.encode(claims.to_string())
+    );
+    let signature = sign_rs256(&pem_to_der(&account.private_key)?, signing_input.as_bytes()).await?;
+    Ok(format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature)))
+}
+
+/// The DER bytes of a PEM `PRIVATE KEY` (PKCS#8), as found in service-account files.
+fn pem_to_der(pem: &str) -> Result<Vec<u8>> {
+    let base64: String = pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .flat_map(|line| line.trim().chars())
+        .collect();
+    STANDARD
+        .decode(base64)
+        .map_err(|e| worker::Error::RustError(format!("Invalid service-account private key: {}", e)))
+}
+
+/// Signs `data` with RSASSA-PKCS1-v1_5 and SHA-256 through the runtime's WebCrypto.
+async fn sign_rs256(pkcs8_der: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let crypto = Reflect::get(&js_sys::global(), &JsValue::from_str("crypto"))?;
+    let subtle = Reflect::get(&crypto, &JsValue::from_str("subtle"))?;
+    let algorithm = Object::new();
+    Reflect::set(&algorithm, &JsValue::from_str("name"), &JsValue::from_str("RSASSA-PKCS1-v1_5"))?;
+    Reflect::set(&algorithm, &JsValue::from_str("hash"), &JsValue::from_str("SHA-256"))?;
+
+    let import_key: Function = Reflect::get(&subtle, &JsValue::from_str("importKey"))?.dyn_into()?;
+    let import_args = Array::of5(
+        &JsValue::from_str("pkcs8"),
+        &Uint8Array::from(pkcs8_der),
+        &algorithm,
+        &JsValue::FALSE,
+        &Array::of1(&JsValue::from_str("sign")),
+    );
+    let promise: Promise = import_key.apply(&subtle, &import_args)?.dyn_into()?;
+    let crypto_key = JsFuture::from(promise).await?;
+
+    let sign: Function = Reflect::get(&subtle, &JsValue::from_str("sign"))?.dyn_into()?;
+    let promise: Promise = sign.call3(&subtle, &algorithm, &crypto_key, &Uint8Array::from(data))?.dyn_into()?;
+    let signature = JsFuture::from(promise).await?;
+    Ok(Uint8Array::new(&signature).to_vec())
+}
+
+// endregion: --- Vertex AI Service Accounts
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_refusals_of_the_account_are_rejections() {
+        assert!(is_token_rejection(400, r#"{"error":"invalid_grant","error_description":"Invalid JWT Signature."}"#));
+        assert!(is_token_rejection(401, r#"{"error":"invalid_client"}"#));
+        assert!(is_token_rejection(401, ""));
+        assert!(!is_token_rejection(429, r#"{"error":"rate_limit_exceeded"}"#));
+        assert!(!is_token_rejection(503, "Service Unavailable"));
+        assert!(!is_token_rejection(400, r#"{"error":"invalid_request"}"#));
+    }
+}
@@ -1,14 +1,24 @@
 //! This module contains the primary request handlers for the worker.
 
 use crate::{
-    d1_storage,
+    balancer, compat, d1_storage::{self, KeyEventRecord}, embeddings,
+    usage::{self, UsageRecord},
     error_handling::{self, AxumWorkerError, AxumWorkerResponse, ErrorAnalysis},
-    gcp, models::*,
+    key_events::{self, KeyHealthEvent, KeyTransition, ProviderHealthEvent},
+    request_id::RequestId,
+    request_validation::{self, RequestError},
+    analytics, chaos::{self, ChaosRule}, gcp, inflight, metrics::{self, RequestOutcome}, models::*, response_headers::HeaderPolicy, workers_ai,
+    sampling::{self, SampleRecord},
+    sse::{self, EventKind, EventScanner},
+    tokens,
     state::strategy::*,
-    util, AppState,
+    settings::Settings,
+    payload_log, provider_breaker::{self, Admission}, provider_health, rate_budget,
+    upstream::{self, Upstream},
+    util, validation, AppState,
 };
 #[cfg(feature = "use_queue")]
-use crate::queue::StateUpdate;
+use crate::queue::{self, StateUpdate};
 use std::sync::Arc;
 use std::time::Duration;
 use axum::{
@@ -17,10 +27,10 @@ use axum::{
     response::IntoResponse,
 };
 use futures_util::future::{select, Either};
-use futures_util::FutureExt;
+use futures_util::{FutureExt, StreamExt};
 use phf::phf_map;
 use tracing::{error, info, instrument, span, warn, Level};
-use worker::{AbortSignal, Date, Delay, Env, Response, Result};
+use worker::{AbortController, AbortSignal, Date, Delay, Env, Response, Result};
 
 static PROVIDER_CUSTOM_AUTH_HEADER: phf::Map<&'static str, &'static str> = phf_map! {
     "google-ai-studio" => "x-goog-api-key",
@@ -36,19 +46,89 @@ fn create_openai_error_response(
     error_type: &str,
     code: &str,
     status_code: u16,
+) -> AxumWorkerResponse {
+    create_retryable_error_response(message, error_type, code, status_code, None)
+}
+
+/// A 400 for a request body that failed `request_validation`, naming the offending field.
+fn create_invalid_request_response(error: RequestError) -> AxumWorkerResponse {
+    let error_response = OpenAiErrorResponse {
+        error: OpenAiError {
+            message: error.message,
+            error_type: "invalid_request_error".to_string(),
+            param: error.param,
+            code: Some("invalid_request".to_string()),
+            retry_after: None,
+        },
+    };
+    AxumWorkerResponse(Response::from_json(&error_response).unwrap().with_status(400))
+}
+
+/// Reads a request body, or returns `None` once it grows past `max_bytes` (0 means
+/// unlimited) without buffering the rest.
+async fn read_body(body: axum::body::Body, max_bytes: u64) -> Result<Option<Bytes>> {
+    let mut stream = body.into_data_stream();
+    let mut buffer = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| worker::Error::from(e.to_string()))?;
+        if max_bytes > 0 && (buffer.len() + chunk.len()) as u64 > max_bytes {
+            return Ok(None);
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+    Ok(Some(Bytes::from(buffer)))
+}
+
+/// The 413 for a body over `MAX_BODY_BYTES`.
+fn create_body_too_large_response(max_bytes: u64) -> AxumWorkerResponse {
+    create_openai_error_response(
+        &format!("The request body exceeds the limit of {} bytes.", max_bytes),
+        "invalid_request_error",
+        "request_too_large",
+        413,
+    )
+}
+
+/// Like `create_openai_error_response`, but tells the client when to retry, both as a
+/// `retry_after` field and a `Retry-After` header.
+fn create_retryable_error_response(
+    message: &str,
+    error_type: &str,
+    code: &str,
+    status_code: u16,
+    retry_after: Option<u64>,
 ) -> AxumWorkerResponse {
     let error = OpenAiError {
         message: message.to_string(),
         error_type: error_type.to_string(),
         param: None,
         code: Some(code.to_string()),
+        retry_after,
     };
     let error_response = OpenAiErrorResponse { error };
-    AxumWorkerResponse(
-        Response::from_json(&error_response)
-            .unwrap()
-            .with_status(status_code),
-    )
+    let mut resp = Response::from_json(&error_response)
+        .unwrap()
+        .with_status(status_code);
+    if let Some(seconds) = retry_after {
+        let _ = resp.headers_mut().set("Retry-After", &seconds.to_string());
+    }
+    AxumWorkerResponse(resp)
+}
+
+/// Seconds until the earliest key of the pool comes off cooldown, whether the cooldown
+/// is key-wide or for this model. `None` when no key is cooling down.
+fn retry_after_seconds(settings: &Settings, provider: &str, model: &str, keys: &[ApiKey]) -> Option<u64> {
+    let now_ms = Date::now().as_millis();
+    let model_expiries_ms = keys
+        .iter()
+        .filter_map(|key| key.get_cooldown_end(model))
+        .map(|end_seconds| end_seconds * 1000);
+    d1_storage::earliest_cooldown_expiry_ms(settings, provider)
+        .into_iter()
+        .chain(model_expiries_ms)
+        .filter(|expiry_ms| *expiry_ms > now_ms)
+        .min()
+        .map(|expiry_ms| (expiry_ms - now_ms).div_ceil(1000))
 }
 
 // A helper to get the Durable Object stub for the API Key Manager.
@@ -62,18 +142,81 @@ enum RequestResult {
     },
 }
 
-#[instrument(skip_all, level = "warn", fields(provider, key_id, retry_attempt = tracing::field::Empty))]
+impl RequestResult {
+    /// The failure reported when an attempt runs past its time budget.
+    fn timed_out(timeout_ms: u64) -> Self {
+        RequestResult::Failure {
+            analysis: ErrorAnalysis::RequestTimeout,
+            body_text: format!("Provider request timed out after {}ms", timeout_ms),
+            status: 504,
+        }
+    }
+}
+
+/// Records how an attempt ended on its span and logs it, so the tracing output shows each
+/// upstream call and failover decision in order.
+fn record_attempt(span: &tracing::Span, status: u16, error_class: Option<&str>, latency_ms: u64) {
+    span.record("status", status);
+    if let Some(error_class) = error_class {
+        span.record("error_class", error_class);
+    }
+    span.record("latency_ms", latency_ms);
+    info!(status, error_class, latency_ms, "Attempt finished");
+}
+
+/// Whether a fetch error means the upstream call was cut short rather than refused.
+fn is_timeout_error(e: &worker::Error) -> bool {
+    let message = e.to_string().to_lowercase();
+    message.contains("abort") || message.contains("timed out") || message.contains("timeout")
+}
+
+/// A controller for one upstream call. Its signal also fires when the whole request is
+/// aborted, so a call can be cancelled on its own timeout without cancelling the request.
+fn attempt_abort_controller(request_signal: &AbortSignal) -> (AbortController, AbortSignal) {
+    let controller = AbortController::default();
+    let request_signal: &worker::web_sys::AbortSignal = request_signal;
+    let attempt_signal: &worker::web_sys::AbortSignal = &controller.signal();
+    let signals = js_sys::Array::of2(request_signal.as_ref(), attempt_signal.as_ref());
+    let signal = AbortSignal::from(worker::web_sys::AbortSignal::any(&signals));
+    (controller, signal)
+}
+
+/// Sends the request to the provider with the given key, retrying transient failures.
+/// `timeout_ms` bounds the whole attempt for this key, retries and reading an error
+/// body included, so a hanging upstream can't stall the failover loop. A chaos rule, if
+/// any, may delay each call or answer it with an injected error instead.
+#[instrument(skip_all, level = "warn", fields(provider, key_id))]
 async fn execute_request_with_retry(
     req: worker::Request,
     provider: &str,
     key_id: &str,
     max_attempts: u32,
     timeout_ms: u64,
-    signal: &AbortSignal,
+    upstream: &Upstream,
+    chaos: Option<&ChaosRule>,
 ) -> Result<RequestResult> {
+    let signal = upstream.signal();
     let mut retry_attempt = 0;
+    let deadline_ms = Date::now().as_millis() + timeout_ms;
     loop {
-        tracing::Span::current().record("retry_attempt", retry_attempt);
+        let remaining_ms = deadline_ms.saturating_sub(Date::now().as_millis());
+        if remaining_ms == 0 {
+            warn!("Attempt budget of {}ms used up before retry for key_id: {}", timeout_ms, key_id);
+            return Ok(RequestResult::timed_out(timeout_ms));
+        }
+
+        // Each call to the provider, retries included, gets its own span.
+        let attempt_span = span!(
+            Level::WARN,
+            "upstream_attempt",
+            attempt = retry_attempt,
+            status = tracing::field::Empty,
+            error_class = tracing::field::Empty,
+            latency_ms = tracing::field::Empty
+        );
+        let _attempt_enter = attempt_span.enter();
+        let attempt_start_ms = Date::now().as_millis();
+        let attempt_latency = || Date::now().as_millis() - attempt_start_ms;
 
         let req_clone = req.clone()?;
         
@@ -89,9 +232,22 @@ async fn execute_request_with_retry(
 
         info!(url = %req_clone.url()?, "Attempting to send request to provider");
 
-                let fetch = worker::Fetch::Request(req_clone);
-        let fetch_future = fetch.send_with_signal(signal);
-        let timeout_future = Delay::from(Duration::from_millis(timeout_ms));
+        let (attempt_controller, attempt_signal) = attempt_abort_controller(signal);
+        let fault = chaos.map(ChaosRule::roll).unwrap_or_default();
+        let fetch_future = async move {
+            if fault.delay_ms > 0 {
+                warn!("Chaos mode: delaying the call for key_id {} by {}ms", key_id, fault.delay_ms);
+                Delay::from(Duration::from_millis(fault.delay_ms)).await;
+            }
+            match fault.error_status {
+                Some(status) => {
+                    warn!("Chaos mode: injecting a {} for key_id {}", status, key_id);
+                    chaos::error_response(status)
+                }
+                None => upstream.send(req_clone, &attempt_signal).await,
+            }
+        };
+        let timeout_future = Delay::from(Duration::from_millis(remaining_ms));
 
         let result = select(fetch_future.boxed_local(), timeout_future.boxed_local()).await;
 
@@ -103,20 +259,17 @@ async fn execute_request_with_retry(
                     timeout_ms, key_id
                 );
 
-                // 1. Abort the local fetch: In Rust's async model, when the `select`
-                //    completes, the future that didn't finish is dropped. Dropping
-                //    the `fetch_future` automatically cancels the underlying request.
-                //    So, no explicit `abort()` call is needed here.
+                // 1. Abort the upstream fetch: dropping `fetch_future` only stops us
+                //    polling it, the runtime keeps the request going until its signal
+                //    fires.
+                attempt_controller.abort();
 
                 // 2. Return a RequestResult::Failure with a specific timeout error:
                 //    We create a `Failure` variant with our new `RequestTimeout` analysis
                 //    and return it immediately. The failover loop in the `forward` function
                 //    will catch this and move to the next key.
-                return Ok(RequestResult::Failure {
-                    analysis: ErrorAnalysis::RequestTimeout,
-                    body_text: format!("Provider request timed out after {}ms", timeout_ms),
-                    status: 504,
-                });
+                record_attempt(&attempt_span, 504, Some(ErrorAnalysis::RequestTimeout.class()), attempt_latency());
+                return Ok(RequestResult::timed_out(timeout_ms));
             }
         };
 
@@ -124,11 +277,25 @@ async fn execute_request_with_retry(
             Ok(mut resp) => {
                 let status = resp.status_code();
                 if status == 200 {
+                    record_attempt(&attempt_span, status, None, attempt_latency());
                     return Ok(RequestResult::Success(resp));
                 }
 
-                let error_body_text = resp.text().await?;
+                // Error bodies are read within the same budget; a provider can stall mid-body too.
+                let remaining_ms = deadline_ms.saturating_sub(Date::now().as_millis());
+                let body_timeout = Delay::from(Duration::from_millis(remaining_ms));
+                let content_encoding = resp.headers().get("Content-Encoding")?;
+                let error_body_text = match select(resp.bytes().boxed_local(), body_timeout.boxed_local()).await {
+                    Either::Left((bytes, _)) => error_handling::decode_error_body(&bytes?, content_encoding.as_deref()),
+                    Either::Right((_, _)) => {
+                        warn!("Reading the error body timed out for key_id: {}", key_id);
+                        attempt_controller.abort();
+                        record_attempt(&attempt_span, 504, Some(ErrorAnalysis::RequestTimeout.class()), attempt_latency());
+                        return Ok(RequestResult::timed_out(timeout_ms));
+                    }
+                };
                 let analysis = error_handling::analyze_provider_error(provider, status, &error_body_text).await;
+                record_attempt(&attempt_span, status, Some(analysis.class()), attempt_latency());
 
                 // --- Refactored Error Handling Logic ---
 
@@ -165,6 +332,13 @@ async fn execute_request_with_retry(
                 }
             }
             Err(e) => {
+                // An aborted or timed-out fetch won't go better on the same key; fail over now.
+                if signal.aborted() || is_timeout_error(&e) {
+                    warn!(error = %e, "Request was aborted or timed out upstream.");
+                    record_attempt(&attempt_span, 504, Some(ErrorAnalysis::RequestTimeout.class()), attempt_latency());
+                    return Ok(RequestResult::timed_out(timeout_ms));
+                }
+                record_attempt(&attempt_span, 504, Some("network_error"), attempt_latency());
                 if retry_attempt + 1 < max_attempts {
                     warn!(error = %e, "Request failed with network error, retrying...");
                 } else {
@@ -200,7 +374,8 @@ pub async fn get_active_keys(provider: &str, env: &Env) -> Result<Vec<ApiKey>> {
     #[cfg(feature = "raw_d1")]
     {
         let db = env.d1("DB")?;
-        Ok(crate::d1_storage::get_healthy_sorted_keys_via_cache(env, &db, provider).await.map_err(|e| worker::Error::from(e))?)
+        let settings = crate::settings::Settings::from_env(env);
+        Ok(crate::d1_storage::get_healthy_sorted_keys_via_cache(&settings, &db, provider).await.map_err(|e| worker::Error::from(e))?)
     }
     #[cfg(not(feature = "raw_d1"))]
     {
@@ -230,7 +405,8 @@ fn set_auth_header(headers: &mut worker::Headers, provider: &str, key: &str) ->
     headers.set(header_name, &header_value)
 }
 
-/// Constructs the final request to be sent to the AI Gateway.
+/// Constructs the final request to be sent to the AI Gateway, or to the provider's
+/// service binding when it has one.
 async fn make_gateway_request(
     method: axum::http::Method,
     headers: &axum::http::HeaderMap,
@@ -260,6 +436,16 @@ async fn make_gateway_request(
     // Add our custom request ID for tracking.
     new_headers.set("X-OneBalance-Request-ID", request_id)?;
 
+    // A provider served by a service binding gets the gateway's path without its prefix.
+    if upstream::binding_name(env, provider).is_some() {
+        let mut req_init = worker::RequestInit::new();
+        req_init
+            .with_method(worker::Method::from(method.to_string()))
+            .with_headers(new_headers)
+            .with_body(body.map(|b| js_sys::Uint8Array::from(b.as_ref()).into()));
+        return worker::Request::new_with_init(&format!("{}{}", upstream::SERVICE_BASE_URL, rest_resource), &req_init);
+    }
+
     // Add the AI Gateway token if it's configured.
     if let Ok(token) = env.secret("AI_GATEWAY_TOKEN") {
         new_headers.set(
@@ -298,9 +484,298 @@ async fn make_gateway_request(
     worker::Request::new_with_init(&url, &req_init)
 }
 
+/// Whether a client header is passed on to a custom provider. Custom providers are
+/// operator-defined URLs, so only the content headers and the OpenAI/Anthropic option
+/// headers go there; the caller's credentials (`Authorization`, `x-api-key`, the session
+/// cookie, ...) never do.
+fn forwarded_to_custom_provider(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    matches!(name.as_str(), "content-type" | "accept")
+        || name.starts_with("openai-")
+        || name.starts_with("anthropic-")
+}
+
+/// Constructs the request to a custom provider, sent to its endpoint directly rather than
+/// through the AI Gateway. `path` is the request path after the provider (or after
+/// `compat/`), and a `provider/model` name in the body is rewritten to the bare model.
+fn make_custom_provider_request(
+    method: axum::http::Method,
+    headers: &axum::http::HeaderMap,
+    body: Option<Bytes>,
+    custom: &CustomProvider,
+    path: &str,
+    key: &str,
+    request_id: &str,
+) -> Result<worker::Request> {
+    let new_headers = worker::Headers::new();
+    for (k, v) in headers.iter().filter(|(k, _)| forwarded_to_custom_provider(k.as_str())) {
+        if let Ok(v_str) = v.to_str() {
+            new_headers.set(k.as_str(), v_str)?;
+        }
+    }
+    new_headers.set(&custom.auth_header, &custom.auth_value(key))?;
+    new_headers.set("X-OneBalance-Request-ID", request_id)?;
+
+    let prefix = format!("{}/", custom.name);
+    let body = match body {
+        Some(bytes) => match serde_json::from_slice::<serde_json::Value>(&bytes) {
+            Ok(mut json) if json.get("model").and_then(|m| m.as_str()).is_some_and(|m| m.starts_with(&prefix)) => {
+                let model = json["model"].as_str().unwrap_or_default()[prefix.len()..].to_string();
+                json["model"] = serde_json::Value::String(model);
+                // The body changed size, so the original Content-Length no longer applies.
+                new_headers.delete("Content-Length")?;
+                Some(Bytes::from(serde_json::to_vec(&json)?))
+            }
+            _ => Some(bytes),
+        },
+        None => None,
+    };
+
+    let mut req_init = worker::RequestInit::new();
+    req_init
+        .with_method(worker::Method::from(method.to_string()))
+        .with_headers(new_headers)
+        .with_body(body.map(|b| js_sys::Uint8Array::from(b.as_ref()).into()));
+    worker::Request::new_with_init(&custom.url(path), &req_init)
+}
+
+/// Reads a streamed response up to its first tokens. An error event before that is
+/// returned as a failure so the caller fails over to another key. Otherwise the response
+/// is rebuilt from the buffered events and the rest of the stream, which is still watched
+/// for errors: a failure after tokens were sent can't be retried, but it is recorded
+/// against the key.
+async fn check_stream_start(
+    state: &Arc<AppState>,
+    mut resp: Response,
+    provider: &str,
+    key_id: &str,
+    latency: i64,
+    timeout_ms: u64,
+) -> Result<RequestResult> {
+    let status = resp.status_code();
+    let headers = resp.headers().clone();
+    let mut stream = resp.stream()?;
+    let mut scanner = EventScanner::default();
+    let mut buffered: Vec<u8> = Vec::new();
+    let deadline_ms = Date::now().as_millis() + timeout_ms;
+
+    'peek: loop {
+        let remaining_ms = deadline_ms.saturating_sub(Date::now().as_millis());
+        let timeout = Delay::from(Duration::from_millis(remaining_ms));
+        let chunk = match select(stream.next(), timeout.boxed_local()).await {
+            Either::Left((Some(chunk), _)) => chunk?,
+            Either::Left((None, _)) => break,
+            Either::Right((_, _)) => {
+                warn!("Stream produced no tokens within {}ms for key_id: {}", timeout_ms, key_id);
+                return Ok(RequestResult::timed_out(timeout_ms));
+            }
+        };
+        buffered.extend_from_slice(&chunk);
+        for event in scanner.push(&chunk) {
+            match sse::classify(&event) {
+                EventKind::Error { status, body } => {
+                    warn!(status, error_body = %body, "Stream failed before its first token. Failing over.");
+                    let analysis = error_handling::analyze_provider_error(provider, status, &body).await;
+                    return Ok(RequestResult::Failure {
+                        analysis,
+                        body_text: body,
+                        status,
+                    });
+                }
+                EventKind::Content => break 'peek,
+                EventKind::Other => {}
+            }
+        }
+        if buffered.len() > sse::MAX_PEEK_BYTES {
+            break;
+        }
+    }
+
+    let state = state.clone();
+    let provider = provider.to_string();
+    let key_id = key_id.to_string();
+    let rest = stream.map(move |chunk| {
+        if let Ok(bytes) = &chunk {
+            for event in scanner.push(bytes) {
+                if let EventKind::Error { status, body } = sse::classify(&event) {
+                    warn!(key_id = %key_id, status, error_body = %body, "Stream failed after tokens were sent.");
+                    record_stream_failure(&state, &provider, &key_id, status, body, latency);
+                }
+            }
+        }
+        chunk
+    });
+    let body = futures_util::stream::once(async move { Ok::<Vec<u8>, worker::Error>(buffered) }).chain(rest);
+    let resp = Response::from_stream(body)?.with_status(status).with_headers(headers);
+    Ok(RequestResult::Success(resp))
+}
+
+/// Records a mid-stream failure against the key in the background.
+fn record_stream_failure(state: &Arc<AppState>, provider: &str, key_id: &str, status: u16, body: String, latency: i64) {
+    let state_clone = state.clone();
+    let provider = provider.to_string();
+    let key_id = key_id.to_string();
+    #[cfg(feature = "wait_until")]
+    state.ctx.wait_until(async move {
+        let analysis = error_handling::analyze_provider_error(&provider, status, &body).await;
+        if let Some(penalty_seconds) = analysis.penalty_seconds() {
+            d1_storage::flag_key_with_cooldown(&state_clone.settings, &provider, &key_id, analysis.class(), penalty_seconds);
+        }
+        #[cfg(not(feature = "use_queue"))]
+        if let Ok(db) = state_clone.db() {
+            if let Err(e) = d1_storage::update_key_metrics(&db, &key_id, false, latency).await {
+                error!("Failed to update key metrics after a stream failure: {}", e);
+            }
+        }
+        #[cfg(feature = "use_queue")]
+        queue::send(
+            &state_clone,
+            StateUpdate::UpdateMetrics {
+                key_id,
+                is_success: false,
+                latency,
+            },
+        )
+        .await;
+    });
+}
+
+/// Runs the configured validators over a successful JSON response. Other responses, e.g.
+/// streams or binary bodies, are passed through unchecked.
+async fn validate_success(
+    mut resp: Response,
+    request_body: &Bytes,
+    validators: &[Box<dyn validation::ResponseValidator>],
+) -> Result<RequestResult> {
+    let is_json = resp
+        .headers()
+        .get("Content-Type")?
+        .is_some_and(|ct| ct.contains("json"));
+    if !is_json {
+        return Ok(RequestResult::Success(resp));
+    }
+
+    let status = resp.status_code();
+    let headers = resp.headers().clone();
+    let body = resp.bytes().await?;
+    let request: serde_json::Value = serde_json::from_slice(request_body).unwrap_or_default();
+    let checked = serde_json::from_slice::<serde_json::Value>(&body)
+        .map_err(|e| format!("the response body is not valid JSON: {}", e))
+        .and_then(|response| validation::validate(validators, &request, &response));
+    match checked {
+        Ok(()) => Ok(RequestResult::Success(
+            Response::from_bytes(body)?.with_status(status).with_headers(headers),
+        )),
+        Err(reason) => {
+            warn!(reason = %reason, "Rejecting a malformed success response.");
+            Ok(RequestResult::Failure {
+                analysis: ErrorAnalysis::MalformedResponse,
+                body_text: reason,
+                status: 502,
+            })
+        }
+    }
+}
+
+/// The payload to log with a failed request, see `payload_log`. Uploads aren't text, so
+/// only JSON requests are logged.
+fn logged_payload(
+    state: &Arc<AppState>,
+    multipart_boundary: &Option<String>,
+    request_body: &[u8],
+    error_body: &str,
+) -> Option<payload_log::Payload> {
+    let request_body = if multipart_boundary.is_none() { request_body } else { &[] };
+    payload_log::capture(&state.settings, true, request_body, error_body)
+}
+
+/// Records the outcome of a proxied request in the background.
+fn record_request_metrics(state: &Arc<AppState>, outcome: RequestOutcome) {
+    let state_clone = state.clone();
+    #[cfg(feature = "wait_until")]
+    state.ctx.wait_until(async move {
+        record_outcome(&state_clone, &outcome).await;
+    });
+}
+
+/// Stores the failover order of a sampled request with each key's score components.
+fn record_key_scores(state: &Arc<AppState>, request_id: &str, provider: &str, model: &str, keys: &[ApiKey]) {
+    let scores = balancer::score_keys(&state.settings, keys, Date::now().as_millis() / 1000);
+    let ranking: Vec<(String, KeyScore)> = keys.iter().map(|key| key.id.clone()).zip(scores).collect();
+    let (request_id, provider, model) = (request_id.to_string(), provider.to_string(), model.to_string());
+    let state_clone = state.clone();
+    #[cfg(feature = "wait_until")]
+    state.ctx.wait_until(async move {
+        if let Ok(db) = state_clone.db() {
+            if let Err(e) = d1_storage::record_key_scores(&db, &request_id, &provider, &model, &ranking).await {
+                error!("Failed to record key scores: {}", e);
+            }
+        }
+    });
+}
+
+/// Reports a provider that became degraded or recovered, and counts the former.
+fn record_provider_transition(state: &Arc<AppState>, provider: &str, transition: provider_health::Transition) {
+    let state_clone = state.clone();
+    let provider = provider.to_string();
+    #[cfg(feature = "wait_until")]
+    state.ctx.wait_until(async move {
+        if let Some(anomaly) = transition.anomaly {
+            if let Ok(db) = state_clone.db() {
+                let deltas = [metrics::provider_degraded(&provider, anomaly.as_str())];
+                if let Err(e) = d1_storage::increment_metrics(&db, &deltas).await {
+                    error!("Failed to record provider anomaly metrics: {}", e);
+                }
+            }
+        }
+        key_events::emit_provider(&state_clone.env, ProviderHealthEvent::new(&provider, &transition)).await;
+    });
+}
+
+/// Logs a provider circuit breaker that opened or closed, and counts the former.
+fn record_breaker_transition(state: &Arc<AppState>, provider: &str, transition: provider_breaker::Transition) {
+    let provider_breaker::Transition::Opened { error_rate } = transition else {
+        info!(provider = provider, "Provider probe succeeded; circuit closed.");
+        return;
+    };
+    warn!(
+        provider = provider,
+        error_rate,
+        open_seconds = state.settings.provider_breaker_open_seconds,
+        "Provider circuit opened."
+    );
+    let state_clone = state.clone();
+    let provider = provider.to_string();
+    #[cfg(feature = "wait_until")]
+    state.ctx.wait_until(async move {
+        if let Ok(db) = state_clone.db() {
+            let deltas = [metrics::provider_circuit_opened(&provider)];
+            if let Err(e) = d1_storage::increment_metrics(&db, &deltas).await {
+                error!("Failed to record provider circuit metrics: {}", e);
+            }
+        }
+    });
+}
+
+/// Writes the outcome to Analytics Engine (when bound), the metrics table and the
+/// request events behind the dashboard.
+async fn record_outcome(state: &AppState, outcome: &RequestOutcome) {
+    analytics::write_request(&state.env, outcome);
+    if let Ok(db) = state.db() {
+        let known_models = d1_storage::get_catalog_models_via_cache(&db, &outcome.provider)
+            .await
+            .unwrap_or_default();
+        if let Err(e) = d1_storage::increment_metrics(&db, &outcome.deltas(&known_models)).await {
+            error!("Failed to record request metrics: {}", e);
+        }
+        if let Err(e) = d1_storage::record_request_event(&db, outcome).await {
+            error!("Failed to record request event: {}", e);
+        }
+    }
+}
 
 /// The new unified forwarding function that contains the full routing logic.
-#[instrument(skip_all, level = "warn", fields(request_id = %uuid::Uuid::new_v4()))]
+#[instrument(skip_all, level = "warn")]
 #[worker::send]
 pub async fn forward(
     State(state): State<Arc<AppState>>,
@@ -314,7 +789,7 @@ pub async fn forward(
         let rest_resource = path;
 
         let main_auth_key = util::get_auth_key_from_axum_header(&req)?;
-        if !util::is_valid_auth_key(&main_auth_key, env) {
+        let Some(caller) = util::authenticate(&main_auth_key, &state).await else {
             return Ok(create_openai_error_response(
                 "Invalid authentication credentials.",
                 "invalid_request_error",
@@ -322,71 +797,396 @@ pub async fn forward(
                 401,
             )
             .into_response());
-        }
+        };
 
         let (parts, body) = req.into_parts();
         let method = parts.method;
         let headers = parts.headers;
+        let request_id = parts
+            .extensions
+            .get::<RequestId>()
+            .map(|id| id.0.clone())
+            .unwrap_or_default();
+
+        let Some(body_bytes) = read_body(body, state.settings.max_body_bytes).await? else {
+            warn!("Request body exceeds MAX_BODY_BYTES.");
+            return Ok(create_body_too_large_response(state.settings.max_body_bytes).into_response());
+        };
 
-        let body_bytes: Bytes = axum::body::to_bytes(body, usize::MAX)
-            .await
-            .map_err(|e| worker::Error::from(e.to_string()))?;
-
-        let (provider, model_name) =
-            util::extract_provider_and_model(&body_bytes, &rest_resource)?;
+        // Multipart routes (e.g. audio transcriptions) carry the model as a form field.
+        let multipart_boundary = headers
+            .get(axum::http::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(util::multipart_boundary);
+        if multipart_boundary.is_none() {
+            if let Err(e) = request_validation::validate_compat(&rest_resource, &body_bytes) {
+                warn!(param = ?e.param, "Rejected invalid request body: {}", e.message);
+                return Ok(create_invalid_request_response(e).into_response());
+            }
+        }
+        let (provider, model_name) = match &multipart_boundary {
+            Some(boundary) => util::extract_provider_and_model_from_multipart(&body_bytes, boundary)?,
+            None => util::extract_provider_and_model(&body_bytes, &rest_resource)?,
+        };
         info!(provider = provider, model = model_name, "Extracted provider and model");
 
-        #[cfg(feature = "use_queue")]
-        let queue = env.queue("STATE_UPDATER")?;
+        if !caller.allows(&provider, &model_name) {
+            warn!(client_id = ?caller.client_id(), provider = provider, model = model_name, "Client key is not scoped for this provider/model.");
+            return Ok(create_openai_error_response(
+                &format!("This API key is not allowed to access {}/{}.", provider, model_name),
+                "invalid_request_error",
+                "model_not_allowed",
+                403,
+            )
+            .into_response());
+        }
 
-        // --- 2. Get and Sort Active Keys by Health ---
-        let sorted_keys = match d1_storage::get_healthy_sorted_keys_via_cache(
-            env,
-            &env.d1("DB")?,
-            &provider,
-        )
-        .await
-        {
-            Ok(keys) if !keys.is_empty() => keys,
-            _ => {
-                error!(provider = provider, "No active keys available for provider.");
-                return Ok(create_openai_error_response(
-                    "No active keys available for this provider.",
+        // --- Soft-launched providers are probed by the scheduled tester but get no live traffic ---
+        let provider_settings = d1_storage::get_provider_settings_via_cache(&state.db()?, &provider)
+            .await
+            .map_err(worker::Error::from)?;
+        if provider_settings.observe_only {
+            warn!(provider = provider, "Provider is in observe-only mode; not routing live traffic.");
+            return Ok(create_openai_error_response(
+                &format!("Provider '{}' is in observe-only mode and not accepting traffic yet.", provider),
+                "server_error",
+                "provider_observe_only",
+                503,
+            )
+            .into_response());
+        }
+
+        // --- A provider failing as a whole is answered right away instead of burning every key ---
+        let probing = match provider_breaker::admit(&state.settings, &provider) {
+            Admission::Allowed => false,
+            Admission::Probe => {
+                info!(provider = provider, "Provider circuit is half-open; probing it with this request.");
+                true
+            }
+            Admission::Rejected { retry_after_seconds } => {
+                warn!(provider = provider, "Provider circuit is open; rejecting the request.");
+                record_request_metrics(&state, RequestOutcome {
+                    provider: provider.clone(),
+                    model: model_name.clone(),
+                    status: 503,
+                    error_class: "provider_circuit_open".to_string(),
+                    ..Default::default()
+                });
+                return Ok(create_retryable_error_response(
+                    &format!("Provider '{}' is failing; requests are paused while it recovers.", provider),
                     "server_error",
-                    "no_keys_available",
+                    "provider_circuit_open",
                     503,
+                    Some(retry_after_seconds),
                 )
                 .into_response());
             }
         };
 
-        let overall_timeout_ms: u64 = match env.var("OVERALL_TIMEOUT_MS") {
-            Ok(v) => v.to_string().parse().unwrap_or(25_000),
-            Err(_) => 25_000,
+        // --- Enforce client quotas before spending a provider key on the request ---
+        if let util::Caller::Client(client) = &caller {
+            if !client.quota.is_unlimited() {
+                let usage = d1_storage::get_client_usage_via_cache(&state.db()?, &client.id)
+                    .await
+                    .map_err(worker::Error::from)?;
+                if let Some(violation) = client.quota.check(&usage) {
+                    warn!(client_id = client.id, violation = ?violation, "Client quota exceeded.");
+                    return Ok(create_openai_error_response(
+                        violation.message(),
+                        "insufficient_quota",
+                        violation.code(),
+                        violation.status(),
+                    )
+                    .into_response());
+                }
+            }
+        }
+
+        if let Some(client_id) = caller.client_id() {
+            let state_clone = state.clone();
+            let client_id = client_id.to_string();
+            #[cfg(feature = "wait_until")]
+            state.ctx.wait_until(async move {
+                if let Ok(db) = state_clone.db() {
+                    if let Err(e) = d1_storage::touch_client_key(&db, &client_id).await {
+                        error!("Failed to update client key last_used_at: {}", e);
+                    }
+                }
+            });
+        }
+
+        // Some compat routes bypass the gateway's unified compat endpoint and go straight to a
+        // provider endpoint, with our `provider/model` name rewritten to the bare model.
+        let is_image_generation = rest_resource.starts_with("compat/images/generations");
+        let rerank_request: Option<RerankRequest> = if rest_resource.starts_with("compat/rerank") {
+            match serde_json::from_slice(&body_bytes) {
+                Ok(req) => Some(req),
+                Err(e) => {
+                    return Ok(create_openai_error_response(
+                        &format!("Invalid rerank request: {}", e),
+                        "invalid_request_error",
+                        "invalid_request",
+                        400,
+                    )
+                    .into_response())
+                }
+            }
+        } else {
+            None
+        };
+        // Providers registered in the `providers` table are called directly, not through the gateway.
+        let custom_provider = match d1_storage::get_custom_provider_via_cache(&state.db()?, &provider).await {
+            Ok(custom) => custom,
+            Err(e) => {
+                warn!("Failed to look up custom provider '{}': {}", provider, e);
+                None
+            }
+        };
+        // Embeddings are translated for the provider picked by the model prefix; custom
+        // providers take the OpenAI shape as is.
+        let embeddings_provider = if rest_resource.starts_with("compat/embeddings") && custom_provider.is_none() {
+            let Some(embedder) = embeddings::provider(&provider) else {
+                return Ok(create_openai_error_response(
+                    &format!("Provider '{}' does not support embeddings.", provider),
+                    "invalid_request_error",
+                    "unsupported_provider",
+                    400,
+                )
+                .into_response());
+            };
+            Some(embedder)
+        } else {
+            None
         };
-        let target_timeout_ms: u64 = match env.var("TARGET_TIMEOUT_MS") {
-            Ok(v) => v.to_string().parse().unwrap_or(10_000),
-            Err(_) => 10_000,
+        let mut needs_rerank_resp_translation = false;
+        let compat_target = if rest_resource.starts_with("compat/audio/transcriptions") {
+            let Some(endpoint) = compat::transcription_endpoint(&provider) else {
+                return Ok(create_openai_error_response(
+                    &format!("Provider '{}' does not support audio transcription.", provider),
+                    "invalid_request_error",
+                    "unsupported_provider",
+                    400,
+                )
+                .into_response());
+            };
+            let Some(boundary) = &multipart_boundary else {
+                return Ok(create_openai_error_response(
+                    "Audio transcription requests must be multipart/form-data.",
+                    "invalid_request_error",
+                    "invalid_content_type",
+                    400,
+                )
+                .into_response());
+            };
+            // The upstream expects the bare model name, not our `provider/model` form.
+            let body = util::replace_multipart_text_field(&body_bytes, boundary, "model", &model_name);
+            Some((endpoint.clone(), Bytes::from(body)))
+        } else if is_image_generation {
+            let Some(endpoint) = compat::image_generation_endpoint(&provider) else {
+                return Ok(create_openai_error_response(
+                    &format!("Provider '{}' does not support image generation.", provider),
+                    "invalid_request_error",
+                    "unsupported_provider",
+                    400,
+                )
+                .into_response());
+            };
+            let mut image_req: serde_json::Value = serde_json::from_slice(&body_bytes)?;
+            image_req["model"] = serde_json::Value::String(model_name.clone());
+            Some((endpoint.clone(), Bytes::from(serde_json::to_vec(&image_req)?)))
+        } else if let Some(rerank_req) = &rerank_request {
+            let account_id = env
+                .secret("CLOUDFLARE_ACCOUNT_ID")
+                .map(|s| s.to_string())
+                .unwrap_or_default();
+            let Some((endpoint, style)) = compat::rerank_endpoint(&provider, &model_name, &account_id) else {
+                return Ok(create_openai_error_response(
+                    &format!("Provider '{}' does not support reranking.", provider),
+                    "invalid_request_error",
+                    "unsupported_provider",
+                    400,
+                )
+                .into_response());
+            };
+            let body = match style {
+                compat::RerankStyle::Cohere => {
+                    let mut cohere_req: serde_json::Value = serde_json::from_slice(&body_bytes)?;
+                    cohere_req["model"] = serde_json::Value::String(model_name.clone());
+                    serde_json::to_vec(&cohere_req)?
+                }
+                compat::RerankStyle::WorkersAi => {
+                    needs_rerank_resp_translation = true;
+                    serde_json::to_vec(&workers_ai::translate_rerank_request(rerank_req))?
+                }
+            };
+            Some((endpoint, Bytes::from(body)))
+        } else if let Some(embedder) = embeddings_provider {
+            let body = embedder.translate_request(&body_bytes, &model_name)?;
+            Some((embedder.endpoint(&model_name), Bytes::from(body)))
+        } else {
+            None
         };
+
+        // GET/DELETE and friends usually carry no body, and fetch rejects a body on GET/HEAD.
+        let passthrough_body = (!body_bytes.is_empty()).then(|| body_bytes.clone());
+
+        let target_timeout_ms = state.settings.target_timeout_ms;
+        // How long a request may be held when every key is on a short cooldown. 0 disables waiting.
+        let max_cooldown_wait_ms = state.settings.cooldown_wait_max_ms;
         let request_start_time = Date::now();
 
+        // Validators applied to successful JSON responses before they are accepted.
+        let response_validators = validation::validators_for(env, &model_name);
+
+        // Upstream calls go to the AI Gateway unless the provider is served by a service binding.
+        let upstream = if state.settings.is_local || custom_provider.is_some() {
+            Upstream::public(&state.signal)
+        } else {
+            Upstream::for_provider(env, &provider, &state.signal)?
+        };
+
+        // Track the request in the in-flight registry, so an admin can find and cancel it.
+        // The registration lives in the state, and so as long as a streamed response.
+        match inflight::Registration::open(env, &request_id, &provider, &model_name, (*state.controller).clone()).await {
+            Ok(Some(registration)) => {
+                let _ = state.inflight.set(registration);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to register the request in the in-flight registry: {}", e),
+        }
+
+        // Faults injected into upstream calls in dev and staging, if chaos mode is configured.
+        let chaos_rule = chaos::rule_for(env, &provider);
+
+        // --- 2. Get and Sort Active Keys by Health ---
+        let mut cooldown_waited_ms: u64 = 0;
+        let sorted_keys = loop {
+            if let Ok(keys) = d1_storage::get_healthy_sorted_keys_via_cache(
+                &state.settings,
+                &state.db()?,
+                &provider,
+            )
+            .await
+            {
+                if !keys.is_empty() {
+                    break keys;
+                }
+            }
+
+            // Every key is cooling down. Rather than failing straight away, optionally hold the
+            // request until the earliest cooldown expires, bounded by the wait limit and the
+            // time left before the overall deadline.
+            let now_ms = Date::now().as_millis();
+            let remaining_ms = state.remaining_ms();
+            let wait_budget_ms = max_cooldown_wait_ms
+                .saturating_sub(cooldown_waited_ms)
+                .min(remaining_ms.saturating_sub(1_000));
+            match d1_storage::earliest_cooldown_expiry_ms(&state.settings, &provider) {
+                Some(expiry_ms) if expiry_ms.saturating_sub(now_ms) < wait_budget_ms => {
+                    // A little slack so the cache entry has expired by the time we look again.
+                    let wait_ms = expiry_ms.saturating_sub(now_ms) + 50;
+                    info!(provider = provider, wait_ms, "All keys are cooling down. Waiting for the earliest to recover.");
+                    Delay::from(Duration::from_millis(wait_ms)).await;
+                    cooldown_waited_ms += wait_ms;
+                }
+                _ => {
+                    error!(provider = provider, "No active keys available for provider.");
+                    record_request_metrics(&state, RequestOutcome {
+                        provider: provider.clone(),
+                        model: model_name.clone(),
+                        status: 503,
+                        latency_ms: Date::now().as_millis() - request_start_time.as_millis(),
+                        error_class: "no_keys_available".to_string(),
+                        ..Default::default()
+                    });
+                    return Ok(create_retryable_error_response(
+                        "No active keys available for this provider.",
+                        "server_error",
+                        "no_keys_available",
+                        503,
+                        retry_after_seconds(&state.settings, &provider, &model_name, &[]),
+                    )
+                    .into_response());
+                }
+            }
+        };
+
+        // A probe risks a single key on a provider that may still be down.
+        let sorted_keys: Vec<ApiKey> = if probing {
+            sorted_keys.into_iter().take(1).collect()
+        } else {
+            sorted_keys
+        };
+
+        // Opt-in: the ranking and its score components, to explain why keys were (not) chosen.
+        if sampling::should_sample(state.settings.score_sample_rate_percent) {
+            record_key_scores(&state, &request_id, &provider, &model_name, &sorted_keys);
+        }
+
         // --- 3. Iterate Through Keys and Attempt Requests (Failover Loop) ---
         let mut last_error_body = "No active keys were available or all attempts failed.".to_string();
         let mut last_error_status = 503;
         let mut last_error_was_cooldown = false;
         let mut failover_attempt = 0;
+        let mut cooldown_events = 0;
+        let mut timeout_events = 0;
+        let mut last_key_hash = String::new();
+        let mut last_error_class = "no_keys_available";
+        // Set when the budget ran out before any key was tried.
+        let mut budget_exhausted = false;
+        // Set when an admin cancelled the request.
+        let mut cancelled = false;
+        // Keys skipped because they used up their per-minute budget.
+        let mut rate_budget_skips = 0;
+        // Charged against a key's token budget when the request is sent.
+        let prompt_tokens_estimate = if provider_settings.tpm_limit > 0 {
+            usage::estimate_prompt_tokens(&body_bytes)
+        } else {
+            0
+        };
 
         for selected_key in &sorted_keys {
-            let key_span = span!(Level::WARN, "key_failover", failover_attempt, key_id = %selected_key.id, key_part = %util::partially_redact_key(&selected_key.key));
+            let key_span = span!(
+                Level::WARN,
+                "key_failover",
+                failover_attempt,
+                key_id = %selected_key.id,
+                key_hash = %util::key_hash(&selected_key.key),
+                key_part = %util::partially_redact_key(&selected_key.key),
+                status = tracing::field::Empty,
+                error_class = tracing::field::Empty,
+                latency_ms = tracing::field::Empty,
+                decision = tracing::field::Empty
+            );
             let _enter = key_span.enter();
 
+            if state.signal.aborted() {
+                warn!("Request was cancelled. Stopping failover.");
+                key_span.record("decision", "cancelled");
+                last_error_status = 503;
+                last_error_class = "request_cancelled";
+                cancelled = true;
+                break;
+            }
+
             // --- Dynamic Timeout Calculation ---
-            let elapsed_ms = Date::now().as_millis() - request_start_time.as_millis();
-            let remaining_ms = overall_timeout_ms.saturating_sub(elapsed_ms);
+            let remaining_ms = state.remaining_ms();
 
-            // Leave a small buffer (e.g., 500ms) to ensure the top-level timeout doesn't race us.
-            if remaining_ms < 500 {
-                warn!("Overall request time limit reached. Stopping failover.");
+            // Another key is only worth trying with enough budget left for a useful attempt;
+            // otherwise answer with the last provider error before the overall timeout hits.
+            if remaining_ms < state.settings.failover_min_budget_ms {
+                warn!(
+                    remaining_ms,
+                    min_budget_ms = state.settings.failover_min_budget_ms,
+                    "Overall request budget nearly spent. Stopping failover."
+                );
+                key_span.record("decision", "budget_exhausted");
+                if failover_attempt == 0 {
+                    // No provider error to fall back on.
+                    last_error_status = 504;
+                    last_error_class = "timeout_budget_exhausted";
+                    budget_exhausted = true;
+                }
                 break;
             }
 
@@ -406,117 +1206,247 @@ pub async fn forward(
                 if now < cooldown_end {
                     warn!(
                         "Key {} is on cooldown for model {}, skipping.",
-                        selected_key.key,
+                        util::partially_redact_key(&selected_key.key),
                         &model_name
                     );
+                    key_span.record("decision", "skip_cooling_key");
                     continue;
                 }
             }
 
+            // Move off a key before the provider rate limits it.
+            if !rate_budget::has_budget(&selected_key.id, &provider_settings) {
+                info!(
+                    "Key {} has used up its per-minute budget, skipping.",
+                    util::partially_redact_key(&selected_key.key)
+                );
+                key_span.record("decision", "skip_rate_budget");
+                rate_budget_skips += 1;
+                if failover_attempt == 0 {
+                    last_error_status = 429;
+                    last_error_class = "key_rate_budget_exhausted";
+                }
+                continue;
+            }
+
             let start_time = Date::now();
 
-            // --- 4. Construct Request based on Environment and Path ---
-            let is_local_dev = env
-                .var("IS_LOCAL")
-                .map(|v| v.to_string() == "true")
-                .unwrap_or(false);
+            // Vertex AI keys stored as service-account JSON authenticate with an access token.
+            let upstream_key = if provider == "google-vertex-ai" && gcp::is_service_account(&selected_key.key) {
+                match gcp::vertex_access_token(&selected_key.id, &selected_key.key, &state.signal).await {
+                    Ok(token) => token,
+                    Err(gcp::TokenError::Rejected(e)) => {
+                        warn!(key_id = %selected_key.id, "Service-account token exchange was refused: {}", e);
+                        let analysis = ErrorAnalysis::KeyIsInvalid;
+                        if let Some(penalty_seconds) = analysis.penalty_seconds() {
+                            d1_storage::flag_key_with_cooldown(&state.settings, &provider, &selected_key.id, analysis.class(), penalty_seconds);
+                        }
+                        key_span.record("decision", "skip_token_exchange_failed");
+                        last_error_status = 401;
+                        last_error_class = analysis.class();
+                        continue;
+                    }
+                    // A cancelled exchange says nothing about the key.
+                    Err(gcp::TokenError::Failed(_)) if state.signal.aborted() => {
+                        warn!("Request was cancelled during the token exchange. Stopping failover.");
+                        key_span.record("decision", "cancelled");
+                        last_error_status = 503;
+                        last_error_class = "request_cancelled";
+                        cancelled = true;
+                        break;
+                    }
+                    // Network errors and Google's own failures only earn the short transient penalty.
+                    Err(gcp::TokenError::Failed(e)) => {
+                        warn!(key_id = %selected_key.id, "Service-account token exchange failed: {}", e);
+                        let analysis = ErrorAnalysis::TransientServerError;
+                        if let Some(penalty_seconds) = analysis.penalty_seconds() {
+                            d1_storage::flag_key_with_cooldown(&state.settings, &provider, &selected_key.id, analysis.class(), penalty_seconds);
+                        }
+                        key_span.record("decision", "skip_token_exchange_error");
+                        last_error_status = 502;
+                        last_error_class = analysis.class();
+                        continue;
+                    }
+                }
+            } else {
+                selected_key.key.clone()
+            };
 
-                        let (request_to_execute, needs_embeddings_resp_translation, needs_chat_resp_translation) = if is_local_dev {
+            // --- 4. Construct Request based on Environment and Path ---
+            let is_local_dev = state.settings.is_local;
+
+            let (request_to_execute, needs_chat_resp_translation) = if let Some(custom) = &custom_provider {
+                // --- CUSTOM PROVIDER PATH ---
+                // Compat routes map onto the endpoint's OpenAI-compatible API, e.g.
+                // `compat/chat/completions` -> `{base_url}/chat/completions`.
+                let path = rest_resource
+                    .strip_prefix("compat/")
+                    .or_else(|| rest_resource.strip_prefix(&format!("{}/", provider)))
+                    .unwrap_or(&rest_resource);
+                let req = make_custom_provider_request(
+                    method.clone(),
+                    &headers,
+                    passthrough_body.clone(),
+                    custom,
+                    path,
+                    &upstream_key,
+                    &request_id,
+                )?;
+                (req, false)
+            } else if is_local_dev {
                 // --- LOCAL DEVELOPMENT PATH ---
-                if rest_resource.starts_with("compat/embeddings") {
-                    // 1. LOCAL OpenAI Embeddings -> Native Gemini Endpoint
-                    let openapi_req: OpenAiEmbeddingsRequest = serde_json::from_slice(&body_bytes)?;
-                    let gemini_req_body = gcp::translate_embeddings_request(openapi_req, &model_name);
-                    let gemini_body_bytes: Bytes = serde_json::to_vec(&gemini_req_body)?.into();
-                    let native_endpoint = format!("https://generativelanguage.googleapis.com/v1beta/models/{}:batchEmbedContents", model_name);
-
-                    let mut headers = worker::Headers::new();
-                    headers.set("Content-Type", "application/json")?;
-                    headers.set("x-goog-api-key", &selected_key.key)?;
+                if let Some((endpoint, compat_body)) = &compat_target {
+                    // 0. LOCAL Provider compat route (audio, images, rerank, embeddings) -> Native provider endpoint
+                    let mut native_headers = worker::Headers::new();
+                    if let Some(ct) = headers.get(axum::http::header::CONTENT_TYPE).and_then(|v| v.to_str().ok()) {
+                        native_headers.set("Content-Type", ct)?;
+                    }
+                    set_auth_header(&mut native_headers, &provider, &upstream_key)?;
                     let mut req_init = worker::RequestInit::new();
                     req_init
                         .with_method(worker::Method::Post)
-                        .with_headers(headers)
-                        .with_body(Some(js_sys::Uint8Array::from(gemini_body_bytes.as_ref()).into()));
-                    (worker::Request::new_with_init(&native_endpoint, &req_init)?, true, false)
-
+                        .with_headers(native_headers)
+                        .with_body(Some(js_sys::Uint8Array::from(compat_body.as_ref()).into()));
+                    (worker::Request::new_with_init(&endpoint.native_url, &req_init)?, false)
                 } else if rest_resource.starts_with("compat/chat/completions") {
                     // 2. LOCAL OpenAI Chat -> Native Gemini Endpoint
                     let openapi_req: OpenAiChatCompletionRequest = serde_json::from_slice(&body_bytes)?;
+                    // Streams are translated event by event, see `gcp::ChatStreamTranslator`.
+                    let method_name = if openapi_req.stream { "streamGenerateContent?alt=sse" } else { "generateContent" };
                     let gemini_req = gcp::translate_chat_request(openapi_req);
                     let gemini_body_bytes: Bytes = serde_json::to_vec(&gemini_req)?.into();
-                    let native_endpoint = format!("https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent", model_name);
+                    let native_endpoint = format!("https://generativelanguage.googleapis.com/v1beta/models/{}:{}", model_name, method_name);
 
                     let mut headers = worker::Headers::new();
                     headers.set("Content-Type", "application/json")?;
-                    headers.set("x-goog-api-key", &selected_key.key)?;
+                    headers.set("x-goog-api-key", &upstream_key)?;
                     let mut req_init = worker::RequestInit::new();
                     req_init
                         .with_method(worker::Method::Post)
                         .with_headers(headers)
                         .with_body(Some(js_sys::Uint8Array::from(gemini_body_bytes.as_ref()).into()));
-                    (worker::Request::new_with_init(&native_endpoint, &req_init)?, false, true)
+                    (worker::Request::new_with_init(&native_endpoint, &req_init)?, true)
                 } else {
                     // 3. LOCAL Native Passthrough -> Native Gemini Endpoint
                     let native_endpoint = format!("https://generativelanguage.googleapis.com/{}", rest_resource.strip_prefix(&format!("{}/", provider)).unwrap_or(&rest_resource));
                     let mut headers = worker::Headers::new();
                     headers.set("Content-Type", "application/json")?;
-                    headers.set("x-goog-api-key", &selected_key.key)?;
+                    headers.set("x-goog-api-key", &upstream_key)?;
                     let mut req_init = worker::RequestInit::new();
                     req_init
                         .with_method(worker::Method::from(method.to_string()))
                         .with_headers(headers)
-                        .with_body(Some(js_sys::Uint8Array::from(body_bytes.as_ref()).into()));
-                    (worker::Request::new_with_init(&native_endpoint, &req_init)?, false, false)
+                        .with_body(passthrough_body.as_ref().map(|b| js_sys::Uint8Array::from(b.as_ref()).into()));
+                    (worker::Request::new_with_init(&native_endpoint, &req_init)?, false)
                 }
             } else {
                 // --- PRODUCTION (AI GATEWAY) PATH ---
-                if rest_resource.starts_with("compat/embeddings") {
-                     // 4. REMOTE OpenAI Embeddings -> AI Gateway (needs translation)
-                   let openapi_req: OpenAiEmbeddingsRequest = serde_json::from_slice(&body_bytes)?;
-                   let gemini_req_body = gcp::translate_embeddings_request(openapi_req, &model_name);
-                   let gemini_body_bytes: Bytes = serde_json::to_vec(&gemini_req_body)?.into();
-                    // The gateway needs the provider-specific path for routing
-                   let provider_rest_resource = format!("google-ai-studio/v1beta/models/{}:batchEmbedContents", model_name);
-
-                   let req = make_gateway_request(
-                       method.clone(),
-                       &headers,
-                       Some(gemini_body_bytes),
-                       env,
-                       &provider_rest_resource,
-                       &selected_key.key,
-                       &uuid::Uuid::new_v4().to_string(),
-                   ).await?;
-                    (req, true, false)
+                if let Some((endpoint, compat_body)) = &compat_target {
+                    // 0. REMOTE Provider compat route (audio, images, rerank, embeddings) -> AI Gateway provider endpoint
+                    // The body was rewritten, so the original Content-Length no longer applies.
+                    let mut compat_headers = headers.clone();
+                    compat_headers.remove(axum::http::header::CONTENT_LENGTH);
+                    let req = make_gateway_request(
+                        method.clone(),
+                        &compat_headers,
+                        Some(compat_body.clone()),
+                        env,
+                        &endpoint.gateway_path,
+                        &upstream_key,
+                        &request_id,
+                    ).await?;
+                    (req, false)
                 } else {
                     // 5. REMOTE Passthrough (compat/chat or native) -> AI Gateway
                     let req = make_gateway_request(
                         method.clone(),
                         &headers,
-                        Some(body_bytes.clone()),
+                        passthrough_body.clone(),
                         env,
                         &rest_resource,
-                        &selected_key.key,
-                        &uuid::Uuid::new_v4().to_string(),
+                        &upstream_key,
+                        &request_id,
                     ).await?;
-                    (req, false, false)
+                    (req, false)
                 }
             };
 
             // --- 5. Execute Request with Retry ---
-            let result = execute_request_with_retry(request_to_execute, &provider, &selected_key.id, 3, attempt_timeout_ms, &state.signal).await?;
+            last_key_hash = util::key_hash(&selected_key.key);
+            if let Some(registration) = state.inflight.get() {
+                registration.set_key(&selected_key.id);
+            }
+            rate_budget::record_request(&selected_key.id, prompt_tokens_estimate);
+            let result = execute_request_with_retry(request_to_execute, &provider, &selected_key.id, 3, attempt_timeout_ms, &upstream, chaos_rule.as_ref()).await?;
+            // A cancelled attempt says nothing about the key.
+            if state.signal.aborted() {
+                warn!("Request was cancelled during the attempt. Stopping failover.");
+                key_span.record("decision", "cancelled");
+                last_error_status = 503;
+                last_error_class = "request_cancelled";
+                cancelled = true;
+                break;
+            }
             let latency = (Date::now().as_millis() - start_time.as_millis()) as i64;
+
+            // A stream can fail before its first token, e.g. with a rate limit reported as an
+            // error event. Nothing has reached the client yet, so it counts as a key failure.
+            let result = match result {
+                RequestResult::Success(resp) if resp.headers().get("Content-Type")?.is_some_and(|ct| sse::is_event_stream(&ct)) => {
+                    check_stream_start(&state, resp, &provider, &selected_key.id, latency, attempt_timeout_ms).await?
+                }
+                other => other,
+            };
+
+            // A malformed success (e.g. empty content) is retried on the next key like an error.
+            let result = match result {
+                RequestResult::Success(resp) if !response_validators.is_empty() => {
+                    validate_success(resp, &body_bytes, &response_validators).await?
+                }
+                other => other,
+            };
             
+            // The attempt's outcome, once streams and validators have had their say.
+            match &result {
+                RequestResult::Success(resp) => {
+                    key_span.record("decision", "success");
+                    record_attempt(&key_span, resp.status_code(), None, latency as u64);
+                }
+                RequestResult::Failure { analysis, status, .. } => {
+                    let decision = if matches!(analysis, ErrorAnalysis::UserError) { "return_error" } else { "failover" };
+                    key_span.record("decision", decision);
+                    record_attempt(&key_span, *status, Some(analysis.class()), latency as u64);
+                }
+            }
+            let provider_error = match &result {
+                RequestResult::Success(_) => Some(false),
+                RequestResult::Failure { analysis, .. } => provider_health::is_provider_error(analysis),
+            };
+            if let Some(error) = provider_error {
+                if let Some(transition) = provider_health::record(&state.settings, &provider, latency as u64, error) {
+                    record_provider_transition(&state, &provider, transition);
+                }
+                if let Some(transition) = provider_breaker::record(&state.settings, &provider, error) {
+                    record_breaker_transition(&state, &provider, transition);
+                }
+            }
+
             // --- 6. Process Result and Update State ---
+            // Set when the outcome is recorded together with usage, once token counts are known.
+            let mut outcome_recorded = false;
             let final_response = match result {
                 RequestResult::Success(mut resp) => {
                     // If we get here, the request was successful. Update metrics and return.
                     let state_clone = state.clone();
                     let selected_key_clone = selected_key.clone();
+                    // The circuit breaker had sidelined this key; it is serving again.
+                    let recovered = selected_key.consecutive_failures >= state.settings.recovery_threshold;
+                    let event_model = model_name.clone();
                     #[cfg(feature = "wait_until")]
                     state.ctx.wait_until(async move {
-                        if let Ok(db) = state_clone.env.d1("DB") {
+                        // With the queue, the metrics are sent below and batched by its consumer.
+                        #[cfg(not(feature = "use_queue"))]
+                        if let Ok(db) = state_clone.db() {
                             let update_future = d1_storage::update_key_metrics(
                                 &db,
                                 &selected_key_clone.id,
@@ -527,22 +1457,133 @@ pub async fn forward(
                                 error!("Failed to update key metrics on success: {}", e);
                             }
                         }
+                        if recovered {
+                            let event = KeyHealthEvent::new(
+                                KeyTransition::Recovered,
+                                &selected_key_clone.id,
+                                &util::key_hash(&selected_key_clone.key),
+                                &selected_key_clone.provider,
+                                &event_model,
+                                &format!("succeeded after {} consecutive failures", selected_key_clone.consecutive_failures),
+                            );
+                            key_events::emit(&state_clone.env, event).await;
+                        }
                     });
+
+                    // Record usage for quota accounting. Image generation is recorded
+                    // below with its per-image cost. Token counts are read from a clone
+                    // of JSON bodies, and estimated from the text when the provider
+                    // doesn't report them (streams only get a prompt estimate). Body-less
+                    // requests (e.g. listing models) aren't billable.
+                    if !is_image_generation && !body_bytes.is_empty() {
+                        let is_json = resp
+                            .headers()
+                            .get("Content-Type")?
+                            .is_some_and(|ct| ct.contains("json"));
+                        let usage_resp = if is_json { resp.cloned().ok() } else { None };
+                        // Opt-in quality sampling of JSON prompt/response pairs (not uploads or streams).
+                        let sample = usage_resp.is_some()
+                            && multipart_boundary.is_none()
+                            && sampling::should_sample(state.settings.sample_rate_percent);
+                        let mut record = UsageRecord {
+                            client_id: caller.client_id().map(str::to_string),
+                            provider: provider.clone(),
+                            model: model_name.clone(),
+                            key_id: selected_key.id.clone(),
+                            endpoint: usage::endpoint_label(&rest_resource, &provider),
+                            ..Default::default()
+                        };
+                        let mut outcome = RequestOutcome {
+                            provider: provider.clone(),
+                            model: model_name.clone(),
+                            status: resp.status_code(),
+                            latency_ms: Date::now().as_millis() - request_start_time.as_millis(),
+                            failovers: failover_attempt,
+                            cooldowns: cooldown_events,
+                            timeouts: timeout_events,
+                            key_hash: last_key_hash.clone(),
+                            ..Default::default()
+                        };
+                        outcome_recorded = true;
+                        let request_body = body_bytes.clone();
+                        // Uploads aren't text, so only JSON requests are logged.
+                        let log_payload = multipart_boundary.is_none();
+                        let state_clone = state.clone();
+                        #[cfg(feature = "wait_until")]
+                        state.ctx.wait_until(async move {
+                            let response_body = match usage_resp {
+                                Some(mut usage_resp) => usage_resp.json::<serde_json::Value>().await.ok(),
+                                None => None,
+                            };
+                            let (prompt, completion) = match response_body.as_ref().and_then(usage::extract_token_usage) {
+                                Some(reported) => reported,
+                                None => {
+                                    record.estimated = true;
+                                    (
+                                        usage::estimate_prompt_tokens(&request_body),
+                                        response_body.as_ref().map_or(0, usage::estimate_completion_tokens),
+                                    )
+                                }
+                            };
+                            record.prompt_tokens = prompt;
+                            record.completion_tokens = completion;
+                            rate_budget::record_tokens(&record.key_id, completion);
+                            record.cost_micros = usage::token_cost_micros(&record.model, prompt, completion);
+                            outcome.prompt_tokens = prompt;
+                            outcome.completion_tokens = completion;
+                            if log_payload {
+                                let response_text = response_body.as_ref().map(|b| b.to_string()).unwrap_or_default();
+                                outcome.payload =
+                                    payload_log::capture(&state_clone.settings, false, &request_body, &response_text);
+                            }
+                            record_outcome(&state_clone, &outcome).await;
+                            if let Ok(db) = state_clone.db() {
+                                if let (true, Some(body)) = (sample, &response_body) {
+                                    let sample = SampleRecord {
+                                        provider: record.provider.clone(),
+                                        model: record.model.clone(),
+                                        key_id: record.key_id.clone(),
+                                        client_id: record.client_id.clone(),
+                                        endpoint: record.endpoint.clone(),
+                                        request_body: sampling::redact(&String::from_utf8_lossy(&request_body)),
+                                        response_body: sampling::redact(&body.to_string()),
+                                        latency_ms: latency,
+                                    };
+                                    if let Err(e) = d1_storage::record_sample(&db, &sample).await {
+                                        error!("Failed to record sample: {}", e);
+                                    }
+                                }
+                                if let Err(e) = d1_storage::record_usage(&db, &record).await {
+                                    error!("Failed to record usage: {}", e);
+                                }
+                            }
+                        });
+                    }
+
                     #[cfg(feature = "use_queue")]
-                    queue
-                        .send(&StateUpdate::UpdateMetrics {
+                    queue::send(
+                        &state,
+                        StateUpdate::UpdateMetrics {
                             key_id: selected_key.id.clone(),
                             is_success: true,
                             latency,
-                        })
-                        .await?;
+                        },
+                    )
+                    .await;
 
                      // Translate response if needed
-                     if needs_embeddings_resp_translation {
-                         let gemini_resp: GeminiEmbeddingsResponse = resp.json().await?;
-                         let openapi_resp =
-                             gcp::translate_embeddings_response(gemini_resp, &model_name);
-                         Response::from_json(&openapi_resp)?
+                     if let Some(embedder) = embeddings_provider {
+                         let body = embedder.translate_response(&resp.bytes().await?, &model_name)?;
+                         let resp_headers = worker::Headers::new();
+                         resp_headers.set("Content-Type", "application/json")?;
+                         Response::from_bytes(body)?.with_headers(resp_headers)
+                     } else if needs_chat_resp_translation && resp.headers().get("Content-Type")?.is_some_and(|ct| sse::is_event_stream(&ct)) {
+                        let status = resp.status_code();
+                        let body = gcp::translate_chat_stream(resp.stream()?, &model_name);
+                        let resp_headers = worker::Headers::new();
+                        resp_headers.set("Content-Type", "text/event-stream")?;
+                        resp_headers.set("Cache-Control", "no-cache")?;
+                        Response::from_stream(body)?.with_status(status).with_headers(resp_headers)
                      } else if needs_chat_resp_translation {
                         let body_bytes = resp.bytes().await?;
                         let Ok(gemini_resp) = serde_json::from_slice::<gcp::GeminiChatResponse>(&body_bytes) else {
@@ -553,6 +1594,42 @@ pub async fn forward(
                         };
                           let openapi_resp = gcp::translate_chat_response(gemini_resp, &model_name);
                           Response::from_json(&openapi_resp)?
+                     } else if let (true, Some(rerank_req)) = (needs_rerank_resp_translation, &rerank_request) {
+                        let workers_resp: WorkersAiRerankResponse = resp.json().await?;
+                        let rerank_resp =
+                            workers_ai::translate_rerank_response(workers_resp, rerank_req, &model_name);
+                        Response::from_json(&rerank_resp)?
+                     } else if is_image_generation {
+                        // Buffer the body to count images, then pass it through unchanged
+                        // (JSON with url/b64_json entries, or a raw binary image).
+                        let status = resp.status_code();
+                        let resp_headers = resp.headers().clone();
+                        let content_type = resp_headers.get("Content-Type")?.unwrap_or_default();
+                        let body = resp.bytes().await?;
+                        let images = usage::count_generated_images(&content_type, &body);
+                        let record = UsageRecord {
+                            client_id: caller.client_id().map(str::to_string),
+                            provider: provider.clone(),
+                            model: model_name.clone(),
+                            key_id: selected_key.id.clone(),
+                            endpoint: "images/generations".to_string(),
+                            images,
+                            cost_micros: usage::image_cost_micros(&model_name, images),
+                            ..Default::default()
+                        };
+                        info!(images, cost_micros = record.cost_micros, "Image generation succeeded.");
+                        let state_clone = state.clone();
+                        #[cfg(feature = "wait_until")]
+                        state.ctx.wait_until(async move {
+                            if let Ok(db) = state_clone.db() {
+                                if let Err(e) = d1_storage::record_usage(&db, &record).await {
+                                    error!("Failed to record image usage: {}", e);
+                                }
+                            }
+                        });
+                        Response::from_bytes(body.to_vec())?
+                            .with_status(status)
+                            .with_headers(resp_headers)
                      } else {
                         resp
                     }
@@ -565,70 +1642,158 @@ pub async fn forward(
                     last_error_body = body_text;
                     last_error_status = status;
                     last_error_was_cooldown = matches!(analysis, ErrorAnalysis::KeyOnCooldown {..});
+                    last_error_class = analysis.class();
+
+                    // Bench the key locally for as long as this kind of failure warrants, so
+                    // a transient 5xx doesn't sideline it as long as an exhausted quota.
+                    if let Some(penalty_seconds) = analysis.penalty_seconds() {
+                        d1_storage::flag_key_with_cooldown(&state.settings, &provider, &selected_key.id, analysis.class(), penalty_seconds);
+                    }
 
                     // Update state based on the specific error analysis.
-                    let state_clone = state.clone();
-                    let selected_key_clone = selected_key.clone();
-                    #[cfg(feature = "wait_until")]
-                    state.ctx.wait_until(async move {
-                         if let Ok(db) = state_clone.env.d1("DB") {
-                            let update_future = d1_storage::update_key_metrics(
-                                &db,
-                                &selected_key_clone.id,
-                                false,
-                                latency,
-                            );
-                            if let Err(e) = update_future.await {
-                                error!("Failed to update key metrics on failure: {}", e);
+                    #[cfg(not(feature = "use_queue"))]
+                    {
+                        let state_clone = state.clone();
+                        let selected_key_clone = selected_key.clone();
+                        #[cfg(feature = "wait_until")]
+                        state.ctx.wait_until(async move {
+                            if let Ok(db) = state_clone.db() {
+                                let update_future = d1_storage::update_key_metrics(
+                                    &db,
+                                    &selected_key_clone.id,
+                                    false,
+                                    latency,
+                                );
+                                if let Err(e) = update_future.await {
+                                    error!("Failed to update key metrics on failure: {}", e);
+                                }
                             }
-                        }
-                    });
+                        });
+                    }
+                    #[cfg(feature = "use_queue")]
+                    queue::send(
+                        &state,
+                        StateUpdate::UpdateMetrics {
+                            key_id: selected_key.id.clone(),
+                            is_success: false,
+                            latency,
+                        },
+                    )
+                    .await;
 
                     match analysis {
                         ErrorAnalysis::KeyIsInvalid => {
-                            // Flag the key for immediate cooldown in the local cache to prevent retries in this request.
-                            // We use a long duration as a safeguard. The permanent block is handled by the D1 update.
-                            d1_storage::flag_key_with_cooldown(&selected_key.id, 300);
-
+                            // The key was flagged in the local cache above to prevent retries in this request.
+                            // The permanent block is handled by the D1 update.
 
                             // Dispatch the database update to the background
                             let state_clone = state.clone();
                             let key_id = selected_key.id.clone();
+                            let block_provider = provider.clone();
+                            let history = KeyEventRecord {
+                                key_id: key_id.clone(),
+                                provider: provider.clone(),
+                                model: model_name.clone(),
+                                event: "blocked",
+                                status: last_error_status,
+                                error_class: last_error_class.to_string(),
+                                message: last_error_body.clone(),
+                            };
+                            let event = KeyHealthEvent::new(
+                                KeyTransition::Blocked,
+                                &selected_key.id,
+                                &last_key_hash,
+                                &provider,
+                                "",
+                                &format!("provider rejected the key as invalid (status {})", last_error_status),
+                            );
                             #[cfg(feature = "wait_until")]
                             state.ctx.wait_until(async move {
-                                if let Ok(db) = state_clone.env.d1("DB") {
-                                    let fut = d1_storage::update_status(
-                                        &db,
-                                        &key_id,
-                                        ApiKeyStatus::Blocked,
-                                    );
-                                    if let Err(e) = fut.await {
-                                        error!("Failed to set key status to Blocked: {}", e);
+                                if let Ok(db) = state_clone.db() {
+                                    let fut = d1_storage::block_key(&db, &key_id, &block_provider, &event.reason);
+                                    match fut.await {
+                                        Ok(()) => {
+                                            if let Err(e) = d1_storage::record_key_event(&db, &history).await {
+                                                error!("Failed to record key event: {}", e);
+                                            }
+                                            key_events::emit(&state_clone.env, event).await
+                                        }
+                                        Err(e) => error!("Failed to set key status to Blocked: {}", e),
                                     }
                                 }
                             });
                         }
                         ErrorAnalysis::KeyOnCooldown { cooldown_seconds } => {
-                            // Flag the key for immediate cooldown in the local cache.
-                            d1_storage::flag_key_with_cooldown(&selected_key.id, cooldown_seconds);
+                            cooldown_events += 1;
 
                              // Dispatch the database update to the background
                              let state_clone = state.clone();
                              let key_id = selected_key.id.clone();
+                             let key_hash = last_key_hash.clone();
                              let provider = provider.clone();
                              let model_name = model_name.clone();
+                             let is_daily_quota = analysis.is_daily_quota();
+                             let history = KeyEventRecord {
+                                 key_id: key_id.clone(),
+                                 provider: provider.clone(),
+                                 model: model_name.clone(),
+                                 event: "cooldown",
+                                 status: last_error_status,
+                                 error_class: last_error_class.to_string(),
+                                 message: last_error_body.clone(),
+                             };
                              #[cfg(feature="wait_until")]
                              state.ctx.wait_until(async move {
-                                if let Ok(db) = state_clone.env.d1("DB") {
+                                if let Ok(db) = state_clone.db() {
                                     let fut = d1_storage::set_key_model_cooldown_if_available(&db, &key_id, &provider, &model_name, cooldown_seconds);
-                                    if let Err(e) = fut.await {
-                                        error!("Failed to set key cooldown: {}", e);
+                                    match fut.await {
+                                        // Only record and report the cooldown when this request started it.
+                                        Ok(true) => {
+                                            if let Err(e) = d1_storage::record_key_event(&db, &history).await {
+                                                error!("Failed to record key event: {}", e);
+                                            }
+                                            if is_daily_quota {
+                                                let event = KeyHealthEvent::new(
+                                                    KeyTransition::DailyQuotaCooldown,
+                                                    &key_id,
+                                                    &key_hash,
+                                                    &provider,
+                                                    &model_name,
+                                                    "daily quota exhausted",
+                                                )
+                                                .with_cooldown(cooldown_seconds);
+                                                key_events::emit(&state_clone.env, event).await;
+                                            }
+                                        }
+                                        Ok(false) => {}
+                                        Err(e) => error!("Failed to set key cooldown: {}", e),
                                     }
                                 }
                              });
                         }
-                        // For UserError, we return immediately to the client.
+                        // A timed-out or aborted attempt fails over right away. It only earns the
+                        // short local penalty applied above and is counted separately in the stats.
+                        ErrorAnalysis::RequestTimeout => {
+                            timeout_events += 1;
+                        }
+                        // For UserError, we return immediately to the client. This is the outcome of
+                        // the request, so it is recorded here, with the payload when failures are logged.
                         ErrorAnalysis::UserError => {
+                             if !outcome_recorded {
+                                 record_request_metrics(&state, RequestOutcome {
+                                     provider: provider.clone(),
+                                     model: model_name.clone(),
+                                     status: last_error_status,
+                                     latency_ms: Date::now().as_millis() - request_start_time.as_millis(),
+                                     failovers: failover_attempt,
+                                     cooldowns: cooldown_events,
+                                     timeouts: timeout_events,
+                                     key_hash: last_key_hash.clone(),
+                                     error_class: last_error_class.to_string(),
+                                     payload: logged_payload(&state, &multipart_boundary, &body_bytes, &last_error_body),
+                                     ..Default::default()
+                                 });
+                             }
                              let resp = Response::from_bytes(last_error_body.into_bytes())?.with_status(last_error_status);
                              return Ok(AxumWorkerResponse(resp).into_response());
                         }
@@ -647,16 +1812,77 @@ pub async fn forward(
                 }
             };
 
+            if !outcome_recorded {
+                record_request_metrics(&state, RequestOutcome {
+                    provider: provider.clone(),
+                    model: model_name.clone(),
+                    status: final_response.status_code(),
+                    latency_ms: Date::now().as_millis() - request_start_time.as_millis(),
+                    failovers: failover_attempt,
+                    cooldowns: cooldown_events,
+                    timeouts: timeout_events,
+                    key_hash: last_key_hash.clone(),
+                    ..Default::default()
+                });
+            }
+            let final_response = HeaderPolicy::from_env(env).apply(final_response)?;
             return Ok(AxumWorkerResponse(final_response).into_response());
         }
 
         // --- 7. Handle Complete Failure ---
         // If the loop finishes, it means no key resulted in a successful response.
         // We now decide what error to return based on the last failure we saw.
+        record_request_metrics(&state, RequestOutcome {
+            provider: provider.clone(),
+            model: model_name.clone(),
+            status: last_error_status,
+            latency_ms: Date::now().as_millis() - request_start_time.as_millis(),
+            failovers: failover_attempt,
+            cooldowns: cooldown_events,
+            timeouts: timeout_events,
+            key_hash: last_key_hash,
+            error_class: last_error_class.to_string(),
+            payload: logged_payload(&state, &multipart_boundary, &body_bytes, &last_error_body),
+            ..Default::default()
+        });
+        let retry_after = retry_after_seconds(&state.settings, &provider, &model_name, &sorted_keys);
+        if failover_attempt == 0 && rate_budget_skips > 0 && !cancelled && !budget_exhausted {
+            // Every usable key is at its budget; the oldest charges leave the window within a minute.
+            return Ok(create_retryable_error_response(
+                "Every key of this provider has used up its per-minute budget.",
+                "server_error",
+                last_error_class,
+                last_error_status,
+                retry_after.or(Some(60)),
+            )
+            .into_response());
+        }
+        if cancelled {
+            return Ok(create_openai_error_response(
+                "The request was cancelled by an administrator.",
+                "server_error",
+                last_error_class,
+                last_error_status,
+            )
+            .into_response());
+        }
+        if budget_exhausted {
+            return Ok(create_retryable_error_response(
+                "The request's time budget ran out before a key could be tried.",
+                "server_error",
+                last_error_class,
+                last_error_status,
+                retry_after,
+            )
+            .into_response());
+        }
         if last_error_was_cooldown {
             // If the last attempt failed due to a rate limit, it's more informative
             // to return the provider's actual error message.
-            let resp = Response::from_bytes(last_error_body.into_bytes())?.with_status(last_error_status);
+            let mut resp = Response::from_bytes(last_error_body.into_bytes())?.with_status(last_error_status);
+            if let Some(seconds) = retry_after {
+                resp.headers_mut().set("Retry-After", &seconds.to_string())?;
+            }
             Ok(AxumWorkerResponse(resp).into_response())
         } else {
             // For all other types of failures (invalid keys, server errors, etc.),
@@ -673,11 +1899,12 @@ pub async fn forward(
                 "All keys for provider failed after failover attempts."
             );
 
-            Ok(create_openai_error_response(
+            Ok(create_retryable_error_response(
                 &last_error_body,
                 "server_error",
                 "all_keys_failed",
                 last_error_status,
+                retry_after,
             )
             .into_response())
         }
@@ -691,9 +1918,8 @@ pub async fn forward(
     }
 }
 
-
-// --- ADD THIS NEW HANDLER ---
-#[instrument(skip_all, level = "warn", fields(request_id = %uuid::Uuid::new_v4()))]
+/// Runs the scheduled key cleanup for one provider now and returns what it did.
+#[instrument(skip_all, level = "warn")]
 #[worker::send]
 pub async fn run_cleanup_handler(
     State(state): State<Arc<AppState>>,
@@ -717,18 +1943,11 @@ pub async fn run_cleanup_handler(
         }
 
         // --- 2. Run Cleanup ---
-        let db = env.d1("DB")?;
-        match d1_storage::delete_permanently_failed_keys(env, &db, &provider).await {
-            Ok(deleted_count) => {
-                let success_message = format!(
-                    "Successfully completed cleanup for provider: {}. Deleted {} keys.",
-                    provider, deleted_count
-                );
-                info!("{}", &success_message);
-                Ok(axum::response::Response::builder()
-                    .status(200)
-                    .body(axum::body::Body::from(success_message))
-                    .unwrap())
+        let db = state.db()?;
+        match d1_storage::run_cleanup(&state.settings, &db, &provider).await {
+            Ok(summary) => {
+                info!(?summary, "Completed cleanup for provider: {}.", provider);
+                Ok(AxumWorkerResponse(Response::from_json(&summary)?).into_response())
             }
             Err(e) => {
                 let error_message = format!(
@@ -751,4 +1970,270 @@ pub async fn run_cleanup_handler(
     }
 }
 
+/// Returns a provider's model names, from the D1 catalog while it is fresh, otherwise
+/// fetched with one of the provider's healthy keys. Falls back to a stale catalog if
+/// the fetch fails.
+async fn provider_models(
+    settings: &Settings,
+    db: &worker::D1Database,
+    provider: &str,
+    signal: &AbortSignal,
+) -> Vec<String> {
+    let ttl_seconds = settings.models_cache_ttl_seconds;
+    let Some(endpoint) = compat::model_list_endpoint(provider) else {
+        return Vec::new();
+    };
+    let cached = d1_storage::get_model_catalog(db, provider).await.ok().flatten();
+    let now = Date::now().as_millis() / 1000;
+    if let Some((models, fetched_at)) = &cached {
+        if now < fetched_at + ttl_seconds {
+            return models.clone();
+        }
+    }
+
+    let keys = d1_storage::get_healthy_sorted_keys_via_cache(settings, db, provider)
+        .await
+        .unwrap_or_default();
+    for key in keys.iter().take(2) {
+        match crate::request::fetch_provider_models(provider, &key.key, endpoint, Some(signal)).await {
+            Ok(models) => {
+                if let Err(e) = d1_storage::save_model_catalog(db, provider, &models).await {
+                    error!(provider, "Failed to cache model list: {}", e);
+                }
+                return models;
+            }
+            Err(e) => warn!(provider, key_id = %key.id, "Failed to list models: {}", e),
+        }
+    }
+    cached.map(|(models, _)| models).unwrap_or_default()
+}
+
+/// OpenAI-compatible `GET /api/compat/models`: the union of the model lists of every
+/// provider with active keys, as `provider/model` ids the compat routes accept.
+#[instrument(skip_all, level = "warn")]
+#[worker::send]
+pub async fn list_models(
+    State(state): State<Arc<AppState>>,
+    req: axum::extract::Request,
+) -> impl IntoResponse {
+    let result: Result<axum::response::Response> = async {
+        let main_auth_key = util::get_auth_key_from_axum_header(&req)?;
+        let Some(caller) = util::authenticate(&main_auth_key, &state).await else {
+            return Ok(create_openai_error_response(
+                "Invalid authentication credentials.",
+                "invalid_request_error",
+                "invalid_api_key",
+                401,
+            )
+            .into_response());
+        };
+
+        let db = state.db()?;
+        let providers = d1_storage::list_active_providers(&db).await.map_err(worker::Error::from)?;
+        let mut data = Vec::new();
+        for provider in providers {
+            let observe_only = d1_storage::get_provider_settings_via_cache(&db, &provider)
+                .await
+                .map(|settings| settings.observe_only)
+                .unwrap_or(false);
+            if observe_only {
+                continue;
+            }
+            for model in provider_models(&state.settings, &db, &provider, &state.signal).await {
+                if caller.allows(&provider, &model) {
+                    data.push(OpenAiModel {
+                        id: format!("{}/{}", provider, model),
+                        object: "model".to_string(),
+                        created: 0,
+                        owned_by: provider.clone(),
+                    });
+                }
+            }
+        }
+
+        let list = OpenAiModelList {
+            object: "list".to_string(),
+            data,
+        };
+        Ok(AxumWorkerResponse(Response::from_json(&list)?).into_response())
+    }
+    .await;
+
+    match result {
+        Ok(resp) => resp.into_response(),
+        Err(e) => AxumWorkerError(e).into_response(),
+    }
+}
+
+/// `GET /api/availability/{provider}/{model}`: whether a request for the model could be
+/// served right now, and by how many keys, so clients can pick a model before sending a
+/// large prompt. Nothing is sent upstream.
+#[instrument(skip_all, level = "warn")]
+#[worker::send]
+pub async fn check_availability(
+    State(state): State<Arc<AppState>>,
+    Path((provider, model)): Path<(String, String)>,
+    req: axum::extract::Request,
+) -> impl IntoResponse {
+    let result: Result<axum::response::Response> = async {
+        let main_auth_key = util::get_auth_key_from_axum_header(&req)?;
+        let Some(caller) = util::authenticate(&main_auth_key, &state).await else {
+            return Ok(create_openai_error_response(
+                "Invalid authentication credentials.",
+                "invalid_request_error",
+                "invalid_api_key",
+                401,
+            )
+            .into_response());
+        };
+        if !caller.allows(&provider, &model) {
+            return Ok(create_openai_error_response(
+                &format!("This API key is not allowed to access {}/{}.", provider, model),
+                "invalid_request_error",
+                "model_not_allowed",
+                403,
+            )
+            .into_response());
+        }
+
+        let db = state.db()?;
+        let observe_only = d1_storage::get_provider_settings_via_cache(&db, &provider)
+            .await
+            .map_err(worker::Error::from)?
+            .observe_only;
+        let keys = if observe_only {
+            Vec::new()
+        } else {
+            d1_storage::get_healthy_sorted_keys_via_cache(&state.settings, &db, &provider)
+                .await
+                .map_err(worker::Error::from)?
+        };
+        let now = Date::now().as_millis() / 1000;
+        let usable_keys = keys
+            .iter()
+            .filter(|key| key.get_cooldown_end(&model).is_none_or(|end| end <= now))
+            .count();
+
+        let availability = KeyAvailability {
+            available: usable_keys > 0,
+            usable_keys,
+            reason: match (observe_only, usable_keys) {
+                (true, _) => Some("provider_observe_only".to_string()),
+                (false, 0) => Some("no_keys_available".to_string()),
+                _ => None,
+            },
+            retry_after: (usable_keys == 0 && !observe_only)
+                .then(|| retry_after_seconds(&state.settings, &provider, &model, &keys))
+                .flatten(),
+            provider,
+            model,
+        };
+        Ok(AxumWorkerResponse(Response::from_json(&availability)?).into_response())
+    }
+    .await;
+
+    match result {
+        Ok(resp) => resp.into_response(),
+        Err(e) => AxumWorkerError(e).into_response(),
+    }
+}
+
+/// `POST /api/compat/tokens/count`: the prompt tokens of a chat completion request, so
+/// clients can budget it before sending it. Gemini models are counted by the provider with
+/// one of the pool's keys; everything else, or Gemini when no key can count, is estimated
+/// locally (see `tokens`). Nothing is charged to the key or the client.
+#[instrument(skip_all, level = "warn")]
+#[worker::send]
+pub async fn count_tokens(State(state): State<Arc<AppState>>, req: axum::extract::Request) -> impl IntoResponse {
+    let result: Result<axum::response::Response> = async {
+        let main_auth_key = util::get_auth_key_from_axum_header(&req)?;
+        let Some(caller) = util::authenticate(&main_auth_key, &state).await else {
+            return Ok(create_openai_error_response(
+                "Invalid authentication credentials.",
+                "invalid_request_error",
+                "invalid_api_key",
+                401,
+            )
+            .into_response());
+        };
+
+        let Some(body_bytes) = read_body(req.into_body(), state.settings.max_body_bytes).await? else {
+            return Ok(create_body_too_large_response(state.settings.max_body_bytes).into_response());
+        };
+        let count_request: TokenCountRequest = match serde_json::from_slice(&body_bytes) {
+            Ok(count_request) => count_request,
+            Err(e) => {
+                return Ok(create_openai_error_response(
+                    &format!("Invalid token count request: {}", e),
+                    "invalid_request_error",
+                    "invalid_request",
+                    400,
+                )
+                .into_response())
+            }
+        };
+        let Some((provider, model)) = count_request.model.split_once('/') else {
+            return Ok(create_openai_error_response(
+                "The model must be given as provider/model.",
+                "invalid_request_error",
+                "invalid_model",
+                400,
+            )
+            .into_response());
+        };
+        if !caller.allows(provider, model) {
+            return Ok(create_openai_error_response(
+                &format!("This API key is not allowed to access {}/{}.", provider, model),
+                "invalid_request_error",
+                "model_not_allowed",
+                403,
+            )
+            .into_response());
+        }
+
+        let mut counted = None;
+        if provider == "google-ai-studio" {
+            let db = state.db()?;
+            let keys = d1_storage::get_healthy_sorted_keys_via_cache(&state.settings, &db, provider)
+                .await
+                .unwrap_or_default();
+            let gemini_request = gcp::translate_chat_request(OpenAiChatCompletionRequest {
+                model: model.to_string(),
+                messages: count_request.messages.clone(),
+                stream: false,
+                tools: Vec::new(),
+                tool_choice: None,
+                response_format: None,
+                temperature: None,
+                top_p: None,
+                max_tokens: None,
+                max_completion_tokens: None,
+                stop: None,
+            });
+            for key in keys.iter().take(2) {
+                match crate::request::fetch_gemini_token_count(&key.key, model, &gemini_request, Some(&state.signal))
+                    .await
+                {
+                    Ok(count) => {
+                        counted = Some(count);
+                        break;
+                    }
+                    Err(e) => warn!(provider, key_id = %key.id, "Failed to count tokens: {}", e),
+                }
+            }
+        }
+
+        let response = TokenCountResponse {
+            input_tokens: counted.unwrap_or_else(|| tokens::count_chat_tokens(&count_request.messages)),
+            method: if counted.is_some() { "provider" } else { "estimate" }.to_string(),
+            model: count_request.model,
+        };
+        Ok(AxumWorkerResponse(Response::from_json(&response)?).into_response())
+    }
+    .await;
 
+    match result {
+        Ok(resp) => resp.into_response(),
+        Err(e) => AxumWorkerError(e).into_response(),
+    }
+}
@@ -1,11 +1,58 @@
 use anyhow::Result;
 use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use toasty::{stmt::IntoSelect, Model};
 use toasty_core::schema::db::Schema;
-use worker::D1Database;
+use tracing::{debug, warn};
+use worker::{D1Database, D1Type};
 
-use crate::hybrid::sql_converter::{to_d1_type, statement_to_sql};
+use crate::hybrid::sql_converter::{
+    count_statement_to_sql, insert_ignoring_conflicts_to_sql, projected_statement_to_sql, to_d1_type, statement_to_sql,
+};
+
+/// Whether generated SQL is run through `EXPLAIN QUERY PLAN` first (see `EXPLAIN_QUERIES`).
+static EXPLAIN_QUERIES: AtomicBool = AtomicBool::new(false);
+
+/// Turns query plan logging on or off for every executor in this isolate.
+pub fn set_explain_queries(enabled: bool) {
+    EXPLAIN_QUERIES.store(enabled, Ordering::Relaxed);
+}
+
+#[derive(Deserialize)]
+struct QueryPlanRow {
+    detail: String,
+}
+
+/// Whether a query plan step reads every row of a table. Scans of a covering index and
+/// index lookups (`SEARCH ...`) are not reported.
+fn is_full_table_scan(detail: &str) -> bool {
+    (detail.starts_with("SCAN ") || detail.starts_with("SCAN TABLE ")) && !detail.contains(" INDEX ")
+}
+
+/// The SQL and bound parameters a query lowers to, as the executor would send them to D1.
+#[derive(Serialize, Debug)]
+pub struct SqlPreview {
+    pub sql: String,
+    pub params: Vec<serde_json::Value>,
+}
+
+impl SqlPreview {
+    fn new(sql: String, params: &[toasty_core::stmt::Value]) -> Self {
+        let params = params
+            .iter()
+            .map(|value| match to_d1_type(value) {
+                D1Type::Null | D1Type::Blob(_) => serde_json::Value::Null,
+                D1Type::Real(v) => serde_json::json!(v),
+                D1Type::Integer(v) => serde_json::json!(v),
+                D1Type::Text(v) => serde_json::json!(v),
+                D1Type::Boolean(v) => serde_json::json!(v),
+            })
+            .collect();
+        Self { sql, params }
+    }
+}
 
 /// Hybrid executor that combines Toasty query building with D1 execution
 pub struct HybridExecutor<'a> {
@@ -32,6 +79,7 @@ impl<'a> HybridExecutor<'a> {
         let d1_params: Vec<_> = params.iter().map(to_d1_type).collect();
         
         // Execute query
+        self.explain(&sql, &d1_params).await;
         let unbound_stmt = self.d1.prepare(&sql);
         let results: Vec<M> = unbound_stmt.bind_refs(&d1_params)?.all().await?.results()?;
         
@@ -51,12 +99,54 @@ impl<'a> HybridExecutor<'a> {
         let d1_params: Vec<_> = params.iter().map(to_d1_type).collect();
         
         // Execute query
+        self.explain(&sql, &d1_params).await;
         let unbound_stmt = self.d1.prepare(&sql);
         let result: Option<M> = unbound_stmt.bind_refs(&d1_params)?.first(None).await?;
         
         Ok(result)
     }
 
+    /// Execute a SELECT query returning only the given columns, deserialized into a row type
+    pub async fn exec_query_columns<M, T>(&self, query: impl IntoSelect<Model = M>, columns: &[&str]) -> Result<Vec<T>>
+    where
+        M: Model,
+        T: DeserializeOwned,
+    {
+        // Convert to Statement<M> then narrow its select list
+        let statement: toasty::stmt::Statement<M> = query.into_select().into();
+        let (sql, params) = projected_statement_to_sql(statement, &self.schema, columns)?;
+        
+        // Convert parameters to D1 types
+        let d1_params: Vec<_> = params.iter().map(to_d1_type).collect();
+        
+        // Execute query
+        self.explain(&sql, &d1_params).await;
+        let unbound_stmt = self.d1.prepare(&sql);
+        let rows: Vec<T> = unbound_stmt.bind_refs(&d1_params)?.all().await?.results()?;
+        
+        Ok(rows)
+    }
+
+    /// Count the rows a SELECT query would return, without fetching them
+    pub async fn exec_count<M>(&self, query: impl IntoSelect<Model = M>) -> Result<u64>
+    where
+        M: Model,
+    {
+        // Convert to Statement<M> then wrap it in a COUNT(*) aggregate
+        let statement: toasty::stmt::Statement<M> = query.into_select().into();
+        let (sql, params) = count_statement_to_sql(statement, &self.schema)?;
+        
+        // Convert parameters to D1 types
+        let d1_params: Vec<_> = params.iter().map(to_d1_type).collect();
+        
+        // Execute count
+        self.explain(&sql, &d1_params).await;
+        let unbound_stmt = self.d1.prepare(&sql);
+        let count: Option<u64> = unbound_stmt.bind_refs(&d1_params)?.first(Some("count")).await?;
+        
+        Ok(count.unwrap_or(0))
+    }
+
     /// Execute an INSERT statement
     pub async fn exec_insert<M>(&self, insert: toasty::stmt::Insert<M>) -> Result<()>
     where
@@ -76,6 +166,57 @@ impl<'a> HybridExecutor<'a> {
         Ok(())
     }
 
+    /// Execute several INSERT statements in a single D1 batch (one round trip, one transaction)
+    pub async fn exec_insert_batch<M>(&self, inserts: Vec<toasty::stmt::Insert<M>>) -> Result<()>
+    where
+        M: Model,
+    {
+        if inserts.is_empty() {
+            return Ok(());
+        }
+
+        let mut statements = Vec::with_capacity(inserts.len());
+        for insert in inserts {
+            // Convert each insert to SQL and bind its parameters
+            let statement: toasty::stmt::Statement<M> = insert.into();
+            let (sql, params) = statement_to_sql(statement, &self.schema)?;
+            let d1_params: Vec<_> = params.iter().map(to_d1_type).collect();
+            statements.push(self.d1.prepare(&sql).bind_refs(&d1_params)?);
+        }
+
+        // Execute all inserts together
+        self.d1.batch(statements).await?;
+
+        Ok(())
+    }
+
+    /// Execute several INSERT statements in a single D1 batch, skipping rows that conflict
+    /// with a unique constraint. Returns the number of rows actually inserted.
+    pub async fn exec_insert_batch_ignoring_conflicts<M>(&self, inserts: Vec<toasty::stmt::Insert<M>>) -> Result<usize>
+    where
+        M: Model,
+    {
+        if inserts.is_empty() {
+            return Ok(0);
+        }
+
+        let mut statements = Vec::with_capacity(inserts.len());
+        for insert in inserts {
+            let statement: toasty::stmt::Statement<M> = insert.into();
+            let (sql, params) = insert_ignoring_conflicts_to_sql(statement, &self.schema)?;
+            let d1_params: Vec<_> = params.iter().map(to_d1_type).collect();
+            statements.push(self.d1.prepare(&sql).bind_refs(&d1_params)?);
+        }
+
+        // Each result reports whether its row was written or skipped
+        let mut inserted = 0;
+        for result in self.d1.batch(statements).await? {
+            inserted += result.meta()?.and_then(|meta| meta.changes).unwrap_or(0);
+        }
+
+        Ok(inserted)
+    }
+
     /// Execute an UPDATE statement
     pub async fn exec_update<M>(&self, update: toasty::stmt::Update<M>) -> Result<()>
     where
@@ -89,6 +230,7 @@ impl<'a> HybridExecutor<'a> {
         let d1_params: Vec<_> = params.iter().map(to_d1_type).collect();
         
         // Execute update
+        self.explain(&sql, &d1_params).await;
         let unbound_stmt = self.d1.prepare(&sql);
         unbound_stmt.bind_refs(&d1_params)?.run().await?;
         
@@ -107,6 +249,7 @@ impl<'a> HybridExecutor<'a> {
         let d1_params: Vec<_> = params.iter().map(to_d1_type).collect();
         
         // Execute delete
+        self.explain(&sql, &d1_params).await;
         let unbound_stmt = self.d1.prepare(&sql);
         unbound_stmt.bind_refs(&d1_params)?.run().await?;
         
@@ -118,11 +261,62 @@ impl<'a> HybridExecutor<'a> {
     where
         T: DeserializeOwned,
     {
+        self.explain(sql, &params).await;
         let unbound_stmt = self.d1.prepare(sql);
         let results: Vec<T> = unbound_stmt.bind_refs(&params)?.all().await?.results()?;
         Ok(results)
     }
 
+    /// The SQL `exec_query` would run for a query, without running it
+    pub fn preview_query<M>(&self, query: impl IntoSelect<Model = M>) -> Result<SqlPreview>
+    where
+        M: Model,
+    {
+        let statement: toasty::stmt::Statement<M> = query.into_select().into();
+        let (sql, params) = statement_to_sql(statement, &self.schema)?;
+        Ok(SqlPreview::new(sql, &params))
+    }
+
+    /// The SQL `exec_query_columns` would run for a query, without running it
+    pub fn preview_query_columns<M>(&self, query: impl IntoSelect<Model = M>, columns: &[&str]) -> Result<SqlPreview>
+    where
+        M: Model,
+    {
+        let statement: toasty::stmt::Statement<M> = query.into_select().into();
+        let (sql, params) = projected_statement_to_sql(statement, &self.schema, columns)?;
+        Ok(SqlPreview::new(sql, &params))
+    }
+
+    /// The SQL `exec_count` would run for a query, without running it
+    pub fn preview_count<M>(&self, query: impl IntoSelect<Model = M>) -> Result<SqlPreview>
+    where
+        M: Model,
+    {
+        let statement: toasty::stmt::Statement<M> = query.into_select().into();
+        let (sql, params) = count_statement_to_sql(statement, &self.schema)?;
+        Ok(SqlPreview::new(sql, &params))
+    }
+
+    /// Log the query plan of generated SQL when `EXPLAIN_QUERIES` is on, warning about
+    /// full table scans. Failures are logged and never fail the query itself.
+    async fn explain(&self, sql: &str, params: &[D1Type<'_>]) {
+        if !EXPLAIN_QUERIES.load(Ordering::Relaxed) {
+            return;
+        }
+        let plan: Vec<QueryPlanRow> = match self.d1.prepare(format!("EXPLAIN QUERY PLAN {}", sql)).bind_refs(params) {
+            Ok(stmt) => match stmt.all().await.and_then(|result| result.results()) {
+                Ok(rows) => rows,
+                Err(e) => return warn!(sql, "Failed to explain query: {}", e),
+            },
+            Err(e) => return warn!(sql, "Failed to bind query to explain: {}", e),
+        };
+        let details: Vec<&str> = plan.iter().map(|row| row.detail.as_str()).collect();
+        debug!(sql, plan = ?details, "Query plan");
+        for detail in details.iter().filter(|detail| is_full_table_scan(detail)) {
+            warn!(sql, step = %detail, "Query does a full table scan; consider an index.");
+        }
+    }
+
     /// Get the underlying D1 database for direct access
     pub fn d1(&self) -> &D1Database {
         self.d1
@@ -3,7 +3,7 @@
 
 use crate::dbmodels::Key as DbKey;
 use crate::hybrid::{HybridExecutor, schema_builder};
-use crate::state::strategy::{ApiKey, ApiKeyStatus};
+use crate::state::strategy::{ApiKey, ApiKeyStatus, KeyTier};
 use anyhow::Result;
 use js_sys::Date;
 use serde_json;
@@ -125,47 +125,27 @@ pub async fn list_keys_hybrid(
     let executor = HybridExecutor::new(db, schema);
     
     // Build base query
-    let query = DbKey::filter_by_provider(provider.to_string())
-        .filter_by_status(status.to_string());
-    
-    // Since Toasty doesn't have built-in limit/offset, we need to handle pagination manually
-    // First, get all matching records
-    let all_results = executor.exec_query(query).await?;
-    let total = all_results.len() as i32;
-    
-    // Sort the results based on the sort criteria
-    let mut sorted_results = all_results;
-    match sort_by {
-        "createdAt" => {
-            if sort_order == "asc" {
-                sorted_results.sort_by_key(|k| k.created_at);
-            } else {
-                sorted_results.sort_by_key(|k| std::cmp::Reverse(k.created_at));
-            }
-        }
-        "totalCoolingSeconds" => {
-            if sort_order == "asc" {
-                sorted_results.sort_by_key(|k| k.total_cooling_seconds);
-            } else {
-                sorted_results.sort_by_key(|k| std::cmp::Reverse(k.total_cooling_seconds));
-            }
-        }
-        _ => {
-            if sort_order == "asc" {
-                sorted_results.sort_by_key(|k| k.updated_at);
-            } else {
-                sorted_results.sort_by_key(|k| std::cmp::Reverse(k.updated_at));
-            }
-        }
-    }
+    let query = || {
+        DbKey::filter_by_provider(provider.to_string())
+            .filter_by_status(status.to_string())
+    };
     
-    // Apply pagination
+    // Count in D1 rather than loading every matching record
+    let total = executor.exec_count(query()).await? as i32;
+    
+    // Sorting, limit and offset are all pushed down into the SQL
+    let order = match (sort_by, sort_order) {
+        ("createdAt", "asc") => DbKey::FIELDS.created_at.asc(),
+        ("createdAt", _) => DbKey::FIELDS.created_at.desc(),
+        ("totalCoolingSeconds", "asc") => DbKey::FIELDS.total_cooling_seconds.asc(),
+        ("totalCoolingSeconds", _) => DbKey::FIELDS.total_cooling_seconds.desc(),
+        (_, "asc") => DbKey::FIELDS.updated_at.asc(),
+        _ => DbKey::FIELDS.updated_at.desc(),
+    };
     let offset = (page - 1) * page_size;
-    let paginated_results: Vec<DbKey> = sorted_results
-        .into_iter()
-        .skip(offset)
-        .take(page_size)
-        .collect();
+    let paginated_results = executor
+        .exec_query(query().order_by(order).limit(page_size as i64).offset(offset as i64))
+        .await?;
     
     // Convert to API models
     let api_keys: Vec<ApiKey> = paginated_results.into_iter().map(db_key_to_api_key).collect();
@@ -189,10 +169,19 @@ fn db_key_to_api_key(db_key: DbKey) -> ApiKey {
         created_at: db_key.created_at as u64,
         updated_at: db_key.updated_at as u64,
         latency_ms: db_key.latency_ms,
+        latency_ewma_ms: db_key.latency_ewma_ms,
+        latency_p50_ms: 0,
+        latency_p95_ms: 0,
         success_rate: db_key.success_rate as f64 / 1000.0,
         consecutive_failures: db_key.consecutive_failures,
         last_checked_at: db_key.last_checked_at as u64,
         last_succeeded_at: db_key.last_succeeded_at as u64,
+        recent_requests: db_key.usage_window_requests as u64,
+        tier: KeyTier::from_db(&db_key.tier),
+        block_reason: db_key.block_reason,
+        note: db_key.note,
+        tags: crate::state::strategy::split_tags(&db_key.tags),
+        weight: db_key.weight,
     }
 }
 
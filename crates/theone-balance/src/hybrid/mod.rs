@@ -5,7 +5,7 @@ pub mod schema_builder;
 pub mod example_usage;
 pub mod update_support;
 
-pub use d1_executor::HybridExecutor;
-pub use sql_converter::{statement_to_sql, to_d1_type};
+pub use d1_executor::{HybridExecutor, SqlPreview};
+pub use sql_converter::{count_statement_to_sql, d1_integer, projected_statement_to_sql, statement_to_sql, to_d1_type};
 pub use result_mapper::map_d1_results;
 pub use schema_builder::{build_schema, create_d1_schema, get_schema};
\ No newline at end of file
@@ -1,4 +1,7 @@
-use crate::dbmodels::Key as DbKey;
+use crate::dbmodels::{
+    AlertEvent, ClientKey, CustomProvider, Key as DbKey, KeyEvent, KeyScoreSample, KeyTag, MetricSeries, ModelCatalog,
+    ProviderSetting, RequestEvent, Sample, Session, Setting, TotpSecret, UsageEvent,
+};
 use std::sync::Arc;
 use toasty::Model;
 use toasty_core::schema;
@@ -12,7 +15,24 @@ pub struct HybridSchema {
 /// Build the database schema for our models using Toasty's schema generation
 pub fn build_schema() -> HybridSchema {
     let builder = schema::Builder::default();
-    let app_schema = schema::app::Schema::from_macro(&[DbKey::schema()])
+    let app_schema = schema::app::Schema::from_macro(&[
+        DbKey::schema(),
+        ClientKey::schema(),
+        UsageEvent::schema(),
+        Sample::schema(),
+        ModelCatalog::schema(),
+        ProviderSetting::schema(),
+        CustomProvider::schema(),
+        MetricSeries::schema(),
+        RequestEvent::schema(),
+        KeyScoreSample::schema(),
+        AlertEvent::schema(),
+        KeyEvent::schema(),
+        Setting::schema(),
+        Session::schema(),
+        TotpSecret::schema(),
+        KeyTag::schema(),
+    ])
         .expect("Failed to build app schema");
     let full_schema = builder
         .build(app_schema, &toasty_core::driver::Capability::SQLITE)
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use toasty::stmt::Statement;
 use toasty_core::stmt::Value;
 
@@ -29,32 +29,253 @@ pub fn statement_to_sql<M>(
     // Serialize the lowered statement to SQL
     let sql = serializer.serialize(&sql_stmt, &mut params);
     
+    Ok((sql, params.into_iter().map(bindable_value).collect()))
+}
+
+/// The largest integer a JS number (and so a D1 parameter) holds exactly: 2^53 - 1.
+const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+/// Turns 64-bit integers a JS number can't hold exactly into decimal strings. SQLite
+/// converts them back to integers for INTEGER columns, so nothing is lost in transit.
+fn bindable_value(value: Value) -> Value {
+    match value {
+        Value::I64(v) if !(-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).contains(&v) => Value::String(v.to_string()),
+        other => other,
+    }
+}
+
+/// Convert a Toasty SELECT into a `COUNT(*)` over the same rows, so the count is computed in D1.
+/// The select is wrapped as a subquery, keeping its filters (and any limit) intact.
+pub fn count_statement_to_sql<M>(
+    statement: Statement<M>,
+    schema: &toasty_core::schema::db::Schema,
+) -> Result<(String, Vec<Value>)> {
+    let (sql, params) = statement_to_sql(statement, schema)?;
+    let inner = sql.trim_end().trim_end_matches(';');
+    Ok((format!("SELECT COUNT(*) AS count FROM ({});", inner), params))
+}
+
+/// Convert a Toasty SELECT into one returning only the given columns, so hot queries don't
+/// read columns they ignore (e.g. the `model_coolings` JSON). Every column must be one the
+/// full select returns.
+pub fn projected_statement_to_sql<M>(
+    statement: Statement<M>,
+    schema: &toasty_core::schema::db::Schema,
+    columns: &[&str],
+) -> Result<(String, Vec<Value>)> {
+    let (sql, params) = statement_to_sql(statement, schema)?;
+    let Some(from) = sql.find(" FROM ").filter(|_| sql.starts_with("SELECT ")) else {
+        bail!("Only SELECT statements can be projected");
+    };
+    let available: Vec<&str> = sql["SELECT ".len()..from].split(", ").collect();
+    let mut projected = Vec::with_capacity(columns.len());
+    for column in columns {
+        let quoted = format!("\"{}\"", column);
+        if !available.contains(&quoted.as_str()) {
+            bail!("Unknown column '{}' in projection", column);
+        }
+        projected.push(quoted);
+    }
+    Ok((format!("SELECT {}{}", projected.join(", "), &sql[from..]), params))
+}
+
+/// Convert a Toasty INSERT into one that silently skips rows violating a unique constraint,
+/// so concurrent inserts of the same row can't fail or create duplicates.
+pub fn insert_ignoring_conflicts_to_sql<M>(
+    statement: Statement<M>,
+    schema: &toasty_core::schema::db::Schema,
+) -> Result<(String, Vec<Value>)> {
+    let (sql, params) = statement_to_sql(statement, schema)?;
+    let sql = match sql.rfind("RETURNING") {
+        Some(pos) => format!("{} ON CONFLICT DO NOTHING {}", sql[..pos].trim_end(), &sql[pos..]),
+        None => format!("{} ON CONFLICT DO NOTHING;", sql.trim_end().trim_end_matches(';')),
+    };
     Ok((sql, params))
 }
 
-/// Convert Toasty value to D1-compatible value
-pub fn to_d1_type(value: &Value) -> worker::D1Type<'static> {
+/// Convert Toasty value to D1-compatible value. Text borrows from the value, so the
+/// parameters must outlive the `bind_refs` call that consumes the result.
+pub fn to_d1_type(value: &Value) -> worker::D1Type<'_> {
     match value {
         Value::Bool(v) => worker::D1Type::Boolean(*v),
         Value::I32(v) => worker::D1Type::Integer(*v),
-        Value::I64(v) => worker::D1Type::Integer(*v as i32), // D1 only supports i32
-        Value::String(v) => {
-            // We need to leak the string to get 'static lifetime
-            let leaked: &'static str = Box::leak(v.clone().into_boxed_str());
-            worker::D1Type::Text(leaked)
-        }
-        Value::Id(id) => {
-            // For ID values, we need to convert to owned string and leak it
-            let id_str = id.to_string();
-            let leaked: &'static str = Box::leak(id_str.into_boxed_str());
-            worker::D1Type::Text(leaked)
-        }
+        Value::I64(v) => d1_integer(*v),
+        Value::String(v) => worker::D1Type::Text(v),
+        Value::Id(id) => match id.as_str() {
+            Some(id) => worker::D1Type::Text(id),
+            None => worker::D1Type::Integer(id.to_int().unwrap_or_default() as i32),
+        },
         Value::Null => worker::D1Type::Null,
         _ => worker::D1Type::Null, // Fallback for unsupported types
     }
 }
 
-/// Convert a vector of Toasty values to D1-compatible values
-pub fn convert_values_for_d1(values: Vec<Value>) -> Vec<worker::D1Type<'static>> {
+/// Binds an integer to D1. D1 integers are i32, so wider values, e.g. millisecond
+/// timestamps or large counters, are bound as doubles, which are exact up to 2^53 (see
+/// `bindable_value`). Raw statements bind their integers through here too.
+pub fn d1_integer(v: i64) -> worker::D1Type<'static> {
+    match i32::try_from(v) {
+        Ok(v) => worker::D1Type::Integer(v),
+        Err(_) => worker::D1Type::Real(v as f64),
+    }
+}
+
+/// Convert a slice of Toasty values to D1-compatible values
+pub fn convert_values_for_d1(values: &[Value]) -> Vec<worker::D1Type<'_>> {
     values.iter().map(to_d1_type).collect()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbmodels::Key as DbKey;
+    use crate::hybrid::schema_builder::get_schema;
+    use toasty::stmt::{IntoInsert, IntoSelect};
+    use worker::D1Type;
+
+    /// The SQL from `FROM` on, and the parameters, of a key query.
+    fn select_sql(query: impl IntoSelect<Model = DbKey>) -> (String, Vec<Value>) {
+        let statement: Statement<DbKey> = query.into_select().into();
+        let (sql, params) = statement_to_sql(statement, get_schema()).unwrap();
+        let from = sql.find(" FROM ").expect("a SELECT statement");
+        (sql[from + 1..].to_string(), params)
+    }
+
+    fn insert_key_with_timestamps(created_at: i64, last_checked_at: i64) -> Statement<DbKey> {
+        DbKey::create()
+            .key("sk-test".to_string())
+            .key_hash("0000000000000000".to_string())
+            .provider("openai".to_string())
+            .status("active".to_string())
+            .model_coolings("{}".to_string())
+            .total_cooling_seconds(0)
+            .created_at(created_at)
+            .updated_at(created_at)
+            .latency_ms(0)
+            .latency_ewma_ms(0)
+            .latency_histogram("[]".to_string())
+            .success_rate(1000)
+            .consecutive_failures(0)
+            .last_checked_at(last_checked_at)
+            .last_succeeded_at(0)
+            .deleted_at(0)
+            .usage_window_start(0)
+            .usage_window_requests(0)
+            .usage_prev_window_requests(0)
+            .tier("free".to_string())
+            .block_reason(String::new())
+            .blocked_at(0)
+            .reactivations(0)
+            .note(String::new())
+            .tags(String::new())
+            .weight(0)
+            .into_insert()
+            .into()
+    }
+
+    /// Reads a bound parameter back as the integer SQLite would store in an INTEGER column.
+    fn stored_integer(param: &D1Type) -> Option<i64> {
+        match param {
+            D1Type::Integer(v) => Some(*v as i64),
+            D1Type::Real(v) if v.fract() == 0.0 => Some(*v as i64),
+            D1Type::Text(v) => v.parse().ok(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn i32_values_bind_as_integers() {
+        assert!(matches!(to_d1_type(&Value::I64(1_700_000_000)), D1Type::Integer(1_700_000_000)));
+        assert!(matches!(to_d1_type(&Value::I64(-5)), D1Type::Integer(-5)));
+    }
+
+    #[test]
+    fn timestamps_beyond_i32_round_trip() {
+        // Millisecond timestamps and seconds past 2038 both overflow i32.
+        for timestamp in [1_760_000_000_000_i64, 2_200_000_000, MAX_SAFE_INTEGER, i64::MAX, i64::MIN] {
+            let (_, params) = statement_to_sql(insert_key_with_timestamps(timestamp, timestamp), get_schema()).unwrap();
+            let bound: Vec<Option<i64>> = params.iter().map(|p| stored_integer(&to_d1_type(p))).collect();
+            assert_eq!(bound.iter().filter(|v| **v == Some(timestamp)).count(), 3, "timestamp {}", timestamp);
+        }
+    }
+
+    #[test]
+    fn unsafe_integers_bind_as_text() {
+        assert!(matches!(bindable_value(Value::I64(MAX_SAFE_INTEGER)), Value::I64(_)));
+        assert!(matches!(bindable_value(Value::I64(MAX_SAFE_INTEGER + 1)), Value::String(s) if s == "9007199254740992"));
+        assert!(matches!(bindable_value(Value::I64(-MAX_SAFE_INTEGER - 1)), Value::String(_)));
+    }
+
+    #[test]
+    fn order_by_limit_and_offset_are_pushed_down() {
+        let query = DbKey::filter_by_provider("openai".to_string())
+            .filter_by_status("active".to_string())
+            .order_by(DbKey::FIELDS.created_at.desc())
+            .limit(20)
+            .offset(40);
+        let (sql, params) = select_sql(query);
+        assert_eq!(
+            sql,
+            r#"FROM "keys" WHERE "provider" = ?1 AND "status" = ?2 ORDER BY "created_at" DESC LIMIT ?3 OFFSET ?4;"#
+        );
+        assert!(matches!(params.as_slice(), [Value::String(_), Value::String(_), Value::I64(20), Value::I64(40)]));
+    }
+
+    #[test]
+    fn order_by_alone_is_pushed_down() {
+        let (sql, params) = select_sql(
+            DbKey::filter_by_provider("openai".to_string()).order_by(DbKey::FIELDS.total_cooling_seconds.asc()),
+        );
+        assert_eq!(sql, r#"FROM "keys" WHERE "provider" = ?1 ORDER BY "total_cooling_seconds" ASC;"#);
+        assert_eq!(params.len(), 1);
+    }
+
+    #[test]
+    fn limit_alone_is_pushed_down() {
+        let (sql, params) = select_sql(DbKey::filter_by_provider("openai".to_string()).limit(5));
+        assert_eq!(sql, r#"FROM "keys" WHERE "provider" = ?1 LIMIT ?2;"#);
+        assert!(matches!(params.as_slice(), [Value::String(_), Value::I64(5)]));
+    }
+
+    #[test]
+    fn offset_without_limit_keeps_every_remaining_row() {
+        // SQLite needs a LIMIT before OFFSET; the builder fills in i64::MAX, bound as text.
+        let (sql, params) = select_sql(DbKey::filter_by_provider("openai".to_string()).offset(10));
+        assert_eq!(sql, r#"FROM "keys" WHERE "provider" = ?1 LIMIT ?2 OFFSET ?3;"#);
+        assert!(matches!(&params[1], Value::String(v) if v == &i64::MAX.to_string()));
+        assert!(matches!(params[2], Value::I64(10)));
+    }
+
+    #[test]
+    fn count_wraps_the_ordered_limited_select() {
+        let statement: Statement<DbKey> = DbKey::filter_by_provider("openai".to_string())
+            .order_by(DbKey::FIELDS.created_at.asc())
+            .limit(5)
+            .into_select()
+            .into();
+        let (sql, _) = count_statement_to_sql(statement, get_schema()).unwrap();
+        assert!(sql.starts_with("SELECT COUNT(*) AS count FROM (SELECT "));
+        assert!(sql.ends_with(r#"FROM "keys" WHERE "provider" = ?1 ORDER BY "created_at" ASC LIMIT ?2);"#));
+    }
+
+    #[test]
+    fn projection_selects_only_the_given_columns() {
+        let statement: Statement<DbKey> = DbKey::filter_by_provider("openai".to_string())
+            .order_by(DbKey::FIELDS.created_at.desc())
+            .limit(20)
+            .into_select()
+            .into();
+        let (sql, params) = projected_statement_to_sql(statement, get_schema(), &["id", "key", "created_at"]).unwrap();
+        assert_eq!(
+            sql,
+            r#"SELECT "id", "key", "created_at" FROM "keys" WHERE "provider" = ?1 ORDER BY "created_at" DESC LIMIT ?2;"#
+        );
+        assert_eq!(params.len(), 2);
+    }
+
+    #[test]
+    fn projection_rejects_unknown_columns() {
+        let statement: Statement<DbKey> = DbKey::filter_by_provider("openai".to_string()).into_select().into();
+        assert!(projected_statement_to_sql(statement, get_schema(), &["id", "secret\" FROM x; --"]).is_err());
+    }
+}
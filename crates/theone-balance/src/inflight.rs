@@ -0,0 +1,232 @@
+//! This module keeps a registry of the proxied requests in flight, so a runaway stream can
+//! be found and cancelled from the admin API during an incident.
+//!
+//! The registry is a single Durable Object, bound as `INFLIGHT_REGISTRY`; without the
+//! binding nothing is tracked. Each request holds a WebSocket to it for as long as it
+//! runs: the socket's attachment is the registry entry, and the socket closing removes
+//! it, whether the request finished or its isolate went away. Cancelling sends a message
+//! over the socket, on which the request aborts its controller, the same way the overall
+//! timeout does.
+
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use worker::wasm_bindgen;
+use worker::wasm_bindgen_futures::spawn_local;
+use worker::{
+    durable_object, Date, Env, Headers, Method, Request, RequestInit, Response, Result, State, WebSocket,
+    WebSocketIncomingMessage, WebSocketPair, WebsocketEvent,
+};
+
+pub const BINDING: &str = "INFLIGHT_REGISTRY";
+/// All requests register with the same instance, so it can list them all.
+const INSTANCE: &str = "registry";
+const CANCEL_MESSAGE: &str = "cancel";
+
+/// A request in flight, as listed by the admin API.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InflightRequest {
+    pub request_id: String,
+    pub provider: String,
+    pub model: String,
+    /// The key of the current attempt, empty before the first one.
+    pub key_id: String,
+    /// Unix milliseconds.
+    pub started_at: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyUpdate {
+    key_id: String,
+}
+
+// region: --- Registry Durable Object
+
+#[durable_object]
+pub struct InflightRegistry {
+    state: State,
+}
+
+impl DurableObject for InflightRegistry {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&self, req: Request) -> Result<Response> {
+        let path = req.path();
+        match (req.method(), path.as_str()) {
+            (Method::Get, "/register") => self.register(&req),
+            (Method::Get, "/requests") => self.list(),
+            (Method::Post, path) if path.starts_with("/cancel/") => self.cancel(&path["/cancel/".len()..]),
+            _ => Response::error("Not Found", 404),
+        }
+    }
+
+    async fn websocket_message(&self, ws: WebSocket, message: WebSocketIncomingMessage) -> Result<()> {
+        // The only message a request sends is the key of its current attempt.
+        if let WebSocketIncomingMessage::String(text) = message {
+            if let (Ok(update), Some(mut entry)) = (
+                serde_json::from_str::<KeyUpdate>(&text),
+                ws.deserialize_attachment::<InflightRequest>()?,
+            ) {
+                entry.key_id = update.key_id;
+                ws.serialize_attachment(&entry)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn websocket_close(&self, ws: WebSocket, _code: usize, _reason: String, _was_clean: bool) -> Result<()> {
+        // Completes the close handshake; the entry goes with the socket.
+        let _ = ws.close(Some(1000), Some("done"));
+        Ok(())
+    }
+
+    async fn websocket_error(&self, ws: WebSocket, _error: worker::Error) -> Result<()> {
+        let _ = ws.close(Some(1011), Some("error"));
+        Ok(())
+    }
+}
+
+impl InflightRegistry {
+    fn register(&self, req: &Request) -> Result<Response> {
+        let url = req.url()?;
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.into_owned())
+                .unwrap_or_default()
+        };
+        let entry = InflightRequest {
+            request_id: param("request_id"),
+            provider: param("provider"),
+            model: param("model"),
+            key_id: String::new(),
+            started_at: Date::now().as_millis(),
+        };
+        if entry.request_id.is_empty() {
+            return Response::error("request_id is required", 400);
+        }
+
+        let pair = WebSocketPair::new()?;
+        self.state.accept_websocket_with_tags(&pair.server, &[entry.request_id.as_str()]);
+        pair.server.serialize_attachment(&entry)?;
+        Response::from_websocket(pair.client)
+    }
+
+    fn list(&self) -> Result<Response> {
+        let mut requests: Vec<InflightRequest> = self
+            .state
+            .get_websockets()
+            .iter()
+            .filter_map(|ws| ws.deserialize_attachment().ok().flatten())
+            .collect();
+        requests.sort_by_key(|r| r.started_at);
+        Response::from_json(&requests)
+    }
+
+    fn cancel(&self, request_id: &str) -> Result<Response> {
+        let sockets = self.state.get_websockets_with_tag(request_id);
+        if sockets.is_empty() {
+            return Response::error("Not Found", 404);
+        }
+        for ws in &sockets {
+            ws.send_with_str(CANCEL_MESSAGE)?;
+        }
+        Ok(Response::empty()?.with_status(202))
+    }
+}
+
+// endregion: --- Registry Durable Object
+
+// region: --- Client
+
+async fn registry_fetch(env: &Env, req: Request) -> Result<Response> {
+    let namespace = env.durable_object(BINDING)?;
+    let stub = namespace.id_from_name(INSTANCE)?.get_stub()?;
+    stub.fetch_with_request(req).await
+}
+
+/// The requests in flight, oldest first.
+pub async fn list(env: &Env) -> Result<Vec<InflightRequest>> {
+    let req = Request::new("https://inflight/requests", Method::Get)?;
+    registry_fetch(env, req).await?.json().await
+}
+
+/// Asks the request to abort. Returns false if no such request is in flight.
+pub async fn cancel(env: &Env, request_id: &str) -> Result<bool> {
+    let mut url = url::Url::parse("https://inflight/cancel")?;
+    url.path_segments_mut()
+        .map_err(|_| worker::Error::RustError("Invalid registry URL".to_string()))?
+        .push(request_id);
+    let resp = registry_fetch(env, Request::new(url.as_str(), Method::Post)?).await?;
+    match resp.status_code() {
+        404 => Ok(false),
+        200..=299 => Ok(true),
+        status => Err(worker::Error::RustError(format!("Registry answered the cancel with {}", status))),
+    }
+}
+
+/// A request's entry in the registry, removed when this is dropped.
+pub struct Registration {
+    ws: WebSocket,
+}
+
+impl Registration {
+    /// Registers the request, aborting `controller` if an admin cancels it. Returns `None`
+    /// when the registry isn't bound.
+    pub async fn open(
+        env: &Env,
+        request_id: &str,
+        provider: &str,
+        model: &str,
+        controller: worker::web_sys::AbortController,
+    ) -> Result<Option<Self>> {
+        if env.durable_object(BINDING).is_err() {
+            return Ok(None);
+        }
+        let query = serde_urlencoded::to_string([("request_id", request_id), ("provider", provider), ("model", model)])
+            .map_err(|e| worker::Error::RustError(e.to_string()))?;
+        let headers = Headers::new();
+        headers.set("Upgrade", "websocket")?;
+        let mut init = RequestInit::new();
+        init.with_headers(headers);
+        let req = Request::new_with_init(&format!("https://inflight/register?{}", query), &init)?;
+        let ws = registry_fetch(env, req)
+            .await?
+            .websocket()
+            .ok_or_else(|| worker::Error::RustError("Registry did not accept the WebSocket".to_string()))?;
+        ws.accept()?;
+
+        let listener = ws.clone();
+        let request_id = request_id.to_string();
+        spawn_local(async move {
+            let Ok(mut events) = listener.events() else {
+                return;
+            };
+            while let Some(Ok(WebsocketEvent::Message(message))) = events.next().await {
+                if message.text().as_deref() == Some(CANCEL_MESSAGE) {
+                    warn!(request_id = %request_id, "Request cancelled through the admin API. Aborting.");
+                    controller.abort();
+                    break;
+                }
+            }
+        });
+        Ok(Some(Registration { ws }))
+    }
+
+    /// Records the key of the current attempt.
+    pub fn set_key(&self, key_id: &str) {
+        if let Ok(update) = serde_json::to_string(&KeyUpdate { key_id: key_id.to_string() }) {
+            let _ = self.ws.send_with_str(update);
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let _ = self.ws.close(Some(1000), Some("done"));
+    }
+}
+
+// endregion: --- Client
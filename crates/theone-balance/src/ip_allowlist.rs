@@ -0,0 +1,108 @@
+//! This module restricts the management surface (login, UI pages and the admin API) to
+//! client IPs in an optional CIDR allowlist, so leaked credentials alone are not enough
+//! to reach it. The proxy routes are not affected.
+//!
+//! The allowlist is read from `ADMIN_IP_ALLOWLIST`, a comma-separated list of CIDRs or
+//! bare addresses, and checked against the `CF-Connecting-IP` header set by Cloudflare.
+
+use crate::{admin::admin_error, AppState};
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::net::IpAddr;
+use std::sync::Arc;
+use tracing::warn;
+use worker::Env;
+
+const CLIENT_IP_HEADER: &str = "CF-Connecting-IP";
+
+struct Cidr {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl Cidr {
+    fn parse(value: &str) -> Option<Self> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value, None),
+        };
+        let network: IpAddr = addr.trim().parse().ok()?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.trim().parse().ok().filter(|p| *p <= max)?,
+            None => max,
+        };
+        Some(Cidr { network, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+pub struct IpAllowlist {
+    ranges: Vec<Cidr>,
+}
+
+impl IpAllowlist {
+    /// Returns `None` when no allowlist is configured, i.e. every IP is allowed.
+    /// Entries that don't parse are logged and ignored.
+    pub fn from_env(env: &Env) -> Option<Self> {
+        let value = env.var("ADMIN_IP_ALLOWLIST").ok()?.to_string();
+        if value.trim().is_empty() {
+            return None;
+        }
+        let ranges = value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .filter_map(|entry| {
+                let cidr = Cidr::parse(entry);
+                if cidr.is_none() {
+                    warn!("Ignoring invalid ADMIN_IP_ALLOWLIST entry '{}'", entry);
+                }
+                cidr
+            })
+            .collect();
+        Some(IpAllowlist { ranges })
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        self.ranges.iter().any(|range| range.contains(ip))
+    }
+}
+
+/// Middleware for the management routes. Requests without a parsable client IP are
+/// rejected whenever an allowlist is configured.
+pub async fn guard(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    if let Some(allowlist) = IpAllowlist::from_env(&state.env) {
+        let client_ip = req
+            .headers()
+            .get(CLIENT_IP_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<IpAddr>().ok());
+        if !client_ip.is_some_and(|ip| allowlist.allows(ip)) {
+            warn!(client_ip = ?client_ip, path = %req.uri().path(), "Blocked management request from an IP outside the allowlist.");
+            return if req.uri().path().starts_with("/api/admin") {
+                admin_error(StatusCode::FORBIDDEN, "Client IP is not allowed.")
+            } else {
+                (StatusCode::FORBIDDEN, "Forbidden").into_response()
+            };
+        }
+    }
+    next.run(req).await
+}
@@ -0,0 +1,159 @@
+//! This module reports key health transitions to an optional webhook, so dashboards and
+//! incident tools (Grafana annotations, paging) can follow the key pool without polling.
+//!
+//! Events are POSTed as JSON to `KEY_EVENTS_WEBHOOK_URL` when a key is blocked as invalid,
+//! enters a daily-quota cooldown, recovers after the circuit breaker sidelined it, or is
+//! reactivated by the blocked-key probation (see `testing::probe_blocked_keys`). When
+//! `KEY_EVENTS_WEBHOOK_SECRET` is set it is sent as a Bearer token. Delivery is best
+//! effort: a failed POST is logged and not retried.
+//!
+//! Provider-wide anomalies go to the same webhook: `provider_degraded` when a provider's
+//! latency or error rate departs from its baseline (see `provider_health`), often before
+//! its keys fail outright, and `provider_recovered` once it is back.
+
+use js_sys::Date;
+use crate::provider_health::Transition;
+use serde::Serialize;
+use tracing::{error, info, warn};
+use worker::{Env, Fetch, Headers, Method, Request, RequestInit};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyTransition {
+    /// Active → Blocked: the provider rejected the key as invalid.
+    Blocked,
+    /// The key ran out of its daily quota for a model.
+    DailyQuotaCooldown,
+    /// A key sidelined by the circuit breaker served a request again.
+    Recovered,
+    /// Blocked → Active: the key passed a probation test.
+    Reactivated,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct KeyHealthEvent {
+    pub event: KeyTransition,
+    pub key_id: String,
+    /// Hash of the key, for correlating with request events; the key itself is never sent.
+    pub key_hash: String,
+    pub provider: String,
+    /// The model the transition applies to, empty when it applies to the whole key.
+    pub model: String,
+    pub reason: String,
+    /// For cooldowns, how long the key is benched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cooldown_seconds: Option<u64>,
+    /// Unix seconds.
+    pub at: u64,
+}
+
+impl KeyHealthEvent {
+    pub fn new(event: KeyTransition, key_id: &str, key_hash: &str, provider: &str, model: &str, reason: &str) -> Self {
+        KeyHealthEvent {
+            event,
+            key_id: key_id.to_string(),
+            key_hash: key_hash.to_string(),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            reason: reason.to_string(),
+            cooldown_seconds: None,
+            at: (Date::now() / 1000.0) as u64,
+        }
+    }
+
+    pub fn with_cooldown(mut self, seconds: u64) -> Self {
+        self.cooldown_seconds = Some(seconds);
+        self
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderTransition {
+    /// The provider's latency or error rate departed from its baseline.
+    ProviderDegraded,
+    /// The provider's latency and error rate are back to their baseline.
+    ProviderRecovered,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ProviderHealthEvent {
+    pub event: ProviderTransition,
+    pub provider: String,
+    /// `latency` or `errors` for `provider_degraded`, empty otherwise.
+    pub anomaly: String,
+    pub reason: String,
+    pub baseline_latency_ms: u64,
+    /// Mean latency of the successful attempts over the last minute.
+    pub window_latency_ms: u64,
+    pub baseline_error_rate: f64,
+    pub window_error_rate: f64,
+    /// Unix seconds.
+    pub at: u64,
+}
+
+impl ProviderHealthEvent {
+    pub fn new(provider: &str, transition: &Transition) -> Self {
+        ProviderHealthEvent {
+            event: match transition.anomaly {
+                Some(_) => ProviderTransition::ProviderDegraded,
+                None => ProviderTransition::ProviderRecovered,
+            },
+            provider: provider.to_string(),
+            anomaly: transition.anomaly.map(|a| a.as_str().to_string()).unwrap_or_default(),
+            reason: transition.reason(),
+            baseline_latency_ms: transition.baseline_latency_ms,
+            window_latency_ms: transition.window_latency_ms,
+            baseline_error_rate: transition.baseline_error_rate,
+            window_error_rate: transition.window_error_rate,
+            at: (Date::now() / 1000.0) as u64,
+        }
+    }
+}
+
+/// Logs the event and POSTs it to the webhook when one is configured.
+pub async fn emit(env: &Env, event: KeyHealthEvent) {
+    info!(event = ?event.event, key_hash = %event.key_hash, provider = %event.provider, model = %event.model, reason = %event.reason, "Key health transition.");
+    deliver(env, &event).await;
+}
+
+/// Logs the event and POSTs it to the webhook when one is configured.
+pub async fn emit_provider(env: &Env, event: ProviderHealthEvent) {
+    warn!(event = ?event.event, provider = %event.provider, reason = %event.reason, "Provider health transition.");
+    deliver(env, &event).await;
+}
+
+async fn deliver(env: &Env, event: &impl Serialize) {
+    let Ok(url) = env.var("KEY_EVENTS_WEBHOOK_URL").map(|v| v.to_string()) else {
+        return;
+    };
+    if url.trim().is_empty() {
+        return;
+    }
+    if let Err(e) = post(env, url.trim(), "KEY_EVENTS_WEBHOOK_SECRET", event).await {
+        error!("Failed to deliver health event to the webhook: {}", e);
+    }
+}
+
+/// POSTs `event` as JSON to `url`, with the secret named `secret_name` as a Bearer token
+/// when it is set.
+pub async fn post(env: &Env, url: &str, secret_name: &str, event: &impl Serialize) -> worker::Result<()> {
+    let headers = Headers::new();
+    headers.set("Content-Type", "application/json")?;
+    if let Ok(secret) = env.secret(secret_name) {
+        headers.set("Authorization", &format!("Bearer {}", secret))?;
+    }
+
+    let mut req_init = RequestInit::new();
+    req_init
+        .with_method(Method::Post)
+        .with_headers(headers)
+        .with_body(Some(serde_json::to_string(event)?.into()));
+
+    let req = Request::new_with_init(url, &req_init)?;
+    let resp = Fetch::Request(req).send().await?;
+    if !(200..300).contains(&resp.status_code()) {
+        warn!(status = resp.status_code(), "Webhook answered with a non-2xx status.");
+    }
+    Ok(())
+}
@@ -0,0 +1,120 @@
+//! This module checks the format of provider keys when they are added or imported, so
+//! truncated pastes and keys meant for another provider are rejected with a precise
+//! message instead of joining the pool and failing on every request.
+//!
+//! Only providers with a well-known key format are checked; keys for any other provider
+//! are accepted as they are.
+//!
+//! The sync CLI compiles this file too, so it only depends on `regex` and `once_cell`.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::BTreeMap;
+
+struct KeyFormat {
+    provider: &'static str,
+    pattern: &'static str,
+    /// What the key should look like, for error messages.
+    hint: &'static str,
+}
+
+/// Ordered from the most to the least specific prefix: an Anthropic key also starts with
+/// `sk-`, so it must be recognized before the OpenAI format is tried.
+const KEY_FORMATS: &[KeyFormat] = &[
+    KeyFormat {
+        provider: "anthropic",
+        pattern: r"^sk-ant-[A-Za-z0-9_-]{20,}$",
+        hint: "sk-ant- followed by at least 20 characters",
+    },
+    KeyFormat {
+        provider: "google-ai-studio",
+        pattern: r"^AIza[A-Za-z0-9_-]{35}$",
+        hint: "AIza followed by 35 characters",
+    },
+    KeyFormat {
+        provider: "openai",
+        pattern: r"^sk-[A-Za-z0-9_-]{20,}$",
+        hint: "sk- followed by at least 20 characters",
+    },
+];
+
+static COMPILED: Lazy<Vec<(&'static KeyFormat, Regex)>> = Lazy::new(|| {
+    KEY_FORMATS
+        .iter()
+        .map(|format| (format, Regex::new(format.pattern).expect("key format patterns are valid")))
+        .collect()
+});
+
+/// Masks a key for display and logging as `sk-...abcd`: the first three and last four
+/// characters. Keys too short to mask meaningfully are hidden entirely.
+pub fn partially_redact_key(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() < 12 {
+        return "****".to_string();
+    }
+    let head: String = chars[..3].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}...{}", head, tail)
+}
+
+/// The provider whose key format `key` matches, if any.
+pub fn detect(key: &str) -> Option<&'static str> {
+    COMPILED
+        .iter()
+        .find(|(_, regex)| regex.is_match(key))
+        .map(|(format, _)| format.provider)
+}
+
+/// Splits a pasted list of keys, one per line or separated by commas. A pasted
+/// service-account JSON spans lines and contains commas, so it is taken as one key.
+pub fn split_keys(keys_str: &str) -> impl Iterator<Item = &str> {
+    let single = keys_str.trim_start().starts_with('{');
+    keys_str
+        .split(move |c| !single && (c == '\n' || c == ','))
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+}
+
+/// Checks that `key` has the format of `provider`'s keys. The key is redacted in the error.
+pub fn check(provider: &str, key: &str) -> Result<(), String> {
+    let Some((format, _)) = COMPILED.iter().find(|(format, _)| format.provider == provider) else {
+        return Ok(());
+    };
+    match detect(key) {
+        Some(detected) if detected == provider => Ok(()),
+        Some(detected) => Err(format!(
+            "key {} looks like a key for {}, not for {}",
+            partially_redact_key(key),
+            detected,
+            provider
+        )),
+        None => Err(format!(
+            "key {} is not a valid {} key (expected {})",
+            partially_redact_key(key),
+            provider,
+            format.hint
+        )),
+    }
+}
+
+/// Groups the keys of a pasted list by the provider their format points to, so keys of
+/// several providers can be added in one go. Keys without a recognizable format go to
+/// `fallback`; duplicates are dropped.
+pub fn group_by_provider<'a>(keys_str: &'a str, fallback: &str) -> BTreeMap<String, Vec<&'a str>> {
+    let mut groups: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for key in split_keys(keys_str) {
+        let provider = detect(key).unwrap_or(fallback);
+        let group = groups.entry(provider.to_string()).or_default();
+        if !group.contains(&key) {
+            group.push(key);
+        }
+    }
+    groups
+}
+
+/// Checks every key of a pasted list, returning one message per rejected key.
+pub fn check_all(provider: &str, keys_str: &str) -> Vec<String> {
+    split_keys(keys_str)
+        .filter_map(|key| check(provider, key).err())
+        .collect()
+}
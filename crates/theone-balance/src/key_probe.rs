@@ -0,0 +1,86 @@
+//! This module builds the requests that test a key against its provider, independent of
+//! the HTTP client: the worker sends them with `Fetch` (see `request`), and the sync CLI
+//! compiles this file natively and sends them with reqwest. It must therefore only use
+//! `super::compat` and plain crates, never `worker`.
+
+use super::compat::{self, ModelListEndpoint};
+use phf::phf_map;
+
+pub static PROVIDER_CUSTOM_AUTH_HEADER: phf::Map<&'static str, &'static str> = phf_map! {
+    "google-ai-studio" => "x-goog-api-key",
+    "anthropic" => "x-api-key",
+    "elevenlabs" => "x-api-key",
+    "azure-openai" => "api-key",
+    "cartesia" => "X-API-Key",
+};
+
+/// A request to send as is.
+#[derive(Debug, Clone)]
+pub struct ProbeRequest {
+    /// `GET` or `POST`.
+    pub method: &'static str,
+    pub url: String,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Option<Vec<u8>>,
+}
+
+/// The headers that authenticate `key` with `provider`: its own key header, or a Bearer token.
+pub fn auth_headers(provider: &str, key: &str) -> Vec<(&'static str, String)> {
+    let mut headers = match PROVIDER_CUSTOM_AUTH_HEADER.get(provider) {
+        Some(name) => vec![(*name, key.to_string())],
+        None => vec![("Authorization", format!("Bearer {}", key))],
+    };
+    if provider == "anthropic" {
+        headers.push(("anthropic-version", "2023-06-01".to_string()));
+    }
+    headers
+}
+
+/// A one-word chat request to `model` with the provider's native API.
+pub fn chat_test_request(provider: &str, key: &str, model: &str) -> Result<ProbeRequest, String> {
+    match provider {
+        "google-ai-studio" => {
+            let body = serde_json::json!({
+                "contents": [{ "parts": [{ "text": "hello" }], "role": "user" }],
+            });
+            let mut headers = auth_headers(provider, key);
+            headers.push(("Content-Type", "application/json".to_string()));
+            Ok(ProbeRequest {
+                method: "POST",
+                url: format!(
+                    "https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent",
+                    model
+                ),
+                headers,
+                body: Some(serde_json::to_vec(&body).map_err(|e| e.to_string())?),
+            })
+        }
+        // For now, only google is supported for testing. Return an error for others.
+        _ => Err(format!("Provider '{}' not supported for testing.", provider)),
+    }
+}
+
+/// Lists the provider's models with `key`, which costs no tokens. `None` for providers
+/// whose model list we don't know how to read.
+pub fn model_list_request(provider: &str, key: &str) -> Option<ProbeRequest> {
+    let endpoint = compat::model_list_endpoint(provider)?;
+    Some(ProbeRequest {
+        method: "GET",
+        url: endpoint.url.to_string(),
+        headers: auth_headers(provider, key),
+        body: None,
+    })
+}
+
+/// The bare model names of a model list response.
+pub fn parse_model_list(endpoint: &ModelListEndpoint, body: &serde_json::Value) -> Vec<String> {
+    body.get(endpoint.list_field)
+        .and_then(|list| list.as_array())
+        .map(|list| {
+            list.iter()
+                .filter_map(|entry| entry.get(endpoint.id_field).and_then(|id| id.as_str()))
+                .map(|id| id.strip_prefix(endpoint.strip_prefix).unwrap_or(id).to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
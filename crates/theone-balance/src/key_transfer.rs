@@ -0,0 +1,208 @@
+//! This module contains the key import/export format used to migrate keys between
+//! deployments. Keys are exchanged as JSON arrays or CSV with a header row; both carry
+//! the key's status, health metrics and the note, tags and weight set by operators.
+//! Transient model cooldowns are not exported.
+
+use crate::{csv_line, key_format};
+use crate::state::strategy::{self, ApiKey, ApiKeyStatus, KeyTier};
+use serde::{Deserialize, Serialize};
+
+/// CSV column order, also written as the header row.
+const CSV_COLUMNS: &[&str] = &[
+    "provider",
+    "key",
+    "status",
+    "latency_ms",
+    "success_rate",
+    "consecutive_failures",
+    "total_cooling_seconds",
+    "created_at",
+    "last_checked_at",
+    "last_succeeded_at",
+    "tier",
+    "note",
+    "tags",
+    "weight",
+];
+
+/// One exported key.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KeyRecord {
+    pub provider: String,
+    pub key: String,
+    /// `active` or `blocked`.
+    #[serde(default = "default_status")]
+    pub status: String,
+    #[serde(default)]
+    pub latency_ms: i64,
+    /// Between 0 and 1.
+    #[serde(default = "default_success_rate")]
+    pub success_rate: f64,
+    #[serde(default)]
+    pub consecutive_failures: i64,
+    #[serde(default)]
+    pub total_cooling_seconds: u64,
+    #[serde(default)]
+    pub created_at: u64,
+    #[serde(default)]
+    pub last_checked_at: u64,
+    #[serde(default)]
+    pub last_succeeded_at: u64,
+    /// `free` or `paid`.
+    #[serde(default = "default_tier")]
+    pub tier: String,
+    #[serde(default)]
+    pub note: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub weight: i64,
+}
+
+fn default_status() -> String {
+    "active".to_string()
+}
+
+fn default_tier() -> String {
+    KeyTier::Free.as_str().to_string()
+}
+
+fn default_success_rate() -> f64 {
+    1.0
+}
+
+impl From<ApiKey> for KeyRecord {
+    fn from(key: ApiKey) -> Self {
+        KeyRecord {
+            provider: key.provider,
+            key: key.key,
+            status: match key.status {
+                ApiKeyStatus::Active => "active".to_string(),
+                ApiKeyStatus::Blocked => "blocked".to_string(),
+            },
+            latency_ms: key.latency_ms,
+            success_rate: key.success_rate,
+            consecutive_failures: key.consecutive_failures,
+            total_cooling_seconds: key.total_cooling_seconds,
+            created_at: key.created_at,
+            last_checked_at: key.last_checked_at,
+            last_succeeded_at: key.last_succeeded_at,
+            tier: key.tier.as_str().to_string(),
+            note: key.note,
+            tags: key.tags,
+            weight: key.weight,
+        }
+    }
+}
+
+impl KeyRecord {
+    /// Checks that the record can be imported as is.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.provider.is_empty()
+            || !self
+                .provider
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        {
+            return Err(format!("invalid provider '{}'", self.provider));
+        }
+        if self.status != "active" && self.status != "blocked" {
+            return Err(format!("invalid status '{}'", self.status));
+        }
+        if self.tier != "free" && self.tier != "paid" {
+            return Err(format!("invalid tier '{}'", self.tier));
+        }
+        if self.key.is_empty() || self.key.chars().any(char::is_whitespace) {
+            return Err("key must be non-empty and contain no whitespace".to_string());
+        }
+        key_format::check(&self.provider, &self.key)?;
+        if !(0.0..=1.0).contains(&self.success_rate) {
+            return Err(format!("success_rate {} is not between 0 and 1", self.success_rate));
+        }
+        if self.latency_ms < 0 || self.consecutive_failures < 0 {
+            return Err("latency_ms and consecutive_failures must not be negative".to_string());
+        }
+        if self.note.chars().count() > strategy::MAX_KEY_NOTE_CHARS {
+            return Err(format!("note is longer than {} characters", strategy::MAX_KEY_NOTE_CHARS));
+        }
+        if strategy::parse_tags(&self.tags.join(","))? != self.tags {
+            return Err(format!("tags {:?} must be lowercase and unique", self.tags));
+        }
+        if self.weight.abs() > strategy::MAX_KEY_WEIGHT {
+            return Err(format!("weight {} is not between -{max} and {max}", self.weight, max = strategy::MAX_KEY_WEIGHT));
+        }
+        Ok(())
+    }
+}
+
+pub fn to_csv(records: &[KeyRecord]) -> String {
+    let mut out = CSV_COLUMNS.join(",");
+    out.push('\n');
+    for r in records {
+        let row = [
+            csv_line::quote_field(&r.provider),
+            csv_line::quote_field(&r.key),
+            csv_line::quote_field(&r.status),
+            r.latency_ms.to_string(),
+            r.success_rate.to_string(),
+            r.consecutive_failures.to_string(),
+            r.total_cooling_seconds.to_string(),
+            r.created_at.to_string(),
+            r.last_checked_at.to_string(),
+            r.last_succeeded_at.to_string(),
+            csv_line::quote_field(&r.tier),
+            csv_line::quote_field(&r.note),
+            // Space-separated, so the field needs no quoting.
+            r.tags.join(" "),
+            r.weight.to_string(),
+        ];
+        out.push_str(&row.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+/// Parses CSV produced by `to_csv`. Columns are matched by the header row; only
+/// `provider` and `key` are required, the rest fall back to the defaults of a new key.
+pub fn from_csv(text: &str) -> Result<Vec<KeyRecord>, String> {
+    let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+    let header = csv_line::split_line(lines.next().ok_or("missing header row")?)?;
+    let column = |name: &str| header.iter().position(|h| h.trim() == name);
+    let (Some(provider_col), Some(key_col)) = (column("provider"), column("key")) else {
+        return Err("header must contain 'provider' and 'key' columns".to_string());
+    };
+
+    let mut records = Vec::new();
+    for (i, line) in lines.enumerate() {
+        let row_no = i + 2;
+        let fields = csv_line::split_line(line).map_err(|e| format!("row {}: {}", row_no, e))?;
+        let get = |name: &str| {
+            column(name)
+                .and_then(|idx| fields.get(idx))
+                .map(|v| v.trim())
+                .filter(|v| !v.is_empty())
+        };
+        let number = |name: &str| -> Result<Option<f64>, String> {
+            get(name)
+                .map(|v| v.parse::<f64>().map_err(|_| format!("row {}: invalid {} '{}'", row_no, name, v)))
+                .transpose()
+        };
+        records.push(KeyRecord {
+            provider: fields.get(provider_col).map(|v| v.trim().to_string()).unwrap_or_default(),
+            key: fields.get(key_col).map(|v| v.trim().to_string()).unwrap_or_default(),
+            status: get("status").map_or_else(default_status, str::to_string),
+            latency_ms: number("latency_ms")?.unwrap_or(0.0) as i64,
+            success_rate: number("success_rate")?.unwrap_or(1.0),
+            consecutive_failures: number("consecutive_failures")?.unwrap_or(0.0) as i64,
+            total_cooling_seconds: number("total_cooling_seconds")?.unwrap_or(0.0) as u64,
+            created_at: number("created_at")?.unwrap_or(0.0) as u64,
+            last_checked_at: number("last_checked_at")?.unwrap_or(0.0) as u64,
+            last_succeeded_at: number("last_succeeded_at")?.unwrap_or(0.0) as u64,
+            tier: get("tier").map_or_else(default_tier, str::to_string),
+            note: get("note").unwrap_or_default().to_string(),
+            tags: strategy::parse_tags(get("tags").unwrap_or_default()).map_err(|e| format!("row {}: {}", row_no, e))?,
+            weight: number("weight")?.unwrap_or(0.0) as i64,
+        });
+    }
+    Ok(records)
+}
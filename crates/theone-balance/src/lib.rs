@@ -6,18 +6,58 @@ macro_rules! console_debug {
 
 // Declare all our modules. The feature flags ensure only the code
 // for the active strategy is included in the final binary.
+pub mod admin;
+pub mod admin_limits;
+pub mod alerts;
+pub mod analytics;
+pub mod auth;
+pub mod balancer;
+pub mod build_info;
+pub mod chaos;
+pub mod compat;
+pub mod csv_line;
 pub mod dbmodels;
+pub mod embeddings;
 pub mod error_handling;
 pub mod gcp;
 pub mod handlers;
 pub mod hybrid;
+pub mod inflight;
+pub mod ip_allowlist;
+pub mod key_events;
+pub mod key_format;
+pub mod key_probe;
+pub mod key_transfer;
+pub mod metrics;
+pub mod migrations;
 pub mod models;
+pub mod openapi;
+pub mod payload_log;
+pub mod provider_breaker;
+pub mod provider_health;
 pub mod queue;
+pub mod rate_budget;
 pub mod request;
+pub mod request_id;
+pub mod request_validation;
+pub mod response_headers;
 pub mod router;
+pub mod sampling;
+pub mod schema_drift;
+pub mod settings;
+pub mod sliding_window;
+pub mod sse;
+pub mod status;
+pub mod storage_context;
 pub mod testing;
+pub mod tokens;
+pub mod totp;
+pub mod upstream;
+pub mod usage;
 pub mod util;
+pub mod validation;
 pub mod web;
+pub mod workers_ai;
 pub mod state {
     pub mod strategy;
 }
@@ -29,6 +69,7 @@ pub mod state_do_kv;
 #[cfg(feature = "do_sqlite")]
 pub mod state_do_sqlite;
 
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Once};
 use tower_service::Service;
 use worker::send::SendWrapper;
@@ -38,6 +79,9 @@ use worker::*;
 use futures_util::future::{select, Either};
 use futures_util::FutureExt;
 use std::time::Duration;
+use settings::Settings;
+use storage_context::LazyStorage;
+use tracing::Instrument;
 // --------------------------
 
 use tracing_subscriber::{
@@ -48,6 +92,10 @@ use tracing_subscriber::{
 use tracing_web::{performance_layer, MakeConsoleWriter};
 
 static START: Once = Once::new();
+/// Set once this isolate has applied pending schema migrations (see `AUTO_MIGRATE`).
+static MIGRATED: AtomicBool = AtomicBool::new(false);
+/// Set once this isolate has compared the D1 schema with the models (see `CHECK_SCHEMA`).
+static SCHEMA_CHECKED: AtomicBool = AtomicBool::new(false);
 
 #[event(start)]
 fn start() {
@@ -57,8 +105,33 @@ fn start() {
 pub struct AppState {
     pub env: SendWrapper<Env>,
     pub ctx: SendWrapper<Context>,
-    // pub controller: SendWrapper<web_sys::AbortController>,
+    /// Aborts the request's upstream calls, on the overall timeout or an admin's cancel.
+    pub controller: SendWrapper<worker::web_sys::AbortController>,
     pub signal: SendWrapper<AbortSignal>,
+    /// When the overall timeout aborts the request, in Unix milliseconds.
+    pub deadline_ms: u64,
+    /// The request's D1 session, opened on first use (see `storage_context`).
+    pub storage: SendWrapper<LazyStorage>,
+    pub settings: Settings,
+    /// The request's entry in the in-flight registry, if it is bound (see `inflight`).
+    pub inflight: SendWrapper<std::cell::OnceCell<inflight::Registration>>,
+}
+
+impl AppState {
+    /// The `DB` binding, through the request's D1 session. The handle is a cheap copy of
+    /// the session's, so every copy shares the session.
+    pub fn db(&self) -> Result<D1Database> {
+        let session: &wasm_bindgen::JsValue = self.storage.get(&self.env)?.db().as_ref();
+        Ok(wasm_bindgen::JsCast::unchecked_from_js(session.clone()))
+    }
+
+    /// Time left before the overall timeout, 0 once the request has been aborted.
+    pub fn remaining_ms(&self) -> u64 {
+        if self.signal.aborted() {
+            return 0;
+        }
+        self.deadline_ms.saturating_sub(Date::now().as_millis())
+    }
 }
 // #[derive(Clone, Debug)]
 // pub struct DummyAppState {
@@ -67,7 +140,7 @@ pub struct AppState {
 
 #[event(fetch)]
 pub async fn fetch(
-    req: HttpRequest,
+    mut req: HttpRequest,
     env: Env,
     _ctx: Context,
 ) -> Result<axum::http::Response<axum::body::Body>> {
@@ -88,35 +161,85 @@ pub async fn fetch(
             .with(fmt_layer)
             .with(perf_layer)
             .init();
+        build_info::log_banner();
     });
 
-    // --- Timeout Configuration ---
-    let overall_timeout_ms: u64 = match env.var("OVERALL_TIMEOUT_MS") {
-        Ok(v) => v.to_string().parse().unwrap_or(25_000),
-        Err(_) => 25_000,
+    let settings = match env.d1("DB") {
+        Ok(db) => Settings::load(&env, &db).await,
+        Err(_) => Settings::from_env(&env),
     };
 
-    let controller = AbortController::default();
-    let signal = controller.signal();
+    // Apply pending schema migrations before the first request this isolate serves.
+    if settings.auto_migrate && !MIGRATED.load(Ordering::Relaxed) {
+        match env.d1("DB") {
+            Ok(db) => match migrations::apply_pending(&db).await {
+                Ok(_) => MIGRATED.store(true, Ordering::Relaxed),
+                Err(e) => tracing::error!("Failed to apply schema migrations: {}", e),
+            },
+            Err(e) => tracing::error!("Failed to get D1 database binding for migrations: {}", e),
+        }
+    }
+
+    // Report schema drift once per isolate, after any migrations above.
+    if settings.check_schema && !SCHEMA_CHECKED.load(Ordering::Relaxed) {
+        match env.d1("DB") {
+            Ok(db) => match schema_drift::check(&db).await {
+                Ok(report) => {
+                    schema_drift::log_report(&report);
+                    SCHEMA_CHECKED.store(true, Ordering::Relaxed);
+                }
+                Err(e) => tracing::error!("Failed to check the D1 schema: {}", e),
+            },
+            Err(e) => tracing::error!("Failed to get D1 database binding for the schema check: {}", e),
+        }
+    }
+
+    hybrid::d1_executor::set_explain_queries(settings.explain_queries);
+
+    // --- Timeout Configuration ---
+    let overall_timeout_ms = settings.overall_timeout_ms;
+
+    let controller = worker::web_sys::AbortController::new()?;
+    let signal = AbortSignal::from(controller.signal());
+    let bookmark = req
+        .headers()
+        .get(storage_context::BOOKMARK_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
     let app_state = Arc::new(AppState {
         env: SendWrapper::new(env),
         ctx: SendWrapper::new(_ctx),
+        controller: SendWrapper::new(controller.clone()),
         signal: SendWrapper::new(signal),
+        deadline_ms: Date::now().as_millis() + overall_timeout_ms,
+        storage: SendWrapper::new(LazyStorage::new(bookmark)),
+        settings,
+        inflight: SendWrapper::new(std::cell::OnceCell::new()),
     });
-    let mut router = router::new().with_state(app_state);
+    let mut router = router::new(app_state.clone()).with_state(app_state.clone());
 
-    let work_future = router.call(req);
+    // Every log line of the request carries its id, including the timeout below.
+    let request_id = request_id::ensure(&mut req);
+    let span = tracing::warn_span!("request", request_id = %request_id);
+    let work_future = router.call(req).instrument(span.clone());
     let timeout_future = Delay::from(Duration::from_millis(overall_timeout_ms));
 
     let result = select(work_future.boxed(), timeout_future.boxed_local()).await;
 
     match result {
         Either::Left((work_result, _)) => {
-            // Work finished first, return the result
-            Ok(work_result?)
+            // Work finished first, return the result with the session's bookmark
+            let mut response = work_result?;
+            if let Some(bookmark) = app_state.storage.bookmark() {
+                if let Ok(value) = axum::http::HeaderValue::from_str(&bookmark) {
+                    response.headers_mut().insert(storage_context::BOOKMARK_HEADER, value);
+                }
+            }
+            Ok(response)
         }
         Either::Right((_, _)) => {
             // Timeout finished first
+            let _entered = span.enter();
             tracing::error!(
                 "Request timed out after {}ms. Aborting.",
                 overall_timeout_ms
@@ -125,10 +248,11 @@ pub async fn fetch(
 
             // Build a timeout response using axum's types
             let body = axum::body::Body::from("Request Timed Out");
-            let response = axum::http::Response::builder()
+            let mut response = axum::http::Response::builder()
                 .status(axum::http::StatusCode::GATEWAY_TIMEOUT)
                 .body(body)
                 .map_err(|e| worker::Error::from(e.to_string()))?;
+            request_id::set_header(&mut response, &request_id);
             Ok(response)
         }
     }
@@ -137,7 +261,7 @@ pub async fn fetch(
 // We also add a scheduled event handler to satisfy the build warning.
 // This worker doesn't use scheduled events, so this is just a placeholder.
 #[event(scheduled)]
-pub async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+pub async fn scheduled(event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
     let db = match env.d1("DB") {
         Ok(db) => db,
         Err(e) => {
@@ -146,27 +270,74 @@ pub async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext)
         }
     };
 
-    // Define the list of providers to run the cleanup task for.
-    // In a real-world scenario, this might come from a configuration or another DB table.
-    let providers_to_clean = vec!["google-ai-studio", "openai", "anthropic"];
+    let settings = Settings::load(&env, &db).await;
+    alerts::evaluate(&env, &db, &settings).await;
+    // The frequent alert trigger skips the daily maintenance below.
+    if event.cron() == alerts::ALERT_CRON {
+        return;
+    }
 
-    for provider in providers_to_clean {
+    // Score soft-launched providers before they are enabled for live traffic.
+    if let Err(e) = testing::probe_observe_only_providers(&db).await {
+        tracing::error!("Failed to probe observe-only providers: {}", e);
+    }
+
+    match d1_storage::hash_client_keys(&db).await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!("Hashed {} client keys.", count),
+        Err(e) => tracing::error!("Failed to hash client keys: {}", e),
+    }
+
+    match d1_storage::backfill_key_hashes(&db).await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!("Backfilled key hashes for {} keys.", count),
+        Err(e) => tracing::error!("Failed to backfill key hashes: {}", e),
+    }
+
+    if let Err(e) = d1_storage::prune_request_events(&db).await {
+        tracing::error!("Failed to prune request events: {}", e);
+    }
+    if let Err(e) = d1_storage::prune_key_scores(&db).await {
+        tracing::error!("Failed to prune key scores: {}", e);
+    }
+    if let Err(e) = d1_storage::prune_alert_events(&db).await {
+        tracing::error!("Failed to prune alert events: {}", e);
+    }
+    if let Err(e) = d1_storage::prune_key_events(&db).await {
+        tracing::error!("Failed to prune key events: {}", e);
+    }
+    if let Err(e) = d1_storage::prune_sessions(&db).await {
+        tracing::error!("Failed to prune sessions: {}", e);
+    }
+    if let Err(e) = d1_storage::prune_key_tags(&db).await {
+        tracing::error!("Failed to prune key tags: {}", e);
+    }
+
+    // Report schema drift between deploys, not just when an isolate starts.
+    if settings.check_schema {
+        match schema_drift::check(&db).await {
+            Ok(report) => schema_drift::log_report(&report),
+            Err(e) => tracing::error!("Failed to check the D1 schema: {}", e),
+        }
+    }
+
+    // Before the cleanup, so a key that passes probation isn't moved to the trash.
+    if let Err(e) = testing::probe_blocked_keys(&env, &db, &settings).await {
+        tracing::error!("Failed to run the blocked-key probation: {}", e);
+    }
+
+    let providers = match d1_storage::list_key_providers(&db).await {
+        Ok(providers) => providers,
+        Err(e) => {
+            tracing::error!("Failed to list providers for the cleanup: {}", e);
+            return;
+        }
+    };
+    for provider in providers {
         tracing::info!("Running scheduled cleanup for provider: {}", provider);
-        match d1_storage::delete_permanently_failed_keys(&env, &db, provider).await {
-            Ok(deleted_count) => {
-                tracing::info!(
-                    "Successfully completed cleanup for provider: {}. Deleted {} keys.",
-                    provider,
-                    deleted_count
-                );
-            }
-            Err(e) => {
-                tracing::error!(
-                    "Failed to run cleanup for provider: {}. Error: {}",
-                    provider,
-                    e
-                );
-            }
+        match d1_storage::run_cleanup(&settings, &db, &provider).await {
+            Ok(summary) => tracing::info!(?summary, "Completed cleanup for provider: {}.", provider),
+            Err(e) => tracing::error!("Failed to run cleanup for provider: {}. Error: {}", provider, e),
         }
     }
 }
@@ -0,0 +1,342 @@
+//! This module contains the Prometheus metrics exporter. Counters and histograms are
+//! kept in the `metrics` D1 table, incremented once per proxied request, and rendered
+//! together with key pool gauges when `/metrics` is scraped.
+
+use crate::{admin::AdminAuth, d1_storage, payload_log::Payload, usage, AppState};
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// Upper bounds of the request latency histogram buckets, in seconds.
+const LATENCY_BUCKETS_SECONDS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+const REQUESTS_TOTAL: &str = "onebalance_requests_total";
+const FAILOVERS_TOTAL: &str = "onebalance_failovers_total";
+const COOLDOWN_EVENTS_TOTAL: &str = "onebalance_cooldown_events_total";
+const UPSTREAM_TIMEOUTS_TOTAL: &str = "onebalance_upstream_timeouts_total";
+const REQUEST_DURATION: &str = "onebalance_request_duration_seconds";
+const PROVIDER_DEGRADED_TOTAL: &str = "onebalance_provider_degraded_total";
+const PROVIDER_CIRCUIT_OPENED_TOTAL: &str = "onebalance_provider_circuit_opened_total";
+const QUEUE_SEND_FAILURES_TOTAL: &str = "onebalance_queue_send_failures_total";
+const QUEUE_UPDATES_DROPPED_TOTAL: &str = "onebalance_queue_updates_dropped_total";
+
+/// The `model` label of requests for models the worker doesn't know, so that made-up model
+/// names in request bodies can't add series without bound.
+const OTHER_MODEL: &str = "other";
+
+/// An increment to one stored series.
+#[derive(Debug, Clone)]
+pub struct MetricDelta {
+    pub name: String,
+    /// Rendered label set, e.g. `provider="openai",status="200"`.
+    pub labels: String,
+    /// Counts, or milliseconds for latency sums.
+    pub value: i64,
+}
+
+/// A stored series, as loaded from D1.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MetricRow {
+    pub name: String,
+    pub labels: String,
+    pub value: i64,
+}
+
+/// Number of keys per provider and status, used for the key pool gauges.
+#[derive(Debug, Clone, Deserialize)]
+pub struct KeyCountRow {
+    pub provider: String,
+    pub status: String,
+    pub count: i64,
+}
+
+/// What happened to one proxied request.
+#[derive(Debug, Clone, Default)]
+pub struct RequestOutcome {
+    pub provider: String,
+    pub model: String,
+    pub status: u16,
+    pub latency_ms: u64,
+    /// Keys tried after the first one.
+    pub failovers: u32,
+    /// Keys that were put on cooldown while serving the request.
+    pub cooldowns: u32,
+    /// Key attempts that timed out or were aborted.
+    pub timeouts: u32,
+    /// Hash of the key that served the request, if one was tried.
+    pub key_hash: String,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Why the request failed (see `ErrorAnalysis::class`), empty on success.
+    pub error_class: String,
+    /// The redacted prompt and response, when payload logging covers the request.
+    pub payload: Option<Payload>,
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn labels(pairs: &[(&str, &str)]) -> String {
+    pairs
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl RequestOutcome {
+    /// Converts the outcome into the series increments it contributes. The model is only used
+    /// as a label if it is in `known_models` (the provider's model catalog) or has a list
+    /// price; any other model is counted as `other`.
+    pub fn deltas(&self, known_models: &[String]) -> Vec<MetricDelta> {
+        let provider = self.provider.as_str();
+        let status = self.status.to_string();
+        let model = if known_models.contains(&self.model) || usage::has_list_price(&self.model) {
+            self.model.as_str()
+        } else {
+            OTHER_MODEL
+        };
+        let mut deltas = vec![MetricDelta {
+            name: REQUESTS_TOTAL.to_string(),
+            labels: labels(&[("provider", provider), ("model", model), ("status", &status)]),
+            value: 1,
+        }];
+        if self.failovers > 0 {
+            deltas.push(MetricDelta {
+                name: FAILOVERS_TOTAL.to_string(),
+                labels: labels(&[("provider", provider)]),
+                value: self.failovers as i64,
+            });
+        }
+        if self.cooldowns > 0 {
+            deltas.push(MetricDelta {
+                name: COOLDOWN_EVENTS_TOTAL.to_string(),
+                labels: labels(&[("provider", provider)]),
+                value: self.cooldowns as i64,
+            });
+        }
+        if self.timeouts > 0 {
+            deltas.push(MetricDelta {
+                name: UPSTREAM_TIMEOUTS_TOTAL.to_string(),
+                labels: labels(&[("provider", provider)]),
+                value: self.timeouts as i64,
+            });
+        }
+
+        // Buckets are stored non-cumulatively (one row per bucket) and summed when rendering.
+        let seconds = self.latency_ms as f64 / 1000.0;
+        let le = LATENCY_BUCKETS_SECONDS
+            .iter()
+            .find(|bound| seconds <= **bound)
+            .map(|bound| bound.to_string())
+            .unwrap_or_else(|| "+Inf".to_string());
+        deltas.push(MetricDelta {
+            name: format!("{}_bucket", REQUEST_DURATION),
+            labels: labels(&[("provider", provider), ("le", &le)]),
+            value: 1,
+        });
+        deltas.push(MetricDelta {
+            name: format!("{}_sum", REQUEST_DURATION),
+            labels: labels(&[("provider", provider)]),
+            value: self.latency_ms as i64,
+        });
+        deltas.push(MetricDelta {
+            name: format!("{}_count", REQUEST_DURATION),
+            labels: labels(&[("provider", provider)]),
+            value: 1,
+        });
+        deltas
+    }
+}
+
+/// A provider flagged as degraded; `anomaly` is `latency` or `errors`.
+pub fn provider_degraded(provider: &str, anomaly: &str) -> MetricDelta {
+    MetricDelta {
+        name: PROVIDER_DEGRADED_TOTAL.to_string(),
+        labels: labels(&[("provider", provider), ("anomaly", anomaly)]),
+        value: 1,
+    }
+}
+
+/// A provider's circuit breaker opened.
+pub fn provider_circuit_opened(provider: &str) -> MetricDelta {
+    MetricDelta {
+        name: PROVIDER_CIRCUIT_OPENED_TOTAL.to_string(),
+        labels: labels(&[("provider", provider)]),
+        value: 1,
+    }
+}
+
+/// A state update the `STATE_UPDATER` queue didn't take right away. `reason` is `unbound`,
+/// `error` or `timeout`.
+pub fn queue_send_failure(reason: &str) -> MetricDelta {
+    MetricDelta {
+        name: QUEUE_SEND_FAILURES_TOTAL.to_string(),
+        labels: labels(&[("reason", reason)]),
+        value: 1,
+    }
+}
+
+/// A state update that was neither queued nor applied directly.
+pub fn queue_update_dropped() -> MetricDelta {
+    MetricDelta {
+        name: QUEUE_UPDATES_DROPPED_TOTAL.to_string(),
+        labels: String::new(),
+        value: 1,
+    }
+}
+
+/// Splits `provider="x",le="0.5"` into the `le` value and the remaining labels.
+fn split_le(labels: &str) -> (String, String) {
+    let mut le = String::new();
+    let rest: Vec<&str> = labels
+        .split(',')
+        .filter(|pair| match pair.strip_prefix("le=") {
+            Some(value) => {
+                le = value.trim_matches('"').to_string();
+                false
+            }
+            None => true,
+        })
+        .collect();
+    (rest.join(","), le)
+}
+
+fn write_series(out: &mut String, name: &str, labels: &str, value: f64) {
+    if labels.is_empty() {
+        let _ = writeln!(out, "{} {}", name, value);
+    } else {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+    }
+}
+
+/// Renders the stored series and key pool gauges in the Prometheus text format.
+pub fn render(rows: &[MetricRow], key_counts: &[KeyCountRow]) -> String {
+    let mut by_name: BTreeMap<&str, Vec<&MetricRow>> = BTreeMap::new();
+    for row in rows {
+        by_name.entry(row.name.as_str()).or_default().push(row);
+    }
+
+    let mut out = String::new();
+    for (name, help) in [
+        (REQUESTS_TOTAL, "Proxied requests by provider, model and final status."),
+        (FAILOVERS_TOTAL, "Failovers to another key while serving a request."),
+        (COOLDOWN_EVENTS_TOTAL, "Keys put on cooldown after a rate limit."),
+        (UPSTREAM_TIMEOUTS_TOTAL, "Key attempts that timed out or were aborted upstream."),
+        (PROVIDER_DEGRADED_TOTAL, "Times a provider's latency or error rate was flagged as anomalous."),
+        (PROVIDER_CIRCUIT_OPENED_TOTAL, "Times a provider's circuit breaker opened."),
+        (QUEUE_SEND_FAILURES_TOTAL, "State updates the queue did not take right away, by reason."),
+        (QUEUE_UPDATES_DROPPED_TOTAL, "State updates neither queued nor applied directly."),
+    ] {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for row in by_name.get(name).into_iter().flatten() {
+            write_series(&mut out, name, &row.labels, row.value as f64);
+        }
+    }
+
+    let _ = writeln!(out, "# HELP {} End-to-end latency of proxied requests.", REQUEST_DURATION);
+    let _ = writeln!(out, "# TYPE {} histogram", REQUEST_DURATION);
+    let bucket_name = format!("{}_bucket", REQUEST_DURATION);
+    let mut buckets: BTreeMap<String, BTreeMap<String, i64>> = BTreeMap::new();
+    for row in by_name.get(bucket_name.as_str()).into_iter().flatten() {
+        let (series, le) = split_le(&row.labels);
+        buckets.entry(series).or_default().insert(le, row.value);
+    }
+    for (series, counts) in &buckets {
+        let mut cumulative = 0;
+        let bounds = LATENCY_BUCKETS_SECONDS.iter().map(|b| b.to_string()).chain(["+Inf".to_string()]);
+        for le in bounds {
+            cumulative += counts.get(&le).copied().unwrap_or(0);
+            let labels = format!("{},le=\"{}\"", series, le);
+            write_series(&mut out, &bucket_name, &labels, cumulative as f64);
+        }
+    }
+    // Sums are stored in milliseconds but exported in seconds.
+    for (suffix, scale) in [("_sum", 1000.0), ("_count", 1.0)] {
+        let name = format!("{}{}", REQUEST_DURATION, suffix);
+        for row in by_name.get(name.as_str()).into_iter().flatten() {
+            write_series(&mut out, &name, &row.labels, row.value as f64 / scale);
+        }
+    }
+
+    let _ = writeln!(out, "# HELP onebalance_keys Keys in the pool by provider and status.");
+    let _ = writeln!(out, "# TYPE onebalance_keys gauge");
+    for count in key_counts {
+        let labels = labels(&[("provider", &count.provider), ("status", &count.status)]);
+        write_series(&mut out, "onebalance_keys", &labels, count.count as f64);
+    }
+
+    out
+}
+
+/// `GET /metrics`, protected by the master key.
+#[worker::send]
+pub async fn metrics_handler(State(state): State<Arc<AppState>>, _auth: AdminAuth) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e)).into_response(),
+    };
+
+    let rows = d1_storage::load_metrics(&db).await;
+    let key_counts = d1_storage::count_keys_by_status(&db).await;
+    match (rows, key_counts) {
+        (Ok(rows), Ok(key_counts)) => (
+            StatusCode::OK,
+            [(
+                header::CONTENT_TYPE,
+                "text/plain; version=0.0.4; charset=utf-8",
+            )],
+            render(&rows, &key_counts),
+        )
+            .into_response(),
+        (Err(e), _) | (_, Err(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to load metrics: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_models_share_one_label() {
+        let outcome = |model: &str| RequestOutcome {
+            provider: "openai".to_string(),
+            model: model.to_string(),
+            status: 200,
+            ..Default::default()
+        };
+        let known = vec!["gpt-5".to_string()];
+        let model_labels = |model: &str| outcome(model).deltas(&known)[0].labels.clone();
+        assert_eq!(model_labels("gpt-5"), r#"provider="openai",model="gpt-5",status="200""#);
+        assert_eq!(model_labels("gpt-4o"), r#"provider="openai",model="gpt-4o",status="200""#);
+        assert_eq!(model_labels("made-up-123"), r#"provider="openai",model="other",status="200""#);
+    }
+
+    #[test]
+    fn provider_rejections_count_once_under_their_status() {
+        let outcome = RequestOutcome {
+            provider: "openai".to_string(),
+            model: "gpt-5".to_string(),
+            status: 400,
+            error_class: "user_error".to_string(),
+            ..Default::default()
+        };
+        let deltas = outcome.deltas(&["gpt-5".to_string()]);
+        let requests: Vec<_> = deltas.iter().filter(|d| d.name == REQUESTS_TOTAL).collect();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].labels, r#"provider="openai",model="gpt-5",status="400""#);
+        assert_eq!(requests[0].value, 1);
+    }
+}
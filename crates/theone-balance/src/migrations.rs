@@ -0,0 +1,659 @@
+//! This module contains the D1 schema migrations. Applied versions are recorded in a
+//! `schema_migrations` table, and pending ones are applied in order, on the first request
+//! an isolate serves when `AUTO_MIGRATE` is `true`, or through the admin API.
+//!
+//! Migrations are written to be safe on databases set up by the drizzle migrations in
+//! `d1schema.ts`: tables and indexes are created `IF NOT EXISTS`, and columns that
+//! already exist are not added again. Append new migrations; never edit applied ones.
+
+use crate::hybrid::d1_integer;
+use js_sys::Date;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use tracing::info;
+use worker::{D1Database, D1Type, Result};
+
+/// One change to the schema.
+pub enum Step {
+    /// A statement that can run more than once, e.g. `CREATE TABLE IF NOT EXISTS`.
+    Sql(&'static str),
+    /// `ALTER TABLE ... ADD COLUMN`, skipped when the column already exists.
+    AddColumn {
+        table: &'static str,
+        column: &'static str,
+        definition: &'static str,
+    },
+}
+
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub steps: &'static [Step],
+}
+
+impl Migration {
+    /// Whether the migration creates or changes `table`, its columns or its indexes.
+    pub fn touches(&self, table: &str) -> bool {
+        self.steps.iter().any(|step| match step {
+            Step::Sql(sql) => {
+                sql.contains(&format!("TABLE IF NOT EXISTS {} (", table)) || sql.contains(&format!(" ON {} (", table))
+            }
+            Step::AddColumn { table: altered, .. } => *altered == table,
+        })
+    }
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_keys",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS keys (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    key TEXT NOT NULL,
+                    provider TEXT NOT NULL,
+                    model_coolings TEXT,
+                    total_cooling_seconds INTEGER DEFAULT 0 NOT NULL,
+                    status TEXT DEFAULT 'active' NOT NULL,
+                    created_at INTEGER DEFAULT (strftime('%s', 'now')) NOT NULL,
+                    updated_at INTEGER DEFAULT (strftime('%s', 'now')) NOT NULL,
+                    latency_ms INTEGER DEFAULT 0 NOT NULL,
+                    success_rate INTEGER DEFAULT 1000 NOT NULL,
+                    consecutive_failures INTEGER DEFAULT 0 NOT NULL,
+                    last_checked_at INTEGER DEFAULT 0 NOT NULL,
+                    last_succeeded_at INTEGER DEFAULT 0 NOT NULL
+                )",
+            ),
+            Step::Sql("CREATE UNIQUE INDEX IF NOT EXISTS provider_key_unq_idx ON keys (provider, key)"),
+            Step::Sql(
+                "CREATE INDEX IF NOT EXISTS provider_status_created_at_idx ON keys (provider, status, created_at)",
+            ),
+            Step::Sql("CREATE INDEX IF NOT EXISTS total_cooling_seconds_idx ON keys (total_cooling_seconds)"),
+        ],
+    },
+    Migration {
+        version: 2,
+        name: "create_client_keys",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS client_keys (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    name TEXT NOT NULL,
+                    key TEXT NOT NULL,
+                    allowed_providers TEXT DEFAULT '' NOT NULL,
+                    allowed_models TEXT DEFAULT '' NOT NULL,
+                    status TEXT DEFAULT 'active' NOT NULL,
+                    max_requests_per_day INTEGER DEFAULT 0 NOT NULL,
+                    max_tokens_per_month INTEGER DEFAULT 0 NOT NULL,
+                    max_budget_micros INTEGER DEFAULT 0 NOT NULL,
+                    created_at INTEGER DEFAULT (strftime('%s', 'now')) NOT NULL,
+                    updated_at INTEGER DEFAULT (strftime('%s', 'now')) NOT NULL,
+                    last_used_at INTEGER DEFAULT 0 NOT NULL
+                )",
+            ),
+            Step::Sql("CREATE UNIQUE INDEX IF NOT EXISTS client_key_unq_idx ON client_keys (key)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS client_status_idx ON client_keys (status)"),
+        ],
+    },
+    Migration {
+        version: 3,
+        name: "create_usage_events",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS usage_events (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    client_id TEXT DEFAULT '' NOT NULL,
+                    provider TEXT NOT NULL,
+                    model TEXT NOT NULL,
+                    key_id TEXT NOT NULL,
+                    endpoint TEXT NOT NULL,
+                    prompt_tokens INTEGER DEFAULT 0 NOT NULL,
+                    completion_tokens INTEGER DEFAULT 0 NOT NULL,
+                    images INTEGER DEFAULT 0 NOT NULL,
+                    cost_micros INTEGER DEFAULT 0 NOT NULL,
+                    estimated INTEGER DEFAULT 0 NOT NULL,
+                    created_at INTEGER DEFAULT (strftime('%s', 'now')) NOT NULL
+                )",
+            ),
+            Step::Sql(
+                "CREATE INDEX IF NOT EXISTS usage_client_created_at_idx ON usage_events (client_id, created_at)",
+            ),
+            Step::Sql(
+                "CREATE INDEX IF NOT EXISTS usage_provider_created_at_idx ON usage_events (provider, created_at)",
+            ),
+        ],
+    },
+    Migration {
+        version: 4,
+        name: "create_samples",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS samples (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    provider TEXT NOT NULL,
+                    model TEXT NOT NULL,
+                    key_id TEXT NOT NULL,
+                    client_id TEXT DEFAULT '' NOT NULL,
+                    endpoint TEXT NOT NULL,
+                    request_body TEXT NOT NULL,
+                    response_body TEXT NOT NULL,
+                    latency_ms INTEGER DEFAULT 0 NOT NULL,
+                    created_at INTEGER DEFAULT (strftime('%s', 'now')) NOT NULL
+                )",
+            ),
+            Step::Sql("CREATE INDEX IF NOT EXISTS sample_provider_idx ON samples (provider)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS sample_created_at_idx ON samples (created_at)"),
+        ],
+    },
+    Migration {
+        version: 5,
+        name: "create_model_catalog",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS model_catalog (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    provider TEXT NOT NULL,
+                    models TEXT DEFAULT '[]' NOT NULL,
+                    fetched_at INTEGER DEFAULT 0 NOT NULL
+                )",
+            ),
+            Step::Sql("CREATE UNIQUE INDEX IF NOT EXISTS model_catalog_provider_unq_idx ON model_catalog (provider)"),
+        ],
+    },
+    Migration {
+        version: 6,
+        name: "create_provider_settings",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS provider_settings (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    provider TEXT NOT NULL,
+                    observe_only INTEGER DEFAULT 0 NOT NULL,
+                    updated_at INTEGER DEFAULT (strftime('%s', 'now')) NOT NULL
+                )",
+            ),
+            Step::Sql(
+                "CREATE UNIQUE INDEX IF NOT EXISTS provider_settings_provider_unq_idx ON provider_settings (provider)",
+            ),
+        ],
+    },
+    Migration {
+        version: 7,
+        name: "create_metrics",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS metrics (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    name TEXT NOT NULL,
+                    labels TEXT DEFAULT '' NOT NULL,
+                    value INTEGER DEFAULT 0 NOT NULL,
+                    updated_at INTEGER DEFAULT (strftime('%s', 'now')) NOT NULL
+                )",
+            ),
+            Step::Sql("CREATE INDEX IF NOT EXISTS metrics_name_idx ON metrics (name)"),
+            Step::Sql("CREATE UNIQUE INDEX IF NOT EXISTS metrics_series_unq_idx ON metrics (name, labels)"),
+        ],
+    },
+    Migration {
+        version: 8,
+        name: "create_request_events",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS request_events (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    provider TEXT NOT NULL,
+                    model TEXT NOT NULL,
+                    status INTEGER NOT NULL,
+                    error_class TEXT DEFAULT '' NOT NULL,
+                    latency_ms INTEGER DEFAULT 0 NOT NULL,
+                    created_at INTEGER DEFAULT (strftime('%s', 'now')) NOT NULL
+                )",
+            ),
+            Step::Sql("CREATE INDEX IF NOT EXISTS request_event_provider_idx ON request_events (provider)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS request_event_created_at_idx ON request_events (created_at)"),
+        ],
+    },
+    Migration {
+        version: 9,
+        name: "keys_soft_delete",
+        steps: &[
+            Step::AddColumn {
+                table: "keys",
+                column: "deleted_at",
+                definition: "INTEGER DEFAULT 0 NOT NULL",
+            },
+            Step::Sql("CREATE INDEX IF NOT EXISTS deleted_at_idx ON keys (deleted_at)"),
+        ],
+    },
+    Migration {
+        version: 10,
+        name: "keys_usage_windows",
+        steps: &[
+            Step::AddColumn {
+                table: "keys",
+                column: "usage_window_start",
+                definition: "INTEGER DEFAULT 0 NOT NULL",
+            },
+            Step::AddColumn {
+                table: "keys",
+                column: "usage_window_requests",
+                definition: "INTEGER DEFAULT 0 NOT NULL",
+            },
+            Step::AddColumn {
+                table: "keys",
+                column: "usage_prev_window_requests",
+                definition: "INTEGER DEFAULT 0 NOT NULL",
+            },
+        ],
+    },
+    Migration {
+        version: 11,
+        name: "keys_key_hash",
+        steps: &[
+            Step::AddColumn {
+                table: "keys",
+                column: "key_hash",
+                definition: "TEXT DEFAULT '' NOT NULL",
+            },
+            Step::Sql(
+                "CREATE UNIQUE INDEX IF NOT EXISTS provider_key_hash_unq_idx ON keys (provider, key_hash) WHERE key_hash != ''",
+            ),
+        ],
+    },
+    Migration {
+        version: 12,
+        name: "keys_recommended_indexes",
+        steps: &[
+            // Routing (`provider`, `status` and `deleted_at` equal) and the list of active
+            // providers, which reads this index alone. Without it SQLite picks
+            // `deleted_at_idx`, where nearly every row has the same value.
+            Step::Sql("CREATE INDEX IF NOT EXISTS keys_routing_idx ON keys (status, deleted_at, provider)"),
+            // The keys page: one provider's live keys, newest first.
+            Step::Sql(
+                "CREATE INDEX IF NOT EXISTS keys_listing_idx ON keys (provider, deleted_at, created_at)",
+            ),
+        ],
+    },
+    Migration {
+        version: 13,
+        name: "create_settings",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS settings (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    name TEXT NOT NULL,
+                    value TEXT NOT NULL,
+                    updated_at INTEGER DEFAULT (strftime('%s', 'now')) NOT NULL
+                )",
+            ),
+            Step::Sql("CREATE UNIQUE INDEX IF NOT EXISTS settings_name_unq_idx ON settings (name)"),
+        ],
+    },
+    Migration {
+        version: 14,
+        name: "keys_tier",
+        steps: &[Step::AddColumn {
+            table: "keys",
+            column: "tier",
+            definition: "TEXT DEFAULT 'free' NOT NULL",
+        }],
+    },
+    Migration {
+        version: 15,
+        name: "provider_settings_rate_budgets",
+        steps: &[
+            Step::AddColumn {
+                table: "provider_settings",
+                column: "rpm_limit",
+                definition: "INTEGER DEFAULT 0 NOT NULL",
+            },
+            Step::AddColumn {
+                table: "provider_settings",
+                column: "tpm_limit",
+                definition: "INTEGER DEFAULT 0 NOT NULL",
+            },
+        ],
+    },
+    Migration {
+        version: 16,
+        name: "create_providers",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS providers (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    name TEXT NOT NULL,
+                    base_url TEXT NOT NULL,
+                    auth_header TEXT DEFAULT 'Authorization' NOT NULL,
+                    auth_scheme TEXT DEFAULT 'Bearer' NOT NULL,
+                    color TEXT DEFAULT '' NOT NULL,
+                    updated_at INTEGER DEFAULT (strftime('%s', 'now')) NOT NULL
+                )",
+            ),
+            Step::Sql("CREATE UNIQUE INDEX IF NOT EXISTS providers_name_unq_idx ON providers (name)"),
+        ],
+    },
+    Migration {
+        version: 17,
+        name: "create_key_scores",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS key_scores (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    request_id TEXT NOT NULL,
+                    provider TEXT NOT NULL,
+                    model TEXT NOT NULL,
+                    key_id TEXT NOT NULL,
+                    rank INTEGER NOT NULL,
+                    latency_score INTEGER NOT NULL,
+                    success_score INTEGER NOT NULL,
+                    failure_penalty INTEGER NOT NULL,
+                    recent_success_bonus INTEGER NOT NULL,
+                    fairness_penalty INTEGER NOT NULL,
+                    total INTEGER NOT NULL,
+                    created_at INTEGER DEFAULT (strftime('%s', 'now')) NOT NULL
+                )",
+            ),
+            Step::Sql("CREATE INDEX IF NOT EXISTS key_score_key_id_idx ON key_scores (key_id)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS key_score_created_at_idx ON key_scores (created_at)"),
+        ],
+    },
+    Migration {
+        version: 18,
+        name: "add_request_event_payloads",
+        steps: &[
+            Step::AddColumn {
+                table: "request_events",
+                column: "request_body",
+                definition: "TEXT DEFAULT '' NOT NULL",
+            },
+            Step::AddColumn {
+                table: "request_events",
+                column: "response_body",
+                definition: "TEXT DEFAULT '' NOT NULL",
+            },
+        ],
+    },
+    Migration {
+        version: 19,
+        name: "create_alert_events",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS alert_events (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    provider TEXT NOT NULL,
+                    metric TEXT NOT NULL,
+                    state TEXT NOT NULL,
+                    value INTEGER NOT NULL,
+                    threshold INTEGER NOT NULL,
+                    message TEXT NOT NULL,
+                    created_at INTEGER DEFAULT (strftime('%s', 'now')) NOT NULL
+                )",
+            ),
+            Step::Sql("CREATE INDEX IF NOT EXISTS alert_event_provider_idx ON alert_events (provider)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS alert_event_created_at_idx ON alert_events (created_at)"),
+        ],
+    },
+    Migration {
+        version: 20,
+        name: "keys_probation",
+        steps: &[
+            Step::AddColumn {
+                table: "keys",
+                column: "block_reason",
+                definition: "TEXT DEFAULT '' NOT NULL",
+            },
+            Step::AddColumn {
+                table: "keys",
+                column: "blocked_at",
+                definition: "INTEGER DEFAULT 0 NOT NULL",
+            },
+            Step::AddColumn {
+                table: "keys",
+                column: "reactivations",
+                definition: "INTEGER DEFAULT 0 NOT NULL",
+            },
+            // Keys blocked before this migration were last updated when they were blocked.
+            Step::Sql("UPDATE keys SET blocked_at = updated_at WHERE status = 'blocked' AND blocked_at = 0"),
+        ],
+    },
+    Migration {
+        version: 21,
+        name: "create_key_events",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS key_events (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    key_id TEXT NOT NULL,
+                    provider TEXT NOT NULL,
+                    model TEXT NOT NULL,
+                    event TEXT NOT NULL,
+                    status INTEGER NOT NULL,
+                    error_class TEXT NOT NULL,
+                    message TEXT NOT NULL,
+                    created_at INTEGER DEFAULT (strftime('%s', 'now')) NOT NULL
+                )",
+            ),
+            Step::Sql("CREATE INDEX IF NOT EXISTS key_event_key_idx ON key_events (key_id, created_at)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS key_event_created_at_idx ON key_events (created_at)"),
+        ],
+    },
+    Migration {
+        version: 22,
+        name: "keys_latency_histogram",
+        steps: &[
+            Step::AddColumn {
+                table: "keys",
+                column: "latency_ewma_ms",
+                definition: "INTEGER DEFAULT 0 NOT NULL",
+            },
+            Step::AddColumn {
+                table: "keys",
+                column: "latency_histogram",
+                definition: "TEXT DEFAULT '[]' NOT NULL",
+            },
+            // The last latency is the best estimate of the average until the next attempt.
+            Step::Sql("UPDATE keys SET latency_ewma_ms = latency_ms WHERE latency_ewma_ms = 0"),
+        ],
+    },
+    Migration {
+        version: 23,
+        name: "client_keys_role",
+        steps: &[Step::AddColumn {
+            table: "client_keys",
+            column: "role",
+            definition: "TEXT DEFAULT '' NOT NULL",
+        }],
+    },
+    Migration {
+        version: 24,
+        name: "create_sessions",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS sessions (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    client_id TEXT NOT NULL,
+                    role TEXT NOT NULL,
+                    created_at INTEGER DEFAULT (strftime('%s', 'now')) NOT NULL,
+                    expires_at INTEGER NOT NULL,
+                    revoked_at INTEGER DEFAULT 0 NOT NULL
+                )",
+            ),
+            Step::Sql("CREATE INDEX IF NOT EXISTS session_client_idx ON sessions (client_id)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS session_expires_at_idx ON sessions (expires_at)"),
+        ],
+    },
+    Migration {
+        version: 25,
+        name: "create_totp_secrets",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS totp_secrets (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    subject TEXT NOT NULL,
+                    secret TEXT NOT NULL,
+                    enabled_at INTEGER DEFAULT 0 NOT NULL,
+                    last_step INTEGER DEFAULT 0 NOT NULL,
+                    created_at INTEGER DEFAULT (strftime('%s', 'now')) NOT NULL
+                )",
+            ),
+            Step::Sql("CREATE UNIQUE INDEX IF NOT EXISTS totp_subject_unq_idx ON totp_secrets (subject)"),
+        ],
+    },
+    Migration {
+        version: 26,
+        name: "keys_metadata",
+        steps: &[
+            Step::AddColumn {
+                table: "keys",
+                column: "note",
+                definition: "TEXT DEFAULT '' NOT NULL",
+            },
+            Step::AddColumn {
+                table: "keys",
+                column: "tags",
+                definition: "TEXT DEFAULT '' NOT NULL",
+            },
+            Step::AddColumn {
+                table: "keys",
+                column: "weight",
+                definition: "INTEGER DEFAULT 0 NOT NULL",
+            },
+            Step::AddColumn {
+                table: "key_scores",
+                column: "weight",
+                definition: "INTEGER DEFAULT 0 NOT NULL",
+            },
+        ],
+    },
+    Migration {
+        version: 27,
+        name: "create_key_tags",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS key_tags (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    key_id TEXT NOT NULL,
+                    provider TEXT NOT NULL,
+                    tag TEXT NOT NULL
+                )",
+            ),
+            Step::Sql("CREATE UNIQUE INDEX IF NOT EXISTS key_tags_key_tag_unq_idx ON key_tags (key_id, tag)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS key_tags_provider_tag_idx ON key_tags (provider, tag)"),
+            Step::Sql(crate::d1_storage::SYNC_KEY_TAGS_SQL),
+        ],
+    },
+    // Client keys are hashed in Rust, on first use or by the daily job, as SQLite has no SHA-256.
+    Migration {
+        version: 28,
+        name: "client_keys_prefix",
+        steps: &[Step::AddColumn {
+            table: "client_keys",
+            column: "key_prefix",
+            definition: "TEXT DEFAULT '' NOT NULL",
+        }],
+    },
+];
+
+const CREATE_MIGRATIONS_TABLE: &str = "CREATE TABLE IF NOT EXISTS schema_migrations (
+    version INTEGER PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    applied_at INTEGER NOT NULL
+)";
+
+/// A migration and when it was applied, for the admin API.
+#[derive(Serialize, Debug)]
+pub struct MigrationStatus {
+    pub version: u32,
+    pub name: &'static str,
+    /// Unix seconds, or `None` while pending.
+    pub applied_at: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct AppliedRow {
+    version: u32,
+    applied_at: i64,
+}
+
+#[derive(Deserialize)]
+struct ColumnRow {
+    name: String,
+}
+
+async fn applied_versions(db: &D1Database) -> Result<HashMap<u32, i64>> {
+    db.prepare(CREATE_MIGRATIONS_TABLE).run().await?;
+    let rows: Vec<AppliedRow> = db
+        .prepare("SELECT version, applied_at FROM schema_migrations")
+        .all()
+        .await?
+        .results()?;
+    Ok(rows.into_iter().map(|row| (row.version, row.applied_at)).collect())
+}
+
+async fn existing_columns(db: &D1Database, table: &str) -> Result<HashSet<String>> {
+    let rows: Vec<ColumnRow> = db
+        .prepare("SELECT name FROM pragma_table_info(?1)")
+        .bind_refs(&[D1Type::Text(table)])?
+        .all()
+        .await?
+        .results()?;
+    Ok(rows.into_iter().map(|row| row.name).collect())
+}
+
+/// Lists every migration with its applied time.
+pub async fn status(db: &D1Database) -> Result<Vec<MigrationStatus>> {
+    let applied = applied_versions(db).await?;
+    Ok(MIGRATIONS
+        .iter()
+        .map(|m| MigrationStatus {
+            version: m.version,
+            name: m.name,
+            applied_at: applied.get(&m.version).copied(),
+        })
+        .collect())
+}
+
+/// The migrations not applied yet, in order.
+pub async fn pending(db: &D1Database) -> Result<Vec<&'static Migration>> {
+    let applied = applied_versions(db).await?;
+    Ok(MIGRATIONS.iter().filter(|m| !applied.contains_key(&m.version)).collect())
+}
+
+/// Applies pending migrations in order and returns the versions applied. Each migration
+/// runs in one D1 batch together with its `schema_migrations` row, so it is applied
+/// entirely or not at all; a concurrent run of the same migration fails on that row.
+pub async fn apply_pending(db: &D1Database) -> Result<Vec<u32>> {
+    let applied = applied_versions(db).await?;
+    let mut newly_applied = Vec::new();
+
+    for migration in MIGRATIONS.iter().filter(|m| !applied.contains_key(&m.version)) {
+        let mut statements = Vec::with_capacity(migration.steps.len() + 1);
+        for step in migration.steps {
+            match step {
+                Step::Sql(sql) => statements.push(db.prepare(*sql)),
+                Step::AddColumn { table, column, definition } => {
+                    if existing_columns(db, table).await?.contains(*column) {
+                        continue;
+                    }
+                    statements.push(db.prepare(format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition)));
+                }
+            }
+        }
+        let now = (Date::now() / 1000.0) as i64;
+        statements.push(
+            db.prepare("INSERT INTO schema_migrations (version, name, applied_at) VALUES (?1, ?2, ?3)")
+                .bind_refs(&[
+                    d1_integer(migration.version as i64),
+                    D1Type::Text(migration.name),
+                    d1_integer(now),
+                ])?,
+        );
+        db.batch(statements).await?;
+
+        info!(version = migration.version, name = migration.name, "Applied schema migration.");
+        newly_applied.push(migration.version);
+    }
+
+    Ok(newly_applied)
+}
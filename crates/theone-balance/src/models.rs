@@ -12,12 +12,98 @@ pub struct OpenAiChatCompletionRequest {
     pub messages: Vec<OpenAiChatMessage>,
     #[serde(default)]
     pub stream: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<OpenAiTool>,
+    /// `"none"`, `"auto"`, `"required"` or `{"type": "function", "function": {"name": ...}}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<OpenAiResponseFormat>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// Supersedes `max_tokens` in newer clients.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_completion_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<OpenAiStop>,
+}
+
+/// Stop sequences: a single string or a list.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum OpenAiStop {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OpenAiStop {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            OpenAiStop::One(stop) => vec![stop],
+            OpenAiStop::Many(stops) => stops,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct OpenAiChatMessage {
     pub role: String,
-    pub content: String,
+    /// `None` for assistant messages that only call tools.
+    #[serde(default)]
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<OpenAiToolCall>,
+    /// The call a `tool` message answers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OpenAiTool {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: OpenAiFunctionDefinition,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OpenAiFunctionDefinition {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// A JSON schema of the arguments.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OpenAiToolCall {
+    /// The position of the call in a streamed delta.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: OpenAiFunctionCall,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OpenAiFunctionCall {
+    pub name: String,
+    /// The arguments as a JSON string.
+    pub arguments: String,
+}
+
+/// JSON mode: `{"type": "json_object"}`, or `{"type": "json_schema", "json_schema": {"schema": ...}}`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OpenAiResponseFormat {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json_schema: Option<serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -31,6 +117,8 @@ pub enum EmbeddingInput {
 pub struct OpenAiEmbeddingsRequest {
     pub input: EmbeddingInput,
     pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -69,6 +157,9 @@ pub struct OpenAiError {
     pub param: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub code: Option<String>,
+    /// Seconds until a key is expected to be available again, for pool exhaustion errors.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -89,21 +180,277 @@ pub struct OpenAiChatChoice {
     pub message: OpenAiChatMessage,
 }
 
+/// One event of a streamed chat completion. The last one has no choices and carries the usage.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OpenAiChatCompletionChunk {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<OpenAiChatChunkChoice>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<OpenAiUsage>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OpenAiChatChunkChoice {
+    pub index: u32,
+    pub delta: OpenAiChatDelta,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct OpenAiChatDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<OpenAiToolCall>,
+}
+
+
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OpenAiModel {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub owned_by: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OpenAiModelList {
+    pub object: String,
+    pub data: Vec<OpenAiModel>,
+}
+
+/// Response of the `/api/availability/{provider}/{model}` preflight check.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeyAvailability {
+    pub provider: String,
+    pub model: String,
+    pub available: bool,
+    /// Keys that could serve a request for this model right now.
+    pub usable_keys: usize,
+    /// When nothing is available: why (`no_keys_available` or `provider_observe_only`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// When nothing is available: seconds until the earliest cooldown expires, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+}
+
+/// Body of `POST /api/compat/tokens/count`: the `provider/model` and messages of a chat
+/// completion request (other fields are ignored).
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TokenCountRequest {
+    pub model: String,
+    pub messages: Vec<OpenAiChatMessage>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TokenCountResponse {
+    pub model: String,
+    pub input_tokens: u64,
+    /// `provider` when the provider counted the tokens, `estimate` otherwise.
+    pub method: String,
+}
+
+// ===================================================================
+// == Cohere Embed API Models (for /compat/embeddings) ==
+// ===================================================================
+
+#[derive(Serialize, Debug)]
+pub struct CohereEmbedRequest {
+    pub model: String,
+    pub texts: Vec<String>,
+    pub input_type: String,
+    pub embedding_types: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_dimension: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CohereEmbedResponse {
+    pub embeddings: CohereEmbeddings,
+    #[serde(default)]
+    pub meta: CohereMeta,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CohereEmbeddings {
+    #[serde(default)]
+    pub float: Vec<Vec<f32>>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct CohereMeta {
+    #[serde(default)]
+    pub billed_units: CohereBilledUnits,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct CohereBilledUnits {
+    #[serde(default)]
+    pub input_tokens: u32,
+}
+
+// ===================================================================
+// == Rerank API Models (Cohere/Jina-style, for /compat/rerank) ==
+// ===================================================================
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum RerankDocument {
+    Text(String),
+    Object { text: String },
+}
+
+impl RerankDocument {
+    pub fn text(&self) -> &str {
+        match self {
+            RerankDocument::Text(text) | RerankDocument::Object { text } => text,
+        }
+    }
+}
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RerankRequest {
+    pub model: String,
+    pub query: String,
+    pub documents: Vec<RerankDocument>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_n: Option<u32>,
+    #[serde(default)]
+    pub return_documents: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RerankResponse {
+    pub model: String,
+    pub results: Vec<RerankResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RerankResult {
+    pub index: u32,
+    pub relevance_score: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document: Option<RerankResultDocument>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RerankResultDocument {
+    pub text: String,
+}
 
 // =================================================================================
-// == Native Google Gemini API Models (for /google-ai-studio/... proxy routes AND internal embeddings translation) ==
+// == Native Cloudflare Workers AI Models (for reranker translation) ==
 // =================================================================================
 
 #[derive(Serialize, Deserialize, Debug)]
+pub struct WorkersAiRerankRequest {
+    pub query: String,
+    pub contexts: Vec<WorkersAiRerankContext>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WorkersAiRerankContext {
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WorkersAiRerankResponse {
+    pub result: WorkersAiRerankResult,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WorkersAiRerankResult {
+    #[serde(default)]
+    pub response: Vec<WorkersAiRerankScore>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WorkersAiRerankScore {
+    pub id: u32,
+    pub score: f64,
+}
+
+// =================================================================================
+// == Native Google Gemini API Models (for /google-ai-studio/... proxy routes AND internal embeddings translation) ==
+// =================================================================================
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
 pub struct GeminiChatRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<GeminiContent>,
     pub contents: Vec<GeminiContent>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<GeminiTool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_config: Option<GeminiToolConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation_config: Option<GeminiGenerationConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiTool {
+    pub function_declarations: Vec<GeminiFunctionDeclaration>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GeminiFunctionDeclaration {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The OpenAPI subset of JSON schema Gemini accepts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiToolConfig {
+    pub function_calling_config: GeminiFunctionCallingConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiFunctionCallingConfig {
+    /// `AUTO`, `ANY` or `NONE`.
+    pub mode: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_function_names: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiGenerationConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_mime_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_schema: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop_sequences: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct GeminiChatResponse {
     pub candidates: Vec<GeminiCandidate>,
+    #[serde(default)]
+    pub usage_metadata: Option<GeminiUsageMetadata>,
 }
 
 #[derive(Serialize, Debug)]
@@ -129,9 +476,39 @@ pub struct GeminiEmbeddingContent {
     pub content: GeminiContent,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// A part of a message: text, a function call by the model, or the result of one.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
 pub struct GeminiPart {
-    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<GeminiFunctionCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_response: Option<GeminiFunctionResponse>,
+}
+
+impl GeminiPart {
+    pub fn text(text: impl Into<String>) -> Self {
+        GeminiPart {
+            text: Some(text.into()),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeminiFunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub args: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeminiFunctionResponse {
+    pub name: String,
+    /// Always a JSON object.
+    pub response: serde_json::Value,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -142,6 +519,37 @@ pub struct GeminiCandidate {
     pub index: u32,
 }
 
+/// One event of a `streamGenerateContent` stream. Candidates and their fields come and go
+/// between events, e.g. the finish reason only arrives with the last text.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GeminiStreamChunk {
+    pub candidates: Vec<GeminiStreamCandidate>,
+    pub usage_metadata: Option<GeminiUsageMetadata>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GeminiStreamCandidate {
+    pub content: Option<GeminiStreamContent>,
+    pub finish_reason: Option<String>,
+    pub index: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct GeminiStreamContent {
+    pub parts: Vec<GeminiPart>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GeminiUsageMetadata {
+    pub prompt_token_count: u32,
+    pub candidates_token_count: u32,
+    pub total_token_count: u32,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GeminiEmbeddingValue {
     pub values: Vec<f32>,
@@ -0,0 +1,1104 @@
+//! This module backs `GET /openapi.json`, an OpenAPI 3.1 description of the proxy's compat
+//! routes, the JSON admin API and the status endpoints, so clients can generate SDKs. The
+//! spec is maintained by hand next to the routes in `router.rs` and `admin.rs`: a route or
+//! a request/response type that changes there needs the matching change here. The UI's
+//! form and fragment routes are left out.
+
+use axum::{
+    http::header,
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::{Map, Value, json};
+
+/// One operation of a path, built up field by field.
+struct Operation {
+    tag: &'static str,
+    summary: &'static str,
+    description: Option<&'static str>,
+    parameters: Vec<Value>,
+    request_body: Option<Value>,
+    responses: Map<String, Value>,
+    public: bool,
+}
+
+impl Operation {
+    fn new(tag: &'static str, summary: &'static str) -> Self {
+        Operation {
+            tag,
+            summary,
+            description: None,
+            parameters: Vec::new(),
+            request_body: None,
+            responses: Map::new(),
+            public: false,
+        }
+    }
+
+    fn description(mut self, description: &'static str) -> Self {
+        self.description = Some(description);
+        self
+    }
+
+    /// A path segment; catch-all segments (`{*model}` in axum) may contain slashes.
+    fn path_param(mut self, name: &str, description: &str) -> Self {
+        self.parameters.push(json!({
+            "name": name,
+            "in": "path",
+            "required": true,
+            "description": description,
+            "schema": { "type": "string" },
+        }));
+        self
+    }
+
+    fn query_param(mut self, name: &str, schema: Value, description: &str) -> Self {
+        self.parameters.push(json!({
+            "name": name,
+            "in": "query",
+            "required": false,
+            "description": description,
+            "schema": schema,
+        }));
+        self
+    }
+
+    fn header_param(mut self, name: &str, description: &str) -> Self {
+        self.parameters.push(json!({
+            "name": name,
+            "in": "header",
+            "required": false,
+            "description": description,
+            "schema": { "type": "string" },
+        }));
+        self
+    }
+
+    fn json_body(self, schema: Value) -> Self {
+        self.body(&[("application/json", schema)])
+    }
+
+    fn body(mut self, content: &[(&str, Value)]) -> Self {
+        let content: Map<String, Value> = content
+            .iter()
+            .map(|(content_type, schema)| (content_type.to_string(), json!({ "schema": schema })))
+            .collect();
+        self.request_body = Some(json!({ "required": true, "content": content }));
+        self
+    }
+
+    /// A response with a JSON body, or without one for `None`.
+    fn response(self, status: u16, description: &str, schema: Option<Value>) -> Self {
+        let content = schema
+            .map(|schema| vec![("application/json", schema)])
+            .unwrap_or_default();
+        self.response_with(status, description, &content)
+    }
+
+    fn response_with(mut self, status: u16, description: &str, content: &[(&str, Value)]) -> Self {
+        let mut response = json!({ "description": description });
+        if !content.is_empty() {
+            let content: Map<String, Value> = content
+                .iter()
+                .map(|(content_type, schema)| {
+                    (content_type.to_string(), json!({ "schema": schema }))
+                })
+                .collect();
+            response["content"] = Value::Object(content);
+        }
+        self.responses.insert(status.to_string(), response);
+        self
+    }
+
+    /// Callable without credentials.
+    fn public(mut self) -> Self {
+        self.public = true;
+        self
+    }
+
+    fn build(self) -> Value {
+        let mut operation = json!({
+            "tags": [self.tag],
+            "summary": self.summary,
+            "responses": self.responses,
+        });
+        if let Some(description) = self.description {
+            operation["description"] = json!(description);
+        }
+        if !self.parameters.is_empty() {
+            operation["parameters"] = Value::Array(self.parameters);
+        }
+        if let Some(body) = self.request_body {
+            operation["requestBody"] = body;
+        }
+        if self.public {
+            operation["security"] = json!([]);
+        }
+        operation
+    }
+}
+
+/// The paths of the spec, in the order they are added.
+#[derive(Default)]
+struct Paths(Map<String, Value>);
+
+impl Paths {
+    fn route(mut self, method: &str, path: &str, operation: Operation) -> Self {
+        let item = self.0.entry(path).or_insert_with(|| json!({}));
+        item[method] = operation.build();
+        self
+    }
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn array_of(schema: Value) -> Value {
+    json!({ "type": "array", "items": schema })
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn integer() -> Value {
+    json!({ "type": "integer" })
+}
+
+// region: --- Paths
+
+/// The OpenAI-compatible routes; their errors use the OpenAI error shape.
+fn compat_paths(paths: Paths) -> Paths {
+    let openai_errors = |operation: Operation| {
+        operation
+            .response(
+                400,
+                "Invalid request body.",
+                Some(schema_ref("OpenAiError")),
+            )
+            .response(401, "Invalid credentials.", Some(schema_ref("OpenAiError")))
+            .response(
+                403,
+                "The client key may not use this provider or model.",
+                Some(schema_ref("OpenAiError")),
+            )
+            .response(
+                429,
+                "Every key is cooling down, or a client quota is used up.",
+                Some(schema_ref("OpenAiError")),
+            )
+    };
+    paths
+        .route(
+            "post",
+            "/api/compat/chat/completions",
+            openai_errors(
+                Operation::new("compat", "Create a chat completion")
+                    .description(
+                        "OpenAI-compatible chat completions for any provider, with the model given as \
+                         `provider/model`. Failed keys are retried with the next key of the pool. With \
+                         `stream: true` the response is a `text/event-stream` of completion chunks.",
+                    )
+                    .json_body(schema_ref("ChatCompletionRequest"))
+                    .response_with(
+                        200,
+                        "The completion, or its chunks when streaming.",
+                        &[
+                            ("application/json", schema_ref("ChatCompletion")),
+                            ("text/event-stream", string()),
+                        ],
+                    ),
+            ),
+        )
+        .route(
+            "post",
+            "/api/compat/embeddings",
+            openai_errors(
+                Operation::new("compat", "Create embeddings")
+                    .json_body(schema_ref("EmbeddingRequest"))
+                    .response(200, "The embeddings, in the OpenAI format.", Some(json!({ "type": "object" }))),
+            ),
+        )
+        .route(
+            "post",
+            "/api/compat/rerank",
+            openai_errors(
+                Operation::new("compat", "Rerank documents for a query")
+                    .json_body(schema_ref("RerankRequest"))
+                    .response(200, "The documents by relevance.", Some(schema_ref("RerankResponse"))),
+            ),
+        )
+        .route(
+            "post",
+            "/api/compat/audio/transcriptions",
+            openai_errors(
+                Operation::new("compat", "Transcribe audio")
+                    .body(&[(
+                        "multipart/form-data",
+                        json!({
+                            "type": "object",
+                            "required": ["file", "model"],
+                            "properties": {
+                                "file": { "type": "string", "format": "binary" },
+                                "model": { "type": "string", "description": "`provider/model`." },
+                            },
+                        }),
+                    )])
+                    .response(200, "The transcription, in the OpenAI format.", Some(json!({ "type": "object" }))),
+            ),
+        )
+        .route(
+            "post",
+            "/api/compat/images/generations",
+            openai_errors(
+                Operation::new("compat", "Generate images")
+                    .json_body(json!({
+                        "type": "object",
+                        "required": ["model", "prompt"],
+                        "properties": {
+                            "model": { "type": "string", "description": "`provider/model`." },
+                            "prompt": { "type": "string" },
+                            "response_format": { "type": "string", "enum": ["url", "b64_json"] },
+                        },
+                    }))
+                    .response(200, "The images, in the OpenAI format.", Some(json!({ "type": "object" }))),
+            ),
+        )
+        .route(
+            "get",
+            "/api/compat/models",
+            Operation::new("compat", "List models")
+                .description("The models of every provider with active keys, as `provider/model` ids.")
+                .response(200, "The models, in the OpenAI format.", Some(schema_ref("ModelList")))
+                .response(401, "Invalid credentials.", Some(schema_ref("OpenAiError"))),
+        )
+        .route(
+            "post",
+            "/api/compat/tokens/count",
+            openai_errors(
+                Operation::new("compat", "Count the prompt tokens of a chat request")
+                    .description(
+                        "Counted by the provider for Gemini models, estimated locally otherwise. Nothing \
+                         is charged to keys or client quotas.",
+                    )
+                    .json_body(schema_ref("TokenCountRequest"))
+                    .response(200, "The token count.", Some(schema_ref("TokenCountResponse"))),
+            ),
+        )
+        .route(
+            "get",
+            "/api/availability/{provider}/{model}",
+            Operation::new("compat", "Check whether a model can be served now")
+                .description("Nothing is sent upstream.")
+                .path_param("provider", "The provider, e.g. `google-ai-studio`.")
+                .path_param("model", "The model; may contain slashes, e.g. `@cf/meta/llama-3-8b-instruct`.")
+                .response(200, "The number of usable keys.", Some(schema_ref("KeyAvailability")))
+                .response(401, "Invalid credentials.", Some(schema_ref("OpenAiError")))
+                .response(403, "The client key may not use this provider or model.", Some(schema_ref("OpenAiError"))),
+        )
+        .route(
+            "post",
+            "/api/{provider}/{path}",
+            Operation::new("proxy", "Proxy a provider's native API")
+                .description(
+                    "Forwards the request to the provider's own API with a key from the pool. GET, PUT, \
+                     PATCH and DELETE are proxied the same way. Request and response bodies are the \
+                     provider's.",
+                )
+                .path_param("provider", "The provider, e.g. `openai`.")
+                .path_param("path", "The provider's path; may contain slashes, e.g. `v1/chat/completions`.")
+                .response(200, "The provider's response.", None)
+                .response(401, "Invalid credentials.", Some(schema_ref("OpenAiError"))),
+        )
+}
+
+fn admin_paths(paths: Paths) -> Paths {
+    let admin = |summary| Operation::new("admin", summary);
+    let key_transfer_params = |operation: Operation| {
+        operation
+            .query_param("provider", string(), "Only this provider's keys.")
+            .query_param(
+                "format",
+                json!({ "type": "string", "enum": ["json", "csv"] }),
+                "Defaults to the content type.",
+            )
+    };
+    paths
+        .route(
+            "get",
+            "/api/admin/clients",
+            admin("List client keys").response(200, "The client keys, redacted.", Some(array_of(schema_ref("ClientKeySummary")))),
+        )
+        .route(
+            "post",
+            "/api/admin/clients",
+            admin("Create a client key")
+                .json_body(schema_ref("CreateClientRequest"))
+                .response(201, "The new client key; the only time its secret is returned.", Some(json!({ "type": "object" }))),
+        )
+        .route(
+            "delete",
+            "/api/admin/clients/{id}",
+            admin("Delete a client key").path_param("id", "The client key id.").response(204, "Deleted.", None),
+        )
+        .route(
+            "post",
+            "/api/admin/clients/{id}/revoke",
+            admin("Revoke a client key").path_param("id", "The client key id.").response(204, "Revoked.", None),
+        )
+        .route(
+            "put",
+            "/api/admin/clients/{id}/role",
+            admin("Set a client key's role")
+                .path_param("id", "The client key id.")
+                .json_body(json!({
+                    "type": "object",
+                    "required": ["role"],
+                    "properties": { "role": { "oneOf": [schema_ref("Role"), { "type": "null" }] } },
+                }))
+                .response(204, "Updated.", None)
+                .response(404, "No such client key.", Some(schema_ref("AdminError"))),
+        )
+        .route(
+            "get",
+            "/api/admin/sessions",
+            admin("List live UI sessions").response(200, "The sessions.", Some(array_of(schema_ref("Session")))),
+        )
+        .route(
+            "post",
+            "/api/admin/sessions/{id}/revoke",
+            admin("Revoke a UI session").path_param("id", "The session id.").response(204, "Revoked.", None),
+        )
+        .route(
+            "delete",
+            "/api/admin/totp/{subject}",
+            admin("Reset two-factor login")
+                .path_param("subject", "`master` or a client key id.")
+                .header_param("X-TOTP-Code", "A current code of the caller's own second factor; not needed with a session started with one.")
+                .response(204, "Reset.", None)
+                .response(403, "The caller hasn't passed their own second factor.", Some(schema_ref("AdminError"))),
+        )
+        .route(
+            "get",
+            "/api/admin/quotas",
+            admin("List client quotas and usage").response(200, "Every client's limits and usage.", Some(array_of(schema_ref("ClientQuotaStatus")))),
+        )
+        .route(
+            "put",
+            "/api/admin/quotas/{id}",
+            admin("Set a client's quota")
+                .path_param("id", "The client key id.")
+                .json_body(schema_ref("ClientQuota"))
+                .response(200, "The stored quota.", Some(schema_ref("ClientQuota")))
+                .response(404, "No such client key.", Some(schema_ref("AdminError"))),
+        )
+        .route(
+            "get",
+            "/api/admin/samples",
+            admin("Export sampled prompts and responses")
+                .query_param("provider", string(), "Only this provider's samples.")
+                .query_param("model", string(), "Only this model's samples.")
+                .query_param("since", integer(), "Unix seconds; samples created at or after it.")
+                .query_param("limit", integer(), "The number of samples.")
+                .response_with(200, "Newline-delimited JSON, newest first.", &[("application/x-ndjson", string())]),
+        )
+        .route(
+            "get",
+            "/api/admin/payloads",
+            admin("List logged payloads")
+                .query_param("provider", string(), "Only this provider's payloads.")
+                .query_param("limit", integer(), "The number of payloads.")
+                .response(200, "The latest logged prompts and responses.", Some(array_of(json!({ "type": "object" })))),
+        )
+        .route(
+            "get",
+            "/api/admin/alerts",
+            admin("List alert events").response(200, "Alert rules that started or stopped firing.", Some(array_of(schema_ref("AlertEvent")))),
+        )
+        .route(
+            "get",
+            "/api/admin/keys/export",
+            key_transfer_params(admin("Export keys"))
+                .response_with(
+                    200,
+                    "The keys with their status and health metrics.",
+                    &[("application/json", array_of(schema_ref("KeyRecord"))), ("text/csv", string())],
+                ),
+        )
+        .route(
+            "post",
+            "/api/admin/keys/import",
+            key_transfer_params(admin("Import keys"))
+                .description("Rejected as a whole if any record is invalid; existing keys are skipped.")
+                .body(&[("application/json", array_of(schema_ref("KeyRecord"))), ("text/csv", string())])
+                .response(200, "What was imported.", Some(schema_ref("ImportSummary")))
+                .response(400, "Invalid records; nothing was imported.", Some(schema_ref("AdminError"))),
+        )
+        .route(
+            "get",
+            "/api/admin/keys/changes",
+            admin("List changed keys")
+                .query_param("updated_since", integer(), "Unix seconds; ignored with a cursor.")
+                .query_param("cursor", string(), "The `next_cursor` of the previous page.")
+                .query_param("provider", string(), "Only this provider's keys.")
+                .query_param("limit", integer(), "Default 100, at most 1000.")
+                .response(200, "Keys changed since the cursor, oldest change first.", Some(schema_ref("KeyChangesResponse")))
+                .response(400, "Invalid cursor.", Some(schema_ref("AdminError"))),
+        )
+        .route(
+            "post",
+            "/api/admin/keys/delete",
+            admin("Move keys to the trash")
+                .json_body(schema_ref("KeyIdsRequest"))
+                .response(204, "Moved.", None),
+        )
+        .route(
+            "post",
+            "/api/admin/keys/restore",
+            admin("Restore keys from the trash").json_body(schema_ref("KeyIdsRequest")).response(204, "Restored.", None),
+        )
+        .route(
+            "post",
+            "/api/admin/keys/purge",
+            admin("Permanently delete keys in the trash").json_body(schema_ref("KeyIdsRequest")).response(204, "Purged.", None),
+        )
+        .route(
+            "get",
+            "/api/admin/keys/{id}/scores",
+            admin("List a key's sampled routing scores")
+                .path_param("id", "The key id.")
+                .response(200, "The stored rankings.", Some(array_of(json!({ "type": "object" })))),
+        )
+        .route(
+            "get",
+            "/api/admin/providers",
+            admin("List provider settings").response(200, "The stored provider settings.", Some(array_of(schema_ref("ProviderSettings")))),
+        )
+        .route(
+            "put",
+            "/api/admin/providers/{provider}",
+            admin("Update provider settings")
+                .path_param("provider", "The provider.")
+                .json_body(schema_ref("ProviderSettingsRequest"))
+                .response(204, "Updated.", None),
+        )
+        .route(
+            "get",
+            "/api/admin/custom-providers",
+            admin("List custom providers").response(200, "The registered providers.", Some(array_of(schema_ref("CustomProvider")))),
+        )
+        .route(
+            "put",
+            "/api/admin/custom-providers/{name}",
+            admin("Register or update a custom provider")
+                .path_param("name", "The provider name, used as the model prefix.")
+                .json_body(schema_ref("CustomProviderRequest"))
+                .response(204, "Stored.", None)
+                .response(400, "Invalid provider.", Some(schema_ref("AdminError"))),
+        )
+        .route(
+            "delete",
+            "/api/admin/custom-providers/{name}",
+            admin("Remove a custom provider").path_param("name", "The provider name.").response(204, "Removed.", None),
+        )
+        .route(
+            "get",
+            "/api/admin/settings",
+            admin("List settings")
+                .response(200, "The effective settings and the stored overrides.", Some(schema_ref("SettingsResponse"))),
+        )
+        .route(
+            "put",
+            "/api/admin/settings/{name}",
+            admin("Override a setting")
+                .path_param("name", "The var name, e.g. `TARGET_TIMEOUT_MS`.")
+                .json_body(json!({ "type": "object", "required": ["value"], "properties": { "value": string() } }))
+                .response(204, "Stored.", None)
+                .response(400, "Unknown setting or invalid value.", Some(schema_ref("AdminError"))),
+        )
+        .route(
+            "delete",
+            "/api/admin/settings/{name}",
+            admin("Remove a setting override").path_param("name", "The var name.").response(204, "Removed.", None),
+        )
+        .route(
+            "get",
+            "/api/admin/inflight",
+            admin("List requests in flight")
+                .query_param("min_age_ms", integer(), "Only requests running at least this long.")
+                .response(200, "The requests, oldest first.", Some(array_of(schema_ref("InflightEntry"))))
+                .response(501, "The in-flight registry is not configured.", Some(schema_ref("AdminError"))),
+        )
+        .route(
+            "post",
+            "/api/admin/inflight/{id}/cancel",
+            admin("Cancel a request in flight")
+                .path_param("id", "The request id.")
+                .response(202, "Cancellation requested.", None)
+                .response(404, "No request with this id is in flight.", Some(schema_ref("AdminError"))),
+        )
+        .route(
+            "get",
+            "/api/admin/debug/penalty-box",
+            admin("Dump the penalty box of the answering isolate")
+                .response(200, "The benched keys.", Some(json!({ "type": "object" }))),
+        )
+        .route(
+            "get",
+            "/api/admin/debug/sql/{operation}",
+            admin("Preview the SQL of a storage operation")
+                .description("The operation's arguments come from the query string. Nothing is run.")
+                .path_param("operation", "The operation, e.g. `list_keys`.")
+                .response(200, "The statements and their parameters.", Some(json!({ "type": "object" }))),
+        )
+        .route(
+            "get",
+            "/api/admin/debug/scores/{provider}",
+            admin("Explain a provider's key scores")
+                .path_param("provider", "The provider.")
+                .response(200, "The weights in effect and each key's inputs and score.", Some(json!({ "type": "object" }))),
+        )
+        .route(
+            "get",
+            "/api/admin/routing/{provider}/{model}",
+            admin("Rank a provider's keys for a model")
+                .path_param("provider", "The provider.")
+                .path_param("model", "The model; may contain slashes.")
+                .response(200, "The keys in the order a request would try them.", Some(json!({ "type": "object" }))),
+        )
+        .route(
+            "get",
+            "/api/admin/migrations",
+            admin("List schema migrations").response(200, "Every migration and when it was applied.", Some(array_of(schema_ref("MigrationStatus")))),
+        )
+        .route(
+            "post",
+            "/api/admin/migrations/apply",
+            admin("Apply pending migrations").response(
+                200,
+                "The versions applied and the migrations afterwards.",
+                Some(json!({
+                    "type": "object",
+                    "properties": {
+                        "applied": array_of(integer()),
+                        "migrations": array_of(schema_ref("MigrationStatus")),
+                    },
+                })),
+            ),
+        )
+        .route(
+            "get",
+            "/api/admin/schema/drift",
+            admin("Compare the live tables with the models").response(200, "The drift report.", Some(json!({ "type": "object" }))),
+        )
+}
+
+fn status_paths(paths: Paths) -> Paths {
+    paths
+        .route(
+            "get",
+            "/api/status",
+            Operation::new("status", "Summarize each provider's health")
+                .public()
+                .response(
+                    200,
+                    "Key pool sizes, breaker state, error rate and latency per provider.",
+                    Some(schema_ref("StatusResponse")),
+                ),
+        )
+        .route(
+            "get",
+            "/version",
+            Operation::new("status", "Show the build serving traffic")
+                .public()
+                .response(
+                    200,
+                    "The version, commit and features.",
+                    Some(schema_ref("BuildInfo")),
+                ),
+        )
+        .route(
+            "get",
+            "/metrics",
+            Operation::new("status", "Export Prometheus metrics").response_with(
+                200,
+                "Metrics in the Prometheus text format.",
+                &[("text/plain", string())],
+            ),
+        )
+        .route(
+            "get",
+            "/openapi.json",
+            Operation::new("status", "This document").public().response(
+                200,
+                "The OpenAPI description.",
+                Some(json!({ "type": "object" })),
+            ),
+        )
+}
+
+// endregion: --- Paths
+
+// region: --- Schemas
+
+fn schemas() -> Map<String, Value> {
+    [
+        ("OpenAiError", json!({
+            "type": "object",
+            "properties": {
+                "error": {
+                    "type": "object",
+                    "properties": {
+                        "message": string(),
+                        "type": string(),
+                        "param": { "type": ["string", "null"] },
+                        "code": { "type": ["string", "null"] },
+                        "retry_after": { "type": ["integer", "null"], "description": "Seconds until a key is free again." },
+                    },
+                },
+            },
+        })),
+        ("AdminError", json!({
+            "type": "object",
+            "properties": {
+                "error": string(),
+                "details": { "type": "array", "items": string(), "description": "Per-record errors of an import." },
+            },
+        })),
+        ("ChatMessage", json!({
+            "type": "object",
+            "required": ["role"],
+            "properties": {
+                "role": { "type": "string", "enum": ["system", "developer", "user", "assistant", "tool"] },
+                "content": { "type": ["string", "null"] },
+                "tool_calls": array_of(json!({ "type": "object" })),
+                "tool_call_id": string(),
+            },
+        })),
+        ("ChatCompletionRequest", json!({
+            "type": "object",
+            "required": ["model", "messages"],
+            "description": "Fields the gateway doesn't know are passed through.",
+            "properties": {
+                "model": { "type": "string", "description": "`provider/model`, e.g. `google-ai-studio/gemini-2.5-flash`." },
+                "messages": array_of(schema_ref("ChatMessage")),
+                "stream": { "type": "boolean" },
+                "tools": array_of(json!({ "type": "object" })),
+                "tool_choice": {},
+                "response_format": { "type": "object" },
+                "temperature": { "type": "number", "minimum": 0, "maximum": 2 },
+                "top_p": { "type": "number", "minimum": 0, "maximum": 1 },
+                "max_tokens": integer(),
+                "max_completion_tokens": integer(),
+                "stop": {},
+            },
+            "additionalProperties": true,
+        })),
+        ("ChatCompletion", json!({
+            "type": "object",
+            "properties": {
+                "id": string(),
+                "object": string(),
+                "created": integer(),
+                "model": string(),
+                "choices": array_of(json!({
+                    "type": "object",
+                    "properties": {
+                        "index": integer(),
+                        "message": schema_ref("ChatMessage"),
+                        "finish_reason": { "type": ["string", "null"] },
+                    },
+                })),
+                "usage": {
+                    "type": "object",
+                    "properties": {
+                        "prompt_tokens": integer(),
+                        "completion_tokens": integer(),
+                        "total_tokens": integer(),
+                    },
+                },
+            },
+        })),
+        ("EmbeddingRequest", json!({
+            "type": "object",
+            "required": ["model", "input"],
+            "properties": {
+                "model": { "type": "string", "description": "`provider/model`." },
+                "input": { "oneOf": [string(), array_of(string())] },
+            },
+            "additionalProperties": true,
+        })),
+        ("RerankRequest", json!({
+            "type": "object",
+            "required": ["model", "query", "documents"],
+            "properties": {
+                "model": { "type": "string", "description": "`provider/model`." },
+                "query": string(),
+                "documents": array_of(json!({ "oneOf": [string(), { "type": "object", "properties": { "text": string() } }] })),
+                "top_n": integer(),
+                "return_documents": { "type": "boolean" },
+            },
+        })),
+        ("RerankResponse", json!({
+            "type": "object",
+            "properties": {
+                "model": string(),
+                "results": array_of(json!({
+                    "type": "object",
+                    "properties": {
+                        "index": integer(),
+                        "relevance_score": { "type": "number" },
+                        "document": { "type": "object" },
+                    },
+                })),
+            },
+        })),
+        ("ModelList", json!({
+            "type": "object",
+            "properties": {
+                "object": string(),
+                "data": array_of(json!({
+                    "type": "object",
+                    "properties": { "id": string(), "object": string(), "owned_by": string() },
+                })),
+            },
+        })),
+        ("TokenCountRequest", json!({
+            "type": "object",
+            "required": ["model", "messages"],
+            "properties": {
+                "model": { "type": "string", "description": "`provider/model`." },
+                "messages": array_of(schema_ref("ChatMessage")),
+            },
+        })),
+        ("TokenCountResponse", json!({
+            "type": "object",
+            "properties": {
+                "model": string(),
+                "input_tokens": integer(),
+                "method": { "type": "string", "enum": ["provider", "estimate"] },
+            },
+        })),
+        ("KeyAvailability", json!({
+            "type": "object",
+            "properties": {
+                "provider": string(),
+                "model": string(),
+                "available": { "type": "boolean" },
+                "usable_keys": integer(),
+                "reason": { "type": "string", "enum": ["no_keys_available", "provider_observe_only"] },
+                "retry_after": { "type": "integer", "description": "Seconds until the earliest cooldown ends." },
+            },
+        })),
+        ("Role", json!({ "type": "string", "enum": ["viewer", "operator", "admin"] })),
+        ("ClientKeySummary", json!({
+            "type": "object",
+            "properties": {
+                "id": string(),
+                "name": string(),
+                "key_preview": string(),
+                "allowed_providers": array_of(string()),
+                "allowed_models": array_of(string()),
+                "role": { "oneOf": [schema_ref("Role"), { "type": "null" }] },
+                "revoked": { "type": "boolean" },
+                "created_at": integer(),
+                "last_used_at": integer(),
+            },
+        })),
+        ("CreateClientRequest", json!({
+            "type": "object",
+            "required": ["name"],
+            "properties": {
+                "name": string(),
+                "allowed_providers": array_of(string()),
+                "allowed_models": { "type": "array", "items": string(), "description": "Glob patterns, e.g. `gemini-2.5-*`." },
+                "role": { "oneOf": [schema_ref("Role"), { "type": "null" }], "description": "Omit for a key that only proxies requests." },
+            },
+        })),
+        ("Session", json!({
+            "type": "object",
+            "properties": {
+                "id": string(),
+                "client_id": { "type": "string", "description": "Empty for the master key." },
+                "role": schema_ref("Role"),
+                "created_at": integer(),
+                "expires_at": integer(),
+            },
+        })),
+        ("ClientQuota", json!({
+            "type": "object",
+            "description": "0 means unlimited.",
+            "properties": {
+                "max_requests_per_day": integer(),
+                "max_tokens_per_month": integer(),
+                "max_budget_micros": integer(),
+            },
+        })),
+        ("ClientQuotaStatus", json!({
+            "type": "object",
+            "properties": {
+                "client_id": string(),
+                "name": string(),
+                "revoked": { "type": "boolean" },
+                "quota": schema_ref("ClientQuota"),
+                "usage": {
+                    "type": "object",
+                    "properties": {
+                        "requests_today": integer(),
+                        "tokens_this_month": integer(),
+                        "cost_micros_this_month": integer(),
+                    },
+                },
+            },
+        })),
+        ("AlertEvent", json!({
+            "type": "object",
+            "properties": {
+                "provider": string(),
+                "metric": { "type": "string", "enum": ["active_keys", "usable_keys", "success_rate"] },
+                "state": { "type": "string", "enum": ["firing", "resolved"] },
+                "value": integer(),
+                "threshold": integer(),
+                "message": string(),
+                "created_at": integer(),
+            },
+        })),
+        ("KeyRecord", json!({
+            "type": "object",
+            "required": ["provider", "key"],
+            "properties": {
+                "provider": string(),
+                "key": string(),
+                "status": { "type": "string", "enum": ["active", "blocked"], "default": "active" },
+                "latency_ms": integer(),
+                "success_rate": { "type": "number", "minimum": 0, "maximum": 1, "default": 1 },
+                "consecutive_failures": integer(),
+                "total_cooling_seconds": integer(),
+                "created_at": integer(),
+                "last_checked_at": integer(),
+                "last_succeeded_at": integer(),
+                "tier": { "type": "string", "enum": ["free", "paid"], "default": "free" },
+                "note": string(),
+                "tags": array_of(string()),
+                "weight": { "type": "integer", "minimum": -10000, "maximum": 10000 },
+            },
+        })),
+        ("ImportSummary", json!({
+            "type": "object",
+            "properties": {
+                "imported": integer(),
+                "duplicates": integer(),
+            },
+        })),
+        ("KeyChangesResponse", json!({
+            "type": "object",
+            "properties": {
+                "keys": array_of(json!({
+                    "type": "object",
+                    "properties": {
+                        "id": string(),
+                        "provider": string(),
+                        "key": string(),
+                        "status": string(),
+                        "latency_ms": integer(),
+                        "success_rate": { "type": "number" },
+                        "consecutive_failures": integer(),
+                        "total_cooling_seconds": integer(),
+                        "created_at": integer(),
+                        "updated_at": integer(),
+                        "deleted_at": { "type": "integer", "description": "0 unless the key is in the trash." },
+                        "tier": string(),
+                    },
+                })),
+                "next_cursor": string(),
+                "has_more": { "type": "boolean" },
+            },
+        })),
+        ("KeyIdsRequest", json!({
+            "type": "object",
+            "required": ["ids"],
+            "properties": { "ids": array_of(string()) },
+        })),
+        ("ProviderSettings", json!({
+            "type": "object",
+            "properties": {
+                "provider": string(),
+                "observe_only": { "type": "boolean" },
+                "rpm_limit": integer(),
+                "tpm_limit": integer(),
+                "updated_at": integer(),
+            },
+        })),
+        ("ProviderSettingsRequest", json!({
+            "type": "object",
+            "description": "Fields left out keep their value; 0 limits mean unlimited.",
+            "properties": {
+                "observe_only": { "type": "boolean" },
+                "rpm_limit": integer(),
+                "tpm_limit": integer(),
+            },
+        })),
+        ("CustomProvider", json!({
+            "type": "object",
+            "properties": {
+                "name": string(),
+                "base_url": string(),
+                "auth_header": string(),
+                "auth_scheme": string(),
+                "color": string(),
+                "updated_at": integer(),
+            },
+        })),
+        ("CustomProviderRequest", json!({
+            "type": "object",
+            "required": ["base_url"],
+            "properties": {
+                "base_url": { "type": "string", "format": "uri" },
+                "auth_header": { "type": "string", "default": "Authorization" },
+                "auth_scheme": { "type": "string", "default": "Bearer" },
+                "color": { "type": "string", "description": "A hex color, e.g. `#10b981`." },
+            },
+        })),
+        ("SettingsResponse", json!({
+            "type": "object",
+            "properties": {
+                "effective": { "type": "object", "description": "The settings in effect, vars and overrides combined." },
+                "overrides": { "type": "object", "additionalProperties": string() },
+            },
+        })),
+        ("InflightEntry", json!({
+            "type": "object",
+            "properties": {
+                "request_id": string(),
+                "provider": string(),
+                "model": string(),
+                "key_id": string(),
+                "started_at": { "type": "integer", "description": "Unix milliseconds." },
+                "running_ms": integer(),
+            },
+        })),
+        ("MigrationStatus", json!({
+            "type": "object",
+            "properties": {
+                "version": integer(),
+                "name": string(),
+                "applied_at": { "type": ["integer", "null"] },
+            },
+        })),
+        ("StatusResponse", json!({
+            "type": "object",
+            "properties": {
+                "generated_at": integer(),
+                "window_seconds": integer(),
+                "providers": array_of(json!({
+                    "type": "object",
+                    "properties": {
+                        "provider": string(),
+                        "status": { "type": "string", "enum": ["operational", "degraded", "down"] },
+                        "active_keys": integer(),
+                        "cooling_keys": integer(),
+                        "blocked_keys": integer(),
+                        "breaker": { "type": "string", "enum": ["closed", "open", "half_open"] },
+                        "requests": integer(),
+                        "error_rate": { "type": ["number", "null"] },
+                        "avg_latency_ms": { "type": ["number", "null"] },
+                    },
+                })),
+            },
+        })),
+        ("BuildInfo", json!({
+            "type": "object",
+            "properties": {
+                "version": string(),
+                "git_sha": string(),
+                "build_timestamp": integer(),
+                "features": array_of(string()),
+                "storage_strategy": string(),
+                "background_tasks": string(),
+            },
+        })),
+    ]
+    .into_iter()
+    .map(|(name, schema)| (name.to_string(), schema))
+    .collect()
+}
+
+// endregion: --- Schemas
+
+/// Builds the OpenAPI document.
+pub fn spec() -> Value {
+    let paths = status_paths(admin_paths(compat_paths(Paths::default())));
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "one-balance",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "AI gateway that balances requests over pools of provider API keys.",
+        },
+        "tags": [
+            { "name": "compat", "description": "OpenAI-compatible routes; models are given as `provider/model`." },
+            { "name": "proxy", "description": "Provider-native routes." },
+            { "name": "admin", "description": "The JSON admin API. Needs the master key or a client key with a role." },
+            { "name": "status", "description": "Health, build and metrics endpoints." },
+        ],
+        "paths": paths.0,
+        "components": {
+            "schemas": schemas(),
+            "securitySchemes": {
+                "bearer": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "`AUTH_KEY` or a client key.",
+                },
+            },
+        },
+        "security": [{ "bearer": [] }],
+    })
+}
+
+pub async fn openapi_handler() -> Response {
+    (
+        [(header::CACHE_CONTROL, "public, max-age=300")],
+        Json(spec()),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_templates_match_declared_path_params() {
+        let spec = spec();
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            let mut in_template: Vec<&str> = path
+                .split('/')
+                .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+                .collect();
+            in_template.sort_unstable();
+            for (method, operation) in item.as_object().unwrap() {
+                let mut declared: Vec<&str> = operation["parameters"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter(|param| param["in"] == "path")
+                    .map(|param| param["name"].as_str().unwrap())
+                    .collect();
+                declared.sort_unstable();
+                assert_eq!(in_template, declared, "{} {}", method, path);
+            }
+        }
+    }
+
+    #[test]
+    fn schema_refs_resolve() {
+        let spec = spec();
+        let text = spec.to_string();
+        let schemas = spec["components"]["schemas"].as_object().unwrap();
+        for reference in text.split("\"#/components/schemas/").skip(1) {
+            let name = &reference[..reference.find('"').unwrap()];
+            assert!(schemas.contains_key(name), "unresolved $ref {}", name);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! This module contains the opt-in payload logging used to debug requests, e.g. a
+//! translation a provider rejects. With `PAYLOAD_LOGGING` set to `failures` or `all`, the
+//! prompt and response of matching requests are stored with their request event, masked
+//! and truncated. Nothing is stored by default.
+//!
+//! Credentials and email addresses are always masked (see `sampling::redact`); operators
+//! can mask more with `PAYLOAD_REDACT_PATTERNS`, a `;`-separated list of regexes (ASCII
+//! classes only, e.g. `[0-9]{3}-[0-9]{2}-[0-9]{4}`). Patterns that don't compile are skipped.
+
+use crate::sampling;
+use crate::settings::{PayloadLogging, Settings};
+use mini_moka::sync::Cache;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Stored payloads are truncated to this many bytes each.
+const MAX_PAYLOAD_BYTES: usize = 4 * 1024;
+
+/// Compiled patterns by their setting value, since settings are re-read on every request.
+static PATTERNS: Lazy<Cache<String, Arc<Vec<Regex>>>> = Lazy::new(|| Cache::builder().max_capacity(8).build());
+
+/// A logged prompt and response, ready to be stored.
+#[derive(Debug, Clone, Default)]
+pub struct Payload {
+    pub request_body: String,
+    pub response_body: String,
+}
+
+fn split_patterns(value: &str) -> impl Iterator<Item = &str> {
+    value.split(';').map(str::trim).filter(|p| !p.is_empty())
+}
+
+/// Checks that every pattern in a `PAYLOAD_REDACT_PATTERNS` value compiles.
+pub fn check_patterns(value: &str) -> Result<(), String> {
+    for pattern in split_patterns(value) {
+        Regex::new(pattern).map_err(|e| format!("'{}' is not a valid pattern: {}", pattern, e))?;
+    }
+    Ok(())
+}
+
+fn patterns(value: &str) -> Arc<Vec<Regex>> {
+    if let Some(compiled) = PATTERNS.get(&value.to_string()) {
+        return compiled;
+    }
+    let compiled: Vec<Regex> = split_patterns(value)
+        .filter_map(|pattern| match Regex::new(pattern) {
+            Ok(regex) => Some(regex),
+            Err(e) => {
+                warn!(pattern, "Skipping invalid payload redaction pattern: {}", e);
+                None
+            }
+        })
+        .collect();
+    let compiled = Arc::new(compiled);
+    PATTERNS.insert(value.to_string(), compiled.clone());
+    compiled
+}
+
+/// Masks and truncates one body.
+fn redact(settings: &Settings, body: &str) -> String {
+    // Redact a little more than is kept, so a match isn't cut in half by the truncation.
+    let mut end = body.len().min(MAX_PAYLOAD_BYTES * 2);
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut text = body[..end].to_string();
+    for regex in patterns(&settings.payload_redact_patterns).iter() {
+        text = regex.replace_all(&text, "[redacted]").into_owned();
+    }
+    let mut text = sampling::redact(&text);
+    if text.len() > MAX_PAYLOAD_BYTES {
+        let mut end = MAX_PAYLOAD_BYTES;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+    text
+}
+
+/// The payload to store with a request event, if `PAYLOAD_LOGGING` covers the request.
+pub fn capture(settings: &Settings, failed: bool, request_body: &[u8], response_body: &str) -> Option<Payload> {
+    let logged = match settings.payload_logging {
+        PayloadLogging::Off => false,
+        PayloadLogging::Failures => failed,
+        PayloadLogging::All => true,
+    };
+    logged.then(|| Payload {
+        request_body: redact(
+            settings,
+            &String::from_utf8_lossy(&request_body[..request_body.len().min(MAX_PAYLOAD_BYTES * 2)]),
+        ),
+        response_body: redact(settings, response_body),
+    })
+}
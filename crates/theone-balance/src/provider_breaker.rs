@@ -0,0 +1,265 @@
+//! This module is the provider-level circuit breaker. When a provider fails as a whole,
+//! trying one key after the other only burns through the pool and puts healthy keys on
+//! cooldown, so once the provider errors of the last minute reach
+//! `PROVIDER_BREAKER_ERROR_RATE_PERCENT` the breaker opens and requests are answered with a
+//! 503 right away. After `PROVIDER_BREAKER_OPEN_SECONDS` it turns half-open: one request at
+//! a time is let through with a single key as a probe; a success closes the breaker, a
+//! provider error opens it again.
+//!
+//! Errors are counted like in `provider_health`, so invalid or rate-limited keys don't trip
+//! the breaker.
+
+use crate::settings::Settings;
+use crate::sliding_window::SlidingWindow;
+use mini_moka::sync::Cache;
+use once_cell::sync::Lazy;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use worker::Date;
+/// Attempts the window needs before the breaker can open.
+const MIN_WINDOW_SAMPLES: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BreakerState {
+    Closed,
+    /// Requests are rejected until `until_ms`.
+    Open { until_ms: u64 },
+    /// Another probe is let through when none started within the last upstream timeout.
+    HalfOpen { probe_started_ms: u64 },
+}
+
+struct Breaker {
+    /// Whether each recent attempt was a provider error.
+    window: SlidingWindow<bool>,
+    state: BreakerState,
+}
+
+impl Default for Breaker {
+    fn default() -> Self {
+        Breaker {
+            window: SlidingWindow::default(),
+            state: BreakerState::Closed,
+        }
+    }
+}
+
+/// Whether a request for a provider may go ahead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Allowed,
+    /// The breaker is half-open and this request probes the provider with one key.
+    Probe,
+    Rejected { retry_after_seconds: u64 },
+}
+
+/// A change of a breaker's state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transition {
+    /// The provider failed too often, or its probe failed.
+    Opened { error_rate: f64 },
+    /// A probe succeeded.
+    Closed,
+}
+
+static BREAKERS: Lazy<Cache<String, Arc<Mutex<Breaker>>>> = Lazy::new(|| {
+    Cache::builder()
+        .max_capacity(1_000)
+        .time_to_idle(Duration::from_secs(3600))
+        .build()
+});
+
+fn enabled(settings: &Settings) -> bool {
+    settings.provider_breaker_error_rate_percent > 0
+}
+
+impl Breaker {
+    fn admit(&mut self, settings: &Settings, now_ms: u64) -> Admission {
+        match self.state {
+            BreakerState::Closed => Admission::Allowed,
+            BreakerState::Open { until_ms } if now_ms < until_ms => Admission::Rejected {
+                retry_after_seconds: (until_ms - now_ms).div_ceil(1000),
+            },
+            // The probe may have been cancelled without an outcome, so another one goes
+            // out once the last can no longer be waiting for its answer.
+            BreakerState::HalfOpen { probe_started_ms } if now_ms.saturating_sub(probe_started_ms) < settings.target_timeout_ms => {
+                Admission::Rejected { retry_after_seconds: 1 }
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => {
+                self.state = BreakerState::HalfOpen { probe_started_ms: now_ms };
+                Admission::Probe
+            }
+        }
+    }
+
+    fn record(&mut self, settings: &Settings, error: bool, now_ms: u64) -> Option<Transition> {
+        let open_until_ms = now_ms + settings.provider_breaker_open_seconds * 1000;
+        match self.state {
+            BreakerState::Closed => {
+                self.window.push(now_ms, error);
+                if self.window.len() < MIN_WINDOW_SAMPLES {
+                    return None;
+                }
+                let errors = self.window.iter().filter(|error| **error).count();
+                let error_rate = errors as f64 / self.window.len() as f64;
+                if error_rate * 100.0 < settings.provider_breaker_error_rate_percent as f64 {
+                    return None;
+                }
+                self.window.clear();
+                self.state = BreakerState::Open { until_ms: open_until_ms };
+                Some(Transition::Opened { error_rate })
+            }
+            // Late outcomes of requests admitted before the breaker opened.
+            BreakerState::Open { .. } => None,
+            BreakerState::HalfOpen { .. } if error => {
+                self.state = BreakerState::Open { until_ms: open_until_ms };
+                Some(Transition::Opened { error_rate: 1.0 })
+            }
+            BreakerState::HalfOpen { .. } => {
+                self.state = BreakerState::Closed;
+                Some(Transition::Closed)
+            }
+        }
+    }
+
+    fn state_name(&self, now_ms: u64) -> &'static str {
+        match self.state {
+            BreakerState::Closed => "closed",
+            BreakerState::Open { until_ms } if now_ms < until_ms => "open",
+            // An open breaker whose time is up lets the next request through as a probe.
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => "half_open",
+        }
+    }
+}
+
+/// Decides whether a request for `provider` goes ahead, turning an open breaker half-open
+/// once its time is up.
+pub fn admit(settings: &Settings, provider: &str) -> Admission {
+    if !enabled(settings) {
+        return Admission::Allowed;
+    }
+    let Some(breaker) = BREAKERS.get(&provider.to_string()) else {
+        return Admission::Allowed;
+    };
+    let mut breaker = breaker.lock().unwrap_or_else(|e| e.into_inner());
+    breaker.admit(settings, Date::now().as_millis())
+}
+
+/// Records an attempt against `provider`. Returns the transition when the breaker opened
+/// or closed with this attempt.
+pub fn record(settings: &Settings, provider: &str, error: bool) -> Option<Transition> {
+    if !enabled(settings) {
+        return None;
+    }
+    let breaker = BREAKERS.get(&provider.to_string()).unwrap_or_else(|| {
+        let breaker = Arc::new(Mutex::new(Breaker::default()));
+        BREAKERS.insert(provider.to_string(), breaker.clone());
+        breaker
+    });
+    let mut breaker = breaker.lock().unwrap_or_else(|e| e.into_inner());
+    breaker.record(settings, error, Date::now().as_millis())
+}
+
+/// The breaker's state for `provider` in this isolate: `closed`, `open` or `half_open`.
+pub fn state(provider: &str) -> &'static str {
+    let Some(breaker) = BREAKERS.get(&provider.to_string()) else {
+        return "closed";
+    };
+    let breaker = breaker.lock().unwrap_or_else(|e| e.into_inner());
+    breaker.state_name(Date::now().as_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sliding_window::WINDOW_MS;
+
+    const OPEN_MS: u64 = 30_000;
+
+    fn settings() -> Settings {
+        Settings {
+            provider_breaker_error_rate_percent: 50,
+            provider_breaker_open_seconds: OPEN_MS / 1000,
+            target_timeout_ms: 10_000,
+            ..Settings::default()
+        }
+    }
+
+    /// A breaker opened at `t = 0` by a window of provider errors.
+    fn opened() -> Breaker {
+        let settings = settings();
+        let mut breaker = Breaker::default();
+        for _ in 1..MIN_WINDOW_SAMPLES {
+            assert_eq!(breaker.record(&settings, true, 0), None);
+        }
+        assert_eq!(breaker.record(&settings, true, 0), Some(Transition::Opened { error_rate: 1.0 }));
+        breaker
+    }
+
+    #[test]
+    fn stays_closed_below_the_error_rate_or_sample_count() {
+        let settings = settings();
+        let mut breaker = Breaker::default();
+        for i in 0..20 {
+            assert_eq!(breaker.record(&settings, i % 3 == 0, 0), None);
+        }
+        let mut few = Breaker::default();
+        for _ in 1..MIN_WINDOW_SAMPLES {
+            assert_eq!(few.record(&settings, true, 0), None);
+        }
+        assert_eq!(few.admit(&settings, 0), Admission::Allowed);
+    }
+
+    #[test]
+    fn errors_outside_the_window_are_forgotten() {
+        let settings = settings();
+        let mut breaker = Breaker::default();
+        for _ in 1..MIN_WINDOW_SAMPLES {
+            breaker.record(&settings, true, 0);
+        }
+        assert_eq!(breaker.record(&settings, true, WINDOW_MS), None);
+        assert_eq!(breaker.state_name(WINDOW_MS), "closed");
+    }
+
+    #[test]
+    fn open_rejects_until_its_time_is_up() {
+        let settings = settings();
+        let mut breaker = opened();
+        assert_eq!(breaker.state_name(0), "open");
+        assert_eq!(breaker.admit(&settings, 0), Admission::Rejected { retry_after_seconds: 30 });
+        assert_eq!(breaker.admit(&settings, OPEN_MS - 1500), Admission::Rejected { retry_after_seconds: 2 });
+        // Late outcomes don't change an open breaker.
+        assert_eq!(breaker.record(&settings, false, 1), None);
+        assert_eq!(breaker.state_name(OPEN_MS), "half_open");
+    }
+
+    #[test]
+    fn half_open_lets_one_probe_through_at_a_time() {
+        let settings = settings();
+        let mut breaker = opened();
+        assert_eq!(breaker.admit(&settings, OPEN_MS), Admission::Probe);
+        assert_eq!(breaker.admit(&settings, OPEN_MS + 1), Admission::Rejected { retry_after_seconds: 1 });
+        // A probe that never reported is replaced after the upstream timeout.
+        assert_eq!(breaker.admit(&settings, OPEN_MS + settings.target_timeout_ms), Admission::Probe);
+    }
+
+    #[test]
+    fn successful_probe_closes_the_breaker() {
+        let settings = settings();
+        let mut breaker = opened();
+        assert_eq!(breaker.admit(&settings, OPEN_MS), Admission::Probe);
+        assert_eq!(breaker.record(&settings, false, OPEN_MS + 1), Some(Transition::Closed));
+        assert_eq!(breaker.state_name(OPEN_MS + 1), "closed");
+        assert_eq!(breaker.admit(&settings, OPEN_MS + 2), Admission::Allowed);
+    }
+
+    #[test]
+    fn failed_probe_opens_the_breaker_again() {
+        let settings = settings();
+        let mut breaker = opened();
+        assert_eq!(breaker.admit(&settings, OPEN_MS), Admission::Probe);
+        assert_eq!(breaker.record(&settings, true, OPEN_MS + 1), Some(Transition::Opened { error_rate: 1.0 }));
+        assert_eq!(breaker.state_name(OPEN_MS + 1), "open");
+        assert_eq!(breaker.admit(&settings, 2 * OPEN_MS), Admission::Rejected { retry_after_seconds: 1 });
+        assert_eq!(breaker.admit(&settings, 2 * OPEN_MS + 1), Admission::Probe);
+    }
+}
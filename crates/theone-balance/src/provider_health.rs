@@ -0,0 +1,175 @@
+//! This module watches each provider's latency and error rate for anomalies, so a
+//! degrading provider is reported before its keys start failing hard. Every attempt feeds
+//! a slowly moving baseline and a one-minute window; when the window's latency reaches
+//! `ANOMALY_LATENCY_FACTOR` times the baseline, or its error rate reaches
+//! `ANOMALY_ERROR_RATE_PERCENT` (and well above the baseline), the provider is flagged as
+//! degraded until the window looks normal again. 0 disables either check.
+//!
+//! Only failures that point at the provider count as errors: server errors, timeouts and
+//! malformed or unrecognized answers. Invalid or rate-limited keys and client errors say
+//! nothing about the provider and are left out. Each isolate judges the traffic it sees.
+
+use crate::error_handling::ErrorAnalysis;
+use crate::settings::Settings;
+use crate::sliding_window::SlidingWindow;
+use mini_moka::sync::Cache;
+use once_cell::sync::Lazy;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use worker::Date;
+/// Weight of one attempt in the baseline.
+const BASELINE_ALPHA: f64 = 0.02;
+/// Attempts the baseline needs before anything is judged against it.
+const MIN_BASELINE_SAMPLES: u64 = 50;
+/// Attempts the window needs before it is judged.
+const MIN_WINDOW_SAMPLES: usize = 5;
+/// An error spike must also be this many times the baseline error rate.
+const ERROR_SPIKE_FACTOR: f64 = 3.0;
+
+#[derive(Clone, Copy, Debug)]
+struct Sample {
+    latency_ms: u64,
+    error: bool,
+}
+
+#[derive(Default)]
+struct Health {
+    baseline_latency_ms: f64,
+    baseline_error_rate: f64,
+    baseline_samples: u64,
+    window: SlidingWindow<Sample>,
+    degraded: bool,
+}
+
+/// Why a provider was flagged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anomaly {
+    Latency,
+    Errors,
+}
+
+impl Anomaly {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Anomaly::Latency => "latency",
+            Anomaly::Errors => "errors",
+        }
+    }
+}
+
+/// A change of a provider's health, with the numbers behind it.
+#[derive(Debug, Clone)]
+pub struct Transition {
+    /// The anomaly that flagged the provider; `None` when it recovered.
+    pub anomaly: Option<Anomaly>,
+    pub baseline_latency_ms: u64,
+    pub window_latency_ms: u64,
+    pub baseline_error_rate: f64,
+    pub window_error_rate: f64,
+}
+
+impl Transition {
+    pub fn reason(&self) -> String {
+        match self.anomaly {
+            Some(Anomaly::Latency) => format!(
+                "latency of {}ms over the last minute against a {}ms baseline",
+                self.window_latency_ms, self.baseline_latency_ms
+            ),
+            Some(Anomaly::Errors) => format!(
+                "error rate of {:.0}% over the last minute against a {:.1}% baseline",
+                self.window_error_rate * 100.0,
+                self.baseline_error_rate * 100.0
+            ),
+            None => "latency and error rate are back to their baseline".to_string(),
+        }
+    }
+}
+
+static HEALTH: Lazy<Cache<String, Arc<Mutex<Health>>>> = Lazy::new(|| {
+    Cache::builder()
+        .max_capacity(1_000)
+        .time_to_idle(Duration::from_secs(3600))
+        .build()
+});
+
+/// Whether an attempt's outcome is a provider error. `None` for outcomes that say nothing
+/// about the provider.
+pub fn is_provider_error(analysis: &ErrorAnalysis) -> Option<bool> {
+    match analysis {
+        ErrorAnalysis::TransientServerError
+        | ErrorAnalysis::RequestTimeout
+        | ErrorAnalysis::MalformedResponse
+        | ErrorAnalysis::Unknown => Some(true),
+        ErrorAnalysis::KeyIsInvalid | ErrorAnalysis::KeyOnCooldown { .. } | ErrorAnalysis::UserError => None,
+    }
+}
+
+/// Records an attempt against `provider`. Returns the transition when the provider became
+/// degraded or recovered with this attempt.
+pub fn record(settings: &Settings, provider: &str, latency_ms: u64, error: bool) -> Option<Transition> {
+    if settings.anomaly_latency_factor == 0 && settings.anomaly_error_rate_percent == 0 {
+        return None;
+    }
+    let health = HEALTH.get(&provider.to_string()).unwrap_or_else(|| {
+        let health = Arc::new(Mutex::new(Health::default()));
+        HEALTH.insert(provider.to_string(), health.clone());
+        health
+    });
+    let mut health = health.lock().unwrap_or_else(|e| e.into_inner());
+
+    let now_ms = Date::now().as_millis();
+    health.window.push(now_ms, Sample { latency_ms, error });
+
+    let transition = judge(settings, &mut health);
+
+    // A degraded window would drag the baseline along with it.
+    if !health.degraded {
+        let alpha = if health.baseline_samples == 0 { 1.0 } else { BASELINE_ALPHA };
+        if !error {
+            health.baseline_latency_ms += alpha * (latency_ms as f64 - health.baseline_latency_ms);
+        }
+        health.baseline_error_rate += alpha * ((error as u8) as f64 - health.baseline_error_rate);
+        health.baseline_samples += 1;
+    }
+    transition
+}
+
+fn judge(settings: &Settings, health: &mut Health) -> Option<Transition> {
+    if health.baseline_samples < MIN_BASELINE_SAMPLES || health.window.len() < MIN_WINDOW_SAMPLES {
+        return None;
+    }
+    let errors = health.window.iter().filter(|s| s.error).count();
+    let successes = health.window.len() - errors;
+    let window_error_rate = errors as f64 / health.window.len() as f64;
+    let window_latency_ms = if successes == 0 {
+        0
+    } else {
+        health.window.iter().filter(|s| !s.error).map(|s| s.latency_ms).sum::<u64>() / successes as u64
+    };
+
+    let latency_anomaly = settings.anomaly_latency_factor > 0
+        && successes > 0
+        && window_latency_ms as f64 >= settings.anomaly_latency_factor as f64 * health.baseline_latency_ms;
+    let error_anomaly = settings.anomaly_error_rate_percent > 0
+        && window_error_rate * 100.0 >= settings.anomaly_error_rate_percent as f64
+        && window_error_rate >= ERROR_SPIKE_FACTOR * health.baseline_error_rate;
+    let anomaly = if error_anomaly {
+        Some(Anomaly::Errors)
+    } else if latency_anomaly {
+        Some(Anomaly::Latency)
+    } else {
+        None
+    };
+
+    if anomaly.is_some() == health.degraded {
+        return None;
+    }
+    health.degraded = anomaly.is_some();
+    Some(Transition {
+        anomaly,
+        baseline_latency_ms: health.baseline_latency_ms.round() as u64,
+        window_latency_ms,
+        baseline_error_rate: health.baseline_error_rate,
+        window_error_rate,
+    })
+}
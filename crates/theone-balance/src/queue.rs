@@ -1,9 +1,33 @@
-use crate::state::strategy::ApiKeyStatus;
+//! This module contains the `STATE_UPDATER` queue: its consumer, which applies key state
+//! updates off the request path, and the producer the proxy sends them with.
+//!
+//! The consumer merges a batch before writing it, so a hot key gets one write per batch
+//! instead of one per request: metrics add up, the last status wins and the longest
+//! cooldown per model wins. A write that fails retries the messages it merged.
+//!
+//! A queue that is missing, failing or backed up must not fail or slow down the request
+//! that produced the update. A send that fails is applied directly in the background
+//! instead; one that takes longer than `SEND_TIMEOUT_MS` keeps going in the background,
+//! falling back the same way if it fails there. Updates that can't be applied either way
+//! are dropped. Each case is counted in the `/metrics` queue counters.
+
+use crate::metrics::{self, MetricDelta};
+use crate::state::strategy::{ApiKeyStatus, KeyMetricsDelta};
+use crate::AppState;
+use futures_util::future::{select, Either};
+use futures_util::FutureExt;
 use serde::{Deserialize, Serialize};
-use tracing::{error, info};
-use worker::{event, Env, MessageExt, Result};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use worker::{event, D1Database, Delay, Env, MessageExt, Result};
+
+pub const BINDING: &str = "STATE_UPDATER";
+/// How long a send may hold up the request before it is moved to the background.
+const SEND_TIMEOUT_MS: u64 = 200;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum StateUpdate {
     SetStatus {
         key_id: String,
@@ -14,6 +38,100 @@ pub enum StateUpdate {
         model: String,
         duration_secs: u64,
     },
+    /// The outcome of one attempt with a key.
+    UpdateMetrics {
+        key_id: String,
+        is_success: bool,
+        latency: i64,
+    },
+}
+
+/// One storage write, standing for one or more merged updates.
+#[derive(Debug, Clone)]
+enum Write {
+    Status {
+        key_id: String,
+        status: ApiKeyStatus,
+    },
+    Cooldown {
+        key_id: String,
+        model: String,
+        duration_secs: u64,
+    },
+    Metrics {
+        key_id: String,
+        delta: KeyMetricsDelta,
+    },
+}
+
+impl From<&StateUpdate> for Write {
+    fn from(update: &StateUpdate) -> Self {
+        match update.clone() {
+            StateUpdate::SetStatus { key_id, status } => Write::Status { key_id, status },
+            StateUpdate::SetCooldown {
+                key_id,
+                model,
+                duration_secs,
+            } => Write::Cooldown {
+                key_id,
+                model,
+                duration_secs,
+            },
+            StateUpdate::UpdateMetrics {
+                key_id,
+                is_success,
+                latency,
+            } => Write::Metrics {
+                key_id,
+                delta: KeyMetricsDelta::attempt(is_success, latency),
+            },
+        }
+    }
+}
+
+impl Write {
+    /// Writes with the same slot are merged.
+    fn slot(&self) -> (&'static str, &str, &str) {
+        match self {
+            Write::Status { key_id, .. } => ("status", key_id, ""),
+            Write::Cooldown { key_id, model, .. } => ("cooldown", key_id, model),
+            Write::Metrics { key_id, .. } => ("metrics", key_id, ""),
+        }
+    }
+
+    /// Merges `later`, a write with the same slot.
+    fn merge(&mut self, later: Write) {
+        match (self, later) {
+            (Write::Status { status, .. }, Write::Status { status: later, .. }) => *status = later,
+            (Write::Cooldown { duration_secs, .. }, Write::Cooldown { duration_secs: later, .. }) => {
+                *duration_secs = (*duration_secs).max(later)
+            }
+            (Write::Metrics { delta, .. }, Write::Metrics { delta: later, .. }) => delta.merge(later),
+            _ => {}
+        }
+    }
+}
+
+/// Merges a batch's updates into writes, each with the indices of the updates it covers.
+fn coalesce<'a>(updates: impl IntoIterator<Item = &'a StateUpdate>) -> Vec<(Write, Vec<usize>)> {
+    let mut writes: Vec<(Write, Vec<usize>)> = Vec::new();
+    let mut slots: HashMap<(&'static str, String, String), usize> = HashMap::new();
+    for (i, update) in updates.into_iter().enumerate() {
+        let write = Write::from(update);
+        let (kind, key_id, model) = write.slot();
+        match slots.entry((kind, key_id.to_string(), model.to_string())) {
+            Entry::Occupied(slot) => {
+                let (merged, sources) = &mut writes[*slot.get()];
+                merged.merge(write);
+                sources.push(i);
+            }
+            Entry::Vacant(slot) => {
+                slot.insert(writes.len());
+                writes.push((write, vec![i]));
+            }
+        }
+    }
+    writes
 }
 
 // Helper to get the Durable Object stub for the API Key Manager.
@@ -60,50 +178,272 @@ pub(crate) async fn set_key_cooldown(
     Ok(())
 }
 
+/// Applies one state update to storage, on the D1 session of the request it comes from.
+async fn apply(update: &StateUpdate, env: &Env, session: Option<&D1Database>) -> Result<()> {
+    apply_write(&Write::from(update), env, session).await
+}
+
+/// Applies one write, on `session` when given; the consumer serves no request and uses
+/// the plain binding.
+async fn apply_write(write: &Write, env: &Env, session: Option<&D1Database>) -> Result<()> {
+    #[cfg(feature = "raw_d1")]
+    let binding;
+    #[cfg(feature = "raw_d1")]
+    let db = match session {
+        Some(db) => db,
+        None => {
+            binding = env.d1("DB")?;
+            &binding
+        }
+    };
+    #[cfg(not(feature = "raw_d1"))]
+    let _ = session;
+
+    match write {
+        Write::Status { key_id, status } => {
+            #[cfg(feature = "raw_d1")]
+            {
+                crate::d1_storage::update_status(db, key_id, *status).await?;
+                Ok(())
+            }
+            #[cfg(not(feature = "raw_d1"))]
+            {
+                set_key_status(key_id, *status, env).await
+            }
+        }
+        Write::Cooldown {
+            key_id,
+            model,
+            duration_secs,
+        } => {
+            #[cfg(feature = "raw_d1")]
+            {
+                crate::d1_storage::set_cooldown(db, key_id, model, *duration_secs).await?;
+                Ok(())
+            }
+            #[cfg(not(feature = "raw_d1"))]
+            {
+                set_key_cooldown(key_id, model, *duration_secs, env).await
+            }
+        }
+        Write::Metrics { key_id, delta } => {
+            #[cfg(feature = "raw_d1")]
+            {
+                crate::d1_storage::apply_key_metrics(db, key_id, delta).await?;
+                Ok(())
+            }
+            // The Durable Object backends don't track key metrics.
+            #[cfg(not(feature = "raw_d1"))]
+            {
+                let _ = (key_id, delta);
+                Ok(())
+            }
+        }
+    }
+}
+
 #[event(queue)]
 pub async fn main(
     batch: worker::MessageBatch<StateUpdate>,
     env: Env,
     _ctx: worker::Context,
 ) -> Result<()> {
-    #[cfg(feature = "raw_d1")]
-    let db = env.d1("DB")?;
-
-    for message in batch.messages()? {
-        info!("Processing state update: {:?}", message.body());
-        let res = match message.body() {
-            StateUpdate::SetStatus { key_id, status } => {
-                #[cfg(feature = "raw_d1")]
-                {
-                    crate::d1_storage::update_status(&db, &key_id, status.clone()).await
-                }
-                #[cfg(not(feature = "raw_d1"))]
-                {
-                    set_key_status(&key_id, status.clone(), &env).await
-                }
+    let messages = batch.messages()?;
+    let writes = coalesce(messages.iter().map(|message| message.body()));
+    info!("Applying {} state updates as {} writes.", messages.len(), writes.len());
+    for (write, sources) in writes {
+        if let Err(e) = apply_write(&write, &env, None).await {
+            error!("Failed to apply state update {:?}: {}", write, e);
+            sources.iter().for_each(|&i| messages[i].retry());
+        } else {
+            sources.iter().for_each(|&i| messages[i].ack());
+        }
+    }
+    Ok(())
+}
+
+// region: --- Producer
+
+/// Sends a state update to the queue without failing the request; see the module docs for
+/// what happens when the queue is missing, failing or backed up.
+pub async fn send(state: &AppState, update: StateUpdate) {
+    let queue = match state.env.queue(BINDING) {
+        Ok(queue) => queue,
+        Err(e) => {
+            warn!("Queue {} is not available: {}. Applying the update directly.", BINDING, e);
+            return fall_back(state, update, "unbound");
+        }
+    };
+
+    let attempt = async move {
+        let result = queue.send(&update).await;
+        (result, update)
+    }
+    .boxed_local();
+    let timeout = Delay::from(Duration::from_millis(SEND_TIMEOUT_MS));
+    match select(attempt, timeout.boxed_local()).await {
+        Either::Left(((Ok(()), _), _)) => {}
+        Either::Left(((Err(e), update), _)) => {
+            warn!("Failed to send the state update to the queue: {}. Applying it directly.", e);
+            fall_back(state, update, "error");
+        }
+        Either::Right((_, attempt)) => {
+            warn!("Queue send took over {}ms. Finishing it in the background.", SEND_TIMEOUT_MS);
+            record(state, vec![metrics::queue_send_failure("timeout")]);
+            #[cfg(feature = "wait_until")]
+            {
+                let env = state.env.clone();
+                let db = state.db().ok();
+                state.ctx.wait_until(async move {
+                    if let (Err(e), update) = attempt.await {
+                        warn!("Background queue send failed: {}. Applying the update directly.", e);
+                        let mut deltas = vec![metrics::queue_send_failure("error")];
+                        if let Err(e) = apply(&update, &env, db.as_ref()).await {
+                            error!("Failed to apply state update {:?} directly: {}", update, e);
+                            deltas.push(metrics::queue_update_dropped());
+                        }
+                        increment(db.as_ref(), deltas).await;
+                    }
+                });
             }
-            StateUpdate::SetCooldown {
-                key_id,
-                model,
-                duration_secs,
-            } => {
-                #[cfg(feature = "raw_d1")]
-                {
-                    crate::d1_storage::set_cooldown(&db, &key_id, &model, *duration_secs).await
-                }
-                #[cfg(not(feature = "raw_d1"))]
-                {
-                    set_key_cooldown(&key_id, &model, *duration_secs, &env).await
-                }
+            #[cfg(not(feature = "wait_until"))]
+            drop(attempt);
+        }
+    }
+}
+
+/// Applies an update the queue didn't take in the background, or drops it when nothing
+/// can run after the response.
+fn fall_back(state: &AppState, update: StateUpdate, reason: &'static str) {
+    #[cfg(feature = "wait_until")]
+    {
+        let env = state.env.clone();
+        let db = state.db().ok();
+        state.ctx.wait_until(async move {
+            let mut deltas = vec![metrics::queue_send_failure(reason)];
+            if let Err(e) = apply(&update, &env, db.as_ref()).await {
+                error!("Failed to apply state update {:?} directly: {}", update, e);
+                deltas.push(metrics::queue_update_dropped());
             }
-        };
+            increment(db.as_ref(), deltas).await;
+        });
+    }
+    #[cfg(not(feature = "wait_until"))]
+    {
+        warn!("Dropping state update {:?}.", update);
+        record(state, vec![metrics::queue_send_failure(reason), metrics::queue_update_dropped()]);
+    }
+}
 
-        if let Err(e) = res {
-            error!("Failed to process state update {:?}: {}", message.body(), e);
-            message.retry();
-        } else {
-            message.ack();
+/// Counts queue failures in the background.
+fn record(state: &AppState, deltas: Vec<MetricDelta>) {
+    #[cfg(feature = "wait_until")]
+    {
+        let db = state.db().ok();
+        state.ctx.wait_until(async move { increment(db.as_ref(), deltas).await });
+    }
+    #[cfg(not(feature = "wait_until"))]
+    let _ = (state, deltas);
+}
+
+async fn increment(db: Option<&D1Database>, deltas: Vec<MetricDelta>) {
+    if let Some(db) = db {
+        if let Err(e) = crate::d1_storage::increment_metrics(db, &deltas).await {
+            error!("Failed to record queue metrics: {}", e);
         }
     }
-    Ok(())
+}
+
+// endregion: --- Producer
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(key_id: &str, status: ApiKeyStatus) -> StateUpdate {
+        StateUpdate::SetStatus {
+            key_id: key_id.to_string(),
+            status,
+        }
+    }
+
+    fn attempt(key_id: &str, is_success: bool, latency: i64) -> StateUpdate {
+        StateUpdate::UpdateMetrics {
+            key_id: key_id.to_string(),
+            is_success,
+            latency,
+        }
+    }
+
+    fn cooldown(key_id: &str, model: &str, duration_secs: u64) -> StateUpdate {
+        StateUpdate::SetCooldown {
+            key_id: key_id.to_string(),
+            model: model.to_string(),
+            duration_secs,
+        }
+    }
+
+    #[test]
+    fn last_status_wins() {
+        let updates = [
+            status("k1", ApiKeyStatus::Active),
+            status("k1", ApiKeyStatus::Blocked),
+            status("k2", ApiKeyStatus::Blocked),
+            status("k1", ApiKeyStatus::Active),
+        ];
+        let writes = coalesce(&updates);
+        assert_eq!(writes.len(), 2);
+        let (Write::Status { key_id, status }, sources) = &writes[0] else {
+            panic!("expected a status write, got {:?}", writes[0]);
+        };
+        assert_eq!((key_id.as_str(), *status), ("k1", ApiKeyStatus::Active));
+        assert_eq!(sources, &[0, 1, 3]);
+        assert_eq!(writes[1].1, [2]);
+    }
+
+    #[test]
+    fn metrics_add_up_per_key() {
+        let updates = [
+            attempt("k1", false, 100),
+            attempt("k1", true, 200),
+            attempt("k2", false, 50),
+            attempt("k1", false, 300),
+        ];
+        let writes = coalesce(&updates);
+        assert_eq!(writes.len(), 2);
+        let (Write::Metrics { key_id, delta }, sources) = &writes[0] else {
+            panic!("expected a metrics write, got {:?}", writes[0]);
+        };
+        assert_eq!(key_id, "k1");
+        assert_eq!(sources, &[0, 1, 3]);
+        assert_eq!((delta.successes, delta.failures, delta.trailing_failures), (1, 2, 1));
+        assert_eq!(delta.latency_ms, Some(300));
+        assert_eq!(delta.latency_total_ms, 600);
+        assert_eq!(delta.latency_histogram.total(), 3);
+    }
+
+    #[test]
+    fn longest_cooldown_per_model_wins() {
+        let updates = [
+            cooldown("k1", "m1", 60),
+            cooldown("k1", "m1", 300),
+            cooldown("k1", "m2", 10),
+            cooldown("k1", "m1", 120),
+        ];
+        let writes = coalesce(&updates);
+        let durations: Vec<(&str, u64)> = writes
+            .iter()
+            .map(|(write, _)| match write {
+                Write::Cooldown { model, duration_secs, .. } => (model.as_str(), *duration_secs),
+                other => panic!("expected a cooldown write, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(durations, [("m1", 300), ("m2", 10)]);
+    }
+
+    #[test]
+    fn different_kinds_for_one_key_stay_apart() {
+        let updates = [status("k1", ApiKeyStatus::Blocked), attempt("k1", false, 10), cooldown("k1", "m1", 60)];
+        assert_eq!(coalesce(&updates).len(), 3);
+    }
 }
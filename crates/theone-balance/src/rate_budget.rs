@@ -0,0 +1,60 @@
+//! This module enforces per-key rate budgets, so the balancer moves traffic off a key
+//! before the provider answers 429. Budgets are set per provider as requests per minute
+//! (`rpm_limit`) and tokens per minute (`tpm_limit`) in the provider settings; 0 means
+//! unlimited.
+//!
+//! Usage is tracked over a sliding one-minute window (see `sliding_window`), so each
+//! isolate enforces the budget on the traffic it sees. Tokens are counted as the prompt
+//! estimate when a request is sent, and the completion once the response's usage is known.
+
+use crate::sliding_window::WindowCache;
+use crate::state::strategy::ProviderSettings;
+use once_cell::sync::Lazy;
+use worker::Date;
+
+/// Requests and tokens a key was charged at a point in time.
+#[derive(Clone, Copy, Debug)]
+struct Charge {
+    requests: u64,
+    tokens: u64,
+}
+
+static WINDOWS: Lazy<WindowCache<Charge>> = Lazy::new(|| WindowCache::new(10_000));
+
+/// A key's requests and tokens over the last minute.
+fn usage(key_id: &str, now_ms: u64) -> (u64, u64) {
+    WINDOWS
+        .read(key_id, |charges| {
+            charges.expire(now_ms);
+            charges
+                .iter()
+                .fold((0, 0), |(requests, tokens), c| (requests + c.requests, tokens + c.tokens))
+        })
+        .unwrap_or((0, 0))
+}
+
+fn charge(key_id: &str, requests: u64, tokens: u64) {
+    let now_ms = Date::now().as_millis();
+    WINDOWS.update(key_id, |charges| charges.push(now_ms, Charge { requests, tokens }));
+}
+
+/// Whether the key can take another request within its provider's budgets.
+pub fn has_budget(key_id: &str, limits: &ProviderSettings) -> bool {
+    if limits.rpm_limit == 0 && limits.tpm_limit == 0 {
+        return true;
+    }
+    let (requests, tokens) = usage(key_id, Date::now().as_millis());
+    (limits.rpm_limit == 0 || requests < limits.rpm_limit) && (limits.tpm_limit == 0 || tokens < limits.tpm_limit)
+}
+
+/// Charges a request sent with the key, with the estimated tokens of its prompt.
+pub fn record_request(key_id: &str, prompt_tokens: u64) {
+    charge(key_id, 1, prompt_tokens);
+}
+
+/// Charges the completion tokens of a request once its usage is known.
+pub fn record_tokens(key_id: &str, tokens: u64) {
+    if tokens > 0 {
+        charge(key_id, 0, tokens);
+    }
+}
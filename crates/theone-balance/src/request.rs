@@ -1,62 +1,100 @@
 //! This module contains shared logic for making HTTP requests.
 
-use crate::gcp::{GeminiChatRequest, GeminiContent, GeminiPart};
-use phf::phf_map;
-use worker::{Fetch, Headers, Method, Request, RequestInit, Response};
-
-pub static PROVIDER_CUSTOM_AUTH_HEADER: phf::Map<&'static str, &'static str> = phf_map! {
-    "google-ai-studio" => "x-goog-api-key",
-    "anthropic" => "x-api-key",
-    "elevenlabs" => "x-api-key",
-    "azure-openai" => "api-key",
-    "cartesia" => "X-API-Key",
-};
+use crate::compat::ModelListEndpoint;
+use crate::gcp::GeminiChatRequest;
+use crate::key_probe::{self, ProbeRequest};
+use worker::{AbortSignal, Fetch, Headers, Method, Request, RequestInit, Response};
+
+/// Sends the request, cancelling it upstream when `signal` fires.
+async fn send(req: Request, signal: Option<&AbortSignal>) -> Result<Response, worker::Error> {
+    match signal {
+        Some(signal) => Fetch::Request(req).send_with_signal(signal).await,
+        None => Fetch::Request(req).send().await,
+    }
+}
+
+/// Sends a request built by `key_probe`.
+async fn send_probe(probe: ProbeRequest, signal: Option<&AbortSignal>) -> Result<Response, worker::Error> {
+    let headers = Headers::new();
+    for (name, value) in &probe.headers {
+        headers.set(name, value)?;
+    }
+    let mut req_init = RequestInit::new();
+    req_init
+        .with_method(if probe.method == "POST" { Method::Post } else { Method::Get })
+        .with_headers(headers)
+        .with_body(probe.body.map(|b| b.into()));
+    let req = Request::new_with_init(&probe.url, &req_init)?;
+    send(req, signal).await
+}
 
 pub async fn send_native_chat_test_request(
     provider: &str,
     key: &str,
     model: &str,
+    signal: Option<&AbortSignal>,
 ) -> Result<Response, worker::Error> {
-    let mut headers = Headers::new();
-    headers.set("Content-Type", "application/json")?;
+    let probe = key_probe::chat_test_request(provider, key, model)?;
+    send_probe(probe, signal).await
+}
 
-    let (url, body) = match provider {
-        "google-ai-studio" => {
-            let auth_header_name = PROVIDER_CUSTOM_AUTH_HEADER
-                .get(provider)
-                .unwrap_or(&"x-goog-api-key");
-            headers.set(auth_header_name, key)?;
-
-            let native_request = GeminiChatRequest {
-                contents: vec![GeminiContent {
-                    role: Some("user".to_string()),
-                    parts: vec![GeminiPart {
-                        text: "hello".to_string(),
-                    }],
-                }],
-            };
-
-            let body_bytes = serde_json::to_vec(&native_request)?;
-            
-            let url = format!("https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent", model);
-
-            (
-                url,
-                Some(body_bytes),
-            )
-        }
-        _ => {
-            // For now, only google is supported for testing. Return an error for others.
-            return Err(format!("Provider '{}' not supported for testing.", provider).into());
-        }
+/// Fetches a provider's model list with the given key and returns the bare model names.
+pub async fn fetch_provider_models(
+    provider: &str,
+    key: &str,
+    endpoint: &ModelListEndpoint,
+    signal: Option<&AbortSignal>,
+) -> Result<Vec<String>, worker::Error> {
+    let probe = ProbeRequest {
+        method: "GET",
+        url: endpoint.url.to_string(),
+        headers: key_probe::auth_headers(provider, key),
+        body: None,
     };
+    let mut resp = send_probe(probe, signal).await?;
+    if resp.status_code() != 200 {
+        return Err(format!(
+            "Listing models for '{}' failed with status {}",
+            provider,
+            resp.status_code()
+        )
+        .into());
+    }
+
+    let body: serde_json::Value = resp.json().await?;
+    Ok(key_probe::parse_model_list(endpoint, &body))
+}
+
+/// Counts the prompt tokens of a Gemini request with the provider's `countTokens`.
+pub async fn fetch_gemini_token_count(
+    key: &str,
+    model: &str,
+    request: &GeminiChatRequest,
+    signal: Option<&AbortSignal>,
+) -> Result<u64, worker::Error> {
+    let headers = Headers::new();
+    headers.set("Content-Type", "application/json")?;
+    headers.set("x-goog-api-key", key)?;
+
+    // Counting the full request (not just `contents`) includes the system instruction and tools.
+    let mut generate_request = serde_json::to_value(request)?;
+    generate_request["model"] = serde_json::Value::String(format!("models/{}", model));
+    let body = serde_json::json!({ "generateContentRequest": generate_request });
 
     let mut req_init = RequestInit::new();
     req_init
         .with_method(Method::Post)
         .with_headers(headers)
-        .with_body(body.map(|b| b.into()));
-
+        .with_body(Some(serde_json::to_string(&body)?.into()));
+    let url = format!("https://generativelanguage.googleapis.com/v1beta/models/{}:countTokens", model);
     let req = Request::new_with_init(&url, &req_init)?;
-    Fetch::Request(req).send().await
+    let mut resp = send(req, signal).await?;
+    if resp.status_code() != 200 {
+        return Err(format!("Counting tokens for '{}' failed with status {}", model, resp.status_code()).into());
+    }
+
+    let body: serde_json::Value = resp.json().await?;
+    body.get("totalTokens")
+        .and_then(|count| count.as_u64())
+        .ok_or_else(|| "countTokens returned no totalTokens".into())
 }
@@ -0,0 +1,103 @@
+//! This module gives every gateway request an id that ties the client's view to the logs.
+//! A valid `X-Request-ID` from the client is kept, otherwise a UUID is generated. The id
+//! is recorded on the root tracing span, sent upstream, returned in the `X-Request-ID`
+//! response header and added as `request_id` to JSON error bodies. A provider's own
+//! `x-request-id` is passed on as `x-upstream-request-id`.
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::Value;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const UPSTREAM_REQUEST_ID_HEADER: &str = "x-upstream-request-id";
+
+/// Error bodies larger than this are passed through without the id.
+const MAX_ERROR_BODY_BYTES: usize = 256 * 1024;
+
+/// The id of the current request, available to handlers as a request extension.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// Client-supplied ids are kept only if they are short and plain, so they can't be used
+/// to inject anything into logs or headers.
+fn is_valid(id: &str) -> bool {
+    (1..=128).contains(&id.len())
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+/// Returns the request's id, generating one and setting the header when the client
+/// didn't send a valid one.
+pub fn ensure<B>(req: &mut axum::http::Request<B>) -> String {
+    let existing = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|id| is_valid(id))
+        .map(str::to_string);
+    let id = existing.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        req.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    id
+}
+
+/// Sets the `X-Request-ID` response header.
+pub fn set_header<B>(resp: &mut axum::http::Response<B>, id: &str) {
+    if let Ok(value) = HeaderValue::from_str(id) {
+        resp.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+}
+
+/// Adds `request_id` to a JSON error body with a top-level `error`, leaving other bodies as they are.
+async fn add_to_error_body(resp: Response, id: &str) -> Response {
+    let is_json = resp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("application/json"));
+    let too_large = resp
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse::<usize>().ok())
+        .is_some_and(|len| len > MAX_ERROR_BODY_BYTES);
+    if !is_json || too_large || !(resp.status().is_client_error() || resp.status().is_server_error()) {
+        return resp;
+    }
+
+    let (mut parts, body) = resp.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_ERROR_BODY_BYTES).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let body = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(Value::Object(mut object)) if object.contains_key("error") => {
+            object
+                .entry("request_id")
+                .or_insert_with(|| Value::String(id.to_string()));
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Body::from(Value::Object(object).to_string())
+        }
+        _ => Body::from(bytes),
+    };
+    Response::from_parts(parts, body)
+}
+
+/// Middleware assigning the request id and returning it in the response.
+pub async fn propagate(mut req: Request, next: Next) -> Response {
+    let id = ensure(&mut req);
+    req.extensions_mut().insert(RequestId(id.clone()));
+    let resp = next.run(req).await;
+    let mut resp = add_to_error_body(resp, &id).await;
+    if let Some(upstream) = resp.headers_mut().remove(REQUEST_ID_HEADER) {
+        resp.headers_mut().insert(UPSTREAM_REQUEST_ID_HEADER, upstream);
+    }
+    set_header(&mut resp, &id);
+    resp
+}
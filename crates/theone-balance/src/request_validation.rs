@@ -0,0 +1,149 @@
+//! This module checks the JSON bodies of the OpenAI-compatible routes before a key is
+//! spent on them, so a malformed request gets a structured 400 naming the offending field
+//! instead of an opaque provider error (or a translation failure) after failover.
+//!
+//! The checks cover the shape every provider needs, not each provider's full schema:
+//! fields the gateway doesn't know are passed through untouched.
+
+use serde_json::{Map, Value};
+
+const CHAT_ROLES: &[&str] = &["system", "developer", "user", "assistant", "tool", "function"];
+
+/// Why a request body was rejected, in the shape of an OpenAI `invalid_request_error`.
+#[derive(Debug)]
+pub struct RequestError {
+    pub message: String,
+    /// The offending field, e.g. `messages[2].role`.
+    pub param: Option<String>,
+}
+
+impl RequestError {
+    fn new(message: impl Into<String>, param: impl Into<String>) -> Self {
+        RequestError {
+            message: message.into(),
+            param: Some(param.into()),
+        }
+    }
+}
+
+/// Validates the JSON body of a compat route. `rest_resource` is the path after `/api/`;
+/// routes without checks (and non-compat routes) always pass.
+pub fn validate_compat(rest_resource: &str, body: &[u8]) -> Result<(), RequestError> {
+    type Check = fn(&Map<String, Value>) -> Result<(), RequestError>;
+    let check: Check = if rest_resource.starts_with("compat/chat/completions") {
+        check_chat
+    } else if rest_resource.starts_with("compat/embeddings") {
+        check_embeddings
+    } else if rest_resource.starts_with("compat/rerank") {
+        check_rerank
+    } else {
+        return Ok(());
+    };
+
+    let value: Value = serde_json::from_slice(body).map_err(|e| RequestError {
+        message: format!("The request body is not valid JSON: {}", e),
+        param: None,
+    })?;
+    let Value::Object(request) = value else {
+        return Err(RequestError {
+            message: "The request body must be a JSON object.".to_string(),
+            param: None,
+        });
+    };
+    check_model(&request)?;
+    check(&request)
+}
+
+fn check_model(request: &Map<String, Value>) -> Result<(), RequestError> {
+    match request.get("model").and_then(Value::as_str) {
+        Some(model) if model.split_once('/').is_some_and(|(p, m)| !p.is_empty() && !m.is_empty()) => Ok(()),
+        Some(_) => Err(RequestError::new("'model' must be given as provider/model.", "model")),
+        None => Err(RequestError::new("'model' is required and must be a string.", "model")),
+    }
+}
+
+fn check_number(request: &Map<String, Value>, field: &str, min: f64, max: f64) -> Result<(), RequestError> {
+    match request.get(field) {
+        None | Some(Value::Null) => Ok(()),
+        Some(value) if value.as_f64().is_some_and(|v| (min..=max).contains(&v)) => Ok(()),
+        Some(_) => Err(RequestError::new(
+            format!("'{}' must be a number between {} and {}.", field, min, max),
+            field,
+        )),
+    }
+}
+
+fn check_chat(request: &Map<String, Value>) -> Result<(), RequestError> {
+    let Some(messages) = request.get("messages").and_then(Value::as_array) else {
+        return Err(RequestError::new("'messages' is required and must be an array.", "messages"));
+    };
+    if messages.is_empty() {
+        return Err(RequestError::new("'messages' must contain at least one message.", "messages"));
+    }
+    for (i, message) in messages.iter().enumerate() {
+        let Some(message) = message.as_object() else {
+            return Err(RequestError::new("Each message must be an object.", format!("messages[{}]", i)));
+        };
+        let role = message.get("role").and_then(Value::as_str).unwrap_or_default();
+        if !CHAT_ROLES.contains(&role) {
+            return Err(RequestError::new(
+                format!("'{}' is not a valid role; expected one of {}.", role, CHAT_ROLES.join(", ")),
+                format!("messages[{}].role", i),
+            ));
+        }
+        // Text, content parts (e.g. images), or nothing for assistant messages with tool calls.
+        if !matches!(message.get("content"), None | Some(Value::Null | Value::String(_) | Value::Array(_))) {
+            return Err(RequestError::new(
+                "'content' must be a string or an array of content parts.",
+                format!("messages[{}].content", i),
+            ));
+        }
+        if role == "tool" && !message.get("tool_call_id").is_some_and(Value::is_string) {
+            return Err(RequestError::new(
+                "Tool messages need the 'tool_call_id' they answer.",
+                format!("messages[{}].tool_call_id", i),
+            ));
+        }
+    }
+    if request.get("stream").is_some_and(|stream| !stream.is_boolean() && !stream.is_null()) {
+        return Err(RequestError::new("'stream' must be a boolean.", "stream"));
+    }
+    check_number(request, "temperature", 0.0, 2.0)?;
+    check_number(request, "top_p", 0.0, 1.0)?;
+    check_number(request, "max_tokens", 1.0, u32::MAX as f64)?;
+    check_number(request, "max_completion_tokens", 1.0, u32::MAX as f64)
+}
+
+fn check_embeddings(request: &Map<String, Value>) -> Result<(), RequestError> {
+    let valid = match request.get("input") {
+        Some(Value::String(input)) => !input.is_empty(),
+        Some(Value::Array(inputs)) => !inputs.is_empty() && inputs.iter().all(Value::is_string),
+        _ => false,
+    };
+    if !valid {
+        return Err(RequestError::new(
+            "'input' must be a non-empty string or array of strings.",
+            "input",
+        ));
+    }
+    check_number(request, "dimensions", 1.0, u32::MAX as f64)
+}
+
+fn check_rerank(request: &Map<String, Value>) -> Result<(), RequestError> {
+    if request.get("query").and_then(Value::as_str).is_none_or(str::is_empty) {
+        return Err(RequestError::new("'query' must be a non-empty string.", "query"));
+    }
+    let valid = request.get("documents").and_then(Value::as_array).is_some_and(|documents| {
+        !documents.is_empty()
+            && documents
+                .iter()
+                .all(|document| document.is_string() || document.get("text").is_some_and(Value::is_string))
+    });
+    if !valid {
+        return Err(RequestError::new(
+            "'documents' must be a non-empty array of strings or {\"text\": ...} objects.",
+            "documents",
+        ));
+    }
+    check_number(request, "top_n", 1.0, u32::MAX as f64)
+}
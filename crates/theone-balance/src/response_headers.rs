@@ -0,0 +1,86 @@
+//! This module contains the policy for which upstream response headers are forwarded to
+//! clients. Only allowlisted headers pass, and the denylist always wins, so provider
+//! internals (cookies, organisation ids, tracing headers) don't leak through the gateway.
+//!
+//! Patterns are case-insensitive header names; a trailing `*` matches by prefix.
+
+use worker::{Env, Headers, Response, Result};
+
+/// Forwarded by default: body metadata, rate-limit information and request ids.
+const DEFAULT_ALLOW: &[&str] = &[
+    "content-type",
+    "content-disposition",
+    "cache-control",
+    "retry-after",
+    "x-ratelimit-*",
+    "anthropic-ratelimit-*",
+    "x-request-id",
+    "request-id",
+    "openai-processing-ms",
+    "cf-aig-*",
+];
+
+/// Always stripped, even when allowlisted.
+const DEFAULT_DENY: &[&str] = &[
+    "set-cookie",
+    "openai-organization",
+    "openai-project",
+    "server",
+    "via",
+    "alt-svc",
+    "nel",
+    "report-to",
+];
+
+pub struct HeaderPolicy {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+fn parse_patterns(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|p| p.trim().to_ascii_lowercase())
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+fn matches(patterns: &[String], name: &str) -> bool {
+    patterns.iter().any(|p| match p.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => p == name,
+    })
+}
+
+impl HeaderPolicy {
+    /// Reads the policy from `FORWARD_RESPONSE_HEADERS`, which replaces the default
+    /// allowlist (`*` forwards everything), and `STRIP_RESPONSE_HEADERS`, which extends
+    /// the default denylist.
+    pub fn from_env(env: &Env) -> Self {
+        let allow = env
+            .var("FORWARD_RESPONSE_HEADERS")
+            .map(|v| parse_patterns(&v.to_string()))
+            .unwrap_or_else(|_| DEFAULT_ALLOW.iter().map(|p| p.to_string()).collect());
+        let mut deny: Vec<String> = DEFAULT_DENY.iter().map(|p| p.to_string()).collect();
+        if let Ok(extra) = env.var("STRIP_RESPONSE_HEADERS") {
+            deny.extend(parse_patterns(&extra.to_string()));
+        }
+        HeaderPolicy { allow, deny }
+    }
+
+    pub fn forwards(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        matches(&self.allow, &name) && !matches(&self.deny, &name)
+    }
+
+    /// Rebuilds the response's headers keeping only the forwarded ones.
+    pub fn apply(&self, resp: Response) -> Result<Response> {
+        let filtered = Headers::new();
+        for (name, value) in resp.headers().entries() {
+            if self.forwards(&name) {
+                filtered.append(&name, &value)?;
+            }
+        }
+        Ok(resp.with_headers(filtered))
+    }
+}
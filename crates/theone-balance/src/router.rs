@@ -1,16 +1,46 @@
 use crate::AppState;
-use crate::{handlers, web};
-use axum::{routing::post, Router};
+use crate::{admin, build_info, handlers, ip_allowlist, metrics, openapi, request_id, status, web};
+use axum::{
+    middleware,
+    routing::{get, post},
+    Router,
+};
 use std::sync::Arc;
 use tower_cookies::CookieManagerLayer;
 
-pub fn new() -> Router<Arc<AppState>> {
+pub fn new(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    // The management surface is restricted by the optional IP allowlist.
+    let ip_guard = middleware::from_fn_with_state(state.clone(), ip_allowlist::guard);
+    // Admin API calls are checked against the caller's role, after the allowlist.
+    let role_guard = middleware::from_fn_with_state(state, admin::authorize);
     Router::new()
-        .merge(web::ui_router())
+        .merge(web::ui_router().route_layer(ip_guard.clone()))
+        .merge(admin::admin_router().route_layer(role_guard).route_layer(ip_guard))
+        // The aggregated model list and token counting take precedence over the catch-all proxy route below.
+        .route("/api/compat/models", get(handlers::list_models))
+        .route("/api/compat/tokens/count", post(handlers::count_tokens))
+        // Preflight check; models may contain slashes (e.g. Workers AI `@cf/...`).
+        .route("/api/availability/{provider}/{*model}", get(handlers::check_availability))
+        // Public per-provider summary for status pages.
+        .route("/api/status", get(status::status_handler))
         // All API requests are now handled by the unified `forward` function.
         // It will internally determine the correct logic (e.g., embeddings fallback) based on the path.
-        .route("/api/{*path}", post(handlers::forward))
+        // Body-less methods (e.g. GET for listing models or retrieving files) are proxied as well.
+        .route(
+            "/api/{*path}",
+            post(handlers::forward)
+                .get(handlers::forward)
+                .put(handlers::forward)
+                .patch(handlers::forward)
+                .delete(handlers::forward),
+        )
         .route("/test/run-cleanup/{provider}", post(handlers::run_cleanup_handler))
+        .route("/metrics", get(metrics::metrics_handler))
+        .route("/version", get(build_info::version_handler))
+        // Hand-maintained description of the compat, admin and status routes for SDK generators.
+        .route("/openapi.json", get(openapi::openapi_handler))
         // Add the cookie manager layer for cookie support
         .layer(CookieManagerLayer::new())
+        // Outermost, so every response (errors included) carries the request id.
+        .layer(middleware::from_fn(request_id::propagate))
 }
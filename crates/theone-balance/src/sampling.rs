@@ -0,0 +1,87 @@
+//! This module contains the opt-in request sampler used for offline quality evaluation.
+//! A small share of successful prompt/response pairs is stored, redacted, in the `samples`
+//! table and can be exported from `/api/admin/samples`.
+
+use serde::{Deserialize, Serialize};
+
+/// Stored bodies are truncated to this many bytes.
+const MAX_SAMPLE_BYTES: usize = 16 * 1024;
+
+/// Token prefixes of well-known provider API keys.
+const SECRET_PREFIXES: &[&str] = &["sk-", "AIza", "xai-", "gsk_", "hf_", "ghp_"];
+
+/// A sampled prompt/response pair, ready to be stored.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SampleRecord {
+    pub provider: String,
+    pub model: String,
+    pub key_id: String,
+    /// The client key id, or `None` for the master key.
+    pub client_id: Option<String>,
+    pub endpoint: String,
+    pub request_body: String,
+    pub response_body: String,
+    pub latency_ms: i64,
+}
+
+/// Decides whether the current request should be sampled.
+pub fn should_sample(rate_percent: f64) -> bool {
+    rate_percent > 0.0 && rand::random::<f64>() * 100.0 < rate_percent
+}
+
+/// Checks whether a word looks like a credential: a known key prefix, or a long
+/// random-looking run of letters and digits.
+fn looks_like_secret(word: &str) -> bool {
+    if SECRET_PREFIXES.iter().any(|p| word.starts_with(p)) && word.len() > 12 {
+        return true;
+    }
+    word.len() >= 32
+        && word.chars().any(|c| c.is_ascii_digit())
+        && word.chars().any(|c| c.is_ascii_alphabetic())
+}
+
+fn looks_like_email(word: &str) -> bool {
+    match word.split_once('@') {
+        Some((local, domain)) => !local.is_empty() && domain.contains('.') && !domain.ends_with('.'),
+        None => false,
+    }
+}
+
+/// Masks credentials and email addresses in a body and truncates it to `MAX_SAMPLE_BYTES`.
+pub fn redact(text: &str) -> String {
+    let is_word_char = |c: char| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '@' | '+');
+    let mut out = String::with_capacity(text.len().min(MAX_SAMPLE_BYTES));
+    let mut word = String::new();
+    let flush = |word: &mut String, out: &mut String| {
+        if looks_like_email(word) {
+            out.push_str("[email]");
+        } else if looks_like_secret(word) {
+            out.push_str("[redacted]");
+        } else {
+            out.push_str(word);
+        }
+        word.clear();
+    };
+
+    for c in text.chars() {
+        if is_word_char(c) {
+            word.push(c);
+        } else {
+            flush(&mut word, &mut out);
+            out.push(c);
+        }
+        if out.len() + word.len() >= MAX_SAMPLE_BYTES {
+            break;
+        }
+    }
+    flush(&mut word, &mut out);
+
+    if out.len() > MAX_SAMPLE_BYTES {
+        let mut end = MAX_SAMPLE_BYTES;
+        while !out.is_char_boundary(end) {
+            end -= 1;
+        }
+        out.truncate(end);
+    }
+    out
+}
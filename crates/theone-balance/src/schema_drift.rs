@@ -0,0 +1,249 @@
+//! This module compares the live D1 schema with the one Toasty builds from the models in
+//! `dbmodels.rs`, so a database that is behind the code (a skipped migration, a column
+//! added by hand on one environment only) is reported before queries start failing.
+//!
+//! Missing tables, columns and unique indexes break queries or conflict handling and make
+//! the schema drifted. Missing plain indexes only cost performance and are listed as advice.
+//!
+//! The report names the pending migrations that would fix each drifted table. Drift no
+//! pending migration touches means `src/migrations.rs` lacks a migration for a model change.
+//! The check runs on demand through the admin API, and with `CHECK_SCHEMA` on the first
+//! request of each isolate and on every cron trigger.
+
+use crate::migrations::{self, Migration};
+
+use crate::hybrid::get_schema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use toasty_core::schema::db::{Schema, Table};
+use tracing::{error, info, warn};
+use worker::{D1Database, D1Type, Result};
+
+const TABLE_COLUMNS_SQL: &str = "SELECT name FROM pragma_table_info(?1)";
+const TABLE_INDEXES_SQL: &str = "SELECT il.name AS index_name, il.\"unique\" AS is_unique, \
+    il.partial AS is_partial, ii.name AS column_name \
+    FROM pragma_index_list(?1) AS il JOIN pragma_index_info(il.name) AS ii \
+    ORDER BY il.name, ii.seqno";
+
+#[derive(Deserialize)]
+struct ColumnRow {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct IndexColumnRow {
+    index_name: String,
+    is_unique: i64,
+    is_partial: i64,
+    column_name: String,
+}
+
+/// An index as it exists in D1, with its columns in order.
+#[derive(Debug, Default)]
+struct LiveIndex {
+    unique: bool,
+    partial: bool,
+    columns: Vec<String>,
+}
+
+/// A table as it exists in D1. A table without columns doesn't exist.
+#[derive(Debug, Default)]
+struct LiveTable {
+    columns: HashSet<String>,
+    indexes: Vec<LiveIndex>,
+}
+
+/// An index the models declare but D1 lacks.
+#[derive(Serialize, Debug)]
+pub struct MissingIndex {
+    /// The name Toasty gives the index; D1 indexes are matched by columns, not by name.
+    pub name: String,
+    pub columns: Vec<String>,
+    pub unique: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TableDrift {
+    pub table: String,
+    pub missing_table: bool,
+    pub missing_columns: Vec<String>,
+    pub missing_indexes: Vec<MissingIndex>,
+    /// Versions of the pending migrations that touch the table; applying them should fix it.
+    pub pending_migrations: Vec<u32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DriftReport {
+    /// `false` when a table, column or unique index is missing.
+    pub ok: bool,
+    /// Only the tables that differ from the models.
+    pub tables: Vec<TableDrift>,
+    /// Versions of every migration not applied yet.
+    pub pending_migrations: Vec<u32>,
+}
+
+impl LiveIndex {
+    /// Whether this index can serve lookups on `columns`: a unique index must cover exactly
+    /// those columns for every row, a plain one only needs them as its leading columns.
+    fn serves(&self, columns: &[String], unique: bool) -> bool {
+        if unique {
+            return self.unique
+                && !self.partial
+                && self.columns.len() == columns.len()
+                && columns.iter().all(|c| self.columns.contains(c));
+        }
+        self.columns.len() >= columns.len()
+            && columns.iter().all(|c| self.columns[..columns.len()].contains(c))
+    }
+}
+
+fn table_drift(table: &Table, live: &LiveTable) -> Option<TableDrift> {
+    if live.columns.is_empty() {
+        return Some(TableDrift {
+            table: table.name.clone(),
+            missing_table: true,
+            missing_columns: Vec::new(),
+            missing_indexes: Vec::new(),
+            pending_migrations: Vec::new(),
+        });
+    }
+
+    let missing_columns: Vec<String> = table
+        .columns
+        .iter()
+        .filter(|column| !live.columns.contains(&column.name))
+        .map(|column| column.name.clone())
+        .collect();
+    let missing_indexes: Vec<MissingIndex> = table
+        .indices
+        .iter()
+        .filter(|index| !index.primary_key)
+        .filter_map(|index| {
+            let columns: Vec<String> = index
+                .columns
+                .iter()
+                .map(|c| table.columns[c.column.index].name.clone())
+                .collect();
+            let served = live.indexes.iter().any(|live| live.serves(&columns, index.unique));
+            (!served).then(|| MissingIndex {
+                name: index.name.clone(),
+                columns,
+                unique: index.unique,
+            })
+        })
+        .collect();
+
+    if missing_columns.is_empty() && missing_indexes.is_empty() {
+        return None;
+    }
+    Some(TableDrift {
+        table: table.name.clone(),
+        missing_table: false,
+        missing_columns,
+        missing_indexes,
+        pending_migrations: Vec::new(),
+    })
+}
+
+fn compare(schema: &Schema, live: &BTreeMap<String, LiveTable>, pending: &[&Migration]) -> DriftReport {
+    let tables: Vec<TableDrift> = schema
+        .tables
+        .iter()
+        .filter_map(|table| table_drift(table, live.get(&table.name)?))
+        .map(|mut drift| {
+            drift.pending_migrations = pending
+                .iter()
+                .filter(|m| m.touches(&drift.table))
+                .map(|m| m.version)
+                .collect();
+            drift
+        })
+        .collect();
+    let ok = tables.iter().all(|t| {
+        !t.missing_table && t.missing_columns.is_empty() && t.missing_indexes.iter().all(|i| !i.unique)
+    });
+    DriftReport {
+        ok,
+        tables,
+        pending_migrations: pending.iter().map(|m| m.version).collect(),
+    }
+}
+
+/// Reads the columns and indexes of every model table from D1, in one batch.
+async fn live_tables(db: &D1Database, schema: &Schema) -> Result<BTreeMap<String, LiveTable>> {
+    let mut statements = Vec::with_capacity(schema.tables.len() * 2);
+    for table in &schema.tables {
+        let name = [D1Type::Text(&table.name)];
+        statements.push(db.prepare(TABLE_COLUMNS_SQL).bind_refs(&name)?);
+        statements.push(db.prepare(TABLE_INDEXES_SQL).bind_refs(&name)?);
+    }
+    let mut results = db.batch(statements).await?.into_iter();
+
+    let mut live = BTreeMap::new();
+    for table in &schema.tables {
+        let (Some(columns), Some(indexes)) = (results.next(), results.next()) else {
+            break;
+        };
+        let mut live_table = LiveTable {
+            columns: columns.results::<ColumnRow>()?.into_iter().map(|row| row.name).collect(),
+            indexes: Vec::new(),
+        };
+        let mut current: Option<(String, LiveIndex)> = None;
+        for row in indexes.results::<IndexColumnRow>()? {
+            if current.as_ref().is_none_or(|(name, _)| *name != row.index_name) {
+                live_table.indexes.extend(current.take().map(|(_, index)| index));
+                current = Some((
+                    row.index_name,
+                    LiveIndex {
+                        unique: row.is_unique != 0,
+                        partial: row.is_partial != 0,
+                        columns: Vec::new(),
+                    },
+                ));
+            }
+            if let Some((_, index)) = current.as_mut() {
+                index.columns.push(row.column_name);
+            }
+        }
+        live_table.indexes.extend(current.map(|(_, index)| index));
+        live.insert(table.name.clone(), live_table);
+    }
+    Ok(live)
+}
+
+/// Compares the live D1 schema with the models.
+pub async fn check(db: &D1Database) -> Result<DriftReport> {
+    let schema = get_schema();
+    let live = live_tables(db, schema).await?;
+    let pending = migrations::pending(db).await?;
+    Ok(compare(schema, &live, &pending))
+}
+
+/// Logs the drift report: an error per table that breaks queries, a warning per index
+/// that is only advisory.
+pub fn log_report(report: &DriftReport) {
+    for table in &report.tables {
+        if table.missing_table {
+            error!(table = %table.table, "Schema drift: table is missing in D1.");
+        } else {
+            if !table.missing_columns.is_empty() {
+                error!(table = %table.table, columns = ?table.missing_columns, "Schema drift: columns are missing in D1.");
+            }
+            for index in &table.missing_indexes {
+                if index.unique {
+                    error!(table = %table.table, columns = ?index.columns, "Schema drift: unique index is missing in D1.");
+                } else {
+                    warn!(table = %table.table, columns = ?index.columns, "No D1 index serves a model index; lookups on it scan the table.");
+                }
+            }
+        }
+        if table.pending_migrations.is_empty() {
+            warn!(table = %table.table, "No pending migration touches the drifted table; src/migrations.rs may lack one.");
+        } else {
+            warn!(table = %table.table, versions = ?table.pending_migrations, "Pending migrations touch the drifted table; apply them to fix it.");
+        }
+    }
+    if report.ok {
+        info!("D1 schema matches the models.");
+    }
+}
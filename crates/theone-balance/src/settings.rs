@@ -0,0 +1,376 @@
+//! This module contains the typed runtime settings, loaded once per request and carried
+//! in `AppState`. Values come from the worker's vars; the tunables in `OVERRIDABLE` can
+//! also be set in the `settings` table (through the admin API), which wins over the vars
+//! so they can be changed without a deploy. Stored overrides are cached for a short time.
+//!
+//! Settings that shape the deployment itself (`IS_LOCAL`, `AUTO_MIGRATE`, ...) are only
+//! read from the vars.
+
+use crate::alerts;
+use crate::d1_storage;
+use crate::payload_log;
+use mini_moka::sync::Cache;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::debug;
+use worker::{D1Database, Env};
+
+/// Settings that can be overridden in the `settings` table.
+pub const OVERRIDABLE: &[&str] = &[
+    "OVERALL_TIMEOUT_MS",
+    "TARGET_TIMEOUT_MS",
+    "COOLDOWN_WAIT_MAX_MS",
+    "FAILOVER_MIN_BUDGET_MS",
+    "RECOVERY_THRESHOLD",
+    "KEY_FAIRNESS_WEIGHT",
+    "SCORE_LATENCY_WEIGHT_PERCENT",
+    "SCORE_SUCCESS_WEIGHT_PERCENT",
+    "SCORE_FAILURE_PENALTY",
+    "SCORE_RECENT_SUCCESS_BONUS",
+    "SCORE_RECENT_SUCCESS_WINDOW_SECONDS",
+    "KEY_TIER_STRATEGY",
+    "MODELS_CACHE_TTL_SECONDS",
+    "SAMPLE_RATE_PERCENT",
+    "SCORE_SAMPLE_RATE_PERCENT",
+    "ANOMALY_LATENCY_FACTOR",
+    "ANOMALY_ERROR_RATE_PERCENT",
+    "PROVIDER_BREAKER_ERROR_RATE_PERCENT",
+    "PROVIDER_BREAKER_OPEN_SECONDS",
+    "EXPLAIN_QUERIES",
+    "MAX_BODY_BYTES",
+    "PAYLOAD_LOGGING",
+    "PAYLOAD_REDACT_PATTERNS",
+    "ALERT_RULES",
+    "BLOCKED_KEY_RETENTION_DAYS",
+    "PROBATION_SAMPLE_SIZE",
+    "PROBATION_MAX_REACTIVATIONS",
+];
+
+/// The stored overrides, shared by the requests of an isolate.
+static OVERRIDES_CACHE: Lazy<Cache<(), HashMap<String, String>>> = Lazy::new(|| {
+    Cache::builder()
+        .time_to_live(Duration::from_secs(30))
+        .build()
+});
+
+/// How key tiers order the failover list. Within a tier keys stay ranked by health.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TierStrategy {
+    /// Free keys first; paid keys only once every free key is cooling down or failing.
+    #[default]
+    FreeFirst,
+    /// Paid keys first, e.g. for latency-sensitive deployments.
+    PaidFirst,
+    /// Tiers are ignored.
+    Mixed,
+}
+
+impl TierStrategy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "free_first" => Some(TierStrategy::FreeFirst),
+            "paid_first" => Some(TierStrategy::PaidFirst),
+            "mixed" => Some(TierStrategy::Mixed),
+            _ => None,
+        }
+    }
+}
+
+/// Which requests keep their (redacted, truncated) prompt and response in the request events.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadLogging {
+    #[default]
+    Off,
+    /// Only requests that failed, e.g. to debug a translation the provider rejects.
+    Failures,
+    All,
+}
+
+impl PayloadLogging {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "off" => Some(PayloadLogging::Off),
+            "failures" => Some(PayloadLogging::Failures),
+            "all" => Some(PayloadLogging::All),
+            _ => None,
+        }
+    }
+}
+
+/// The weights of the key health score (see `balancer::score_keys`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ScoringConfig {
+    /// Scales the latency score (10000 minus the p95 latency), in percent.
+    pub latency_weight_percent: i64,
+    /// Scales the success score (the success rate scaled to 1000), in percent.
+    pub success_weight_percent: i64,
+    /// Subtracted per consecutive failure.
+    pub failure_penalty: i64,
+    /// Added for keys that succeeded within `recent_success_window_seconds`.
+    pub recent_success_bonus: i64,
+    pub recent_success_window_seconds: u64,
+}
+
+impl Default for ScoringConfig {
+    fn default() -> Self {
+        ScoringConfig {
+            latency_weight_percent: 100,
+            success_weight_percent: 100,
+            failure_penalty: 50,
+            recent_success_bonus: 10,
+            recent_success_window_seconds: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Settings {
+    /// Budget for a whole request, failovers included.
+    pub overall_timeout_ms: u64,
+    /// Budget for one upstream call.
+    pub target_timeout_ms: u64,
+    /// How long a request may wait for a key to come off a short cooldown; 0 disables waiting.
+    pub cooldown_wait_max_ms: u64,
+    /// The failover loop stops trying new keys once less than this is left of the overall budget.
+    pub failover_min_budget_ms: u64,
+    /// Consecutive failures after which the circuit breaker sidelines a key.
+    pub recovery_threshold: i64,
+    /// How strongly traffic is spread across keys; 0 ranks purely by health.
+    pub key_fairness_weight: i64,
+    pub scoring: ScoringConfig,
+    /// Whether free or paid keys are tried first.
+    pub key_tier_strategy: TierStrategy,
+    pub models_cache_ttl_seconds: u64,
+    /// Percentage (0-100) of successful requests sampled for evaluation.
+    pub sample_rate_percent: f64,
+    /// Percentage (0-100) of requests whose key ranking is stored with its score components.
+    pub score_sample_rate_percent: f64,
+    /// A provider whose latency over the last minute reaches this multiple of its baseline
+    /// is flagged as degraded; 0 disables the check.
+    pub anomaly_latency_factor: u64,
+    /// A provider whose error rate over the last minute reaches this percentage is flagged
+    /// as degraded; 0 disables the check.
+    pub anomaly_error_rate_percent: u64,
+    /// A provider whose error rate over the last minute reaches this percentage gets its
+    /// circuit breaker opened; 0 disables the breaker.
+    pub provider_breaker_error_rate_percent: u64,
+    /// How long an open breaker rejects requests before probing the provider again.
+    pub provider_breaker_open_seconds: u64,
+    /// Request bodies larger than this are rejected with a 413; 0 means unlimited.
+    pub max_body_bytes: u64,
+    pub payload_logging: PayloadLogging,
+    /// Extra regexes, separated by `;`, whose matches are masked in logged payloads.
+    pub payload_redact_patterns: String,
+    /// The alert rules evaluated by the scheduled handler (see `alerts`); empty disables alerting.
+    pub alert_rules: String,
+    /// Blocked keys are moved to the trash by the cleanup job after this many days; 0 keeps them.
+    pub blocked_key_retention_days: u64,
+    /// Blocked keys re-tested by each daily probation pass; 0 disables probation.
+    pub probation_sample_size: u64,
+    /// Probation stops re-testing a key once it was reactivated this many times.
+    pub probation_max_reactivations: u64,
+    pub explain_queries: bool,
+    pub is_local: bool,
+    /// Admin API calls per credential and minute; 0 means unlimited. Read from the vars
+    /// only, so a leaked admin token can't lift its own limit.
+    pub admin_rate_limit_per_minute: u64,
+    /// Key exports, reveals and test runs per credential and minute; 0 means unlimited.
+    pub admin_sensitive_rate_limit_per_minute: u64,
+    /// How long a UI or admin API session lasts after login. Read from the vars only, like
+    /// the admin rate limits.
+    pub session_ttl_seconds: u64,
+    /// How long a device skips the second login factor once remembered; 0 disables
+    /// remembering devices. Read from the vars only.
+    pub totp_remember_days: u64,
+    /// The deployment, e.g. `staging`, from `DEPLOY_ENV`; empty when unset.
+    pub deploy_env: String,
+    pub auto_migrate: bool,
+    pub check_schema: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            overall_timeout_ms: 25_000,
+            target_timeout_ms: 10_000,
+            cooldown_wait_max_ms: 0,
+            failover_min_budget_ms: 1_000,
+            recovery_threshold: 5,
+            key_fairness_weight: 500,
+            scoring: ScoringConfig::default(),
+            key_tier_strategy: TierStrategy::FreeFirst,
+            models_cache_ttl_seconds: 3600,
+            sample_rate_percent: 0.0,
+            score_sample_rate_percent: 0.0,
+            anomaly_latency_factor: 5,
+            anomaly_error_rate_percent: 50,
+            provider_breaker_error_rate_percent: 90,
+            provider_breaker_open_seconds: 30,
+            max_body_bytes: 32 * 1024 * 1024,
+            payload_logging: PayloadLogging::Off,
+            payload_redact_patterns: String::new(),
+            alert_rules: String::new(),
+            blocked_key_retention_days: 30,
+            probation_sample_size: 10,
+            probation_max_reactivations: 3,
+            explain_queries: false,
+            is_local: false,
+            admin_rate_limit_per_minute: 120,
+            admin_sensitive_rate_limit_per_minute: 10,
+            session_ttl_seconds: 12 * 60 * 60,
+            totp_remember_days: 30,
+            deploy_env: String::new(),
+            auto_migrate: false,
+            check_schema: false,
+        }
+    }
+}
+
+impl Settings {
+    /// Builds the settings from a lookup by var name; missing or unparsable values keep
+    /// their defaults.
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let defaults = Settings::default();
+        let number = |name: &str, default: u64| lookup(name).and_then(|v| v.trim().parse().ok()).unwrap_or(default);
+        let signed = |name: &str, default: i64| lookup(name).and_then(|v| v.trim().parse().ok()).unwrap_or(default);
+        let flag = |name: &str| lookup(name).is_some_and(|v| v.trim() == "true");
+        Settings {
+            overall_timeout_ms: number("OVERALL_TIMEOUT_MS", defaults.overall_timeout_ms),
+            target_timeout_ms: number("TARGET_TIMEOUT_MS", defaults.target_timeout_ms),
+            cooldown_wait_max_ms: number("COOLDOWN_WAIT_MAX_MS", defaults.cooldown_wait_max_ms),
+            failover_min_budget_ms: number("FAILOVER_MIN_BUDGET_MS", defaults.failover_min_budget_ms),
+            recovery_threshold: signed("RECOVERY_THRESHOLD", defaults.recovery_threshold),
+            key_fairness_weight: signed("KEY_FAIRNESS_WEIGHT", defaults.key_fairness_weight),
+            scoring: ScoringConfig {
+                latency_weight_percent: signed(
+                    "SCORE_LATENCY_WEIGHT_PERCENT",
+                    defaults.scoring.latency_weight_percent,
+                ),
+                success_weight_percent: signed(
+                    "SCORE_SUCCESS_WEIGHT_PERCENT",
+                    defaults.scoring.success_weight_percent,
+                ),
+                failure_penalty: signed("SCORE_FAILURE_PENALTY", defaults.scoring.failure_penalty),
+                recent_success_bonus: signed("SCORE_RECENT_SUCCESS_BONUS", defaults.scoring.recent_success_bonus),
+                recent_success_window_seconds: number(
+                    "SCORE_RECENT_SUCCESS_WINDOW_SECONDS",
+                    defaults.scoring.recent_success_window_seconds,
+                ),
+            },
+            key_tier_strategy: lookup("KEY_TIER_STRATEGY")
+                .and_then(|v| TierStrategy::parse(&v))
+                .unwrap_or(defaults.key_tier_strategy),
+            models_cache_ttl_seconds: number("MODELS_CACHE_TTL_SECONDS", defaults.models_cache_ttl_seconds),
+            sample_rate_percent: lookup("SAMPLE_RATE_PERCENT")
+                .and_then(|v| v.trim().parse::<f64>().ok())
+                .unwrap_or(defaults.sample_rate_percent)
+                .clamp(0.0, 100.0),
+            score_sample_rate_percent: lookup("SCORE_SAMPLE_RATE_PERCENT")
+                .and_then(|v| v.trim().parse::<f64>().ok())
+                .unwrap_or(defaults.score_sample_rate_percent)
+                .clamp(0.0, 100.0),
+            anomaly_latency_factor: number("ANOMALY_LATENCY_FACTOR", defaults.anomaly_latency_factor),
+            anomaly_error_rate_percent: number("ANOMALY_ERROR_RATE_PERCENT", defaults.anomaly_error_rate_percent)
+                .min(100),
+            provider_breaker_error_rate_percent: number(
+                "PROVIDER_BREAKER_ERROR_RATE_PERCENT",
+                defaults.provider_breaker_error_rate_percent,
+            )
+            .min(100),
+            provider_breaker_open_seconds: number(
+                "PROVIDER_BREAKER_OPEN_SECONDS",
+                defaults.provider_breaker_open_seconds,
+            ),
+            max_body_bytes: number("MAX_BODY_BYTES", defaults.max_body_bytes),
+            payload_logging: lookup("PAYLOAD_LOGGING")
+                .and_then(|v| PayloadLogging::parse(&v))
+                .unwrap_or(defaults.payload_logging),
+            payload_redact_patterns: lookup("PAYLOAD_REDACT_PATTERNS").unwrap_or_default(),
+            alert_rules: lookup("ALERT_RULES").unwrap_or_default(),
+            blocked_key_retention_days: number("BLOCKED_KEY_RETENTION_DAYS", defaults.blocked_key_retention_days),
+            probation_sample_size: number("PROBATION_SAMPLE_SIZE", defaults.probation_sample_size),
+            probation_max_reactivations: number("PROBATION_MAX_REACTIVATIONS", defaults.probation_max_reactivations),
+            explain_queries: flag("EXPLAIN_QUERIES"),
+            is_local: flag("IS_LOCAL"),
+            admin_rate_limit_per_minute: number("ADMIN_RATE_LIMIT_PER_MINUTE", defaults.admin_rate_limit_per_minute),
+            admin_sensitive_rate_limit_per_minute: number(
+                "ADMIN_SENSITIVE_RATE_LIMIT_PER_MINUTE",
+                defaults.admin_sensitive_rate_limit_per_minute,
+            ),
+            session_ttl_seconds: number("SESSION_TTL_SECONDS", defaults.session_ttl_seconds).max(60),
+            totp_remember_days: number("TOTP_REMEMBER_DAYS", defaults.totp_remember_days),
+            deploy_env: lookup("DEPLOY_ENV").map(|v| v.trim().to_string()).unwrap_or_default(),
+            auto_migrate: flag("AUTO_MIGRATE"),
+            check_schema: flag("CHECK_SCHEMA"),
+        }
+    }
+
+    /// The settings from the worker's vars alone.
+    pub fn from_env(env: &Env) -> Self {
+        Settings::from_lookup(|name| env.var(name).ok().map(|v| v.to_string()))
+    }
+
+    /// The settings from the vars and the stored overrides. Overrides that can't be read,
+    /// e.g. before the `settings` table is migrated, are skipped.
+    pub async fn load(env: &Env, db: &D1Database) -> Self {
+        let overrides = match OVERRIDES_CACHE.get(&()) {
+            Some(cached) => cached,
+            None => {
+                let overrides = match d1_storage::list_settings(db).await {
+                    Ok(stored) => stored
+                        .into_iter()
+                        .filter(|(name, _)| OVERRIDABLE.contains(&name.as_str()))
+                        .collect(),
+                    Err(e) => {
+                        debug!("Stored settings unavailable, using vars only: {}", e);
+                        HashMap::new()
+                    }
+                };
+                OVERRIDES_CACHE.insert((), overrides.clone());
+                overrides
+            }
+        };
+        Settings::from_lookup(|name| {
+            overrides
+                .get(name)
+                .cloned()
+                .or_else(|| env.var(name).ok().map(|v| v.to_string()))
+        })
+    }
+}
+
+/// Checks that a value can be stored for a setting: the name must be overridable and the
+/// value must parse as the setting's type.
+pub fn validate(name: &str, value: &str) -> Result<(), String> {
+    if !OVERRIDABLE.contains(&name) {
+        return Err(format!("'{}' can't be overridden; expected one of {}", name, OVERRIDABLE.join(", ")));
+    }
+    let value = value.trim();
+    let valid = match name {
+        "EXPLAIN_QUERIES" => value == "true" || value == "false",
+        "SAMPLE_RATE_PERCENT" | "SCORE_SAMPLE_RATE_PERCENT" => value.parse::<f64>().is_ok_and(|v| (0.0..=100.0).contains(&v)),
+        "RECOVERY_THRESHOLD" | "KEY_FAIRNESS_WEIGHT" | "SCORE_FAILURE_PENALTY" | "SCORE_RECENT_SUCCESS_BONUS" => {
+            value.parse::<i64>().is_ok()
+        }
+        "SCORE_LATENCY_WEIGHT_PERCENT" | "SCORE_SUCCESS_WEIGHT_PERCENT" => value.parse::<u64>().is_ok(),
+        "KEY_TIER_STRATEGY" => TierStrategy::parse(value).is_some(),
+        "PAYLOAD_LOGGING" => PayloadLogging::parse(value).is_some(),
+        "PAYLOAD_REDACT_PATTERNS" => return payload_log::check_patterns(value),
+        "ALERT_RULES" => return alerts::check_rules(value),
+        "ANOMALY_ERROR_RATE_PERCENT" | "PROVIDER_BREAKER_ERROR_RATE_PERCENT" => value.parse::<u64>().is_ok_and(|v| v <= 100),
+        _ => value.parse::<u64>().is_ok(),
+    };
+    if !valid {
+        return Err(format!("'{}' is not a valid value for {}", value, name));
+    }
+    Ok(())
+}
+
+/// Drops the cached overrides of this isolate, after they were changed.
+pub fn invalidate_cache() {
+    OVERRIDES_CACHE.invalidate(&());
+}
@@ -0,0 +1,136 @@
+//! This module is the one-minute sliding window shared by the admin rate limits, the
+//! per-key rate budgets, the provider health baselines and the provider breakers. Each
+//! of them keeps its windows in a per-isolate cache, so an isolate only sees the traffic
+//! it served itself.
+
+use mini_moka::sync::Cache;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const WINDOW_MS: u64 = 60_000;
+
+/// Entries of the last `WINDOW_MS`, each with the time it was pushed, oldest first.
+#[derive(Debug, Clone)]
+pub struct SlidingWindow<T> {
+    entries: VecDeque<(u64, T)>,
+}
+
+impl<T> Default for SlidingWindow<T> {
+    fn default() -> Self {
+        SlidingWindow { entries: VecDeque::new() }
+    }
+}
+
+impl<T> SlidingWindow<T> {
+    /// Drops the entries that are `WINDOW_MS` or older at `now_ms`.
+    pub fn expire(&mut self, now_ms: u64) {
+        while self.entries.front().is_some_and(|(at_ms, _)| now_ms.saturating_sub(*at_ms) >= WINDOW_MS) {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Adds an entry at `now_ms` and drops the expired ones.
+    pub fn push(&mut self, now_ms: u64, value: T) {
+        self.entries.push_back((now_ms, value));
+        self.expire(now_ms);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Whole seconds until the oldest entry leaves the window, at least 1.
+    pub fn retry_after_seconds(&self, now_ms: u64) -> u64 {
+        let oldest_ms = self.entries.front().map_or(now_ms, |(at_ms, _)| *at_ms);
+        (oldest_ms + WINDOW_MS).saturating_sub(now_ms).div_ceil(1000).max(1)
+    }
+}
+
+/// Windows by id. Idle entries are evicted once their window would be empty anyway.
+pub struct WindowCache<T> {
+    windows: Cache<String, Arc<Mutex<SlidingWindow<T>>>>,
+}
+
+impl<T: Send + Sync + 'static> WindowCache<T> {
+    pub fn new(max_capacity: u64) -> Self {
+        WindowCache {
+            windows: Cache::builder()
+                .max_capacity(max_capacity)
+                .time_to_idle(Duration::from_millis(WINDOW_MS))
+                .build(),
+        }
+    }
+
+    /// Runs `f` on the window of `id`, starting an empty one if there is none.
+    pub fn update<R>(&self, id: &str, f: impl FnOnce(&mut SlidingWindow<T>) -> R) -> R {
+        let window = self.windows.get(&id.to_string()).unwrap_or_else(|| {
+            let window = Arc::new(Mutex::new(SlidingWindow::default()));
+            self.windows.insert(id.to_string(), window.clone());
+            window
+        });
+        let mut window = window.lock().unwrap_or_else(|e| e.into_inner());
+        f(&mut window)
+    }
+
+    /// Runs `f` on the window of `id`, or returns `None` if there is none.
+    pub fn read<R>(&self, id: &str, f: impl FnOnce(&mut SlidingWindow<T>) -> R) -> Option<R> {
+        let window = self.windows.get(&id.to_string())?;
+        let mut window = window.lock().unwrap_or_else(|e| e.into_inner());
+        Some(f(&mut window))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_expire_after_the_window() {
+        let mut window = SlidingWindow::default();
+        window.push(0, 'a');
+        window.push(1_000, 'b');
+        window.push(WINDOW_MS - 1, 'c');
+        assert_eq!(window.iter().collect::<String>(), "abc");
+
+        window.expire(WINDOW_MS);
+        assert_eq!(window.iter().collect::<String>(), "bc");
+        window.push(WINDOW_MS + 1_000, 'd');
+        assert_eq!(window.iter().collect::<String>(), "cd");
+        window.expire(3 * WINDOW_MS);
+        assert!(window.is_empty());
+    }
+
+    #[test]
+    fn retry_after_counts_until_the_oldest_entry_leaves() {
+        let mut window = SlidingWindow::default();
+        window.push(0, ());
+        window.push(30_000, ());
+        assert_eq!(window.retry_after_seconds(0), 60);
+        assert_eq!(window.retry_after_seconds(58_500), 2);
+        assert_eq!(window.retry_after_seconds(59_999), 1);
+    }
+
+    #[test]
+    fn cache_keeps_one_window_per_id() {
+        let cache = WindowCache::new(10);
+        assert_eq!(cache.read("a", |w: &mut SlidingWindow<u64>| w.len()), None);
+        cache.update("a", |w| w.push(0, 2));
+        cache.update("a", |w| w.push(1, 3));
+        cache.update("b", |w| w.push(1, 5));
+        assert_eq!(cache.read("a", |w| w.iter().sum::<u64>()), Some(5));
+        assert_eq!(cache.read("b", |w| w.len()), Some(1));
+    }
+}
@@ -0,0 +1,121 @@
+//! This module contains helpers for inspecting server-sent event (SSE) streams from
+//! providers. A stream can start with a 200 and still fail later, e.g. when the provider
+//! hits a rate limit mid-flight and reports it as an error event.
+
+use serde_json::Value;
+
+/// Events are buffered while waiting for the first content; past this size the stream
+/// is passed through even if nothing recognisable as content has arrived.
+pub const MAX_PEEK_BYTES: usize = 64 * 1024;
+
+/// Splits a byte stream into complete events.
+#[derive(Default)]
+pub struct EventScanner {
+    pending: Vec<u8>,
+}
+
+impl EventScanner {
+    /// Adds a chunk and returns the events it completed.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.pending.extend(chunk.iter().filter(|b| **b != b'\r'));
+        let mut events = Vec::new();
+        while let Some(end) = self.pending.windows(2).position(|w| w == b"\n\n") {
+            let event: Vec<u8> = self.pending.drain(..end + 2).collect();
+            events.push(String::from_utf8_lossy(&event[..end]).into_owned());
+        }
+        events
+    }
+}
+
+pub enum EventKind {
+    /// The provider reported an error. `status` is the HTTP status it corresponds to.
+    Error { status: u16, body: String },
+    /// The event carries generated tokens.
+    Content,
+    /// Comments, keep-alives, role-only deltas, `[DONE]` and anything unrecognised.
+    Other,
+}
+
+/// Whether the response is an event stream.
+pub fn is_event_stream(content_type: &str) -> bool {
+    content_type.contains("text/event-stream")
+}
+
+/// Classifies one event from an OpenAI, Gemini or Anthropic style stream.
+pub fn classify(event: &str) -> EventKind {
+    let mut event_name = "";
+    let mut data = String::new();
+    for line in event.lines() {
+        if let Some(name) = line.strip_prefix("event:") {
+            event_name = name.trim();
+        } else if let Some(value) = line.strip_prefix("data:") {
+            if !data.is_empty() {
+                data.push('\n');
+            }
+            data.push_str(value.trim_start());
+        }
+    }
+    if data.is_empty() || data == "[DONE]" {
+        return EventKind::Other;
+    }
+
+    let Ok(json) = serde_json::from_str::<Value>(&data) else {
+        return if event_name == "error" {
+            EventKind::Error { status: 500, body: data }
+        } else {
+            EventKind::Other
+        };
+    };
+    let error = json.get("error").filter(|e| !e.is_null());
+    if event_name == "error" || error.is_some() || json.get("type").and_then(Value::as_str) == Some("error") {
+        return EventKind::Error {
+            status: error_status(error.unwrap_or(&json)),
+            body: data,
+        };
+    }
+    if has_tokens(&json) {
+        EventKind::Content
+    } else {
+        EventKind::Other
+    }
+}
+
+/// Maps an error object to the HTTP status the provider would have used up front.
+fn error_status(error: &Value) -> u16 {
+    if let Some(code) = error.get("code").and_then(Value::as_u64).filter(|c| (400..600).contains(c)) {
+        return code as u16;
+    }
+    let text = error.to_string().to_lowercase();
+    if text.contains("rate_limit")
+        || text.contains("rate limit")
+        || text.contains("resource_exhausted")
+        || text.contains("quota")
+    {
+        429
+    } else {
+        500
+    }
+}
+
+fn has_tokens(json: &Value) -> bool {
+    let non_empty = |v: Option<&Value>| v.and_then(Value::as_str).is_some_and(|s| !s.is_empty());
+    // OpenAI-compatible chat and completion chunks.
+    if let Some(choices) = json.get("choices").and_then(Value::as_array) {
+        return choices.iter().any(|c| {
+            let delta = c.get("delta");
+            non_empty(delta.and_then(|d| d.get("content")))
+                || delta.and_then(|d| d.get("tool_calls")).is_some()
+                || non_empty(c.get("text"))
+        });
+    }
+    // Gemini.
+    if let Some(candidates) = json.get("candidates").and_then(Value::as_array) {
+        return candidates.iter().any(|c| {
+            c.pointer("/content/parts")
+                .and_then(Value::as_array)
+                .is_some_and(|parts| parts.iter().any(|p| non_empty(p.get("text")) || p.get("functionCall").is_some()))
+        });
+    }
+    // Anthropic.
+    json.get("type").and_then(Value::as_str) == Some("content_block_delta")
+}
@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use crate::usage::ClientQuota;
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
 pub enum ApiKeyStatus {
@@ -7,6 +8,34 @@ pub enum ApiKeyStatus {
     Blocked,
 }
 
+/// The billing tier of a key. Free keys are used first so paid quota is only spent once
+/// they are exhausted (see `settings::TierStrategy`).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyTier {
+    #[default]
+    Free,
+    Paid,
+}
+
+impl KeyTier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyTier::Free => "free",
+            KeyTier::Paid => "paid",
+        }
+    }
+
+    /// Parses a stored tier; anything but `paid` is free.
+    pub fn from_db(value: &str) -> Self {
+        if value == "paid" {
+            KeyTier::Paid
+        } else {
+            KeyTier::Free
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ApiKey {
     pub id: String,
@@ -21,8 +50,18 @@ pub struct ApiKey {
     pub created_at: u64,
     #[serde(default)]
     pub updated_at: u64,
+    /// The latency of the last attempt.
     #[serde(default)]
     pub latency_ms: i64,
+    /// Exponentially weighted moving average of the latency, 0 before the first attempt.
+    #[serde(default)]
+    pub latency_ewma_ms: i64,
+    /// Median latency from the key's histogram, 0 before the first attempt.
+    #[serde(default)]
+    pub latency_p50_ms: i64,
+    /// 95th percentile latency from the key's histogram, 0 before the first attempt.
+    #[serde(default)]
+    pub latency_p95_ms: i64,
     #[serde(default)]
     pub success_rate: f64,
     #[serde(default)]
@@ -31,6 +70,41 @@ pub struct ApiKey {
     pub last_checked_at: u64,
     #[serde(default)]
     pub last_succeeded_at: u64,
+    /// Estimated requests served over the last usage window (see `d1_storage::KEY_USAGE_WINDOW_SECONDS`).
+    #[serde(default)]
+    pub recent_requests: u64,
+    #[serde(default)]
+    pub tier: KeyTier,
+    /// Why the key was blocked, empty while it is active.
+    #[serde(default)]
+    pub block_reason: String,
+    #[serde(default)]
+    pub note: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Added to the health score, so a positive weight moves the key up the failover order.
+    #[serde(default)]
+    pub weight: i64,
+}
+
+/// The components of a key's health score, which orders the failover list: the higher
+/// `total`, the earlier the key is tried.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyScore {
+    /// Lower p95 latency scores higher.
+    pub latency_score: i64,
+    /// The success rate, scaled to 0-1000.
+    pub success_score: i64,
+    /// Grows with consecutive failures.
+    pub failure_penalty: i64,
+    /// For a success in the last five minutes, to break ties.
+    pub recent_success_bonus: i64,
+    /// Positive for keys that served more than their share recently, negative for idle ones.
+    pub fairness_penalty: i64,
+    /// The key's manual weight.
+    #[serde(default)]
+    pub weight: i64,
+    pub total: i64,
 }
 
 impl ApiKey {
@@ -39,3 +113,375 @@ impl ApiKey {
         self.model_coolings.get(model).cloned()
     }
 }
+
+/// Bounds of the metadata an operator can set on a key.
+pub const MAX_KEY_NOTE_CHARS: usize = 500;
+pub const MAX_KEY_TAGS: usize = 10;
+pub const MAX_KEY_TAG_CHARS: usize = 32;
+pub const MAX_KEY_WEIGHT: i64 = 10_000;
+
+/// Parses comma- or space-separated tags, lowercased and deduplicated. Tags may contain
+/// letters, digits, `-`, `_` and `.`.
+pub fn parse_tags(input: &str) -> Result<Vec<String>, String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in input.split(|c: char| c == ',' || c.is_whitespace()).filter(|t| !t.is_empty()) {
+        let tag = tag.to_lowercase();
+        if tag.len() > MAX_KEY_TAG_CHARS {
+            return Err(format!("tag '{}' is longer than {} characters", tag, MAX_KEY_TAG_CHARS));
+        }
+        if !tag.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+            return Err(format!("tag '{}' may only contain letters, digits, '-', '_' and '.'", tag));
+        }
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    if tags.len() > MAX_KEY_TAGS {
+        return Err(format!("a key can have at most {} tags", MAX_KEY_TAGS));
+    }
+    Ok(tags)
+}
+
+/// Stores tags as `,a,b,`, so a tag can be matched with `LIKE '%,tag,%'`.
+pub fn join_tags(tags: &[String]) -> String {
+    if tags.is_empty() {
+        String::new()
+    } else {
+        format!(",{},", tags.join(","))
+    }
+}
+
+/// The inverse of `join_tags`.
+pub fn split_tags(stored: &str) -> Vec<String> {
+    stored.split(',').filter(|t| !t.is_empty()).map(str::to_string).collect()
+}
+
+/// Upper bounds, in ms, of the latency histogram buckets. A last bucket takes everything
+/// slower; quantiles falling into it are reported as `LATENCY_OVERFLOW_MS`.
+pub const LATENCY_BUCKETS_MS: [i64; 15] = [
+    100, 200, 300, 500, 750, 1_000, 1_500, 2_000, 3_000, 5_000, 7_500, 10_000, 15_000, 20_000, 30_000,
+];
+pub const LATENCY_OVERFLOW_MS: i64 = 60_000;
+
+/// Attempt latencies counted in fixed buckets. Stored per key as a JSON array, where the
+/// counts decay so the histogram follows the key's recent behaviour (see
+/// `d1_storage::apply_key_metrics`); quantiles only depend on the relative counts.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    pub buckets: [u64; LATENCY_BUCKETS_MS.len() + 1],
+}
+
+impl LatencyHistogram {
+    /// Parses a stored histogram; anything unreadable is empty.
+    pub fn from_db(value: &str) -> Self {
+        let mut histogram = LatencyHistogram::default();
+        if let Ok(counts) = serde_json::from_str::<Vec<u64>>(value) {
+            for (bucket, count) in histogram.buckets.iter_mut().zip(counts) {
+                *bucket = count;
+            }
+        }
+        histogram
+    }
+
+    pub fn to_db(&self) -> String {
+        serde_json::to_string(&self.buckets.as_slice()).unwrap_or_default()
+    }
+
+    pub fn record(&mut self, latency_ms: i64) {
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&bound| latency_ms <= bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.buckets[bucket] += 1;
+    }
+
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets) {
+            *bucket += count;
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// The latency below which `quantile` (0-1) of the attempts fall, interpolated within
+    /// its bucket; 0 for an empty histogram.
+    pub fn quantile(&self, quantile: f64) -> i64 {
+        let total = self.total();
+        if total == 0 {
+            return 0;
+        }
+        let target = quantile.clamp(0.0, 1.0) * total as f64;
+        let mut below = 0.0;
+        for (i, &count) in self.buckets.iter().enumerate() {
+            let count = count as f64;
+            if count > 0.0 && below + count >= target {
+                let Some(&upper) = LATENCY_BUCKETS_MS.get(i) else {
+                    return LATENCY_OVERFLOW_MS;
+                };
+                let lower = if i == 0 { 0 } else { LATENCY_BUCKETS_MS[i - 1] };
+                return lower + ((upper - lower) as f64 * (target - below) / count).round() as i64;
+            }
+            below += count;
+        }
+        LATENCY_OVERFLOW_MS
+    }
+}
+
+/// Attempts with one key, merged so several of them are written in a single update.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyMetricsDelta {
+    pub successes: u64,
+    pub failures: u64,
+    /// Failures after the last success, or all of them when none succeeded.
+    pub trailing_failures: u64,
+    /// The latency of the last attempt.
+    pub latency_ms: Option<i64>,
+    /// The sum of all the attempts' latencies, for the moving average.
+    pub latency_total_ms: i64,
+    /// The attempts' latencies.
+    pub latency_histogram: LatencyHistogram,
+}
+
+impl KeyMetricsDelta {
+    pub fn attempt(is_success: bool, latency_ms: i64) -> Self {
+        let mut latency_histogram = LatencyHistogram::default();
+        latency_histogram.record(latency_ms);
+        KeyMetricsDelta {
+            successes: is_success as u64,
+            failures: !is_success as u64,
+            trailing_failures: !is_success as u64,
+            latency_ms: Some(latency_ms),
+            latency_total_ms: latency_ms,
+            latency_histogram,
+        }
+    }
+
+    pub fn attempts(&self) -> u64 {
+        self.successes + self.failures
+    }
+
+    /// Adds the attempts of `later`, which happened after these.
+    pub fn merge(&mut self, later: KeyMetricsDelta) {
+        self.trailing_failures = if later.successes > 0 {
+            later.trailing_failures
+        } else {
+            self.trailing_failures + later.trailing_failures
+        };
+        self.successes += later.successes;
+        self.failures += later.failures;
+        self.latency_ms = later.latency_ms.or(self.latency_ms);
+        self.latency_total_ms += later.latency_total_ms;
+        self.latency_histogram.merge(&later.latency_histogram);
+    }
+}
+
+/// Operator settings for a provider. Providers without a stored row use the defaults.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ProviderSettings {
+    pub provider: String,
+    /// Soft launch: keys are probed and scored by the scheduled tester, but live
+    /// traffic is not routed to them.
+    #[serde(default)]
+    pub observe_only: bool,
+    /// Requests per minute a single key may take; 0 means unlimited.
+    #[serde(default)]
+    pub rpm_limit: u64,
+    /// Tokens per minute a single key may take; 0 means unlimited.
+    #[serde(default)]
+    pub tpm_limit: u64,
+    #[serde(default)]
+    pub updated_at: u64,
+}
+
+/// A self-hosted or niche OpenAI-compatible endpoint registered by operators. Requests for
+/// it skip the AI Gateway and go straight to `base_url`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CustomProvider {
+    pub name: String,
+    pub base_url: String,
+    pub auth_header: String,
+    /// Put before the key in the auth header; empty sends the key alone.
+    pub auth_scheme: String,
+    /// A CSS color for the UI; empty uses the default.
+    pub color: String,
+    #[serde(default)]
+    pub updated_at: u64,
+}
+
+impl CustomProvider {
+    /// The value of the auth header for `key`.
+    pub fn auth_value(&self, key: &str) -> String {
+        if self.auth_scheme.is_empty() {
+            key.to_string()
+        } else {
+            format!("{} {}", self.auth_scheme, key)
+        }
+    }
+
+    /// The endpoint URL for `path`, the request path after the provider.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url.trim_end_matches('/'), path.trim_start_matches('/'))
+    }
+}
+
+/// What a client key may do in the UI and the admin API, each role including the ones
+/// before it: viewers browse keys and stats, operators manage keys, admins also manage
+/// clients and configuration. The master key acts as an admin.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Operator,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+
+    /// Parses a role name; `None` for anything else, e.g. the empty role of a key that
+    /// only proxies requests.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "viewer" => Some(Role::Viewer),
+            "operator" => Some(Role::Operator),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// A downstream client key, as seen by the routing and admin layers.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ClientKey {
+    pub id: String,
+    pub name: String,
+    /// The secret, only known when `create_client_key` issues it: D1 stores its SHA-256.
+    #[serde(default)]
+    pub key: String,
+    /// The start of the secret, to tell keys apart.
+    #[serde(default)]
+    pub key_prefix: String,
+    /// Providers this key may route to. Empty means all providers.
+    #[serde(default)]
+    pub allowed_providers: Vec<String>,
+    /// Models this key may use. Entries ending in `*` match by prefix. Empty means all models.
+    #[serde(default)]
+    pub allowed_models: Vec<String>,
+    pub revoked: bool,
+    #[serde(default)]
+    pub quota: ClientQuota,
+    /// Access to the UI and the admin API; `None` for keys that only proxy requests.
+    #[serde(default)]
+    pub role: Option<Role>,
+    #[serde(default)]
+    pub created_at: u64,
+    #[serde(default)]
+    pub last_used_at: u64,
+}
+
+impl ClientKey {
+    /// How the key is shown after it was issued.
+    pub fn key_preview(&self) -> String {
+        if self.key_prefix.is_empty() {
+            "****".to_string()
+        } else {
+            format!("{}...", self.key_prefix)
+        }
+    }
+
+    /// Checks whether this key is allowed to call the given provider and model.
+    /// A key limited to some models can't make requests whose model is unknown (empty).
+    pub fn allows(&self, provider: &str, model: &str) -> bool {
+        if self.revoked {
+            return false;
+        }
+        let provider_ok =
+            self.allowed_providers.is_empty() || self.allowed_providers.iter().any(|p| p == provider);
+        let model_ok = self.allowed_models.is_empty()
+            || (!model.is_empty()
+                && self.allowed_models.iter().any(|m| match m.strip_suffix('*') {
+                    Some(prefix) => model.starts_with(prefix),
+                    None => m == model,
+                }));
+        provider_ok && model_ok
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_key(allowed_models: &[&str]) -> ClientKey {
+        serde_json::from_value(serde_json::json!({
+            "id": "c1",
+            "name": "test",
+            "key": "sk-test",
+            "allowed_models": allowed_models,
+            "revoked": false,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn model_scopes_reject_unknown_models() {
+        let scoped = client_key(&["gemini-*"]);
+        assert!(scoped.allows("google-ai-studio", "gemini-2.0-flash"));
+        assert!(!scoped.allows("google-ai-studio", "v1beta/models"));
+        assert!(!scoped.allows("google-ai-studio", ""));
+
+        let unscoped = client_key(&[]);
+        assert!(unscoped.allows("google-ai-studio", ""));
+    }
+
+    fn tags(list: &[&str]) -> Vec<String> {
+        list.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn tags_are_split_lowercased_and_deduplicated() {
+        assert_eq!(parse_tags(" EU, paid  batch,eu ,,").unwrap(), tags(&["eu", "paid", "batch"]));
+        assert_eq!(parse_tags("").unwrap(), tags(&[]));
+        assert_eq!(parse_tags("v1.5 team_a team-b").unwrap(), tags(&["v1.5", "team_a", "team-b"]));
+    }
+
+    #[test]
+    fn tags_outside_the_charset_are_rejected() {
+        // `,` and `%` would break the stored encoding and the `LIKE` match.
+        for input in ["a%b", "a'b", "a/b", "tag;", "日本", "café"] {
+            assert!(parse_tags(input).is_err(), "{input} should be rejected");
+        }
+    }
+
+    #[test]
+    fn tag_limits_apply_after_deduplication() {
+        let longest = "a".repeat(MAX_KEY_TAG_CHARS);
+        assert_eq!(parse_tags(&longest).unwrap(), vec![longest.clone()]);
+        assert!(parse_tags(&format!("{longest}b")).is_err());
+
+        let most: Vec<String> = (0..MAX_KEY_TAGS).map(|i| format!("t{i}")).collect();
+        assert_eq!(parse_tags(&most.join(",")).unwrap(), most);
+        assert!(parse_tags(&format!("{},t1", most.join(","))).is_ok());
+        assert!(parse_tags(&format!("{},extra", most.join(","))).is_err());
+    }
+
+    #[test]
+    fn tags_are_stored_between_commas_and_round_trip() {
+        assert_eq!(join_tags(&tags(&["eu", "paid"])), ",eu,paid,");
+        assert_eq!(join_tags(&[]), "");
+        for list in [tags(&[]), tags(&["eu"]), tags(&["eu", "paid", "v1.5"])] {
+            assert_eq!(split_tags(&join_tags(&list)), list);
+        }
+        // Every stored tag can be matched on its own, as `SYNC_KEY_TAGS_SQL` and the filters expect.
+        let stored = join_tags(&tags(&["eu", "eu-west"]));
+        assert!(stored.contains(",eu,") && stored.contains(",eu-west,"));
+        assert!(!stored.contains(",eu-,"));
+    }
+}
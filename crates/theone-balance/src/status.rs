@@ -0,0 +1,94 @@
+//! This module backs `GET /api/status`, a per-provider summary for external dashboards
+//! and public status pages: key pool sizes, the circuit breaker, and the error rate and
+//! latency of recent traffic. It carries no key material and needs no credentials.
+
+use crate::{d1_storage, provider_breaker, AppState};
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use serde::Serialize;
+use std::sync::Arc;
+use worker::Date;
+
+/// The traffic the error rate is computed over.
+const WINDOW_SECONDS: i32 = 60 * 60;
+/// A provider failing at least this share of its recent requests is reported as degraded.
+const DEGRADED_ERROR_RATE: f64 = 0.5;
+
+#[derive(Serialize, Debug)]
+pub struct ProviderStatus {
+    pub provider: String,
+    /// `operational`, `degraded` or `down`.
+    pub status: &'static str,
+    pub active_keys: i64,
+    /// Active keys with at least one model on cooldown.
+    pub cooling_keys: i64,
+    pub blocked_keys: i64,
+    /// `closed`, `open` or `half_open`, as seen by the isolate that answered.
+    pub breaker: &'static str,
+    pub requests: i64,
+    /// Share of failed requests over the window; `None` without traffic.
+    pub error_rate: Option<f64>,
+    /// Average latency of the active keys, in milliseconds.
+    pub avg_latency_ms: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct StatusResponse {
+    /// Unix seconds.
+    pub generated_at: u64,
+    pub window_seconds: i32,
+    pub providers: Vec<ProviderStatus>,
+}
+
+#[worker::send]
+pub async fn status_handler(State(state): State<Arc<AppState>>) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e)).into_response(),
+    };
+    let stats = match d1_storage::get_provider_stats(&db, WINDOW_SECONDS).await {
+        Ok(stats) => stats,
+        Err(e) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load provider stats: {}", e)).into_response()
+        }
+    };
+
+    let providers = stats
+        .into_iter()
+        .map(|s| {
+            let breaker = provider_breaker::state(&s.provider);
+            let error_rate = s.success_rate().map(|rate| 1.0 - rate);
+            let status = if s.active_keys == 0 || breaker == "open" {
+                "down"
+            } else if breaker == "half_open"
+                || s.cooling_keys >= s.active_keys
+                || error_rate.is_some_and(|rate| rate >= DEGRADED_ERROR_RATE)
+            {
+                "degraded"
+            } else {
+                "operational"
+            };
+            ProviderStatus {
+                status,
+                active_keys: s.active_keys,
+                cooling_keys: s.cooling_keys,
+                blocked_keys: s.blocked_keys,
+                breaker,
+                requests: s.requests,
+                error_rate,
+                avg_latency_ms: s.avg_latency_ms,
+                provider: s.provider,
+            }
+        })
+        .collect();
+    let response = StatusResponse {
+        generated_at: Date::now().as_millis() / 1000,
+        window_seconds: WINDOW_SECONDS,
+        providers,
+    };
+    // Status pages poll; a short shared cache keeps that off D1.
+    ([(header::CACHE_CONTROL, "public, max-age=30")], Json(response)).into_response()
+}
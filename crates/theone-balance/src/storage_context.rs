@@ -0,0 +1,106 @@
+//! This module holds the per-request storage context: one D1 session shared by every
+//! storage call of a request, and the Toasty schema resolved once for its executors.
+//!
+//! All queries of a session are sequentially consistent, so a request reads its own
+//! writes even when D1 serves reads from replicas. The session's bookmark is returned in
+//! the `x-d1-bookmark` response header; a client sending it back on its next request
+//! continues from at least that point, so it also sees its earlier writes.
+//!
+//! Without a bookmark the session starts at the primary (`first-primary`), matching the
+//! behaviour of plain D1 calls. Set `D1_READ_REPLICAS=true` to start at any replica
+//! (`first-unconstrained`) instead.
+
+use crate::hybrid::{get_schema, HybridExecutor};
+use std::cell::OnceCell;
+use std::sync::Arc;
+use toasty_core::schema::db::Schema;
+use worker::js_sys::{Function, Reflect};
+use worker::wasm_bindgen::{JsCast, JsValue};
+use worker::{D1Database, Env, Result};
+
+pub const BOOKMARK_HEADER: &str = "x-d1-bookmark";
+
+pub struct StorageContext {
+    /// The D1 session. It is used through the `D1Database` API, which the session
+    /// object implements apart from `dump` and `exec`.
+    db: D1Database,
+    schema: Arc<Schema>,
+}
+
+fn call_method(target: &JsValue, name: &str, args: &[JsValue]) -> Result<JsValue> {
+    let method: Function = Reflect::get(target, &JsValue::from_str(name))?.dyn_into()?;
+    let args: worker::js_sys::Array = args.iter().collect();
+    Ok(method.apply(target, &args)?)
+}
+
+/// Client bookmarks are opaque to us; only their size and alphabet are checked.
+fn is_valid_bookmark(bookmark: &str) -> bool {
+    (1..=256).contains(&bookmark.len())
+        && bookmark.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+impl StorageContext {
+    /// Opens a session on the `DB` binding, continuing from `bookmark` when one is given.
+    /// Falls back to the plain binding when the runtime has no D1 sessions.
+    pub fn open(env: &Env, bookmark: Option<&str>) -> Result<Self> {
+        let db = env.d1("DB")?;
+        let read_replicas = env.var("D1_READ_REPLICAS").map(|v| v.to_string() == "true").unwrap_or(false);
+        let constraint = match bookmark.filter(|b| is_valid_bookmark(b)) {
+            Some(bookmark) => bookmark,
+            None if read_replicas => "first-unconstrained",
+            None => "first-primary",
+        };
+
+        let db = match call_method(db.as_ref(), "withSession", &[JsValue::from_str(constraint)]) {
+            Ok(session) if session.is_object() => D1Database::unchecked_from_js(session),
+            _ => db,
+        };
+        Ok(StorageContext {
+            db,
+            schema: get_schema().clone(),
+        })
+    }
+
+    pub fn db(&self) -> &D1Database {
+        &self.db
+    }
+
+    /// An executor on the request's session.
+    pub fn executor(&self) -> HybridExecutor<'_> {
+        HybridExecutor::new(&self.db, self.schema.clone())
+    }
+
+    /// The latest bookmark of the session, `None` before its first query or without sessions.
+    pub fn bookmark(&self) -> Option<String> {
+        call_method(self.db.as_ref(), "getBookmark", &[]).ok()?.as_string()
+    }
+}
+
+/// The storage context of a request, opened on first use.
+#[derive(Default)]
+pub struct LazyStorage {
+    bookmark: Option<String>,
+    context: OnceCell<StorageContext>,
+}
+
+impl LazyStorage {
+    pub fn new(bookmark: Option<String>) -> Self {
+        LazyStorage {
+            bookmark,
+            context: OnceCell::new(),
+        }
+    }
+
+    pub fn get(&self, env: &Env) -> Result<&StorageContext> {
+        if let Some(context) = self.context.get() {
+            return Ok(context);
+        }
+        let context = StorageContext::open(env, self.bookmark.as_deref())?;
+        Ok(self.context.get_or_init(|| context))
+    }
+
+    /// The session's bookmark, if the request used storage at all.
+    pub fn bookmark(&self) -> Option<String> {
+        self.context.get()?.bookmark()
+    }
+}
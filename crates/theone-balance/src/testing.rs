@@ -1,9 +1,13 @@
 //! This module contains logic for testing keys.
 
-use crate::{d1_storage, request, AppState};
+use crate::key_events::{self, KeyHealthEvent, KeyTransition};
+use crate::settings::Settings;
+use crate::{compat, d1_storage, request, util, AppState};
+use futures_util::future::join_all;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use tracing::{error, info};
+use tracing::{error, info, warn};
+use worker::{AbortSignal, D1Database, Date, Env};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TestResult {
@@ -12,8 +16,8 @@ pub struct TestResult {
     pub details: String,
 }
 
-async fn test_single_key(provider: &str, key: &str, model: &str) -> Result<(), worker::Error> {
-    let mut resp = request::send_native_chat_test_request(provider, key, model).await?;
+async fn test_single_key(provider: &str, key: &str, model: &str, signal: &AbortSignal) -> Result<(), worker::Error> {
+    let mut resp = request::send_native_chat_test_request(provider, key, model, Some(signal)).await?;
 
     if resp.status_code() == 200 {
         Ok(())
@@ -31,7 +35,7 @@ pub async fn test_keys(
     key_ids: Vec<String>,
 ) -> worker::Result<Vec<TestResult>> {
     info!("Testing {} keys for provider {}", key_ids.len(), provider);
-    let db = state.env.d1("DB")?;
+    let db = state.db()?;
 
     let keys_to_test = d1_storage::get_keys_by_ids(&db, key_ids)
         .await
@@ -39,13 +43,13 @@ pub async fn test_keys(
     let mut results = Vec::new();
 
     for key in keys_to_test {
-        info!("Testing key: {} for provider {}", key.key, provider);
+        info!("Testing key: {} for provider {}", util::partially_redact_key(&key.key), provider);
 
-        let test_result = test_single_key(provider, &key.key, model).await;
+        let test_result = test_single_key(provider, &key.key, model, &state.signal).await;
 
         let result = match test_result {
             Ok(_) => {
-                info!("Key {} passed test.", key.key);
+                info!("Key {} passed test.", util::partially_redact_key(&key.key));
                 TestResult {
                     key: key.key,
                     passed: true,
@@ -53,7 +57,7 @@ pub async fn test_keys(
                 }
             }
             Err(e) => {
-                error!("Key {} failed test: {}", key.key, e.to_string());
+                error!("Key {} failed test: {}", util::partially_redact_key(&key.key), e.to_string());
                 TestResult {
                     key: key.key,
                     passed: false,
@@ -66,3 +70,110 @@ pub async fn test_keys(
 
     Ok(results)
 }
+
+/// Probes one key with a request that costs no tokens (listing models) and returns
+/// whether it succeeded and how long it took.
+async fn probe_key(provider: &str, key: &str) -> Option<(bool, i64)> {
+    let endpoint = compat::model_list_endpoint(provider)?;
+    let start = Date::now().as_millis();
+    let result = request::fetch_provider_models(provider, key, endpoint, None).await;
+    let latency = (Date::now().as_millis() - start) as i64;
+    if let Err(e) = &result {
+        warn!(provider, "Probe failed: {}", e);
+    }
+    Some((result.is_ok(), latency))
+}
+
+/// Probes every active key of each observe-only provider and records the outcome in the
+/// key's health metrics, so the provider has a track record before it takes live traffic.
+pub async fn probe_observe_only_providers(db: &D1Database) -> worker::Result<()> {
+    let settings = d1_storage::list_provider_settings(db)
+        .await
+        .map_err(|e| worker::Error::from(e.to_string()))?;
+
+    for provider in settings.into_iter().filter(|s| s.observe_only).map(|s| s.provider) {
+        if compat::model_list_endpoint(&provider).is_none() {
+            warn!(provider, "No probe available for observe-only provider; skipping.");
+            continue;
+        }
+        let keys = d1_storage::get_active_keys(db, &provider)
+            .await
+            .map_err(|e| worker::Error::from(e.to_string()))?;
+        info!(provider, "Probing {} keys of observe-only provider.", keys.len());
+
+        let probes = keys.iter().map(|key| probe_key(&provider, &key.key));
+        let outcomes = join_all(probes).await;
+        for (key, outcome) in keys.iter().zip(outcomes) {
+            let Some((ok, latency)) = outcome else { continue };
+            if let Err(e) = d1_storage::update_key_metrics(db, &key.id, ok, latency).await {
+                error!(key_id = %key.id, "Failed to record probe result: {}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Keys blocked more recently than this aren't re-tested yet, so a key the provider just
+/// rejected isn't reactivated by a probe racing the block.
+const PROBATION_MIN_BLOCKED_SECONDS: i64 = 6 * 60 * 60;
+
+/// Re-tests a sample of keys a provider blocked and reactivates those that pass, so a key
+/// blocked during a provider incident or a billing lapse comes back on its own. Keys
+/// blocked by an operator are left alone, and a key stops being re-tested once it was
+/// reactivated `PROBATION_MAX_REACTIVATIONS` times, so one that keeps failing stays blocked.
+pub async fn probe_blocked_keys(env: &Env, db: &D1Database, settings: &Settings) -> worker::Result<()> {
+    if settings.probation_sample_size == 0 {
+        return Ok(());
+    }
+    let candidates = d1_storage::list_probation_candidates(
+        db,
+        PROBATION_MIN_BLOCKED_SECONDS,
+        settings.probation_max_reactivations,
+        settings.probation_sample_size,
+    )
+    .await
+    .map_err(|e| worker::Error::from(e.to_string()))?;
+    info!("Probation: re-testing {} blocked keys.", candidates.len());
+
+    let probes = candidates.iter().map(|key| probe_key(&key.provider, &key.key));
+    let outcomes = join_all(probes).await;
+    for (key, outcome) in candidates.iter().zip(outcomes) {
+        match outcome {
+            Some((true, _)) => {}
+            Some((false, _)) => continue,
+            None => {
+                warn!(provider = %key.provider, "No probe available for a blocked key; skipping.");
+                continue;
+            }
+        }
+        if let Err(e) = d1_storage::reactivate_key(db, &key.id, &key.provider).await {
+            error!(key_id = %key.id, "Failed to reactivate key after probation: {}", e);
+            continue;
+        }
+        let event = KeyHealthEvent::new(
+            KeyTransition::Reactivated,
+            &key.id,
+            &util::key_hash(&key.key),
+            &key.provider,
+            "",
+            &format!(
+                "passed probation after being blocked ({}); reactivation {} of {}",
+                key.block_reason,
+                key.reactivations + 1,
+                settings.probation_max_reactivations
+            ),
+        );
+        let history = d1_storage::KeyEventRecord {
+            key_id: key.id.clone(),
+            provider: key.provider.clone(),
+            event: "reactivated",
+            message: event.reason.clone(),
+            ..Default::default()
+        };
+        if let Err(e) = d1_storage::record_key_event(db, &history).await {
+            error!(key_id = %key.id, "Failed to record key event: {}", e);
+        }
+        key_events::emit(env, event).await;
+    }
+    Ok(())
+}
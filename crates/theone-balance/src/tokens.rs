@@ -0,0 +1,99 @@
+//! This module estimates the prompt tokens of a chat request for
+//! `POST /api/compat/tokens/count`, so clients can budget a request before sending it.
+//!
+//! Without shipping a BPE vocabulary, the estimate follows how tiktoken's `cl100k` and
+//! `o200k` tokenizers work: text is first split into pieces by the same pre-tokenization
+//! rules (words with their leading space, digit groups of up to three, punctuation runs,
+//! newlines), and each piece is counted as the tokens BPE typically merges it into. Chat
+//! messages add OpenAI's documented framing overhead. Gemini models are counted exactly
+//! by the provider's `countTokens` instead (see `request::fetch_gemini_token_count`).
+
+use crate::models::OpenAiChatMessage;
+
+/// Framing tokens around every message (`<|start|>{role}\n ... <|end|>`).
+const TOKENS_PER_MESSAGE: u64 = 3;
+/// Every reply is primed with `<|start|>assistant<|message|>`.
+const TOKENS_PER_REPLY: u64 = 3;
+/// ASCII letters a single word token commonly covers; longer words split.
+const LETTERS_PER_TOKEN: u64 = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Class {
+    Letter,
+    Digit,
+    Newline,
+    Space,
+    Other,
+}
+
+fn class(c: char) -> Class {
+    if c.is_alphabetic() || c == '\'' {
+        Class::Letter
+    } else if c.is_numeric() {
+        Class::Digit
+    } else if c == '\n' || c == '\r' {
+        Class::Newline
+    } else if c.is_whitespace() {
+        Class::Space
+    } else {
+        Class::Other
+    }
+}
+
+/// Tokens of one pre-tokenized piece of a single class.
+fn piece_tokens(class: Class, piece: &[char]) -> u64 {
+    let len = piece.len() as u64;
+    match class {
+        Class::Letter => {
+            let ascii = piece.iter().filter(|c| c.is_ascii()).count() as u64;
+            // Non-Latin scripts (CJK in particular) are about a token per character.
+            ascii.div_ceil(LETTERS_PER_TOKEN) + (len - ascii)
+        }
+        Class::Digit => len.div_ceil(3),
+        Class::Other => len.div_ceil(2),
+        Class::Newline | Class::Space => 1,
+    }
+}
+
+/// Estimates the tokens of a piece of text.
+pub fn count_text_tokens(text: &str) -> u64 {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let current = class(chars[i]);
+        let mut end = i + 1;
+        while end < chars.len() && class(chars[end]) == current {
+            end += 1;
+        }
+        // A single space before a word or punctuation is part of that piece, not a token.
+        let absorbed = current == Class::Space
+            && end - i == 1
+            && chars[i] == ' '
+            && end < chars.len()
+            && matches!(class(chars[end]), Class::Letter | Class::Other);
+        if !absorbed {
+            tokens += piece_tokens(current, &chars[i..end]);
+        }
+        i = end;
+    }
+    tokens
+}
+
+/// Estimates the prompt tokens of a chat completion request with these messages.
+pub fn count_chat_tokens(messages: &[OpenAiChatMessage]) -> u64 {
+    let message_tokens: u64 = messages
+        .iter()
+        .map(|message| {
+            let mut tokens = TOKENS_PER_MESSAGE + count_text_tokens(&message.role);
+            if let Some(content) = &message.content {
+                tokens += count_text_tokens(content);
+            }
+            for call in &message.tool_calls {
+                tokens += count_text_tokens(&call.function.name) + count_text_tokens(&call.function.arguments);
+            }
+            tokens
+        })
+        .sum();
+    message_tokens + TOKENS_PER_REPLY
+}
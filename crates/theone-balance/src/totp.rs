@@ -0,0 +1,132 @@
+//! This module implements the time-based one-time passwords (RFC 6238) of the optional
+//! second login factor: 6-digit codes of 30-second steps, HMAC-SHA1 as authenticator apps
+//! expect. Codes of the previous and next step are accepted for clock drift, and each
+//! step only once. The login flow around it lives in `auth`.
+
+use crate::{auth, d1_storage};
+use serde::Deserialize;
+use worker::{D1Database, Date, Result};
+
+const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+/// Shown as the account's issuer in authenticator apps.
+const ISSUER: &str = "One Balance";
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// The TOTP secret of the master key or a client key, as stored in `totp_secrets`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Enrollment {
+    /// `master`, or the client key id.
+    pub subject: String,
+    /// Base32.
+    pub secret: String,
+    /// When the first code was confirmed; 0 while the secret is being provisioned.
+    pub enabled_at: i64,
+    pub last_step: i64,
+}
+
+impl Enrollment {
+    pub fn enabled(&self) -> bool {
+        self.enabled_at > 0
+    }
+}
+
+/// A new random 160-bit secret, base32-encoded.
+pub fn generate_secret() -> String {
+    base32_encode(&rand::random::<[u8; 20]>())
+}
+
+/// The `otpauth://` URI authenticator apps read from the provisioning QR code.
+pub fn provisioning_uri(secret: &str, account: &str) -> String {
+    let issuer = url::form_urlencoded::byte_serialize(ISSUER.as_bytes()).collect::<String>().replace('+', "%20");
+    let account = url::form_urlencoded::byte_serialize(account.as_bytes()).collect::<String>().replace('+', "%20");
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}"
+    )
+}
+
+/// Checks `code` against the enrollment and marks its step as used, so the same code is
+/// rejected when it is entered again.
+pub async fn check(db: &D1Database, enrollment: &Enrollment, code: &str) -> Result<bool> {
+    let code = code.trim().replace(' ', "");
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return Ok(false);
+    }
+    let Some(secret) = base32_decode(&enrollment.secret) else {
+        return Ok(false);
+    };
+
+    let current_step = (Date::now().as_millis() / 1000 / STEP_SECONDS) as i64;
+    for step in [current_step - 1, current_step, current_step + 1] {
+        if step <= enrollment.last_step {
+            continue;
+        }
+        let hmac = auth::hmac("SHA-1", &secret, &step.to_be_bytes()).await?;
+        if format!("{:0width$}", truncate(&hmac), width = DIGITS as usize) == code {
+            return Ok(d1_storage::use_totp_step(db, &enrollment.subject, step).await?);
+        }
+    }
+    Ok(false)
+}
+
+/// The dynamic truncation of RFC 4226, reduced to `DIGITS` digits.
+fn truncate(hmac: &[u8]) -> u32 {
+    let offset = (hmac[hmac.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([hmac[offset] & 0x7f, hmac[offset + 1], hmac[offset + 2], hmac[offset + 3]]);
+    binary % 10u32.pow(DIGITS)
+}
+
+/// RFC 4648 base32 without padding, as used in `otpauth://` URIs.
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+/// Decodes base32, ignoring case, spaces and padding.
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in encoded.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = BASE32_ALPHABET.iter().position(|&a| a as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base32_matches_rfc_4648() {
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("mzxw6ytboi======"), Some(b"foobar".to_vec()));
+        assert_eq!(base32_decode("MZXW1"), None);
+    }
+
+    #[test]
+    fn truncation_matches_rfc_4226() {
+        let hmac = [
+            0x1f, 0x86, 0x98, 0x69, 0x0e, 0x02, 0xca, 0x16, 0x61, 0x85, 0x50, 0xef, 0x7f, 0x19, 0xda, 0x8e, 0x94, 0x5b,
+            0x55, 0x5a,
+        ];
+        assert_eq!(truncate(&hmac), 872921);
+    }
+}
@@ -0,0 +1,89 @@
+//! This module decides how a request reaches its provider. By default upstream calls go
+//! to the AI Gateway over public fetch. A provider can instead be served by another Worker
+//! through a Service Binding, a provider shim for private adapters or lower latency:
+//! `SERVICE_BINDINGS="my-provider:MY_SHIM,openai:OPENAI_SHIM"` maps providers to bindings
+//! declared under `services` in the wrangler config.
+//!
+//! The shim receives the request the gateway would, with the key's auth header set, at
+//! `https://service-binding/{provider}/{path}`.
+
+use worker::js_sys::{Function, Promise, Reflect};
+use worker::wasm_bindgen::{JsCast, JsValue};
+use worker::wasm_bindgen_futures::JsFuture;
+use worker::{AbortSignal, Env, Fetch, Fetcher, Request, Response, Result};
+
+/// Base URL of requests sent through a service binding; only the path reaches the shim.
+pub const SERVICE_BASE_URL: &str = "https://service-binding/";
+
+/// Where the upstream calls of a request go.
+enum Target {
+    /// The AI Gateway, over public fetch.
+    Gateway,
+    /// A Worker bound as a service.
+    Service(Fetcher),
+}
+
+/// The upstream of a request and the signal that cancels its calls.
+pub struct Upstream {
+    target: Target,
+    signal: AbortSignal,
+}
+
+/// The binding configured for `provider` in `SERVICE_BINDINGS`, if any.
+pub fn binding_name(env: &Env, provider: &str) -> Option<String> {
+    let config = env.var("SERVICE_BINDINGS").ok()?.to_string();
+    config.split(',').find_map(|entry| {
+        let (name, binding) = entry.split_once(':')?;
+        (name.trim() == provider && !binding.trim().is_empty()).then(|| binding.trim().to_string())
+    })
+}
+
+impl Upstream {
+    /// The upstream for `provider`. A configured binding that doesn't exist is an error
+    /// rather than a silent fallback to the gateway.
+    pub fn for_provider(env: &Env, provider: &str, signal: &AbortSignal) -> Result<Self> {
+        let target = match binding_name(env, provider) {
+            Some(binding) => Target::Service(env.service(&binding).map_err(|e| {
+                worker::Error::RustError(format!(
+                    "Service binding '{}' for provider '{}' is not available: {}",
+                    binding, provider, e
+                ))
+            })?),
+            None => Target::Gateway,
+        };
+        Ok(Upstream {
+            target,
+            signal: signal.clone(),
+        })
+    }
+
+    /// Public fetch, e.g. to the native endpoints in local development.
+    pub fn public(signal: &AbortSignal) -> Self {
+        Upstream {
+            target: Target::Gateway,
+            signal: signal.clone(),
+        }
+    }
+
+    /// The request's signal; calls are also cancelled when it fires.
+    pub fn signal(&self) -> &AbortSignal {
+        &self.signal
+    }
+
+    /// Sends the request, cancelling it when `signal` fires.
+    pub async fn send(&self, req: Request, signal: &AbortSignal) -> Result<Response> {
+        match &self.target {
+            Target::Gateway => Fetch::Request(req).send_with_signal(signal).await,
+            Target::Service(fetcher) => {
+                // `Fetcher::fetch_request` takes no init, so the signal is passed to the
+                // binding's `fetch` directly.
+                let init = worker::web_sys::RequestInit::new();
+                init.set_signal(Some(signal));
+                let fetch: Function = Reflect::get(fetcher.as_ref(), &JsValue::from_str("fetch"))?.dyn_into()?;
+                let promise: Promise = fetch.call2(fetcher.as_ref(), req.inner(), &init)?.dyn_into()?;
+                let resp: worker::web_sys::Response = JsFuture::from(promise).await?.dyn_into()?;
+                Ok(Response::from(resp))
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! This module contains usage and cost accounting for proxied requests.
+
+use phf::phf_map;
+use serde::{Deserialize, Serialize};
+
+/// A single billable upstream call, as recorded in the `usage_events` table.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct UsageRecord {
+    /// The client key id, or `None` for the master key.
+    pub client_id: Option<String>,
+    pub provider: String,
+    pub model: String,
+    pub key_id: String,
+    pub endpoint: String,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub images: u64,
+    /// Cost in millionths of a US dollar.
+    pub cost_micros: u64,
+    /// Whether the token counts were estimated because the provider didn't report usage.
+    pub estimated: bool,
+}
+
+/// Per-image list prices in millionths of a USD, keyed by model name.
+/// Models that are not listed are recorded with zero cost.
+static IMAGE_PRICES_MICROS: phf::Map<&'static str, u64> = phf_map! {
+    "dall-e-2" => 20_000,
+    "dall-e-3" => 40_000,
+    "gpt-image-1" => 42_000,
+    "grok-2-image" => 70_000,
+};
+
+/// Returns the cost of generating `images` images with the given model.
+pub fn image_cost_micros(model: &str, images: u64) -> u64 {
+    IMAGE_PRICES_MICROS.get(model).copied().unwrap_or(0) * images
+}
+
+/// Counts the images in an OpenAI-style `images/generations` response.
+/// A non-JSON (binary) body is counted as a single image.
+pub fn count_generated_images(content_type: &str, body: &[u8]) -> u64 {
+    if !content_type.contains("json") {
+        return 1;
+    }
+    serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|v| v.get("data").and_then(|d| d.as_array()).map(|d| d.len() as u64))
+        .unwrap_or(0)
+}
+
+/// Per-token list prices as (input, output) in millionths of a USD per million tokens,
+/// i.e. the USD price per million tokens scaled by 1_000_000.
+static TOKEN_PRICES_MICROS_PER_MTOK: phf::Map<&'static str, (u64, u64)> = phf_map! {
+    "gpt-4o" => (2_500_000, 10_000_000),
+    "gpt-4o-mini" => (150_000, 600_000),
+    "gpt-4.1" => (2_000_000, 8_000_000),
+    "gpt-4.1-mini" => (400_000, 1_600_000),
+    "gemini-2.5-pro" => (1_250_000, 10_000_000),
+    "gemini-2.5-flash" => (300_000, 2_500_000),
+    "claude-sonnet-4-0" => (3_000_000, 15_000_000),
+    "claude-3-5-haiku-latest" => (800_000, 4_000_000),
+};
+
+/// Whether the model has a per-token or per-image list price.
+pub fn has_list_price(model: &str) -> bool {
+    TOKEN_PRICES_MICROS_PER_MTOK.contains_key(model) || IMAGE_PRICES_MICROS.contains_key(model)
+}
+
+/// Returns the cost of a call with the given token counts. Unknown models cost nothing.
+pub fn token_cost_micros(model: &str, prompt_tokens: u64, completion_tokens: u64) -> u64 {
+    let Some((input, output)) = TOKEN_PRICES_MICROS_PER_MTOK.get(model) else {
+        return 0;
+    };
+    (prompt_tokens * input + completion_tokens * output) / 1_000_000
+}
+
+/// Reads (prompt, completion) token counts from a provider response body.
+/// Understands the OpenAI, Anthropic and Gemini usage shapes.
+pub fn extract_token_usage(body: &serde_json::Value) -> Option<(u64, u64)> {
+    let get = |v: &serde_json::Value, key: &str| v.get(key).and_then(|n| n.as_u64());
+    if let Some(usage) = body.get("usage") {
+        if let (Some(p), Some(c)) = (get(usage, "prompt_tokens"), get(usage, "completion_tokens")) {
+            return Some((p, c));
+        }
+        if let (Some(p), Some(c)) = (get(usage, "input_tokens"), get(usage, "output_tokens")) {
+            return Some((p, c));
+        }
+        // Embeddings only report prompt tokens.
+        if let Some(p) = get(usage, "prompt_tokens") {
+            return Some((p, 0));
+        }
+    }
+    if let Some(meta) = body.get("usageMetadata") {
+        let p = get(meta, "promptTokenCount").unwrap_or(0);
+        let c = get(meta, "candidatesTokenCount").unwrap_or(0);
+        return Some((p, c));
+    }
+    None
+}
+
+// region: --- Token Estimation
+
+/// JSON keys whose string values are metadata rather than model input or output.
+const NON_TEXT_KEYS: &[&str] = &[
+    "model", "role", "id", "object", "type", "finish_reason", "finishReason", "encoding_format",
+    "response_format", "user", "name", "tool_call_id", "mimeType", "mime_type", "url",
+];
+
+/// Approximates the token count of a piece of text without a tokenizer.
+/// ASCII text averages about four characters per token for common BPE vocabularies;
+/// other scripts (CJK in particular) are closer to one token per character.
+pub fn estimate_tokens(text: &str) -> u64 {
+    let (ascii, other) = text.chars().fold((0u64, 0u64), |(ascii, other), c| {
+        if c.is_ascii() {
+            (ascii + 1, other)
+        } else {
+            (ascii, other + 1)
+        }
+    });
+    ascii.div_ceil(4) + other
+}
+
+/// Appends every text-bearing string in a JSON value to `out`, skipping metadata fields.
+fn collect_text(value: &serde_json::Value, out: &mut String) {
+    match value {
+        serde_json::Value::String(s) => {
+            out.push_str(s);
+            out.push(' ');
+        }
+        serde_json::Value::Array(items) => items.iter().for_each(|v| collect_text(v, out)),
+        serde_json::Value::Object(map) => {
+            for (key, v) in map {
+                if !NON_TEXT_KEYS.contains(&key.as_str()) {
+                    collect_text(v, out);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Estimates the prompt tokens of a JSON request body. Non-JSON bodies (e.g. audio uploads)
+/// are not estimated.
+pub fn estimate_prompt_tokens(request_body: &[u8]) -> u64 {
+    let Ok(body) = serde_json::from_slice::<serde_json::Value>(request_body) else {
+        return 0;
+    };
+    let mut text = String::new();
+    collect_text(&body, &mut text);
+    estimate_tokens(&text)
+}
+
+/// Estimates the completion tokens of a JSON response body from its generated text,
+/// in the OpenAI (`choices`), Gemini (`candidates`) or Anthropic (`content`) shape.
+pub fn estimate_completion_tokens(response_body: &serde_json::Value) -> u64 {
+    let mut text = String::new();
+    for key in ["choices", "candidates", "content"] {
+        if let Some(generated) = response_body.get(key) {
+            collect_text(generated, &mut text);
+        }
+    }
+    estimate_tokens(&text)
+}
+
+// endregion: --- Token Estimation
+
+/// Returns a short endpoint label for a request path, e.g. `chat/completions`.
+pub fn endpoint_label(rest_resource: &str, provider: &str) -> String {
+    rest_resource
+        .strip_prefix("compat/")
+        .or_else(|| rest_resource.strip_prefix(&format!("{}/", provider)))
+        .unwrap_or(rest_resource)
+        .to_string()
+}
+
+// region: --- Client Quotas
+
+/// Limits attached to a client key. A value of zero means "unlimited".
+/// Requests are counted over the last 24 hours; tokens and spend over the last 30 days.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ClientQuota {
+    #[serde(default)]
+    pub max_requests_per_day: u64,
+    #[serde(default)]
+    pub max_tokens_per_month: u64,
+    #[serde(default)]
+    pub max_budget_micros: u64,
+}
+
+/// A client's consumption over the quota windows.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct ClientUsage {
+    #[serde(default)]
+    pub requests_today: u64,
+    #[serde(default)]
+    pub tokens_this_month: u64,
+    #[serde(default)]
+    pub cost_micros_this_month: u64,
+}
+
+/// Which limit a client has run into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuotaViolation {
+    RequestsPerDay,
+    TokensPerMonth,
+    Budget,
+}
+
+impl QuotaViolation {
+    /// HTTP status for the violation: 402 for an exhausted budget, 429 otherwise.
+    pub fn status(&self) -> u16 {
+        match self {
+            QuotaViolation::Budget => 402,
+            _ => 429,
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            QuotaViolation::RequestsPerDay => "Daily request quota exceeded for this API key.",
+            QuotaViolation::TokensPerMonth => "Monthly token quota exceeded for this API key.",
+            QuotaViolation::Budget => "Spending budget exhausted for this API key.",
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            QuotaViolation::Budget => "budget_exceeded",
+            _ => "quota_exceeded",
+        }
+    }
+}
+
+impl ClientQuota {
+    pub fn is_unlimited(&self) -> bool {
+        *self == ClientQuota::default()
+    }
+
+    /// Returns the first limit the given usage has reached, if any.
+    pub fn check(&self, usage: &ClientUsage) -> Option<QuotaViolation> {
+        if self.max_budget_micros > 0 && usage.cost_micros_this_month >= self.max_budget_micros {
+            return Some(QuotaViolation::Budget);
+        }
+        if self.max_requests_per_day > 0 && usage.requests_today >= self.max_requests_per_day {
+            return Some(QuotaViolation::RequestsPerDay);
+        }
+        if self.max_tokens_per_month > 0 && usage.tokens_this_month >= self.max_tokens_per_month {
+            return Some(QuotaViolation::TokensPerMonth);
+        }
+        None
+    }
+}
+
+// endregion: --- Client Quotas
@@ -1,7 +1,10 @@
 //! Utility functions for request handling, parsing, and data manipulation.
 
+use crate::d1_storage;
+use crate::state::strategy::ClientKey;
+use crate::AppState;
 use rand::seq::SliceRandom;
-use tracing::warn;
+use tracing::{error, warn};
 use worker::{Env, Request, Result};
 
 /// Extracts the API key from the Authorization header of an axum request.
@@ -48,6 +51,78 @@ pub fn is_valid_auth_key(key: &str, env: &Env) -> bool {
     }
 }
 
+/// Compares a key against the master AUTH_KEY without logging.
+fn matches_master_key(key: &str, env: &Env) -> bool {
+    !key.is_empty()
+        && env
+            .secret("AUTH_KEY")
+            .map(|master_key| master_key.to_string() == key)
+            .unwrap_or(false)
+}
+
+/// The identity behind a downstream request's bearer token.
+#[derive(Clone, Debug)]
+pub enum Caller {
+    /// The master AUTH_KEY, which may call any provider and model.
+    Master,
+    /// An issued client key, limited to its own scopes.
+    Client(ClientKey),
+}
+
+impl Caller {
+    /// Checks whether this caller may route to the given provider and model.
+    pub fn allows(&self, provider: &str, model: &str) -> bool {
+        match self {
+            Caller::Master => true,
+            Caller::Client(client) => client.allows(provider, model),
+        }
+    }
+
+    /// Returns the client key id, or `None` for the master key.
+    pub fn client_id(&self) -> Option<&str> {
+        match self {
+            Caller::Master => None,
+            Caller::Client(client) => Some(&client.id),
+        }
+    }
+}
+
+/// Resolves a bearer token to either the master key or an active client key.
+pub async fn authenticate(key: &str, state: &AppState) -> Option<Caller> {
+    if key.is_empty() {
+        return None;
+    }
+    if matches_master_key(key, &state.env) {
+        return Some(Caller::Master);
+    }
+
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => {
+            error!("Failed to get D1 binding for client key lookup: {}", e);
+            return None;
+        }
+    };
+    match d1_storage::find_client_key_via_cache(&db, key).await {
+        Ok(Some(client)) if !client.revoked => Some(Caller::Client(client)),
+        Ok(Some(client)) => {
+            warn!(client_id = %client.id, "Auth Check Failed: client key has been revoked");
+            None
+        }
+        Ok(None) => {
+            warn!(
+                "Auth Check Failed: Provided key='{}' matches neither the master key nor a client key",
+                partially_redact_key(key)
+            );
+            None
+        }
+        Err(e) => {
+            error!("Failed to look up client key: {}", e);
+            None
+        }
+    }
+}
+
 /// Extracts the provider and model from the request body or the resource path.
 pub fn extract_provider_and_model(
     body_bytes: &[u8],
@@ -56,9 +131,10 @@ pub fn extract_provider_and_model(
     // Try to get from body first
     if let Ok(json_body) = serde_json::from_slice::<serde_json::Value>(body_bytes) {
         if let Some(model_str) = json_body.get("model").and_then(|m| m.as_str()) {
-            let parts: Vec<&str> = model_str.split('/').collect();
-            if parts.len() >= 2 {
-                return Ok((parts[0].to_string(), parts[1].to_string()));
+            // Split on the first slash only: model names may contain slashes themselves
+            // (e.g. `workers-ai/@cf/baai/bge-reranker-base`).
+            if let Some((provider, model)) = model_str.split_once('/') {
+                return Ok((provider.to_string(), model.to_string()));
             }
         }
     }
@@ -84,11 +160,112 @@ pub fn shuffle_keys<T>(keys: &mut [T]) {
     keys.shuffle(&mut rand::rng());
 }
 
-/// Redacts the middle of a key for safe logging.
-pub fn partially_redact_key(key: &str) -> String {
-    if key.len() < 8 {
-        return "key-too-short".to_string();
+/// Returns a stable, non-reversible label for an API key (64-bit FNV-1a, hex), used to group
+/// analytics datapoints per key and to detect duplicate keys without comparing secrets.
+pub fn key_hash(key: &str) -> String {
+    let hash = key.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+// Lives in `key_format`, which the sync CLI shares and so can't depend on this module.
+pub use crate::key_format::partially_redact_key;
+
+/// Returns the boundary of a `multipart/form-data` content type, if it is one.
+pub fn multipart_boundary(content_type: &str) -> Option<String> {
+    let mut parts = content_type.split(';');
+    if !parts.next()?.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    parts
+        .filter_map(|p| p.trim().strip_prefix("boundary="))
+        .map(|b| b.trim_matches('"').to_string())
+        .next()
+}
+
+/// Splits a multipart body into its raw parts (headers + content), excluding delimiters.
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut parts = Vec::new();
+    let mut positions = Vec::new();
+    let mut i = 0;
+    while i + delimiter.len() <= body.len() {
+        if body[i..].starts_with(&delimiter) {
+            positions.push(i);
+            i += delimiter.len();
+        } else {
+            i += 1;
+        }
+    }
+    for window in positions.windows(2) {
+        let start = window[0] + delimiter.len();
+        let end = window[1];
+        let part = &body[start..end];
+        let part = part.strip_prefix(b"\r\n").unwrap_or(part);
+        let part = part.strip_suffix(b"\r\n").unwrap_or(part);
+        parts.push(part);
+    }
+    parts
+}
+
+/// Returns the field name from a part's Content-Disposition header, and the offset of its content.
+fn multipart_part_name(part: &[u8]) -> Option<(String, usize)> {
+    let header_end = part.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let headers = std::str::from_utf8(&part[..header_end]).ok()?;
+    let name = headers
+        .lines()
+        .find(|l| l.to_ascii_lowercase().starts_with("content-disposition"))?
+        .split(';')
+        .filter_map(|p| p.trim().strip_prefix("name="))
+        .map(|n| n.trim_matches('"').to_string())
+        .next()?;
+    Some((name, header_end + 4))
+}
+
+/// Reads a text field from a multipart body.
+pub fn get_multipart_text_field(body: &[u8], boundary: &str, field: &str) -> Option<String> {
+    split_multipart(body, boundary).into_iter().find_map(|part| {
+        let (name, offset) = multipart_part_name(part)?;
+        if name == field {
+            String::from_utf8(part[offset..].to_vec()).ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// Rewrites the value of a text field in a multipart body, keeping the same boundary.
+pub fn replace_multipart_text_field(body: &[u8], boundary: &str, field: &str, value: &str) -> Vec<u8> {
+    let parts = split_multipart(body, boundary);
+    let mut out = Vec::with_capacity(body.len());
+    for part in parts {
+        out.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        match multipart_part_name(part) {
+            Some((name, offset)) if name == field => {
+                out.extend_from_slice(&part[..offset]);
+                out.extend_from_slice(value.as_bytes());
+            }
+            _ => out.extend_from_slice(part),
+        }
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    out
+}
+
+/// Extracts the provider and model from the `model` field of a multipart body
+/// (e.g. `openai/whisper-1`), as used by the audio compat routes.
+pub fn extract_provider_and_model_from_multipart(
+    body_bytes: &[u8],
+    boundary: &str,
+) -> Result<(String, String)> {
+    let model_str = get_multipart_text_field(body_bytes, boundary, "model")
+        .ok_or_else(|| worker::Error::from("Multipart request is missing the 'model' field."))?;
+    match model_str.trim().split_once('/') {
+        Some((provider, model)) if !provider.is_empty() && !model.is_empty() => {
+            Ok((provider.to_string(), model.to_string()))
+        }
+        _ => Err("The 'model' field must be in the form 'provider/model'.".into()),
     }
-    let len = key.len();
-    format!("{}...{}", &key[..4], &key[len-4..])
 }
@@ -0,0 +1,191 @@
+//! This module contains optional validators for successful (2xx) JSON responses. A
+//! provider sometimes answers with a 200 that is useless to the client, e.g. empty
+//! content or invalid JSON although JSON mode was requested; a failed validation is
+//! treated like a transient error and the request fails over to the next key.
+//!
+//! Validators are configured per model with `RESPONSE_VALIDATORS`, a `;`-separated list
+//! of `model-pattern:validator,validator` rules. Patterns ending in `*` match by prefix
+//! and the first matching rule wins, e.g. `gemini-2.5-*:non_empty,json_mode;*:json_mode`.
+
+use serde_json::Value;
+use tracing::warn;
+use worker::Env;
+
+pub trait ResponseValidator {
+    /// The name used in `RESPONSE_VALIDATORS`.
+    fn name(&self) -> &'static str;
+    /// Checks a response body against the request that produced it.
+    fn validate(&self, request: &Value, response: &Value) -> Result<(), String>;
+}
+
+/// Rejects completions without any text or tool call.
+pub struct NonEmptyContent;
+
+/// When the request asked for JSON output, rejects completions whose text isn't valid JSON.
+pub struct JsonMode;
+
+/// The generated texts of an OpenAI, Gemini or Anthropic style completion, or `None` if
+/// the body isn't a completion (embeddings, model lists, ...).
+fn completion_texts(response: &Value) -> Option<Vec<&str>> {
+    if let Some(choices) = response.get("choices").and_then(Value::as_array) {
+        return Some(
+            choices
+                .iter()
+                .filter_map(|c| {
+                    c.pointer("/message/content")
+                        .or_else(|| c.get("text"))
+                        .and_then(Value::as_str)
+                })
+                .collect(),
+        );
+    }
+    if let Some(candidates) = response.get("candidates").and_then(Value::as_array) {
+        return Some(
+            candidates
+                .iter()
+                .filter_map(|c| c.pointer("/content/parts").and_then(Value::as_array))
+                .flatten()
+                .filter_map(|p| p.get("text").and_then(Value::as_str))
+                .collect(),
+        );
+    }
+    if response.get("type").and_then(Value::as_str) == Some("message") {
+        return Some(
+            response
+                .get("content")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(|b| b.get("text").and_then(Value::as_str))
+                .collect(),
+        );
+    }
+    None
+}
+
+fn has_tool_calls(response: &Value) -> bool {
+    let openai = response
+        .get("choices")
+        .and_then(Value::as_array)
+        .is_some_and(|choices| {
+            choices.iter().any(|c| {
+                c.pointer("/message/tool_calls")
+                    .and_then(Value::as_array)
+                    .is_some_and(|calls| !calls.is_empty())
+            })
+        });
+    let gemini = response
+        .get("candidates")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|c| c.pointer("/content/parts").and_then(Value::as_array))
+        .flatten()
+        .any(|p| p.get("functionCall").is_some());
+    let anthropic = response
+        .get("content")
+        .and_then(Value::as_array)
+        .is_some_and(|blocks| blocks.iter().any(|b| b.get("type").and_then(Value::as_str) == Some("tool_use")));
+    openai || gemini || anthropic
+}
+
+fn json_mode_requested(request: &Value) -> bool {
+    let openai = request
+        .pointer("/response_format/type")
+        .and_then(Value::as_str)
+        .is_some_and(|t| t == "json_object" || t == "json_schema");
+    let gemini = request
+        .pointer("/generationConfig/responseMimeType")
+        .and_then(Value::as_str)
+        == Some("application/json");
+    openai || gemini
+}
+
+impl ResponseValidator for NonEmptyContent {
+    fn name(&self) -> &'static str {
+        "non_empty"
+    }
+
+    fn validate(&self, _request: &Value, response: &Value) -> Result<(), String> {
+        let Some(texts) = completion_texts(response) else {
+            return Ok(());
+        };
+        if texts.iter().all(|t| t.trim().is_empty()) && !has_tool_calls(response) {
+            return Err("the provider returned an empty completion".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl ResponseValidator for JsonMode {
+    fn name(&self) -> &'static str {
+        "json_mode"
+    }
+
+    fn validate(&self, request: &Value, response: &Value) -> Result<(), String> {
+        if !json_mode_requested(request) {
+            return Ok(());
+        }
+        let Some(texts) = completion_texts(response) else {
+            return Ok(());
+        };
+        for text in texts {
+            if let Err(e) = serde_json::from_str::<Value>(text) {
+                return Err(format!("the completion is not valid JSON although JSON mode was requested: {}", e));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn validator_by_name(name: &str) -> Option<Box<dyn ResponseValidator>> {
+    match name {
+        "non_empty" => Some(Box::new(NonEmptyContent)),
+        "json_mode" => Some(Box::new(JsonMode)),
+        _ => None,
+    }
+}
+
+fn pattern_matches(pattern: &str, model: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => model.starts_with(prefix),
+        None => pattern == model,
+    }
+}
+
+/// Returns the validators configured for a model, empty when none apply.
+pub fn validators_for(env: &Env, model: &str) -> Vec<Box<dyn ResponseValidator>> {
+    let Ok(config) = env.var("RESPONSE_VALIDATORS") else {
+        return Vec::new();
+    };
+    let config = config.to_string();
+    let Some((_, names)) = config
+        .split(';')
+        .filter_map(|rule| rule.split_once(':'))
+        .find(|(pattern, _)| pattern_matches(pattern.trim(), model))
+    else {
+        return Vec::new();
+    };
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let validator = validator_by_name(name);
+            if validator.is_none() {
+                warn!("Ignoring unknown response validator '{}'", name);
+            }
+            validator
+        })
+        .collect()
+}
+
+/// Runs every validator, returning the first failure.
+pub fn validate(validators: &[Box<dyn ResponseValidator>], request: &Value, response: &Value) -> Result<(), String> {
+    for validator in validators {
+        validator
+            .validate(request, response)
+            .map_err(|e| format!("{} validation failed: {}", validator.name(), e))?;
+    }
+    Ok(())
+}
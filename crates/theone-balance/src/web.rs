@@ -1,12 +1,18 @@
 //! This module contains all UI-related logic, including Axum handlers and Maud templates.
 
-use crate::{d1_storage, state::strategy::ApiKey, testing, util, AppState};
+use crate::{
+    admin_limits, auth,
+    d1_storage::{self, ErrorClassCount, KeyListPage, ProviderDashboardStats, TagCount},
+    key_format,
+    state::strategy::{self, ApiKey, ApiKeyStatus, ClientKey, CustomProvider, KeyTier, ProviderSettings, Role},
+    testing, totp, util, AppState,
+};
 use axum::{
     body::Bytes,
     extract::{Form, FromRef, FromRequestParts, Path, Query, State},
-    http::{request::Parts, StatusCode},
+    http::{request::Parts, HeaderMap, Method, StatusCode},
     response::{IntoResponse, Json, Redirect, Response},
-    routing::{get, post},
+    routing::{get, patch, post},
     Router,
 };
 use base64::{engine::general_purpose, Engine as _};
@@ -15,8 +21,9 @@ use phf::phf_map;
 use serde::{Deserialize, Deserializer};
 use std::fmt;
 use std::sync::Arc;
-use time::Duration;
+use tower_cookies::cookie::SameSite;
 use tower_cookies::{Cookie, Cookies};
+use worker::send::SendFuture;
 use worker::Date;
 use tracing::{error, info, warn};
 
@@ -50,6 +57,9 @@ static PROVIDER_CONFIGS: phf::Map<&'static str, ProviderConfig> = phf_map! {
     "replicate" => ProviderConfig { color: "from-slate-500 to-gray-600", icon: "⧉", bg_color: "from-slate-50 to-gray-50" },
 };
 
+const CUSTOM_PROVIDER_COLOR: &str = "from-slate-400 to-slate-600";
+const CUSTOM_PROVIDER_BG_COLOR: &str = "from-slate-50 to-gray-100";
+
 // --- Router ---
 
 pub fn ui_router() -> Router<Arc<AppState>> {
@@ -59,12 +69,29 @@ pub fn ui_router() -> Router<Arc<AppState>> {
             "/login",
             get(get_login_page_handler).post(post_login_handler),
         )
+        .route(
+            "/login/2fa",
+            get(get_second_factor_page_handler).post(post_second_factor_handler),
+        )
+        .route("/logout", post(post_logout_handler))
+        .route("/account/2fa", get(get_totp_page_handler).post(post_totp_handler))
         .route(
             "/keys/{provider}",
             get(get_keys_list_page_handler).post(post_keys_list_handler),
         )
         .route("/api/keys/add/{provider}", post(post_add_keys_api_handler))
         .route("/api/keys/{id}/coolings", get(get_key_coolings_handler))
+        .route("/api/keys/{id}/scores", get(get_key_scores_handler))
+        .route("/api/keys/{id}/events", get(get_key_events_handler))
+        .route("/api/keys/{id}/reveal", get(get_key_reveal_handler))
+        .route("/api/keys/{id}", patch(patch_key_handler))
+        .route(
+            "/clients",
+            get(get_clients_page_handler).post(post_clients_handler),
+        )
+        .route("/dashboard", get(get_dashboard_page_handler))
+        .route("/keys/{provider}/availability", get(get_availability_page_handler))
+        .route("/keys/{provider}/table", get(get_keys_table_fragment_handler))
 }
 
 // --- Handlers ---
@@ -75,32 +102,125 @@ pub struct LoginForm {
     auth_key: String,
 }
 
-pub async fn get_login_page_handler() -> Markup {
-    page_layout(login_page())
+pub async fn get_login_page_handler(cookies: Cookies) -> Markup {
+    page_layout(Theme::from_cookies(&cookies), login_page())
 }
 
+#[worker::send]
 pub async fn post_login_handler(
     cookies: Cookies,
     State(state): State<Arc<AppState>>,
     Form(form): Form<LoginForm>,
 ) -> impl IntoResponse {
-    if util::is_valid_auth_key(&form.auth_key, &state.env) {
-        let cookie = Cookie::build(("auth_key", form.auth_key))
-            .path("/")
-            .http_only(true)
-            .same_site(tower_cookies::cookie::SameSite::Strict)
-            .max_age(Duration::days(365));
-        cookies.add(cookie.into());
-        Redirect::to("/").into_response()
-    } else {
-        (StatusCode::FORBIDDEN, "Invalid auth key").into_response()
+    // The master key, or a client key with a role.
+    let Some(principal) = auth::authenticate_key(&form.auth_key, &state).await else {
+        return (StatusCode::FORBIDDEN, "Invalid auth key").into_response();
+    };
+    match auth::login(&state, &principal, &cookies).await {
+        Ok(auth::LoginStep::SessionStarted) => Redirect::to("/").into_response(),
+        Ok(auth::LoginStep::CodeRequired) => Redirect::to("/login/2fa").into_response(),
+        Err(e) => {
+            error!("Failed to start session: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to start session: {}", e)).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SecondFactorForm {
+    code: String,
+    remember: Option<String>,
+}
+
+#[worker::send]
+pub async fn get_second_factor_page_handler(cookies: Cookies, State(state): State<Arc<AppState>>) -> Response {
+    if auth::pending_challenge(&state.env, &cookies).await.is_none() {
+        return Redirect::to("/login").into_response();
+    }
+    page_layout(
+        Theme::from_cookies(&cookies),
+        second_factor_page(state.settings.totp_remember_days, None),
+    )
+    .into_response()
+}
+
+/// The code step of a login with a second factor. Attempts count against the stricter
+/// admin rate limit, so codes can't be guessed within the challenge's lifetime.
+#[worker::send]
+pub async fn post_second_factor_handler(
+    cookies: Cookies,
+    State(state): State<Arc<AppState>>,
+    Form(form): Form<SecondFactorForm>,
+) -> Response {
+    let Some(principal) = auth::pending_challenge(&state.env, &cookies).await else {
+        return Redirect::to("/login").into_response();
+    };
+    if let Some(resp) = code_rate_limit(&state, &principal.subject) {
+        return resp;
+    }
+    let remember = form.remember.is_some();
+    match auth::complete_login(&state, &principal, &form.code, remember, &cookies).await {
+        Ok(true) => Redirect::to("/").into_response(),
+        Ok(false) => (
+            StatusCode::UNAUTHORIZED,
+            page_layout(
+                Theme::from_cookies(&cookies),
+                second_factor_page(state.settings.totp_remember_days, Some("Invalid code, try again.")),
+            ),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to complete login: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to complete login: {}", e)).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LogoutForm {
+    csrf_token: Option<String>,
+}
+
+/// Revokes the session and returns to the login page. Without a valid session there is
+/// nothing to protect, so only the cookies are cleared.
+#[worker::send]
+pub async fn post_logout_handler(
+    cookies: Cookies,
+    State(state): State<Arc<AppState>>,
+    Form(form): Form<LogoutForm>,
+) -> Response {
+    if let Some(session_id) = auth::from_cookies(&state, &cookies).await.and_then(|p| p.session_id) {
+        if !auth::check_csrf(&state.env, &session_id, form.csrf_token.as_deref()).await {
+            return (StatusCode::FORBIDDEN, "Invalid or missing CSRF token.").into_response();
+        }
     }
+    if let Err(e) = auth::logout(&state, &cookies).await {
+        error!("Failed to revoke session: {}", e);
+    }
+    Redirect::to("/login").into_response()
 }
 // endregion: --- Login Handlers
 
 // region: --- Provider Page Handlers
-pub async fn get_providers_page_handler(_layout: PageLayout) -> Markup {
-    page_layout(providers_page())
+#[worker::send]
+pub async fn get_providers_page_handler(State(state): State<Arc<AppState>>, layout: PageLayout) -> Markup {
+    let (custom_providers, tag_counts) = match state.db() {
+        Ok(db) => (
+            d1_storage::list_custom_providers(&db).await.unwrap_or_else(|e| {
+                error!("Failed to list custom providers: {}", e);
+                Vec::new()
+            }),
+            d1_storage::list_tag_counts(&db, None).await.unwrap_or_else(|e| {
+                error!("Failed to count key tags: {}", e);
+                Vec::new()
+            }),
+        ),
+        Err(e) => {
+            error!("Database error: {}", e);
+            (Vec::new(), Vec::new())
+        }
+    };
+    page_layout(layout.theme, providers_page(&custom_providers, &tag_counts, &layout.csrf_token))
 }
 // endregion: --- Provider Page Handlers
 
@@ -114,6 +234,77 @@ pub struct KeysListParams {
     sort_order: Option<String>,
 }
 
+impl KeysListParams {
+    /// The status tab, search, page and sort order, with their defaults filled in.
+    fn resolve(&self) -> (&str, &str, usize, &str, &str) {
+        (
+            self.status.as_deref().unwrap_or("active"),
+            self.q.as_deref().unwrap_or(""),
+            self.page.unwrap_or(1),
+            self.sort_by.as_deref().unwrap_or(""),
+            self.sort_order.as_deref().unwrap_or("desc"),
+        )
+    }
+}
+
+/// What the keys list is rendered for: the provider, the status tab, search, page and sort
+/// order its links carry, the tag counts of its filter and the CSRF token of its forms.
+struct KeysPageCtx<'a> {
+    provider: &'a str,
+    status: &'a str,
+    q: &'a str,
+    page: usize,
+    page_size: usize,
+    sort_by: &'a str,
+    sort_order: &'a str,
+    tag_counts: &'a [TagCount],
+    csrf_token: &'a str,
+}
+
+impl<'a> KeysPageCtx<'a> {
+    fn new(provider: &'a str, params: &'a KeysListParams, tag_counts: &'a [TagCount], csrf_token: &'a str) -> Self {
+        let (status, q, page, sort_by, sort_order) = params.resolve();
+        Self {
+            provider,
+            status,
+            q,
+            page,
+            page_size: 20,
+            sort_by,
+            sort_order,
+            tag_counts,
+            csrf_token,
+        }
+    }
+}
+
+/// Lists the page of keys the params select, as shown by both the keys page and its table fragment.
+async fn list_keys_for_params(
+    db: &worker::D1Database,
+    provider: &str,
+    params: &KeysListParams,
+) -> Result<(Vec<ApiKey>, i32), Response> {
+    let (status, q, page, sort_by, sort_order) = params.resolve();
+    let list = KeyListPage { status, q, page, page_size: 20, sort_by, sort_order };
+    d1_storage::list_keys(db, provider, &list)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to list keys: {}", e),
+            )
+                .into_response()
+        })
+}
+
+/// The tags of a provider's keys for the tag filter, which the keys page can do without.
+async fn list_tag_counts_or_empty(db: &worker::D1Database, provider: &str) -> Vec<TagCount> {
+    d1_storage::list_tag_counts(db, Some(provider)).await.unwrap_or_else(|e| {
+        error!("Failed to count key tags: {}", e);
+        Vec::new()
+    })
+}
+
 // #[axum::debug_handler]
 #[worker::send]
 pub async fn get_keys_list_page_handler(
@@ -121,7 +312,7 @@ pub async fn get_keys_list_page_handler(
     Path(provider): Path<String>,
     cookies: Cookies,
     Query(params): Query<KeysListParams>,
-    _layout: PageLayout,
+    layout: PageLayout,
 ) -> Response {
     let mut test_results: Option<Vec<testing::TestResult>> = None;
     if let Some(cookie) = cookies.get("test_results") {
@@ -134,12 +325,7 @@ pub async fn get_keys_list_page_handler(
         cookies.remove(Cookie::named("test_results"));
     }
 
-    let status: &str = params.status.as_deref().unwrap_or("active");
-    let q: &str = params.q.as_deref().unwrap_or("");
-    let page = params.page.unwrap_or(1);
-    let sort_by: &str = params.sort_by.as_deref().unwrap_or("");
-    let sort_order: &str = params.sort_order.as_deref().unwrap_or("desc");
-    let db = match state.env.d1("DB") {
+    let db = match state.db() {
         Ok(db) => db,
         Err(e) => {
             return (
@@ -150,32 +336,18 @@ pub async fn get_keys_list_page_handler(
         }
     };
 
-    let (keys, total) =
-        // match d1_storage::list_keys(&db, &provider, status, q, page, 20, sort_by, sort_order).await
-        match d1_storage::list_keys(&db, provider.as_str(), &status, &q, page, 20, sort_by, sort_order).await
-        {
-            Ok(data) => data,
-            Err(e) => {
-                return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("Failed to list keys: {}", e),
-                )
-                    .into_response()
-            }
-        };
+    let (keys, total) = match list_keys_for_params(&db, &provider, &params).await {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
 
-    let content = keys_list_page(
-        provider.as_str(),
-        status,
-        q,
-        keys,
-        total,
-        page,
-        20,
-        sort_by,
-        sort_order,
-        test_results,
-    );
+    let provider_settings = d1_storage::get_provider_settings_via_cache(&db, &provider)
+        .await
+        .unwrap_or_default();
+    let tag_counts = list_tag_counts_or_empty(&db, &provider).await;
+
+    let ctx = KeysPageCtx::new(&provider, &params, &tag_counts, &layout.csrf_token);
+    let content = keys_list_page(&ctx, keys, total, test_results, &provider_settings);
     //(
     //    StatusCode::OK,
     //    format!(
@@ -184,7 +356,36 @@ pub async fn get_keys_list_page_handler(
     //    ),
     //)
     // .into_response()
-    (StatusCode::OK, page_layout(content)).into_response()
+    (StatusCode::OK, page_layout(layout.theme, content)).into_response()
+}
+
+/// Renders just the keys table, which `script.js` swaps in place when switching status tabs,
+/// paging or searching, instead of reloading the whole page.
+#[worker::send]
+pub async fn get_keys_table_fragment_handler(
+    State(state): State<Arc<AppState>>,
+    Path(provider): Path<String>,
+    Query(params): Query<KeysListParams>,
+    layout: PageLayout,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Database error: {}", e),
+            )
+                .into_response()
+        }
+    };
+    let (keys, total) = match list_keys_for_params(&db, &provider, &params).await {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let tag_counts = list_tag_counts_or_empty(&db, &provider).await;
+    let ctx = KeysPageCtx::new(&provider, &params, &tag_counts, &layout.csrf_token);
+    let table = build_keys_table(&ctx, keys, total);
+    (StatusCode::OK, table).into_response()
 }
 
 // When a form has multiple checkboxes with the same name, it can be submitted
@@ -255,7 +456,7 @@ pub struct KeysListForm {
 //    let sort_by = params.sort_by.as_deref().unwrap_or("");
 //    let sort_order = params.sort_order.as_deref().unwrap_or("desc");
 //
-//    let db = match state.env.d1("DB") {
+//    let db = match state.db() {
 //        Ok(db) => db,
 //        Err(e) => {
 //            return (
@@ -292,6 +493,7 @@ pub async fn post_keys_list_handler(
     State(state): State<Arc<AppState>>,
     Path(provider): Path<String>,
     cookies: Cookies,
+    layout: PageLayout,
     body: axum::body::Bytes,
 ) -> impl IntoResponse {
     let pairs: Vec<(String, String)> = match serde_urlencoded::from_bytes(&body) {
@@ -310,6 +512,9 @@ pub async fn post_keys_list_handler(
     let mut keys: Option<String> = None;
     let mut key_id: Vec<String> = Vec::new();
     let mut model: Option<String> = None;
+    let mut rpm_limit: Option<u64> = None;
+    let mut tpm_limit: Option<u64> = None;
+    let mut csrf_token: Option<String> = None;
 
     for (key, value) in pairs {
         match key.as_str() {
@@ -317,9 +522,15 @@ pub async fn post_keys_list_handler(
             "keys" => keys = Some(value),
             "key_id[]" => key_id.push(value),
             "model" => model = Some(value),
+            "rpm_limit" => rpm_limit = value.trim().parse().ok(),
+            "tpm_limit" => tpm_limit = value.trim().parse().ok(),
+            "csrf_token" => csrf_token = Some(value),
             _ => {} // Ignore other fields
         }
     }
+    if let Some(resp) = layout.check_csrf(csrf_token.as_deref()) {
+        return resp;
+    }
 
     if action.is_empty() {
         let error_message = "Form is missing 'action' field".to_string();
@@ -333,9 +544,18 @@ pub async fn post_keys_list_handler(
         key_id,
     };
     info!("Form data: {:?}", form);
+    // The provider's settings are configuration rather than keys.
+    let is_provider_setting = matches!(form.action.as_str(), "observe-only-on" | "observe-only-off" | "rate-limits");
+    if is_provider_setting && layout.role < Role::Admin {
+        return (StatusCode::FORBIDDEN, "Changing provider settings requires the admin role.").into_response();
+    }
     if form.action == "add" {
         if let Some(keys_str) = form.keys {
-            let db = state.env.d1("DB").unwrap();
+            let rejected = key_format::check_all(&provider, &keys_str);
+            if !rejected.is_empty() {
+                return (StatusCode::BAD_REQUEST, format!("No keys were added: {}", rejected.join("; "))).into_response();
+            }
+            let db = state.db().unwrap();
             match d1_storage::add_keys(&db, &provider, &keys_str).await {
                 Ok(_) => (), // All good
                 Err(e) => {
@@ -347,9 +567,41 @@ pub async fn post_keys_list_handler(
                 }
             }
         }
+    } else if form.action == "preview" {
+        let keys_str = form.keys.unwrap_or_default();
+        return (
+            StatusCode::OK,
+            page_layout(
+                layout.theme,
+                key_preview_page(&provider, &keys_str, &layout.csrf_token),
+            ),
+        )
+            .into_response();
+    } else if form.action == "add-detected" {
+        if let Some(keys_str) = form.keys {
+            let groups = key_format::group_by_provider(&keys_str, &provider);
+            let rejected: Vec<String> = groups
+                .iter()
+                .flat_map(|(group, keys)| keys.iter().filter_map(|key| key_format::check(group, key).err()))
+                .collect();
+            if !rejected.is_empty() {
+                return (StatusCode::BAD_REQUEST, format!("No keys were added: {}", rejected.join("; "))).into_response();
+            }
+            let db = state.db().unwrap();
+            for (group, keys) in &groups {
+                if let Err(e) = d1_storage::add_keys(&db, group, &keys.join("\n")).await {
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to add {} keys: {}", group, e),
+                    )
+                        .into_response();
+                }
+                info!(provider = %group, count = keys.len(), "Added keys with detected provider.");
+            }
+        }
     } else if form.action == "delete" {
         if !form.key_id.is_empty() {
-            let db = state.env.d1("DB").unwrap();
+            let db = state.db().unwrap();
             match d1_storage::delete_keys(&db, form.key_id).await {
                 Ok(_) => (), // All good
                 Err(e) => {
@@ -361,6 +613,51 @@ pub async fn post_keys_list_handler(
                 }
             }
         }
+    } else if form.action == "block" || form.action == "activate" {
+        if !form.key_id.is_empty() {
+            let db = state.db().unwrap();
+            let status = if form.action == "block" {
+                ApiKeyStatus::Blocked
+            } else {
+                ApiKeyStatus::Active
+            };
+            if let Err(e) = d1_storage::bulk_update_status(&db, form.key_id, status).await {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to {} keys: {}", form.action, e),
+                )
+                    .into_response();
+            }
+        }
+    } else if form.action == "tier-free" || form.action == "tier-paid" {
+        if !form.key_id.is_empty() {
+            let db = state.db().unwrap();
+            let tier = if form.action == "tier-paid" { KeyTier::Paid } else { KeyTier::Free };
+            if let Err(e) = d1_storage::bulk_update_tier(&db, form.key_id, tier).await {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to change the tier of keys: {}", e),
+                )
+                    .into_response();
+            }
+        }
+    } else if form.action == "restore" || form.action == "purge" {
+        if !form.key_id.is_empty() {
+            let db = state.db().unwrap();
+            let result = if form.action == "restore" {
+                d1_storage::restore_keys(&db, form.key_id).await
+            } else {
+                d1_storage::purge_keys(&db, form.key_id).await
+            };
+            if let Err(e) = result {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to {} keys: {}", form.action, e),
+                )
+                    .into_response();
+            }
+        }
+        return Redirect::to(&format!("/keys/{}?status=trash", provider)).into_response();
     } else if form.action == "test" {
         // Only allow testing for the google-ai-studio provider for now.
         if provider != "google-ai-studio" {
@@ -369,6 +666,9 @@ pub async fn post_keys_list_handler(
             return Redirect::to(&format!("/keys/{}", provider)).into_response();
         }
 
+        if let Some(resp) = sensitive_rate_limit(&state, &layout) {
+            return resp;
+        }
         if !form.key_id.is_empty() {
             let test_model = model.as_deref().unwrap_or("gemini-2.5-pro");
             let results = testing::test_keys(state, &provider, test_model, form.key_id)
@@ -385,8 +685,27 @@ pub async fn post_keys_list_handler(
                 cookies.add(Cookie::new("test_results", encoded));
             }
         }
+    } else if form.action == "observe-only-on" || form.action == "observe-only-off" {
+        let db = state.db().unwrap();
+        let observe_only = form.action == "observe-only-on";
+        if let Err(e) = d1_storage::set_provider_observe_only(&db, &provider, observe_only).await {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to update provider settings: {}", e),
+            )
+                .into_response();
+        }
+    } else if form.action == "rate-limits" {
+        let db = state.db().unwrap();
+        if let Err(e) = d1_storage::set_provider_rate_limits(&db, &provider, rpm_limit, tpm_limit).await {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to update provider settings: {}", e),
+            )
+                .into_response();
+        }
     } else if form.action == "delete-all-blocked" {
-        let db = state.env.d1("DB").unwrap();
+        let db = state.db().unwrap();
         match d1_storage::delete_all_blocked(&db, &provider).await {
             Ok(_) => (), // All good
             Err(e) => {
@@ -416,7 +735,7 @@ pub async fn post_keys_list_handler(
 //    let sort_by = params.sort_by.as_deref().unwrap_or("");
 //    let sort_order = params.sort_order.as_deref().unwrap_or("desc");
 //
-//    let db = match state.env.d1("DB") {
+//    let db = match state.db() {
 //        Ok(db) => db,
 //        Err(e) => {
 //            return (
@@ -448,14 +767,13 @@ pub async fn post_keys_list_handler(
 //}
 // endregion: --- Keys List Page Handlers
 
-// region: --- API Handlers
+// region: --- Dashboard Page Handlers
 #[worker::send]
-pub async fn post_add_keys_api_handler(
+pub async fn get_dashboard_page_handler(
     State(state): State<Arc<AppState>>,
-    Path(provider): Path<String>,
-    body: String,
-) -> impl IntoResponse {
-    let db = match state.env.d1("DB") {
+    layout: PageLayout,
+) -> Response {
+    let db = match state.db() {
         Ok(db) => db,
         Err(e) => {
             return (
@@ -465,24 +783,32 @@ pub async fn post_add_keys_api_handler(
                 .into_response()
         }
     };
-    match d1_storage::add_keys(&db, &provider, &body).await {
-        Ok(_) => (StatusCode::OK, "Keys added successfully").into_response(),
-        Err(e) => (
+
+    let stats = d1_storage::get_provider_stats(&db, 24 * 60 * 60).await;
+    let errors = d1_storage::get_recent_error_classes(&db).await;
+    match (stats, errors) {
+        (Ok(stats), Ok(errors)) => (
+            StatusCode::OK,
+            page_layout(layout.theme, dashboard_page(stats, errors)),
+        )
+            .into_response(),
+        (Err(e), _) | (_, Err(e)) => (
             StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to add keys: {}", e),
+            format!("Failed to load dashboard stats: {}", e),
         )
             .into_response(),
     }
 }
+// endregion: --- Dashboard Page Handlers
 
-// region: --- API Handlers
+// region: --- Availability Page Handlers
 #[worker::send]
-pub async fn get_key_coolings_handler(
+pub async fn get_availability_page_handler(
     State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
-    _layout: PageLayout,
+    Path(provider): Path<String>,
+    layout: PageLayout,
 ) -> Response {
-    let db = match state.env.d1("DB") {
+    let db = match state.db() {
         Ok(db) => db,
         Err(e) => {
             return (
@@ -493,58 +819,578 @@ pub async fn get_key_coolings_handler(
         }
     };
 
-    match d1_storage::get_key_coolings(&db, &id).await {
-        Ok(Some(key)) => (StatusCode::OK, Json(key)).into_response(),
-        Ok(None) => (StatusCode::NOT_FOUND, "Key not found").into_response(),
+    let keys = match d1_storage::list_all_keys(&db, Some(&provider)).await {
+        Ok(keys) => keys,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to load keys: {}", e),
+            )
+                .into_response()
+        }
+    };
+    // Models no key has cooled down for yet come from the cached model list, if any.
+    let catalog = match d1_storage::get_model_catalog(&db, &provider).await {
+        Ok(catalog) => catalog.map(|(models, _)| models).unwrap_or_default(),
+        Err(e) => {
+            error!("Failed to load the model catalog of {}: {}", provider, e);
+            Vec::new()
+        }
+    };
+
+    (
+        StatusCode::OK,
+        page_layout(layout.theme, availability_page(&provider, &keys, &catalog)),
+    )
+        .into_response()
+}
+// endregion: --- Availability Page Handlers
+
+// region: --- Client Keys Page Handlers
+#[worker::send]
+pub async fn get_clients_page_handler(
+    State(state): State<Arc<AppState>>,
+    cookies: Cookies,
+    layout: PageLayout,
+) -> Response {
+    // A freshly issued key is passed through a one-shot cookie so it can be shown exactly once.
+    let mut new_key: Option<String> = None;
+    if let Some(cookie) = cookies.get("new_client_key") {
+        if let Ok(decoded) = general_purpose::STANDARD.decode(cookie.value()) {
+            new_key = String::from_utf8(decoded).ok();
+        }
+        cookies.remove(Cookie::build(("new_client_key", "")).path("/").into());
+    }
+
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Database error: {}", e),
+            )
+                .into_response()
+        }
+    };
+
+    match d1_storage::list_client_keys(&db).await {
+        Ok(clients) => (
+            StatusCode::OK,
+            page_layout(
+                layout.theme,
+                clients_page(clients, new_key, &layout.csrf_token),
+            ),
+        )
+            .into_response(),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to get key coolings: {}", e),
+            format!("Failed to list client keys: {}", e),
         )
             .into_response(),
     }
 }
-// endregion: --- API Handlers
 
-// --- Page Components (Maud HTML) ---
+#[derive(Deserialize, Debug)]
+pub struct ClientsForm {
+    action: String,
+    id: Option<String>,
+    name: Option<String>,
+    allowed_providers: Option<String>,
+    allowed_models: Option<String>,
+    role: Option<String>,
+    csrf_token: Option<String>,
+}
 
-// region: --- Layout
-fn page_layout(content: Markup) -> Markup {
-    html! {
-        (DOCTYPE)
-        html lang="en" {
-            head {
-                meta charset="UTF-8";
-                meta name="viewport" content="width=device-width, initial-scale=1.0";
-                title { "One Balance" }
-                link rel="icon" href="data:image/svg+xml,<svg xmlns=%22http://www.w3.org/2000/svg%22 viewBox=%220 0 100 100%22><text y=%22.9em%22 font-size=%2290%22>⚖️</text></svg>";
-                script src="https://cdn.tailwindcss.com" {}
-                style { (PreEscaped(include_str!("web/style.css"))) }
-                script { (PreEscaped(include_str!("web/script.js"))) }
-            }
-            body class="breathing-bg min-h-screen text-gray-900 flex flex-col" {
-                main class="container mx-auto mt-12 px-6 max-w-7xl flex-grow" {
-                    (content)
-                }
-                footer class="text-center py-12 text-sm text-gray-600 space-y-3" {
-                    p {
-                        a href="https://github.com/inevity/theone" target="_blank" rel="noopener noreferrer" class="hover:text-blue-600 transition-colors duration-300 font-medium" {
-                            "THEONE on GitHub"
-                        }
-                    }
-                    p {
-                        a href="https://github.com/glidea/zenfeed" target="_blank" rel="noopener noreferrer" class="hover:text-blue-600 transition-colors duration-300 font-medium" {
-                            "zenfeed — Make RSS 📰 great again with AI 🧠✨!!"
-                        }
-                    }
-                }
+fn split_form_list(value: Option<&str>) -> Vec<String> {
+    value
+        .unwrap_or("")
+        .split([',', '\n'])
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+#[worker::send]
+pub async fn post_clients_handler(
+    State(state): State<Arc<AppState>>,
+    cookies: Cookies,
+    layout: PageLayout,
+    Form(form): Form<ClientsForm>,
+) -> Response {
+    if let Some(resp) = layout.check_csrf(form.csrf_token.as_deref()) {
+        return resp;
+    }
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Database error: {}", e),
+            )
+                .into_response()
+        }
+    };
+
+    let result = match form.action.as_str() {
+        "create" => {
+            let name = form.name.as_deref().unwrap_or("").trim().to_string();
+            if name.is_empty() {
+                return (StatusCode::BAD_REQUEST, "Client name is required").into_response();
             }
+            let providers = split_form_list(form.allowed_providers.as_deref());
+            let models = split_form_list(form.allowed_models.as_deref());
+            let role = form.role.as_deref().and_then(Role::parse);
+            d1_storage::create_client_key(&db, &name, &providers, &models, role)
+                .await
+                .map(|client| {
+                    let encoded = general_purpose::STANDARD.encode(client.key);
+                    cookies.add(
+                        Cookie::build(("new_client_key", encoded))
+                            .path("/")
+                            .http_only(true)
+                            .secure(!state.settings.is_local)
+                            .same_site(SameSite::Strict)
+                            .into(),
+                    );
+                })
+        }
+        "revoke" => match form.id.as_deref() {
+            Some(id) => d1_storage::revoke_client_key(&db, id).await,
+            None => return (StatusCode::BAD_REQUEST, "Missing client id").into_response(),
+        },
+        "delete" => match form.id.as_deref() {
+            Some(id) => d1_storage::delete_client_key(&db, id).await,
+            None => return (StatusCode::BAD_REQUEST, "Missing client id").into_response(),
+        },
+        "set-role" => match form.id.as_deref() {
+            Some(id) => d1_storage::set_client_role(&db, id, form.role.as_deref().and_then(Role::parse))
+                .await
+                .map(|_| ()),
+            None => return (StatusCode::BAD_REQUEST, "Missing client id").into_response(),
+        },
+        other => {
+            warn!("Unknown clients form action: {}", other);
+            return (StatusCode::BAD_REQUEST, "Unknown action").into_response();
         }
+    };
+
+    if let Err(e) = result {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to {} client key: {}", form.action, e),
+        )
+            .into_response();
     }
+
+    Redirect::to("/clients").into_response()
 }
-// endregion: --- Layout
+// endregion: --- Client Keys Page Handlers
 
-// region: --- Login Page
-fn login_page() -> Markup {
+// region: --- Two-Factor Page Handlers
+#[worker::send]
+pub async fn get_totp_page_handler(State(state): State<Arc<AppState>>, layout: PageLayout) -> Response {
+    totp_page_response(&state, &layout, StatusCode::OK, None).await
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TotpForm {
+    action: String,
+    code: Option<String>,
+    csrf_token: Option<String>,
+}
+
+/// Provisions, confirms or removes the caller's own second factor. Confirming and removing
+/// take a current code.
+#[worker::send]
+pub async fn post_totp_handler(
+    State(state): State<Arc<AppState>>,
+    layout: PageLayout,
+    Form(form): Form<TotpForm>,
+) -> Response {
+    if let Some(resp) = layout.check_csrf(form.csrf_token.as_deref()) {
+        return resp;
+    }
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Database error: {}", e),
+            )
+                .into_response()
+        }
+    };
+    let enrollment = match d1_storage::get_totp(&db, &layout.subject).await {
+        Ok(enrollment) => enrollment,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get second factor: {}", e)).into_response(),
+    };
+
+    let result = match (form.action.as_str(), enrollment) {
+        ("provision", Some(enrollment)) if enrollment.enabled() => {
+            return (StatusCode::CONFLICT, "Remove the second factor before setting up a new one").into_response();
+        }
+        ("provision", _) => d1_storage::provision_totp(&db, &layout.subject, &totp::generate_secret()).await,
+        (action @ ("enable" | "disable"), Some(enrollment)) if enrollment.enabled() == (action == "disable") => {
+            if let Some(resp) = code_rate_limit(&state, &layout.subject) {
+                return resp;
+            }
+            let code = form.code.as_deref().unwrap_or_default();
+            match totp::check(&db, &enrollment, code).await {
+                Ok(true) if action == "enable" => d1_storage::enable_totp(&db, &layout.subject).await,
+                Ok(true) => d1_storage::delete_totp(&db, &layout.subject).await,
+                Ok(false) => {
+                    return totp_page_response(&state, &layout, StatusCode::UNAUTHORIZED, Some("Invalid code, try again."))
+                        .await
+                }
+                Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to check code: {}", e)).into_response(),
+            }
+        }
+        (other, _) => {
+            warn!("Unexpected two-factor form action: {}", other);
+            return (StatusCode::BAD_REQUEST, "Unknown action").into_response();
+        }
+    };
+
+    if let Err(e) = result {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to {} second factor: {}", form.action, e),
+        )
+            .into_response();
+    }
+    info!(subject = %layout.subject, action = %form.action, "Changed second factor.");
+    Redirect::to("/account/2fa").into_response()
+}
+
+async fn totp_page_response(state: &AppState, layout: &PageLayout, status: StatusCode, error: Option<&str>) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Database error: {}", e),
+            )
+                .into_response()
+        }
+    };
+    match d1_storage::get_totp(&db, &layout.subject).await {
+        Ok(enrollment) => (
+            status,
+            page_layout(
+                layout.theme,
+                totp_page(&layout.subject, enrollment, error, &layout.csrf_token),
+            ),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to get second factor: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+/// Counts a second-factor code attempt against the stricter admin rate limit.
+fn code_rate_limit(state: &AppState, subject: &str) -> Option<Response> {
+    admin_limits::check(&state.settings, subject, admin_limits::Bucket::Sensitive).err().map(|retry_after| {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(axum::http::header::RETRY_AFTER, retry_after.to_string())],
+            format!("Too many attempts. Retry in {} seconds.", retry_after),
+        )
+            .into_response()
+    })
+}
+// endregion: --- Two-Factor Page Handlers
+
+// region: --- API Handlers
+#[worker::send]
+pub async fn post_add_keys_api_handler(
+    State(state): State<Arc<AppState>>,
+    Path(provider): Path<String>,
+    layout: PageLayout,
+    headers: HeaderMap,
+    body: String,
+) -> impl IntoResponse {
+    // Posted by scripts rather than forms, so the token comes in a header.
+    if let Some(resp) = layout.check_csrf(headers.get("X-CSRF-Token").and_then(|v| v.to_str().ok())) {
+        return resp;
+    }
+    let rejected = key_format::check_all(&provider, &body);
+    if !rejected.is_empty() {
+        return (StatusCode::BAD_REQUEST, format!("No keys were added: {}", rejected.join("; "))).into_response();
+    }
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Database error: {}", e),
+            )
+                .into_response()
+        }
+    };
+    match d1_storage::add_keys(&db, &provider, &body).await {
+        Ok(_) => (StatusCode::OK, "Keys added successfully").into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to add keys: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+// region: --- API Handlers
+#[worker::send]
+pub async fn get_key_coolings_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    _layout: PageLayout,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Database error: {}", e),
+            )
+                .into_response()
+        }
+    };
+
+    match d1_storage::get_key_coolings(&db, &id).await {
+        Ok(Some(mut key)) => {
+            // The full key is only served by the reveal endpoint.
+            key.key = util::partially_redact_key(&key.key);
+            (StatusCode::OK, Json(key)).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "Key not found").into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to get key coolings: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+/// The key's stored rankings from sampled requests, for the key details.
+#[worker::send]
+pub async fn get_key_scores_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    _layout: PageLayout,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e)).into_response(),
+    };
+    match d1_storage::list_key_scores(&db, &id).await {
+        Ok(rows) => (StatusCode::OK, Json(rows)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get key scores: {}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct KeyEventsParams {
+    pub limit: Option<u32>,
+}
+
+/// The key's latest blocks, cooldowns and reactivations with the provider's reason, for
+/// the key details.
+#[worker::send]
+pub async fn get_key_events_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(params): Query<KeyEventsParams>,
+    _layout: PageLayout,
+) -> Response {
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e)).into_response(),
+    };
+    let limit = params.limit.unwrap_or(20).min(100);
+    match d1_storage::list_key_events(&db, &id, limit).await {
+        Ok(rows) => (StatusCode::OK, Json(rows)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get key events: {}", e)).into_response(),
+    }
+}
+
+/// Counts a key reveal or test run against the admin's stricter rate limit, like the
+/// exports of the admin API, returning the `429` to answer with when it is over the limit.
+fn sensitive_rate_limit(state: &AppState, layout: &PageLayout) -> Option<Response> {
+    admin_limits::check(&state.settings, &layout.subject, admin_limits::Bucket::Sensitive).err().map(|retry_after| {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(axum::http::header::RETRY_AFTER, retry_after.to_string())],
+            format!("Rate limit exceeded. Retry in {} seconds.", retry_after),
+        )
+            .into_response()
+    })
+}
+
+/// Returns the full key for the reveal-on-click in the keys list.
+#[worker::send]
+pub async fn get_key_reveal_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    layout: PageLayout,
+) -> Response {
+    if let Some(resp) = sensitive_rate_limit(&state, &layout) {
+        return resp;
+    }
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Database error: {}", e),
+            )
+                .into_response()
+        }
+    };
+
+    match d1_storage::get_key_coolings(&db, &id).await {
+        Ok(Some(key)) => {
+            info!(key_id = %id, "Revealed key in the UI.");
+            (
+                StatusCode::OK,
+                [(axum::http::header::CACHE_CONTROL, "no-store")],
+                Json(serde_json::json!({ "key": key.key })),
+            )
+                .into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "Key not found").into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to get key: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+/// Edits the note, tags, weight or tier of a key from the edit dialog of the keys page.
+#[worker::send]
+pub async fn patch_key_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    layout: PageLayout,
+    headers: HeaderMap,
+    Json(mut update): Json<d1_storage::KeyMetadataUpdate>,
+) -> Response {
+    if let Some(resp) = layout.check_csrf(headers.get("X-CSRF-Token").and_then(|v| v.to_str().ok())) {
+        return resp;
+    }
+    if let Err(e) = update.validate() {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let db = match state.db() {
+        Ok(db) => db,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Database error: {}", e),
+            )
+                .into_response()
+        }
+    };
+
+    match d1_storage::update_key_metadata(&db, &id, update).await {
+        Ok(Some(mut key)) => {
+            info!(key_id = %id, subject = %layout.subject, "Edited key metadata in the UI.");
+            key.key = util::partially_redact_key(&key.key);
+            (StatusCode::OK, Json(key)).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "Key not found").into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to update key: {}", e),
+        )
+            .into_response(),
+    }
+}
+// endregion: --- API Handlers
+
+// --- Page Components (Maud HTML) ---
+
+// region: --- Layout
+/// The color theme of the UI, switched in the footer and kept in the `theme` cookie. Without
+/// the cookie, `script.js` follows the browser's preference.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Theme {
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+impl Theme {
+    const COOKIE: &'static str = "theme";
+
+    fn from_cookies(cookies: &Cookies) -> Self {
+        match cookies.get(Self::COOKIE).as_ref().map(|c| c.value()) {
+            Some("light") => Theme::Light,
+            Some("dark") => Theme::Dark,
+            _ => Theme::System,
+        }
+    }
+
+    /// The `data-theme` attribute of the page, which `web/style.css` keys the dark variants on.
+    fn attribute(self) -> Option<&'static str> {
+        match self {
+            Theme::System => None,
+            Theme::Light => Some("light"),
+            Theme::Dark => Some("dark"),
+        }
+    }
+}
+
+fn page_layout(theme: Theme, content: Markup) -> Markup {
+    html! {
+        (DOCTYPE)
+        html lang="en" data-theme=[theme.attribute()] {
+            head {
+                meta charset="UTF-8";
+                meta name="viewport" content="width=device-width, initial-scale=1.0";
+                title { "One Balance" }
+                link rel="icon" href="data:image/svg+xml,<svg xmlns=%22http://www.w3.org/2000/svg%22 viewBox=%220 0 100 100%22><text y=%22.9em%22 font-size=%2290%22>⚖️</text></svg>";
+                script src="https://cdn.tailwindcss.com" {}
+                style { (PreEscaped(include_str!("web/style.css"))) }
+                script { (PreEscaped(include_str!("web/script.js"))) }
+            }
+            body class="breathing-bg min-h-screen text-gray-900 flex flex-col" {
+                main class="container mx-auto mt-12 px-6 max-w-7xl flex-grow" {
+                    (content)
+                }
+                footer class="text-center py-12 text-sm text-gray-600 space-y-3" {
+                    p class="flex justify-center gap-6" {
+                        button type="button" id="timeDisplayToggle" onclick="toggleTimeDisplay()"
+                               class="hover:text-blue-600 transition-colors duration-300 font-medium" {
+                            "Show absolute times"
+                        }
+                        button type="button" id="themeToggle" onclick="toggleTheme()"
+                               class="hover:text-blue-600 transition-colors duration-300 font-medium" {
+                            @if theme == Theme::Dark { "Light mode" } @else { "Dark mode" }
+                        }
+                    }
+                    p {
+                        a href="https://github.com/inevity/theone" target="_blank" rel="noopener noreferrer" class="hover:text-blue-600 transition-colors duration-300 font-medium" {
+                            "THEONE on GitHub"
+                        }
+                    }
+                    p {
+                        a href="https://github.com/glidea/zenfeed" target="_blank" rel="noopener noreferrer" class="hover:text-blue-600 transition-colors duration-300 font-medium" {
+                            "zenfeed — Make RSS 📰 great again with AI 🧠✨!!"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+// endregion: --- Layout
+
+// region: --- Login Page
+fn login_page() -> Markup {
     html! {
         div class="flex items-center justify-center min-h-[70vh] relative" {
             div class="absolute top-20 left-1/4 w-32 h-32 bg-blue-200/30 rounded-full blur-3xl floating-element" {}
@@ -576,38 +1422,446 @@ fn login_page() -> Markup {
         }
     }
 }
-// endregion: --- Login Page
 
-// region: --- Providers Page
-fn providers_page() -> Markup {
+fn second_factor_page(remember_days: u64, error: Option<&str>) -> Markup {
+    html! {
+        div class="flex items-center justify-center min-h-[70vh] relative" {
+            div class="max-w-md w-full mx-6 relative z-10" {
+                div class="text-center mb-12" {
+                    h2 class="text-4xl font-bold bg-gradient-to-r from-gray-900 to-gray-700 bg-clip-text text-transparent mb-3" { "Two-Factor Check" }
+                    p class="text-gray-600 text-lg" { "Enter the code from your authenticator app" }
+                }
+
+                div class="glass-card-warm rounded-3xl p-10" {
+                    form action="/login/2fa" method="POST" class="space-y-8" {
+                        @if let Some(error) = error {
+                            p class="text-sm font-semibold text-red-700" { (error) }
+                        }
+                        div {
+                            label for="code" class="block text-gray-800 text-sm font-bold mb-4 tracking-wide" { "Code" }
+                            input type="text" id="code" name="code" inputmode="numeric" autocomplete="one-time-code"
+                                   pattern="[0-9 ]{6,7}" maxlength="7" autofocus
+                                   class="input-field w-full px-5 py-4 rounded-2xl text-gray-900 placeholder-gray-500 focus:outline-none text-base font-mono tracking-widest"
+                                   placeholder="123456" required;
+                        }
+                        @if remember_days > 0 {
+                            label class="flex items-center gap-3 text-sm text-gray-700" {
+                                input type="checkbox" name="remember" value="1";
+                                "Remember this device for " (remember_days) " days"
+                            }
+                        }
+                        button type="submit" class="btn-primary w-full py-4 px-6 text-white font-bold rounded-2xl focus:outline-none focus:ring-4 focus:ring-blue-200 text-base tracking-wide" {
+                            "Verify"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+// endregion: --- Login Page
+
+// region: --- Two-Factor Page
+fn totp_page(subject: &str, enrollment: Option<totp::Enrollment>, error: Option<&str>, csrf_token: &str) -> Markup {
+    html! {
+        div class="mb-8" {
+            nav class="flex items-center space-x-2 text-sm text-gray-600 mb-4" {
+                a href="/" class="hover:text-blue-600 transition-colors duration-200 font-medium" { "Providers" }
+                svg class="w-4 h-4" fill="none" stroke="currentColor" viewBox="0 0 24 24" {
+                    path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M9 5l7 7-7 7" {}
+                }
+                span class="text-gray-900 font-semibold" { "Two-Factor Authentication" }
+            }
+        }
+        div class="glass-card bg-white/80 rounded-3xl shadow-xl p-8 border border-gray-200 max-w-xl mx-auto space-y-6" {
+            @if let Some(error) = error {
+                p class="text-sm font-semibold text-red-700" { (error) }
+            }
+            @match enrollment {
+                Some(enrollment) if enrollment.enabled() => {
+                    p class="text-sm text-gray-700" {
+                        "A second factor protects logins as " span class="font-mono" { (subject) } " since "
+                        (timestamp(enrollment.enabled_at as u64, " ago")) "."
+                    }
+                    form method="POST" action="/account/2fa" class="flex gap-3" {
+                        (csrf_field(csrf_token))
+                        input type="hidden" name="action" value="disable";
+                        (build_code_input())
+                        button type="submit" onclick="return confirm('Remove the second factor?');"
+                               class="px-4 py-2.5 bg-red-600 hover:bg-red-700 text-white font-semibold rounded-xl text-sm" { "Remove" }
+                    }
+                }
+                Some(enrollment) => {
+                    @let uri = totp::provisioning_uri(&enrollment.secret, subject);
+                    p class="text-sm text-gray-700" { "Scan the code with an authenticator app, then enter the code it shows to turn the second factor on." }
+                    div id="totpQr" data-uri=(uri) class="flex justify-center bg-white rounded-2xl p-4" {}
+                    script { (PreEscaped(include_str!("web/qrcode.js"))) }
+                    p class="text-xs text-gray-600" { "Or enter the secret by hand:" }
+                    (build_copyable_key(&enrollment.secret))
+                    form method="POST" action="/account/2fa" class="flex gap-3" {
+                        (csrf_field(csrf_token))
+                        input type="hidden" name="action" value="enable";
+                        (build_code_input())
+                        button type="submit" class="px-4 py-2.5 bg-emerald-600 hover:bg-emerald-700 text-white font-semibold rounded-xl text-sm" { "Turn on" }
+                    }
+                    form method="POST" action="/account/2fa" {
+                        (csrf_field(csrf_token))
+                        input type="hidden" name="action" value="provision";
+                        button type="submit" class="text-xs font-semibold text-gray-600 hover:text-blue-600" { "Generate a new secret" }
+                    }
+                }
+                None => {
+                    p class="text-sm text-gray-700" { "Require a code from an authenticator app in addition to the auth key when logging in as " span class="font-mono" { (subject) } "." }
+                    form method="POST" action="/account/2fa" {
+                        (csrf_field(csrf_token))
+                        input type="hidden" name="action" value="provision";
+                        button type="submit" class="px-4 py-2.5 bg-slate-600 hover:bg-slate-700 text-white font-semibold rounded-xl text-sm" { "Set up" }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The hidden field carrying the session's CSRF token; every POST form of a page needs one.
+fn csrf_field(csrf_token: &str) -> Markup {
+    html! {
+        input type="hidden" name="csrf_token" value=(csrf_token);
+    }
+}
+
+fn build_code_input() -> Markup {
+    html! {
+        input type="text" name="code" inputmode="numeric" autocomplete="one-time-code" pattern="[0-9 ]{6,7}" maxlength="7" required
+              placeholder="123456" class="input-field flex-1 px-4 py-2.5 bg-white border border-gray-300 rounded-xl text-sm font-mono tracking-widest";
+    }
+}
+// endregion: --- Two-Factor Page
+
+// region: --- Providers Page
+fn providers_page(custom_providers: &[CustomProvider], tag_counts: &[TagCount], csrf_token: &str) -> Markup {
+    html! {
+        div class="text-center mb-20 relative" {
+            div class="absolute top-0 left-1/2 transform -translate-x-1/2 -translate-y-8 w-64 h-32 bg-gradient-to-r from-blue-200/20 to-purple-200/20 rounded-full blur-3xl" {}
+            h1 class="text-6xl font-bold bg-gradient-to-r from-gray-900 via-blue-800 to-gray-900 bg-clip-text text-transparent mb-6 relative" { "Select Provider" }
+            div class="relative flex justify-center gap-6" {
+                a href="/dashboard" class="text-sm font-semibold text-gray-600 hover:text-blue-600 transition-colors duration-300" { "Dashboard →" }
+                a href="/clients" class="text-sm font-semibold text-gray-600 hover:text-blue-600 transition-colors duration-300" { "Manage client keys →" }
+                a href="/account/2fa" class="text-sm font-semibold text-gray-600 hover:text-blue-600 transition-colors duration-300" { "Two-factor auth →" }
+                form action="/logout" method="POST" {
+                    (csrf_field(csrf_token))
+                    button type="submit" class="text-sm font-semibold text-gray-600 hover:text-red-600 transition-colors duration-300" { "Sign out" }
+                }
+            }
+        }
+
+        div class="grid grid-cols-1 sm:grid-cols-2 lg:grid-cols-3 xl:grid-cols-4 gap-8 max-w-7xl mx-auto" {
+            // A stored color, of a custom provider or an endpoint override, is set inline.
+            @for (p_name, config) in &PROVIDER_CONFIGS {
+                @let style = custom_providers.iter().find(|c| c.name == *p_name).and_then(custom_color_style);
+                (provider_card(p_name, config.icon, config.color, config.bg_color, style.as_deref()))
+            }
+            @for custom in custom_providers.iter().filter(|c| !PROVIDER_CONFIGS.contains_key(c.name.as_str())) {
+                @let icon = custom.name.chars().next().map(|c| c.to_ascii_uppercase().to_string()).unwrap_or_default();
+                (provider_card(&custom.name, &icon, CUSTOM_PROVIDER_COLOR, CUSTOM_PROVIDER_BG_COLOR, custom_color_style(custom).as_deref()))
+            }
+        }
+        (build_tag_facet(tag_counts))
+    }
+}
+
+/// How many live keys carry each tag, with a link per provider to its tagged keys.
+fn build_tag_facet(tag_counts: &[TagCount]) -> Markup {
+    if tag_counts.is_empty() {
+        return html! {};
+    }
+    // The counts come ordered by tag, so each tag's providers are adjacent.
+    let tags = tag_counts.chunk_by(|a, b| a.tag == b.tag);
+    html! {
+        div class="glass-card bg-white/80 rounded-3xl shadow-xl border border-gray-200 p-6 mt-12 max-w-7xl mx-auto" {
+            h2 class="text-lg font-bold text-gray-900 mb-4" { "Key Tags" }
+            div class="flex flex-wrap gap-3" {
+                @for group in tags {
+                    div class="px-3 py-2 rounded-xl border border-gray-300 bg-white/80 text-sm" {
+                        span class="font-semibold text-gray-900" { (group[0].tag) }
+                        " "
+                        span class="text-gray-600" { (group.iter().map(|c| c.keys).sum::<i64>()) }
+                        div class="flex flex-wrap gap-x-2 mt-1 text-xs" {
+                            @for count in group {
+                                a href=(build_page_link(&count.provider, "active", &format!("tag:{}", count.tag), 1, 20, "", "desc"))
+                                  class="text-blue-700 hover:underline" { (count.provider) " " (count.keys) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn custom_color_style(custom: &CustomProvider) -> Option<String> {
+    (!custom.color.is_empty()).then(|| format!("background: {}", custom.color))
+}
+
+fn provider_card(p_name: &str, icon: &str, color: &str, bg_color: &str, color_style: Option<&str>) -> Markup {
+    html! {
+        div class="glass-card rounded-3xl p-8 transition-all duration-500 hover:cursor-pointer group hover:shadow-2xl" {
+            a href={"/keys/" (p_name) "?status=active"} class="block" {
+                div class="flex items-center justify-between" {
+                    div class="flex items-center space-x-5" {
+                        div class="relative" {
+                            div class={"w-14 h-14 bg-gradient-to-br "(bg_color)" rounded-2xl flex items-center justify-center group-hover:scale-110 transition-all duration-300 shadow-lg"} {
+                                div class={"w-8 h-8 bg-gradient-to-br "(color)" rounded-xl flex items-center justify-center text-white font-bold text-sm shadow-inner"} style=[color_style] {
+                                    (icon)
+                                }
+                            }
+                            div class={"absolute -top-1 -right-1 w-4 h-4 bg-gradient-to-br "(color)" rounded-full opacity-60 group-hover:opacity-100 transition-opacity duration-300"} style=[color_style] {}
+                        }
+                        div {
+                            h3 class="text-xl font-bold text-gray-900 group-hover:text-blue-600 transition-colors duration-300 mb-1" { (p_name) }
+                        }
+                    }
+                    div class="flex items-center space-x-2" {
+                        div class={"w-2 h-2 bg-gradient-to-r "(color)" rounded-full opacity-60 group-hover:opacity-100 transition-opacity duration-300"} style=[color_style] {}
+                        svg class="w-6 h-6 text-gray-400 transform transition-all duration-300 group-hover:translate-x-2 group-hover:text-blue-500" fill="none" stroke="currentColor" viewBox="0 0 24 24" {
+                            path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M9 5l7 7-7 7" {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+// endregion: --- Providers Page
+
+// region: --- Dashboard Page
+fn dashboard_page(stats: Vec<ProviderDashboardStats>, errors: Vec<ErrorClassCount>) -> Markup {
+    html! {
+        div class="mb-8" {
+            nav class="flex items-center space-x-2 text-sm text-gray-600 mb-4" {
+                a href="/" class="hover:text-blue-600 transition-colors duration-200 font-medium" { "Providers" }
+                svg class="w-4 h-4" fill="none" stroke="currentColor" viewBox="0 0 24 24" {
+                    path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M9 5l7 7-7 7" {}
+                }
+                span class="text-gray-900 font-semibold" { "Dashboard" }
+            }
+        }
+        div class="glass-card bg-white/80 rounded-3xl shadow-xl border border-gray-200 overflow-hidden mb-8 max-w-5xl mx-auto backdrop-blur-xl" {
+            table class="w-full" {
+                thead {
+                    tr class="bg-gradient-to-r from-slate-100/90 to-gray-100/90 border-b border-gray-400/80" {
+                        th class="p-4 text-left font-semibold text-slate-800 text-sm" { "Provider" }
+                        th class="p-4 text-right font-semibold text-slate-800 text-sm" { "Active" }
+                        th class="p-4 text-right font-semibold text-slate-800 text-sm" { "Cooling" }
+                        th class="p-4 text-right font-semibold text-slate-800 text-sm" { "Blocked" }
+                        th class="p-4 text-right font-semibold text-slate-800 text-sm" { "Avg Latency" }
+                        th class="p-4 text-right font-semibold text-slate-800 text-sm" { "Requests (24h)" }
+                        th class="p-4 text-right font-semibold text-slate-800 text-sm" { "Success (24h)" }
+                    }
+                }
+                tbody class="divide-y divide-gray-300/60" {
+                    @if stats.is_empty() {
+                        tr {
+                            td colspan="7" class="text-center p-12 text-gray-700 bg-slate-100/40" { "No keys added yet" }
+                        }
+                    }
+                    @for s in &stats {
+                        tr class="even:bg-slate-100/40 odd:bg-white/60" {
+                            td class="p-4 text-sm font-medium text-slate-900" {
+                                a href={"/keys/" (s.provider) "?status=active"} class="hover:text-blue-600" { (s.provider) }
+                            }
+                            td class="p-4 text-right font-mono text-sm text-emerald-700" { (s.active_keys) }
+                            td class="p-4 text-right font-mono text-sm text-amber-700" { (s.cooling_keys) }
+                            td class="p-4 text-right font-mono text-sm text-red-700" { (s.blocked_keys) }
+                            td class="p-4 text-right font-mono text-sm text-slate-700" {
+                                @if let Some(latency) = s.avg_latency_ms { (format!("{:.0}ms", latency)) } @else { "-" }
+                            }
+                            td class="p-4 text-right font-mono text-sm text-slate-700" { (s.requests) }
+                            td class="p-4 text-right font-mono text-sm text-slate-700" {
+                                @if let Some(rate) = s.success_rate() { (format!("{:.1}%", rate * 100.0)) } @else { "-" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        div class="glass-card bg-white/80 rounded-3xl shadow-xl border border-gray-200 overflow-hidden max-w-5xl mx-auto backdrop-blur-xl" {
+            h2 class="text-xl font-bold text-gray-900 p-6 pb-2" { "Recent Errors (24h)" }
+            table class="w-full" {
+                thead {
+                    tr class="bg-gradient-to-r from-slate-100/90 to-gray-100/90 border-b border-gray-400/80" {
+                        th class="p-4 text-left font-semibold text-slate-800 text-sm" { "Provider" }
+                        th class="p-4 text-left font-semibold text-slate-800 text-sm" { "Error Class" }
+                        th class="p-4 text-right font-semibold text-slate-800 text-sm" { "Count" }
+                        th class="p-4 text-right font-semibold text-slate-800 text-sm" { "Last Seen" }
+                    }
+                }
+                tbody class="divide-y divide-gray-300/60" {
+                    @if errors.is_empty() {
+                        tr {
+                            td colspan="4" class="text-center p-12 text-gray-700 bg-slate-100/40" { "No failed requests in the last 24 hours" }
+                        }
+                    }
+                    @for e in &errors {
+                        tr class="even:bg-slate-100/40 odd:bg-white/60" {
+                            td class="p-4 text-sm font-medium text-slate-900" { (e.provider) }
+                            td class="p-4 font-mono text-sm text-slate-700" { (e.error_class) }
+                            td class="p-4 text-right font-mono text-sm text-slate-700" { (e.count) }
+                            td class="p-4 text-right text-sm text-slate-700" { (timestamp(e.last_seen_at as u64, " ago")) }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+// endregion: --- Dashboard Page
+
+// region: --- Availability Page
+/// A model x key matrix: for each model, which keys can serve it right now, which are
+/// cooling down for it and until when, and which are blocked altogether.
+fn availability_page(provider: &str, keys: &[ApiKey], catalog: &[String]) -> Markup {
+    let now = Date::now().as_millis() / 1000;
+    let mut models: Vec<&str> = catalog.iter().map(String::as_str).collect();
+    models.extend(keys.iter().flat_map(|k| k.model_coolings.keys().map(String::as_str)));
+    models.sort_unstable();
+    models.dedup();
+    let cooling_until = |k: &ApiKey, model: &str| k.get_cooldown_end(model).filter(|end_at| *end_at > now);
+
+    html! {
+        div class="mb-8" {
+            nav class="flex items-center space-x-2 text-sm text-gray-600 mb-4" {
+                a href="/" class="hover:text-blue-600 transition-colors duration-200 font-medium" { "Providers" }
+                svg class="w-4 h-4" fill="none" stroke="currentColor" viewBox="0 0 24 24" {
+                    path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M9 5l7 7-7 7" {}
+                }
+                a href={"/keys/" (provider) "?status=active"} class="hover:text-blue-600 transition-colors duration-200 font-medium" { (provider) }
+                svg class="w-4 h-4" fill="none" stroke="currentColor" viewBox="0 0 24 24" {
+                    path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M9 5l7 7-7 7" {}
+                }
+                span class="text-gray-900 font-semibold" { "Model Availability" }
+            }
+        }
+        div class="glass-card bg-white/80 rounded-3xl shadow-xl border border-gray-200 overflow-x-auto backdrop-blur-xl" {
+            table class="text-sm" {
+                thead {
+                    tr class="bg-gradient-to-r from-slate-100/90 to-gray-100/90 border-b border-gray-400/80" {
+                        th class="p-3 text-left font-semibold text-slate-800 sticky left-0 bg-slate-100" { "Model" }
+                        th class="p-3 text-right font-semibold text-slate-800" { "Usable" }
+                        @for k in keys {
+                            th class="p-3 font-mono text-xs font-medium text-slate-700 whitespace-nowrap" title=(k.id) {
+                                (util::partially_redact_key(&k.key))
+                            }
+                        }
+                    }
+                }
+                tbody class="divide-y divide-gray-300/60" {
+                    @if models.is_empty() || keys.is_empty() {
+                        tr {
+                            td colspan=(keys.len() + 2) class="text-center p-12 text-gray-700 bg-slate-100/40" {
+                                @if keys.is_empty() { "No keys added yet" } @else { "No models seen yet: models show up once listed or cooled down for" }
+                            }
+                        }
+                    }
+                    @for model in &models {
+                        @let usable = keys.iter().filter(|k| k.status == ApiKeyStatus::Active && cooling_until(k, model).is_none()).count();
+                        tr class="even:bg-slate-100/40 odd:bg-white/60" {
+                            td class="p-3 font-mono text-slate-900 whitespace-nowrap sticky left-0 bg-white" { (model) }
+                            td class={"p-3 text-right font-mono font-semibold " (if usable == 0 { "text-red-700" } else { "text-emerald-700" })} {
+                                (usable) "/" (keys.len())
+                            }
+                            @for k in keys {
+                                @if k.status != ApiKeyStatus::Active {
+                                    td class="p-3 text-center text-xs text-gray-500 bg-gray-100" { "blocked" }
+                                } @else if let Some(end_at) = cooling_until(k, model) {
+                                    @let relative = format!("{} left", format_exact_duration(end_at - now));
+                                    td class="p-3 text-center text-xs text-amber-800 bg-amber-50 whitespace-nowrap" {
+                                        time datetime=(format_iso_utc(end_at)) data-ts=(end_at) data-relative=(relative) title=(format_iso_utc(end_at)) { (relative) }
+                                    }
+                                } @else {
+                                    td class="p-3 text-center text-xs text-emerald-700" { "usable" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+// endregion: --- Availability Page
+
+// region: --- Client Keys Page
+fn clients_page(clients: Vec<ClientKey>, new_key: Option<String>, csrf_token: &str) -> Markup {
     html! {
-        div class="text-center mb-20 relative" {
-            div class="absolute top-0 left-1/2 transform -translate-x-1/2 -translate-y-8 w-64 h-32 bg-gradient-to-r from-blue-200/20 to-purple-200/20 rounded-full blur-3xl" {}
-            h1 class="text-6xl font-bold bg-gradient-to-r from-gray-900 via-blue-800 to-gray-900 bg-clip-text text-transparent mb-6 relative" { "Select Provider" }
+        div class="mb-8" {
+            nav class="flex items-center space-x-2 text-sm text-gray-600 mb-4" {
+                a href="/" class="hover:text-blue-600 transition-colors duration-200 font-medium" { "Providers" }
+                svg class="w-4 h-4" fill="none" stroke="currentColor" viewBox="0 0 24 24" {
+                    path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M9 5l7 7-7 7" {}
+                }
+                span class="text-gray-900 font-semibold" { "Client Keys" }
+            }
         }
-
-        div class="grid grid-cols-1 sm:grid-cols-2 lg:grid-cols-3 xl:grid-cols-4 gap-8 max-w-7xl mx-auto" {
-            @for (p_name, config) in &PROVIDER_CONFIGS {
-                div class="glass-card rounded-3xl p-8 transition-all duration-500 hover:cursor-pointer group hover:shadow-2xl" {
-                    a href={"/keys/" (p_name) "?status=active"} class="block" {
-                        div class="flex items-center justify-between" {
-                            div class="flex items-center space-x-5" {
-                                div class="relative" {
-                                    div class={"w-14 h-14 bg-gradient-to-br "(config.bg_color)" rounded-2xl flex items-center justify-center group-hover:scale-110 transition-all duration-300 shadow-lg"} {
-                                        div class={"w-8 h-8 bg-gradient-to-br "(config.color)" rounded-xl flex items-center justify-center text-white font-bold text-sm shadow-inner"} {
-                                            (config.icon)
-                                        }
-                                    }
-                                    div class={"absolute -top-1 -right-1 w-4 h-4 bg-gradient-to-br "(config.color)" rounded-full opacity-60 group-hover:opacity-100 transition-opacity duration-300"} {}
+        @if let Some(key) = new_key {
+            div class="glass-card-warm rounded-3xl p-6 mb-8 max-w-5xl mx-auto border border-emerald-300" {
+                p class="text-sm font-semibold text-emerald-800 mb-3" { "New client key issued. Copy it now, it will not be shown again." }
+                (build_copyable_key(&key))
+            }
+        }
+        div class="glass-card bg-white/80 rounded-3xl shadow-xl border border-gray-200 overflow-hidden mb-8 max-w-5xl mx-auto backdrop-blur-xl" {
+            table class="w-full" {
+                thead {
+                    tr class="bg-gradient-to-r from-slate-100/90 to-gray-100/90 border-b border-gray-400/80" {
+                        th class="p-4 text-left font-semibold text-slate-800 text-sm" { "Name" }
+                        th class="p-4 text-left font-semibold text-slate-800 text-sm" { "Key" }
+                        th class="p-4 text-left font-semibold text-slate-800 text-sm" { "Providers" }
+                        th class="p-4 text-left font-semibold text-slate-800 text-sm" { "Models" }
+                        th class="p-4 text-left font-semibold text-slate-800 text-sm" { "Role" }
+                        th class="p-4 text-left font-semibold text-slate-800 text-sm" { "Last Used" }
+                        th class="p-4 text-left font-semibold text-slate-800 text-sm" { "Actions" }
+                    }
+                }
+                tbody class="divide-y divide-gray-300/60" {
+                    @if clients.is_empty() {
+                        tr {
+                            td colspan="7" class="text-center p-12 text-gray-700 bg-slate-100/40" { "No client keys issued yet" }
+                        }
+                    }
+                    @for c in &clients {
+                        tr class="even:bg-slate-100/40 odd:bg-white/60" {
+                            td class="p-4 text-sm font-medium text-slate-900" {
+                                (c.name)
+                                @if c.revoked {
+                                    span class="ml-2 px-2 py-0.5 bg-red-100 text-red-800 text-xs font-semibold rounded-full" { "Revoked" }
                                 }
-                                div {
-                                    h3 class="text-xl font-bold text-gray-900 group-hover:text-blue-600 transition-colors duration-300 mb-1" { (p_name) }
+                            }
+                            td class="p-4 font-mono text-sm text-slate-700" { (c.key_preview()) }
+                            td class="p-4 text-sm text-slate-700" { (scope_label(&c.allowed_providers)) }
+                            td class="p-4 text-sm text-slate-700" { (scope_label(&c.allowed_models)) }
+                            td class="p-4 text-sm text-slate-700" {
+                                form method="POST" action="/clients" class="flex gap-2" {
+                                    (csrf_field(csrf_token))
+                                    input type="hidden" name="action" value="set-role";
+                                    input type="hidden" name="id" value=(c.id);
+                                    (build_role_select(c.role))
+                                    button type="submit" class="px-3 py-1.5 bg-slate-600 hover:bg-slate-700 text-white font-semibold rounded-lg text-xs" { "Set" }
                                 }
                             }
-                            div class="flex items-center space-x-2" {
-                                div class={"w-2 h-2 bg-gradient-to-r "(config.color)" rounded-full opacity-60 group-hover:opacity-100 transition-opacity duration-300"} {}
-                                svg class="w-6 h-6 text-gray-400 transform transition-all duration-300 group-hover:translate-x-2 group-hover:text-blue-500" fill="none" stroke="currentColor" viewBox="0 0 24 24" {
-                                    path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M9 5l7 7-7 7" {}
+                            td class="p-4 text-sm text-slate-700" {
+                                @if c.last_used_at == 0 { "-" } @else { (timestamp(c.last_used_at, " ago")) }
+                            }
+                            td class="p-4" {
+                                form method="POST" action="/clients" class="flex gap-2" {
+                                    (csrf_field(csrf_token))
+                                    input type="hidden" name="id" value=(c.id);
+                                    @if !c.revoked {
+                                        button type="submit" name="action" value="revoke"
+                                                class="px-3 py-1.5 bg-amber-500 hover:bg-amber-600 text-white font-semibold rounded-lg text-xs" { "Revoke" }
+                                    }
+                                    button type="submit" name="action" value="delete"
+                                            onclick="return confirm('Delete this client key permanently?');"
+                                            class="px-3 py-1.5 bg-red-600 hover:bg-red-700 text-white font-semibold rounded-lg text-xs" { "Delete" }
                                 }
                             }
                         }
@@ -615,28 +1869,69 @@ fn providers_page() -> Markup {
                 }
             }
         }
+        div class="glass-card bg-white/80 rounded-3xl shadow-xl p-6 border border-gray-200 max-w-5xl mx-auto" {
+            h2 class="text-xl font-bold text-gray-900 mb-6" { "Issue Client Key" }
+            form method="POST" action="/clients" class="space-y-4" {
+                (csrf_field(csrf_token))
+                input type="hidden" name="action" value="create";
+                input type="text" name="name" required placeholder="Name (e.g. team-a)"
+                       class="input-field w-full px-4 py-2.5 bg-white border border-gray-300 rounded-xl text-sm";
+                input type="text" name="allowed_providers" placeholder="Allowed providers, comma-separated (empty = all)"
+                       class="input-field w-full px-4 py-2.5 bg-white border border-gray-300 rounded-xl text-sm";
+                input type="text" name="allowed_models" placeholder="Allowed models, comma-separated, * suffix for prefix (empty = all)"
+                       class="input-field w-full px-4 py-2.5 bg-white border border-gray-300 rounded-xl text-sm";
+                label class="flex items-center gap-3 text-sm text-gray-700" {
+                    "UI and admin API access"
+                    (build_role_select(None))
+                }
+                div class="flex justify-end" {
+                    button type="submit" class="btn-primary px-6 py-3 text-white font-semibold rounded-xl" { "Issue Key" }
+                }
+            }
+        }
     }
 }
-// endregion: --- Providers Page
+
+fn build_role_select(selected: Option<Role>) -> Markup {
+    html! {
+        select name="role" class="px-2 py-1.5 bg-white border border-gray-300 rounded-lg text-sm text-gray-900" {
+            option value="" selected[selected.is_none()] { "None (proxy only)" }
+            @for role in [Role::Viewer, Role::Operator, Role::Admin] {
+                option value=(role.as_str()) selected[selected == Some(role)] { (role.as_str()) }
+            }
+        }
+    }
+}
+
+fn scope_label(scope: &[String]) -> String {
+    if scope.is_empty() {
+        "All".to_string()
+    } else {
+        scope.join(", ")
+    }
+}
+// endregion: --- Client Keys Page
 
 // region: --- Keys List Page
 fn keys_list_page(
-    provider: &str,
-    current_status: &str,
-    q: &str,
+    ctx: &KeysPageCtx,
     keys: Vec<ApiKey>,
     total: i32,
-    page: usize,
-    page_size: usize,
-    sort_by: &str,
-    sort_order: &str,
     test_results: Option<Vec<testing::TestResult>>,
+    provider_settings: &ProviderSettings,
 ) -> Markup {
+    let provider = ctx.provider;
     html! {
         (build_breadcrumb(provider))
-        (build_keys_table(provider, current_status, q, keys, total, page, page_size, sort_by, sort_order))
-        (build_add_keys_form(provider, current_status, q, page, sort_by, sort_order))
+        (build_observe_only_banner(provider, provider_settings.observe_only, ctx.csrf_token))
+        (build_rate_limits_form(provider, provider_settings, ctx.csrf_token))
+        // `script.js` replaces the contents with the `/keys/{provider}/table` fragment.
+        div id="keys-table" data-provider=(provider) {
+            (build_keys_table(ctx, keys, total))
+        }
+        (build_add_keys_form(ctx))
         (build_model_coolings_modal())
+        (build_key_editor_modal(ctx.csrf_token))
         (build_test_results_modal(test_results))
     }
 }
@@ -650,38 +1945,64 @@ fn build_breadcrumb(provider: &str) -> Markup {
                     path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M9 5l7 7-7 7" {}
                 }
                 span class="text-gray-900 font-semibold" { (provider) }
+                a href={"/keys/" (provider) "/availability"} class="ml-auto text-sm font-semibold text-gray-600 hover:text-blue-600 transition-colors duration-300" { "Model availability →" }
             }
         }
     }
 }
 
-fn build_keys_table(
-    provider: &str,
-    current_status: &str,
-    q: &str,
-    keys: Vec<ApiKey>,
-    total: i32,
-    page: usize,
-    page_size: usize,
-    sort_by: &str,
-    sort_order: &str,
-) -> Markup {
-    let key_rows = build_key_rows(keys);
-    let pagination_controls = build_pagination_controls(
-        provider,
-        current_status,
-        q,
-        page,
-        page_size,
-        total as usize,
-        sort_by,
-        sort_order,
-    );
+fn build_rate_limits_form(provider: &str, settings: &ProviderSettings, csrf_token: &str) -> Markup {
+    html! {
+        form method="post" action=(format!("/keys/{}", provider)) class="mb-6 flex items-center justify-end gap-3 text-sm text-gray-700" {
+            (csrf_field(csrf_token))
+            span title="The balancer stops sending traffic to a key once it reaches these per-minute budgets; 0 means unlimited." { "Per-key budget" }
+            label class="flex items-center gap-1" {
+                input type="number" name="rpm_limit" min="0" value=(settings.rpm_limit)
+                      class="w-24 px-2 py-1.5 bg-white border border-gray-300 rounded-lg text-gray-900";
+                "RPM"
+            }
+            label class="flex items-center gap-1" {
+                input type="number" name="tpm_limit" min="0" value=(settings.tpm_limit)
+                      class="w-28 px-2 py-1.5 bg-white border border-gray-300 rounded-lg text-gray-900";
+                "TPM"
+            }
+            button type="submit" name="action" value="rate-limits" class="px-3 py-1.5 text-sm text-gray-600 border border-gray-300 rounded-lg hover:bg-gray-50" { "Save" }
+        }
+    }
+}
+
+fn build_observe_only_banner(provider: &str, observe_only: bool, csrf_token: &str) -> Markup {
+    html! {
+        form method="post" action=(format!("/keys/{}", provider)) class="mb-6" {
+            (csrf_field(csrf_token))
+            @if observe_only {
+                div class="flex items-center justify-between bg-amber-50 border border-amber-200 rounded-xl px-5 py-4" {
+                    div {
+                        p class="font-semibold text-amber-800" { "Observe-only mode" }
+                        p class="text-sm text-amber-700" { "Keys are probed and scored by the scheduled tester, but live traffic is not routed to this provider." }
+                    }
+                    button type="submit" name="action" value="observe-only-off" class="px-4 py-2 bg-amber-600 text-white rounded-lg hover:bg-amber-700 text-sm font-medium" { "Enable live traffic" }
+                }
+            } @else {
+                div class="flex justify-end" {
+                    button type="submit" name="action" value="observe-only-on" class="px-3 py-1.5 text-sm text-gray-600 border border-gray-300 rounded-lg hover:bg-gray-50" { "Switch to observe-only" }
+                }
+            }
+        }
+    }
+}
+
+fn build_keys_table(ctx: &KeysPageCtx, keys: Vec<ApiKey>, total: i32) -> Markup {
+    let KeysPageCtx { provider, status: current_status, q, sort_by, sort_order, .. } = *ctx;
+    let key_rows = build_key_rows(keys, current_status);
+    let pagination_controls = build_pagination_controls(ctx, total as usize);
 
     html! {
         div class="glass-card bg-white/80 rounded-3xl shadow-xl border border-gray-200 overflow-hidden mb-8 max-w-5xl mx-auto backdrop-blur-xl" {
             form method="POST" {
+                (csrf_field(ctx.csrf_token))
                 (build_table_header(provider, current_status, q, sort_by, sort_order))
+                (build_tag_filter(ctx))
                 (build_table_content(&key_rows, provider, current_status, q, sort_by, sort_order))
                 (build_table_footer(total, &pagination_controls))
             }
@@ -701,7 +2022,7 @@ fn build_table_header(
     let delete_all_button = if current_status == "blocked" {
         html! {
             button type="submit" name="action" value="delete-all-blocked"
-                    onclick="return confirm('Are you sure you want to delete all blocked keys? This action cannot be undone.');"
+                    onclick="return confirm('Move all blocked keys to the trash?');"
                     class="px-4 py-2.5 bg-red-800 hover:bg-red-900 text-white font-semibold rounded-xl text-sm transition-all duration-200 hover:shadow-lg hover:shadow-red-800/25 hover:-translate-y-0.5 border border-red-800" {
                 "Delete ALL"
             }
@@ -752,12 +2073,45 @@ fn build_table_header(
                     }
                 }
                 div class="flex items-center gap-2" {
-                    (test_controls)
-                    button type="submit" name="action" value="delete"
-                            class="px-4 py-2.5 bg-red-600 hover:bg-red-700 text-white font-semibold rounded-xl text-sm transition-all duration-200 hover:shadow-lg hover:shadow-red-600/25 hover:-translate-y-0.5 border border-red-600" {
-                        "Delete Selected"
+                    @if current_status == "trash" {
+                        button type="submit" name="action" value="restore"
+                                class="px-4 py-2.5 bg-green-600 hover:bg-green-700 text-white font-semibold rounded-xl text-sm transition-all duration-200 hover:shadow-lg hover:shadow-green-600/25 hover:-translate-y-0.5 border border-green-600" {
+                            "Restore Selected"
+                        }
+                        button type="submit" name="action" value="purge"
+                                onclick="return confirm('Permanently delete the selected keys? This action cannot be undone.');"
+                                class="px-4 py-2.5 bg-red-800 hover:bg-red-900 text-white font-semibold rounded-xl text-sm transition-all duration-200 hover:shadow-lg hover:shadow-red-800/25 hover:-translate-y-0.5 border border-red-800" {
+                            "Delete Forever"
+                        }
+                    } @else {
+                        (test_controls)
+                        @if current_status == "blocked" {
+                            button type="submit" name="action" value="activate"
+                                    class="px-4 py-2.5 bg-green-600 hover:bg-green-700 text-white font-semibold rounded-xl text-sm transition-all duration-200 hover:shadow-lg hover:shadow-green-600/25 hover:-translate-y-0.5 border border-green-600" {
+                                "Activate Selected"
+                            }
+                        } @else {
+                            button type="submit" name="action" value="block"
+                                    class="px-4 py-2.5 bg-amber-600 hover:bg-amber-700 text-white font-semibold rounded-xl text-sm transition-all duration-200 hover:shadow-lg hover:shadow-amber-600/25 hover:-translate-y-0.5 border border-amber-600" {
+                                "Block Selected"
+                            }
+                        }
+                        button type="submit" name="action" value="tier-free"
+                                title="Free keys are tried before paid keys"
+                                class="px-4 py-2.5 bg-white/80 hover:bg-white text-gray-800 font-semibold rounded-xl text-sm transition-all duration-200 hover:-translate-y-0.5 border border-gray-300" {
+                            "Mark Free"
+                        }
+                        button type="submit" name="action" value="tier-paid"
+                                title="Paid keys are tried once free keys are exhausted"
+                                class="px-4 py-2.5 bg-white/80 hover:bg-white text-gray-800 font-semibold rounded-xl text-sm transition-all duration-200 hover:-translate-y-0.5 border border-gray-300" {
+                            "Mark Paid"
+                        }
+                        button type="submit" name="action" value="delete"
+                                class="px-4 py-2.5 bg-red-600 hover:bg-red-700 text-white font-semibold rounded-xl text-sm transition-all duration-200 hover:shadow-lg hover:shadow-red-600/25 hover:-translate-y-0.5 border border-red-600" {
+                            "Delete Selected"
+                        }
+                        (delete_all_button)
                     }
-                    (delete_all_button)
                 }
             }
         }
@@ -771,7 +2125,7 @@ fn build_status_tabs(
     sort_by: &str,
     sort_order: &str,
 ) -> Markup {
-    let statuses = ["active", "blocked"];
+    let statuses = ["active", "blocked", "trash"];
     html! {
         @for s in &statuses {
             @let is_active = *s == current_status;
@@ -786,6 +2140,44 @@ fn build_status_tabs(
     }
 }
 
+/// Toggles `tag:{tag}` in a search.
+fn toggle_tag_filter(q: &str, tag: &str) -> String {
+    let term = format!("tag:{}", tag);
+    let mut terms: Vec<&str> = q.split_whitespace().collect();
+    if let Some(pos) = terms.iter().position(|t| *t == term) {
+        terms.remove(pos);
+    } else {
+        terms.push(&term);
+    }
+    terms.join(" ")
+}
+
+/// The tags of the provider's keys with their counts; each narrows the list to the keys
+/// carrying it, and selected tags combine.
+fn build_tag_filter(ctx: &KeysPageCtx) -> Markup {
+    let KeysPageCtx { provider, status: current_status, q, sort_by, sort_order, tag_counts, .. } = *ctx;
+    if tag_counts.is_empty() {
+        return html! {};
+    }
+    html! {
+        div class="flex flex-wrap items-center gap-2 px-6 py-3 border-b border-gray-200/60 bg-white/20" {
+            span class="text-xs font-semibold text-gray-600 mr-1" { "Tags" }
+            @for count in tag_counts {
+                @let selected = q.split_whitespace().any(|t| t.strip_prefix("tag:") == Some(count.tag.as_str()));
+                @let link = build_page_link(provider, current_status, &toggle_tag_filter(q, &count.tag), 1, 20, sort_by, sort_order);
+                @let classes = if selected {
+                    "bg-blue-600 text-white border-blue-600"
+                } else {
+                    "bg-white/80 text-gray-700 border-gray-300 hover:border-gray-400"
+                };
+                a href=(link) class={"px-2.5 py-1 rounded-lg text-xs font-medium border transition-colors " (classes)} {
+                    (count.tag) " " span class="opacity-70" { (count.keys) }
+                }
+            }
+        }
+    }
+}
+
 fn build_search_form(provider: &str, current_status: &str) -> Markup {
     html! {
         form id="search-form" method="GET" action={"/keys/" (provider)} class="hidden" {
@@ -809,7 +2201,9 @@ fn build_table_content(
                     col class="w-12";
                     col class="w-80";
                     col class="w-32";
+                    col class="w-56";
                     col class="w-24";
+                    col class="w-28";
                 }
                 thead {
                     tr class="bg-gradient-to-r from-slate-100/90 to-gray-100/90 border-b border-gray-400/80 backdrop-blur-sm" {
@@ -820,7 +2214,9 @@ fn build_table_content(
                         }
                         th class="p-4 text-left font-semibold text-slate-800 text-sm tracking-wide" { "API Key" }
                         (sortable_th("Cooling Time", "totalCoolingSeconds", provider, current_status, q, sort_by, sort_order))
+                        th class="p-4 text-left font-semibold text-slate-800 text-sm tracking-wide" { "Cooling Models" }
                         (sortable_th("Used Time", "createdAt", provider, current_status, q, sort_by, sort_order))
+                        th class="p-4 text-left font-semibold text-slate-800 text-sm tracking-wide" { "Tier" }
                     }
                 }
                 tbody class="divide-y divide-gray-300/60" {
@@ -831,7 +2227,7 @@ fn build_table_content(
     }
 }
 
-fn build_key_rows(keys: Vec<ApiKey>) -> Markup {
+fn build_key_rows(keys: Vec<ApiKey>, current_status: &str) -> Markup {
     if keys.is_empty() {
         return build_empty_state();
     }
@@ -843,16 +2239,88 @@ fn build_key_rows(keys: Vec<ApiKey>) -> Markup {
                            class="h-4 w-4 text-blue-600 bg-white border-gray-500 rounded focus:ring-blue-500 focus:ring-2 transition-colors backdrop-blur-sm";
                 }
                 td class="p-4" {
-                    (build_copyable_key(&k.key))
+                    (build_masked_key(&k.id, &k.key))
+                    @if k.status == ApiKeyStatus::Blocked && !k.block_reason.is_empty() {
+                        div class="mt-1 text-xs text-red-700" { (k.block_reason) }
+                    }
+                    @if !k.note.is_empty() {
+                        div class="mt-1 text-xs text-slate-600 truncate" title=(k.note) { (k.note) }
+                    }
+                    @if !k.tags.is_empty() {
+                        div class="mt-1 flex flex-wrap gap-1" {
+                            @for tag in &k.tags {
+                                a href=(build_page_link(&k.provider, current_status, &format!("tag:{}", tag), 1, 20, "", "desc"))
+                                  class="px-2 py-0.5 rounded-md text-xs font-medium bg-blue-50 text-blue-700 hover:bg-blue-100" { (tag) }
+                            }
+                        }
+                    }
                 }
                 td class="p-4" {
                     span class="text-sm text-slate-800 cursor-pointer hover:text-blue-700 transition-colors duration-200 font-medium px-2 py-1 rounded-md hover:bg-blue-100/80 backdrop-blur-sm"
-                          title="Click to view model cooling details"
-                          onclick=(format!("showModelCoolings('{}', '{}')", k.id, k.key)) { (format_cooling_time(k.total_cooling_seconds)) }
+                          title=(format!("{} in total. Click to view model cooling details", format_exact_duration(k.total_cooling_seconds)))
+                          onclick=(format!("showModelCoolings('{}', '{}')", k.id, util::partially_redact_key(&k.key))) { (format_cooling_time(k.total_cooling_seconds)) }
+                }
+                td class="p-4" {
+                    (build_cooling_models(&k))
+                }
+                td class="p-4 text-sm text-slate-700 font-medium" { (timestamp(k.created_at, "")) }
+                td class="p-4" {
+                    div class="flex items-center gap-1" {
+                        @if k.tier == KeyTier::Paid {
+                            span class="px-2 py-1 rounded-md text-xs font-semibold bg-amber-100 text-amber-800" { "paid" }
+                        } @else {
+                            span class="px-2 py-1 rounded-md text-xs font-semibold bg-green-100 text-green-800" { "free" }
+                        }
+                        button type="button" title="Edit note, tags, weight and tier"
+                               data-key-id=(k.id) data-note=(k.note) data-tags=(k.tags.join(" "))
+                               data-weight=(k.weight) data-tier=(k.tier.as_str())
+                               onclick="openKeyEditor(this)"
+                               class="p-1 rounded-md text-slate-500 hover:text-blue-700 hover:bg-blue-100/80" {
+                            svg class="w-4 h-4" fill="none" stroke="currentColor" viewBox="0 0 24 24" {
+                                path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M15.232 5.232l3.536 3.536M9 13l6.232-6.232a2.5 2.5 0 113.536 3.536L12.536 16.536 9 17l.464-3.536z" {}
+                            }
+                        }
+                    }
+                    @if k.weight != 0 {
+                        div class="mt-1 text-xs text-slate-600" title="Added to the key's health score" {
+                            "weight " (format!("{:+}", k.weight))
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// How many cooling models are listed by name before the rest are counted.
+const MAX_COOLING_BADGES: usize = 3;
+
+/// The models a key is cooling for, soonest to recover first, and when the first recovers.
+fn build_cooling_models(k: &ApiKey) -> Markup {
+    let now = Date::now().as_millis() / 1000;
+    let mut coolings: Vec<(&String, u64)> = k.model_coolings.iter().map(|(model, end_at)| (model, *end_at)).collect();
+    coolings.sort_by_key(|(model, end_at)| (*end_at, model.as_str()));
+    let Some((_, soonest)) = coolings.first().copied() else {
+        return html! { span class="text-sm text-slate-400" { "-" } };
+    };
+    let hidden: Vec<&str> = coolings.iter().skip(MAX_COOLING_BADGES).map(|(model, _)| model.as_str()).collect();
+    let soonest_iso = format_iso_utc(soonest);
+    let soonest_relative = format!("next ends in {}", format_exact_duration(soonest.saturating_sub(now)));
+    html! {
+        div class="flex flex-wrap gap-1" {
+            @for (model, end_at) in coolings.iter().take(MAX_COOLING_BADGES) {
+                span class="px-2 py-0.5 rounded-md text-xs font-mono bg-red-50 text-red-700 truncate max-w-full"
+                     title=(format!("{}: {} left", model, format_exact_duration(end_at.saturating_sub(now)))) { (model) }
+            }
+            @if !hidden.is_empty() {
+                span class="px-2 py-0.5 rounded-md text-xs font-medium bg-slate-100 text-slate-600" title=(hidden.join(", ")) {
+                    "+" (hidden.len())
                 }
-                td class="p-4 text-sm text-slate-700 font-medium" { (format_used_time(k.created_at)) }
             }
         }
+        div class="text-xs text-slate-500 mt-1" {
+            time datetime=(soonest_iso) data-ts=(soonest) data-relative=(soonest_relative) title=(soonest_iso) { (soonest_relative) }
+        }
     }
 }
 
@@ -902,6 +2370,21 @@ fn build_copyable_key(key: &str) -> Markup {
     }
 }
 
+/// Shows a stored key masked. The first click fetches and shows the full key, later
+/// clicks copy it.
+fn build_masked_key(key_id: &str, key: &str) -> Markup {
+    html! {
+        div class="relative inline-block" {
+            code class="px-3 py-2 bg-slate-200/80 border border-slate-300/70 rounded-lg text-sm font-mono text-slate-900 cursor-pointer hover:bg-slate-300/80 hover:border-slate-400/70 transition-all duration-200 inline-block truncate max-w-full group-hover:shadow-sm backdrop-blur-sm"
+                  onclick=(format!("revealKey('{}', this)", key_id))
+                  title="Click to reveal" { (util::partially_redact_key(key)) }
+            div class="absolute -top-8 left-1/2 transform -translate-x-1/2 bg-emerald-700 text-white text-xs px-2 py-1 rounded opacity-0 pointer-events-none transition-opacity duration-300 whitespace-nowrap copy-tooltip backdrop-blur-sm" {
+                "Copied!"
+            }
+        }
+    }
+}
+
 fn format_used_time(created_at: u64) -> String {
     let now = Date::now().as_millis() / 1000;
     let used_seconds = now.saturating_sub(created_at);
@@ -918,6 +2401,53 @@ fn format_used_time(created_at: u64) -> String {
     }
 }
 
+/// A Unix time as an ISO 8601 UTC string, e.g. `2025-07-21T09:30:00Z`.
+fn format_iso_utc(unix_seconds: u64) -> String {
+    match time::OffsetDateTime::from_unix_timestamp(unix_seconds as i64) {
+        Ok(t) => format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            t.year(),
+            t.month() as u8,
+            t.day(),
+            t.hour(),
+            t.minute(),
+            t.second()
+        ),
+        Err(_) => unix_seconds.to_string(),
+    }
+}
+
+/// A point in time, shown as the time elapsed since then followed by `suffix`. The exact
+/// UTC time is kept in `datetime`; the page script shows it in the browser's timezone as
+/// the tooltip and, when the operator switches to absolute times, as the text.
+fn timestamp(unix_seconds: u64, suffix: &str) -> Markup {
+    let iso = format_iso_utc(unix_seconds);
+    let relative = format!("{}{}", format_used_time(unix_seconds), suffix);
+    html! {
+        time datetime=(iso) data-ts=(unix_seconds) data-relative=(relative) title=(iso) { (relative) }
+    }
+}
+
+/// A duration to the second, e.g. `2d 3h 4m 5s`.
+fn format_exact_duration(total_seconds: u64) -> String {
+    let parts = [
+        (total_seconds / 86400, "d"),
+        ((total_seconds % 86400) / 3600, "h"),
+        ((total_seconds % 3600) / 60, "m"),
+        (total_seconds % 60, "s"),
+    ];
+    let text: Vec<String> = parts
+        .iter()
+        .filter(|(value, _)| *value > 0)
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect();
+    if text.is_empty() {
+        "0s".to_string()
+    } else {
+        text.join(" ")
+    }
+}
+
 fn format_cooling_time(total_seconds: u64) -> String {
     if total_seconds == 0 {
         return "-".to_string();
@@ -938,7 +2468,7 @@ fn format_cooling_time(total_seconds: u64) -> String {
 fn build_empty_state() -> Markup {
     html! {
         tr {
-            td colspan="4" class="text-center p-12 text-gray-700 bg-slate-100/40 backdrop-blur-sm" {
+            td colspan="6" class="text-center p-12 text-gray-700 bg-slate-100/40 backdrop-blur-sm" {
                 div class="flex flex-col items-center gap-3" {
                     svg class="w-12 h-12 text-gray-500" fill="none" stroke="currentColor" viewBox="0 0 24 24" {
                         path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M20 13V6a2 2 0 00-2-2H6a2 2 0 00-2 2v7m16 0v5a2 2 0 01-2 2H6a2 2 0 01-2-2v-5m16 0h-2.586a1 1 0 00-.707.293l-2.414 2.414a1 1 0 01-.707.293h-3.172a1 1 0 01-.707-.293l-2.414-2.414A1 1 0 006.586 13H4" {}
@@ -968,17 +2498,9 @@ fn build_table_footer(total: i32, pagination_controls: &Markup) -> Markup {
     }
 }
 
-fn build_pagination_controls(
-    provider: &str,
-    current_status: &str,
-    q: &str,
-    page: usize,
-    page_size: usize,
-    total: usize,
-    sort_by: &str,
-    sort_order: &str,
-) -> Markup {
-    let num_pages = (total as f64 / page_size as f64).ceil() as usize;
+fn build_pagination_controls(ctx: &KeysPageCtx, total: usize) -> Markup {
+    let page = ctx.page;
+    let num_pages = (total as f64 / ctx.page_size as f64).ceil() as usize;
     if num_pages <= 1 {
         return html! {};
     }
@@ -990,15 +2512,15 @@ fn build_pagination_controls(
     let next_disabled = page >= num_pages;
 
     html! {
-        (build_pagination_button("prev", prev_page, prev_disabled, provider, current_status, q, sort_by, sort_order))
+        (build_pagination_button(ctx, "prev", prev_page, prev_disabled))
         @for p in page_numbers {
             @if let Some(page_num) = p {
-                (build_page_number_button(page_num, page, provider, current_status, q, sort_by, sort_order))
+                (build_page_number_button(ctx, page_num))
             } @else {
                 span class="px-3 py-2 text-sm font-medium text-gray-500" { "..." }
             }
         }
-        (build_pagination_button("next", next_page, next_disabled, provider, current_status, q, sort_by, sort_order))
+        (build_pagination_button(ctx, "next", next_page, next_disabled))
     }
 }
 
@@ -1032,23 +2554,14 @@ fn generate_page_numbers(current_page: usize, total_pages: usize) -> Vec<Option<
     pages
 }
 
-fn build_pagination_button(
-    btn_type: &str,
-    target_page: usize,
-    disabled: bool,
-    provider: &str,
-    status: &str,
-    q: &str,
-    sort_by: &str,
-    sort_order: &str,
-) -> Markup {
+fn build_pagination_button(ctx: &KeysPageCtx, btn_type: &str, target_page: usize, disabled: bool) -> Markup {
     let icon = if btn_type == "prev" {
         html! { path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M15 19l-7-7 7-7" {} }
     } else {
         html! { path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M9 5l7 7-7 7" {} }
     };
 
-    let link = build_page_link(provider, status, q, target_page, 20, sort_by, sort_order);
+    let link = build_page_link(ctx.provider, ctx.status, ctx.q, target_page, 20, ctx.sort_by, ctx.sort_order);
     let base_classes = "p-2 rounded-lg text-sm font-medium transition-all duration-200";
     let disabled_classes =
         "bg-gray-200 text-gray-400 cursor-not-allowed border border-gray-300 pointer-events-none";
@@ -1078,17 +2591,9 @@ fn build_pagination_button(
     }
 }
 
-fn build_page_number_button(
-    page_item: usize,
-    current_page: usize,
-    provider: &str,
-    status: &str,
-    q: &str,
-    sort_by: &str,
-    sort_order: &str,
-) -> Markup {
-    let is_current = page_item == current_page;
-    let link = build_page_link(provider, status, q, page_item, 20, sort_by, sort_order);
+fn build_page_number_button(ctx: &KeysPageCtx, page_item: usize) -> Markup {
+    let is_current = page_item == ctx.page;
+    let link = build_page_link(ctx.provider, ctx.status, ctx.q, page_item, 20, ctx.sort_by, ctx.sort_order);
     let base_classes = "px-3 py-2 rounded-lg text-sm font-medium transition-all duration-200";
     let current_classes = "bg-blue-600 text-white shadow-lg shadow-blue-600/30 border border-blue-600 pointer-events-none";
     let other_classes = "bg-white text-gray-800 hover:bg-gray-50 border border-gray-300 hover:border-gray-400 shadow-sm";
@@ -1146,14 +2651,8 @@ fn build_page_link(
 
 // endregion: --- Keys List Page
 
-fn build_add_keys_form(
-    provider: &str,
-    current_status: &str,
-    q: &str,
-    page: usize,
-    sort_by: &str,
-    sort_order: &str,
-) -> Markup {
+fn build_add_keys_form(ctx: &KeysPageCtx) -> Markup {
+    let provider = ctx.provider;
     html! {
         div class="glass-card bg-white/80 rounded-3xl shadow-xl p-6 border border-gray-200 max-w-5xl mx-auto" {
             div class="flex items-center gap-3 mb-6" {
@@ -1165,7 +2664,7 @@ fn build_add_keys_form(
                 h2 class="text-xl font-bold text-gray-900" { "Add New Keys" }
             }
             form method="POST" {
-                input type="hidden" name="action" value="add";
+                (csrf_field(ctx.csrf_token))
                 div class="mb-6" {
                     label class="block text-gray-800 text-sm font-semibold mb-3" { "API Keys" }
                     textarea name="keys"
@@ -1173,8 +2672,13 @@ fn build_add_keys_form(
                               rows="4"
                               placeholder="Enter API keys, one per line or separated by commas" {}
                 }
-                div class="flex justify-end" {
-                    button type="submit"
+                div class="flex justify-end gap-3" {
+                    button type="submit" name="action" value="preview"
+                            formaction={"/keys/" (provider)}
+                            class="px-6 py-3 bg-white border border-gray-300 hover:bg-gray-50 text-gray-800 font-semibold rounded-xl" {
+                        "Detect Providers"
+                    }
+                    button type="submit" name="action" value="add"
                             formaction={"/keys/" (provider)}
                             class="btn-primary px-6 py-3 text-white font-semibold rounded-xl focus:outline-none focus:ring-4 focus:ring-blue-200" {
                         "Add Keys"
@@ -1185,13 +2689,96 @@ fn build_add_keys_form(
     }
 }
 
+/// Shows which provider each pasted key will be added to before anything is stored.
+fn key_preview_page(provider: &str, keys_str: &str, csrf_token: &str) -> Markup {
+    let groups = key_format::group_by_provider(keys_str, provider);
+    let rejected: Vec<String> = groups
+        .iter()
+        .flat_map(|(group, keys)| keys.iter().filter_map(|key| key_format::check(group, key).err()))
+        .collect();
+    html! {
+        div class="mb-8" {
+            nav class="flex items-center space-x-2 text-sm text-gray-600 mb-4" {
+                a href="/" class="hover:text-blue-600 transition-colors duration-200 font-medium" { "Providers" }
+                svg class="w-4 h-4" fill="none" stroke="currentColor" viewBox="0 0 24 24" {
+                    path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M9 5l7 7-7 7" {}
+                }
+                a href={"/keys/" (provider)} class="hover:text-blue-600 transition-colors duration-200 font-medium" { (provider) }
+                svg class="w-4 h-4" fill="none" stroke="currentColor" viewBox="0 0 24 24" {
+                    path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M9 5l7 7-7 7" {}
+                }
+                span class="text-gray-900 font-semibold" { "Detected Providers" }
+            }
+        }
+        div class="glass-card bg-white/80 rounded-3xl shadow-xl border border-gray-200 overflow-hidden mb-8 max-w-5xl mx-auto backdrop-blur-xl" {
+            table class="w-full" {
+                thead {
+                    tr class="bg-gradient-to-r from-slate-100/90 to-gray-100/90 border-b border-gray-400/80" {
+                        th class="p-4 text-left font-semibold text-slate-800 text-sm" { "Provider" }
+                        th class="p-4 text-left font-semibold text-slate-800 text-sm" { "Keys" }
+                        th class="p-4 text-left font-semibold text-slate-800 text-sm" { "Preview" }
+                    }
+                }
+                tbody class="divide-y divide-gray-300/60" {
+                    @if groups.is_empty() {
+                        tr {
+                            td colspan="3" class="text-center p-12 text-gray-700 bg-slate-100/40" { "No keys were pasted" }
+                        }
+                    }
+                    @for (group, keys) in &groups {
+                        tr class="even:bg-slate-100/40 odd:bg-white/60" {
+                            td class="p-4 text-sm font-medium text-slate-900" {
+                                (group)
+                                @if group != provider {
+                                    span class="ml-2 px-2 py-0.5 bg-blue-100 text-blue-800 text-xs font-semibold rounded-full" { "Detected" }
+                                }
+                            }
+                            td class="p-4 text-sm text-slate-700" { (keys.len()) }
+                            td class="p-4 font-mono text-sm text-slate-700" {
+                                @for key in keys.iter().take(3) {
+                                    div { (util::partially_redact_key(key)) }
+                                }
+                                @if keys.len() > 3 {
+                                    div class="text-gray-500" { "and " (keys.len() - 3) " more" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        div class="glass-card bg-white/80 rounded-3xl shadow-xl p-6 border border-gray-200 max-w-5xl mx-auto" {
+            @if !rejected.is_empty() {
+                div class="mb-6 p-4 bg-red-50 border border-red-200 rounded-xl text-sm text-red-800" {
+                    p class="font-semibold mb-2" { "Fix these keys before adding:" }
+                    @for message in &rejected {
+                        div { (message) }
+                    }
+                }
+            }
+            form method="POST" action={"/keys/" (provider)} class="flex justify-end gap-3" {
+                (csrf_field(csrf_token))
+                input type="hidden" name="keys" value=(keys_str);
+                a href={"/keys/" (provider)}
+                        class="px-6 py-3 bg-white border border-gray-300 hover:bg-gray-50 text-gray-800 font-semibold rounded-xl" { "Cancel" }
+                @if rejected.is_empty() && !groups.is_empty() {
+                    button type="submit" name="action" value="add-detected"
+                            class="btn-primary px-6 py-3 text-white font-semibold rounded-xl focus:outline-none focus:ring-4 focus:ring-blue-200" {
+                        "Add to " (groups.len()) @if groups.len() == 1 { " Provider" } @else { " Providers" }
+                    }
+                }
+            }
+        }
+    }
+}
+
 fn build_model_coolings_modal() -> Markup {
     html! {
         div id="modelCoolingsModal" class="fixed inset-0 bg-black bg-opacity-50 backdrop-blur-sm hidden items-center justify-center z-50" onclick="closeModal(event)" {
-            div class="glass-card bg-white rounded-3xl shadow-2xl border border-gray-200 max-w-2xl w-full mx-6 max-h-[80vh] overflow-hidden" onclick="event.stopPropagation()" {
+            div class="glass-card bg-white rounded-3xl shadow-2xl border border-gray-200 max-w-4xl w-full mx-6 max-h-[80vh] overflow-hidden" onclick="event.stopPropagation()" {
                 div class="p-6 border-b border-gray-200 bg-white/80" {
                     div class="flex items-center justify-between" {
-                        h3 class="text-xl font-bold text-gray-900" { "Model Cooling Details" }
+                        h3 class="text-xl font-bold text-gray-900" { "Key Details" }
                         button onclick="closeModal()" class="p-2 hover:bg-gray-100 rounded-lg transition-colors duration-200" {
                             svg class="w-5 h-5 text-gray-500" fill="none" stroke="currentColor" viewBox="0 0 24 24" {
                                 path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M6 18L18 6M6 6l12 12" {}
@@ -1200,8 +2787,65 @@ fn build_model_coolings_modal() -> Markup {
                     }
                     p class="text-sm text-gray-600 mt-2" { "Key: " span id="modalKeyName" class="font-mono" {} }
                 }
-                div class="p-6 overflow-y-auto max-h-96" {
+                div class="p-6 overflow-y-auto max-h-[60vh]" {
+                    h4 class="font-semibold text-gray-900 mb-3" { "Model Coolings" }
                     div id="modelCoolingsTable" {}
+                    h4 class="font-semibold text-gray-900 mt-6 mb-1" { "Routing Scores" }
+                    p class="text-xs text-gray-500 mb-3" {
+                        "Score components when sampled requests ranked this key (rank 0 is tried first). Total = latency + success - failures + recent success - fairness + weight."
+                    }
+                    div id="keyScoresTable" {}
+                    h4 class="font-semibold text-gray-900 mt-6 mb-1" { "Recent Events" }
+                    p class="text-xs text-gray-500 mb-3" {
+                        "Why the key was blocked, cooled down or reactivated, with the provider's message."
+                    }
+                    div id="keyEventsTable" {}
+                }
+            }
+        }
+    }
+}
+
+/// The dialog behind the edit button of a key row, saved through `PATCH /api/keys/{id}`.
+fn build_key_editor_modal(csrf_token: &str) -> Markup {
+    html! {
+        div id="keyEditorModal" class="fixed inset-0 bg-black bg-opacity-50 backdrop-blur-sm hidden items-center justify-center z-50" onclick="closeKeyEditor(event)" {
+            div class="glass-card bg-white rounded-3xl shadow-2xl border border-gray-200 max-w-lg w-full mx-6" onclick="event.stopPropagation()" {
+                form id="keyEditorForm" onsubmit="saveKeyMetadata(event)" class="p-6 space-y-4" {
+                    (csrf_field(csrf_token))
+                    h3 class="text-xl font-bold text-gray-900" { "Edit Key" }
+                    label class="block text-sm font-medium text-gray-700" {
+                        "Note"
+                        textarea name="note" rows="3" maxlength=(strategy::MAX_KEY_NOTE_CHARS)
+                                 class="mt-1 w-full px-3 py-2 bg-white border border-gray-300 rounded-xl text-gray-900 text-sm" {}
+                    }
+                    label class="block text-sm font-medium text-gray-700" {
+                        "Tags"
+                        input type="text" name="tags" placeholder="team-a prod"
+                              class="mt-1 w-full px-3 py-2 bg-white border border-gray-300 rounded-xl text-gray-900 text-sm";
+                        span class="block mt-1 text-xs text-gray-500" {
+                            "Separated by spaces or commas. Search for " code { "tag:team-a" } " to list the keys carrying a tag."
+                        }
+                    }
+                    div class="flex gap-4" {
+                        label class="block text-sm font-medium text-gray-700" title="Added to the key's health score; higher is tried earlier" {
+                            "Weight"
+                            input type="number" name="weight" min=(-strategy::MAX_KEY_WEIGHT) max=(strategy::MAX_KEY_WEIGHT)
+                                  class="mt-1 w-32 px-3 py-2 bg-white border border-gray-300 rounded-xl text-gray-900 text-sm";
+                        }
+                        label class="block text-sm font-medium text-gray-700" {
+                            "Tier"
+                            select name="tier" class="mt-1 w-32 px-3 py-2 bg-white border border-gray-300 rounded-xl text-gray-900 text-sm" {
+                                option value="free" { "free" }
+                                option value="paid" { "paid" }
+                            }
+                        }
+                    }
+                    p id="keyEditorError" class="text-sm text-red-600 hidden" {}
+                    div class="flex justify-end gap-2" {
+                        button type="button" onclick="closeKeyEditor()" class="px-4 py-2 text-sm text-gray-700 border border-gray-300 rounded-xl hover:bg-gray-50" { "Cancel" }
+                        button type="submit" class="px-4 py-2 text-sm bg-blue-600 hover:bg-blue-700 text-white font-semibold rounded-xl" { "Save" }
+                    }
                 }
             }
         }
@@ -1249,7 +2893,7 @@ fn build_test_results_table(results: Vec<testing::TestResult>) -> Markup {
             tbody {
                 @for result in results {
                     tr class="border-b border-gray-200" {
-                        td class="p-4 font-mono text-sm" { (result.key) }
+                        td class="p-4 font-mono text-sm" { (util::partially_redact_key(&result.key)) }
                         td class="p-4" {
                             @if result.passed {
                                 span class="px-3 py-1 bg-green-100 text-green-800 text-xs font-semibold rounded-full" { "Passed" }
@@ -1297,7 +2941,43 @@ impl From<worker::Error> for WebError {
 */
 
 // region: --- PageLayout Extractor
-pub struct PageLayout;
+/// Extractor for the UI: the caller must be logged in, with a role that may use the route
+/// (see `required_role`).
+pub struct PageLayout {
+    pub role: Role,
+    /// The `auth::Principal::subject` the admin rate limits are counted for.
+    subject: String,
+    /// The session's token for the forms of the page, see `check_csrf`.
+    csrf_token: String,
+    theme: Theme,
+}
+
+impl PageLayout {
+    /// Rejects a post whose CSRF token is missing or isn't the session's.
+    fn check_csrf(&self, submitted: Option<&str>) -> Option<Response> {
+        let valid = submitted.is_some_and(|token| auth::constant_time_eq(token.as_bytes(), self.csrf_token.as_bytes()));
+        (!valid).then(|| {
+            warn!(subject = %self.subject, "Rejected a post with a missing or invalid CSRF token.");
+            (StatusCode::FORBIDDEN, "Invalid or missing CSRF token. Reload the page and try again.").into_response()
+        })
+    }
+}
+
+/// The role a UI route needs: the client keys page is for admins, changing or revealing
+/// keys for operators, and browsing and the caller's own account for viewers. Provider settings changed from the keys
+/// page are checked by the handler.
+fn required_role(method: &Method, path: &str) -> Role {
+    if path.starts_with("/account") {
+        // Everyone manages their own second factor.
+        Role::Viewer
+    } else if path.starts_with("/clients") {
+        Role::Admin
+    } else if method != Method::GET || path.ends_with("/reveal") {
+        Role::Operator
+    } else {
+        Role::Viewer
+    }
+}
 
 impl<S> FromRequestParts<S> for PageLayout
 where
@@ -1318,14 +2998,30 @@ where
                     .into_response()
             })?;
 
-        if let Some(cookie) = cookies.get("auth_key") {
-            let auth_key = cookie.value().to_string();
-            if util::is_valid_auth_key(&auth_key, &app_state.env) {
-                return Ok(PageLayout);
-            }
-        }
+        // Sessions are looked up in D1, whose futures aren't `Send` as extractors must be.
+        let Some(principal) = SendFuture::new(auth::from_cookies(&app_state, &cookies)).await else {
+            return Err(Redirect::to("/login").into_response());
+        };
+        let role = principal.role;
 
-        Err(Redirect::to("/login").into_response())
+        let required = required_role(&parts.method, parts.uri.path());
+        if role < required {
+            return Err((
+                StatusCode::FORBIDDEN,
+                format!("This page requires the {} role.", required.as_str()),
+            )
+                .into_response());
+        }
+        let session_id = principal.session_id.unwrap_or_default();
+        let csrf_token = SendFuture::new(auth::csrf_token(&app_state.env, &session_id))
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to derive the CSRF token: {}", e)).into_response())?;
+        Ok(PageLayout {
+            role,
+            subject: principal.subject,
+            csrf_token,
+            theme: Theme::from_cookies(&cookies),
+        })
     }
 }
 //impl<S, B> FromRequest<S, B> for PageLayout
@@ -1,3 +1,10 @@
+// The page carries `data-theme` only when the theme was picked in the footer toggle; otherwise
+// follow the browser's preference. This runs in the head so the page never flashes light.
+if (!document.documentElement.dataset.theme
+    && window.matchMedia && window.matchMedia('(prefers-color-scheme: dark)').matches) {
+    document.documentElement.dataset.theme = 'dark';
+}
+
 
 
 function closeTestResultsModal(event) {
@@ -38,6 +45,25 @@ function copyToClipboard(text, element) {
     });
 }
 
+async function revealKey(keyId, element) {
+    if (element.dataset.revealed) {
+        copyToClipboard(element.textContent, element);
+        return;
+    }
+    try {
+        const response = await fetch(`/api/keys/${keyId}/reveal`);
+        if (!response.ok) {
+            throw new Error(`HTTP error! status: ${response.status}`);
+        }
+        const data = await response.json();
+        element.textContent = data.key;
+        element.dataset.revealed = 'true';
+        element.title = 'Click to copy';
+    } catch (error) {
+        console.error('Failed to reveal key:', error);
+    }
+}
+
 async function showModelCoolings(keyId, keyName) {
     const modalKeyName = document.getElementById('modalKeyName');
     const modalTable = document.getElementById('modelCoolingsTable');
@@ -47,6 +73,8 @@ async function showModelCoolings(keyId, keyName) {
     modalTable.innerHTML = '<p class=\"text-gray-600 text-center py-8\">Loading...</p>';
     modal.classList.remove('hidden');
     modal.classList.add('flex');
+    showKeyScores(keyId);
+    showKeyEvents(keyId);
 
     try {
         const response = await fetch(`/api/keys/${keyId}/coolings`);
@@ -63,6 +91,7 @@ async function showModelCoolings(keyId, keyName) {
             const rows = Object.entries(modelCoolings).map(([model, coolingEnd]) => {
                 const isAvailable = coolingEnd < now;
                 const remainingTime = isAvailable ? '-' : formatTime(coolingEnd - now);
+                const coolingEndAt = new Date(coolingEnd * 1000);
                 // We don't have total_seconds from this endpoint, so we can't display it.
                 // You might need to adjust your API if this is required.
                 const statusClass = isAvailable ? 'text-green-600 bg-green-50' : 'text-red-600 bg-red-50';
@@ -71,7 +100,7 @@ async function showModelCoolings(keyId, keyName) {
                 return `
                     <tr class=\"border-b border-gray-200\">
                         <td class=\"p-3 font-mono text-sm\">${model}</td>
-                        <td class=\"p-3 text-sm\">${remainingTime}</td>
+                        <td class=\"p-3 text-sm\" title=\"Until ${coolingEndAt.toLocaleString(undefined, { timeZoneName: 'short' })} (${coolingEndAt.toISOString()})\">${remainingTime}</td>
                         <td class=\"p-3\">
                             <span class=\"px-2 py-1 rounded-lg text-xs font-medium ${statusClass}\">${status}</span>
                         </td>
@@ -100,6 +129,132 @@ async function showModelCoolings(keyId, keyName) {
     }
 }
 
+async function showKeyScores(keyId) {
+    const scoresTable = document.getElementById('keyScoresTable');
+    scoresTable.innerHTML = '<p class=\"text-gray-600 text-center py-4\">Loading...</p>';
+
+    try {
+        const response = await fetch(`/api/keys/${keyId}/scores`);
+        if (!response.ok) {
+            throw new Error(`HTTP error! status: ${response.status}`);
+        }
+        const scores = await response.json();
+
+        if (scores.length === 0) {
+            scoresTable.innerHTML = '<p class=\"text-gray-600 text-center py-4\">No sampled rankings. Set SCORE_SAMPLE_RATE_PERCENT to record them.</p>';
+            return;
+        }
+        const rows = scores.map(score => {
+            const at = new Date(score.created_at * 1000);
+            return `
+                <tr class=\"border-b border-gray-200\">
+                    <td class=\"p-2 text-xs\" title=\"${escapeHtml(`${at.toISOString()} (request ${score.request_id})`)}\">${escapeHtml(at.toLocaleString())}</td>
+                    <td class=\"p-2 font-mono text-xs\">${escapeHtml(score.model)}</td>
+                    <td class=\"p-2 text-sm text-right\">${escapeHtml(score.rank)}</td>
+                    <td class=\"p-2 text-sm text-right\">${escapeHtml(score.latency_score)}</td>
+                    <td class=\"p-2 text-sm text-right\">${escapeHtml(score.success_score)}</td>
+                    <td class=\"p-2 text-sm text-right\">-${escapeHtml(score.failure_penalty)}</td>
+                    <td class=\"p-2 text-sm text-right\">+${escapeHtml(score.recent_success_bonus)}</td>
+                    <td class=\"p-2 text-sm text-right\">${escapeHtml(-score.fairness_penalty)}</td>
+                    <td class=\"p-2 text-sm text-right\">${escapeHtml(score.weight)}</td>
+                    <td class=\"p-2 text-sm text-right font-semibold\">${escapeHtml(score.total)}</td>
+                </tr>
+            `;
+        }).join('');
+
+        scoresTable.innerHTML = `
+            <table class=\"w-full\">
+                <thead>
+                    <tr class=\"border-b border-gray-200 bg-gray-50\">
+                        <th class=\"p-2 text-left text-xs font-semibold text-gray-900\">When</th>
+                        <th class=\"p-2 text-left text-xs font-semibold text-gray-900\">Model</th>
+                        <th class=\"p-2 text-right text-xs font-semibold text-gray-900\">Rank</th>
+                        <th class=\"p-2 text-right text-xs font-semibold text-gray-900\">Latency</th>
+                        <th class=\"p-2 text-right text-xs font-semibold text-gray-900\">Success</th>
+                        <th class=\"p-2 text-right text-xs font-semibold text-gray-900\">Failures</th>
+                        <th class=\"p-2 text-right text-xs font-semibold text-gray-900\">Recent</th>
+                        <th class=\"p-2 text-right text-xs font-semibold text-gray-900\">Fairness</th>
+                        <th class=\"p-2 text-right text-xs font-semibold text-gray-900\">Weight</th>
+                        <th class=\"p-2 text-right text-xs font-semibold text-gray-900\">Total</th>
+                    </tr>
+                </thead>
+                <tbody>
+                    ${rows}
+                </tbody>
+            </table>
+        `;
+    } catch (e) {
+        console.error('Error fetching key scores:', e);
+        scoresTable.innerHTML = `<p class=\"text-red-600 text-center py-4\">Error: ${e.message}</p>`;
+    }
+}
+
+// Escapes quotes too, so the result is safe inside attribute values.
+function escapeHtml(text) {
+    const div = document.createElement('div');
+    div.textContent = text;
+    return div.innerHTML.replace(/"/g, '&quot;').replace(/'/g, '&#39;');
+}
+
+async function showKeyEvents(keyId) {
+    const eventsTable = document.getElementById('keyEventsTable');
+    eventsTable.innerHTML = '<p class=\"text-gray-600 text-center py-4\">Loading...</p>';
+
+    try {
+        const response = await fetch(`/api/keys/${keyId}/events`);
+        if (!response.ok) {
+            throw new Error(`HTTP error! status: ${response.status}`);
+        }
+        const events = await response.json();
+
+        if (events.length === 0) {
+            eventsTable.innerHTML = '<p class=\"text-gray-600 text-center py-4\">No blocks or cooldowns recorded.</p>';
+            return;
+        }
+        const eventClasses = {
+            blocked: 'text-red-600 bg-red-50',
+            cooldown: 'text-amber-700 bg-amber-50',
+            reactivated: 'text-green-600 bg-green-50',
+        };
+        // Provider messages are untrusted, so everything from the API is escaped.
+        const rows = events.map(event => {
+            const at = new Date(event.created_at * 1000);
+            const eventClass = eventClasses[event.event] || 'text-gray-700 bg-gray-50';
+            return `
+                <tr class=\"border-b border-gray-200 align-top\">
+                    <td class=\"p-2 text-xs whitespace-nowrap\" title=\"${at.toISOString()}\">${at.toLocaleString()}</td>
+                    <td class=\"p-2\"><span class=\"px-2 py-1 rounded-lg text-xs font-medium ${eventClass}\">${escapeHtml(event.event)}</span></td>
+                    <td class=\"p-2 font-mono text-xs\">${escapeHtml(event.model || '-')}</td>
+                    <td class=\"p-2 text-sm text-right\">${event.status || '-'}</td>
+                    <td class=\"p-2 text-xs\">${escapeHtml(event.error_class || '-')}</td>
+                    <td class=\"p-2 font-mono text-xs break-all\">${escapeHtml(event.message)}</td>
+                </tr>
+            `;
+        }).join('');
+
+        eventsTable.innerHTML = `
+            <table class=\"w-full\">
+                <thead>
+                    <tr class=\"border-b border-gray-200 bg-gray-50\">
+                        <th class=\"p-2 text-left text-xs font-semibold text-gray-900\">When</th>
+                        <th class=\"p-2 text-left text-xs font-semibold text-gray-900\">Event</th>
+                        <th class=\"p-2 text-left text-xs font-semibold text-gray-900\">Model</th>
+                        <th class=\"p-2 text-right text-xs font-semibold text-gray-900\">Status</th>
+                        <th class=\"p-2 text-left text-xs font-semibold text-gray-900\">Class</th>
+                        <th class=\"p-2 text-left text-xs font-semibold text-gray-900\">Message</th>
+                    </tr>
+                </thead>
+                <tbody>
+                    ${rows}
+                </tbody>
+            </table>
+        `;
+    } catch (e) {
+        console.error('Error fetching key events:', e);
+        eventsTable.innerHTML = `<p class=\"text-red-600 text-center py-4\">Error: ${e.message}</p>`;
+    }
+}
+
 function closeModal(event) {
     if (!event || event.target === event.currentTarget) {
         const modal = document.getElementById('modelCoolingsModal');
@@ -123,3 +278,209 @@ function formatTime(seconds) {
     }
     return `${minutes}m`;
 }
+
+// Timestamps are rendered as the time elapsed, with the exact UTC time in `datetime`.
+// Their tooltips show the browser's local time, and the footer toggle switches the text
+// between relative and absolute times; the choice is remembered.
+function applyTimeDisplay() {
+    const absolute = localStorage.getItem('timeDisplay') === 'absolute';
+    document.querySelectorAll('time[data-ts]').forEach(el => {
+        const local = new Date(Number(el.dataset.ts) * 1000).toLocaleString(undefined, { timeZoneName: 'short' });
+        el.title = `${local} (${el.getAttribute('datetime')})`;
+        el.textContent = absolute ? local : el.dataset.relative;
+    });
+    const toggle = document.getElementById('timeDisplayToggle');
+    if (toggle) {
+        toggle.textContent = absolute ? 'Show relative times' : 'Show absolute times';
+    }
+}
+
+function toggleTimeDisplay() {
+    const absolute = localStorage.getItem('timeDisplay') === 'absolute';
+    localStorage.setItem('timeDisplay', absolute ? 'relative' : 'absolute');
+    applyTimeDisplay();
+}
+
+document.addEventListener('DOMContentLoaded', applyTimeDisplay);
+
+// The chosen theme goes into the `theme` cookie so the server renders it with the page.
+function applyThemeToggle() {
+    const toggle = document.getElementById('themeToggle');
+    if (toggle) {
+        toggle.textContent = document.documentElement.dataset.theme === 'dark' ? 'Light mode' : 'Dark mode';
+    }
+}
+
+function toggleTheme() {
+    const theme = document.documentElement.dataset.theme === 'dark' ? 'light' : 'dark';
+    document.documentElement.dataset.theme = theme;
+    document.cookie = `theme=${theme}; path=/; max-age=31536000; SameSite=Lax`;
+    applyThemeToggle();
+}
+
+document.addEventListener('DOMContentLoaded', applyThemeToggle);
+
+function renderTotpQr() {
+    // The provisioning page loads the QR code generator only while a secret is pending.
+    const container = document.getElementById('totpQr');
+    if (!container || typeof qrcode === 'undefined') {
+        return;
+    }
+    const qr = qrcode(0, 'M');
+    qr.addData(container.dataset.uri);
+    qr.make();
+    container.innerHTML = qr.createSvgTag({ cellSize: 5, margin: 2 });
+}
+
+document.addEventListener('DOMContentLoaded', renderTotpQr);
+
+// The keys table swaps in the `/keys/{provider}/table` fragment when switching status tabs,
+// paging, sorting or searching, and keeps the address bar in step so reloads and the back
+// button land on the same view. Links and the search form still work without scripts.
+// A new load aborts the one in flight, so a slow response can't overwrite a newer view.
+let keysTableLoad;
+
+// Returns whether the table was replaced.
+async function loadKeysTable(url, push) {
+    const container = document.getElementById('keys-table');
+    const target = new URL(url, window.location.origin);
+    if (keysTableLoad) {
+        keysTableLoad.abort();
+    }
+    const load = new AbortController();
+    keysTableLoad = load;
+    container.classList.add('opacity-60');
+    try {
+        const response = await fetch(`${target.pathname}/table${target.search}`, { signal: load.signal });
+        // An expired session is redirected to the login page, which the full page shows.
+        if (!response.ok || response.redirected) {
+            window.location.href = target.href;
+            return false;
+        }
+        const html = await response.text();
+        if (load.signal.aborted) {
+            return false;
+        }
+        container.innerHTML = html;
+        if (push) {
+            history.pushState({ keysTable: true }, '', target.pathname + target.search);
+        }
+        applyTimeDisplay();
+        return true;
+    } catch (e) {
+        if (load.signal.aborted) {
+            return false;
+        }
+        console.error('Failed to load the keys table:', e);
+        window.location.href = target.href;
+        return false;
+    } finally {
+        if (keysTableLoad === load) {
+            keysTableLoad = undefined;
+            container.classList.remove('opacity-60');
+        }
+    }
+}
+
+let keysSearchTimer;
+
+async function searchKeys(input) {
+    // The search box belongs to the hidden search form through its `form` attribute.
+    const form = document.getElementById('search-form');
+    const params = new URLSearchParams(new FormData(form));
+    const caret = input.selectionStart;
+    if (!await loadKeysTable(`${form.getAttribute('action')}?${params}`, true)) {
+        return;
+    }
+    // The search box is part of the swapped markup, so focus moves to its replacement.
+    const replacement = document.querySelector('#keys-table input[name="q"]');
+    if (replacement) {
+        replacement.focus();
+        replacement.setSelectionRange(caret, caret);
+    }
+}
+
+document.addEventListener('DOMContentLoaded', () => {
+    const container = document.getElementById('keys-table');
+    if (!container) {
+        return;
+    }
+    container.addEventListener('click', event => {
+        const link = event.target.closest('a[href]');
+        if (!link || event.metaKey || event.ctrlKey || event.shiftKey || event.button !== 0) {
+            return;
+        }
+        if (link.getAttribute('href') !== '#' && new URL(link.href).pathname === window.location.pathname) {
+            event.preventDefault();
+            loadKeysTable(link.href, true);
+        }
+    });
+    container.addEventListener('submit', event => {
+        if (event.target.id === 'search-form') {
+            event.preventDefault();
+            clearTimeout(keysSearchTimer);
+            searchKeys(container.querySelector('input[name="q"]'));
+        }
+    });
+    container.addEventListener('input', event => {
+        if (event.target.name === 'q') {
+            clearTimeout(keysSearchTimer);
+            keysSearchTimer = setTimeout(() => searchKeys(event.target), 300);
+        }
+    });
+    history.replaceState({ keysTable: true }, '');
+    window.addEventListener('popstate', event => {
+        if (event.state && event.state.keysTable) {
+            loadKeysTable(window.location.href, false);
+        }
+    });
+});
+
+function openKeyEditor(button) {
+    const form = document.getElementById('keyEditorForm');
+    form.dataset.keyId = button.dataset.keyId;
+    form.elements.note.value = button.dataset.note;
+    form.elements.tags.value = button.dataset.tags;
+    form.elements.weight.value = button.dataset.weight;
+    form.elements.tier.value = button.dataset.tier;
+    document.getElementById('keyEditorError').classList.add('hidden');
+    const modal = document.getElementById('keyEditorModal');
+    modal.classList.remove('hidden');
+    modal.classList.add('flex');
+}
+
+function closeKeyEditor(event) {
+    if (!event || event.target === event.currentTarget) {
+        const modal = document.getElementById('keyEditorModal');
+        modal.classList.add('hidden');
+        modal.classList.remove('flex');
+    }
+}
+
+async function saveKeyMetadata(event) {
+    event.preventDefault();
+    const form = event.target;
+    const error = document.getElementById('keyEditorError');
+    const body = {
+        note: form.elements.note.value,
+        tags: form.elements.tags.value.split(/[\s,]+/).filter(tag => tag),
+        weight: Number(form.elements.weight.value) || 0,
+        tier: form.elements.tier.value,
+    };
+    try {
+        const response = await fetch(`/api/keys/${form.dataset.keyId}`, {
+            method: 'PATCH',
+            headers: { 'Content-Type': 'application/json', 'X-CSRF-Token': form.elements.csrf_token.value },
+            body: JSON.stringify(body),
+        });
+        if (!response.ok) {
+            throw new Error(await response.text());
+        }
+        closeKeyEditor();
+        // Re-render the rows with the saved values.
+        loadKeysTable(window.location.href, false);
+    } catch (e) {
+        error.textContent = e.message;
+        error.classList.remove('hidden');
+    }
+}
@@ -0,0 +1,57 @@
+//! This module handles the translation logic between Cohere/Jina-style rerank models
+//! and the native Cloudflare Workers AI reranker models.
+
+pub use crate::models::{
+    RerankRequest, RerankResponse, RerankResult, RerankResultDocument, WorkersAiRerankContext,
+    WorkersAiRerankRequest, WorkersAiRerankResponse,
+};
+
+/// Translates a Cohere-style rerank request into a native Workers AI reranker request.
+pub fn translate_rerank_request(req: &RerankRequest) -> WorkersAiRerankRequest {
+    WorkersAiRerankRequest {
+        query: req.query.clone(),
+        contexts: req
+            .documents
+            .iter()
+            .map(|doc| WorkersAiRerankContext {
+                text: doc.text().to_string(),
+            })
+            .collect(),
+        top_k: req.top_n,
+    }
+}
+
+/// Translates a native Workers AI reranker response back into a Cohere-style one,
+/// ordered by descending relevance.
+pub fn translate_rerank_response(
+    resp: WorkersAiRerankResponse,
+    req: &RerankRequest,
+    model_name: &str,
+) -> RerankResponse {
+    let mut results: Vec<RerankResult> = resp
+        .result
+        .response
+        .into_iter()
+        .map(|score| RerankResult {
+            index: score.id,
+            relevance_score: score.score,
+            document: if req.return_documents {
+                req.documents.get(score.id as usize).map(|doc| RerankResultDocument {
+                    text: doc.text().to_string(),
+                })
+            } else {
+                None
+            },
+        })
+        .collect();
+
+    results.sort_by(|a, b| b.relevance_score.total_cmp(&a.relevance_score));
+    if let Some(top_n) = req.top_n {
+        results.truncate(top_n as usize);
+    }
+
+    RerankResponse {
+        model: model_name.to_string(),
+        results,
+    }
+}
@@ -25,8 +25,34 @@
         }
     ],
     "triggers": {
-        "crons": ["0 0 * * *"]
+        "crons": ["0 0 * * *", "*/5 * * * *"]
     },
+//    "analytics_engine_datasets": [
+//        {
+//            "binding": "ANALYTICS",
+//            "dataset": "one_balance_requests"
+//        }
+//    ],
+//    "services": [
+//        {
+//            "binding": "MY_SHIM",
+//            "service": "my-provider-shim"
+//        }
+//    ],
+//    "durable_objects": {
+//        "bindings": [
+//            {
+//                "name": "INFLIGHT_REGISTRY",
+//                "class_name": "InflightRegistry"
+//            }
+//        ]
+//    },
+//    "migrations": [
+//        {
+//            "tag": "v1",
+//            "new_classes": ["InflightRegistry"]
+//        }
+//    ],
 //    "queues": {
 //        "producers": [
 //            {
@@ -55,7 +81,83 @@
        // "TARGET_TIMEOUT_MS": "40000"
         "TARGET_TIMEOUT_MS": "10000",
        // default 10
-        "RECOVERY_THRESHOLD": "5"
+        "RECOVERY_THRESHOLD": "5",
+       // how strongly traffic is spread across a provider's keys by recent usage; 0 ranks keys by health only; default 500
+       // "KEY_FAIRNESS_WEIGHT": "500",
+       // key health score weights; latency and success scores are scaled in percent; defaults 100, 100, 50, 10, 300
+       // "SCORE_LATENCY_WEIGHT_PERCENT": "100",
+       // "SCORE_SUCCESS_WEIGHT_PERCENT": "100",
+       // "SCORE_FAILURE_PENALTY": "50",
+       // "SCORE_RECENT_SUCCESS_BONUS": "10",
+       // "SCORE_RECENT_SUCCESS_WINDOW_SECONDS": "300",
+       // whether free or paid keys are tried first: free_first, paid_first or mixed; default free_first
+       // "KEY_TIER_STRATEGY": "free_first",
+       // stop failing over to new keys once less than this is left of OVERALL_TIMEOUT_MS; default 1000
+       // "FAILOVER_MIN_BUDGET_MS": "1000",
+       // providers served by another Worker through a service binding ("provider:BINDING,..."); see "services" above
+       // "SERVICE_BINDINGS": "my-provider:MY_SHIM",
+       // hold requests up to this long when every key is on a short cooldown; default 0 (fail fast)
+       // "COOLDOWN_WAIT_MAX_MS": "3000",
+       // request bodies larger than this many bytes are rejected with a 413; 0 disables; default 33554432 (32 MiB)
+       // "MAX_BODY_BYTES": "33554432",
+       // validators for successful JSON responses, per model ("pattern:validator,...;..."); a failure retries on another key
+       // "RESPONSE_VALIDATORS": "gemini-2.5-*:non_empty,json_mode;*:json_mode",
+       // upstream response headers passed to clients (comma-separated, trailing * = prefix); default: content type, rate-limit and request id headers
+       // "FORWARD_RESPONSE_HEADERS": "content-type,retry-after,x-ratelimit-*",
+       // extra headers to strip, on top of cookies, org ids and server headers
+       // "STRIP_RESPONSE_HEADERS": "cf-aig-log-id",
+       // CIDRs allowed to reach the login page, UI and admin API (checked against CF-Connecting-IP); default: any
+       // "ADMIN_IP_ALLOWLIST": "203.0.113.0/24,2001:db8::/32",
+       // admin API calls per credential and minute (per isolate); 0 disables; default 120
+       // "ADMIN_RATE_LIMIT_PER_MINUTE": "120",
+       // key exports, key reveals and key test runs per credential and minute (per isolate); 0 disables; default 10
+       // "ADMIN_SENSITIVE_RATE_LIMIT_PER_MINUTE": "10",
+       // seconds a UI login lasts; sessions are signed with the secret SESSION_SECRET (AUTH_KEY when unset); default 43200
+       // "SESSION_TTL_SECONDS": "43200",
+       // days a device skips the two-factor code once remembered at login; 0 disables remembering; default 30
+       // "TOTP_REMEMBER_DAYS": "30",
+       // apply pending schema migrations (src/migrations.rs) on the first request of each isolate; default false
+       // "AUTO_MIGRATE": "true",
+       // log missing D1 tables, columns and indexes compared with the models on the first request of each isolate and on each cron trigger; default false
+       // "CHECK_SCHEMA": "true",
+       // start D1 sessions at any read replica instead of the primary (clients keep read-your-writes via the x-d1-bookmark header); default false
+       // "D1_READ_REPLICAS": "true",
+       // log the query plan of generated SQL and warn about full table scans (debugging only, one extra D1 call per query); default false
+       // "EXPLAIN_QUERIES": "true",
+       // POST key health transitions (blocked, daily quota cooldown, recovered) as JSON; optional secret KEY_EVENTS_WEBHOOK_SECRET is sent as a Bearer token
+       // "KEY_EVENTS_WEBHOOK_URL": "https://hooks.example.com/onebalance",
+       // flag a provider as degraded (provider_degraded webhook event) when its last-minute latency reaches this multiple of its baseline; 0 disables; default 5
+       // "ANOMALY_LATENCY_FACTOR": "5",
+       // ... or when its last-minute error rate reaches this percentage (and 3x its baseline); 0 disables; default 50
+       // "ANOMALY_ERROR_RATE_PERCENT": "50",
+       // provider error rate over the last minute (percent) that opens a provider's circuit breaker; 0 disables it; default 90
+       // "PROVIDER_BREAKER_ERROR_RATE_PERCENT": "90",
+       // how long an open breaker rejects requests before probing the provider again; default 30
+       // "PROVIDER_BREAKER_OPEN_SECONDS": "30",
+       // the deployment name; namespaces the penalty box and enables chaos mode in dev/staging
+       // "DEPLOY_ENV": "staging",
+       // dev/staging only: inject upstream delays and errors per provider ("pattern:setting,...;..."), see README
+       // "CHAOS_MODE": "openai:delay_ms=200-3000,error_rate=20;*:error_rate=5,error_status=429",
+       // percentage of requests whose key ranking is stored with its score components (see key details); default 0 (off)
+       // "SCORE_SAMPLE_RATE_PERCENT": "1",
+       // store redacted, truncated prompts and responses with the request events: off, failures or all; default off
+       // "PAYLOAD_LOGGING": "failures",
+       // extra ;-separated regexes masked in logged payloads, on top of keys and emails
+       // "PAYLOAD_REDACT_PATTERNS": "[0-9]{3}-[0-9]{2}-[0-9]{4}",
+       // alert rules checked every 5 minutes (provider-pattern:metric<threshold, metrics active_keys, usable_keys, success_rate); empty disables
+       // "ALERT_RULES": "*:usable_keys<2,success_rate<90",
+       // POST alert transitions here; optional secret ALERT_WEBHOOK_SECRET is sent as a Bearer token
+       // "ALERT_WEBHOOK_URL": "https://hooks.slack.com/services/...",
+       // "json" (default) or "slack" for a Slack-compatible {"text": ...} payload
+       // "ALERT_WEBHOOK_FORMAT": "slack",
+       // days after which the daily cleanup moves blocked keys to the trash; 0 keeps them; default 30
+       // "BLOCKED_KEY_RETENTION_DAYS": "30",
+       // blocked keys re-tested (and reactivated if they pass) by each daily probation pass; 0 disables; default 10
+       // "PROBATION_SAMPLE_SIZE": "10",
+       // reactivations after which probation leaves a key blocked; default 3
+       // "PROBATION_MAX_REACTIVATIONS": "3",
+       // percentage of successful JSON requests stored (redacted) for quality evaluation; default 0 (off)
+        "SAMPLE_RATE_PERCENT": "0"
     },
     "observability": {
       "enabled": true,
@@ -58,6 +58,14 @@ impl Id {
         }
     }
 
+    /// Return the string representation of the record identifier, if it has one.
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.repr {
+            Repr::Int(_) => None,
+            Repr::String(id) => Some(id),
+        }
+    }
+
     pub fn to_primitive(&self) -> stmt::Value {
         match &self.repr {
             Repr::Int(_) => todo!(),
@@ -62,6 +62,9 @@ impl ToSql for &stmt::Expr {
 
                 fmt!(f, expr.expr " LIKE " pattern);
             }
+            Pattern(stmt::ExprPattern::Like(expr)) => {
+                fmt!(f, expr.expr " LIKE " expr.pattern " ESCAPE '\\'");
+            }
             Record(expr) => {
                 let exprs = Comma(&expr.fields);
                 fmt!(f, "(" exprs ")");
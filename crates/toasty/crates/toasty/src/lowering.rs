@@ -70,6 +70,11 @@ fn lower_query(schema: &Schema, query: &mut stmt::Query) -> Result<(), LoweringE
             
             // Lower the returning
             ctx.visit_returning_mut(&mut select.returning);
+
+            // Lower the ordering, so it refers to columns rather than model fields
+            if let Some(order_by) = &mut query.order_by {
+                ctx.visit_order_by_mut(order_by);
+            }
         }
     }
     Ok(())
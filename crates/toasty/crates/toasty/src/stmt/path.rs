@@ -61,6 +61,14 @@ impl<T: ?Sized> Path<T> {
         }
     }
 
+    /// Matches a SQL `LIKE` pattern. A backslash escapes a literal `%` or `_` in the pattern.
+    pub fn like(self, pattern: impl IntoExpr<T>) -> Expr<bool> {
+        Expr {
+            untyped: stmt::Expr::like(self.untyped.into_stmt(), pattern.into_expr().untyped),
+            _p: PhantomData,
+        }
+    }
+
     pub fn gt(self, rhs: impl IntoExpr<T>) -> Expr<bool> {
         Expr {
             untyped: stmt::Expr::gt(self.untyped.into_stmt(), rhs.into_expr().untyped),